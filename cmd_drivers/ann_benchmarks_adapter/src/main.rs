@@ -0,0 +1,204 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+//! Runs the ann-benchmarks protocol against bin files: fit on the base points, then for each
+//! search list size set the query arguments, batch query and score the batch results.
+//! ann-benchmarks itself runs the index through the module of python/ann_benchmarks.
+use diskann::{
+    common::{ANNError, ANNResult},
+    index::AnnBenchmarksIndex,
+    utils::load_bin,
+};
+use std::{env, time::Instant};
+
+#[allow(clippy::too_many_arguments)]
+fn run_ann_benchmarks(
+    metric: &str,
+    base_file: &str,
+    query_file: &str,
+    truthset_file: &str,
+    k_value: usize,
+    l_values: &[u32],
+    max_degree: u32,
+    l_build: u32,
+    alpha: f32,
+    num_threads: u32,
+) -> ANNResult<()> {
+    let (base, base_num, dim) = load_bin::<f32>(base_file, 0)?;
+    let (queries, query_num, query_dim) = load_bin::<f32>(query_file, 0)?;
+    if query_dim != dim {
+        return Err(ANNError::log_index_error(format!(
+            "Queries have dimension {}, base points have dimension {}",
+            query_dim, dim
+        )));
+    }
+
+    let truthset = if truthset_file.is_empty() {
+        None
+    } else {
+        Some(load_bin::<u32>(truthset_file, 0)?)
+    };
+
+    let mut algo = AnnBenchmarksIndex::new(metric, max_degree, l_build, alpha, num_threads)?;
+    let start = Instant::now();
+    algo.fit(&base, dim)?;
+    println!(
+        "Fitted {} points of dimension {} in {:.2}s",
+        base_num,
+        dim,
+        start.elapsed().as_secs_f64()
+    );
+
+    println!("{:>48}{:>12}{:>12}", "Algorithm", "QPS", "Recall");
+    for l_value in l_values {
+        algo.set_query_arguments(*l_value);
+
+        let start = Instant::now();
+        algo.batch_query(&queries, k_value)?;
+        let qps = query_num as f64 / start.elapsed().as_secs_f64();
+
+        let recall = match &truthset {
+            Some((gt_ids, _, gt_dim)) => format!(
+                "{:.4}",
+                recall(algo.get_batch_results(), gt_ids, *gt_dim, k_value)
+            ),
+            None => "-".to_string(),
+        };
+        println!("{:>48}{:>12.1}{:>12}", algo.to_string(), qps, recall);
+    }
+
+    Ok(())
+}
+
+/// Fraction of the first k ground truth ids found in the results, as ann-benchmarks reports it
+fn recall(results: &[Vec<u32>], gt_ids: &[u32], gt_dim: usize, k_value: usize) -> f64 {
+    let k_value = k_value.min(gt_dim);
+    let found: usize = results
+        .iter()
+        .enumerate()
+        .map(|(i, ids)| {
+            let gt = &gt_ids[i * gt_dim..i * gt_dim + k_value];
+            ids.iter().filter(|id| gt.contains(id)).count()
+        })
+        .sum();
+    found as f64 / (results.len() * k_value).max(1) as f64
+}
+
+fn main() -> ANNResult<()> {
+    let mut metric = String::from("euclidean");
+    let mut base_file = String::new();
+    let mut query_file = String::new();
+    let mut truthset_file = String::new();
+    let mut k_value: usize = 10;
+    let mut l_values: Vec<u32> = vec![10, 20, 40, 80, 160];
+    let mut max_degree: u32 = 64;
+    let mut l_build: u32 = 100;
+    let mut alpha: f32 = 1.2;
+    let mut num_threads: u32 = 1;
+
+    let args: Vec<String> = env::args().collect();
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        let ann_error =
+            || ANNError::log_index_config_error(String::from(arg), format!("Missing {}", arg));
+        let parse_error = |err: String| {
+            ANNError::log_index_config_error(String::from(arg), format!("ParseError: {}", err))
+        };
+        match arg.as_str() {
+            "--help" | "-h" => {
+                print_help();
+                return Ok(());
+            }
+            "--metric" => metric = iter.next().ok_or_else(ann_error)?.to_owned(),
+            "--base_file" => base_file = iter.next().ok_or_else(ann_error)?.to_owned(),
+            "--query_file" => query_file = iter.next().ok_or_else(ann_error)?.to_owned(),
+            "--gt_file" => truthset_file = iter.next().ok_or_else(ann_error)?.to_owned(),
+            "--count" | "-K" => {
+                k_value = iter
+                    .next()
+                    .ok_or_else(ann_error)?
+                    .parse()
+                    .map_err(|err: std::num::ParseIntError| parse_error(err.to_string()))?;
+            }
+            "--search_list" | "-L" => {
+                l_values = iter
+                    .next()
+                    .ok_or_else(ann_error)?
+                    .split(',')
+                    .map(|l| l.parse::<u32>())
+                    .collect::<Result<Vec<u32>, _>>()
+                    .map_err(|err| parse_error(err.to_string()))?;
+            }
+            "--max_degree" | "-R" => {
+                max_degree = iter
+                    .next()
+                    .ok_or_else(ann_error)?
+                    .parse()
+                    .map_err(|err: std::num::ParseIntError| parse_error(err.to_string()))?;
+            }
+            "--l_build" => {
+                l_build = iter
+                    .next()
+                    .ok_or_else(ann_error)?
+                    .parse()
+                    .map_err(|err: std::num::ParseIntError| parse_error(err.to_string()))?;
+            }
+            "--alpha" => {
+                alpha = iter
+                    .next()
+                    .ok_or_else(ann_error)?
+                    .parse()
+                    .map_err(|err: std::num::ParseFloatError| parse_error(err.to_string()))?;
+            }
+            "--num_threads" => {
+                num_threads = iter
+                    .next()
+                    .ok_or_else(ann_error)?
+                    .parse()
+                    .map_err(|err: std::num::ParseIntError| parse_error(err.to_string()))?;
+            }
+            _ => {
+                return Err(ANNError::log_index_error(format!(
+                    "Unknown argument: {}",
+                    arg
+                )));
+            }
+        }
+    }
+
+    if base_file.is_empty() || query_file.is_empty() {
+        return Err(ANNError::log_index_error(String::from(
+            "Both --base_file and --query_file are required!",
+        )));
+    }
+
+    run_ann_benchmarks(
+        &metric,
+        &base_file,
+        &query_file,
+        &truthset_file,
+        k_value,
+        &l_values,
+        max_degree,
+        l_build,
+        alpha,
+        num_threads,
+    )
+}
+
+fn print_help() {
+    println!("Run the ann-benchmarks fit/query protocol against float bin files");
+    println!("Arguments");
+    println!("--help, -h                Print information on arguments");
+    println!("--metric                  ann-benchmarks metric <euclidean/angular> (default: euclidean)");
+    println!("--base_file               Float bin file of the points to fit (required)");
+    println!("--query_file              Float bin file of the queries (required)");
+    println!("--gt_file                 Ground truth file, recall is reported if given");
+    println!("--count, -K               Number of neighbors per query (default: 10)");
+    println!("--search_list, -L         Comma separated search list sizes (default: 10,20,40,80,160)");
+    println!("--max_degree, -R          Maximum graph degree (default: 64)");
+    println!("--l_build                 Build search list size (default: 100)");
+    println!("--alpha                   Pruning alpha (default: 1.2)");
+    println!("--num_threads             Number of threads (default: 1)");
+}
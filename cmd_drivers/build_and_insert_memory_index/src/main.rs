@@ -9,14 +9,15 @@ use diskann::{
     index::create_inmem_index,
     utils::round_up,
     model::{
+        default_param_vals::{ALPHA, BUILD_LIST_SIZE, MAX_DEGREE},
         IndexWriteParametersBuilder,
         IndexConfiguration, 
-        vertex::{DIM_128, DIM_256, DIM_104}
+        vertex::{DIM_128, DIM_256, DIM_104, DIM_384, DIM_768, DIM_1024, DIM_1536}
     },
-    utils::{load_metadata_from_file, Timer},
+    utils::{load_metadata_from_file, OutputFormat, Report, Timer},
 };
 
-use vector::{Metric, FullPrecisionDistance, Half};
+use vector::{BFloat16, FullPrecisionDistance, Half, Metric};
 
 // The main function to build an in-memory index
 #[allow(clippy::too_many_arguments)]
@@ -31,19 +32,24 @@ fn build_and_insert_in_memory_index<T> (
     num_threads: u32,
     _use_pq_build: bool,
     _num_pq_bytes: usize,
-    use_opq: bool
+    use_opq: bool,
+    format: OutputFormat,
 ) -> ANNResult<()> 
 where 
     T: Default + Copy + Sync + Send + Into<f32>,
     [T; DIM_104]: FullPrecisionDistance<T, DIM_104>,
     [T; DIM_128]: FullPrecisionDistance<T, DIM_128>,
-    [T; DIM_256]: FullPrecisionDistance<T, DIM_256>
+    [T; DIM_256]: FullPrecisionDistance<T, DIM_256>,
+    [T; DIM_384]: FullPrecisionDistance<T, DIM_384>,
+    [T; DIM_768]: FullPrecisionDistance<T, DIM_768>,
+    [T; DIM_1024]: FullPrecisionDistance<T, DIM_1024>,
+    [T; DIM_1536]: FullPrecisionDistance<T, DIM_1536>
 {
     let index_write_parameters = IndexWriteParametersBuilder::new(l, r)
         .with_alpha(alpha)
         .with_saturate_graph(false)
         .with_num_threads(num_threads)
-        .build();
+        .try_build()?;
 
     let (data_num, data_dim) = load_metadata_from_file(data_path)?;
 
@@ -70,10 +76,28 @@ where
     println!("Initial indexing time: {}", diff.as_secs_f64());
 
     let (delta_data_num, _) = load_metadata_from_file(delta_path)?;
-    
+
+    let insert_timer = Timer::new();
     index.insert(delta_path, delta_data_num)?;
+    let insert_time = insert_timer.elapsed();
 
     index.save(save_path)?;
+
+    let mut report = Report::new(&[
+        "data_path",
+        "num_points",
+        "initial_time_s",
+        "num_inserted",
+        "insert_time_s",
+    ]);
+    report.add_row(vec![
+        data_path.into(),
+        data_num.into(),
+        diff.as_secs_f64().into(),
+        delta_data_num.into(),
+        insert_time.as_secs_f64().into(),
+    ])?;
+    report.print(format);
     
     Ok(())
 }
@@ -86,13 +110,14 @@ fn main() -> ANNResult<()> {
     let mut index_path_prefix = String::new();
 
     let mut num_threads = 0u32;
-    let mut r = 64u32;
-    let mut l = 100u32;
+    let mut r = MAX_DEGREE;
+    let mut l = BUILD_LIST_SIZE;
 
-    let mut alpha = 1.2f32;
+    let mut alpha = ALPHA;
     let mut build_pq_bytes = 0u32;
     let mut _use_pq_build = false;
     let mut use_opq = false;
+    let mut format = OutputFormat::Text;
 
     let args: Vec<String> = env::args().collect();
     let mut iter = args.iter().skip(1).peekable();
@@ -260,6 +285,17 @@ fn main() -> ANNResult<()> {
                         )
                     })?;
             }
+            "--format" => {
+                format = iter
+                    .next()
+                    .ok_or_else(|| {
+                        ANNError::log_index_config_error(
+                            "format".to_string(),
+                            "Missing output format".to_string(),
+                        )
+                    })?
+                    .parse()?;
+            }
             _ => {
                 return Err(ANNError::log_index_config_error(
                     String::from(""),
@@ -308,6 +344,7 @@ fn main() -> ANNResult<()> {
                 _use_pq_build,
                 build_pq_bytes as usize,
                 use_opq,
+                format,
             )?;
         }
         "uint8" => {
@@ -323,6 +360,7 @@ fn main() -> ANNResult<()> {
                 _use_pq_build,
                 build_pq_bytes as usize,
                 use_opq,
+                format,
             )?;
         }
         "float" => {
@@ -338,6 +376,7 @@ fn main() -> ANNResult<()> {
                 _use_pq_build,
                 build_pq_bytes as usize,
                 use_opq,
+                format,
             )?;
         }
         "f16" => {
@@ -353,6 +392,23 @@ fn main() -> ANNResult<()> {
                 _use_pq_build,
                 build_pq_bytes as usize,
                 use_opq,
+                format,
+            )?;
+        }
+        "bf16" => {
+            build_and_insert_in_memory_index::<BFloat16>(
+                metric,
+                &data_path,
+                &insert_path,
+                r,
+                l,
+                alpha,
+                &index_path_prefix,
+                num_threads,
+                _use_pq_build,
+                build_pq_bytes as usize,
+                use_opq,
+                format,
             )?;
         }
         _ => {
@@ -367,8 +423,8 @@ fn main() -> ANNResult<()> {
 fn print_help() {
     println!("Arguments");
     println!("--help, -h                Print information on arguments");
-    println!("--data_type               data type <int8/uint8/float> (required)");
-    println!("--dist_fn                 distance function <l2/cosine> (required)");
+    println!("--data_type               data type <int8/uint8/float/f16/bf16> (required)");
+    println!("--dist_fn                 distance function <l2/l1/chebyshev/cosine> (required)");
     println!("--data_path               Input data file in bin format for initial build (required)");
     println!("--insert_path             Input data file in bin format for insert (required)");
     println!("--index_path_prefix       Path prefix for saving index file components (required)");
@@ -378,5 +434,6 @@ fn print_help() {
     println!("--num_threads, -T         Number of threads used for building index (defaults to num of CPU logic cores)");
     println!("--build_PQ_bytes          Number of PQ bytes to build the index; 0 for full precision build (default: 0)");
     println!("--use_opq                 Set true for OPQ compression while using PQ distance comparisons for building the index, and false for PQ compression (default: false)");
+    println!("--format                  Format of the run summary <text/json/csv>, json and csv are printed at the end of the run (default: text)");
 }
 
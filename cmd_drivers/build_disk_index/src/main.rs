@@ -8,16 +8,16 @@ use diskann::{
     common::{ANNError, ANNResult},
     index::ann_disk_index::create_disk_index,
     model::{
-        default_param_vals::ALPHA,
+        default_param_vals::{ALPHA, BUILD_LIST_SIZE, MAX_DEGREE},
         vertex::{DIM_104, DIM_128, DIM_256},
         DiskIndexBuildParameters, IndexConfiguration, IndexWriteParametersBuilder,
     },
     storage::DiskIndexStorage,
     utils::round_up,
-    utils::{load_metadata_from_file, Timer},
+    utils::{load_metadata_from_file, OutputFormat, Report, Timer},
 };
 
-use vector::{FullPrecisionDistance, Half, Metric};
+use vector::{BFloat16, FullPrecisionDistance, Half, Metric};
 
 /// The main function to build a disk index
 #[allow(clippy::too_many_arguments)]
@@ -32,6 +32,8 @@ fn build_disk_index<T>(
     index_build_ram_limit_gb: f64,
     num_pq_chunks: usize,
     use_opq: bool,
+    random_seed: Option<u64>,
+    format: OutputFormat,
 ) -> ANNResult<()>
 where
     T: Default + Copy + Sync + Send + Into<f32>,
@@ -45,11 +47,11 @@ where
     let index_write_parameters = IndexWriteParametersBuilder::new(l, r)
         .with_saturate_graph(true)
         .with_num_threads(num_threads)
-        .build();
+        .try_build()?;
 
     let (data_num, data_dim) = load_metadata_from_file(data_path)?;
 
-    let config = IndexConfiguration::new(
+    let mut config = IndexConfiguration::new(
         metric,
         data_dim,
         round_up(data_dim as u64, 8_u64) as usize,
@@ -61,6 +63,7 @@ where
         1f32,
         index_write_parameters,
     );
+    config.random_seed = random_seed;
     let storage = DiskIndexStorage::new(data_path.to_string(), index_path_prefix.to_string())?;
     let mut index = create_disk_index::<T>(Some(disk_index_build_parameters), config, storage)?;
 
@@ -71,6 +74,28 @@ where
     let diff = timer.elapsed();
     println!("Indexing time: {}", diff.as_secs_f64());
 
+    let mut report = Report::new(&[
+        "data_path",
+        "num_points",
+        "dim",
+        "max_degree",
+        "l_build",
+        "num_threads",
+        "num_pq_chunks",
+        "indexing_time_s",
+    ]);
+    report.add_row(vec![
+        data_path.into(),
+        data_num.into(),
+        data_dim.into(),
+        r.into(),
+        l.into(),
+        num_threads.into(),
+        num_pq_chunks.into(),
+        diff.as_secs_f64().into(),
+    ])?;
+    report.print(format);
+
     Ok(())
 }
 
@@ -81,13 +106,15 @@ fn main() -> ANNResult<()> {
     let mut index_path_prefix = String::new();
 
     let mut num_threads = 0u32;
-    let mut r = 64u32;
-    let mut l = 100u32;
+    let mut r = MAX_DEGREE;
+    let mut l = BUILD_LIST_SIZE;
     let mut search_ram_limit_gb = 0f64;
     let mut index_build_ram_limit_gb = 0f64;
 
     let mut build_pq_bytes = 0u32;
     let mut use_opq = false;
+    let mut random_seed = None;
+    let mut format = OutputFormat::Text;
 
     let args: Vec<String> = env::args().collect();
     let mut iter = args.iter().skip(1).peekable();
@@ -261,6 +288,35 @@ fn main() -> ANNResult<()> {
                         )
                     })?;
             }
+            "--random_seed" => {
+                random_seed = Some(
+                    iter.next()
+                        .ok_or_else(|| {
+                            ANNError::log_index_config_error(
+                                "random_seed".to_string(),
+                                "Missing random seed".to_string(),
+                            )
+                        })?
+                        .parse()
+                        .map_err(|err| {
+                            ANNError::log_index_config_error(
+                                "random_seed".to_string(),
+                                format!("ParseIntError: {}", err),
+                            )
+                        })?,
+                );
+            }
+            "--format" => {
+                format = iter
+                    .next()
+                    .ok_or_else(|| {
+                        ANNError::log_index_config_error(
+                            "format".to_string(),
+                            "Missing output format".to_string(),
+                        )
+                    })?
+                    .parse()?;
+            }
             _ => {
                 return Err(ANNError::log_index_config_error(
                     String::from(""),
@@ -302,6 +358,8 @@ fn main() -> ANNResult<()> {
             index_build_ram_limit_gb,
             build_pq_bytes as usize,
             use_opq,
+            random_seed,
+            format,
         ),
         "uint8" => build_disk_index::<u8>(
             metric,
@@ -314,6 +372,8 @@ fn main() -> ANNResult<()> {
             index_build_ram_limit_gb,
             build_pq_bytes as usize,
             use_opq,
+            random_seed,
+            format,
         ),
         "float" => build_disk_index::<f32>(
             metric,
@@ -326,6 +386,8 @@ fn main() -> ANNResult<()> {
             index_build_ram_limit_gb,
             build_pq_bytes as usize,
             use_opq,
+            random_seed,
+            format,
         ),
         "f16" => build_disk_index::<Half>(
             metric,
@@ -338,9 +400,25 @@ fn main() -> ANNResult<()> {
             index_build_ram_limit_gb,
             build_pq_bytes as usize,
             use_opq,
+            random_seed,
+            format,
+        ),
+        "bf16" => build_disk_index::<BFloat16>(
+            metric,
+            &data_path,
+            r,
+            l,
+            &index_path_prefix,
+            num_threads,
+            search_ram_limit_gb,
+            index_build_ram_limit_gb,
+            build_pq_bytes as usize,
+            use_opq,
+            random_seed,
+            format,
         ),
         _ => {
-            println!("Unsupported type. Use one of int8, uint8, float or f16.");
+            println!("Unsupported type. Use one of int8, uint8, float, f16 or bf16.");
             return Err(ANNError::log_index_config_error(
                 "data_type".to_string(),
                 "Invalid data type".to_string(),
@@ -354,7 +432,10 @@ fn main() -> ANNResult<()> {
             Ok(())
         }
         Err(err) => {
-            eprintln!("Error: {:?}", err);
+            eprintln!("Error: {}", err);
+            if let Some(backtrace) = err.backtrace() {
+                eprintln!("{}", backtrace);
+            }
             Err(err)
         }
     }
@@ -363,8 +444,8 @@ fn main() -> ANNResult<()> {
 fn print_help() {
     println!("Arguments");
     println!("--help, -h                Print information on arguments");
-    println!("--data_type               data type <int8/uint8/float> (required)");
-    println!("--dist_fn                 distance function <l2/cosine> (required)");
+    println!("--data_type               data type <int8/uint8/float/f16/bf16> (required)");
+    println!("--dist_fn                 distance function <l2/l1/chebyshev/cosine> (required)");
     println!("--data_path               Input data file in bin format (required)");
     println!("--index_path_prefix       Path prefix for saving index file components (required)");
     println!("--max_degree, -R          Maximum graph degree (default: 64)");
@@ -374,4 +455,6 @@ fn print_help() {
     println!("--num_threads, -T         Number of threads used for building index (defaults to num of CPU logic cores)");
     println!("--build_PQ_bytes          Number of PQ bytes to build the index; 0 for full precision build (default: 0)");
     println!("--use_opq                 Set true for OPQ compression while using PQ distance comparisons for building the index, and false for PQ compression (default: false)");
+    println!("--random_seed             Seed of the random choices of the build, so a rebuild gives the same index with -T 1 (default: drawn from the OS)");
+    println!("--format                  Format of the build summary <text/json/csv>, json and csv are printed at the end of the build (default: text)");
 }
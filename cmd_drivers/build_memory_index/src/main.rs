@@ -4,25 +4,35 @@
  */
 use clap::{Parser, ValueEnum};
 use std::path::PathBuf;
+#[cfg(feature = "gpu")]
+use std::sync::Arc;
 
 use diskann::{
-    common::ANNResult,
+    common::{ANNError, ANNResult},
     index::create_inmem_index,
     model::{
-        vertex::{DIM_104, DIM_128, DIM_256},
+        default_param_vals::{ALPHA, BUILD_LIST_SIZE, MAX_DEGREE},
+        vertex::{DIM_104, DIM_1024, DIM_128, DIM_1536, DIM_256, DIM_384, DIM_768},
         IndexConfiguration, IndexWriteParametersBuilder,
     },
     utils::round_up,
-    utils::{load_metadata_from_file, Timer},
+    utils::{
+        load_metadata_from_file, quantize_f32_bin_to_i8, requantize_i8_bin_symmetric,
+        Int8Quantizer, NpyElement, NpyFile, OutputFormat, Preprocessing, Report, Timer,
+    },
 };
+#[cfg(feature = "gpu")]
+use diskann::index::GpuDistance;
 
-use vector::{FullPrecisionDistance, Half, Metric};
+use vector::{BFloat16, FullPrecisionDistance, Half, Metric};
 
-/// The main function to build an in-memory index
+/// The main function to build an in-memory index, from the rows of a mapped .npy file and
+/// their dimension when given, else from the bin file at data_path
 #[allow(clippy::too_many_arguments)]
 fn build_in_memory_index<T>(
     metric: Metric,
     data_path: &str,
+    npy_rows: Option<(&[T], usize)>,
     r: u32,
     l: u32,
     alpha: f32,
@@ -31,20 +41,29 @@ fn build_in_memory_index<T>(
     _use_pq_build: bool,
     _num_pq_bytes: usize,
     use_opq: bool,
+    use_gpu: bool,
+    format: OutputFormat,
 ) -> ANNResult<()>
 where
     T: Default + Copy + Sync + Send + Into<f32>,
     [T; DIM_104]: FullPrecisionDistance<T, DIM_104>,
     [T; DIM_128]: FullPrecisionDistance<T, DIM_128>,
     [T; DIM_256]: FullPrecisionDistance<T, DIM_256>,
+    [T; DIM_384]: FullPrecisionDistance<T, DIM_384>,
+    [T; DIM_768]: FullPrecisionDistance<T, DIM_768>,
+    [T; DIM_1024]: FullPrecisionDistance<T, DIM_1024>,
+    [T; DIM_1536]: FullPrecisionDistance<T, DIM_1536>,
 {
     let index_write_parameters = IndexWriteParametersBuilder::new(l, r)
         .with_alpha(alpha)
         .with_saturate_graph(false)
         .with_num_threads(num_threads)
-        .build();
+        .try_build()?;
 
-    let (data_num, data_dim) = load_metadata_from_file(data_path)?;
+    let (data_num, data_dim) = match npy_rows {
+        Some((rows, dim)) => (rows.len() / dim, dim),
+        None => load_metadata_from_file(data_path)?,
+    };
 
     let config = IndexConfiguration::new(
         metric,
@@ -58,23 +77,178 @@ where
         1f32,
         index_write_parameters,
     );
-    let mut index = create_inmem_index::<T>(config)?;
+    let mut index = create_inmem_index::<T>(with_gpu(config, use_gpu)?)?;
 
     let timer = Timer::new();
 
-    index.build(data_path, data_num)?;
+    match npy_rows {
+        Some((rows, _)) => index.build_from_rows(rows)?,
+        None => index.build(data_path, data_num)?,
+    }
 
     let diff = timer.elapsed();
 
     println!("Indexing time: {}", diff.as_secs_f64());
     index.save(save_path)?;
 
+    let mut report = Report::new(&[
+        "data_path",
+        "num_points",
+        "dim",
+        "max_degree",
+        "l_build",
+        "alpha",
+        "num_threads",
+        "indexing_time_s",
+    ]);
+    report.add_row(vec![
+        data_path.into(),
+        data_num.into(),
+        data_dim.into(),
+        r.into(),
+        l.into(),
+        alpha.into(),
+        num_threads.into(),
+        diff.as_secs_f64().into(),
+    ])?;
+    report.print(format);
+
     Ok(())
 }
 
+/// Prepare the int8 data file to build from, quantizing or re-centering the input if asked.
+/// The parameters of the stored values are saved next to the index.
+fn prepare_int8_data(args: &BuildMemoryIndexArgs) -> ANNResult<String> {
+    let data_path = args.data_path.to_string_lossy().to_string();
+    let int8_data_path = format!("{}_int8.bin", args.index_path_prefix);
+    let params_path = format!("{}_int8_params.bin", args.index_path_prefix);
+
+    let quantizer = if args.quantize_to_int8 {
+        quantize_f32_bin_to_i8(&data_path, &int8_data_path, args.dist_fn)?
+    } else {
+        let input_quantizer = Int8Quantizer::new(args.int8_scale, args.int8_zero_point)?;
+        if input_quantizer.zero_point == 0 || args.dist_fn != Metric::Cosine {
+            // The zero point cancels out in L2/L1 distances, build from the input as is
+            input_quantizer.save(&params_path)?;
+            return Ok(data_path);
+        }
+        requantize_i8_bin_symmetric(&data_path, &int8_data_path, &input_quantizer)?
+    };
+
+    println!(
+        "Quantized {} to {} with scale: {} zero_point: {}",
+        data_path, int8_data_path, quantizer.scale, quantizer.zero_point
+    );
+    quantizer.save(&params_path)?;
+    Ok(int8_data_path)
+}
+
+/// Prepare the float data file to build from, applying the requested preprocessing.
+/// Its parameters are saved next to the index so the queries can be preprocessed the same way.
+fn prepare_float_data(args: &BuildMemoryIndexArgs, npy: &Option<NpyFile>) -> ANNResult<String> {
+    let data_path = args.data_path.to_string_lossy().to_string();
+    if npy.is_some() {
+        return Ok(data_path);
+    }
+    let preprocessing =
+        Preprocessing::fit_f32_bin(&data_path, args.center, args.standardize, args.normalize)?;
+    if preprocessing.is_identity() {
+        return Ok(data_path);
+    }
+
+    let preprocessed_data_path = format!("{}_preprocessed.bin", args.index_path_prefix);
+    preprocessing.preprocess_f32_bin(&data_path, &preprocessed_data_path)?;
+    preprocessing.save(&format!("{}_preprocessing.bin", args.index_path_prefix))?;
+    println!("Preprocessed {} into {}", data_path, preprocessed_data_path);
+    Ok(preprocessed_data_path)
+}
+
+/// Rows of a .npy data file with their dimension, None for a bin file
+fn npy_rows<T: NpyElement>(npy: &Option<NpyFile>) -> ANNResult<Option<(&[T], usize)>> {
+    npy.as_ref()
+        .map(|npy| Ok((npy.rows::<T>()?, npy.dim())))
+        .transpose()
+}
+
+/// Compute the pruning distances of the build on the GPU when asked and there is one
+#[cfg(feature = "gpu")]
+fn with_gpu(config: IndexConfiguration, use_gpu: bool) -> ANNResult<IndexConfiguration> {
+    if !use_gpu {
+        return Ok(config);
+    }
+    match GpuDistance::new()? {
+        Some(gpu_distance) => Ok(config.with_gpu_distance(Arc::new(gpu_distance))),
+        None => {
+            println!("No GPU adapter, pruning on the CPU");
+            Ok(config)
+        }
+    }
+}
+
+/// Without the gpu feature the build prunes on the CPU
+#[cfg(not(feature = "gpu"))]
+fn with_gpu(config: IndexConfiguration, use_gpu: bool) -> ANNResult<IndexConfiguration> {
+    if use_gpu {
+        return Err(ANNError::log_index_config_error(
+            "use_gpu".to_string(),
+            "use_gpu requires build_memory_index built with the gpu feature".to_string(),
+        ));
+    }
+    Ok(config)
+}
+
 fn main() -> ANNResult<()> {
     let args = BuildMemoryIndexArgs::parse();
 
+    if args.quantize_to_int8 && args.data_type != DataType::Int8 {
+        return Err(ANNError::log_index_config_error(
+            "quantize_to_int8".to_string(),
+            "quantize_to_int8 requires data_type int8".to_string(),
+        ));
+    }
+
+    if (args.normalize || args.center || args.standardize) && args.data_type != DataType::Float {
+        return Err(ANNError::log_index_config_error(
+            "normalize".to_string(),
+            "normalize, center and standardize require data_type float".to_string(),
+        ));
+    }
+
+    let npy_extension = args
+        .data_path
+        .extension()
+        .filter(|ext| *ext == "npy" || *ext == "npz");
+    if args.npz_array.is_some() && npy_extension.is_none_or(|ext| ext != "npz") {
+        return Err(ANNError::log_index_config_error(
+            "npz_array".to_string(),
+            "npz_array names an array of an .npz data_path".to_string(),
+        ));
+    }
+
+    let npy = if npy_extension.is_some() {
+        if args.data_type == DataType::BF16
+            || args.quantize_to_int8
+            || args.int8_zero_point != 0
+            || args.normalize
+            || args.center
+            || args.standardize
+        {
+            return Err(ANNError::log_index_config_error(
+                "data_path".to_string(),
+                "npy and npz data is built from as it is, in float, fp16, int8 or binary"
+                    .to_string(),
+            ));
+        }
+        let data_path = args.data_path.to_string_lossy();
+        Some(if npy_extension.is_some_and(|ext| ext == "npz") {
+            NpyFile::open_npz(&data_path, args.npz_array.as_deref())?
+        } else {
+            NpyFile::open(&data_path)?
+        })
+    } else {
+        None
+    };
+
     let _use_pq_build = args.build_pq_bytes > 0;
 
     println!(
@@ -83,21 +257,44 @@ fn main() -> ANNResult<()> {
     );
 
     let err = match args.data_type {
-        DataType::Float => build_in_memory_index::<f32>(
-            args.dist_fn,
-            &args.data_path.to_string_lossy(),
-            args.max_degree,
-            args.l_build,
-            args.alpha,
-            &args.index_path_prefix,
-            args.num_threads,
-            _use_pq_build,
-            args.build_pq_bytes,
-            args.use_opq,
-        ),
-        DataType::FP16 => build_in_memory_index::<Half>(
+        DataType::Float => prepare_float_data(&args, &npy).and_then(|data_path| {
+            build_in_memory_index::<f32>(
+                args.dist_fn,
+                &data_path,
+                npy_rows(&npy)?,
+                args.max_degree,
+                args.l_build,
+                args.alpha,
+                &args.index_path_prefix,
+                args.num_threads,
+                _use_pq_build,
+                args.build_pq_bytes,
+                args.use_opq,
+                args.use_gpu,
+                args.format,
+            )
+        }),
+        DataType::FP16 => npy_rows(&npy).and_then(|npy_rows| {
+            build_in_memory_index::<Half>(
+                args.dist_fn,
+                &args.data_path.to_string_lossy(),
+                npy_rows,
+                args.max_degree,
+                args.l_build,
+                args.alpha,
+                &args.index_path_prefix,
+                args.num_threads,
+                _use_pq_build,
+                args.build_pq_bytes,
+                args.use_opq,
+                args.use_gpu,
+                args.format,
+            )
+        }),
+        DataType::BF16 => build_in_memory_index::<BFloat16>(
             args.dist_fn,
             &args.data_path.to_string_lossy(),
+            None,
             args.max_degree,
             args.l_build,
             args.alpha,
@@ -106,7 +303,43 @@ fn main() -> ANNResult<()> {
             _use_pq_build,
             args.build_pq_bytes,
             args.use_opq,
+            args.use_gpu,
+            args.format,
         ),
+        DataType::Int8 => prepare_int8_data(&args).and_then(|data_path| {
+            build_in_memory_index::<i8>(
+                args.dist_fn,
+                &data_path,
+                npy_rows(&npy)?,
+                args.max_degree,
+                args.l_build,
+                args.alpha,
+                &args.index_path_prefix,
+                args.num_threads,
+                _use_pq_build,
+                args.build_pq_bytes,
+                args.use_opq,
+                args.use_gpu,
+                args.format,
+            )
+        }),
+        DataType::Binary => npy_rows(&npy).and_then(|npy_rows| {
+            build_in_memory_index::<u8>(
+                args.dist_fn,
+                &args.data_path.to_string_lossy(),
+                npy_rows,
+                args.max_degree,
+                args.l_build,
+                args.alpha,
+                &args.index_path_prefix,
+                args.num_threads,
+                _use_pq_build,
+                args.build_pq_bytes,
+                args.use_opq,
+                args.use_gpu,
+                args.format,
+            )
+        }),
     };
 
     match err {
@@ -127,12 +360,22 @@ enum DataType {
     Float,
 
     /// Half data type.
+    #[value(alias = "f16")]
     FP16,
+
+    /// bfloat16 data type, as exported by PyTorch.
+    BF16,
+
+    /// int8 data type, optionally quantized from float with --quantize_to_int8.
+    Int8,
+
+    /// Packed binary codes, 8 dimensions per byte. Use with the hamming or tanimoto distance function.
+    Binary,
 }
 
 #[derive(Debug, Parser)]
 struct BuildMemoryIndexArgs {
-    /// data type <int8/uint8/float / fp16> (required)
+    /// data type <float / fp16 / bf16 / int8 / binary> (required)
     #[arg(long = "data_type", default_value = "float")]
     pub data_type: DataType,
 
@@ -141,23 +384,29 @@ struct BuildMemoryIndexArgs {
     pub dist_fn: Metric,
 
     /// Path to the data file. The file should be in the format specified by the `data_type` argument.
+    /// A .npy file holding a two dimensional array of that type is mapped and built from as it is,
+    /// as is an array of an .npz file saved by np.savez.
     #[arg(long = "data_path", short, required = true)]
     pub data_path: PathBuf,
 
+    /// Array of the .npz data file to build from, needed when it holds more than one
+    #[arg(long = "npz_array")]
+    pub npz_array: Option<String>,
+
     /// Path to the index file. The index will be saved to this prefixed name.
     #[arg(long = "index_path_prefix", short, required = true)]
     pub index_path_prefix: String,
 
     /// Number of max out degree from a vertex.
-    #[arg(long = "max_degree", short = 'R', default_value = "64")]
+    #[arg(long = "max_degree", short = 'R', default_value_t = MAX_DEGREE)]
     pub max_degree: u32,
 
     /// Number of candidates to consider when building out edges
-    #[arg(long = "l_build", short = 'L', default_value = "100")]
+    #[arg(long = "l_build", short = 'L', default_value_t = BUILD_LIST_SIZE)]
     pub l_build: u32,
 
     /// alpha controls density and diameter of graph, set 1 for sparse graph, 1.2 or 1.4 for denser graphs with lower diameter
-    #[arg(long, short, default_value = "1.2")]
+    #[arg(long, short, default_value_t = ALPHA)]
     pub alpha: f32,
 
     /// Number of threads to use.
@@ -171,4 +420,39 @@ struct BuildMemoryIndexArgs {
     /// Set true for OPQ compression while using PQ distance comparisons for building the index, and false for PQ compression
     #[arg(long = "use_opq", short, default_value = "false")]
     pub use_opq: bool,
+
+    /// Quantize the float input to int8 before building, fitting scale and zero point to the data.
+    /// The quantized data and its parameters are saved next to the index.
+    #[arg(long = "quantize_to_int8", default_value = "false")]
+    pub quantize_to_int8: bool,
+
+    /// Scale the int8 input was quantized with
+    #[arg(long = "int8_scale", default_value = "1.0")]
+    pub int8_scale: f32,
+
+    /// Zero point the int8 input was quantized with. With the cosine distance, data with a
+    /// non-zero zero point is re-centered before building.
+    #[arg(long = "int8_zero_point", default_value = "0", allow_hyphen_values = true)]
+    pub int8_zero_point: i8,
+
+    /// Scale every float vector to unit L2 norm before building. Queries are normalized the
+    /// same way by search_memory_index.
+    #[arg(long = "normalize", default_value = "false")]
+    pub normalize: bool,
+
+    /// Subtract the mean of each dimension from the float data (and the queries)
+    #[arg(long = "center", default_value = "false")]
+    pub center: bool,
+
+    /// Divide each dimension of the float data (and the queries) by its standard deviation
+    #[arg(long = "standardize", default_value = "false")]
+    pub standardize: bool,
+
+    /// Compute the pruning distances of the build on a GPU, with the gpu feature
+    #[arg(long = "use_gpu", default_value = "false")]
+    pub use_gpu: bool,
+
+    /// Format of the build summary <text/json/csv>, json and csv are printed after the index is saved
+    #[arg(long = "format", default_value = "text")]
+    pub format: OutputFormat,
 }
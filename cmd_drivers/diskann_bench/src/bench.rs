@@ -0,0 +1,281 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Instant;
+
+use diskann::{
+    common::{ANNError, ANNResult},
+    index::{ann_disk_index::ANNDiskIndex, DiskIndex},
+    instrumentation::{BuildReport, LatencyRecorder},
+    model::{
+        vertex::{DIM_104, DIM_128, DIM_256},
+        DiskIndexBuildParameters, IndexConfiguration, IndexWriteParametersBuilder, SearchParams,
+    },
+    storage::DiskIndexStorage,
+    utils::{compute_ground_truth, load_bin, load_metadata_from_file, round_up},
+};
+use serde::Serialize;
+use vector::FullPrecisionDistance;
+
+use crate::BenchArgs;
+
+/// Results of the workloads
+#[derive(Debug, Serialize)]
+pub(crate) struct BenchReport {
+    data_path: String,
+    num_points: usize,
+    dim: usize,
+    num_queries: usize,
+    recall_at: u32,
+
+    /// None when the index was built beforehand
+    build: Option<BuildBench>,
+
+    /// One run of the queries per search list size
+    search: Vec<SearchBench>,
+
+    target_recall: f64,
+
+    /// The run with the most QPS among those reaching the target recall, None if none did
+    at_target_recall: Option<SearchBench>,
+}
+
+#[derive(Debug, Serialize)]
+struct BuildBench {
+    duration_secs: f64,
+
+    /// Phases, peak memory, parameters and output sizes of the build
+    report: Option<BuildReport>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct SearchBench {
+    search_list: u32,
+    beam_width: usize,
+
+    /// Recall at K in percent
+    recall: f64,
+    qps: f64,
+    mean_latency_us: f64,
+    p50_latency_us: f64,
+    p95_latency_us: f64,
+    p99_latency_us: f64,
+    max_latency_us: f64,
+    mean_ios: f64,
+}
+
+/// Run the workloads on the dataset of the arguments
+pub(crate) fn run<T>(args: &BenchArgs) -> ANNResult<BenchReport>
+where
+    T: Default + Copy + Sync + Send + Into<f32>,
+    [T; DIM_104]: FullPrecisionDistance<T, DIM_104>,
+    [T; DIM_128]: FullPrecisionDistance<T, DIM_128>,
+    [T; DIM_256]: FullPrecisionDistance<T, DIM_256>,
+{
+    let (_, dim) = load_metadata_from_file(&args.data_path)?;
+    let aligned_dim = round_up(dim, 8);
+    match aligned_dim {
+        DIM_104 => run_with_dim::<T, DIM_104>(args),
+        DIM_128 => run_with_dim::<T, DIM_128>(args),
+        DIM_256 => run_with_dim::<T, DIM_256>(args),
+        _ => Err(ANNError::log_index_error(format!(
+            "Invalid dimension: {}",
+            aligned_dim
+        ))),
+    }
+}
+
+fn run_with_dim<T, const N: usize>(args: &BenchArgs) -> ANNResult<BenchReport>
+where
+    T: Default + Copy + Sync + Send + Into<f32>,
+    [T; N]: FullPrecisionDistance<T, N>,
+{
+    let (num_points, dim) = load_metadata_from_file(&args.data_path)?;
+    let build = if args.skip_build {
+        None
+    } else {
+        Some(bench_build::<T, N>(args, num_points, dim)?)
+    };
+
+    let (queries, num_queries, query_dim) = load_bin::<T>(&args.query_file, 0)?;
+    if query_dim != dim {
+        return Err(ANNError::log_index_error(format!(
+            "ERROR: Queries of {} have dimension {}, the points of {} have dimension {}.",
+            args.query_file, query_dim, args.data_path, dim
+        )));
+    }
+    let k = args.recall_at as usize;
+    let (truth, truth_k) = load_truth::<T, N>(args, &queries, num_queries, dim)?;
+
+    let runtime = tokio::runtime::Runtime::new()?;
+    let search = runtime.block_on(bench_search::<T, N>(args, &queries, dim, &truth, truth_k))?;
+    let at_target_recall = search
+        .iter()
+        .filter(|run| run.recall >= args.target_recall)
+        .max_by(|a, b| a.qps.total_cmp(&b.qps))
+        .cloned();
+
+    Ok(BenchReport {
+        data_path: args.data_path.clone(),
+        num_points,
+        dim,
+        num_queries,
+        recall_at: k as u32,
+        build,
+        search,
+        target_recall: args.target_recall,
+        at_target_recall,
+    })
+}
+
+/// Build the disk index and time it
+fn bench_build<T, const N: usize>(
+    args: &BenchArgs,
+    num_points: usize,
+    dim: usize,
+) -> ANNResult<BuildBench>
+where
+    T: Default + Copy + Sync + Send + Into<f32>,
+    [T; N]: FullPrecisionDistance<T, N>,
+{
+    let disk_build_param =
+        DiskIndexBuildParameters::new(args.search_dram_budget, args.build_dram_budget)?;
+    let index_write_parameters = IndexWriteParametersBuilder::new(args.l_build, args.max_degree)
+        .with_alpha(args.alpha)
+        .with_saturate_graph(true)
+        .with_num_threads(args.num_threads)
+        .try_build()?;
+    let config = IndexConfiguration::new(
+        args.dist_fn,
+        dim,
+        round_up(dim, 8),
+        num_points,
+        args.num_pq_chunks > 0,
+        args.num_pq_chunks,
+        false,
+        0,
+        1f32,
+        index_write_parameters,
+    );
+    let storage =
+        DiskIndexStorage::<T>::new(args.data_path.clone(), args.index_path_prefix.clone())?;
+    let mut index = DiskIndex::<T, N>::new(Some(disk_build_param), config, storage);
+
+    let timer = Instant::now();
+    index.build("")?;
+    Ok(BuildBench {
+        duration_secs: timer.elapsed().as_secs_f64(),
+        report: index.build_report().cloned(),
+    })
+}
+
+/// Ids of the nearest neighbors of the queries and their number per query, from the truth
+/// set or by brute force
+fn load_truth<T, const N: usize>(
+    args: &BenchArgs,
+    queries: &[T],
+    num_queries: usize,
+    dim: usize,
+) -> ANNResult<(Vec<u32>, usize)>
+where
+    T: Default + Copy + Sync + Send,
+    [T; N]: FullPrecisionDistance<T, N>,
+{
+    let k = args.recall_at as usize;
+    let (truth, num_truth_queries, truth_k) = match &args.gt_file {
+        // The distances after the ids, if any, aren't read
+        Some(gt_file) => load_bin::<u32>(gt_file, 0)?,
+        None => {
+            let (points, _, _) = load_bin::<T>(&args.data_path, 0)?;
+            let ground_truth = compute_ground_truth::<T, N>(
+                &points,
+                queries,
+                dim,
+                k,
+                args.dist_fn,
+                args.num_threads,
+            )?;
+            (ground_truth.ids, ground_truth.num_queries, k)
+        }
+    };
+    if num_truth_queries != num_queries || k == 0 || k > truth_k {
+        return Err(ANNError::log_index_config_error(
+            "recall_at".to_string(),
+            format!(
+                "The truth set has {} neighbors of {} queries, recall at {} of {} queries can't be measured",
+                truth_k, num_truth_queries, k, num_queries
+            ),
+        ));
+    }
+    Ok((truth, truth_k))
+}
+
+/// Search the queries once per search list size and measure the recall, QPS and latency
+async fn bench_search<T, const N: usize>(
+    args: &BenchArgs,
+    queries: &[T],
+    dim: usize,
+    truth: &[u32],
+    truth_k: usize,
+) -> ANNResult<Vec<SearchBench>>
+where
+    T: Default + Copy + Sync + Send + Into<f32>,
+    [T; N]: FullPrecisionDistance<T, N>,
+{
+    let storage =
+        DiskIndexStorage::<T>::new(args.data_path.clone(), args.index_path_prefix.clone())?;
+    let layout_meta = storage.load_disk_layout_meta()?;
+    let max_search_list = args.search_lists.iter().copied().max().unwrap_or_default();
+    let config = IndexConfiguration::new(
+        args.dist_fn,
+        layout_meta.dim,
+        round_up(layout_meta.dim, 8),
+        layout_meta.num_pts,
+        false,
+        0,
+        false,
+        0,
+        1f32,
+        IndexWriteParametersBuilder::new(max_search_list, 4).build(),
+    );
+    let recorder = Arc::new(LatencyRecorder::new());
+    let mut index =
+        DiskIndex::<T, N>::new(None, config, storage).with_latency_recorder(recorder.clone());
+    index.load(args.num_nodes_to_cache).await?;
+
+    let k = args.recall_at as usize;
+    let num_queries = queries.len() / dim;
+    let mut runs = Vec::with_capacity(args.search_lists.len());
+    for &search_list in args.search_lists.iter() {
+        let params = SearchParams::new(search_list, args.beam_width, None, true)?;
+        recorder.reset();
+        let mut num_found = 0;
+        let mut total_ios = 0u64;
+        let timer = Instant::now();
+        for (query, truth) in queries.chunks_exact(dim).zip(truth.chunks_exact(truth_k)) {
+            let (ids, _, stats) = index.search_with_stats(query, k, &params).await?;
+            let truth: HashSet<&u32> = truth[..k].iter().collect();
+            num_found += ids.iter().filter(|id| truth.contains(id)).count();
+            total_ios += stats.n_ios as u64;
+        }
+        let elapsed = timer.elapsed().as_secs_f64();
+
+        let latency = recorder.snapshot().total;
+        runs.push(SearchBench {
+            search_list,
+            beam_width: args.beam_width,
+            recall: 100.0 * num_found as f64 / (num_queries * k).max(1) as f64,
+            qps: num_queries as f64 / elapsed,
+            mean_latency_us: latency.mean_us,
+            p50_latency_us: latency.p50_us,
+            p95_latency_us: latency.p95_us,
+            p99_latency_us: latency.p99_us,
+            max_latency_us: latency.max_us,
+            mean_ios: total_ios as f64 / num_queries.max(1) as f64,
+        });
+    }
+    Ok(runs)
+}
@@ -0,0 +1,147 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+//! Standard workloads on a dataset, printed as JSON so runs of different releases can be
+//! compared: the time and memory of the disk index build, then for each search list size the
+//! recall, QPS and latency percentiles of the queries, and the fastest search reaching the
+//! target recall.
+mod bench;
+
+use std::fs;
+
+use clap::{Parser, ValueEnum};
+use diskann::{
+    common::{ANNError, ANNResult},
+    model::default_param_vals::{ALPHA, BUILD_LIST_SIZE, MAX_DEGREE},
+};
+use vector::{BFloat16, Half, Metric};
+
+fn main() -> ANNResult<()> {
+    let args = BenchArgs::parse();
+    let report = match args.data_type {
+        DataType::Float => bench::run::<f32>(&args)?,
+        DataType::FP16 => bench::run::<Half>(&args)?,
+        DataType::BF16 => bench::run::<BFloat16>(&args)?,
+        DataType::Int8 => bench::run::<i8>(&args)?,
+        DataType::Uint8 => bench::run::<u8>(&args)?,
+    };
+
+    let json = serde_json::to_string_pretty(&report).map_err(|err| {
+        ANNError::log_index_error(format!("ERROR: Failed to serialize the results: {}", err))
+    })?;
+    match &args.output {
+        Some(output) => fs::write(output, json)?,
+        None => println!("{}", json),
+    }
+    Ok(())
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
+enum DataType {
+    /// Float data type.
+    Float,
+
+    /// Half data type.
+    #[value(alias = "f16")]
+    FP16,
+
+    /// bfloat16 data type, as exported by PyTorch.
+    BF16,
+
+    /// int8 data type.
+    Int8,
+
+    /// uint8 data type.
+    Uint8,
+}
+
+/// Benchmark the build and the search of a disk index on a dataset
+#[derive(Debug, Parser)]
+#[command(name = "diskann-bench")]
+struct BenchArgs {
+    /// data type of the dataset and the queries
+    #[arg(long = "data_type", default_value = "float")]
+    pub data_type: DataType,
+
+    /// distance function <l2/l1/chebyshev/cosine>
+    #[arg(long = "dist_fn", default_value = "l2")]
+    pub dist_fn: Metric,
+
+    /// Input data file in bin format (required)
+    #[arg(long = "data_path", short, required = true)]
+    pub data_path: String,
+
+    /// Path prefix of the index files (required)
+    #[arg(long = "index_path_prefix", short, required = true)]
+    pub index_path_prefix: String,
+
+    /// Query file in bin format (required)
+    #[arg(long = "query_file", short, required = true)]
+    pub query_file: String,
+
+    /// Truth set of the queries, computed by brute force over the data file if not set
+    #[arg(long = "gt_file")]
+    pub gt_file: Option<String>,
+
+    /// Search the index already at the index path prefix instead of building it
+    #[arg(long = "skip_build")]
+    pub skip_build: bool,
+
+    /// Maximum graph degree
+    #[arg(long = "max_degree", short = 'R', default_value_t = MAX_DEGREE)]
+    pub max_degree: u32,
+
+    /// Build complexity, higher value results in better graphs
+    #[arg(long = "l_build", default_value_t = BUILD_LIST_SIZE)]
+    pub l_build: u32,
+
+    /// alpha controls density and diameter of the graph
+    #[arg(long, short, default_value_t = ALPHA)]
+    pub alpha: f32,
+
+    /// Number of threads used for building the index and the truth set
+    #[arg(long = "num_threads", short = 'T', default_value = "1")]
+    pub num_threads: u32,
+
+    /// DRAM budget in GB of the search, which bounds the size of the PQ codes
+    #[arg(long = "search_DRAM_budget", short = 'B', default_value = "1")]
+    pub search_dram_budget: f64,
+
+    /// DRAM budget in GB of the build
+    #[arg(long = "build_DRAM_budget", short = 'M', default_value = "4")]
+    pub build_dram_budget: f64,
+
+    /// Number of PQ chunks, derived from the search DRAM budget if 0
+    #[arg(long = "num_pq_chunks", default_value = "0")]
+    pub num_pq_chunks: usize,
+
+    /// Number of results per query
+    #[arg(long = "recall_at", short = 'K', default_value = "10")]
+    pub recall_at: u32,
+
+    /// Search list sizes to measure, comma separated
+    #[arg(
+        long = "search_lists",
+        short = 'L',
+        value_delimiter = ',',
+        default_value = "10,20,50,100"
+    )]
+    pub search_lists: Vec<u32>,
+
+    /// Nodes read per round trip to the disk
+    #[arg(long = "beam_width", short = 'W', default_value = "4")]
+    pub beam_width: usize,
+
+    /// Number of nodes around the medoid cached in memory
+    #[arg(long = "num_nodes_to_cache", default_value = "0")]
+    pub num_nodes_to_cache: usize,
+
+    /// Recall in percent the QPS is reported at
+    #[arg(long = "target_recall", default_value = "90")]
+    pub target_recall: f64,
+
+    /// File the results are written to, stdout if not set
+    #[arg(long, short)]
+    pub output: Option<String>,
+}
@@ -0,0 +1,70 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+use std::collections::HashSet;
+use std::mem::size_of;
+
+use diskann::{
+    common::{ANNError, ANNResult},
+    utils::{get_file_size, load_bin, Report},
+};
+
+use crate::EvalArgs;
+
+/// Measure the recall at K of the results of search against the truth set
+pub(crate) fn eval(args: &EvalArgs) -> ANNResult<()> {
+    let (results, num_queries, result_k) = load_bin::<u32>(&args.result_path, 0)?;
+    let (truth, num_truth_queries, truth_k) = load_truthset_ids(&args.gt_file)?;
+    let k = args.recall_at as usize;
+    if num_queries != num_truth_queries {
+        return Err(ANNError::log_index_error(format!(
+            "ERROR: {} has results of {} queries, but truth set {} has {}.",
+            args.result_path, num_queries, args.gt_file, num_truth_queries
+        )));
+    }
+    if k == 0 || k > result_k || k > truth_k {
+        return Err(ANNError::log_index_config_error(
+            "recall_at".to_string(),
+            format!(
+                "recall_at {} must be positive and at most the {} results and {} neighbors of a query",
+                k, result_k, truth_k
+            ),
+        ));
+    }
+
+    let num_found: usize = results
+        .chunks_exact(result_k)
+        .zip(truth.chunks_exact(truth_k))
+        .map(|(results, truth)| {
+            let truth: HashSet<&u32> = truth[..k].iter().collect();
+            results[..k].iter().filter(|id| truth.contains(id)).count()
+        })
+        .sum();
+    let recall = 100.0 * num_found as f64 / (num_queries * k).max(1) as f64;
+    println!("Recall@{} over {} queries: {:.2}", k, num_queries, recall);
+
+    let mut report = Report::new(&["num_queries", "recall_at", "recall"]);
+    report.add_row(vec![
+        num_queries.into(),
+        args.recall_at.into(),
+        recall.into(),
+    ])?;
+    report.print(args.format);
+
+    Ok(())
+}
+
+/// Ids of a truth set, written with or without the distances after them
+fn load_truthset_ids(filename: &str) -> ANNResult<(Vec<u32>, usize, usize)> {
+    let (ids, num_queries, k) = load_bin::<u32>(filename, 0)?;
+    let ids_size = 2 * size_of::<i32>() + num_queries * k * size_of::<u32>();
+    let file_size = get_file_size(filename)? as usize;
+    if file_size != ids_size && file_size != ids_size + num_queries * k * size_of::<f32>() {
+        return Err(ANNError::log_index_error(format!(
+            "ERROR: Truth set {} has {} bytes, not the size of {} queries of {} neighbors.",
+            filename, file_size, num_queries, k
+        )));
+    }
+    Ok((ids, num_queries, k))
+}
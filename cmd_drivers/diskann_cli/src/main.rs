@@ -0,0 +1,338 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+mod eval;
+mod replay;
+mod search;
+mod verify;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+use diskann::{
+    common::ANNResult,
+    index::ann_disk_index::build_disk_index,
+    model::{
+        default_param_vals::{ALPHA, BUILD_LIST_SIZE, MAX_DEGREE},
+        vertex::{DIM_104, DIM_128, DIM_256},
+        DiskIndexBuildParameters, IndexWriteParametersBuilder,
+    },
+    utils::{load_metadata_from_file, OutputFormat, Report, Timer},
+};
+use vector::{BFloat16, FullPrecisionDistance, Half, Metric};
+
+/// Build a disk index from the dataset
+fn build<T>(args: &BuildArgs) -> ANNResult<()>
+where
+    T: Default + Copy + Sync + Send + Into<f32>,
+    [T; DIM_104]: FullPrecisionDistance<T, DIM_104>,
+    [T; DIM_128]: FullPrecisionDistance<T, DIM_128>,
+    [T; DIM_256]: FullPrecisionDistance<T, DIM_256>,
+{
+    let disk_index_build_parameters =
+        DiskIndexBuildParameters::new(args.search_dram_budget, args.build_dram_budget)?;
+    let index_write_parameters = IndexWriteParametersBuilder::new(args.l_build, args.max_degree)
+        .with_alpha(args.alpha)
+        .with_saturate_graph(true)
+        .with_num_threads(args.num_threads)
+        .try_build()?;
+    let (num_points, dim) = load_metadata_from_file(&args.data_path)?;
+
+    let timer = Timer::new();
+    build_disk_index::<T>(
+        &args.data_path,
+        &args.index_path_prefix,
+        args.dist_fn,
+        index_write_parameters,
+        disk_index_build_parameters,
+        args.num_pq_chunks,
+    )?;
+    let indexing_time = timer.elapsed().as_secs_f64();
+    println!("Indexing time: {}", indexing_time);
+
+    let mut report = Report::new(&[
+        "data_path",
+        "num_points",
+        "dim",
+        "max_degree",
+        "l_build",
+        "num_threads",
+        "num_pq_chunks",
+        "indexing_time_s",
+    ]);
+    report.add_row(vec![
+        args.data_path.as_str().into(),
+        num_points.into(),
+        dim.into(),
+        args.max_degree.into(),
+        args.l_build.into(),
+        args.num_threads.into(),
+        args.num_pq_chunks.into(),
+        indexing_time.into(),
+    ])?;
+    report.print(args.format);
+
+    Ok(())
+}
+
+fn main() -> ANNResult<()> {
+    match Cli::parse().command {
+        Command::Build(args) => match args.data_type {
+            DataType::Float => build::<f32>(&args),
+            DataType::FP16 => build::<Half>(&args),
+            DataType::BF16 => build::<BFloat16>(&args),
+            DataType::Int8 => build::<i8>(&args),
+            DataType::Uint8 => build::<u8>(&args),
+        },
+        Command::Search(args) => match args.data_type {
+            DataType::Float => search::search_disk_index::<f32>(&args),
+            DataType::FP16 => search::search_disk_index::<Half>(&args),
+            DataType::BF16 => search::search_disk_index::<BFloat16>(&args),
+            DataType::Int8 => search::search_disk_index::<i8>(&args),
+            DataType::Uint8 => search::search_disk_index::<u8>(&args),
+        },
+        Command::Eval(args) => eval::eval(&args),
+        Command::Replay(args) => match args.data_type {
+            DataType::Float => replay::replay_query_log::<f32>(&args),
+            DataType::FP16 => replay::replay_query_log::<Half>(&args),
+            DataType::BF16 => replay::replay_query_log::<BFloat16>(&args),
+            DataType::Int8 => replay::replay_query_log::<i8>(&args),
+            DataType::Uint8 => replay::replay_query_log::<u8>(&args),
+        },
+        Command::Verify(args) => match args.data_type {
+            DataType::Float => verify::verify_disk_index::<f32>(&args),
+            DataType::FP16 => verify::verify_disk_index::<Half>(&args),
+            DataType::BF16 => verify::verify_disk_index::<BFloat16>(&args),
+            DataType::Int8 => verify::verify_disk_index::<i8>(&args),
+            DataType::Uint8 => verify::verify_disk_index::<u8>(&args),
+        },
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
+enum DataType {
+    /// Float data type.
+    Float,
+
+    /// Half data type.
+    #[value(alias = "f16")]
+    FP16,
+
+    /// bfloat16 data type, as exported by PyTorch.
+    BF16,
+
+    /// int8 data type.
+    Int8,
+
+    /// uint8 data type.
+    Uint8,
+}
+
+/// Build, search, evaluate and verify disk indexes from the command line
+#[derive(Debug, Parser)]
+#[command(name = "diskann-cli")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Build a disk index from a dataset
+    Build(BuildArgs),
+
+    /// Search the queries of a query file and write the ids of their results
+    Search(SearchArgs),
+
+    /// Measure the recall of search results against a truth set
+    Eval(EvalArgs),
+
+    /// Search the queries of a query log again and compare with the recorded results
+    Replay(ReplayArgs),
+
+    /// Check the files of a disk index for truncation and corruption
+    Verify(VerifyArgs),
+}
+
+#[derive(Debug, Args)]
+struct BuildArgs {
+    /// data type of the dataset
+    #[arg(long = "data_type", default_value = "float")]
+    pub data_type: DataType,
+
+    /// distance function <l2/l1/chebyshev/cosine>
+    #[arg(long = "dist_fn", default_value = "l2")]
+    pub dist_fn: Metric,
+
+    /// Input data file in bin format (required)
+    #[arg(long = "data_path", short, required = true)]
+    pub data_path: String,
+
+    /// Path prefix of the index files to write (required)
+    #[arg(long = "index_path_prefix", short, required = true)]
+    pub index_path_prefix: String,
+
+    /// Maximum graph degree
+    #[arg(long = "max_degree", short = 'R', default_value_t = MAX_DEGREE)]
+    pub max_degree: u32,
+
+    /// Build complexity, higher value results in better graphs
+    #[arg(long = "l_build", short = 'L', default_value_t = BUILD_LIST_SIZE)]
+    pub l_build: u32,
+
+    /// alpha controls density and diameter of the graph
+    #[arg(long, short, default_value_t = ALPHA)]
+    pub alpha: f32,
+
+    /// Number of threads used for building the index
+    #[arg(long = "num_threads", short = 'T', default_value = "1")]
+    pub num_threads: u32,
+
+    /// DRAM budget in GB of the search, which bounds the size of the PQ codes (required)
+    #[arg(long = "search_DRAM_budget", short = 'B', required = true)]
+    pub search_dram_budget: f64,
+
+    /// DRAM budget in GB of the build (required)
+    #[arg(long = "build_DRAM_budget", short = 'M', required = true)]
+    pub build_dram_budget: f64,
+
+    /// Number of PQ chunks, derived from the search DRAM budget if 0
+    #[arg(long = "num_pq_chunks", default_value = "0")]
+    pub num_pq_chunks: usize,
+
+    /// Output format <text/json/csv>
+    #[arg(long, default_value = "text")]
+    pub format: OutputFormat,
+}
+
+#[derive(Debug, Args)]
+struct SearchArgs {
+    /// data type of the dataset and the queries
+    #[arg(long = "data_type", default_value = "float")]
+    pub data_type: DataType,
+
+    /// distance function <l2/l1/chebyshev/cosine>
+    #[arg(long = "dist_fn", default_value = "l2")]
+    pub dist_fn: Metric,
+
+    /// Data file the index was built from (required)
+    #[arg(long = "data_path", short, required = true)]
+    pub data_path: String,
+
+    /// Path prefix of the index files (required)
+    #[arg(long = "index_path_prefix", short, required = true)]
+    pub index_path_prefix: String,
+
+    /// Query file in bin format (required)
+    #[arg(long = "query_file", short, required = true)]
+    pub query_file: String,
+
+    /// File the ids of the results are written to in bin format (required)
+    #[arg(long = "result_path", short, required = true)]
+    pub result_path: String,
+
+    /// Number of results per query
+    #[arg(long = "recall_at", short = 'K', default_value = "10")]
+    pub recall_at: u32,
+
+    /// Search list size
+    #[arg(long = "search_list", short = 'L', default_value = "100")]
+    pub search_list: u32,
+
+    /// Nodes read per round trip to the disk
+    #[arg(long = "beam_width", short = 'W', default_value = "4")]
+    pub beam_width: usize,
+
+    /// Number of nodes around the medoid cached in memory
+    #[arg(long = "num_nodes_to_cache", default_value = "0")]
+    pub num_nodes_to_cache: usize,
+
+    /// File the queries, their results and stats are recorded to, for replay
+    #[arg(long = "query_log")]
+    pub query_log: Option<String>,
+
+    /// Fraction of the queries recorded to the query log
+    #[arg(long = "query_log_sample_rate", default_value = "1.0")]
+    pub query_log_sample_rate: f64,
+
+    /// Output format <text/json/csv>
+    #[arg(long, default_value = "text")]
+    pub format: OutputFormat,
+}
+
+#[derive(Debug, Args)]
+struct ReplayArgs {
+    /// data type of the dataset and the queries
+    #[arg(long = "data_type", default_value = "float")]
+    pub data_type: DataType,
+
+    /// distance function <l2/l1/chebyshev/cosine>
+    #[arg(long = "dist_fn", default_value = "l2")]
+    pub dist_fn: Metric,
+
+    /// Data file the index was built from (required)
+    #[arg(long = "data_path", short, required = true)]
+    pub data_path: String,
+
+    /// Path prefix of the index files (required)
+    #[arg(long = "index_path_prefix", short, required = true)]
+    pub index_path_prefix: String,
+
+    /// Query log written by search (required)
+    #[arg(long = "query_log", short, required = true)]
+    pub query_log: String,
+
+    /// Search list size, the recorded one of each query if not set
+    #[arg(long = "search_list", short = 'L')]
+    pub search_list: Option<u32>,
+
+    /// Nodes read per round trip to the disk, the recorded ones of each query if not set
+    #[arg(long = "beam_width", short = 'W')]
+    pub beam_width: Option<usize>,
+
+    /// Number of nodes around the medoid cached in memory
+    #[arg(long = "num_nodes_to_cache", default_value = "0")]
+    pub num_nodes_to_cache: usize,
+
+    /// Output format <text/json/csv>
+    #[arg(long, default_value = "text")]
+    pub format: OutputFormat,
+}
+
+#[derive(Debug, Args)]
+struct VerifyArgs {
+    /// data type of the dataset
+    #[arg(long = "data_type", default_value = "float")]
+    pub data_type: DataType,
+
+    /// Data file the index was built from (required)
+    #[arg(long = "data_path", short, required = true)]
+    pub data_path: String,
+
+    /// Path prefix of the index files (required)
+    #[arg(long = "index_path_prefix", short, required = true)]
+    pub index_path_prefix: String,
+
+    /// Output format <text/json/csv>
+    #[arg(long, default_value = "text")]
+    pub format: OutputFormat,
+}
+
+#[derive(Debug, Args)]
+struct EvalArgs {
+    /// Ids of the results written by search (required)
+    #[arg(long = "result_path", short, required = true)]
+    pub result_path: String,
+
+    /// Truth set file, with or without distances (required)
+    #[arg(long = "gt_file", short, required = true)]
+    pub gt_file: String,
+
+    /// Number of results per query the recall is measured on
+    #[arg(long = "recall_at", short = 'K', default_value = "10")]
+    pub recall_at: u32,
+
+    /// Output format <text/json/csv>
+    #[arg(long, default_value = "text")]
+    pub format: OutputFormat,
+}
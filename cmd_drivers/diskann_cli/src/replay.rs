@@ -0,0 +1,160 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+use diskann::{
+    common::{ANNError, ANNResult},
+    index::DiskIndex,
+    instrumentation::{compare_replay, load_query_log, QueryStats, RecordedQuery},
+    model::{
+        vertex::{DIM_104, DIM_128, DIM_256},
+        IndexConfiguration, SearchParams,
+    },
+    storage::DiskIndexStorage,
+    utils::Report,
+};
+use vector::{BFloat16, FullPrecisionDistance, Half};
+
+use crate::search::search_configuration;
+use crate::ReplayArgs;
+
+/// Element types of the recorded queries, which the query log holds as f32
+pub(crate) trait FromF32 {
+    fn from_f32(value: f32) -> Self;
+}
+
+impl FromF32 for f32 {
+    fn from_f32(value: f32) -> Self {
+        value
+    }
+}
+
+impl FromF32 for Half {
+    fn from_f32(value: f32) -> Self {
+        Half::from_f32(value)
+    }
+}
+
+impl FromF32 for BFloat16 {
+    fn from_f32(value: f32) -> Self {
+        BFloat16::from_f32(value)
+    }
+}
+
+impl FromF32 for i8 {
+    fn from_f32(value: f32) -> Self {
+        value as i8
+    }
+}
+
+impl FromF32 for u8 {
+    fn from_f32(value: f32) -> Self {
+        value as u8
+    }
+}
+
+/// Search the queries of the query log again and compare the results and latency with the
+/// recorded ones
+pub(crate) fn replay_query_log<T>(args: &ReplayArgs) -> ANNResult<()>
+where
+    T: Default + Copy + Sync + Send + Into<f32> + FromF32,
+    [T; DIM_104]: FullPrecisionDistance<T, DIM_104>,
+    [T; DIM_128]: FullPrecisionDistance<T, DIM_128>,
+    [T; DIM_256]: FullPrecisionDistance<T, DIM_256>,
+{
+    let recorded = load_query_log(&args.query_log)?;
+    let search_list = recorded
+        .iter()
+        .map(|query| replay_params(&query.params, args).map(|params| params.l_value()))
+        .try_fold(0, |max, l| l.map(|l| max.max(l)))?;
+
+    let storage =
+        DiskIndexStorage::<T>::new(args.data_path.clone(), args.index_path_prefix.clone())?;
+    let config = search_configuration(&storage, args.dist_fn, search_list)?;
+
+    let runtime = tokio::runtime::Runtime::new()?;
+    match config.aligned_dim {
+        DIM_104 => runtime.block_on(replay::<T, DIM_104>(args, config, storage, &recorded)),
+        DIM_128 => runtime.block_on(replay::<T, DIM_128>(args, config, storage, &recorded)),
+        DIM_256 => runtime.block_on(replay::<T, DIM_256>(args, config, storage, &recorded)),
+        _ => Err(ANNError::log_index_error(format!(
+            "Invalid dimension: {}",
+            config.aligned_dim
+        ))),
+    }
+}
+
+/// Parameters of the recorded search with the list size and beam width of the arguments
+fn replay_params(recorded: &SearchParams, args: &ReplayArgs) -> ANNResult<SearchParams> {
+    let mut params = SearchParams::new(
+        args.search_list.unwrap_or(recorded.l_value()),
+        args.beam_width.unwrap_or(recorded.beam_width()),
+        recorded.max_ios(),
+        recorded.reorder(),
+    )?
+    .with_adaptive_prefetch(recorded.adaptive_prefetch());
+    if let Some(rerank_size) = recorded.rerank_size() {
+        params = params.with_rerank(rerank_size);
+    }
+    if let Some(patience) = recorded.patience() {
+        params = params.with_patience(patience)?;
+    }
+    Ok(params)
+}
+
+async fn replay<T, const N: usize>(
+    args: &ReplayArgs,
+    config: IndexConfiguration,
+    storage: DiskIndexStorage<T>,
+    recorded: &[RecordedQuery],
+) -> ANNResult<()>
+where
+    T: Default + Copy + Sync + Send + Into<f32> + FromF32,
+    [T; N]: FullPrecisionDistance<T, N>,
+{
+    let mut index = DiskIndex::<T, N>::new(None, config, storage);
+    index.load(args.num_nodes_to_cache).await?;
+
+    let mut replayed: Vec<(Vec<u32>, QueryStats)> = Vec::with_capacity(recorded.len());
+    for query in recorded {
+        let params = replay_params(&query.params, args)?;
+        let vector: Vec<T> = query
+            .query
+            .iter()
+            .map(|&value| T::from_f32(value))
+            .collect();
+        let (ids, _, stats) = index.search_with_stats(&vector, query.k, &params).await?;
+        replayed.push((ids, stats));
+    }
+
+    let comparison = compare_replay(recorded, &replayed)?;
+    println!(
+        "Replayed {} queries, recall against the recording {:.2}, mean latency {:.2}us (recorded {:.2}us), p99 latency {:.2}us (recorded {:.2}us)",
+        comparison.num_queries,
+        comparison.recall,
+        comparison.replayed_latency.mean_us,
+        comparison.recorded_latency.mean_us,
+        comparison.replayed_latency.p99_us,
+        comparison.recorded_latency.p99_us
+    );
+
+    let mut report = Report::new(&[
+        "num_queries",
+        "recall",
+        "mean_latency_us",
+        "recorded_mean_latency_us",
+        "p99_latency_us",
+        "recorded_p99_latency_us",
+    ]);
+    report.add_row(vec![
+        comparison.num_queries.into(),
+        comparison.recall.into(),
+        comparison.replayed_latency.mean_us.into(),
+        comparison.recorded_latency.mean_us.into(),
+        comparison.replayed_latency.p99_us.into(),
+        comparison.recorded_latency.p99_us.into(),
+    ])?;
+    report.print(args.format);
+
+    Ok(())
+}
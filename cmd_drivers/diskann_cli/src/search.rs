@@ -0,0 +1,145 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+use std::sync::Arc;
+use std::time::Instant;
+
+use diskann::{
+    common::{ANNError, ANNResult},
+    index::DiskIndex,
+    instrumentation::{LatencyRecorder, QueryRecorder},
+    model::{
+        vertex::{DIM_104, DIM_128, DIM_256},
+        IndexConfiguration, IndexWriteParametersBuilder, SearchParams,
+    },
+    storage::DiskIndexStorage,
+    utils::{load_bin, round_up, save_bin_u32, Report},
+};
+use vector::{FullPrecisionDistance, Metric};
+
+use crate::SearchArgs;
+
+/// Search the queries of the query file in the disk index and write the ids of the results
+pub(crate) fn search_disk_index<T>(args: &SearchArgs) -> ANNResult<()>
+where
+    T: Default + Copy + Sync + Send + Into<f32>,
+    [T; DIM_104]: FullPrecisionDistance<T, DIM_104>,
+    [T; DIM_128]: FullPrecisionDistance<T, DIM_128>,
+    [T; DIM_256]: FullPrecisionDistance<T, DIM_256>,
+{
+    let storage =
+        DiskIndexStorage::<T>::new(args.data_path.clone(), args.index_path_prefix.clone())?;
+    let config = search_configuration(&storage, args.dist_fn, args.search_list)?;
+
+    let runtime = tokio::runtime::Runtime::new()?;
+    match config.aligned_dim {
+        DIM_104 => runtime.block_on(search::<T, DIM_104>(args, config, storage)),
+        DIM_128 => runtime.block_on(search::<T, DIM_128>(args, config, storage)),
+        DIM_256 => runtime.block_on(search::<T, DIM_256>(args, config, storage)),
+        _ => Err(ANNError::log_index_error(format!(
+            "Invalid dimension: {}",
+            config.aligned_dim
+        ))),
+    }
+}
+
+/// Configuration of the disk index in storage for searches with lists of search_list nodes
+pub(crate) fn search_configuration<T>(
+    storage: &DiskIndexStorage<T>,
+    dist_fn: Metric,
+    search_list: u32,
+) -> ANNResult<IndexConfiguration> {
+    let layout_meta = storage.load_disk_layout_meta()?;
+    Ok(IndexConfiguration::new(
+        dist_fn,
+        layout_meta.dim,
+        round_up(layout_meta.dim, 8),
+        layout_meta.num_pts,
+        false,
+        0,
+        false,
+        0,
+        1f32,
+        IndexWriteParametersBuilder::new(search_list, 4).build(),
+    ))
+}
+
+async fn search<T, const N: usize>(
+    args: &SearchArgs,
+    config: IndexConfiguration,
+    storage: DiskIndexStorage<T>,
+) -> ANNResult<()>
+where
+    T: Default + Copy + Sync + Send + Into<f32>,
+    [T; N]: FullPrecisionDistance<T, N>,
+{
+    let recorder = Arc::new(LatencyRecorder::new());
+    let mut index =
+        DiskIndex::<T, N>::new(None, config, storage).with_latency_recorder(recorder.clone());
+    let query_recorder = match &args.query_log {
+        Some(query_log) => Some(Arc::new(QueryRecorder::create(
+            query_log,
+            args.query_log_sample_rate,
+        )?)),
+        None => None,
+    };
+    if let Some(query_recorder) = &query_recorder {
+        index = index.with_query_recorder(query_recorder.clone());
+    }
+    index.load(args.num_nodes_to_cache).await?;
+
+    let (queries, num_queries, dim) = load_bin::<T>(&args.query_file, 0)?;
+    let k = args.recall_at as usize;
+    let params = SearchParams::new(args.search_list, args.beam_width, None, true)?;
+
+    // Queries with fewer than K results are padded with u32::MAX
+    let mut result_ids = vec![u32::MAX; num_queries * k];
+    let mut total_ios = 0u64;
+    let timer = Instant::now();
+    for (query, results) in queries
+        .chunks_exact(dim)
+        .zip(result_ids.chunks_exact_mut(k))
+    {
+        let (ids, _, stats) = index.search_with_stats(query, k, &params).await?;
+        results[..ids.len()].copy_from_slice(&ids);
+        total_ios += stats.n_ios as u64;
+    }
+    let elapsed = timer.elapsed().as_secs_f64();
+    if let Some(query_recorder) = &query_recorder {
+        query_recorder.flush()?;
+    }
+
+    let qps = num_queries as f64 / elapsed;
+    let latency = recorder.snapshot().total;
+    let mean_ios = total_ios as f64 / num_queries.max(1) as f64;
+    println!(
+        "Searched {} queries, QPS {:.2}, mean latency {:.2}us, p99 latency {:.2}us, mean IOs {:.2}",
+        num_queries, qps, latency.mean_us, latency.p99_us, mean_ios
+    );
+    save_bin_u32(&args.result_path, &result_ids, num_queries, k, 0)?;
+
+    let mut report = Report::new(&[
+        "num_queries",
+        "recall_at",
+        "search_list",
+        "beam_width",
+        "qps",
+        "mean_latency_us",
+        "p99_latency_us",
+        "mean_ios",
+    ]);
+    report.add_row(vec![
+        num_queries.into(),
+        args.recall_at.into(),
+        args.search_list.into(),
+        args.beam_width.into(),
+        qps.into(),
+        latency.mean_us.into(),
+        latency.p99_us.into(),
+        mean_ios.into(),
+    ])?;
+    report.print(args.format);
+
+    Ok(())
+}
@@ -0,0 +1,60 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+use diskann::{
+    common::{ANNError, ANNResult},
+    storage::DiskIndexStorage,
+    utils::Report,
+};
+
+use crate::VerifyArgs;
+
+/// Check the files of a disk index and print what is wrong with them, failing if anything is
+pub(crate) fn verify_disk_index<T>(args: &VerifyArgs) -> ANNResult<()> {
+    let storage =
+        DiskIndexStorage::<T>::new(args.data_path.clone(), args.index_path_prefix.clone())?;
+    let verification = storage.verify()?;
+    println!(
+        "Index of {} points of dimension {}, {} edges, max degree {}, {} nodes reachable from medoid {}, {} issues",
+        verification.num_points,
+        verification.dim,
+        verification.num_edges,
+        verification.max_degree,
+        verification.num_reachable,
+        verification.medoid,
+        verification.issues.len()
+    );
+
+    let mut report = Report::new(&[
+        "num_points",
+        "dim",
+        "medoid",
+        "num_edges",
+        "max_degree",
+        "num_reachable",
+        "num_issues",
+    ]);
+    report.add_row(vec![
+        verification.num_points.into(),
+        verification.dim.into(),
+        verification.medoid.into(),
+        verification.num_edges.into(),
+        verification.max_degree.into(),
+        verification.num_reachable.into(),
+        verification.issues.len().into(),
+    ])?;
+    report.print(args.format);
+
+    for issue in verification.issues.iter() {
+        eprintln!("{}", issue);
+    }
+    if !verification.is_ok() {
+        return Err(ANNError::log_index_error(format!(
+            "ERROR: Index {} has {} issues.",
+            args.index_path_prefix,
+            verification.issues.len()
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,173 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+mod repl;
+use bytemuck::Pod;
+use diskann::{
+    common::{ANNError, ANNResult},
+    index,
+    model::{
+        configuration::index_write_parameters::{default_param_vals, IndexWriteParametersBuilder},
+        vertex::{DIM_104, DIM_1024, DIM_128, DIM_1536, DIM_256, DIM_384, DIM_768},
+        IndexConfiguration,
+    },
+    utils::{load_metadata_from_file, round_up},
+};
+use repl::{FromPromptValue, ReplSession};
+use std::{env, fs::File, io::Read, mem::size_of};
+use vector::{BFloat16, FullPrecisionDistance, Half, Metric};
+
+fn repl_memory_index<T>(
+    metric: Metric,
+    index_path: &str,
+    num_threads: u32,
+    k_value: usize,
+    l_value: u32,
+) -> ANNResult<()>
+where
+    T: Default + Copy + Sized + Pod + Sync + Send + Into<f32> + FromPromptValue,
+    [T; DIM_104]: FullPrecisionDistance<T, DIM_104>,
+    [T; DIM_128]: FullPrecisionDistance<T, DIM_128>,
+    [T; DIM_256]: FullPrecisionDistance<T, DIM_256>,
+    [T; DIM_384]: FullPrecisionDistance<T, DIM_384>,
+    [T; DIM_768]: FullPrecisionDistance<T, DIM_768>,
+    [T; DIM_1024]: FullPrecisionDistance<T, DIM_1024>,
+    [T; DIM_1536]: FullPrecisionDistance<T, DIM_1536>,
+{
+    let data_file = format!("{}.data", index_path);
+    let (index_num_points, dim) = load_metadata_from_file(&data_file)?;
+    let aligned_dim = round_up(dim as u64, 8_u64) as usize;
+    let num_frozen_pts = get_graph_num_frozen_points(index_path)?;
+
+    let index_write_params = IndexWriteParametersBuilder::new(
+        l_value.max(default_param_vals::MAX_DEGREE),
+        default_param_vals::MAX_DEGREE,
+    )
+    .with_num_threads(num_threads)
+    .build();
+
+    let index_config = IndexConfiguration::new(
+        metric,
+        dim,
+        aligned_dim,
+        index_num_points,
+        false,
+        0,
+        false,
+        num_frozen_pts,
+        1f32,
+        index_write_params,
+    );
+    let mut index = index::create_inmem_index::<T>(index_config)?;
+    index.load(index_path, index_num_points)?;
+
+    ReplSession::new(
+        index,
+        data_file,
+        index_num_points,
+        dim,
+        aligned_dim,
+        k_value,
+        l_value,
+    )
+    .run()
+}
+
+fn get_graph_num_frozen_points(graph_file: &str) -> ANNResult<usize> {
+    let mut file = File::open(graph_file)?;
+    let mut usize_buffer = [0; size_of::<usize>()];
+    let mut u32_buffer = [0; size_of::<u32>()];
+
+    file.read_exact(&mut usize_buffer)?;
+    file.read_exact(&mut u32_buffer)?;
+    file.read_exact(&mut u32_buffer)?;
+    file.read_exact(&mut usize_buffer)?;
+
+    Ok(usize::from_le_bytes(usize_buffer))
+}
+
+fn main() -> ANNResult<()> {
+    let mut data_type: String = String::new();
+    let mut metric: Option<Metric> = None;
+    let mut index_path: String = String::new();
+    let mut num_threads: u32 = 1;
+    let mut k_value: usize = 10;
+    let mut l_value: u32 = 100;
+
+    let args: Vec<String> = env::args().collect();
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        let ann_error =
+            || ANNError::log_index_config_error(String::from(arg), format!("Missing {}", arg));
+        let parse_error = |err: std::num::ParseIntError| {
+            ANNError::log_index_config_error(String::from(arg), format!("ParseError: {}", err))
+        };
+        match arg.as_str() {
+            "--help" | "-h" => {
+                print_help();
+                return Ok(());
+            }
+            "--data_type" => {
+                data_type = iter.next().ok_or_else(ann_error)?.to_owned();
+            }
+            "--dist_fn" => {
+                metric = Some(iter.next().ok_or_else(ann_error)?.parse().map_err(|err| {
+                    ANNError::log_index_config_error(
+                        String::from(arg),
+                        format!("ParseError: {}", err),
+                    )
+                })?);
+            }
+            "--index_path_prefix" => {
+                index_path = iter.next().ok_or_else(ann_error)?.to_owned();
+            }
+            "--recall_at" | "-K" => {
+                k_value = iter.next().ok_or_else(ann_error)?.parse().map_err(parse_error)?;
+            }
+            "--search_list" | "-L" => {
+                l_value = iter.next().ok_or_else(ann_error)?.parse().map_err(parse_error)?;
+            }
+            "--num_threads" => {
+                num_threads = iter.next().ok_or_else(ann_error)?.parse().map_err(parse_error)?;
+            }
+            _ => {
+                return Err(ANNError::log_index_error(format!(
+                    "Unknown argument: {}",
+                    arg
+                )));
+            }
+        }
+    }
+
+    let metric = metric.ok_or_else(|| ANNError::log_index_error(String::from("No metric given!")))?;
+    if index_path.is_empty() {
+        return Err(ANNError::log_index_error(String::from(
+            "No index_path_prefix given!",
+        )));
+    }
+
+    match data_type.as_str() {
+        "float" => repl_memory_index::<f32>(metric, &index_path, num_threads, k_value, l_value),
+        "int8" => repl_memory_index::<i8>(metric, &index_path, num_threads, k_value, l_value),
+        "uint8" => repl_memory_index::<u8>(metric, &index_path, num_threads, k_value, l_value),
+        "f16" => repl_memory_index::<Half>(metric, &index_path, num_threads, k_value, l_value),
+        "bf16" => repl_memory_index::<BFloat16>(metric, &index_path, num_threads, k_value, l_value),
+        _ => Err(ANNError::log_index_error(format!(
+            "Unknown data type: {}!",
+            data_type
+        ))),
+    }
+}
+
+fn print_help() {
+    println!("Interactive queries against an in-memory index");
+    println!("Arguments");
+    println!("--help, -h                Print information on arguments");
+    println!("--data_type               data type <int8/uint8/float/f16/bf16> (required)");
+    println!("--dist_fn                 distance function <l2/l1/chebyshev/cosine/hamming/tanimoto> (required)");
+    println!("--index_path_prefix       Path prefix to the index (required)");
+    println!("--recall_at, -K           Initial number of results per query (default: 10)");
+    println!("--search_list, -L         Initial search list size (default: 100)");
+    println!("--num_threads             Number of threads used by the index (default: 1)");
+}
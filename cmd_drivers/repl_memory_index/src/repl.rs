@@ -0,0 +1,343 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+use bytemuck::{cast_slice, Pod};
+use diskann::{
+    common::{ANNError, ANNResult},
+    index::ANNInmemIndex,
+    instrumentation::QueryStats,
+    model::{SearchResult, SearchResultFields},
+};
+use std::fs::File;
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+use std::mem::size_of;
+use vector::{BFloat16, Half};
+
+/// Conversion of a value typed at the prompt into the index data type
+pub(crate) trait FromPromptValue: Sized {
+    fn from_prompt_value(value: f32) -> Self;
+}
+
+impl FromPromptValue for f32 {
+    fn from_prompt_value(value: f32) -> Self {
+        value
+    }
+}
+
+impl FromPromptValue for Half {
+    fn from_prompt_value(value: f32) -> Self {
+        Half::from_f32(value)
+    }
+}
+
+impl FromPromptValue for BFloat16 {
+    fn from_prompt_value(value: f32) -> Self {
+        BFloat16::from_f32(value)
+    }
+}
+
+impl FromPromptValue for i8 {
+    fn from_prompt_value(value: f32) -> Self {
+        value as i8
+    }
+}
+
+impl FromPromptValue for u8 {
+    fn from_prompt_value(value: f32) -> Self {
+        value as u8
+    }
+}
+
+/// One line typed at the prompt
+enum Command {
+    Help,
+    Quit,
+    Show,
+    Set(String, String),
+    Explain(bool),
+    QueryId(u32),
+    QueryVector(Vec<f32>),
+}
+
+fn parse_command(line: &str) -> Result<Command, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words.as_slice() {
+        ["help"] | ["?"] => Ok(Command::Help),
+        ["quit"] | ["exit"] => Ok(Command::Quit),
+        ["show"] => Ok(Command::Show),
+        ["set", name, value] => Ok(Command::Set(name.to_string(), value.to_string())),
+        ["explain", "on"] => Ok(Command::Explain(true)),
+        ["explain", "off"] => Ok(Command::Explain(false)),
+        ["id", id] => id
+            .parse()
+            .map(Command::QueryId)
+            .map_err(|err| format!("Invalid id {}: {}", id, err)),
+        _ => line
+            .split(|c: char| c == ',' || c.is_whitespace() || c == '[' || c == ']')
+            .filter(|value| !value.is_empty())
+            .map(|value| {
+                value
+                    .parse::<f32>()
+                    .map_err(|err| format!("Invalid vector value {}: {}", value, err))
+            })
+            .collect::<Result<Vec<f32>, String>>()
+            .map(Command::QueryVector),
+    }
+}
+
+/// Interactive session over a loaded in-memory index
+pub(crate) struct ReplSession<T>
+where
+    T: Default + Copy + Sync + Send + Into<f32>,
+{
+    index: Box<dyn ANNInmemIndex<T>>,
+    data_file: String,
+    num_points: usize,
+    dim: usize,
+    aligned_dim: usize,
+    k_value: usize,
+    l_value: u32,
+    fields: SearchResultFields,
+    explain: bool,
+}
+
+impl<T> ReplSession<T>
+where
+    T: Default + Copy + Sync + Send + Into<f32> + Pod + FromPromptValue,
+{
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        index: Box<dyn ANNInmemIndex<T>>,
+        data_file: String,
+        num_points: usize,
+        dim: usize,
+        aligned_dim: usize,
+        k_value: usize,
+        l_value: u32,
+    ) -> Self {
+        Self {
+            index,
+            data_file,
+            num_points,
+            dim,
+            aligned_dim,
+            k_value,
+            l_value,
+            fields: SearchResultFields::NONE,
+            explain: false,
+        }
+    }
+
+    /// Read commands from stdin until quit or end of input
+    pub(crate) fn run(&mut self) -> ANNResult<()> {
+        println!("Loaded {} points of dimension {}. Type help for commands.", self.num_points, self.dim);
+
+        let stdin = io::stdin();
+        let mut lines = stdin.lock().lines();
+        loop {
+            print!("diskann> ");
+            io::stdout().flush()?;
+
+            let line = match lines.next() {
+                Some(line) => line?,
+                None => break,
+            };
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let command = match parse_command(line) {
+                Ok(command) => command,
+                Err(err) => {
+                    println!("{}", err);
+                    continue;
+                }
+            };
+
+            let outcome = match command {
+                Command::Quit => break,
+                Command::Help => {
+                    print_commands();
+                    Ok(())
+                }
+                Command::Show => {
+                    self.print_settings();
+                    Ok(())
+                }
+                Command::Set(name, value) => self.set(&name, &value),
+                Command::Explain(explain) => {
+                    self.explain = explain;
+                    Ok(())
+                }
+                Command::QueryId(id) => self
+                    .read_point(id)
+                    .and_then(|query| self.query(&query)),
+                Command::QueryVector(values) => self.query_values(&values),
+            };
+
+            // Errors are reported and the session keeps going
+            if let Err(err) = outcome {
+                println!("Error: {}", err);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn set(&mut self, name: &str, value: &str) -> ANNResult<()> {
+        let parse_error =
+            |err: std::num::ParseIntError| ANNError::log_index_config_error(name.to_string(), err.to_string());
+
+        match name {
+            "k" | "K" => self.k_value = value.parse().map_err(parse_error)?,
+            "l" | "L" => self.l_value = value.parse().map_err(parse_error)?,
+            "fields" => {
+                self.fields = match value {
+                    "none" => SearchResultFields::NONE,
+                    "labels" => SearchResultFields {
+                        labels: true,
+                        ..SearchResultFields::NONE
+                    },
+                    "payload" => SearchResultFields {
+                        payload: true,
+                        ..SearchResultFields::NONE
+                    },
+                    "tags" => SearchResultFields {
+                        tags: true,
+                        ..SearchResultFields::NONE
+                    },
+                    "all" => SearchResultFields::ALL,
+                    _ => {
+                        return Err(ANNError::log_index_config_error(
+                            name.to_string(),
+                            format!("Unknown fields {}, use none/labels/payload/tags/all", value),
+                        ))
+                    }
+                }
+            }
+            _ => {
+                return Err(ANNError::log_index_config_error(
+                    name.to_string(),
+                    "Unknown setting, use k, L or fields".to_string(),
+                ))
+            }
+        }
+
+        self.print_settings();
+        Ok(())
+    }
+
+    fn print_settings(&self) {
+        println!(
+            "K: {}  L: {}  labels: {}  payload: {}  explain: {}",
+            self.k_value, self.l_value, self.fields.labels, self.fields.payload, self.explain
+        );
+    }
+
+    fn query_values(&self, values: &[f32]) -> ANNResult<()> {
+        if values.len() != self.dim {
+            return Err(ANNError::log_index_error(format!(
+                "Query has {} values, the index dimension is {}",
+                values.len(),
+                self.dim
+            )));
+        }
+
+        let mut query = vec![T::default(); self.aligned_dim];
+        for (dst, value) in query.iter_mut().zip(values) {
+            *dst = T::from_prompt_value(*value);
+        }
+        self.query(&query)
+    }
+
+    /// Use a point of the data file as query, padded to the aligned dimension
+    fn read_point(&self, id: u32) -> ANNResult<Vec<T>> {
+        if id as usize >= self.num_points {
+            return Err(ANNError::log_index_error(format!(
+                "Id {} is out of range, the index has {} points",
+                id, self.num_points
+            )));
+        }
+
+        let row_size = self.dim * size_of::<T>();
+        let mut file = File::open(&self.data_file)?;
+        file.seek(SeekFrom::Start((2 * size_of::<i32>() + id as usize * row_size) as u64))?;
+        let mut buffer = vec![0u8; row_size];
+        file.read_exact(&mut buffer)?;
+
+        let mut query = vec![T::default(); self.aligned_dim];
+        query[..self.dim].copy_from_slice(cast_slice::<u8, T>(&buffer));
+        Ok(query)
+    }
+
+    fn query(&self, query: &[T]) -> ANNResult<()> {
+        let (results, stats) =
+            self.index
+                .search_with_details(query, self.k_value, self.l_value, self.fields)?;
+
+        print_results(&results);
+        if self.explain {
+            print_explain(&stats, results.len(), self.k_value, self.l_value);
+        } else {
+            println!(
+                "{} results, {} hops, {} distance comparisons, {:.2} us",
+                results.len(),
+                stats.n_hops,
+                stats.n_cmps,
+                stats.total_us
+            );
+        }
+
+        Ok(())
+    }
+}
+
+fn print_results(results: &[SearchResult]) {
+    println!("{:>6}{:>12}{:>16}", "Rank", "Id", "Distance");
+    for (rank, result) in results.iter().enumerate() {
+        let mut line = format!("{:>6}{:>12}{:>16.4}", rank, result.id, result.distance);
+        if let Some(labels) = &result.labels {
+            line.push_str(&format!("  labels: {:?}", labels));
+        }
+        if let Some(payload) = &result.payload {
+            line.push_str(&format!("  payload: {} bytes", payload.len()));
+        }
+        if let Some(tag) = &result.tag {
+            line.push_str(&format!("  tag: {}", tag));
+        }
+        println!("{}", line);
+    }
+}
+
+fn print_explain(stats: &QueryStats, num_results: usize, k_value: usize, l_value: u32) {
+    println!("Search explained");
+    println!("  K requested / returned        {} / {}", k_value, num_results);
+    println!("  search list size (L)          {}", l_value);
+    println!("  nodes expanded (hops)         {}", stats.n_hops);
+    println!("  distance comparisons          {}", stats.n_cmps);
+    if stats.n_hops > 0 {
+        println!(
+            "  comparisons per hop           {:.2}",
+            stats.n_cmps as f64 / stats.n_hops as f64
+        );
+    }
+    println!("  total time (us)               {:.2}", stats.total_us);
+    println!("  cpu time (us)                 {:.2}", stats.cpu_us);
+    println!("  io time (us)                  {:.2}", stats.io_us);
+    println!("  sectors read                  {}", stats.n_ios);
+}
+
+fn print_commands() {
+    println!("Commands");
+    println!("<v1> <v2> ...            Query with a vector, values separated by spaces or commas");
+    println!("id <n>                   Query with the vector of point n in the index");
+    println!("set k <n>                Number of results to return");
+    println!("set L <n>                Search list size");
+    println!("set fields <f>           Result fields to show <none/labels/payload/tags/all>");
+    println!("explain on|off           Show the full query statistics after each query");
+    println!("show                     Print the current settings");
+    println!("help                     Print this message");
+    println!("quit                     Leave the REPL");
+}
@@ -3,19 +3,23 @@
  * Licensed under the MIT license.
  */
 mod search_index_utils;
+mod warm_cold;
 use bytemuck::Pod;
 use diskann::{
     common::{ANNError, ANNResult},
     index,
     model::{
         configuration::index_write_parameters::{default_param_vals, IndexWriteParametersBuilder},
-        vertex::{DIM_104, DIM_128, DIM_256},
+        vertex::{DIM_104, DIM_1024, DIM_128, DIM_1536, DIM_256, DIM_384, DIM_768},
         IndexConfiguration,
     },
-    utils::{load_metadata_from_file, save_bin_u32},
+    utils::{
+        build_thread_pool, load_metadata_from_file, save_bin_u32, OutputFormat, Preprocessing,
+        Report,
+    },
 };
 use std::{env, path::Path, process::exit, time::Instant};
-use vector::{FullPrecisionDistance, Half, Metric};
+use vector::{BFloat16, FullPrecisionDistance, Half, Metric};
 
 use rayon::prelude::*;
 
@@ -32,12 +36,20 @@ fn search_memory_index<T>(
     l_vec: &Vec<u32>,
     show_qps_per_thread: bool,
     fail_if_recall_below: f32,
+    num_frontiers: usize,
+    format: OutputFormat,
+    warm_cold: bool,
+    drop_page_cache: bool,
 ) -> ANNResult<i32>
 where
     T: Default + Copy + Sized + Pod + Sync + Send + Into<f32>,
     [T; DIM_104]: FullPrecisionDistance<T, DIM_104>,
     [T; DIM_128]: FullPrecisionDistance<T, DIM_128>,
     [T; DIM_256]: FullPrecisionDistance<T, DIM_256>,
+    [T; DIM_384]: FullPrecisionDistance<T, DIM_384>,
+    [T; DIM_768]: FullPrecisionDistance<T, DIM_768>,
+    [T; DIM_1024]: FullPrecisionDistance<T, DIM_1024>,
+    [T; DIM_1536]: FullPrecisionDistance<T, DIM_1536>,
 {
     // Load the query file
     let (query, query_num, query_dim, query_aligned_dim) =
@@ -90,7 +102,25 @@ where
         num_frozen_pts,
         1f32,
         index_write_params,
-    );
+    )
+    .with_num_search_frontiers(num_frontiers);
+
+    if warm_cold {
+        warm_cold::search_warm_cold::<T>(
+            index_config,
+            index_path,
+            index_num_points,
+            &query,
+            query_num,
+            query_aligned_dim,
+            recall_at,
+            l_vec,
+            drop_page_cache,
+            format,
+        )?;
+        return Ok(0);
+    }
+
     let mut index = index::create_inmem_index::<T>(index_config)?;
 
     index.load(index_path, index_num_points)?;
@@ -121,13 +151,25 @@ where
     println!("{}", table_header_str);
     println!("{}", "=".repeat(table_width));
 
+    let mut report_columns = vec![
+        "L".to_string(),
+        "qps".to_string(),
+        "avg_dist_cmps".to_string(),
+        "mean_latency_us".to_string(),
+        "p999_latency_us".to_string(),
+    ];
+    if calc_recall_flag {
+        report_columns.extend((first_recall..=recall_at).map(|r| format!("recall@{}", r)));
+    }
+    let mut report = Report::new(&report_columns.iter().map(String::as_str).collect::<Vec<_>>());
+
     let mut query_result_ids: Vec<Vec<u32>> =
         vec![vec![0; query_num * recall_at as usize]; l_vec.len()];
     let mut latency_stats: Vec<f32> = vec![0.0; query_num];
     let mut cmp_stats: Vec<u32> = vec![0; query_num];
     let mut best_recall = 0.0;
 
-    std::env::set_var("RAYON_NUM_THREADS", num_threads.to_string());
+    let pool = build_thread_pool(num_threads)?;
 
     for test_id in 0..l_vec.len() {
         let l_value = l_vec[test_id];
@@ -147,15 +189,17 @@ where
             .zip(query.par_chunks(query_aligned_dim));
 
         let start = Instant::now();
-        zipped.for_each(|(((cmp, latency), query_result), query_chunk)| {
-            let query_start = Instant::now();
-            *cmp = index
-                .search(query_chunk, recall_at as usize, l_value, query_result)
-                .unwrap();
-
-            let query_end = Instant::now();
-            let diff = query_end.duration_since(query_start);
-            *latency = diff.as_micros() as f32;
+        pool.install(|| {
+            zipped.for_each(|(((cmp, latency), query_result), query_chunk)| {
+                let query_start = Instant::now();
+                *cmp = index
+                    .search(query_chunk, recall_at as usize, l_value, query_result)
+                    .unwrap();
+
+                let query_end = Instant::now();
+                let diff = query_end.duration_since(query_start);
+                *latency = diff.as_micros() as f32;
+            })
         });
         let diff = Instant::now().duration_since(start);
 
@@ -184,15 +228,22 @@ where
         let mean_latency = latency_stats.iter().sum::<f32>() / query_num as f32;
         let avg_cmps = cmp_stats.iter().sum::<u32>() as f32 / query_num as f32;
 
+        let p999_latency = latency_stats[(0.999 * query_num as f32).round() as usize];
         let mut stat_str = format!(
             "{: >4}{: >12.2}{: >18.2}{: >20.2}{: >15.2}",
-            l_value,
-            displayed_qps,
-            avg_cmps,
-            mean_latency,
-            latency_stats[(0.999 * query_num as f32).round() as usize]
+            l_value, displayed_qps, avg_cmps, mean_latency, p999_latency
         );
 
+        let mut report_row = vec![
+            l_value.into(),
+            displayed_qps.into(),
+            avg_cmps.into(),
+            mean_latency.into(),
+            p999_latency.into(),
+        ];
+        report_row.extend(recalls.iter().map(|recall| (*recall).into()));
+        report.add_row(report_row)?;
+
         for recall in recalls.iter() {
             stat_str.push_str(&format!("{: >12.2}", recall));
             best_recall = f32::max(best_recall, *recall);
@@ -220,6 +271,8 @@ where
         )?;
     }
 
+    report.print(format);
+
     if best_recall >= fail_if_recall_below {
         Ok(0)
     } else {
@@ -227,6 +280,27 @@ where
     }
 }
 
+/// Apply the preprocessing the index was built with to the float queries, if there is one.
+/// Returns the query file to search with.
+fn preprocess_queries(
+    index_path: &str,
+    query_file: &str,
+    result_path_prefix: &str,
+) -> ANNResult<String> {
+    let params_path = format!("{}_preprocessing.bin", index_path);
+    if !Path::new(&params_path).exists() {
+        return Ok(query_file.to_string());
+    }
+
+    let preprocessed_file = format!("{}_preprocessed_queries.bin", result_path_prefix);
+    Preprocessing::load(&params_path)?.preprocess_f32_bin(query_file, &preprocessed_file)?;
+    println!(
+        "Preprocessed queries with {} into {}",
+        params_path, preprocessed_file
+    );
+    Ok(preprocessed_file)
+}
+
 fn main() -> ANNResult<()> {
     let return_val: i32;
     {
@@ -242,6 +316,10 @@ fn main() -> ANNResult<()> {
         let mut l_vec: Vec<u32> = Vec::new();
         let mut show_qps_per_thread: bool = false;
         let mut fail_if_recall_below: f32 = 0.0;
+        let mut num_frontiers: usize = 1;
+        let mut format = OutputFormat::Text;
+        let mut warm_cold: bool = false;
+        let mut drop_page_cache: bool = false;
 
         let args: Vec<String> = env::args().collect();
         let mut iter = args.iter().skip(1).peekable();
@@ -318,6 +396,23 @@ fn main() -> ANNResult<()> {
                             )
                         })?;
                 }
+                "--num_frontiers" => {
+                    num_frontiers = iter.next().ok_or_else(ann_error)?.parse().map_err(|err| {
+                        ANNError::log_index_config_error(
+                            String::from(arg),
+                            format!("ParseError: {}", err),
+                        )
+                    })?;
+                }
+                "--format" => {
+                    format = iter.next().ok_or_else(ann_error)?.parse()?;
+                }
+                "--warm_cold" => {
+                    warm_cold = true;
+                }
+                "--drop_page_cache" => {
+                    drop_page_cache = true;
+                }
                 _ => {
                     return Err(ANNError::log_index_error(format!(
                         "Unknown argument: {}",
@@ -327,6 +422,13 @@ fn main() -> ANNResult<()> {
             }
         }
 
+        if drop_page_cache && !warm_cold {
+            return Err(ANNError::log_index_config_error(
+                String::from("--drop_page_cache"),
+                String::from("--drop_page_cache is only used with --warm_cold"),
+            ));
+        }
+
         if metric.is_none() {
             return Err(ANNError::log_index_error(String::from("No metric given!")));
         } else if recall_at.is_none() {
@@ -339,6 +441,7 @@ fn main() -> ANNResult<()> {
         // but keep the structure in place here for future data types
         match data_type.as_str() {
             "float" => {
+                let query_file = preprocess_queries(&index_path, &query_file, &result_path_prefix)?;
                 return_val = search_memory_index::<f32>(
                     metric.unwrap(),
                     &index_path,
@@ -351,6 +454,10 @@ fn main() -> ANNResult<()> {
                     &l_vec,
                     show_qps_per_thread,
                     fail_if_recall_below,
+                    num_frontiers,
+                    format,
+                    warm_cold,
+                    drop_page_cache,
                 )?;
             }
             "int8" => {
@@ -366,6 +473,10 @@ fn main() -> ANNResult<()> {
                     &l_vec,
                     show_qps_per_thread,
                     fail_if_recall_below,
+                    num_frontiers,
+                    format,
+                    warm_cold,
+                    drop_page_cache,
                 )?;
             }
             "uint8" => {
@@ -381,6 +492,10 @@ fn main() -> ANNResult<()> {
                     &l_vec,
                     show_qps_per_thread,
                     fail_if_recall_below,
+                    num_frontiers,
+                    format,
+                    warm_cold,
+                    drop_page_cache,
                 )?;
             }
             "f16" => {
@@ -396,6 +511,29 @@ fn main() -> ANNResult<()> {
                     &l_vec,
                     show_qps_per_thread,
                     fail_if_recall_below,
+                    num_frontiers,
+                    format,
+                    warm_cold,
+                    drop_page_cache,
+                )?;
+            }
+            "bf16" => {
+                return_val = search_memory_index::<BFloat16>(
+                    metric.unwrap(),
+                    &index_path,
+                    &result_path_prefix,
+                    &query_file,
+                    &truthset_file,
+                    num_cpus,
+                    recall_at.unwrap(),
+                    print_all_recalls,
+                    &l_vec,
+                    show_qps_per_thread,
+                    fail_if_recall_below,
+                    num_frontiers,
+                    format,
+                    warm_cold,
+                    drop_page_cache,
                 )?;
             }
             _ => {
@@ -415,8 +553,8 @@ fn main() -> ANNResult<()> {
 fn print_help() {
     println!("Arguments");
     println!("--help, -h                Print information on arguments");
-    println!("--data_type               data type <int8/uint8/float> (required)");
-    println!("--dist_fn                 distance function <l2/cosine> (required)");
+    println!("--data_type               data type <int8/uint8/float/f16/bf16> (required)");
+    println!("--dist_fn                 distance function <l2/l1/chebyshev/cosine/hamming/tanimoto> (required)");
     println!("--index_path_prefix       Path prefix to the index (required)");
     println!("--result_path             Path prefix for saving results of the queries (required)");
     println!("--query_file              Query file in binary format");
@@ -427,4 +565,8 @@ fn print_help() {
     println!("----num_threads, -T       Number of threads used for building index (defaults to num_cpus::get())");
     println!("--qps_per_thread          Print overall QPS divided by the number of threads in the output table");
     println!("--fail_if_recall_below    If set to a value >0 and <100%, program returns -1 if best recall found is below this threshold");
+    println!("--num_frontiers           Number of frontiers each search starts from, more frontiers trade latency for recall (default: 1)");
+    println!("--format                  Format of the results table <text/json/csv>, json and csv are printed at the end of the run (default: text)");
+    println!("--warm_cold               Reload the index for every L and report the latency of the first (cold) and second (warm) pass over the queries");
+    println!("--drop_page_cache         With --warm_cold, evict the index files from the OS page cache before every reload (Linux only)");
 }
@@ -0,0 +1,159 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+//! Cold vs warm latency. For every L the index is loaded again, optionally after evicting its
+//! files from the page cache, and the queries are searched twice: the first pass runs on a
+//! freshly loaded index with cold CPU caches and TLBs, the second one on the warmed-up index.
+//! Both passes are reported, the cold one with the time it took to load the index.
+
+use bytemuck::Pod;
+use diskann::{
+    common::{ANNError, ANNResult},
+    index::{self, ANNInmemIndex},
+    model::{
+        vertex::{DIM_1024, DIM_104, DIM_128, DIM_1536, DIM_256, DIM_384, DIM_768},
+        IndexConfiguration,
+    },
+    utils::{OutputFormat, Report},
+};
+use platform::evict_from_page_cache;
+use rayon::prelude::*;
+use std::time::Instant;
+use vector::FullPrecisionDistance;
+
+/// Latency summary of one pass over the queries
+struct PassStats {
+    qps: f32,
+    mean_latency: f32,
+    p999_latency: f32,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn search_warm_cold<T>(
+    index_config: IndexConfiguration,
+    index_path: &str,
+    index_num_points: usize,
+    query: &[T],
+    query_num: usize,
+    query_aligned_dim: usize,
+    recall_at: u32,
+    l_vec: &[u32],
+    drop_page_cache: bool,
+    format: OutputFormat,
+) -> ANNResult<()>
+where
+    T: Default + Copy + Sized + Pod + Sync + Send + Into<f32>,
+    [T; DIM_104]: FullPrecisionDistance<T, DIM_104>,
+    [T; DIM_128]: FullPrecisionDistance<T, DIM_128>,
+    [T; DIM_256]: FullPrecisionDistance<T, DIM_256>,
+    [T; DIM_384]: FullPrecisionDistance<T, DIM_384>,
+    [T; DIM_768]: FullPrecisionDistance<T, DIM_768>,
+    [T; DIM_1024]: FullPrecisionDistance<T, DIM_1024>,
+    [T; DIM_1536]: FullPrecisionDistance<T, DIM_1536>,
+{
+    println!(
+        "{:>4}{:>8}{:>14}{:>12}{:>20}{:>15}",
+        "Ls", "Cache", "Load time (s)", "QPS", "Mean Latency (mus)", "99.9 Latency"
+    );
+    println!("{}", "=".repeat(4 + 8 + 14 + 12 + 20 + 15));
+
+    let mut report = Report::new(&[
+        "L",
+        "cache",
+        "load_time_s",
+        "qps",
+        "mean_latency_us",
+        "p999_latency_us",
+    ]);
+
+    for &l_value in l_vec {
+        if l_value < recall_at {
+            println!(
+                "Ignoring search with L:{} since it's smaller than K:{}",
+                l_value, recall_at
+            );
+            continue;
+        }
+
+        if drop_page_cache {
+            for file in [index_path.to_string(), format!("{}.data", index_path)] {
+                evict_from_page_cache(&file)?;
+            }
+        }
+
+        let load_start = Instant::now();
+        let mut index = index::create_inmem_index::<T>(index_config.clone())?;
+        index.load(index_path, index_num_points)?;
+        let load_time = load_start.elapsed().as_secs_f32();
+
+        for (cache, load_time) in [("cold", Some(load_time)), ("warm", None)] {
+            let stats = search_pass(
+                index.as_ref(),
+                query,
+                query_num,
+                query_aligned_dim,
+                recall_at,
+                l_value,
+            )?;
+            let load_time_str = load_time.map_or("-".to_string(), |t| format!("{:.3}", t));
+            println!(
+                "{: >4}{: >8}{: >14}{: >12.2}{: >20.2}{: >15.2}",
+                l_value, cache, load_time_str, stats.qps, stats.mean_latency, stats.p999_latency
+            );
+            report.add_row(vec![
+                l_value.into(),
+                cache.into(),
+                load_time.unwrap_or(f32::NAN).into(),
+                stats.qps.into(),
+                stats.mean_latency.into(),
+                stats.p999_latency.into(),
+            ])?;
+        }
+    }
+
+    report.print(format);
+    Ok(())
+}
+
+fn search_pass<T>(
+    index: &dyn ANNInmemIndex<T>,
+    query: &[T],
+    query_num: usize,
+    query_aligned_dim: usize,
+    recall_at: u32,
+    l_value: u32,
+) -> ANNResult<PassStats>
+where
+    T: Default + Copy + Sync + Send + Into<f32>,
+{
+    if query_num == 0 {
+        return Err(ANNError::log_index_error(
+            "The query file has no queries".to_string(),
+        ));
+    }
+
+    let mut latency_stats: Vec<f32> = vec![0.0; query_num];
+    let mut result_ids = vec![0u32; query_num * recall_at as usize];
+
+    let start = Instant::now();
+    latency_stats
+        .par_iter_mut()
+        .zip(result_ids.par_chunks_mut(recall_at as usize))
+        .zip(query.par_chunks(query_aligned_dim))
+        .try_for_each(|((latency, query_result), query_chunk)| {
+            let query_start = Instant::now();
+            index.search(query_chunk, recall_at as usize, l_value, query_result)?;
+            *latency = query_start.elapsed().as_micros() as f32;
+            ANNResult::Ok(())
+        })?;
+    let elapsed = start.elapsed().as_secs_f32();
+
+    latency_stats.sort_by(|a, b| a.total_cmp(b));
+    let p999_index = ((0.999 * query_num as f32).round() as usize).min(query_num - 1);
+    Ok(PassStats {
+        qps: query_num as f32 / elapsed,
+        mean_latency: latency_stats.iter().sum::<f32>() / query_num as f32,
+        p999_latency: latency_stats[p999_index],
+    })
+}
@@ -0,0 +1,10 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed=proto/diskann_search.proto");
+    let file_descriptors = protox::compile(["diskann_search.proto"], ["proto"])?;
+    tonic_build::configure().compile_fds(file_descriptors)?;
+    Ok(())
+}
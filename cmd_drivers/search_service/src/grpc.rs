@@ -0,0 +1,276 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! gRPC server of the service, generated from proto/diskann_search.proto by tonic.
+//!
+//! The handlers block, on the index lock or on their batch of searches, so each call runs on
+//! the blocking threads of the tokio runtime and leaves its workers to the connections.
+//! WatchEvents streams the events of the notifier of the service as they're published.
+
+use std::pin::Pin;
+use std::sync::Arc;
+
+use diskann::index::IndexEvent;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Request, Response};
+
+use crate::proto::diskann_search_server::{DiskannSearch, DiskannSearchServer};
+use crate::proto::{
+    self, index_event, BuildRequest, BuildResponse, DeleteRequest, DeleteResponse,
+    HealthRequest, HealthResponse, InsertRequest, InsertResponse, SearchRequest,
+    SearchResponse, WatchEventsRequest,
+};
+use crate::{SearchService, Status, StatusCode};
+
+/// Stream of the WatchEvents RPC
+pub type IndexEventStream =
+    Pin<Box<dyn Stream<Item = Result<proto::IndexEvent, tonic::Status>> + Send>>;
+
+impl From<Status> for tonic::Status {
+    fn from(status: Status) -> Self {
+        let code = match status.code {
+            StatusCode::InvalidArgument => tonic::Code::InvalidArgument,
+            StatusCode::FailedPrecondition => tonic::Code::FailedPrecondition,
+            StatusCode::PermissionDenied => tonic::Code::PermissionDenied,
+            StatusCode::Internal => tonic::Code::Internal,
+            StatusCode::Unavailable => tonic::Code::Unavailable,
+        };
+        tonic::Status::new(code, status.message)
+    }
+}
+
+/// The service behind the generated gRPC server
+#[derive(Debug, Clone)]
+pub struct GrpcSearchService {
+    service: Arc<SearchService>,
+}
+
+impl GrpcSearchService {
+    /// Serve the RPCs of service
+    pub fn new(service: Arc<SearchService>) -> Self {
+        Self { service }
+    }
+
+    /// gRPC server of the service, to add to a tonic router
+    pub fn into_server(self) -> DiskannSearchServer<Self> {
+        DiskannSearchServer::new(self)
+    }
+
+    /// Run handler on the request on a blocking thread
+    async fn call<Req, Resp>(
+        &self,
+        request: Request<Req>,
+        handler: fn(&SearchService, Req) -> Result<Resp, Status>,
+    ) -> Result<Response<Resp>, tonic::Status>
+    where
+        Req: Send + 'static,
+        Resp: Send + 'static,
+    {
+        let service = self.service.clone();
+        let request = request.into_inner();
+        let response = tokio::task::spawn_blocking(move || handler(&service, request))
+            .await
+            .map_err(|err| tonic::Status::internal(format!("the handler failed: {}", err)))??;
+        Ok(Response::new(response))
+    }
+}
+
+/// Message of an event
+fn event_message(event: IndexEvent) -> proto::IndexEvent {
+    let version = event.version();
+    let event = match event {
+        IndexEvent::VersionSwapped { .. } => {
+            index_event::Event::VersionSwapped(proto::VersionSwapped {})
+        }
+        IndexEvent::Merged {
+            num_merged_indices, ..
+        } => index_event::Event::Merged(proto::Merged {
+            num_merged_indices: num_merged_indices as u64,
+        }),
+        IndexEvent::Deleted { vertex_ids, .. } => {
+            index_event::Event::Deleted(proto::Deleted { ids: vertex_ids })
+        }
+    };
+    proto::IndexEvent {
+        version,
+        event: Some(event),
+    }
+}
+
+#[tonic::async_trait]
+impl DiskannSearch for GrpcSearchService {
+    async fn build(
+        &self,
+        request: Request<BuildRequest>,
+    ) -> Result<Response<BuildResponse>, tonic::Status> {
+        self.call(request, SearchService::build).await
+    }
+
+    async fn insert(
+        &self,
+        request: Request<InsertRequest>,
+    ) -> Result<Response<InsertResponse>, tonic::Status> {
+        self.call(request, SearchService::insert).await
+    }
+
+    async fn delete(
+        &self,
+        request: Request<DeleteRequest>,
+    ) -> Result<Response<DeleteResponse>, tonic::Status> {
+        self.call(request, SearchService::delete).await
+    }
+
+    async fn search(
+        &self,
+        request: Request<SearchRequest>,
+    ) -> Result<Response<SearchResponse>, tonic::Status> {
+        self.call(request, SearchService::search).await
+    }
+
+    async fn health(
+        &self,
+        request: Request<HealthRequest>,
+    ) -> Result<Response<HealthResponse>, tonic::Status> {
+        self.call(request, SearchService::health).await
+    }
+
+    type WatchEventsStream = IndexEventStream;
+
+    async fn watch_events(
+        &self,
+        _request: Request<WatchEventsRequest>,
+    ) -> Result<Response<IndexEventStream>, tonic::Status> {
+        let events = self.service.events().clone();
+        let stream = BroadcastStream::new(events.subscribe())
+            .map(move |event| match event {
+                Ok(event) => event_message(event),
+                // The events it missed are gone, the client starts over from the version
+                Err(BroadcastStreamRecvError::Lagged(num_missed_events)) => proto::IndexEvent {
+                    version: events.version(),
+                    event: Some(index_event::Event::Lagged(proto::Lagged {
+                        num_missed_events,
+                    })),
+                },
+            })
+            .map(Ok);
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+#[cfg(test)]
+mod grpc_test {
+    use diskann::index::{create_inmem_index, QueryBatcherConfig};
+    use diskann::model::{IndexConfigurationBuilder, IndexWriteParametersBuilder};
+    use tokio::net::TcpListener;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::transport::Server;
+    use vector::Metric;
+
+    use super::*;
+    use crate::proto::diskann_search_client::DiskannSearchClient;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn clients_call_the_rpcs_over_grpc() {
+        let vectors: Vec<Vec<f32>> = (0..100)
+            .map(|i| vec![(i % 10) as f32, (i / 10) as f32, 0.5, 0.5])
+            .collect();
+        let index_write_parameters = IndexWriteParametersBuilder::new(50, 16)
+            .with_num_threads(1)
+            .build();
+        let config = IndexConfigurationBuilder::new(Metric::L2, 4, 200)
+            .with_index_write_parameters(index_write_parameters)
+            .build();
+        let mut index = create_inmem_index::<f32>(config.clone()).unwrap();
+        index.build_from_vectors(&vectors).unwrap();
+        let service = SearchService::new(index, config, 50, QueryBatcherConfig::default()).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(
+            Server::builder()
+                .add_service(GrpcSearchService::new(Arc::new(service)).into_server())
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+
+        let mut client = DiskannSearchClient::connect(format!("http://{}", address))
+            .await
+            .unwrap();
+        let health = client.health(HealthRequest {}).await.unwrap().into_inner();
+        assert!(health.ready);
+        assert_eq!(health.kernel_report, vector::kernel_report());
+        let mut events = client
+            .watch_events(WatchEventsRequest {})
+            .await
+            .unwrap()
+            .into_inner();
+        let response = client
+            .search(SearchRequest {
+                query: vectors[42].clone(),
+                k: 3,
+                list_size: 0,
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.ids[0], 42);
+        assert_eq!(response.distances.len(), 3);
+
+        let inserted = vec![42.0, 42.0, 0.5, 0.5];
+        let id = client
+            .insert(InsertRequest {
+                vector: inserted.clone(),
+            })
+            .await
+            .unwrap()
+            .into_inner()
+            .id;
+        assert_eq!(id, 100);
+
+        // Another client sees the insert, and the delete takes it out of the results
+        let mut other = DiskannSearchClient::connect(format!("http://{}", address))
+            .await
+            .unwrap();
+        let search = SearchRequest {
+            query: inserted,
+            k: 1,
+            list_size: 0,
+        };
+        let ids = |response: Result<Response<SearchResponse>, tonic::Status>| {
+            response.unwrap().into_inner().ids
+        };
+        assert_eq!(ids(other.search(search.clone()).await), vec![id]);
+        client.delete(DeleteRequest { id }).await.unwrap();
+        assert_ne!(ids(other.search(search.clone()).await), vec![id]);
+        assert_eq!(
+            events.message().await.unwrap(),
+            Some(proto::IndexEvent {
+                version: 1,
+                event: Some(index_event::Event::Deleted(proto::Deleted { ids: vec![id] })),
+            })
+        );
+
+        // Failed calls answer their status and leave the channel usable
+        let status = client
+            .search(SearchRequest {
+                query: vec![1.0],
+                k: 1,
+                list_size: 0,
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        let status = client
+            .build(BuildRequest {
+                data_path: "points.bin".to_string(),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+        assert_eq!(ids(client.search(search).await).len(), 1);
+    }
+}
@@ -0,0 +1,22 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Vector search service over an in-memory index, serving the gRPC service of
+//! proto/diskann_search.proto with tonic.
+//! The handlers of the service module are synchronous and transport independent, the grpc
+//! module runs them on the blocking threads of the tokio runtime.
+
+/// Messages, client and server of proto/diskann_search.proto, generated by build.rs
+#[allow(missing_docs, missing_debug_implementations, clippy::all)]
+pub mod proto {
+    tonic::include_proto!("diskann.search.v1");
+}
+
+pub mod service;
+pub use service::*;
+
+pub mod grpc;
+pub use grpc::*;
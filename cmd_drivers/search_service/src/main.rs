@@ -0,0 +1,114 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+
+use clap::Parser;
+use tonic::transport::Server;
+
+use diskann::{
+    common::{ANNError, ANNResult},
+    index::{create_inmem_index, QueryBatcherConfig},
+    model::{
+        default_param_vals::{ALPHA, BUILD_LIST_SIZE, MAX_DEGREE},
+        IndexConfigurationBuilder, IndexWriteParametersBuilder,
+    },
+    utils::load_metadata_from_file,
+};
+use search_service::{GrpcSearchService, SearchService};
+use vector::Metric;
+
+/// Load the index and serve it on the address
+async fn serve(args: &SearchServiceArgs) -> ANNResult<()> {
+    let address: SocketAddr = args.address.parse().map_err(|err| {
+        ANNError::log_index_error(format!("ERROR: Invalid address {}: {}", args.address, err))
+    })?;
+    let data_file = format!("{}.data", args.index_path_prefix);
+    let (num_points, dim) = load_metadata_from_file(&data_file)?;
+    let max_points = args.max_points.unwrap_or(2 * num_points).max(num_points);
+
+    let index_write_parameters = IndexWriteParametersBuilder::new(args.l_build, args.max_degree)
+        .with_alpha(args.alpha)
+        .with_num_threads(args.num_threads)
+        .build();
+    let config = IndexConfigurationBuilder::new(args.dist_fn, dim, max_points)
+        .with_index_write_parameters(index_write_parameters)
+        .build();
+    let mut index = create_inmem_index::<f32>(config.clone())?;
+    index.load(&args.index_path_prefix, num_points)?;
+    println!(
+        "Loaded {} points of dimension {}, room for {}",
+        num_points, dim, max_points
+    );
+
+    let mut service = SearchService::new(
+        index,
+        config,
+        args.search_list,
+        QueryBatcherConfig::default(),
+    )?;
+    if let Some(data_dir) = &args.data_dir {
+        service = service.with_data_dir(Path::new(data_dir))?;
+    }
+
+    println!("Serving on {}", address);
+    Server::builder()
+        .add_service(GrpcSearchService::new(Arc::new(service)).into_server())
+        .serve(address)
+        .await
+        .map_err(|err| {
+            ANNError::log_index_error(format!("ERROR: Can't serve on {}: {}", address, err))
+        })
+}
+
+#[tokio::main]
+async fn main() -> ANNResult<()> {
+    let args = SearchServiceArgs::parse();
+    serve(&args).await
+}
+
+#[derive(Debug, Parser)]
+struct SearchServiceArgs {
+    /// distance function <l2/l1/chebyshev/cosine/hamming/tanimoto>
+    #[arg(long = "dist_fn", default_value = "l2")]
+    pub dist_fn: Metric,
+
+    /// Path prefix of the float index to serve (required)
+    #[arg(long = "index_path_prefix", short, required = true)]
+    pub index_path_prefix: String,
+
+    /// Address the server listens on
+    #[arg(long, default_value = "0.0.0.0:50051")]
+    pub address: String,
+
+    /// Directory of the bin files the Build RPC may read (default: Build is disabled)
+    #[arg(long = "data_dir")]
+    pub data_dir: Option<String>,
+
+    /// Most points the index holds with inserts (default: twice the loaded points)
+    #[arg(long = "max_points")]
+    pub max_points: Option<usize>,
+
+    /// Maximum graph degree of inserts and builds
+    #[arg(long = "max_degree", short = 'R', default_value_t = MAX_DEGREE)]
+    pub max_degree: u32,
+
+    /// Build complexity of inserts and builds
+    #[arg(long = "l_build", short = 'L', default_value_t = BUILD_LIST_SIZE)]
+    pub l_build: u32,
+
+    /// alpha controls density and diameter of the graph
+    #[arg(long, short, default_value_t = ALPHA)]
+    pub alpha: f32,
+
+    /// Search list size of the requests that don't give one
+    #[arg(long = "search_list", default_value = "100")]
+    pub search_list: u32,
+
+    /// Number of threads of builds
+    #[arg(long = "num_threads", short = 'T', default_value = "1")]
+    pub num_threads: u32,
+}
@@ -0,0 +1,377 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Handlers of the RPCs of proto/diskann_search.proto over a loaded index.
+//!
+//! The handlers take and return the generated messages and block, the grpc module serves
+//! them. Build replaces the whole index and holds the write lock, the other RPCs share the
+//! read lock and run concurrently. Searches go through a QueryBatcher, which groups the
+//! concurrent ones into batches searched together under one read lock. Build only reads bin
+//! files of the data directory the service is given, and fails without one. The served index
+//! publishes its deletions to the event notifier of the service, and Build a version swap.
+
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use diskann::common::{ANNError, ANNResult, ErrorKind};
+use diskann::index::{
+    create_inmem_index, ANNInmemIndex, BatchQuery, IndexEventNotifier, QueryBatcher,
+    QueryBatcherConfig,
+};
+use diskann::model::{IndexConfiguration, SearchResultFields};
+use diskann::utils::load_metadata_from_file;
+use rayon::prelude::*;
+use vector::{kernel_report, kernel_selections};
+
+use crate::proto::{
+    BuildRequest, BuildResponse, DeleteRequest, DeleteResponse, HealthRequest, HealthResponse,
+    InsertRequest, InsertResponse, KernelSelection, SearchRequest, SearchResponse,
+};
+
+/// Code of a failed RPC, the gRPC status code of the same name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusCode {
+    /// The request doesn't fit the index
+    InvalidArgument,
+
+    /// The service isn't set up for the request
+    FailedPrecondition,
+
+    /// The request reaches outside of what the service serves
+    PermissionDenied,
+
+    /// The index failed to serve the request
+    Internal,
+
+    /// A thread panicked holding the index lock
+    Unavailable,
+}
+
+/// Failure of an RPC
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Status {
+    /// Code of the failure
+    pub code: StatusCode,
+
+    /// Message of the failure
+    pub message: String,
+}
+
+impl Status {
+    pub(crate) fn invalid_argument(message: String) -> Self {
+        Self {
+            code: StatusCode::InvalidArgument,
+            message,
+        }
+    }
+
+    pub(crate) fn permission_denied(message: String) -> Self {
+        Self {
+            code: StatusCode::PermissionDenied,
+            message,
+        }
+    }
+}
+
+impl From<ANNError> for Status {
+    fn from(err: ANNError) -> Self {
+        let code = match err.kind() {
+            ErrorKind::IndexConfig => StatusCode::InvalidArgument,
+            ErrorKind::LockPoison => StatusCode::Unavailable,
+            _ if err.is_retryable() => StatusCode::Unavailable,
+            _ => StatusCode::Internal,
+        };
+        Self {
+            code,
+            message: err.to_string(),
+        }
+    }
+}
+
+/// Index served by the RPCs
+pub struct SearchService {
+    index: Arc<RwLock<Box<dyn ANNInmemIndex<f32>>>>,
+
+    /// Batches the searches of the served index
+    batcher: QueryBatcher<f32>,
+
+    /// Configuration of the indexes Build creates
+    config: IndexConfiguration,
+
+    /// Search list size of the requests that leave it at 0
+    default_list_size: u32,
+
+    /// Directory of the bin files Build reads, canonical. Build fails without one.
+    data_dir: Option<PathBuf>,
+
+    /// Publishes the changes of the served index
+    events: Arc<IndexEventNotifier>,
+}
+
+impl std::fmt::Debug for SearchService {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SearchService")
+            .field("config", &self.config)
+            .field("default_list_size", &self.default_list_size)
+            .field("batcher", &self.batcher)
+            .field("data_dir", &self.data_dir)
+            .field("events", &self.events)
+            .finish()
+    }
+}
+
+impl SearchService {
+    /// Serve a loaded index, config being the one it was created with, batching the searches
+    /// within the limits of batcher_config
+    pub fn new(
+        mut index: Box<dyn ANNInmemIndex<f32>>,
+        config: IndexConfiguration,
+        default_list_size: u32,
+        batcher_config: QueryBatcherConfig,
+    ) -> ANNResult<Self> {
+        let events = Arc::new(IndexEventNotifier::default());
+        index.set_event_notifier(events.clone());
+        let index = Arc::new(RwLock::new(index));
+        let batch_index = index.clone();
+        let batcher = QueryBatcher::new(
+            Arc::new(move |batch: &[BatchQuery<f32>]| {
+                let index = match batch_index.read() {
+                    Ok(index) => index,
+                    Err(_) => return batch.iter().map(|_| Err(lock_poisoned())).collect(),
+                };
+                batch
+                    .par_iter()
+                    .map(|query| {
+                        index
+                            .search_with_details(
+                                &query.query,
+                                query.k_value,
+                                query.l_value,
+                                SearchResultFields::NONE,
+                            )
+                            .map(|(results, _)| results)
+                    })
+                    .collect()
+            }),
+            batcher_config,
+        )?;
+
+        Ok(Self {
+            index,
+            batcher,
+            config,
+            default_list_size,
+            data_dir: None,
+            events,
+        })
+    }
+
+    /// Notifier of the changes of the served index
+    pub fn events(&self) -> &Arc<IndexEventNotifier> {
+        &self.events
+    }
+
+    /// Let Build read the bin files of data_dir
+    pub fn with_data_dir(mut self, data_dir: &Path) -> ANNResult<Self> {
+        self.data_dir = Some(data_dir.canonicalize().map_err(ANNError::log_io_error)?);
+        Ok(self)
+    }
+
+    /// Build an index from a bin file of the data directory and replace the served one with it
+    pub fn build(&self, request: BuildRequest) -> Result<BuildResponse, Status> {
+        let data_file = self.data_file(&request.data_path)?;
+        let (num_points, dim) = load_metadata_from_file(&data_file).map_err(ANNError::from)?;
+        if dim != self.config.dim {
+            return Err(Status::invalid_argument(format!(
+                "{} has vectors of dimension {}, the index {}",
+                request.data_path, dim, self.config.dim
+            )));
+        }
+
+        // Searches keep going to the served index while the new one builds
+        let mut index = create_inmem_index::<f32>(self.config.clone())?;
+        index.build(&data_file, num_points)?;
+        index.set_event_notifier(self.events.clone());
+        *self.index.write().map_err(|_| lock_poisoned())? = index;
+        self.events.notify_version_swap();
+
+        Ok(BuildResponse {
+            num_points: num_points as u64,
+        })
+    }
+
+    /// Insert a vector
+    pub fn insert(&self, request: InsertRequest) -> Result<InsertResponse, Status> {
+        self.check_dimension(&request.vector)?;
+        let index = self.index.read().map_err(|_| lock_poisoned())?;
+        Ok(InsertResponse {
+            id: index.insert_point(&request.vector)?,
+        })
+    }
+
+    /// Delete a point
+    pub fn delete(&self, request: DeleteRequest) -> Result<DeleteResponse, Status> {
+        let index = self.index.read().map_err(|_| lock_poisoned())?;
+        index.delete_point(request.id)?;
+        Ok(DeleteResponse {})
+    }
+
+    /// Search the nearest neighbors of a query
+    pub fn search(&self, request: SearchRequest) -> Result<SearchResponse, Status> {
+        self.check_dimension(&request.query)?;
+        if request.k == 0 {
+            return Err(Status::invalid_argument("k must be positive".to_string()));
+        }
+        let list_size = match request.list_size {
+            0 => self.default_list_size,
+            list_size => list_size,
+        }
+        .max(request.k);
+
+        let results = self
+            .batcher
+            .search(&request.query, request.k as usize, list_size)?;
+        Ok(SearchResponse {
+            ids: results.iter().map(|result| result.id).collect(),
+            distances: results.iter().map(|result| result.distance).collect(),
+        })
+    }
+
+    /// Whether the index serves and the implementation of each distance kernel
+    pub fn health(&self, _request: HealthRequest) -> Result<HealthResponse, Status> {
+        Ok(HealthResponse {
+            ready: !self.index.is_poisoned(),
+            kernels: kernel_selections()
+                .into_iter()
+                .map(|selection| KernelSelection {
+                    kernel: selection.kernel.to_string(),
+                    backend: selection.backend.to_string(),
+                })
+                .collect(),
+            kernel_report: kernel_report(),
+        })
+    }
+
+    /// Path of the bin file at data_path in the data directory
+    fn data_file(&self, data_path: &str) -> Result<String, Status> {
+        let data_dir = self.data_dir.as_ref().ok_or_else(|| Status {
+            code: StatusCode::FailedPrecondition,
+            message: "Build is disabled, the service has no data directory".to_string(),
+        })?;
+
+        // Only plain relative paths, and no link of the directory may lead out of it
+        let relative = Path::new(data_path);
+        if !relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return Err(Status::permission_denied(format!(
+                "{} isn't a path in the data directory",
+                data_path
+            )));
+        }
+        let path = data_dir
+            .join(relative)
+            .canonicalize()
+            .map_err(|err| Status::invalid_argument(format!("{}: {}", data_path, err)))?;
+        if !path.starts_with(data_dir) {
+            return Err(Status::permission_denied(format!(
+                "{} leads out of the data directory",
+                data_path
+            )));
+        }
+        path.into_os_string()
+            .into_string()
+            .map_err(|_| Status::invalid_argument(format!("{} isn't UTF-8", data_path)))
+    }
+
+    fn check_dimension(&self, vector: &[f32]) -> Result<(), Status> {
+        if vector.len() != self.config.dim {
+            return Err(Status::invalid_argument(format!(
+                "the vector has {} values, the index {}",
+                vector.len(),
+                self.config.dim
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn lock_poisoned() -> ANNError {
+    ANNError::log_lock_poison_error("a thread panicked holding the index lock".to_string())
+}
+
+#[cfg(test)]
+mod service_test {
+    use diskann::index::{create_inmem_index, IndexEvent};
+    use diskann::model::{IndexConfigurationBuilder, IndexWriteParametersBuilder};
+    use diskann::utils::save_data_in_base_dimensions;
+    use vector::Metric;
+
+    use super::*;
+
+    fn service(vectors: &[Vec<f32>]) -> SearchService {
+        let index_write_parameters = IndexWriteParametersBuilder::new(50, 16)
+            .with_num_threads(1)
+            .build();
+        let config = IndexConfigurationBuilder::new(Metric::L2, 4, 200)
+            .with_index_write_parameters(index_write_parameters)
+            .build();
+        let mut index = create_inmem_index::<f32>(config.clone()).unwrap();
+        index.build_from_vectors(vectors).unwrap();
+        SearchService::new(index, config, 50, QueryBatcherConfig::default()).unwrap()
+    }
+
+    #[test]
+    fn build_only_reads_the_data_directory() {
+        let vectors: Vec<Vec<f32>> = (0..100)
+            .map(|i| vec![(i % 10) as f32, (i / 10) as f32, 0.5, 0.5])
+            .collect();
+        let data_dir = std::env::temp_dir().join("build_only_reads_the_data_directory");
+        std::fs::create_dir_all(&data_dir).unwrap();
+        let mut values: Vec<f32> = vectors[..60].concat();
+        let data_file = data_dir.join("points.bin");
+        save_data_in_base_dimensions(data_file.to_str().unwrap(), &mut values, 60, 4, 4, 0).unwrap();
+        let build = |service: &SearchService, data_path: &str| {
+            service.build(BuildRequest {
+                data_path: data_path.to_string(),
+            })
+        };
+
+        let service = service(&vectors);
+        let status = build(&service, "points.bin").unwrap_err();
+        assert_eq!(status.code, StatusCode::FailedPrecondition);
+
+        let service = service.with_data_dir(&data_dir).unwrap();
+        for data_path in [
+            "../build_only_reads_the_data_directory/points.bin",
+            data_file.to_str().unwrap(),
+        ] {
+            let status = build(&service, data_path).unwrap_err();
+            assert_eq!(status.code, StatusCode::PermissionDenied);
+        }
+        let status = build(&service, "missing.bin").unwrap_err();
+        assert_eq!(status.code, StatusCode::InvalidArgument);
+
+        let mut events = service.events().subscribe();
+        let response = build(&service, "points.bin").unwrap();
+        assert_eq!(response.num_points, 60);
+        assert_eq!(
+            events.try_recv().unwrap(),
+            IndexEvent::VersionSwapped { version: 1 }
+        );
+
+        // The new index publishes its deletions too
+        service.delete(DeleteRequest { id: 7 }).unwrap();
+        assert_eq!(events.try_recv().unwrap().version(), 2);
+
+        let health = service.health(HealthRequest {}).unwrap();
+        assert!(health.ready);
+        assert_eq!(health.kernels.len(), kernel_selections().len());
+        assert_eq!(health.kernel_report, kernel_report());
+        std::fs::remove_dir_all(&data_dir).unwrap();
+    }
+}
@@ -5,7 +5,7 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 
 use rand::{thread_rng, Rng};
-use vector::{FullPrecisionDistance, Metric};
+use vector::{distance_l2_slice_f32, FullPrecisionDistance, Metric};
 
 // make sure the vector is 256-bit (32 bytes) aligned required by _mm256_load_ps
 #[repr(C, align(32))]
@@ -42,6 +42,42 @@ fn prepare_random_aligned_vectors() -> (Box<Vector32ByteAligned>, Box<Vector32By
     (a, b)
 }
 
-criterion_group!(benches, benchmark_l2_distance_float_rust,);
+#[repr(C, align(32))]
+struct AlignedVector<const D: usize> {
+    v: [f32; D],
+}
+
+/// Kernel compiled for the dimension against the same loop over a runtime length
+fn benchmark_dimension_specialization<const D: usize>(c: &mut Criterion) {
+    let a = Box::new(AlignedVector::<D> {
+        v: [(); D].map(|_| thread_rng().gen_range(0.0..100.0)),
+    });
+    let b = Box::new(AlignedVector::<D> {
+        v: [(); D].map(|_| thread_rng().gen_range(0.0..100.0)),
+    });
+    let mut group = c.benchmark_group(format!("dimension-specialization-{}", D));
+
+    group.bench_function("specialized", |f| {
+        f.iter(|| {
+            black_box(<[f32; D]>::distance_compare(
+                black_box(&a.v),
+                black_box(&b.v),
+                Metric::L2,
+            ))
+        })
+    });
+
+    group.bench_function("runtime length", |f| {
+        f.iter(|| black_box(distance_l2_slice_f32(black_box(&a.v[..]), black_box(&b.v[..]))))
+    });
+}
+
+criterion_group!(
+    benches,
+    benchmark_l2_distance_float_rust,
+    benchmark_dimension_specialization::<384>,
+    benchmark_dimension_specialization::<768>,
+    benchmark_dimension_specialization::<1536>,
+);
 criterion_main!(benches);
 
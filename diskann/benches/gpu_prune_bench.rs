@@ -0,0 +1,145 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+use std::sync::Arc;
+use std::time::Duration;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rand::distributions::{Distribution, Uniform};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rayon::prelude::*;
+
+use diskann::index::{ANNInmemIndex, GpuDistance, InmemIndex};
+use diskann::model::configuration::index_write_parameters::IndexWriteParametersBuilder;
+use diskann::model::vertex::DIM_128;
+use diskann::model::IndexConfiguration;
+use diskann::utils::file_util::save_data_in_base_dimensions;
+use vector::{FullPrecisionDistance, Metric};
+
+const NUM_POOLS: usize = 128;
+const POOL_SIZE: usize = 256;
+const NUM_POINTS: usize = 4096;
+const L: u32 = 64;
+const R: u32 = 32;
+
+// make sure the vector is 256-bit (32 bytes) aligned required by _mm256_load_ps
+#[repr(C, align(32))]
+#[derive(Clone, Copy)]
+struct AlignedVector {
+    v: [f32; DIM_128],
+}
+
+fn random_vectors(count: usize) -> Vec<AlignedVector> {
+    let mut rng = StdRng::seed_from_u64(42);
+    let values = Uniform::new(0.0f32, 128.0);
+    (0..count)
+        .map(|_| AlignedVector {
+            v: [(); DIM_128].map(|_| values.sample(&mut rng)),
+        })
+        .collect()
+}
+
+fn open_gpu() -> GpuDistance {
+    GpuDistance::new()
+        .unwrap()
+        .expect("the gpu benchmark needs an adapter, e.g. Mesa's llvmpipe")
+}
+
+/// The lower triangles of a batch of pools, as the build compares them for one batch
+fn benchmark_pool_distances(c: &mut Criterion) {
+    let gpu_distance = open_gpu();
+    println!("GPU adapter: {}", gpu_distance.adapter_name());
+    let pools: Vec<Vec<AlignedVector>> = random_vectors(NUM_POOLS * POOL_SIZE)
+        .chunks(POOL_SIZE)
+        .map(|pool| pool.to_vec())
+        .collect();
+    let vectors: Vec<f32> = pools.iter().flatten().flat_map(|vector| vector.v).collect();
+    let pool_sizes = vec![POOL_SIZE; NUM_POOLS];
+
+    let mut group = c.benchmark_group("gpu-prune-pool-distances");
+    group.sample_size(10);
+    group.measurement_time(Duration::from_secs(10));
+
+    group.bench_function("cpu", |f| {
+        f.iter(|| {
+            let triangles: Vec<Vec<f32>> = pools
+                .par_iter()
+                .map(|pool| {
+                    let mut triangle = Vec::with_capacity(POOL_SIZE * (POOL_SIZE - 1) / 2);
+                    for i in 1..pool.len() {
+                        for j in 0..i {
+                            triangle.push(<[f32; DIM_128]>::distance_compare(
+                                &pool[i].v,
+                                &pool[j].v,
+                                Metric::L2,
+                            ));
+                        }
+                    }
+                    triangle
+                })
+                .collect();
+            black_box(triangles)
+        })
+    });
+
+    group.bench_function("gpu", |f| {
+        f.iter(|| {
+            black_box(
+                gpu_distance
+                    .pool_distances(&vectors, &pool_sizes, DIM_128, Metric::L2)
+                    .unwrap()
+                    .unwrap(),
+            )
+        })
+    });
+
+    group.finish();
+}
+
+/// A whole in-memory build, pruning on the CPU threads against in batches on the GPU
+fn benchmark_build(c: &mut Criterion) {
+    let data_file = std::env::temp_dir().join("gpu_prune_bench_4096pts.fbin");
+    let data_file = data_file.to_str().unwrap();
+    let mut data: Vec<f32> = random_vectors(NUM_POINTS)
+        .iter()
+        .flat_map(|vector| vector.v)
+        .collect();
+    save_data_in_base_dimensions(data_file, &mut data, NUM_POINTS, DIM_128, DIM_128, 0).unwrap();
+
+    let index_write_parameters = IndexWriteParametersBuilder::new(L, R).build();
+    let config = IndexConfiguration::new(
+        Metric::L2,
+        DIM_128,
+        DIM_128,
+        NUM_POINTS,
+        false,
+        0,
+        false,
+        0,
+        1.0f32,
+        index_write_parameters,
+    );
+    let gpu_config = config.clone().with_gpu_distance(Arc::new(open_gpu()));
+
+    let mut group = c.benchmark_group("gpu-prune-build");
+    group.sample_size(10);
+    group.measurement_time(Duration::from_secs(20));
+
+    for (name, config) in [("cpu", config), ("gpu", gpu_config)] {
+        group.bench_function(name, |f| {
+            f.iter(|| {
+                let mut index = InmemIndex::<f32, DIM_128>::new(config.clone()).unwrap();
+                ANNInmemIndex::build(&mut index, data_file, NUM_POINTS).unwrap();
+                black_box(index)
+            })
+        });
+    }
+
+    group.finish();
+    std::fs::remove_file(data_file).unwrap();
+}
+
+criterion_group!(benches, benchmark_pool_distances, benchmark_build);
+criterion_main!(benches);
@@ -0,0 +1,66 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+use std::time::Duration;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use diskann::index::{ANNInmemIndex, InmemIndex};
+use diskann::model::configuration::index_write_parameters::IndexWriteParametersBuilder;
+use diskann::model::vertex::DIM_128;
+use diskann::model::IndexConfiguration;
+use vector::Metric;
+
+const INDEX_PATH: &str = "tests/data/truth_index_siftsmall_learn_256pts_R4_L50_A1.2";
+const NUM_POINTS: usize = 256;
+const L: u32 = 50;
+const K: usize = 10;
+
+/// Search every point of the index for its neighbors
+fn search_all(index: &InmemIndex<f32, DIM_128>, queries: &[[f32; DIM_128]]) {
+    let mut indices = [0u32; K];
+    for query in queries {
+        ANNInmemIndex::search(index, query, K, L, &mut indices).unwrap();
+        black_box(&indices);
+    }
+}
+
+fn benchmark_graph_layout(c: &mut Criterion) {
+    let index_write_parameters = IndexWriteParametersBuilder::new(L, 4).build();
+    let config = IndexConfiguration::new(
+        Metric::L2,
+        128,
+        128,
+        NUM_POINTS,
+        false,
+        0,
+        false,
+        0,
+        1.0f32,
+        index_write_parameters,
+    );
+    let mut index = InmemIndex::<f32, DIM_128>::new(config).unwrap();
+    ANNInmemIndex::load(&mut index, INDEX_PATH, NUM_POINTS).unwrap();
+    let queries: Vec<[f32; DIM_128]> = (0..NUM_POINTS as u32)
+        .map(|id| *index.dataset.get_vertex(id).unwrap().vector())
+        .collect();
+
+    let mut group = c.benchmark_group("graph-layout-search");
+    group.measurement_time(Duration::from_secs(3));
+
+    println!("vec-per-vertex graph: {} bytes", index.final_graph.memory_bytes());
+    group.bench_function("vec-per-vertex", |f| f.iter(|| search_all(&index, &queries)));
+
+    index.compact_graph().unwrap();
+    println!(
+        "arena graph: {} bytes",
+        index.arena_graph.as_ref().unwrap().memory_bytes()
+    );
+    group.bench_function("arena", |f| f.iter(|| search_all(&index, &queries)));
+
+    group.finish();
+}
+
+criterion_group!(benches, benchmark_graph_layout);
+criterion_main!(benches);
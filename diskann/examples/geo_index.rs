@@ -0,0 +1,77 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+//! Index of geographic points searched by great-circle distance, plugging a non-Euclidean
+//! Distance into an in-memory index.
+//!
+//! Run with `cargo run -p diskann --example geo_index`.
+
+use diskann::common::ANNResult;
+use diskann::index::{ANNInmemIndex, InmemIndex};
+use diskann::model::configuration::index_write_parameters::IndexWriteParametersBuilder;
+use diskann::model::IndexConfiguration;
+use vector::{HaversineDistance, Metric};
+
+/// Latitude and longitude, padded to a vector length the index is compiled for
+const GEO_DIM: usize = 8;
+
+const CITIES: [(&str, f32, f32); 16] = [
+    ("Amsterdam", 52.3676, 4.9041),
+    ("Auckland", -36.8485, 174.7633),
+    ("Buenos Aires", -34.6037, -58.3816),
+    ("Cairo", 30.0444, 31.2357),
+    ("Cape Town", -33.9249, 18.4241),
+    ("Chicago", 41.8781, -87.6298),
+    ("Lima", -12.0464, -77.0428),
+    ("London", 51.5074, -0.1278),
+    ("Mumbai", 19.0760, 72.8777),
+    ("Nairobi", -1.2921, 36.8219),
+    ("Paris", 48.8566, 2.3522),
+    ("Reykjavik", 64.1466, -21.9426),
+    ("Seattle", 47.6062, -122.3321),
+    ("Singapore", 1.3521, 103.8198),
+    ("Sydney", -33.8688, 151.2093),
+    ("Tokyo", 35.6762, 139.6503),
+];
+
+fn main() -> ANNResult<()> {
+    let points: Vec<Vec<f32>> = CITIES.iter().map(|&(_, lat, lon)| vec![lat, lon]).collect();
+
+    let index_write_parameters = IndexWriteParametersBuilder::new(16, 4)
+        .with_alpha(1.2)
+        .with_num_threads(1)
+        .build();
+    let config = IndexConfiguration::new(
+        Metric::L2, // ignored by HaversineDistance
+        2,
+        GEO_DIM,
+        points.len(),
+        false,
+        0,
+        false,
+        0,
+        1f32,
+        index_write_parameters,
+    );
+    let distance = HaversineDistance::default();
+    let mut index = InmemIndex::<f32, GEO_DIM, _>::with_distance(config, distance)?;
+    index.build_from_vectors(&points)?;
+
+    // Honolulu, whose nearest cities lie on both sides of the antimeridian
+    let (lat, lon) = (21.3069, -157.8583);
+    let mut nearest = [0u32; 3];
+    index.search(&[lat, lon], nearest.len(), 16, &mut nearest)?;
+
+    println!("Cities nearest to Honolulu:");
+    for id in nearest {
+        let (name, city_lat, city_lon) = CITIES[id as usize];
+        println!(
+            "  {:<12} {:>8.0} km",
+            name,
+            distance.between(lat, lon, city_lat, city_lon)
+        );
+    }
+
+    Ok(())
+}
@@ -3,7 +3,7 @@
  * Licensed under the MIT license.
  */
 use hashbrown::HashSet;
-use vector::{FullPrecisionDistance, Metric};
+use vector::{Distance, FullPrecisionDistance, Metric};
 
 use crate::common::{ANNError, ANNResult};
 use crate::index::InmemIndex;
@@ -12,12 +12,16 @@ use crate::model::neighbor::SortedNeighborVector;
 use crate::model::scratch::InMemQueryScratch;
 use crate::model::Neighbor;
 
-impl<T, const N: usize> InmemIndex<T, N>
+impl<T, const N: usize, D> InmemIndex<T, N, D>
 where
     T: Default + Copy + Sync + Send + Into<f32>,
     [T; N]: FullPrecisionDistance<T, N>,
+    D: Distance<T, N>,
 {
-    /// A method that occludes a list of neighbors based on some criteria
+    /// A method that occludes a list of neighbors based on some criteria.
+    /// pool_distances are the distances from each candidate of the truncated pool to the ones
+    /// before it when a GPU computed them, the one from candidate i to candidate j < i at
+    /// i * (i - 1) / 2 + j.
     #[allow(clippy::too_many_arguments)]
     fn occlude_list(
         &self,
@@ -29,6 +33,7 @@ where
         result: &mut AdjacencyList,
         scratch: &mut InMemQueryScratch<T, N>,
         delete_set_ptr: Option<&HashSet<u32>>,
+        pool_distances: Option<&[f32]>,
     ) -> ANNResult<()> {
         if pool.is_empty() {
             return Ok(());
@@ -45,6 +50,16 @@ where
             pool.truncate(max_candidate_size);
         }
 
+        if let Some(distances) = pool_distances {
+            if distances.len() != pool.len() * (pool.len() - 1) / 2 {
+                return Err(ANNError::log_index_error(format!(
+                    "ERROR: {} pool distances for a pool of {} candidates",
+                    distances.len(),
+                    pool.len()
+                )));
+            }
+        }
+
         let occlude_factor = &mut scratch.occlude_factor;
 
         // occlude_list can be called with the same scratch more than once by
@@ -54,12 +69,65 @@ where
         // Initialize occlude_factor to pool.len() many 0.0 values for correctness
         occlude_factor.resize(pool.len(), 0.0);
 
+        // Vectors of the pool entries already considered, in the order they were considered.
+        // Each candidate folds in the ones added since the current round started, which are
+        // exactly the earlier pool entries that would have occluded it in this round.
+        let mut occluders: Vec<&[T; N]> = Vec::with_capacity(pool.len());
+        let mut occluder_ids: Vec<u32> = Vec::with_capacity(pool.len());
+        let mut occluder_positions: Vec<usize> = Vec::with_capacity(pool.len());
+
+        // Candidates past the refined head of the pool are compared with the compressed vectors
+        let num_refined =
+            (pool.len() as f32 * self.configuration.prune_refine_fraction).ceil() as usize;
+
         let mut cur_alpha = 1.0;
         while cur_alpha <= alpha && result.len() < degree as usize {
+            let round_start = occluders.len();
             for (i, neighbor) in pool.iter().enumerate() {
                 if result.len() >= degree as usize {
                     break;
                 }
+
+                // todo - self.filtered_index
+                let vector = self.dataset.get_vertex(neighbor.id)?.vector();
+                let new_occluders = &occluders[round_start..];
+                if occlude_factor[i] <= alpha && !new_occluders.is_empty() {
+                    // The occluders of this round are before the candidate in the pool
+                    let closest = match (pool_distances, &self.prune_vectors) {
+                        (Some(distances), _) => occluder_positions[round_start..]
+                            .iter()
+                            .map(|&position| distances[i * (i - 1) / 2 + position])
+                            .enumerate()
+                            .min_by(|(_, a), (_, b)| a.total_cmp(b)),
+                        (None, Some(prune_vectors)) if i >= num_refined => prune_vectors.argmin(
+                            neighbor.id,
+                            &occluder_ids[round_start..],
+                            self.configuration.dist_metric,
+                        ),
+                        _ => self.distance.argmin(
+                            vector,
+                            new_occluders,
+                            self.configuration.dist_metric,
+                        ),
+                    };
+                    if let Some((_, djk)) = closest {
+                        match self.configuration.dist_metric {
+                            Metric::L2
+                            | Metric::L1
+                            | Metric::Chebyshev
+                            | Metric::Cosine
+                            | Metric::Hamming
+                            | Metric::Tanimoto => {
+                                occlude_factor[i] = if djk == 0.0 {
+                                    f32::MAX
+                                } else {
+                                    occlude_factor[i].max(neighbor.distance / djk)
+                                };
+                            }
+                        }
+                    }
+                }
+
                 if occlude_factor[i] > cur_alpha {
                     continue;
                 }
@@ -74,24 +142,10 @@ where
                     result.push(neighbor.id);
                 }
 
-                // Update occlude factor for points from i+1 to pool.len()
-                for (j, neighbor2) in pool.iter().enumerate().skip(i + 1) {
-                    if occlude_factor[j] > alpha {
-                        continue;
-                    }
-
-                    // todo - self.filtered_index
-                    let djk = self.get_distance(neighbor2.id, neighbor.id)?;
-                    match self.configuration.dist_metric {
-                        Metric::L2 | Metric::Cosine => {
-                            occlude_factor[j] = if djk == 0.0 {
-                                f32::MAX
-                            } else {
-                                occlude_factor[j].max(neighbor2.distance / djk)
-                            };
-                        }
-                    }
-                }
+                // The entry occludes the ones after it, see the fold above
+                occluders.push(vector);
+                occluder_ids.push(neighbor.id);
+                occluder_positions.push(i);
             }
 
             cur_alpha *= 1.2;
@@ -127,6 +181,30 @@ where
             self.configuration.index_write_parameter.alpha,
             pruned_list,
             scratch,
+            None,
+        )
+    }
+
+    /// Prunes the neighbors of a data point like prune_neighbors, with the distances between
+    /// the candidates a GPU computed for the pool sorted and truncated to max_occlusion_size
+    #[cfg(feature = "gpu")]
+    pub(crate) fn prune_neighbors_with_pool_distances(
+        &self,
+        location: u32,
+        pool: &mut Vec<Neighbor>,
+        pool_distances: &[f32],
+        pruned_list: &mut AdjacencyList,
+        scratch: &mut InMemQueryScratch<T, N>,
+    ) -> ANNResult<()> {
+        self.robust_prune(
+            location,
+            pool,
+            self.configuration.index_write_parameter.max_degree,
+            self.configuration.index_write_parameter.max_occlusion_size,
+            self.configuration.index_write_parameter.alpha,
+            pruned_list,
+            scratch,
+            Some(pool_distances),
         )
     }
 
@@ -141,6 +219,7 @@ where
     /// * `alpha` - A parameter that controls the occlusion pruning strategy.
     /// * `pruned_list` - A vector to store the ids of the pruned neighbors.
     /// * `scratch` - A mutable reference to a scratch space for in-memory queries.
+    /// * `pool_distances` - Distances between the candidates computed on a GPU, see occlude_list.
     ///
     /// # Error
     ///
@@ -155,6 +234,7 @@ where
         alpha: f32,
         pruned_list: &mut AdjacencyList,
         scratch: &mut InMemQueryScratch<T, N>,
+        pool_distances: Option<&[f32]>,
     ) -> ANNResult<()> {
         if pool.is_empty() {
             // if the pool is empty, behave like a noop
@@ -178,6 +258,7 @@ where
             pruned_list,
             scratch,
             Option::None,
+            pool_distances,
         )?;
 
         if pruned_list.len() > range as usize {
@@ -222,6 +303,8 @@ where
             return Err(ANNError::log_index_error("src_pool is empty.".to_string()));
         }
 
+        self.read_ahead_for_inter_insert(src_pool, range)?;
+
         for &vertex_id in src_pool {
             // vertex is the index of a neighbor of n
             // Assert that vertex is within the valid range of points
@@ -249,6 +332,33 @@ where
 
                 self.set_neighbors(vertex_id, new_out_neighbors)?;
             }
+            self.write_through(vertex_id)?;
+        }
+
+        Ok(())
+    }
+
+    /// Issue the memory reads the insert loop below will need before it starts, so they overlap
+    /// instead of stalling one node at a time: the vectors of the nodes getting a back edge, and
+    /// for the nodes that are already full, the vectors of their neighbors that pruning compares.
+    /// Adjacency read here may be stale by the time the node is locked for write, it's only a hint.
+    /// A node store is asked to read ahead the nodes the loop writes through.
+    fn read_ahead_for_inter_insert(&self, vertex_ids: &[u32], range: u32) -> ANNResult<()> {
+        if let Some(node_store) = &self.node_store {
+            self.lock_node_store(node_store)?.read_ahead(vertex_ids)?;
+        }
+
+        for &vertex_id in vertex_ids {
+            self.dataset.prefetch_vector(vertex_id);
+        }
+
+        for &vertex_id in vertex_ids {
+            let vertex_guard = self.final_graph.read_vertex_and_neighbors(vertex_id)?;
+            if vertex_guard.size() >= range as usize {
+                for &neighbor in vertex_guard.get_neighbors().iter() {
+                    self.dataset.prefetch_vector(neighbor);
+                }
+            }
         }
 
         Ok(())
@@ -6,16 +6,100 @@
 
 //! Search algorithm for index construction and query
 
+use std::ops::Range;
+use std::time::Instant;
+
 use crate::common::{ANNError, ANNResult};
 use crate::index::InmemIndex;
-use crate::model::{scratch::InMemQueryScratch, Neighbor, Vertex};
+use crate::instrumentation::QueryStats;
+use crate::model::data_store::LabelFilter;
+use crate::model::{scratch::InMemQueryScratch, Neighbor, NeighborPriorityQueue, Vertex};
 use hashbrown::hash_set::Entry::*;
-use vector::FullPrecisionDistance;
+use vector::{Distance, FullPrecisionDistance};
+
+/// Rounds of expansions between two merges of the search frontiers
+pub(crate) const FRONTIER_MERGE_INTERVAL: usize = 4;
+
+/// Entry points of a filtered search taken from the points of each label in the filter
+const ENTRY_POINTS_PER_LABEL: usize = 8;
+
+/// How the query is compared to the points during a search
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryComparison<'a, const N: usize> {
+    /// Distance of the index over all dimensions
+    Full,
+
+    /// Distance with a weight per dimension
+    Weighted(&'a [f32; N]),
+
+    /// Distance over a contiguous range of dimensions only
+    Subspace(Range<usize>),
+}
+
+/// Bounds that stop a search before it runs out of unvisited candidates, trading recall on
+/// hard queries for a bounded latency
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SearchLimits {
+    /// Most nodes expanded. None for no limit.
+    pub max_hops: Option<usize>,
+
+    /// Most expansions in a row that don't bring a candidate closer than the closest one
+    /// found before them. None for no limit.
+    pub patience: Option<usize>,
+}
+
+impl SearchLimits {
+    /// Whether the search may expand another node after expanding num_hops nodes, the last
+    /// ones tracked by stall
+    fn allow_hop(&self, num_hops: usize, stall: &StallCounter) -> bool {
+        self.max_hops.is_none_or(|max_hops| num_hops < max_hops) && !stall.exceeds(self.patience)
+    }
+}
+
+/// Callbacks of a search, told about every node it expands. Lets a caller trace the path of a
+/// query through the graph, e.g. to instrument or visualize it, without changing the search.
+pub trait SearchVisitor {
+    /// Called when the search expands node id at distance from the query, hop being the number
+    /// of nodes it expanded before
+    fn node_expanded(&mut self, id: u32, distance: f32, hop: usize);
+}
+
+/// Expansions in a row since the closest candidate of a search last improved
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct StallCounter {
+    best_distance: f32,
+    stale_hops: usize,
+}
 
-impl<T, const N: usize> InmemIndex<T, N>
+impl StallCounter {
+    pub(crate) fn new() -> Self {
+        Self {
+            best_distance: f32::INFINITY,
+            stale_hops: 0,
+        }
+    }
+
+    /// Record num_hops more expansions after which the closest candidate is at best_distance
+    pub(crate) fn expanded(&mut self, num_hops: usize, best_distance: f32) {
+        if best_distance < self.best_distance {
+            self.best_distance = best_distance;
+            self.stale_hops = 0;
+        } else {
+            self.stale_hops += num_hops;
+        }
+    }
+
+    /// Whether patience expansions in a row brought nothing closer
+    pub(crate) fn exceeds(&self, patience: Option<usize>) -> bool {
+        patience.is_some_and(|patience| self.stale_hops >= patience)
+    }
+}
+
+impl<T, const N: usize, D> InmemIndex<T, N, D>
 where
     T: Default + Copy + Sync + Send + Into<f32>,
     [T; N]: FullPrecisionDistance<T, N>,
+    D: Distance<T, N>,
 {
     /// Search for query using given L value, for benchmarking purposes
     /// # Arguments
@@ -28,14 +112,207 @@ where
         scratch: &mut InMemQueryScratch<T, N>,
         search_list_size: usize,
     ) -> ANNResult<u32> {
+        let query_stats = self.search_with_query_stats(query, scratch, search_list_size)?;
+
+        Ok(query_stats.n_cmps)
+    }
+
+    /// Search for query using given L value and collect the query statistics
+    /// # Arguments
+    /// * `query` - query vertex
+    /// * `scratch` - in-memory query scratch
+    /// * `search_list_size` - search list size to use
+    pub fn search_with_query_stats(
+        &self,
+        query: &Vertex<T, N>,
+        scratch: &mut InMemQueryScratch<T, N>,
+        search_list_size: usize,
+    ) -> ANNResult<QueryStats> {
+        self.search_with_comparison(
+            query,
+            scratch,
+            search_list_size,
+            &QueryComparison::Full,
+            SearchLimits::default(),
+            None,
+        )
+    }
+
+    /// Search for query using given L value and collect the query statistics, comparing the
+    /// query to the points with a weight per dimension if weights are given
+    /// # Arguments
+    /// * `query` - query vertex
+    /// * `scratch` - in-memory query scratch
+    /// * `search_list_size` - search list size to use
+    /// * `weights` - optional weight of each dimension, applied to this query only
+    pub fn search_with_query_stats_and_weights(
+        &self,
+        query: &Vertex<T, N>,
+        scratch: &mut InMemQueryScratch<T, N>,
+        search_list_size: usize,
+        weights: Option<&[f32; N]>,
+    ) -> ANNResult<QueryStats> {
+        let comparison = match weights {
+            Some(weights) => QueryComparison::Weighted(weights),
+            None => QueryComparison::Full,
+        };
+        self.search_with_comparison(
+            query,
+            scratch,
+            search_list_size,
+            &comparison,
+            SearchLimits::default(),
+            None,
+        )
+    }
+
+    /// Search for query using given L value and collect the query statistics
+    /// # Arguments
+    /// * `query` - query vertex
+    /// * `scratch` - in-memory query scratch
+    /// * `search_list_size` - search list size to use
+    /// * `comparison` - how the query is compared to the points, for this query only
+    /// * `limits` - bounds that stop the search early, flagged in the statistics when they do
+    /// * `visitor` - optional callbacks of every node the search expands. A brute force search
+    ///   expands none.
+    pub fn search_with_comparison(
+        &self,
+        query: &Vertex<T, N>,
+        scratch: &mut InMemQueryScratch<T, N>,
+        search_list_size: usize,
+        comparison: &QueryComparison<N>,
+        limits: SearchLimits,
+        visitor: Option<&mut dyn SearchVisitor>,
+    ) -> ANNResult<QueryStats> {
+        if self.is_brute_force() {
+            return self.brute_force_search(query, scratch, search_list_size, comparison, None);
+        }
+
+        let timer = Instant::now();
         let init_ids = self.get_init_ids()?;
-        self.init_graph_for_point(query, init_ids, scratch)?;
+        self.init_graph_for_point(query, init_ids, scratch, comparison)?;
         // Scratch is created using largest L val from search_memory_index, so we artifically make it smaller here
         // This allows us to use the same scratch for all L values without having to rebuild the query scratch
         scratch.best_candidates.set_capacity(search_list_size);
-        let (_, cmp) = self.greedy_search(query, scratch)?;
+        let (visited_nodes, cmp, early_terminated) = if self.configuration.num_search_frontiers > 1
+        {
+            self.multi_frontier_search(
+                query,
+                scratch,
+                search_list_size,
+                comparison,
+                limits,
+                visitor,
+            )?
+        } else {
+            self.greedy_search(query, scratch, comparison, limits, visitor)?
+        };
+
+        let total_us = timer.elapsed().as_secs_f64() * 1e6;
+        Ok(QueryStats {
+            total_us,
+            cpu_us: total_us,
+            n_cmps: cmp,
+            n_hops: visited_nodes.len().try_into()?,
+            early_terminated,
+            ..Default::default()
+        })
+    }
+
+    /// Search for query using given L value among the points whose labels satisfy filter and
+    /// collect the query statistics. Only matching points enter the candidates, so the L slots
+    /// aren't spent on points that would be filtered out afterwards.
+    /// # Arguments
+    /// * `query` - query vertex
+    /// * `scratch` - in-memory query scratch
+    /// * `search_list_size` - search list size to use
+    /// * `filter` - condition on the labels of the points to return
+    pub fn search_with_label_filter(
+        &self,
+        query: &Vertex<T, N>,
+        scratch: &mut InMemQueryScratch<T, N>,
+        search_list_size: usize,
+        filter: &LabelFilter,
+    ) -> ANNResult<QueryStats> {
+        if self.is_brute_force() {
+            return self.brute_force_search(
+                query,
+                scratch,
+                search_list_size,
+                &QueryComparison::Full,
+                Some(filter),
+            );
+        }
+
+        let timer = Instant::now();
+        let init_ids = self.get_filtered_init_ids(filter)?;
+        self.init_graph_for_point(query, init_ids, scratch, &QueryComparison::Full)?;
+        scratch.best_candidates.set_capacity(search_list_size);
+        let (visited_nodes, cmp) = self.filtered_greedy_search(query, scratch, filter)?;
+
+        let total_us = timer.elapsed().as_secs_f64() * 1e6;
+        Ok(QueryStats {
+            total_us,
+            cpu_us: total_us,
+            n_cmps: cmp,
+            n_hops: visited_nodes.len().try_into()?,
+            ..Default::default()
+        })
+    }
+
+    /// Compare the query to every point, satisfying filter if one is given, and keep the
+    /// search_list_size closest in scratch.best_candidates. Used instead of the graph while the
+    /// index is below the brute force threshold.
+    /// # Arguments
+    /// * `query` - query vertex
+    /// * `scratch` - in-memory query scratch
+    /// * `search_list_size` - search list size to use
+    /// * `comparison` - how the query is compared to the points, for this query only
+    /// * `filter` - optional condition on the labels of the points to return
+    pub fn brute_force_search(
+        &self,
+        query: &Vertex<T, N>,
+        scratch: &mut InMemQueryScratch<T, N>,
+        search_list_size: usize,
+        comparison: &QueryComparison<N>,
+        filter: Option<&LabelFilter>,
+    ) -> ANNResult<QueryStats> {
+        let timer = Instant::now();
+        scratch.query.memcpy(query.vector())?;
+        scratch.best_candidates.reserve(search_list_size);
+        scratch.best_candidates.set_capacity(search_list_size);
+        let query_vertex = Vertex::<T, N>::try_from((&scratch.query[..], query.vertex_id()))
+            .map_err(|err| {
+                ANNError::log_index_error(format!(
+                    "TryFromSliceError: failed to get Vertex for query, err={}",
+                    err
+                ))
+            })?;
 
-        Ok(cmp)
+        // Points whose slots are reserved but whose vectors are still being copied are skipped
+        let num_points: u32 = self.num_written_pts().try_into()?;
+        let mut cmps: u32 = 0;
+        for id in 0..num_points {
+            if filter.is_some_and(|filter| !self.matches_label_filter(id, filter)) {
+                continue;
+            }
+            if id + 1 < num_points {
+                self.dataset.prefetch_vector(id + 1);
+            }
+
+            let vertex = self.dataset.get_vertex(id)?;
+            let distance = self.compare_to_query(&query_vertex, &vertex, comparison);
+            scratch.best_candidates.insert(Neighbor::new(id, distance));
+            cmps += 1;
+        }
+
+        let total_us = timer.elapsed().as_secs_f64() * 1e6;
+        Ok(QueryStats {
+            total_us,
+            cpu_us: total_us,
+            n_cmps: cmps,
+            ..Default::default()
+        })
     }
 
     /// search for point
@@ -49,17 +326,50 @@ where
         scratch: &mut InMemQueryScratch<T, N>,
     ) -> ANNResult<Vec<Neighbor>> {
         let init_ids = self.get_init_ids()?;
-        self.init_graph_for_point(query, init_ids, scratch)?;
-        let (mut visited_nodes, _) = self.greedy_search(query, scratch)?;
+        self.init_graph_for_point(query, init_ids, scratch, &QueryComparison::Full)?;
+        let (mut visited_nodes, _, _) = self.greedy_search(
+            query,
+            scratch,
+            &QueryComparison::Full,
+            SearchLimits::default(),
+            None,
+        )?;
 
         visited_nodes.retain(|&element| element.id != query.vertex_id());
         Ok(visited_nodes)
     }
 
+    /// Distance from the query to a vertex as the comparison of the query asks
+    #[inline(always)]
+    fn compare_to_query(
+        &self,
+        query: &Vertex<T, N>,
+        vertex: &Vertex<T, N>,
+        comparison: &QueryComparison<N>,
+    ) -> f32 {
+        match comparison {
+            QueryComparison::Full => self.compare_vertices(query, vertex),
+            QueryComparison::Weighted(weights) => self.distance.distance_weighted(
+                query.vector(),
+                vertex.vector(),
+                weights,
+                self.configuration.dist_metric,
+            ),
+            QueryComparison::Subspace(dims) => self.distance.distance_subspace(
+                query.vector(),
+                vertex.vector(),
+                dims.clone(),
+                self.configuration.dist_metric,
+            ),
+        }
+    }
+
     /// Returns the locations of start point and frozen points suitable for use with iterate_to_fixed_point.
     fn get_init_ids(&self) -> ANNResult<Vec<u32>> {
-        let mut init_ids = Vec::with_capacity(1 + self.configuration.num_frozen_pts);
+        let mut init_ids =
+            Vec::with_capacity(1 + self.entry_points.len() + self.configuration.num_frozen_pts);
         init_ids.push(self.start);
+        init_ids.extend_from_slice(&self.entry_points);
 
         for frozen in self.configuration.max_points
             ..(self.configuration.max_points + self.configuration.num_frozen_pts)
@@ -73,17 +383,48 @@ where
         Ok(init_ids)
     }
 
+    /// Start points of a filtered search: the usual ones that satisfy the filter, and a few
+    /// points spread over the points of every label the filter mentions.
+    fn get_filtered_init_ids(&self, filter: &LabelFilter) -> ANNResult<Vec<u32>> {
+        let mut init_ids: Vec<u32> = self
+            .get_init_ids()?
+            .into_iter()
+            .filter(|&id| self.matches_label_filter(id, filter))
+            .collect();
+
+        for label in filter.labels() {
+            let points = self.point_metadata.points_with_label(label);
+            let step = (points.len() / ENTRY_POINTS_PER_LABEL).max(1);
+            for &id in points.iter().step_by(step).take(ENTRY_POINTS_PER_LABEL) {
+                if (id as usize) < self.num_active_pts
+                    && self.matches_label_filter(id, filter)
+                    && !init_ids.contains(&id)
+                {
+                    init_ids.push(id);
+                }
+            }
+        }
+
+        Ok(init_ids)
+    }
+
+    #[inline(always)]
+    fn matches_label_filter(&self, vertex_id: u32, filter: &LabelFilter) -> bool {
+        filter.matches(self.point_metadata.labels(vertex_id))
+    }
+
     /// Initialize graph for point
     /// # Arguments
     /// * `query` - query vertex
     /// * `init_ids` - initial nodes from which search starts
     /// * `scratch` - in-memory query scratch
-    /// * `search_list_size_override` - override for search list size in index config
+    /// * `comparison` - how the query is compared to the points
     fn init_graph_for_point(
         &self,
         query: &Vertex<T, N>,
         init_ids: Vec<u32>,
         scratch: &mut InMemQueryScratch<T, N>,
+        comparison: &QueryComparison<N>,
     ) -> ANNResult<()> {
         scratch
             .best_candidates
@@ -118,7 +459,7 @@ where
 
                 let vertex = self.dataset.get_vertex(id)?;
 
-                let distance = vertex.compare(&query_vertex, self.configuration.dist_metric);
+                let distance = self.compare_to_query(&query_vertex, &vertex, comparison);
                 let neighbor = Neighbor::new(id, distance);
                 scratch.best_candidates.insert(neighbor);
             }
@@ -128,16 +469,23 @@ where
     }
 
     /// GreedySearch against query node
-    /// Returns visited nodes
+    /// Returns visited nodes, the number of comparisons and whether limits stopped the search
+    /// with unvisited candidates left
     /// # Arguments
     /// * `query` - query vertex
     /// * `scratch` - in-memory query scratch
+    /// * `comparison` - how the query is compared to the points
+    /// * `limits` - bounds that stop the search early
+    /// * `visitor` - optional callbacks of the expansions
     /// TODO: use_filter, filter_label, search_invocation
     fn greedy_search(
         &self,
         query: &Vertex<T, N>,
         scratch: &mut InMemQueryScratch<T, N>,
-    ) -> ANNResult<(Vec<Neighbor>, u32)> {
+        comparison: &QueryComparison<N>,
+        limits: SearchLimits,
+        mut visitor: Option<&mut dyn SearchVisitor>,
+    ) -> ANNResult<(Vec<Neighbor>, u32, bool)> {
         let mut visited_nodes =
             Vec::with_capacity((3 * scratch.candidate_size + scratch.max_degree) as usize);
 
@@ -153,8 +501,14 @@ where
                 ))
             })?;
 
-        while scratch.best_candidates.has_notvisited_node() {
+        let mut stall = StallCounter::new();
+        while scratch.best_candidates.has_notvisited_node()
+            && limits.allow_hop(visited_nodes.len(), &stall)
+        {
             let closest_node = scratch.best_candidates.closest_notvisited();
+            if let Some(visitor) = visitor.as_deref_mut() {
+                visitor.node_expanded(closest_node.id, closest_node.distance, visited_nodes.len());
+            }
 
             // Add node to visited nodes to create pool for prune later
             // TODO: search_invocation and use_filter
@@ -165,12 +519,8 @@ where
 
             let max_vertex_id = self.configuration.max_points + self.configuration.num_frozen_pts;
 
-            for id in self
-                .final_graph
-                .read_vertex_and_neighbors(closest_node.id)?
-                .get_neighbors()
-            {
-                let current_vertex_id = *id;
+            self.copy_neighbors(closest_node.id, &mut scratch.neighbor_scratch)?;
+            for &current_vertex_id in scratch.neighbor_scratch.iter() {
                 debug_assert!(
                     (current_vertex_id as usize) < max_vertex_id,
                     "current_vertex_id {} is out of valid range of points {}",
@@ -181,8 +531,7 @@ where
                     continue;
                 }
 
-                // quickly de-dup. Remember, we are in a read lock
-                // we want to exit out of it quickly
+                // quickly de-dup
                 if scratch.node_visited_robinset.insert(current_vertex_id) {
                     scratch.id_scratch.push(current_vertex_id);
                 }
@@ -196,17 +545,238 @@ where
                 }
 
                 let vertex = self.dataset.get_vertex(id)?;
-                let distance = query_vertex.compare(&vertex, self.configuration.dist_metric);
+                let distance = self.compare_to_query(&query_vertex, &vertex, comparison);
 
                 // Insert <id, dist> pairs into the pool of candidates
                 scratch.best_candidates.insert(Neighbor::new(id, distance));
             }
 
+            cmps += len as u32;
+            stall.expanded(1, scratch.best_candidates[0].distance);
+        }
+
+        let early_terminated = scratch.best_candidates.has_notvisited_node();
+        Ok((visited_nodes, cmps, early_terminated))
+    }
+
+    /// GreedySearch against query node keeping only the points that satisfy filter as candidates.
+    /// The neighbors of an expanded node that don't satisfy the filter are skipped over to their
+    /// own neighbors, so matching points stay reachable when the graph links them only through
+    /// other points. Returns visited nodes
+    /// # Arguments
+    /// * `query` - query vertex
+    /// * `scratch` - in-memory query scratch
+    /// * `filter` - condition on the labels of the candidates
+    fn filtered_greedy_search(
+        &self,
+        query: &Vertex<T, N>,
+        scratch: &mut InMemQueryScratch<T, N>,
+        filter: &LabelFilter,
+    ) -> ANNResult<(Vec<Neighbor>, u32)> {
+        let mut visited_nodes =
+            Vec::with_capacity((3 * scratch.candidate_size + scratch.max_degree) as usize);
+        let mut cmps: u32 = 0;
+        let max_vertex_id = self.configuration.max_points + self.configuration.num_frozen_pts;
+
+        let query_vertex = Vertex::<T, N>::try_from((&scratch.query[..], query.vertex_id()))
+            .map_err(|err| {
+                ANNError::log_index_error(format!(
+                    "TryFromSliceError: failed to get Vertex for query, err={}",
+                    err
+                ))
+            })?;
+
+        let mut hops = Vec::new();
+        while scratch.best_candidates.has_notvisited_node() {
+            let closest_node = scratch.best_candidates.closest_notvisited();
+            visited_nodes.push(closest_node);
+            scratch.id_scratch.clear();
+
+            // Copied out so that no vertex lock is held while reading the second hop
+            self.copy_neighbors(closest_node.id, &mut scratch.neighbor_scratch)?;
+            for &id in scratch.neighbor_scratch.iter() {
+                if id as usize >= max_vertex_id || !scratch.node_visited_robinset.insert(id) {
+                    continue;
+                }
+
+                if self.matches_label_filter(id, filter) {
+                    scratch.id_scratch.push(id);
+                    continue;
+                }
+
+                self.copy_neighbors(id, &mut hops)?;
+                for &hop in hops.iter() {
+                    if (hop as usize) < max_vertex_id
+                        && self.matches_label_filter(hop, filter)
+                        && scratch.node_visited_robinset.insert(hop)
+                    {
+                        scratch.id_scratch.push(hop);
+                    }
+                }
+            }
+
+            let len = scratch.id_scratch.len();
+            for (m, &id) in scratch.id_scratch.iter().enumerate() {
+                if m + 1 < len {
+                    let next_node = unsafe { *scratch.id_scratch.get_unchecked(m + 1) };
+                    self.dataset.prefetch_vector(next_node);
+                }
+
+                let vertex = self.dataset.get_vertex(id)?;
+                let distance =
+                    self.compare_to_query(&query_vertex, &vertex, &QueryComparison::Full);
+                scratch.best_candidates.insert(Neighbor::new(id, distance));
+            }
+
             cmps += len as u32;
         }
 
         Ok((visited_nodes, cmps))
     }
+
+    /// Beam search from several entry points at once. Frontier 0 starts from the candidates
+    /// already in scratch, the others from points spread over the dataset. Each round expands
+    /// the closest unvisited node of every frontier and computes the distances of the whole
+    /// round as one prefetched batch. Every FRONTIER_MERGE_INTERVAL rounds the frontiers drop
+    /// the candidates that can't make the top search_list_size of all frontiers combined.
+    /// Returns visited nodes and leaves the merged candidates in scratch.best_candidates.
+    /// Stops early as limits say, counting the expansions of all frontiers together, and
+    /// returns whether it did with unvisited candidates left. The hops given to visitor are
+    /// numbered across the frontiers too.
+    fn multi_frontier_search(
+        &self,
+        query: &Vertex<T, N>,
+        scratch: &mut InMemQueryScratch<T, N>,
+        search_list_size: usize,
+        comparison: &QueryComparison<N>,
+        limits: SearchLimits,
+        mut visitor: Option<&mut dyn SearchVisitor>,
+    ) -> ANNResult<(Vec<Neighbor>, u32, bool)> {
+        let num_frontiers = self.configuration.num_search_frontiers;
+        let max_vertex_id = self.configuration.max_points + self.configuration.num_frozen_pts;
+        let query_vertex = Vertex::<T, N>::try_from((&scratch.query[..], query.vertex_id()))
+            .map_err(|err| {
+                ANNError::log_index_error(format!(
+                    "TryFromSliceError: failed to get Vertex for query, err={}",
+                    err
+                ))
+            })?;
+
+        let mut frontiers: Vec<NeighborPriorityQueue> = (0..num_frontiers)
+            .map(|_| {
+                let mut frontier = NeighborPriorityQueue::with_capacity(search_list_size);
+                frontier.set_tie_epsilon(scratch.best_candidates.tie_epsilon());
+                frontier
+            })
+            .collect();
+
+        for i in 0..scratch.best_candidates.size() {
+            frontiers[0].insert(scratch.best_candidates[i]);
+        }
+        for (i, frontier) in frontiers.iter_mut().enumerate().skip(1) {
+            let id: u32 = (i * self.num_active_pts / num_frontiers).try_into()?;
+            if scratch.node_visited_robinset.insert(id) {
+                let vertex = self.dataset.get_vertex(id)?;
+                let distance = self.compare_to_query(&query_vertex, &vertex, comparison);
+                frontier.insert(Neighbor::new(id, distance));
+            }
+        }
+
+        let mut visited_nodes = Vec::with_capacity(3 * search_list_size);
+        let mut cmps: u32 = 0;
+        let mut batch: Vec<(usize, u32)> = Vec::new();
+        let mut round = 0;
+        let mut stall = StallCounter::new();
+        let mut early_terminated = false;
+        loop {
+            batch.clear();
+            let mut num_expanded = 0;
+            for (f, frontier) in frontiers.iter_mut().enumerate() {
+                if !frontier.has_notvisited_node() {
+                    continue;
+                }
+                if !limits.allow_hop(visited_nodes.len(), &stall) {
+                    early_terminated = true;
+                    break;
+                }
+
+                let closest_node = frontier.closest_notvisited();
+                if let Some(visitor) = visitor.as_deref_mut() {
+                    visitor.node_expanded(
+                        closest_node.id,
+                        closest_node.distance,
+                        visited_nodes.len(),
+                    );
+                }
+                visited_nodes.push(closest_node);
+                num_expanded += 1;
+
+                self.copy_neighbors(closest_node.id, &mut scratch.neighbor_scratch)?;
+                for &id in scratch.neighbor_scratch.iter() {
+                    if (id as usize) < max_vertex_id && scratch.node_visited_robinset.insert(id) {
+                        batch.push((f, id));
+                    }
+                }
+            }
+
+            if num_expanded == 0 {
+                break;
+            }
+
+            for (m, &(f, id)) in batch.iter().enumerate() {
+                if let Some(&(_, next_node)) = batch.get(m + 1) {
+                    self.dataset.prefetch_vector(next_node);
+                }
+
+                let vertex = self.dataset.get_vertex(id)?;
+                let distance = self.compare_to_query(&query_vertex, &vertex, comparison);
+                frontiers[f].insert(Neighbor::new(id, distance));
+            }
+            cmps += batch.len() as u32;
+            let best_distance = frontiers
+                .iter()
+                .filter(|frontier| frontier.size() > 0)
+                .map(|frontier| frontier[0].distance)
+                .fold(f32::INFINITY, f32::min);
+            stall.expanded(num_expanded, best_distance);
+
+            round += 1;
+            if round % FRONTIER_MERGE_INTERVAL == 0 {
+                merge_frontiers(&mut frontiers, search_list_size);
+            }
+        }
+
+        scratch.best_candidates.clear();
+        for frontier in frontiers.iter() {
+            for i in 0..frontier.size() {
+                scratch.best_candidates.insert(frontier[i]);
+            }
+        }
+
+        Ok((visited_nodes, cmps, early_terminated))
+    }
+}
+
+/// Drop the candidates of every frontier that are farther than the search_list_size-th
+/// closest candidate of all frontiers, they can't make the final results anymore.
+pub(crate) fn merge_frontiers(frontiers: &mut [NeighborPriorityQueue], search_list_size: usize) {
+    let mut distances: Vec<f32> = frontiers
+        .iter()
+        .flat_map(|frontier| (0..frontier.size()).map(move |i| frontier[i].distance))
+        .collect();
+    if distances.len() <= search_list_size {
+        return;
+    }
+
+    let (_, threshold, _) =
+        distances.select_nth_unstable_by(search_list_size - 1, |a, b| a.total_cmp(b));
+    let threshold = *threshold;
+    for frontier in frontiers.iter_mut() {
+        let keep = (0..frontier.size())
+            .take_while(|&i| frontier[i].distance <= threshold)
+            .count();
+        frontier.truncate(keep);
+    }
 }
 
 #[cfg(test)]
@@ -216,6 +786,7 @@ mod search_test {
     use crate::model::configuration::index_write_parameters::IndexWriteParametersBuilder;
     use crate::model::graph::AdjacencyList;
     use crate::model::IndexConfiguration;
+    use crate::test_utils::get_test_file_path;
     use crate::test_utils::inmem_index_initialization::create_index_with_test_data;
 
     use super::*;
@@ -288,6 +859,23 @@ mod search_test {
         assert!(scratch.best_candidates[0].visited);
     }
 
+    #[test]
+    fn search_for_point_with_cosine_metric() {
+        let mut index = create_index_with_test_data();
+        index.configuration.dist_metric = Metric::Cosine;
+        let query = index.dataset.get_vertex(index.start).unwrap();
+
+        let mut scratch = InMemQueryScratch::new(
+            index.configuration.index_write_parameter.search_list_size,
+            &index.configuration.index_write_parameter,
+            false,
+        )
+        .unwrap();
+        index.search_for_point(&query, &mut scratch).unwrap();
+        assert_eq!(scratch.best_candidates[0].id, index.start);
+        assert!(scratch.best_candidates[0].distance.abs() < 1e-6);
+    }
+
     fn set_neighbors(index: &InmemIndex<f32, 128>, vertex_id: u32, neighbors: Vec<u32>) {
         index
             .final_graph
@@ -356,4 +944,126 @@ mod search_test {
         assert_eq!(scratch.best_candidates[14].id, 11);
         assert_eq!(scratch.best_candidates[14].distance, 449266.0_f32);
     }
+
+    /// Recall@10 of the first 64 points searched as queries, against a brute force scan
+    fn recall_at_10(index: &InmemIndex<f32, 128>, search_list_size: usize) -> f32 {
+        let num_queries = 64;
+        let mut found = 0;
+        for query_id in 0..num_queries {
+            let mut truth: Vec<(f32, u32)> = (0..index.num_active_pts as u32)
+                .map(|id| (index.get_distance(query_id, id).unwrap(), id))
+                .collect();
+            truth.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+            let query = index.dataset.get_vertex(query_id).unwrap();
+            let mut scratch = InMemQueryScratch::new(
+                search_list_size as u32,
+                &index.configuration.index_write_parameter,
+                false,
+            )
+            .unwrap();
+            index
+                .search_with_query_stats(&query, &mut scratch, search_list_size)
+                .unwrap();
+
+            found += (0..10)
+                .filter(|&i| {
+                    truth[..10]
+                        .iter()
+                        .any(|t| t.1 == scratch.best_candidates[i].id)
+                })
+                .count();
+        }
+
+        found as f32 / (num_queries * 10) as f32
+    }
+
+    #[test]
+    fn multi_frontier_search_improves_recall() {
+        let mut index = create_index_with_test_data();
+        index
+            .load_graph(
+                get_test_file_path("tests/data/truth_index_siftsmall_learn_256pts_R4_L50_A1.2")
+                    .as_str(),
+                256,
+            )
+            .unwrap();
+
+        let single_frontier_recall = recall_at_10(&index, 10);
+        index.configuration.num_search_frontiers = 4;
+        let multi_frontier_recall = recall_at_10(&index, 10);
+
+        // The R=4 graph is sparse enough for a single beam of L=10 to get stuck
+        assert!(single_frontier_recall < 0.75);
+        assert!(multi_frontier_recall > single_frontier_recall + 0.1);
+    }
+
+    /// User-defined distance looking at the first dimension only
+    struct FirstDimensionDistance;
+
+    impl<const N: usize> Distance<f32, N> for FirstDimensionDistance {
+        fn distance(&self, a: &[f32; N], b: &[f32; N], _metric: Metric) -> f32 {
+            (a[0] - b[0]).abs()
+        }
+    }
+
+    #[test]
+    fn search_with_user_defined_distance() {
+        let index_write_parameters = IndexWriteParametersBuilder::new(50, 4)
+            .with_alpha(1.2)
+            .build();
+        let config = IndexConfiguration::new(
+            Metric::L2,
+            128,
+            128,
+            256,
+            false,
+            0,
+            false,
+            0,
+            1f32,
+            index_write_parameters,
+        );
+        let mut index =
+            InmemIndex::<f32, 128, _>::with_distance(config, FirstDimensionDistance).unwrap();
+        index
+            .dataset
+            .build_from_file(
+                get_test_file_path("tests/data/siftsmall_learn_256pts.fbin").as_str(),
+                256,
+            )
+            .unwrap();
+        index.num_active_pts = 256;
+        index
+            .load_graph(
+                get_test_file_path("tests/data/truth_index_siftsmall_learn_256pts_R4_L50_A1.2")
+                    .as_str(),
+                256,
+            )
+            .unwrap();
+
+        let query = index.dataset.get_vertex(14).unwrap();
+        let query_x = query.vector()[0];
+        assert_eq!(
+            index.get_distance(14, 15).unwrap(),
+            (query_x - index.dataset.get_vertex(15).unwrap().vector()[0]).abs()
+        );
+
+        let mut scratch = InMemQueryScratch::new(
+            index.configuration.index_write_parameter.search_list_size,
+            &index.configuration.index_write_parameter,
+            false,
+        )
+        .unwrap();
+        index
+            .search_with_query_stats(&query, &mut scratch, 50)
+            .unwrap();
+
+        for i in 0..scratch.best_candidates.size() {
+            let candidate = scratch.best_candidates[i];
+            let x = index.dataset.get_vertex(candidate.id).unwrap().vector()[0];
+            assert_eq!(candidate.distance, (query_x - x).abs());
+        }
+        assert_eq!(scratch.best_candidates[0].distance, 0.0);
+    }
 }
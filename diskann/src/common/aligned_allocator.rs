@@ -7,26 +7,41 @@
 //! Aligned allocator
 
 use std::alloc::Layout;
+use std::marker::PhantomData;
 use std::ops::{Deref, DerefMut, Range};
-use std::ptr::copy_nonoverlapping;
+use std::ptr::{copy_nonoverlapping, NonNull};
 
 use super::{ANNResult, ANNError};
 
-#[derive(Debug)]
 /// A box that holds a slice but is aligned to the specified layout.
 ///
 /// This type is useful for working with types that require a certain alignment,
 /// such as SIMD vectors or FFI structs. It allocates memory using the global allocator
 /// and frees it when dropped. It also implements Deref and DerefMut to allow access
 /// to the underlying slice.
+///
+/// The memory is held through a raw pointer rather than a Box, so that elements no other
+/// thread reads can be written through a shared reference with write_shared. While such writes
+/// run, the box must only be read through get_range for ranges apart from the written elements:
+/// Deref hands out a reference to the whole slice, which a concurrent write invalidates.
 pub struct AlignedBoxWithSlice<T> {
     /// The layout of the allocated memory.
     layout: Layout,
 
-    /// The slice that points to the allocated memory.
-    val: Box<[T]>,
+    /// The start of the allocated memory, owned by the box.
+    ptr: NonNull<T>,
+
+    /// The number of elements in the allocated memory.
+    len: usize,
+
+    _marker: PhantomData<T>,
 }
 
+// SAFETY: the box owns its elements like a Box<[T]> does. Writes through a shared reference
+// only happen through write_shared, whose callers keep them apart from every other access.
+unsafe impl<T: Send> Send for AlignedBoxWithSlice<T> {}
+unsafe impl<T: Sync> Sync for AlignedBoxWithSlice<T> {}
+
 impl<T> AlignedBoxWithSlice<T> {
     /// Creates a new `AlignedBoxWithSlice` with the given capacity and alignment.
     /// The allocated memory are set to 0.
@@ -45,44 +60,91 @@ impl<T> AlignedBoxWithSlice<T> {
         let layout = Layout::from_size_align(allocsize, alignment)
             .map_err(ANNError::log_mem_alloc_layout_error)?;
 
-        let val = unsafe {
-            let mem = std::alloc::alloc_zeroed(layout);
-            let ptr = mem as *mut T;
-            let slice = std::slice::from_raw_parts_mut(ptr, capacity);
-            std::boxed::Box::from_raw(slice)
-        };
-
-        Ok(Self { layout, val })
+        let mem = unsafe { std::alloc::alloc_zeroed(layout) };
+        let ptr = NonNull::new(mem as *mut T).ok_or_else(|| {
+            ANNError::log_index_error(format!("failed to allocate {} bytes", allocsize))
+        })?;
+
+        Ok(Self {
+            layout,
+            ptr,
+            len: capacity,
+            _marker: PhantomData,
+        })
     }
 
     /// Returns a reference to the slice.
     pub fn as_slice(&self) -> &[T] {
-        &self.val
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
     }
 
     /// Returns a mutable reference to the slice.
     pub fn as_mut_slice(&mut self) -> &mut [T] {
-        &mut self.val
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+
+    /// Returns the number of elements, without borrowing them as Deref does
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns whether the box holds no elements
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns a reference to the elements of range, None if it is out of the box. Unlike
+    /// indexing through Deref, no reference to the other elements is made, so the range may be
+    /// read while write_shared writes elements apart from it.
+    pub fn get_range(&self, range: Range<usize>) -> Option<&[T]> {
+        if range.start > range.end || range.end > self.len {
+            return None;
+        }
+
+        // SAFETY: the range is within the allocation, and only its elements are borrowed
+        Some(unsafe { std::slice::from_raw_parts(self.ptr.as_ptr().add(range.start), range.len()) })
+    }
+
+    /// Copies src to the elements from offset on while the box is shared.
+    ///
+    /// # Safety
+    ///
+    /// No other thread may read or write the written elements until the writes are published
+    /// to it, e.g. by a release store it loads with acquire ordering, and the box may only be
+    /// read through get_range meanwhile.
+    pub unsafe fn write_shared(&self, offset: usize, src: &[T]) -> ANNResult<()> {
+        let end = offset.checked_add(src.len());
+        if end.is_none_or(|end| end > self.len) {
+            return Err(ANNError::log_index_error(format!(
+                "Cannot write {} elements at {} into a box of {} elements",
+                src.len(),
+                offset,
+                self.len
+            )));
+        }
+
+        copy_nonoverlapping(src.as_ptr(), self.ptr.as_ptr().add(offset), src.len());
+        Ok(())
     }
 
     /// Copies data from the source slice to the destination box.
     pub fn memcpy(&mut self, src: &[T]) -> ANNResult<()> {
-        if src.len() > self.val.len() {
-            return Err(ANNError::log_index_error(format!("source slice is too large (src:{}, dst:{})", src.len(), self.val.len())));
+        if src.len() > self.len {
+            return Err(ANNError::log_index_error(format!("source slice is too large (src:{}, dst:{})", src.len(), self.len)));
         }
 
         // Check that they don't overlap
         let src_ptr = src.as_ptr();
         let src_end = unsafe { src_ptr.add(src.len()) };
-        let dst_ptr = self.val.as_mut_ptr();
-        let dst_end = unsafe { dst_ptr.add(self.val.len()) };
+        let dst_ptr = self.ptr.as_ptr();
+        let dst_end = unsafe { dst_ptr.add(self.len) };
 
         if src_ptr < dst_end && src_end > dst_ptr {
             return Err(ANNError::log_index_error("Source and destination overlap".to_string()));
         }
 
         unsafe {
-            copy_nonoverlapping(src.as_ptr(), self.val.as_mut_ptr(), src.len());
+            copy_nonoverlapping(src.as_ptr(), self.ptr.as_ptr(), src.len());
         }
 
         Ok(())
@@ -101,7 +163,7 @@ impl<T> AlignedBoxWithSlice<T> {
         }
 
         let mut slices = Vec::with_capacity(range.len() / slice_len);
-        let mut remaining_slice = &mut self.val[range];
+        let mut remaining_slice = &mut self.as_mut_slice()[range];
 
         while remaining_slice.len() >= slice_len {
             let (left, right) = remaining_slice.split_at_mut(slice_len);
@@ -117,14 +179,16 @@ impl<T> AlignedBoxWithSlice<T> {
 impl<T> Drop for AlignedBoxWithSlice<T> {
     /// Frees the memory allocated for the slice using the global allocator.
     fn drop(&mut self) {
-        let val = std::mem::take(&mut self.val);
-        let mut val2 = std::mem::ManuallyDrop::new(val);
-        let ptr = val2.as_mut_ptr();
+        unsafe { std::alloc::dealloc(self.ptr.as_ptr() as *mut u8, self.layout) }
+    }
+}
 
-        unsafe {
-            // let nonNull = NonNull::new_unchecked(ptr as *mut u8);
-            std::alloc::dealloc(ptr as *mut u8, self.layout)
-        }
+impl<T: std::fmt::Debug> std::fmt::Debug for AlignedBoxWithSlice<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AlignedBoxWithSlice")
+            .field("layout", &self.layout)
+            .field("val", &self.as_slice())
+            .finish()
     }
 }
 
@@ -132,13 +196,13 @@ impl<T> Deref for AlignedBoxWithSlice<T> {
     type Target = [T];
 
     fn deref(&self) -> &Self::Target {
-        &self.val
+        self.as_slice()
     }
 }
 
 impl<T> DerefMut for AlignedBoxWithSlice<T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.val
+        self.as_mut_slice()
     }
 }
 
@@ -193,6 +257,17 @@ mod tests {
         });
     }
 
+    #[test]
+    fn ranges_are_read_and_written_while_shared() {
+        let data = AlignedBoxWithSlice::<f32>::new(8, 32).unwrap();
+        unsafe { data.write_shared(4, &[1.0, 2.0]).unwrap() };
+        assert_eq!(data.get_range(3..6).unwrap(), &[0.0, 1.0, 2.0]);
+        assert_eq!(data.get_range(8..8).unwrap(), &[] as &[f32]);
+        assert!(data.get_range(6..9).is_none());
+        assert!(unsafe { data.write_shared(7, &[1.0, 2.0]) }.is_err());
+        assert!(unsafe { data.write_shared(usize::MAX, &[1.0]) }.is_err());
+    }
+
     #[test]
     fn as_slice_test() {
         let size = 1_000_000;
@@ -1,14 +1,82 @@
 use std::alloc::LayoutError;
 use std::array::TryFromSliceError;
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::fmt::Display;
 use std::io;
 use std::num::TryFromIntError;
-use tokio::task::JoinError; // Changed from std::thread::JoinError
 
 use log::error;
 
 /// Result type alias
 pub type ANNResult<T> = Result<T, ANNError>;
 
+/// Category of an ANNError, one per variant, for callers that branch on the error rather
+/// than on its message. The discriminant of a kind is its code.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    /// Index construction and search error
+    Index = 1,
+
+    /// Index configuration error
+    IndexConfig = 2,
+
+    /// Integer conversion error
+    TryFromInt = 3,
+
+    /// IO error
+    IO = 4,
+
+    /// Layout error in memory allocation
+    MemoryAllocLayout = 5,
+
+    /// A lock was poisoned by a thread that panicked holding it
+    LockPoison = 6,
+
+    /// A disk index file isn't aligned
+    DiskIOAlignment = 7,
+
+    /// Logging error
+    Log = 8,
+
+    /// PQ construction error
+    PQ = 9,
+
+    /// Array conversion error
+    TryFromSlice = 10,
+
+    /// Task joining error
+    Join = 11,
+}
+
+impl ErrorKind {
+    /// Every kind, in the order of their codes
+    pub const ALL: [ErrorKind; 11] = [
+        ErrorKind::Index,
+        ErrorKind::IndexConfig,
+        ErrorKind::TryFromInt,
+        ErrorKind::IO,
+        ErrorKind::MemoryAllocLayout,
+        ErrorKind::LockPoison,
+        ErrorKind::DiskIOAlignment,
+        ErrorKind::Log,
+        ErrorKind::PQ,
+        ErrorKind::TryFromSlice,
+        ErrorKind::Join,
+    ];
+
+    /// Numeric code of the kind. Codes are stable across releases, new kinds get new codes
+    /// and 0 is never used, so it can stand for success on the other side of an FFI or RPC.
+    pub const fn code(&self) -> u32 {
+        *self as u32
+    }
+
+    /// Kind of a numeric code, None if no kind has it
+    pub fn from_code(code: u32) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.code() == code)
+    }
+}
+
 /// DiskANN Error
 /// ANNError is `Send` (i.e., safe to send across threads)
 #[derive(thiserror::Error, Debug)]
@@ -71,12 +139,112 @@ pub enum ANNError {
         err: TryFromSliceError,
     },
 
-    /// JoinError from task joining failures.
-    #[error("JoinError: {0}")]
-    JoinError(#[from] JoinError),
+    /// A task or thread panicked or was cancelled before it finished.
+    #[error("JoinError: {err}")]
+    JoinError { err: String, cancelled: bool },
+
+    /// An error with what was being done when it happened, e.g. the file and offset of a read.
+    /// The backtrace is only captured when RUST_BACKTRACE or RUST_LIB_BACKTRACE is set.
+    #[error("{context}: {source}")]
+    Context {
+        context: String,
+        source: Box<ANNError>,
+        backtrace: Option<Box<Backtrace>>,
+    },
+}
+
+/// Adds what was being done to the error of a result, as in
+/// `File::open(path).with_context(|| format!("opening {}", path))?`
+pub trait ANNResultExt<T> {
+    /// Wrap the error in context
+    fn context<C: Display>(self, context: C) -> ANNResult<T>;
+
+    /// Wrap the error in the context f returns, f is only called on error
+    fn with_context<C: Display, F: FnOnce() -> C>(self, f: F) -> ANNResult<T>;
+}
+
+impl<T, E: Into<ANNError>> ANNResultExt<T> for Result<T, E> {
+    fn context<C: Display>(self, context: C) -> ANNResult<T> {
+        self.map_err(|err| err.into().wrap(context.to_string()))
+    }
+
+    fn with_context<C: Display, F: FnOnce() -> C>(self, f: F) -> ANNResult<T> {
+        self.map_err(|err| err.into().wrap(f().to_string()))
+    }
 }
 
 impl ANNError {
+    /// Category of the error
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            ANNError::IndexError { .. } => ErrorKind::Index,
+            ANNError::IndexConfigError { .. } => ErrorKind::IndexConfig,
+            ANNError::TryFromIntError { .. } => ErrorKind::TryFromInt,
+            ANNError::IOError { .. } => ErrorKind::IO,
+            ANNError::MemoryAllocLayoutError { .. } => ErrorKind::MemoryAllocLayout,
+            ANNError::LockPoisonError { .. } => ErrorKind::LockPoison,
+            ANNError::DiskIOAlignmentError { .. } => ErrorKind::DiskIOAlignment,
+            ANNError::LogError { .. } => ErrorKind::Log,
+            ANNError::PQError { .. } => ErrorKind::PQ,
+            ANNError::TryFromSliceError { .. } => ErrorKind::TryFromSlice,
+            ANNError::JoinError { .. } => ErrorKind::Join,
+            ANNError::Context { source, .. } => source.kind(),
+        }
+    }
+
+    /// Stable numeric code of the error, see ErrorKind::code
+    pub fn code(&self) -> u32 {
+        self.kind().code()
+    }
+
+    /// Whether the same call may succeed if made again: IO that was interrupted or timed out,
+    /// and tasks cancelled before they finished. Invalid input, corrupt files and poisoned
+    /// locks fail the same way every time.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ANNError::IOError { err } | ANNError::LogError { err } => matches!(
+                err.kind(),
+                io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ),
+            ANNError::JoinError { cancelled, .. } => *cancelled,
+            ANNError::Context { source, .. } => source.is_retryable(),
+            _ => false,
+        }
+    }
+
+    /// Wrap the error in context, capturing a backtrace if enabled and the error has none yet
+    pub fn wrap(self, context: String) -> Self {
+        let backtrace = match &self {
+            ANNError::Context { .. } => None,
+            _ => Some(Backtrace::capture())
+                .filter(|backtrace| backtrace.status() == BacktraceStatus::Captured)
+                .map(Box::new),
+        };
+        ANNError::Context {
+            context,
+            source: Box::new(self),
+            backtrace,
+        }
+    }
+
+    /// The error under all its context
+    pub fn root_cause(&self) -> &ANNError {
+        match self {
+            ANNError::Context { source, .. } => source.root_cause(),
+            _ => self,
+        }
+    }
+
+    /// Backtrace of where context was first added to the error, None if none was captured
+    pub fn backtrace(&self) -> Option<&Backtrace> {
+        match self {
+            ANNError::Context {
+                source, backtrace, ..
+            } => source.backtrace().or(backtrace.as_deref()),
+            _ => None,
+        }
+    }
+
     /// Create, log, and return IndexError
     #[inline]
     pub fn log_index_error(err: String) -> Self {
@@ -133,6 +301,13 @@ impl ANNError {
         ANNError::PQError { err }
     }
 
+    /// Create, log, and return JoinError
+    #[inline]
+    pub fn log_join_error(err: String, cancelled: bool) -> Self {
+        error!("JoinError: {}", err);
+        ANNError::JoinError { err, cancelled }
+    }
+
     /// Create, log, and return TryFromSliceError
     #[inline]
     pub fn log_try_from_slice_error(err: TryFromSliceError) -> Self {
@@ -141,6 +316,13 @@ impl ANNError {
     }
 }
 
+#[cfg(feature = "disk-index")]
+impl From<tokio::task::JoinError> for ANNError {
+    fn from(err: tokio::task::JoinError) -> Self {
+        ANNError::log_join_error(err.to_string(), err.is_cancelled())
+    }
+}
+
 #[cfg(test)]
 mod ann_result_test {
     use super::*;
@@ -150,4 +332,49 @@ mod ann_result_test {
         fn assert_send<T: Send>() {}
         assert_send::<ANNError>();
     }
+
+    #[test]
+    fn error_codes_are_stable_and_unique() {
+        let err = ANNError::log_index_config_error("alpha".to_string(), "negative".to_string());
+        assert_eq!(err.kind(), ErrorKind::IndexConfig);
+        assert_eq!(err.code(), 2);
+        assert_eq!(ANNError::log_pq_error(String::new()).code(), 9);
+
+        for (i, kind) in ErrorKind::ALL.into_iter().enumerate() {
+            assert_eq!(kind.code(), i as u32 + 1);
+            assert_eq!(ErrorKind::from_code(kind.code()), Some(kind));
+        }
+        assert_eq!(ErrorKind::from_code(0), None);
+    }
+
+    #[test]
+    fn only_transient_errors_are_retryable() {
+        let timed_out = ANNError::log_io_error(io::Error::from(io::ErrorKind::TimedOut));
+        assert!(timed_out.is_retryable());
+        let not_found = ANNError::log_io_error(io::Error::from(io::ErrorKind::NotFound));
+        assert!(!not_found.is_retryable());
+        assert!(!ANNError::log_lock_poison_error(String::new()).is_retryable());
+        assert!(!ANNError::log_index_error(String::new()).is_retryable());
+        assert!(ANNError::log_join_error(String::new(), true).is_retryable());
+        assert!(!ANNError::log_join_error(String::new(), false).is_retryable());
+    }
+
+    #[test]
+    fn context_chains_onto_the_error() {
+        let result: io::Result<()> = Err(io::Error::from(io::ErrorKind::TimedOut));
+        let err = result
+            .context("reading 4096 bytes at offset 8192")
+            .with_context(|| format!("searching {}", "index_disk.index"))
+            .unwrap_err();
+
+        let message = err.to_string();
+        assert!(message
+            .starts_with("searching index_disk.index: reading 4096 bytes at offset 8192: IOError"));
+        assert_eq!(err.kind(), ErrorKind::IO);
+        assert!(err.is_retryable());
+        assert!(matches!(err.root_cause(), ANNError::IOError { .. }));
+
+        let ok: ANNResult<u32> = Ok(1);
+        assert_eq!(ok.with_context(|| -> String { unreachable!() }).unwrap(), 1);
+    }
 }
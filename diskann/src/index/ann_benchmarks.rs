@@ -0,0 +1,248 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Adapter following the ann-benchmarks algorithm interface
+//! (fit / set_query_arguments / query / batch_query / get_batch_results).
+//! ann-benchmarks hands over f32 row-major arrays and names its metrics "euclidean" and
+//! "angular", the adapter takes care of the padding to the aligned dimension and of the
+//! data file the in-memory index is built from.
+
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use rayon::prelude::*;
+use vector::Metric;
+
+use crate::common::{ANNError, ANNResult};
+use crate::index::{create_inmem_index, ANNInmemIndex};
+use crate::model::{IndexConfiguration, IndexWriteParametersBuilder};
+use crate::utils::{delete_file, round_up, save_bin_f32};
+
+/// Sequence number of the data files written by fit, so adapters can fit concurrently
+static FIT_FILE_SEQUENCE: AtomicUsize = AtomicUsize::new(0);
+
+/// In-memory index wrapped in the ann-benchmarks algorithm interface
+pub struct AnnBenchmarksIndex {
+    metric: Metric,
+    max_degree: u32,
+    l_build: u32,
+    alpha: f32,
+    num_threads: u32,
+    l_search: u32,
+    dim: usize,
+    aligned_dim: usize,
+    index: Option<Box<dyn ANNInmemIndex<f32>>>,
+    batch_results: Vec<Vec<u32>>,
+}
+
+impl AnnBenchmarksIndex {
+    /// Create an adapter for the ann-benchmarks metric name ("euclidean" or "angular")
+    /// and the build parameters of the index
+    pub fn new(
+        metric: &str,
+        max_degree: u32,
+        l_build: u32,
+        alpha: f32,
+        num_threads: u32,
+    ) -> ANNResult<Self> {
+        let metric = match metric {
+            "euclidean" => Metric::L2,
+            "angular" => Metric::Cosine,
+            _ => {
+                return Err(ANNError::log_index_config_error(
+                    "metric".to_string(),
+                    format!("Unsupported ann-benchmarks metric {}, use euclidean or angular", metric),
+                ))
+            }
+        };
+
+        Ok(Self {
+            metric,
+            max_degree,
+            l_build,
+            alpha,
+            num_threads,
+            l_search: l_build,
+            dim: 0,
+            aligned_dim: 0,
+            index: None,
+            batch_results: Vec::new(),
+        })
+    }
+
+    /// Build the index over row-major points of dimension dim
+    pub fn fit(&mut self, data: &[f32], dim: usize) -> ANNResult<()> {
+        if dim == 0 || data.is_empty() || !data.len().is_multiple_of(dim) {
+            return Err(ANNError::log_index_error(format!(
+                "Data of {} values can't be split in points of dimension {}",
+                data.len(),
+                dim
+            )));
+        }
+
+        let num_points = data.len() / dim;
+        let aligned_dim = round_up(dim, 8);
+        let index_write_parameters = IndexWriteParametersBuilder::new(self.l_build, self.max_degree)
+            .with_alpha(self.alpha)
+            .with_num_threads(self.num_threads)
+            .build();
+        let config = IndexConfiguration::new(
+            self.metric,
+            dim,
+            aligned_dim,
+            num_points,
+            false,
+            0,
+            false,
+            0,
+            1f32,
+            index_write_parameters,
+        );
+        let mut index = create_inmem_index::<f32>(config)?;
+
+        // The index builds from a bin file, write the points to a temporary one
+        let data_file = std::env::temp_dir().join(format!(
+            "diskann_ann_benchmarks_{}_{}.bin",
+            std::process::id(),
+            FIT_FILE_SEQUENCE.fetch_add(1, Ordering::Relaxed)
+        ));
+        let data_file = data_file.to_string_lossy().to_string();
+        save_bin_f32(&data_file, data, num_points, dim, 0)?;
+        let build_result = index.build(&data_file, num_points);
+        delete_file(&data_file)?;
+        build_result?;
+
+        self.dim = dim;
+        self.aligned_dim = aligned_dim;
+        self.index = Some(index);
+        self.batch_results.clear();
+        Ok(())
+    }
+
+    /// Set the search list size used by the following queries
+    pub fn set_query_arguments(&mut self, l_search: u32) {
+        self.l_search = l_search;
+    }
+
+    /// Ids of the n nearest neighbors of v
+    pub fn query(&self, v: &[f32], n: usize) -> ANNResult<Vec<u32>> {
+        let index = self.fitted_index()?;
+        if v.len() != self.dim {
+            return Err(ANNError::log_index_error(format!(
+                "Query has dimension {}, the index was fitted with dimension {}",
+                v.len(),
+                self.dim
+            )));
+        }
+
+        let mut query = vec![0f32; self.aligned_dim];
+        query[..self.dim].copy_from_slice(v);
+        self.search(index, &query, n)
+    }
+
+    /// Search row-major queries in parallel, the results are kept for get_batch_results
+    pub fn batch_query(&mut self, queries: &[f32], n: usize) -> ANNResult<()> {
+        let index = self.fitted_index()?;
+        if !queries.len().is_multiple_of(self.dim) {
+            return Err(ANNError::log_index_error(format!(
+                "Queries of {} values can't be split in points of dimension {}",
+                queries.len(),
+                self.dim
+            )));
+        }
+
+        let batch_results = queries
+            .par_chunks(self.dim)
+            .map(|v| {
+                let mut query = vec![0f32; self.aligned_dim];
+                query[..self.dim].copy_from_slice(v);
+                self.search(index, &query, n)
+            })
+            .collect::<ANNResult<Vec<Vec<u32>>>>()?;
+
+        self.batch_results = batch_results;
+        Ok(())
+    }
+
+    /// Results of the last batch_query, one list of ids per query
+    pub fn get_batch_results(&self) -> &[Vec<u32>] {
+        &self.batch_results
+    }
+
+    fn fitted_index(&self) -> ANNResult<&dyn ANNInmemIndex<f32>> {
+        self.index.as_deref().ok_or_else(|| {
+            ANNError::log_index_error("The index must be fitted before querying".to_string())
+        })
+    }
+
+    fn search(&self, index: &dyn ANNInmemIndex<f32>, query: &[f32], n: usize) -> ANNResult<Vec<u32>> {
+        // The search list can't be shorter than the number of results
+        let l_value = self.l_search.max(n as u32);
+        let mut ids = vec![0u32; n];
+        index.search(query, n, l_value, &mut ids)?;
+        Ok(ids)
+    }
+}
+
+/// Name reported in the ann-benchmarks results
+impl fmt::Display for AnnBenchmarksIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "DiskANN(R={}, L={}, alpha={}, L_search={})",
+            self.max_degree, self.l_build, self.alpha, self.l_search
+        )
+    }
+}
+
+impl fmt::Debug for AnnBenchmarksIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AnnBenchmarksIndex")
+            .field("metric", &self.metric)
+            .field("max_degree", &self.max_degree)
+            .field("l_build", &self.l_build)
+            .field("alpha", &self.alpha)
+            .field("l_search", &self.l_search)
+            .field("dim", &self.dim)
+            .field("fitted", &self.index.is_some())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod ann_benchmarks_test {
+    use super::*;
+
+    #[test]
+    fn fit_and_query_like_ann_benchmarks() {
+        let dim = 100;
+        let num_points = 200;
+        let data: Vec<f32> = (0..num_points * dim)
+            .map(|i| ((i / dim) as f32 * 0.37 + (i % dim) as f32 * 0.11).sin())
+            .collect();
+
+        let mut algo = AnnBenchmarksIndex::new("euclidean", 16, 50, 1.2, 1).unwrap();
+        assert!(algo.query(&data[..dim], 5).is_err());
+        algo.fit(&data, dim).unwrap();
+        algo.set_query_arguments(40);
+        assert_eq!(algo.to_string(), "DiskANN(R=16, L=50, alpha=1.2, L_search=40)");
+
+        // Every point is its own nearest neighbor
+        assert_eq!(algo.query(&data[7 * dim..8 * dim], 3).unwrap()[0], 7);
+
+        let queries = &data[..10 * dim];
+        algo.batch_query(queries, 3).unwrap();
+        let results = algo.get_batch_results();
+        assert_eq!(results.len(), 10);
+        for (i, ids) in results.iter().enumerate() {
+            assert_eq!(ids.len(), 3);
+            assert_eq!(ids[0], i as u32);
+        }
+
+        assert!(algo.query(&data[..dim - 1], 3).is_err());
+        assert!(AnnBenchmarksIndex::new("jaccard", 16, 50, 1.2, 1).is_err());
+    }
+}
@@ -6,10 +6,11 @@
 
 //! ANN disk index abstraction
 
-use vector::FullPrecisionDistance;
+use vector::{FullPrecisionDistance, Metric};
 
-use crate::model::{IndexConfiguration, DiskIndexBuildParameters};
+use crate::model::{IndexConfiguration, DiskIndexBuildParameters, IndexWriteParameters};
 use crate::storage::DiskIndexStorage;
+use crate::utils::{load_metadata_from_file, round_up};
 use crate::model::vertex::{DIM_128, DIM_256, DIM_104};
 
 use crate::common::{ANNResult, ANNError};
@@ -36,6 +37,10 @@ where
     [T; DIM_128]: FullPrecisionDistance<T, DIM_128>,
     [T; DIM_256]: FullPrecisionDistance<T, DIM_256>,
 {
+    if disk_build_param.is_some() {
+        config.index_write_parameter.validate()?;
+    }
+
     match config.aligned_dim {
         DIM_104 => {
             let index = Box::new(DiskIndex::<T, DIM_104>::new(disk_build_param, config, storage));
@@ -52,3 +57,90 @@ where
         _ => Err(ANNError::log_index_error(format!("Invalid dimension: {}", config.aligned_dim))),
     }
 }
+
+/// Build a disk index from a data file in one call: train PQ and compress the data, build the
+/// in-memory graph, then write the disk layout and the warm-up queries. The outputs are
+/// {index_path_prefix}_disk.index, {index_path_prefix}.bin_pq_pivots.bin and
+/// {index_path_prefix}.bin_pq_compressed.bin. With num_pq_chunks 0 the number of PQ chunks is
+/// derived from the search RAM budget.
+pub fn build_disk_index<T>(
+    data_path: &str,
+    index_path_prefix: &str,
+    metric: Metric,
+    index_write_parameters: IndexWriteParameters,
+    disk_build_param: DiskIndexBuildParameters,
+    num_pq_chunks: usize,
+) -> ANNResult<()>
+where
+    T: Default + Copy + Sync + Send + Into<f32>,
+    [T; DIM_104]: FullPrecisionDistance<T, DIM_104>,
+    [T; DIM_128]: FullPrecisionDistance<T, DIM_128>,
+    [T; DIM_256]: FullPrecisionDistance<T, DIM_256>,
+{
+    let (data_num, data_dim) = load_metadata_from_file(data_path)?;
+
+    let config = IndexConfiguration::new(
+        metric,
+        data_dim,
+        round_up(data_dim, 8),
+        data_num,
+        num_pq_chunks > 0,
+        num_pq_chunks,
+        false,
+        0,
+        1f32,
+        index_write_parameters,
+    );
+    let storage = DiskIndexStorage::new(data_path.to_string(), index_path_prefix.to_string())?;
+    let mut index = create_disk_index::<T>(Some(disk_build_param), config, storage)?;
+
+    index.build("")
+}
+
+#[cfg(test)]
+mod ann_disk_index_test {
+    use std::fs;
+
+    use crate::model::IndexWriteParametersBuilder;
+    use crate::test_utils::get_test_file_path;
+    use crate::utils::file_exists;
+
+    use super::*;
+
+    #[test]
+    fn build_disk_index_writes_outputs() {
+        let index_path_prefix = "build_disk_index_writes_outputs";
+        let index_write_parameters = IndexWriteParametersBuilder::new(50, 4)
+            .with_saturate_graph(true)
+            .with_num_threads(1)
+            .build();
+        let disk_build_param = DiskIndexBuildParameters::new(0.01, 1.0).unwrap();
+
+        build_disk_index::<f32>(
+            get_test_file_path("tests/data/siftsmall_learn_256pts.fbin").as_str(),
+            index_path_prefix,
+            Metric::L2,
+            index_write_parameters,
+            disk_build_param,
+            16,
+        )
+        .unwrap();
+
+        let outputs = [
+            format!("{}_disk.index", index_path_prefix),
+            format!("{}.bin_pq_pivots.bin", index_path_prefix),
+            format!("{}.bin_pq_compressed.bin", index_path_prefix),
+        ];
+        for output in outputs {
+            assert!(file_exists(&output), "{} is missing", output);
+        }
+        assert!(!file_exists(&format!("{}_mem.index", index_path_prefix)));
+        assert!(!file_exists(&format!("{}_mem.index.data", index_path_prefix)));
+
+        for file in fs::read_dir(".").unwrap().flatten() {
+            if file.file_name().to_string_lossy().starts_with(index_path_prefix) {
+                fs::remove_file(file.path()).unwrap();
+            }
+        }
+    }
+}
@@ -1,16 +1,27 @@
 use std::mem;
+use std::sync::Arc;
 
 use log::{info, error};
-use vector::FullPrecisionDistance;
+use tracing::info_span;
+use vector::{kernel_report, FullPrecisionDistance};
 
-use crate::common::{ANNResult, ANNError};
+use crate::common::{ANNResult, ANNResultExt, ANNError};
 use crate::index::{InmemIndex, ANNInmemIndex};
+use crate::instrumentation::{
+    next_span_id, BuildReport, BuildReportParameters, DiskIndexBuildLogger, LatencyRecorder,
+    ProgressNotifier, QueryRecorder, BUILD_SPAN_TARGET,
+};
 use crate::model::configuration::DiskIndexBuildParameters;
-use crate::model::{IndexConfiguration, MAX_PQ_TRAINING_SET_SIZE, MAX_PQ_CHUNKS, generate_quantized_data, GRAPH_SLACK_FACTOR};
-use crate::storage::DiskIndexStorage;
-use crate::utils::set_rayon_num_threads;
+use crate::model::{IndexConfiguration, MAX_PQ_TRAINING_SET_SIZE, MAX_PQ_CHUNKS, generate_quantized_data, PQRotation, PQTrainingSample, GRAPH_SLACK_FACTOR};
+use crate::storage::{DiskIndexStorage, IndexVerificationReport, IoCoordinator};
+use crate::utils::{
+    install_on_pool, lock_index_output, step_seed, RandomStep,
+};
 
 use super::ann_disk_index::ANNDiskIndex;
+#[cfg(target_os = "linux")]
+use super::disk_index_search::DiskSearchData;
+use super::sharded_build::build_sharded_graph;
 
 pub const OVERHEAD_FACTOR: f64 = 1.1f64;
 
@@ -27,6 +38,28 @@ where
     configuration: IndexConfiguration, 
 
     pub storage: DiskIndexStorage<T>,
+
+    /// Loaded by load for the query path
+    #[cfg(target_os = "linux")]
+    pub(super) search_data: Option<DiskSearchData<T, N>>,
+
+    /// Records the latency of every search when set
+    pub(super) latency_recorder: Option<Arc<LatencyRecorder>>,
+
+    /// Appends a sample of the searches to a query log when set
+    pub(super) query_recorder: Option<Arc<QueryRecorder>>,
+
+    /// Takes turns for the sector reads of the searches with the writes of the file when set
+    pub(super) io_coordinator: Option<Arc<IoCoordinator>>,
+
+    /// Publishes the progress of the graph build when set
+    progress_notifier: Option<Arc<ProgressNotifier>>,
+
+    /// Report of the last build
+    build_report: Option<BuildReport>,
+
+    /// File the report of a build is written to as JSON when set
+    build_report_file: Option<String>,
 }
 
 impl<T, const N: usize> DiskIndex<T, N>
@@ -43,9 +76,64 @@ where
             disk_build_param,
             configuration,
             storage,
+            #[cfg(target_os = "linux")]
+            search_data: None,
+            latency_recorder: None,
+            query_recorder: None,
+            io_coordinator: None,
+            progress_notifier: None,
+            build_report: None,
+            build_report_file: None,
         }
     }
 
+    /// Record the latency of every search in recorder, which may be shared with other indexes
+    pub fn with_latency_recorder(mut self, recorder: Arc<LatencyRecorder>) -> Self {
+        self.latency_recorder = Some(recorder);
+        self
+    }
+
+    pub fn latency_recorder(&self) -> Option<&Arc<LatencyRecorder>> {
+        self.latency_recorder.as_ref()
+    }
+
+    /// Append the searches the recorder samples, with their results and stats, to its log
+    pub fn with_query_recorder(mut self, recorder: Arc<QueryRecorder>) -> Self {
+        self.query_recorder = Some(recorder);
+        self
+    }
+
+    /// Read the sectors of the searches in turns given by coordinator, shared with the
+    /// AlignedFileStorageProvider writing the nodes of the index in place
+    pub fn with_io_coordinator(mut self, coordinator: Arc<IoCoordinator>) -> Self {
+        self.io_coordinator = Some(coordinator);
+        self
+    }
+
+    /// Write the report of a build to filename as JSON
+    pub fn with_build_report_file(mut self, filename: String) -> Self {
+        self.build_report_file = Some(filename);
+        self
+    }
+
+    /// Report of the last build of this index: durations of its steps, peak memory,
+    /// parameters and output file sizes
+    pub fn build_report(&self) -> Option<&BuildReport> {
+        self.build_report.as_ref()
+    }
+
+    /// Check the files of the index for truncation and corruption, see
+    /// DiskIndexStorage::verify
+    pub fn verify(&self) -> ANNResult<IndexVerificationReport> {
+        self.storage.verify()
+    }
+
+    /// Publish the progress of the in-memory graph build with notifier
+    pub fn with_progress_notifier(mut self, notifier: Arc<ProgressNotifier>) -> Self {
+        self.progress_notifier = Some(notifier);
+        self
+    }
+
     pub fn disk_build_param(&self) -> &Option<DiskIndexBuildParameters> {
         &self.disk_build_param
     }
@@ -55,16 +143,32 @@ where
     }
 
     fn build_inmem_index(&self, num_points: usize, data_path: &str, inmem_index_path: &str) -> ANNResult<()> {
+        // Over the budget the graph is built in shards that each fit it
         let estimated_index_ram = self.estimate_ram_usage(num_points);
-        if estimated_index_ram >= self.fetch_disk_build_param()?.index_build_ram_limit() * 1024_f64 * 1024_f64 * 1024_f64 {
-            return Err(ANNError::log_index_error(format!(
-                "Insufficient memory budget for index build, index_build_ram_limit={}GB estimated_index_ram={}GB",
-                self.fetch_disk_build_param()?.index_build_ram_limit(),
+        let index_build_ram_limit = self.fetch_disk_build_param()?.index_build_ram_limit();
+        if estimated_index_ram >= index_build_ram_limit {
+            info!(
+                "Index build needs {}GB, over the index_build_ram_limit of {}GB, building in shards",
                 estimated_index_ram / (1024_f64 * 1024_f64 * 1024_f64),
-            )));
+                index_build_ram_limit / (1024_f64 * 1024_f64 * 1024_f64),
+            );
+            return build_sharded_graph::<T, N>(
+                &self.configuration,
+                data_path,
+                inmem_index_path,
+                index_build_ram_limit,
+                |size| self.estimate_ram_usage(size),
+            );
         }
 
-        let mut index = InmemIndex::<T, N>::new(self.configuration.clone())?;
+        // The graph is built on the full precision vectors, PQ is only used by the disk search
+        let mut config = self.configuration.clone();
+        config.use_pq_dist = false;
+
+        let mut index = InmemIndex::<T, N>::new(config)?;
+        if let Some(notifier) = &self.progress_notifier {
+            index.set_progress_notifier(notifier.clone());
+        }
         index.build(data_path, num_points)?;
         index.save(inmem_index_path)?;
 
@@ -98,9 +202,13 @@ where
     [T; N]: FullPrecisionDistance<T, N>,
 {
     fn build(&mut self, codebook_prefix: &str) -> ANNResult<()> {
-        if self.configuration.index_write_parameter.num_threads > 0 {
-            set_rayon_num_threads(self.configuration.index_write_parameter.num_threads);
-        }
+        // Held for the whole build so a concurrent build can't interleave writes to the same outputs
+        let _output_lock = lock_index_output(self.storage.index_path_prefix())?;
+
+        self.configuration.start_thread_pool()?;
+
+        let mut logger = DiskIndexBuildLogger::for_points(self.configuration.max_points);
+        let build_id = next_span_id();
 
         info!("Starting index build: R={} L={} Query RAM budget={} Indexing RAM budget={} T={}",
             self.configuration.index_write_parameter.max_degree, 
@@ -109,45 +217,102 @@ where
             self.fetch_disk_build_param()?.index_build_ram_limit(),
             self.configuration.index_write_parameter.num_threads
         );
+        info!("Distance kernels: {}", kernel_report());
 
         // PQ memory consumption = PQ pivots + PQ compressed table
         // PQ pivots: dim * num_centroids * sizeof::<T>()
         // PQ compressed table: num_pts * num_pq_chunks * (dim / num_pq_chunks) * sizeof::<u8>()
         // * Because num_centroids is 256, centroid id can be represented by u8
+        // * With 4-bit codes num_centroids is 16, and two centroid ids share a u8
+        // An explicit number of chunks in the configuration overrides the search RAM budget
         let num_points = self.configuration.max_points;
         let dim = self.configuration.dim;
-        let p_val = MAX_PQ_TRAINING_SET_SIZE / (num_points as f64);
-        let mut num_pq_chunks = ((self.fetch_disk_build_param()?.search_ram_limit() / (num_points as f64)).floor()) as usize;
+        let code_bits = self.configuration.pq_code_bits;
+        let training_sample = match self.configuration.pq_training_set_size {
+            0 => PQTrainingSample::Rate(MAX_PQ_TRAINING_SET_SIZE / (num_points as f64)),
+            sample_size => PQTrainingSample::Reservoir(sample_size),
+        };
+        let mut num_pq_chunks = if self.configuration.num_pq_chunks > 0 {
+            self.configuration.num_pq_chunks
+        } else {
+            let code_bytes = (self.fetch_disk_build_param()?.search_ram_limit()
+                / (num_points as f64))
+                .floor() as usize;
+            code_bytes * code_bits.codes_per_byte()
+        };
         num_pq_chunks = if num_pq_chunks == 0 { 1 } else { num_pq_chunks };
         num_pq_chunks = if num_pq_chunks > dim { dim } else { num_pq_chunks };
         num_pq_chunks = if num_pq_chunks > MAX_PQ_CHUNKS { MAX_PQ_CHUNKS } else { num_pq_chunks };
 
-        info!("Compressing {}-dimensional data into {} bytes per vector.", dim, num_pq_chunks);
+        let pq_rotation = if self.configuration.use_opq {
+            PQRotation::Learned
+        } else {
+            self.configuration.pq_rotation
+        };
 
-        generate_quantized_data::<T>(
-            p_val,
-            num_pq_chunks,
-            codebook_prefix,
-            self.storage.get_pq_storage(),
-        )?;
+        info!("Compressing {}-dimensional data into {} bytes per vector.", dim, code_bits.code_bytes(num_pq_chunks));
 
-        info!("Finished PQ construction");
+        let random_seed = self.configuration.random_seed;
+        let pq_storage = self.storage.get_pq_storage();
+        let thread_pool = self.configuration.thread_pool.as_deref();
+        info_span!(target: BUILD_SPAN_TARGET, "pq_construction", build_id, num_points)
+            .in_scope(|| {
+                install_on_pool(thread_pool, || {
+                    generate_quantized_data::<T>(
+                        training_sample,
+                        num_pq_chunks,
+                        pq_rotation,
+                        code_bits,
+                        codebook_prefix,
+                        pq_storage,
+                        random_seed,
+                    )
+                })
+            })
+            .context("PQ construction")?;
+
+        logger.log_checkpoint("PQ construction")?;
 
         let inmem_index_path = self.storage.index_path_prefix().clone() + "_mem.index";
-        self.build_inmem_index(num_points, self.storage.dataset_file(), inmem_index_path.as_str())?;
-        info!("Finished in-memory index build");
+        info_span!(target: BUILD_SPAN_TARGET, "graph_build", build_id, num_points)
+            .in_scope(|| {
+                self.build_inmem_index(num_points, self.storage.dataset_file(), inmem_index_path.as_str())
+            })
+            .context("In-memory index build")?;
+        logger.log_checkpoint("In-memory index build")?;
 
-        self.storage.create_disk_layout()?;
-        info!("Finished disk layout creation");
+        info_span!(target: BUILD_SPAN_TARGET, "disk_layout", build_id, num_points)
+            .in_scope(|| self.storage.create_disk_layout())
+            .context("Disk layout creation")?;
+        logger.log_checkpoint("Disk layout creation")?;
 
         let ten_percent_points = ((num_points as f64) * 0.1_f64).ceil();
         let num_sample_points = if ten_percent_points > (MAX_SAMPLE_POINTS_FOR_WARMUP as f64) { MAX_SAMPLE_POINTS_FOR_WARMUP as f64 } else { ten_percent_points };
         let sample_sampling_rate = num_sample_points / (num_points as f64);
-        self.storage.gen_query_warmup_data(sample_sampling_rate)?;
-        info!("Generated query warm-up data");
+        let warmup_seed = step_seed(self.configuration.random_seed, RandomStep::WarmupSample);
+        info_span!(target: BUILD_SPAN_TARGET, "query_warmup", build_id, num_points = num_sample_points as u64)
+            .in_scope(|| self.storage.gen_query_warmup_data(sample_sampling_rate, warmup_seed))
+            .context("Query warm-up data")?;
+        logger.log_checkpoint("Query warm-up data")?;
+
+        self.storage.index_build_cleanup().context("Index build cleanup")?;
+        logger.log_checkpoint("Index build cleanup")?;
 
-        self.storage.index_build_cleanup()?;
-        info!("Cleaned up index build resources");
+        let parameters = BuildReportParameters {
+            num_points,
+            dim,
+            metric: format!("{:?}", self.configuration.dist_metric),
+            index_write: self.configuration.index_write_parameter,
+            num_pq_chunks,
+            pq_code_bits: 8 / code_bits.codes_per_byte(),
+            search_ram_limit_bytes: self.fetch_disk_build_param()?.search_ram_limit(),
+            index_build_ram_limit_bytes: self.fetch_disk_build_param()?.index_build_ram_limit(),
+        };
+        let report = logger.finalize(parameters, &self.storage.output_files());
+        if let Some(report_file) = &self.build_report_file {
+            report.save_json(report_file)?;
+        }
+        self.build_report = Some(report);
 
         Ok(())
     }
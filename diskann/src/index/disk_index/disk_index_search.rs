@@ -0,0 +1,1604 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_docs)]
+
+//! Beam search over a disk index.
+//! The PQ codes of all points stay in memory and rank the candidates by approximate L2 distance.
+//! The nodes within a few hops of the medoid are cached when the index is loaded; every other
+//! node is read from its sector when expanded, beam_width nodes per round trip to the disk. The
+//! full precision vectors come with the nodes, so the expanded nodes are reranked by the exact
+//! distance of the index metric. With adaptive prefetch, the nodes read per round trip follow
+//! the PrefetchWindow of the index instead of the fixed beam width.
+//! A search ranked by PQ distance can rerank the closest candidates of its search list: their
+//! vectors sit in the same sector as their neighbors, so one more round trip reads them.
+//! An index loaded from a StorageProvider reads its nodes from the provider instead of the
+//! sectors of the disk index file.
+
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use byteorder::{ByteOrder, LittleEndian};
+use log::info;
+use platform::FileLock;
+use tracing::{field, info_span, Span};
+use vector::{FullPrecisionDistance, Metric};
+
+use crate::algorithm::search::search::{merge_frontiers, StallCounter, FRONTIER_MERGE_INTERVAL};
+use crate::common::{ANNError, ANNResult};
+use crate::instrumentation::{next_span_id, QueryStats, SEARCH_SPAN_TARGET};
+use crate::model::{
+    aggregate_coords, pq_dist_lookup, pq_dist_lookup_packed4, AlignedRead, FixedChunkPQTable,
+    LinuxAlignedFileReader, Neighbor, NeighborPriorityQueue, PQCodeBits, SearchParams,
+    MAX_N_SECTOR_READS, SECTOR_LEN,
+};
+use crate::storage::{
+    copy_nodes, AlignedFileStorageProvider, DiskLayoutMeta, IoClass, IoCoordinator, IoPermit,
+    SectorUsageStats, StorageProvider, StoredNode, DEFAULT_SECTOR_USAGE_DECAY,
+};
+use crate::utils::lock_index_input;
+
+use super::{DiskIndex, PrefetchWindow, DEFAULT_MAX_QUEUE_DEPTH};
+
+/// A node read from the disk index
+struct DiskNode<T> {
+    /// Full precision vector padded with zeros to N values
+    vector: Vec<T>,
+
+    /// Ids of the neighbors
+    neighbors: Vec<u32>,
+}
+
+impl<T> DiskNode<T> {
+    /// Full precision distance from the query to the vector of node id
+    fn distance<const N: usize>(&self, id: u32, query: &[T; N], metric: Metric) -> ANNResult<f32>
+    where
+        [T; N]: FullPrecisionDistance<T, N>,
+    {
+        let vector = <&[T; N]>::try_from(self.vector.as_slice()).map_err(|err| {
+            ANNError::log_index_error(format!("Failed to get vector {}, err={}", id, err))
+        })?;
+        Ok(<[T; N]>::distance_compare(query, vector, metric))
+    }
+}
+
+/// Where the nodes missing from the cache are read from
+enum NodeSource<T> {
+    /// Sectors of the disk index file
+    File(LinuxAlignedFileReader),
+
+    /// Nodes of a storage provider
+    Provider(Box<dyn StorageProvider<T> + Send + Sync>),
+}
+
+/// What the disk index keeps in memory to search
+pub(crate) struct DiskSearchData<T, const N: usize> {
+    layout_meta: DiskLayoutMeta,
+
+    pq_table: FixedChunkPQTable,
+
+    /// PQ codes of all points, num_pq_chunks per point, packed two per byte for 4-bit codes
+    pq_codes: Vec<u8>,
+
+    num_pq_chunks: usize,
+
+    /// Nodes around the medoid, never read from disk during search
+    node_cache: HashMap<u32, DiskNode<T>>,
+
+    nodes: NodeSource<T>,
+
+    /// Nodes read per round trip by the searches with adaptive prefetch
+    prefetch: PrefetchWindow,
+
+    /// Sectors read by the searches, None when the nodes are read from a provider
+    sector_usage: Option<SectorUsageStats>,
+
+    /// Gives the turns of the reads when the nodes are also written in place
+    io_coordinator: Option<Arc<IoCoordinator>>,
+
+    /// Keeps builds from writing the index while it is loaded
+    _input_lock: FileLock,
+}
+
+impl<T, const N: usize> DiskSearchData<T, N>
+where
+    T: Default + Copy + Into<f32>,
+{
+    /// Read nodes from the disk index, reading the sectors of every node once, or from the
+    /// provider
+    async fn read_nodes(&self, ids: &[u32]) -> ANNResult<Vec<DiskNode<T>>> {
+        let _permit = self.read_permit().await?;
+        let reader = match &self.nodes {
+            NodeSource::File(reader) => reader,
+            NodeSource::Provider(provider) => {
+                return ids
+                    .iter()
+                    .map(|&id| self.stored_node(id, provider.get_node(id)?))
+                    .collect()
+            }
+        };
+
+        let mut sectors = Vec::new();
+        let mut sector_index = HashMap::new();
+        for &id in ids {
+            let sector = self.layout_meta.node_sector(id);
+            sector_index.entry(sector).or_insert_with(|| {
+                sectors.push(sector);
+                sectors.len() - 1
+            });
+        }
+
+        if let Some(sector_usage) = &self.sector_usage {
+            for &sector in &sectors {
+                sector_usage.record_access(sector);
+            }
+        }
+
+        let read_len = self.layout_meta.sectors_per_node() * SECTOR_LEN;
+        let read_requests = sectors
+            .iter()
+            .map(|&sector| AlignedRead::new((sector * SECTOR_LEN) as u64, vec![0u8; read_len]))
+            .collect::<ANNResult<Vec<_>>>()?;
+        let sector_reads = reader.read(read_requests).await?;
+
+        ids.iter()
+            .map(|&id| {
+                let sector_read = &sector_reads[sector_index[&self.layout_meta.node_sector(id)]];
+                self.parse_node(&sector_read.aligned_buf, id)
+            })
+            .collect()
+    }
+
+    /// Turn of a read from the io coordinator, if there is one, waiting for it on a blocking
+    /// thread while a write holds the file
+    async fn read_permit(&self) -> ANNResult<Option<IoPermit>> {
+        let Some(coordinator) = &self.io_coordinator else {
+            return Ok(None);
+        };
+        if let Some(permit) = coordinator.try_acquire(IoClass::Read)? {
+            return Ok(Some(permit));
+        }
+
+        let coordinator = coordinator.clone();
+        tokio::task::spawn_blocking(move || coordinator.acquire(IoClass::Read))
+            .await?
+            .map(Some)
+    }
+
+    /// Sectors of num_nodes nodes read from the disk
+    fn sectors_read(&self, num_nodes: usize) -> u64 {
+        (num_nodes * self.layout_meta.sectors_per_node()) as u64
+    }
+
+    /// Pad the vector of a node of the provider with zeros to N values
+    fn stored_node(&self, id: u32, node: StoredNode<T>) -> ANNResult<DiskNode<T>> {
+        if node.vector.len() != self.layout_meta.dim {
+            return Err(ANNError::log_index_error(format!(
+                "ERROR: Node {} has a vector of dimension {}, expecting {}.",
+                id,
+                node.vector.len(),
+                self.layout_meta.dim
+            )));
+        }
+
+        let mut vector = node.vector;
+        vector.resize(N, T::default());
+        Ok(DiskNode {
+            vector,
+            neighbors: node.neighbors,
+        })
+    }
+
+    /// Parse a node from the sectors it starts in:
+    /// {vector: [T; dim]}{num_nbrs: u32}{neighbors: [u32; num_nbrs]}
+    fn parse_node(&self, sector_buf: &[u8], id: u32) -> ANNResult<DiskNode<T>> {
+        let node_start = self.layout_meta.node_offset_in_sector(id);
+        let node_buf = &sector_buf[node_start..node_start + self.layout_meta.max_node_len];
+
+        let vector_len = self.layout_meta.dim * mem::size_of::<T>();
+        let mut vector = vec![T::default(); N];
+        // SAFETY: the vector holds N >= dim values of T, and T is a plain number type
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                node_buf.as_ptr(),
+                vector.as_mut_ptr() as *mut u8,
+                vector_len,
+            );
+        }
+
+        let num_nbrs = LittleEndian::read_u32(&node_buf[vector_len..]) as usize;
+        let nbrs_start = vector_len + mem::size_of::<u32>();
+        let nbrs_end = nbrs_start + num_nbrs * mem::size_of::<u32>();
+        if nbrs_end > node_buf.len() {
+            return Err(ANNError::log_index_error(format!(
+                "ERROR: Node {} has {} neighbors, more than a node of {}B holds.",
+                id, num_nbrs, self.layout_meta.max_node_len
+            )));
+        }
+
+        let mut neighbors = vec![0u32; num_nbrs];
+        LittleEndian::read_u32_into(&node_buf[nbrs_start..nbrs_end], &mut neighbors);
+
+        Ok(DiskNode { vector, neighbors })
+    }
+
+    /// Cache the nodes of ids in the disk index
+    async fn cache_nodes(&mut self, ids: &[u32]) -> ANNResult<()> {
+        for ids in ids.chunks(MAX_N_SECTOR_READS) {
+            let nodes = self.read_nodes(ids).await?;
+            self.node_cache.extend(ids.iter().copied().zip(nodes));
+        }
+        Ok(())
+    }
+
+    /// Cache up to num_nodes nodes in breadth-first order from the medoid, so searches skip
+    /// the reads of the first hops. Returns the number of BFS levels cached, the last one
+    /// possibly in part.
+    async fn cache_bfs_levels(&mut self, num_nodes: usize) -> ANNResult<usize> {
+        let medoid = self.layout_meta.medoid;
+        let mut seen = HashSet::from([medoid]);
+        let mut frontier = vec![medoid];
+        let mut num_levels = 0;
+
+        while !frontier.is_empty() && self.node_cache.len() < num_nodes {
+            frontier.truncate(num_nodes - self.node_cache.len());
+            num_levels += 1;
+
+            let mut next_frontier = Vec::new();
+            for ids in frontier.chunks(MAX_N_SECTOR_READS) {
+                let nodes = self.read_nodes(ids).await?;
+                for (&id, node) in ids.iter().zip(nodes) {
+                    for &nbr in &node.neighbors {
+                        if (nbr as usize) < self.layout_meta.num_pts && seen.insert(nbr) {
+                            next_frontier.push(nbr);
+                        }
+                    }
+                    self.node_cache.insert(id, node);
+                }
+            }
+            frontier = next_frontier;
+        }
+
+        Ok(num_levels)
+    }
+
+    /// PQ distances from the query to the points
+    fn pq_distances(&self, ids: &[u32], query_pq_dists: &[f32]) -> Vec<f32> {
+        if ids.is_empty() {
+            return Vec::new();
+        }
+
+        let code_bits = self.pq_table.code_bits();
+        let code_bytes = code_bits.code_bytes(self.num_pq_chunks);
+        let codes = aggregate_coords(ids, &self.pq_codes, code_bytes);
+        match code_bits {
+            PQCodeBits::Eight => {
+                pq_dist_lookup(&codes, ids.len(), self.num_pq_chunks, query_pq_dists)
+            }
+            PQCodeBits::Four => {
+                pq_dist_lookup_packed4(&codes, ids.len(), self.num_pq_chunks, query_pq_dists)
+            }
+        }
+    }
+
+    /// Full precision distances from the query to the points, taking the vectors from the cache
+    /// or reading them from the disk. Returns the neighbors and the number of nodes read.
+    async fn full_precision_distances(
+        &self,
+        ids: &[u32],
+        query: &[T; N],
+        metric: Metric,
+    ) -> ANNResult<(Vec<Neighbor>, usize)>
+    where
+        [T; N]: FullPrecisionDistance<T, N>,
+    {
+        let (cached_ids, uncached_ids): (Vec<u32>, Vec<u32>) =
+            ids.iter().partition(|id| self.node_cache.contains_key(id));
+        let mut neighbors = Vec::with_capacity(ids.len());
+        for id in cached_ids {
+            let distance = self.node_cache[&id].distance(id, query, metric)?;
+            neighbors.push(Neighbor::new(id, distance));
+        }
+        for chunk in uncached_ids.chunks(MAX_N_SECTOR_READS) {
+            for (&id, node) in chunk.iter().zip(self.read_nodes(chunk).await?) {
+                neighbors.push(Neighbor::new(id, node.distance(id, query, metric)?));
+            }
+        }
+        Ok((neighbors, uncached_ids.len()))
+    }
+}
+
+impl<T, const N: usize> DiskIndex<T, N>
+where
+    T: Default + Copy + Sync + Send + Into<f32>,
+    [T; N]: FullPrecisionDistance<T, N>,
+{
+    /// Load the disk index layout metadata, the PQ pivots and codes for search, and cache
+    /// num_nodes_to_cache nodes of the most accessed sectors in the saved usage statistics, or
+    /// else closest to the medoid in hops. The searches count the sectors they read on top of
+    /// the saved statistics.
+    pub async fn load(&mut self, num_nodes_to_cache: usize) -> ANNResult<()> {
+        let input_lock = lock_index_input(self.storage.index_path_prefix())?;
+        let layout_meta = self.storage.load_disk_layout_meta()?;
+        if layout_meta.dim * mem::size_of::<T>() + mem::size_of::<u32>() > layout_meta.max_node_len
+        {
+            return Err(ANNError::log_index_error(format!(
+                "ERROR: Nodes of {}B can't hold vectors of dimension {}.",
+                layout_meta.max_node_len, layout_meta.dim
+            )));
+        }
+
+        let hot_nodes = self.storage.hot_nodes_for_warmup(num_nodes_to_cache)?;
+        let sector_usage = match self.storage.load_sector_usage(&layout_meta)? {
+            Some(sector_usage) => sector_usage,
+            None => SectorUsageStats::new(layout_meta.num_sectors(), DEFAULT_SECTOR_USAGE_DECAY)?,
+        };
+
+        let reader = LinuxAlignedFileReader::new_with_runtime(
+            &self.storage.disk_index_file(),
+            self.index_configuration().runtime.clone(),
+        )
+        .await?;
+        self.load_search_data(
+            layout_meta,
+            NodeSource::File(reader),
+            input_lock,
+            num_nodes_to_cache,
+            hot_nodes,
+        )
+        .await?;
+
+        if let Some(search_data) = self.search_data.as_mut() {
+            search_data.sector_usage = Some(sector_usage);
+        }
+        Ok(())
+    }
+
+    /// Load the PQ pivots and codes for search like load, the vectors and the neighbors of the
+    /// nodes being read from provider instead of the disk index file
+    pub async fn load_from_provider(
+        &mut self,
+        provider: Box<dyn StorageProvider<T> + Send + Sync>,
+        num_nodes_to_cache: usize,
+    ) -> ANNResult<()> {
+        let input_lock = lock_index_input(self.storage.index_path_prefix())?;
+        let metadata = provider.metadata()?;
+        let max_node_len =
+            metadata.dim * mem::size_of::<T>() + (metadata.max_degree + 1) * mem::size_of::<u32>();
+        let layout_meta = DiskLayoutMeta {
+            num_pts: metadata.num_points,
+            dim: metadata.dim,
+            medoid: metadata.medoid,
+            max_node_len,
+            num_nodes_per_sector: SECTOR_LEN / max_node_len,
+            frozen_point: metadata.frozen_point,
+        };
+        self.load_search_data(
+            layout_meta,
+            NodeSource::Provider(provider),
+            input_lock,
+            num_nodes_to_cache,
+            None,
+        )
+        .await
+    }
+
+    /// Save the usage statistics of the sectors read since the index was loaded from the disk
+    /// index file, on top of the statistics it was loaded with, for the warm-up of the next load
+    pub fn save_sector_usage(&self) -> ANNResult<()> {
+        match self
+            .search_data
+            .as_ref()
+            .and_then(|search_data| search_data.sector_usage.as_ref())
+        {
+            Some(sector_usage) => sector_usage.save(&self.storage.sector_usage_file()),
+            None => Err(ANNError::log_index_error(
+                "Disk index is not loaded from the disk index file".to_string(),
+            )),
+        }
+    }
+
+    /// Fold the sectors read in the current epoch into the decayed usage statistics
+    pub fn end_sector_usage_epoch(&mut self) {
+        if let Some(sector_usage) = self
+            .search_data
+            .as_mut()
+            .and_then(|search_data| search_data.sector_usage.as_mut())
+        {
+            sector_usage.end_epoch();
+        }
+    }
+
+    /// Copy the metadata and the nodes of the index to provider, from the provider it was
+    /// loaded from or else from the disk index file. Returns the number of nodes copied.
+    pub fn save_to_provider(&self, provider: &mut dyn StorageProvider<T>) -> ANNResult<usize> {
+        match self
+            .search_data
+            .as_ref()
+            .map(|search_data| &search_data.nodes)
+        {
+            Some(NodeSource::Provider(loaded_from)) => copy_nodes(loaded_from.as_ref(), provider),
+            _ => copy_nodes(&AlignedFileStorageProvider::open(&self.storage)?, provider),
+        }
+    }
+
+    /// Load the PQ pivots and codes for the nodes of layout_meta read from nodes, and cache
+    /// the hot nodes, or num_nodes_to_cache nodes closest to the medoid in hops when there are
+    /// none
+    async fn load_search_data(
+        &mut self,
+        layout_meta: DiskLayoutMeta,
+        nodes: NodeSource<T>,
+        input_lock: FileLock,
+        num_nodes_to_cache: usize,
+        hot_nodes: Option<Vec<u32>>,
+    ) -> ANNResult<()> {
+        if layout_meta.dim > N {
+            return Err(ANNError::log_index_error(format!(
+                "ERROR: Disk index has dimension {}, more than the aligned dimension {}.",
+                layout_meta.dim, N
+            )));
+        }
+
+        let (pq_codes, num_pq_pts, num_pq_chunks) = self.storage.load_pq_compressed_data()?;
+        if num_pq_pts < layout_meta.num_pts {
+            return Err(ANNError::log_pq_error(format!(
+                "ERROR: PQ compressed file has {} points, but the disk index has {} points.",
+                num_pq_pts, layout_meta.num_pts
+            )));
+        }
+
+        let pq_pivot_data = self.storage.load_pq_pivots_bin(&num_pq_chunks)?;
+        let pq_table = pq_pivot_data.into_pq_table(num_pq_chunks);
+        let code_bytes = pq_table.code_bits().code_bytes(num_pq_chunks);
+        if pq_codes.len() < num_pq_pts * code_bytes {
+            return Err(ANNError::log_pq_error(format!(
+                "ERROR: PQ compressed file has {} bytes of codes, but {} points need {}.",
+                pq_codes.len(),
+                num_pq_pts,
+                num_pq_pts * code_bytes
+            )));
+        }
+
+        let mut search_data = DiskSearchData {
+            layout_meta,
+            pq_table,
+            pq_codes,
+            num_pq_chunks,
+            node_cache: HashMap::new(),
+            nodes,
+            prefetch: PrefetchWindow::new(1, DEFAULT_MAX_QUEUE_DEPTH),
+            sector_usage: None,
+            io_coordinator: self.io_coordinator.clone(),
+            _input_lock: input_lock,
+        };
+        match hot_nodes {
+            Some(hot_nodes) if !hot_nodes.is_empty() => {
+                search_data.cache_nodes(&hot_nodes).await?;
+                info!(
+                    "Cached {} nodes of the most accessed sectors",
+                    search_data.node_cache.len()
+                );
+            }
+            _ => {
+                let num_levels = search_data.cache_bfs_levels(num_nodes_to_cache).await?;
+                info!(
+                    "Cached {} nodes in {} BFS levels from medoid {}",
+                    search_data.node_cache.len(),
+                    num_levels,
+                    search_data.layout_meta.medoid
+                );
+            }
+        }
+
+        self.search_data = Some(search_data);
+        Ok(())
+    }
+
+    /// Search the k nearest neighbors of the query with a search list of size l, expanding up to
+    /// beam_width nodes per round trip to the disk. Returns the ids and the distances in
+    /// ascending order of distance.
+    pub async fn search(
+        &self,
+        query: &[T],
+        k: usize,
+        l: usize,
+        beam_width: usize,
+    ) -> ANNResult<(Vec<u32>, Vec<f32>)> {
+        if k == 0 || k > l || beam_width == 0 {
+            return Err(ANNError::log_index_error(format!(
+                "Invalid search parameters k={} l={} beam_width={}, expecting 0 < k <= l and beam_width > 0",
+                k, l, beam_width
+            )));
+        }
+
+        let params = SearchParams::new(l.try_into()?, beam_width.min(l), None, true)?;
+        self.search_with_params(query, k, &params).await
+    }
+
+    /// Search the k nearest neighbors of the query with params. The search stops once
+    /// params.max_ios nodes were read from the disk, cached nodes don't count, or once
+    /// params.patience nodes in a row were expanded without a closer candidate by PQ distance. Without reorder,
+    /// the expanded nodes are ranked by their PQ distance and their vectors aren't compared.
+    /// Returns the ids and the distances in ascending order of distance.
+    pub async fn search_with_params(
+        &self,
+        query: &[T],
+        k: usize,
+        params: &SearchParams,
+    ) -> ANNResult<(Vec<u32>, Vec<f32>)> {
+        let (ids, distances, _) = self.search_with_stats(query, k, params).await?;
+        Ok((ids, distances))
+    }
+
+    /// Search the k nearest neighbors of the query with params like search_with_params, and
+    /// collect the query statistics
+    pub async fn search_with_stats(
+        &self,
+        query: &[T],
+        k: usize,
+        params: &SearchParams,
+    ) -> ANNResult<(Vec<u32>, Vec<f32>, QueryStats)> {
+        let mut results = self.search_batch(&[query], k, params).await?;
+        results.pop().ok_or_else(|| {
+            ANNError::log_index_error("The search returned no result for the query".to_string())
+        })
+    }
+
+    /// Search the k nearest neighbors of every query with params like search_with_stats. The
+    /// searches advance together: each round trip to the disk reads the beams of all the
+    /// searches still running, and a sector several of them expand is read once.
+    /// Returns the ids, the distances and the statistics of each query, in the order of queries.
+    pub async fn search_batch(
+        &self,
+        queries: &[&[T]],
+        k: usize,
+        params: &SearchParams,
+    ) -> ANNResult<Vec<(Vec<u32>, Vec<f32>, QueryStats)>> {
+        params.check_k(k)?;
+        let search_data = self.search_data.as_ref().ok_or_else(|| {
+            ANNError::log_index_error("Disk index is not loaded for search".to_string())
+        })?;
+        let tie_epsilon = self.index_configuration().distance_tie_epsilon;
+        let mut searches = queries
+            .iter()
+            .map(|query| {
+                BeamSearch::new(
+                    search_data,
+                    query,
+                    params,
+                    tie_epsilon,
+                    self.index_configuration().num_search_frontiers,
+                )
+            })
+            .collect::<ANNResult<Vec<_>>>()?;
+
+        let beam_width = params.beam_width();
+        loop {
+            let window = if params.adaptive_prefetch() {
+                search_data.prefetch.window(beam_width)
+            } else {
+                beam_width
+            };
+            let mut round_ids = Vec::new();
+            let mut num_running = 0;
+            for search in searches
+                .iter_mut()
+                .filter(|search| search.is_running(params))
+            {
+                round_ids.extend_from_slice(search.next_beam(window, params)?);
+                num_running += 1;
+            }
+            if num_running == 0 {
+                break;
+            }
+            round_ids.sort_unstable();
+            round_ids.dedup();
+
+            let io_spans: Vec<Span> = searches
+                .iter()
+                .filter(|search| search.in_round)
+                .map(|search| search.io_span())
+                .collect();
+            let io_timer = Instant::now();
+            let queue_depth = search_data.prefetch.begin_reads(round_ids.len());
+            let read_nodes = search_data.read_nodes(&round_ids).await;
+            let io_time = io_timer.elapsed();
+            drop(io_spans);
+            search_data
+                .prefetch
+                .complete_reads(round_ids.len(), queue_depth, io_time, beam_width);
+            let read_nodes: HashMap<u32, DiskNode<T>> =
+                round_ids.into_iter().zip(read_nodes?).collect();
+
+            let metric = self.index_configuration().dist_metric;
+            for search in searches.iter_mut().filter(|search| search.in_round) {
+                search.expand(&read_nodes, io_time, params, metric)?;
+            }
+        }
+
+        let mut results = Vec::with_capacity(searches.len());
+        for search in searches {
+            let query = search.query;
+            let (ids, distances, stats) = search
+                .finish(k, params, self.index_configuration().dist_metric)
+                .await?;
+            if let Some(recorder) = &self.latency_recorder {
+                recorder.record(&stats);
+            }
+            if let Some(recorder) = &self.query_recorder {
+                recorder.record(query, k, params, &ids, &distances, &stats)?;
+            }
+            results.push((ids, distances, stats));
+        }
+        Ok(results)
+    }
+}
+
+/// The beam search of one query over a loaded disk index
+struct BeamSearch<'a, T, const N: usize> {
+    search_data: &'a DiskSearchData<T, N>,
+
+    query: &'a [T],
+
+    /// Id of the query in the spans of its phases
+    query_id: u64,
+
+    /// Query padded with zeros to N values
+    aligned_query: [T; N],
+
+    /// Distances from the query to the PQ centers of every chunk
+    query_pq_dists: Vec<f32>,
+
+    /// Candidates of the independent frontiers, the first one starting from the medoid
+    frontiers: Vec<NeighborPriorityQueue>,
+
+    /// Rounds of expansions so far
+    num_rounds: usize,
+
+    visited: HashSet<u32>,
+
+    /// Expanded nodes with the distance they are ranked by
+    expanded: Vec<Neighbor>,
+
+    /// Nodes expanded this round with the frontiers they were taken from, and those of them
+    /// read from the disk
+    beam: Vec<u32>,
+    beam_frontiers: Vec<usize>,
+    uncached_ids: Vec<u32>,
+
+    /// Whether the search takes part in the current round
+    in_round: bool,
+
+    /// PQ distances of the nodes of the beams
+    pq_distances: HashMap<u32, f32>,
+
+    num_ios: usize,
+
+    stats: QueryStats,
+
+    stall: StallCounter,
+
+    /// Span of the expansions, until the search finishes
+    beam_span: Span,
+
+    /// Sectors read by the expansions so far
+    sectors_read: u64,
+
+    timer: Instant,
+}
+
+impl<'a, T, const N: usize> BeamSearch<'a, T, N>
+where
+    T: Default + Copy + Into<f32>,
+    [T; N]: FullPrecisionDistance<T, N>,
+{
+    /// Start the search of query from the medoid, and num_frontiers - 1 more frontiers from
+    /// points spread over the ids
+    fn new(
+        search_data: &'a DiskSearchData<T, N>,
+        query: &'a [T],
+        params: &SearchParams,
+        tie_epsilon: f32,
+        num_frontiers: usize,
+    ) -> ANNResult<Self> {
+        let timer = Instant::now();
+        let layout_meta = &search_data.layout_meta;
+        if query.len() != layout_meta.dim {
+            return Err(ANNError::log_index_error(format!(
+                "Query has dimension {}, but the index has dimension {}",
+                query.len(),
+                layout_meta.dim
+            )));
+        }
+        let mut aligned_query = [T::default(); N];
+        aligned_query[..query.len()].copy_from_slice(query);
+
+        let query_id = next_span_id();
+        let query_pq_dists = info_span!(target: SEARCH_SPAN_TARGET, "pq_table_compute", query_id)
+            .in_scope(|| {
+                let mut query_f32: Vec<f32> = query.iter().map(|&value| value.into()).collect();
+                search_data.pq_table.preprocess_query(&mut query_f32);
+                search_data.pq_table.populate_chunk_distances(&query_f32)
+            });
+
+        let num_frontiers = num_frontiers.max(1);
+        let mut frontiers: Vec<NeighborPriorityQueue> = (0..num_frontiers)
+            .map(|_| {
+                let mut frontier = NeighborPriorityQueue::with_capacity(params.l_value() as usize);
+                frontier.set_tie_epsilon(tie_epsilon);
+                frontier
+            })
+            .collect();
+        let mut visited = HashSet::from([layout_meta.medoid]);
+        let mut starts = vec![(0, layout_meta.medoid)];
+        for f in 1..num_frontiers {
+            let id = (f * layout_meta.num_pts / num_frontiers) as u32;
+            if visited.insert(id) {
+                starts.push((f, id));
+            }
+        }
+        let start_ids: Vec<u32> = starts.iter().map(|&(_, id)| id).collect();
+        let start_dists = search_data.pq_distances(&start_ids, &query_pq_dists);
+        for ((f, id), distance) in starts.into_iter().zip(start_dists) {
+            frontiers[f].insert(Neighbor::new(id, distance));
+        }
+
+        Ok(Self {
+            search_data,
+            query,
+            query_id,
+            aligned_query,
+            query_pq_dists,
+            frontiers,
+            num_rounds: 0,
+            visited,
+            expanded: Vec::new(),
+            beam: Vec::with_capacity(params.beam_width() * num_frontiers),
+            beam_frontiers: Vec::with_capacity(params.beam_width() * num_frontiers),
+            uncached_ids: Vec::new(),
+            in_round: false,
+            pq_distances: HashMap::new(),
+            num_ios: 0,
+            stats: QueryStats::default(),
+            stall: StallCounter::new(),
+            beam_span: info_span!(
+                target: SEARCH_SPAN_TARGET,
+                "beam_search",
+                query_id,
+                sectors_read = field::Empty
+            ),
+            sectors_read: 0,
+            timer,
+        })
+    }
+
+    /// Whether the search has candidates left to expand within its limits
+    fn is_running(&self, params: &SearchParams) -> bool {
+        self.has_notvisited_node()
+            && params
+                .max_ios()
+                .is_none_or(|max_ios| self.num_ios < max_ios)
+            && !self.stall.exceeds(params.patience())
+    }
+
+    /// Whether any frontier has candidates left to expand
+    fn has_notvisited_node(&self) -> bool {
+        self.frontiers
+            .iter()
+            .any(|frontier| frontier.has_notvisited_node())
+    }
+
+    /// Take the next beam of up to window candidates of every frontier, returns the ids of its
+    /// nodes to read
+    fn next_beam(&mut self, window: usize, params: &SearchParams) -> ANNResult<&[u32]> {
+        self.beam.clear();
+        self.beam_frontiers.clear();
+        for (f, frontier) in self.frontiers.iter_mut().enumerate() {
+            let beam_start = self.beam.len();
+            while frontier.has_notvisited_node() && self.beam.len() - beam_start < window {
+                let candidate = frontier.closest_notvisited();
+                self.pq_distances.insert(candidate.id, candidate.distance);
+                self.beam.push(candidate.id);
+                self.beam_frontiers.push(f);
+            }
+        }
+
+        let node_cache = &self.search_data.node_cache;
+        self.uncached_ids.clear();
+        self.uncached_ids
+            .extend(self.beam.iter().filter(|id| !node_cache.contains_key(id)));
+        if let Some(max_ios) = params.max_ios() {
+            self.uncached_ids.truncate(max_ios - self.num_ios);
+        }
+        self.num_ios += self.uncached_ids.len();
+        self.sectors_read += self.search_data.sectors_read(self.uncached_ids.len());
+        self.beam_span.record("sectors_read", self.sectors_read);
+        self.stats.prefetch_window = self.beam.len().try_into()?;
+        self.in_round = true;
+        Ok(&self.uncached_ids)
+    }
+
+    /// Span of the reads of the current beam, within the span of the expansions
+    fn io_span(&self) -> Span {
+        info_span!(
+            target: SEARCH_SPAN_TARGET,
+            parent: &self.beam_span,
+            "io_wait",
+            query_id = self.query_id,
+            sectors_read = self.search_data.sectors_read(self.uncached_ids.len())
+        )
+    }
+
+    /// Expand the nodes of the beam into the frontiers they were taken from, the uncached ones
+    /// taken from read_nodes. Every FRONTIER_MERGE_INTERVAL rounds the frontiers drop the
+    /// candidates that can't make the search list of all frontiers combined.
+    fn expand(
+        &mut self,
+        read_nodes: &HashMap<u32, DiskNode<T>>,
+        io_time: Duration,
+        params: &SearchParams,
+        metric: Metric,
+    ) -> ANNResult<()> {
+        self.in_round = false;
+        self.stats.io_us += io_time.as_secs_f64() * 1e6;
+        self.stats.n_hops += u32::try_from(self.beam.len())?;
+
+        let search_data = self.search_data;
+        let layout_meta = &search_data.layout_meta;
+        let beam = || {
+            self.beam
+                .iter()
+                .copied()
+                .zip(self.beam_frontiers.iter().copied())
+        };
+        let cached_nodes =
+            beam().filter_map(|(id, f)| search_data.node_cache.get(&id).map(|node| (id, f, node)));
+        let uncached_nodes = beam()
+            .filter(|(id, _)| !search_data.node_cache.contains_key(id))
+            .take(self.uncached_ids.len())
+            .map(|(id, f)| (id, f, &read_nodes[&id]));
+        for (id, f, node) in cached_nodes.chain(uncached_nodes) {
+            if layout_meta.frozen_point != Some(id) {
+                let distance = if params.reorder() {
+                    node.distance(id, &self.aligned_query, metric)?
+                } else {
+                    self.pq_distances[&id]
+                };
+                self.expanded.push(Neighbor::new(id, distance));
+            }
+
+            let new_nbrs: Vec<u32> = node
+                .neighbors
+                .iter()
+                .copied()
+                .filter(|&nbr| (nbr as usize) < layout_meta.num_pts && self.visited.insert(nbr))
+                .collect();
+            self.stats.n_cmps += u32::try_from(new_nbrs.len())?;
+            let nbr_dists = search_data.pq_distances(&new_nbrs, &self.query_pq_dists);
+            for (nbr, distance) in new_nbrs.into_iter().zip(nbr_dists) {
+                self.frontiers[f].insert(Neighbor::new(nbr, distance));
+            }
+        }
+        let best_distance = self
+            .frontiers
+            .iter()
+            .filter(|frontier| frontier.size() > 0)
+            .map(|frontier| frontier[0].distance)
+            .fold(f32::INFINITY, f32::min);
+        self.stall.expanded(self.beam.len(), best_distance);
+
+        self.num_rounds += 1;
+        if self.frontiers.len() > 1 && self.num_rounds.is_multiple_of(FRONTIER_MERGE_INTERVAL) {
+            merge_frontiers(&mut self.frontiers, params.l_value() as usize);
+        }
+        Ok(())
+    }
+
+    /// Rank the expanded nodes, reranking the search list if params ask for it, and return
+    /// the k closest ids and distances with the statistics of the search
+    async fn finish(
+        mut self,
+        k: usize,
+        params: &SearchParams,
+        metric: Metric,
+    ) -> ANNResult<(Vec<u32>, Vec<f32>, QueryStats)> {
+        let search_data = self.search_data;
+        self.stats.early_terminated = self.has_notvisited_node();
+        drop(mem::replace(&mut self.beam_span, Span::none()));
+        let mut frontiers = mem::take(&mut self.frontiers);
+        let best_candidates = match frontiers.len() {
+            1 => frontiers.swap_remove(0),
+            _ => {
+                let mut merged = NeighborPriorityQueue::with_capacity(params.l_value() as usize);
+                merged.set_tie_epsilon(frontiers[0].tie_epsilon());
+                for frontier in &frontiers {
+                    for i in 0..frontier.size() {
+                        merged.insert(frontier[i]);
+                    }
+                }
+                merged
+            }
+        };
+
+        let mut expanded = self.expanded;
+        if let Some(rerank_size) = params.rerank_size() {
+            let rerank_span = info_span!(
+                target: SEARCH_SPAN_TARGET,
+                "rerank",
+                query_id = self.query_id,
+                sectors_read = field::Empty
+            );
+            // The expanded nodes already have their full precision distance with reorder
+            let mut reranked = Vec::with_capacity(rerank_size);
+            let mut to_read = Vec::new();
+            let exact_distances: HashMap<u32, f32> = if params.reorder() {
+                expanded.iter().map(|nbr| (nbr.id, nbr.distance)).collect()
+            } else {
+                HashMap::new()
+            };
+            for i in 0..best_candidates.size() {
+                let id = best_candidates[i].id;
+                if search_data.layout_meta.frozen_point == Some(id) {
+                    continue;
+                }
+                if reranked.len() + to_read.len() == rerank_size {
+                    break;
+                }
+                match exact_distances.get(&id) {
+                    Some(&distance) => reranked.push(Neighbor::new(id, distance)),
+                    None => to_read.push(id),
+                }
+            }
+            let (read, num_reads) = search_data
+                .full_precision_distances(&to_read, &self.aligned_query, metric)
+                .await?;
+            self.num_ios += num_reads;
+            rerank_span.record("sectors_read", search_data.sectors_read(num_reads));
+            reranked.extend(read);
+            expanded = reranked;
+        }
+
+        expanded.sort_by(|a, b| a.distance.total_cmp(&b.distance).then(a.id.cmp(&b.id)));
+        expanded.truncate(k);
+        let (ids, distances): (Vec<u32>, Vec<f32>) =
+            expanded.iter().map(|nbr| (nbr.id, nbr.distance)).unzip();
+
+        let mut stats = self.stats;
+        stats.n_ios = self.num_ios.try_into()?;
+        stats.total_us = self.timer.elapsed().as_secs_f64() * 1e6;
+        stats.cpu_us = stats.total_us - stats.io_us;
+        Ok((ids, distances, stats))
+    }
+}
+
+#[cfg(test)]
+mod disk_index_search_test {
+    use std::fs;
+    use std::sync::{Arc, Mutex};
+
+    use vector::Metric;
+
+    use crate::index::{QueryBatcher, QueryBatcherConfig};
+    use crate::model::vertex::DIM_128;
+    use crate::model::{IndexConfiguration, IndexWriteParametersBuilder};
+    use crate::storage::{DiskIndexStorage, InMemoryStorageProvider, IoFairnessConfig};
+    use crate::test_utils::get_test_file_path;
+    use crate::utils::{load_bin, lock_index_output};
+
+    use super::*;
+
+    const TEST_DATA_FILE: &str = "tests/data/siftsmall_learn_256pts.fbin";
+    const DISK_INDEX_FILE: &str =
+        "tests/data/truth_disk_index_siftsmall_learn_256pts_R4_L50_A1.2_disk.index";
+    const PQ_PIVOTS_FILE: &str = "tests/data/siftsmall_learn.bin_pq_pivots.bin";
+    const PQ_COMPRESSED_FILE: &str = "tests/data/siftsmall_learn.bin_pq_compressed.bin";
+
+    #[tokio::test]
+    async fn search_finds_indexed_points() {
+        let index_path_prefix = "disk_index_search_test";
+        let index_files = [
+            (DISK_INDEX_FILE, format!("{}_disk.index", index_path_prefix)),
+            (
+                PQ_PIVOTS_FILE,
+                format!("{}.bin_pq_pivots.bin", index_path_prefix),
+            ),
+            (
+                PQ_COMPRESSED_FILE,
+                format!("{}.bin_pq_compressed.bin", index_path_prefix),
+            ),
+        ];
+        for (test_file, index_file) in &index_files {
+            fs::copy(get_test_file_path(test_file), index_file).unwrap();
+        }
+
+        let config = IndexConfiguration::new(
+            Metric::L2,
+            128,
+            DIM_128,
+            256,
+            false,
+            0,
+            false,
+            0,
+            1.0,
+            IndexWriteParametersBuilder::new(50, 4).build(),
+        );
+        let storage = DiskIndexStorage::<f32>::new(
+            get_test_file_path(TEST_DATA_FILE),
+            index_path_prefix.to_string(),
+        )
+        .unwrap();
+        let mut index = DiskIndex::<f32, DIM_128>::new(None, config, storage);
+
+        let (data, num_points, dim) = load_bin::<f32>(TEST_DATA_FILE, 0).unwrap();
+        assert!(index.search(&data[..dim], 5, 50, 4).await.is_err());
+
+        index.load(16).await.unwrap();
+        assert_eq!(index.search_data.as_ref().unwrap().node_cache.len(), 16);
+        assert!(lock_index_output(index_path_prefix).is_err());
+        assert!(index.search(&data[..dim - 1], 5, 50, 4).await.is_err());
+        assert!(index.search(&data[..dim], 10, 5, 4).await.is_err());
+
+        // A search list over all points reaches every node of the graph, a shorter one ranks
+        // by the coarse 1-chunk PQ codes and may miss a few
+        let mut num_found = 0;
+        for id in (0..num_points).step_by(17) {
+            let query = &data[id * dim..(id + 1) * dim];
+            let (ids, distances) = index.search(query, 5, num_points, 4).await.unwrap();
+            assert_eq!(ids.len(), 5);
+            assert_eq!(ids[0], id as u32);
+            assert_eq!(distances[0], 0.0);
+            assert!(distances.windows(2).all(|pair| pair[0] <= pair[1]));
+
+            let (ids, _) = index.search(query, 5, 50, 4).await.unwrap();
+            if ids[0] == id as u32 {
+                num_found += 1;
+            }
+        }
+        assert!(num_found >= 14);
+
+        // Without reorder the results are ranked by PQ distance, with an IO limit the search
+        // stops early with fewer expanded nodes to rank
+        let query = &data[..dim];
+        let exact = SearchParams::new(50, 4, None, true).unwrap();
+        let pq_ranked = SearchParams::new(50, 4, None, false).unwrap();
+        let limited = SearchParams::new(50, 4, Some(4), true).unwrap();
+        let (_, exact_distances) = index.search_with_params(query, 50, &exact).await.unwrap();
+        let (_, pq_distances) = index
+            .search_with_params(query, 50, &pq_ranked)
+            .await
+            .unwrap();
+        assert!(pq_distances.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_ne!(pq_distances, exact_distances);
+        let (limited_ids, _) = index.search_with_params(query, 50, &limited).await.unwrap();
+        assert!(limited_ids.len() < 50);
+        assert!(limited_ids.len() <= 16 + 4);
+        assert!(index.search_with_params(query, 51, &exact).await.is_err());
+        let (_, _, stats) = index.search_with_stats(query, 5, &limited).await.unwrap();
+        assert!(stats.early_terminated);
+        let (_, _, full_stats) = index.search_with_stats(query, 5, &exact).await.unwrap();
+        assert!(!full_stats.early_terminated);
+
+        // With patience the search stops once a few rounds find nothing closer
+        let impatient = exact.with_patience(4).unwrap();
+        let (ids, _, stats) = index.search_with_stats(query, 5, &impatient).await.unwrap();
+        assert_eq!(ids[0], 0);
+        assert!(stats.early_terminated);
+        assert!(stats.n_hops < full_stats.n_hops);
+
+        // With adaptive prefetch the window starts at one node and never exceeds the beam width
+        let adaptive = exact.with_adaptive_prefetch(true);
+        let (ids, _, stats) = index.search_with_stats(query, 5, &adaptive).await.unwrap();
+        assert_eq!(ids[0], 0);
+        assert!(stats.prefetch_window >= 1 && stats.prefetch_window <= 4);
+        assert!(stats.n_ios <= stats.n_hops);
+        assert_eq!(index.search_data.as_ref().unwrap().prefetch.in_flight(), 0);
+        let (_, _, stats) = index.search_with_stats(query, 5, &exact).await.unwrap();
+        assert!(stats.n_hops > 0);
+
+        // Reranking the PQ ranked search list returns full precision distances, reading the
+        // vectors of the candidates that weren't expanded
+        let reranked = pq_ranked.with_rerank(20);
+        let (ids, distances) = index.search_with_params(query, 5, &reranked).await.unwrap();
+        assert_eq!(ids[0], 0);
+        assert_eq!(distances[..4], exact_distances[..4]);
+        assert!(distances.windows(2).all(|pair| pair[0] <= pair[1]));
+        let (ids, _, stats) = index
+            .search_with_stats(query, 5, &limited.with_rerank(20))
+            .await
+            .unwrap();
+        assert_eq!(ids.len(), 5);
+        assert!(stats.n_ios > 4);
+        let too_many = index.search_with_params(query, 21, &reranked).await;
+        assert!(too_many.is_err());
+
+        // A batch finds what its queries find one at a time
+        let queries: Vec<&[f32]> = (0..num_points)
+            .step_by(31)
+            .map(|id| &data[id * dim..(id + 1) * dim])
+            .collect();
+        let batch = index.search_batch(&queries, 5, &exact).await.unwrap();
+        assert_eq!(batch.len(), queries.len());
+        for (query, (ids, distances, stats)) in queries.iter().zip(batch) {
+            let (single_ids, single_distances, single_stats) =
+                index.search_with_stats(query, 5, &exact).await.unwrap();
+            assert_eq!(ids, single_ids);
+            assert_eq!(distances, single_distances);
+            assert_eq!(stats.n_hops, single_stats.n_hops);
+        }
+        assert!(index.search_batch(&[], 5, &exact).await.unwrap().is_empty());
+        assert!(index
+            .search_batch(&[query, &query[1..]], 5, &exact)
+            .await
+            .is_err());
+
+        // Batched single queries go through search_batch and find the same neighbors
+        let index = Arc::new(index);
+        let batcher =
+            QueryBatcher::for_disk_index(index.clone(), 4, QueryBatcherConfig::default()).unwrap();
+        let expected = index.search(query, 5, 50, 4).await.unwrap();
+        let query = query.to_vec();
+        let results = tokio::task::spawn_blocking(move || batcher.search(&query, 5, 50))
+            .await
+            .unwrap()
+            .unwrap();
+        let ids: Vec<u32> = results.iter().map(|result| result.id).collect();
+        assert_eq!(ids, expected.0);
+
+        // An index loaded from a copy of its nodes in a provider finds the same neighbors
+        let mut memory = InMemoryStorageProvider::new(dim, 4, 0);
+        assert_eq!(index.save_to_provider(&mut memory).unwrap(), num_points);
+        let mut from_provider = DiskIndex::<f32, DIM_128>::new(
+            None,
+            index.index_configuration().clone(),
+            DiskIndexStorage::<f32>::new(
+                get_test_file_path(TEST_DATA_FILE),
+                index_path_prefix.to_string(),
+            )
+            .unwrap(),
+        );
+        from_provider
+            .load_from_provider(Box::new(memory), 16)
+            .await
+            .unwrap();
+        for id in (0..num_points).step_by(17) {
+            let query = &data[id * dim..(id + 1) * dim];
+            assert_eq!(
+                from_provider.search(query, 5, 50, 4).await.unwrap(),
+                index.search(query, 5, 50, 4).await.unwrap()
+            );
+        }
+
+        // Four frontiers merged every few rounds expand more nodes and find more of the points
+        // a short search list misses from the medoid
+        let mut multi_frontier = DiskIndex::<f32, DIM_128>::new(
+            None,
+            index
+                .index_configuration()
+                .clone()
+                .with_num_search_frontiers(4),
+            DiskIndexStorage::<f32>::new(
+                get_test_file_path(TEST_DATA_FILE),
+                index_path_prefix.to_string(),
+            )
+            .unwrap(),
+        );
+        multi_frontier.load(16).await.unwrap();
+        let short = SearchParams::new(8, 2, None, true).unwrap();
+        let (mut single_found, mut multi_found) = (0, 0);
+        for id in (0..num_points).step_by(3) {
+            let query = &data[id * dim..(id + 1) * dim];
+            let (ids, _, single_stats) = index.search_with_stats(query, 5, &short).await.unwrap();
+            single_found += usize::from(ids[0] == id as u32);
+            let (ids, distances, stats) = multi_frontier
+                .search_with_stats(query, 5, &short)
+                .await
+                .unwrap();
+            multi_found += usize::from(ids[0] == id as u32);
+            assert!(distances.windows(2).all(|pair| pair[0] <= pair[1]));
+            assert!(stats.n_hops > single_stats.n_hops);
+        }
+        assert!(multi_found > single_found);
+
+        for (_, index_file) in &index_files {
+            fs::remove_file(index_file).unwrap();
+        }
+    }
+
+    /// Span name, field name and value
+    type SpanField = (&'static str, &'static str, u64);
+
+    /// Fields the spans are opened or recorded with
+    #[derive(Clone, Default)]
+    struct SpanFields(Arc<Mutex<Vec<SpanField>>>);
+
+    struct SpanFieldVisitor<'a>(&'static str, &'a SpanFields);
+
+    impl tracing::field::Visit for SpanFieldVisitor<'_> {
+        fn record_u64(&mut self, field: &tracing::field::Field, value: u64) {
+            self.1 .0.lock().unwrap().push((self.0, field.name(), value));
+        }
+
+        fn record_debug(&mut self, _: &tracing::field::Field, _: &dyn std::fmt::Debug) {}
+    }
+
+    impl<S> tracing_subscriber::Layer<S> for SpanFields
+    where
+        S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+    {
+        fn on_new_span(
+            &self,
+            attrs: &tracing::span::Attributes<'_>,
+            _: &tracing::span::Id,
+            _: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            attrs.record(&mut SpanFieldVisitor(attrs.metadata().name(), self));
+        }
+
+        fn on_record(
+            &self,
+            id: &tracing::span::Id,
+            values: &tracing::span::Record<'_>,
+            ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            let name = ctx.span(id).unwrap().name();
+            values.record(&mut SpanFieldVisitor(name, self));
+        }
+    }
+
+    #[tokio::test]
+    async fn search_spans_record_query_id_and_sectors_read() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let index_path_prefix = "disk_index_search_test_spans";
+        let index_files = [
+            (DISK_INDEX_FILE, format!("{}_disk.index", index_path_prefix)),
+            (
+                PQ_PIVOTS_FILE,
+                format!("{}.bin_pq_pivots.bin", index_path_prefix),
+            ),
+            (
+                PQ_COMPRESSED_FILE,
+                format!("{}.bin_pq_compressed.bin", index_path_prefix),
+            ),
+        ];
+        for (test_file, index_file) in &index_files {
+            fs::copy(get_test_file_path(test_file), index_file).unwrap();
+        }
+
+        let config = IndexConfiguration::new(
+            Metric::L2,
+            128,
+            DIM_128,
+            256,
+            false,
+            0,
+            false,
+            0,
+            1.0,
+            IndexWriteParametersBuilder::new(50, 4).build(),
+        );
+        let storage = DiskIndexStorage::<f32>::new(
+            get_test_file_path(TEST_DATA_FILE),
+            index_path_prefix.to_string(),
+        )
+        .unwrap();
+        let mut index = DiskIndex::<f32, DIM_128>::new(None, config, storage);
+        index.load(0).await.unwrap();
+
+        let spans = SpanFields::default();
+        let subscriber = tracing_subscriber::registry().with(spans.clone());
+        let (data, _, dim) = load_bin::<f32>(TEST_DATA_FILE, 0).unwrap();
+        let params = SearchParams::new(50, 4, None, false).unwrap().with_rerank(20);
+        {
+            let _default = tracing::subscriber::set_default(subscriber);
+            index.search_with_params(&data[..dim], 5, &params).await.unwrap();
+        }
+        for (_, index_file) in &index_files {
+            fs::remove_file(index_file).unwrap();
+        }
+
+        let fields = spans.0.lock().unwrap();
+        let values = |span: &str, field: &str| -> Vec<u64> {
+            fields
+                .iter()
+                .filter(|(name, field_name, _)| *name == span && *field_name == field)
+                .map(|(_, _, value)| *value)
+                .collect()
+        };
+        let query_ids: HashSet<u64> = ["pq_table_compute", "beam_search", "io_wait", "rerank"]
+            .into_iter()
+            .flat_map(|span| {
+                let ids = values(span, "query_id");
+                assert!(!ids.is_empty(), "no {} span", span);
+                ids
+            })
+            .collect();
+        assert_eq!(query_ids.len(), 1);
+
+        // The expansions record the sectors read so far, the reads of each round their own
+        let beam_sectors_read = values("beam_search", "sectors_read");
+        let io_sectors_read = values("io_wait", "sectors_read");
+        assert!(beam_sectors_read.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!(
+            beam_sectors_read.last().copied(),
+            Some(io_sectors_read.iter().sum())
+        );
+        assert!(io_sectors_read.iter().all(|&sectors_read| sectors_read > 0));
+        assert_eq!(values("rerank", "sectors_read").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn searches_take_turns_with_writes_in_place() {
+        let index_path_prefix = "disk_index_search_test_io_coordinator";
+        let index_files = [
+            (DISK_INDEX_FILE, format!("{}_disk.index", index_path_prefix)),
+            (
+                PQ_PIVOTS_FILE,
+                format!("{}.bin_pq_pivots.bin", index_path_prefix),
+            ),
+            (
+                PQ_COMPRESSED_FILE,
+                format!("{}.bin_pq_compressed.bin", index_path_prefix),
+            ),
+        ];
+        for (test_file, index_file) in &index_files {
+            fs::copy(get_test_file_path(test_file), index_file).unwrap();
+        }
+        let config = IndexConfiguration::new(
+            Metric::L2,
+            128,
+            DIM_128,
+            256,
+            false,
+            0,
+            false,
+            0,
+            1.0,
+            IndexWriteParametersBuilder::new(50, 4).build(),
+        );
+        let storage = DiskIndexStorage::<f32>::new(
+            get_test_file_path(TEST_DATA_FILE),
+            index_path_prefix.to_string(),
+        )
+        .unwrap();
+        let coordinator = Arc::new(IoCoordinator::new(IoFairnessConfig::default()).unwrap());
+        let mut provider = AlignedFileStorageProvider::open(&storage)
+            .unwrap()
+            .with_io_coordinator(coordinator.clone());
+        let mut index = DiskIndex::<f32, DIM_128>::new(None, config, storage)
+            .with_io_coordinator(coordinator.clone());
+        index.load(16).await.unwrap();
+        let (data, num_points, dim) = load_bin::<f32>(TEST_DATA_FILE, 0).unwrap();
+        let queries: Vec<usize> = (0..num_points).step_by(7).collect();
+        let mut expected = Vec::new();
+        for &id in &queries {
+            let query = &data[id * dim..(id + 1) * dim];
+            expected.push(index.search(query, 5, 50, 4).await.unwrap());
+        }
+
+        // The nodes are rewritten with what they hold while the same searches run again
+        let writer = std::thread::spawn(move || {
+            for _ in 0..4 {
+                for id in 0..num_points as u32 {
+                    let node = provider.get_node(id).unwrap();
+                    provider.put_node(id, &node).unwrap();
+                }
+            }
+        });
+        let mut results = Vec::new();
+        for _ in 0..4 {
+            for &id in &queries {
+                let query = &data[id * dim..(id + 1) * dim];
+                results.push(index.search(query, 5, 50, 4).await.unwrap());
+            }
+        }
+        writer.join().unwrap();
+        drop(index);
+
+        for (_, index_file) in &index_files {
+            fs::remove_file(index_file).unwrap();
+        }
+
+        for (i, result) in results.iter().enumerate() {
+            assert_eq!(*result, expected[i % queries.len()]);
+        }
+        let reads = coordinator.wait_stats(IoClass::Read).unwrap();
+        let writes = coordinator.wait_stats(IoClass::Write).unwrap();
+        assert!(reads.granted > 0);
+        assert_eq!(writes.granted, 4 * num_points as u64);
+    }
+
+    #[tokio::test]
+    async fn searches_pick_the_nodes_cached_at_the_next_load() {
+        let index_path_prefix = "disk_index_search_test_sector_usage";
+        let index_files = [
+            (DISK_INDEX_FILE, format!("{}_disk.index", index_path_prefix)),
+            (
+                PQ_PIVOTS_FILE,
+                format!("{}.bin_pq_pivots.bin", index_path_prefix),
+            ),
+            (
+                PQ_COMPRESSED_FILE,
+                format!("{}.bin_pq_compressed.bin", index_path_prefix),
+            ),
+        ];
+        for (test_file, index_file) in &index_files {
+            fs::copy(get_test_file_path(test_file), index_file).unwrap();
+        }
+        let config = IndexConfiguration::new(
+            Metric::L2,
+            128,
+            DIM_128,
+            256,
+            false,
+            0,
+            false,
+            0,
+            1.0,
+            IndexWriteParametersBuilder::new(50, 4).build(),
+        );
+        let new_index = || {
+            let storage = DiskIndexStorage::<f32>::new(
+                get_test_file_path(TEST_DATA_FILE),
+                index_path_prefix.to_string(),
+            )
+            .unwrap();
+            DiskIndex::<f32, DIM_128>::new(None, config.clone(), storage)
+        };
+        let (data, num_points, dim) = load_bin::<f32>(TEST_DATA_FILE, 0).unwrap();
+
+        // The reads of the warm-up aren't counted
+        let mut index = new_index();
+        assert!(index.save_sector_usage().is_err());
+        index.load(16).await.unwrap();
+        index.save_sector_usage().unwrap();
+        let layout_meta = index.storage.load_disk_layout_meta().unwrap();
+        let unused = index
+            .storage
+            .load_sector_usage(&layout_meta)
+            .unwrap()
+            .unwrap();
+
+        for id in (0..num_points).step_by(5) {
+            let query = &data[id * dim..(id + 1) * dim];
+            index.search(query, 5, 50, 4).await.unwrap();
+        }
+        index.end_sector_usage_epoch();
+        index.save_sector_usage().unwrap();
+        drop(index);
+
+        let mut index = new_index();
+        let hot_nodes = index.storage.hot_nodes_for_warmup(10).unwrap().unwrap();
+        index.load(10).await.unwrap();
+        let mut cached: Vec<u32> = index
+            .search_data
+            .as_ref()
+            .unwrap()
+            .node_cache
+            .keys()
+            .copied()
+            .collect();
+        drop(index);
+
+        for (_, index_file) in &index_files {
+            fs::remove_file(index_file).unwrap();
+        }
+        fs::remove_file(format!("{}_sector_usage.bin", index_path_prefix)).unwrap();
+
+        assert!((0..unused.num_sectors()).all(|sector| unused.score(sector) == 0.0));
+        assert_eq!(hot_nodes.len(), 10);
+        cached.sort_unstable();
+        let mut hot_nodes = hot_nodes;
+        hot_nodes.sort_unstable();
+        assert_eq!(cached, hot_nodes);
+    }
+
+    /// Lay the nodes of the test disk index out anew, padded to node_len bytes, under a header
+    /// claiming num_nodes_per_sector nodes per sector
+    fn write_relaid_index(filename: &str, node_len: usize, num_nodes_per_sector: usize) {
+        let bytes = fs::read(get_test_file_path(DISK_INDEX_FILE)).unwrap();
+        let (mut meta, _, _) = load_bin::<u64>(DISK_INDEX_FILE, 0).unwrap();
+        let from = DiskLayoutMeta {
+            num_pts: meta[0] as usize,
+            dim: meta[1] as usize,
+            medoid: meta[2] as u32,
+            max_node_len: meta[3] as usize,
+            num_nodes_per_sector: meta[4] as usize,
+            frozen_point: None,
+        };
+        let to = DiskLayoutMeta {
+            max_node_len: node_len,
+            num_nodes_per_sector,
+            ..from
+        };
+
+        let mut relaid = vec![0u8; to.num_sectors() * SECTOR_LEN];
+        for id in 0..from.num_pts as u32 {
+            let start = from.node_sector(id) * SECTOR_LEN + from.node_offset_in_sector(id);
+            let to_start = to.node_sector(id) * SECTOR_LEN + to.node_offset_in_sector(id);
+            relaid[to_start..to_start + from.max_node_len]
+                .copy_from_slice(&bytes[start..start + from.max_node_len]);
+        }
+        meta[3] = node_len as u64;
+        meta[4] = num_nodes_per_sector as u64;
+        meta[8] = relaid.len() as u64;
+        LittleEndian::write_i32(&mut relaid, meta.len() as i32);
+        LittleEndian::write_i32(&mut relaid[4..], 1);
+        LittleEndian::write_u64_into(&meta, &mut relaid[8..8 + meta.len() * 8]);
+        fs::write(filename, relaid).unwrap();
+    }
+
+    #[tokio::test]
+    async fn nodes_larger_than_a_sector_are_read_whole() {
+        let index_path_prefix = "disk_index_search_test_large_nodes";
+        let index_files = [
+            (
+                PQ_PIVOTS_FILE,
+                format!("{}.bin_pq_pivots.bin", index_path_prefix),
+            ),
+            (
+                PQ_COMPRESSED_FILE,
+                format!("{}.bin_pq_compressed.bin", index_path_prefix),
+            ),
+        ];
+        for (test_file, index_file) in &index_files {
+            fs::copy(get_test_file_path(test_file), index_file).unwrap();
+        }
+        let disk_index_file = format!("{}_disk.index", index_path_prefix);
+        let config = IndexConfiguration::new(
+            Metric::L2,
+            128,
+            DIM_128,
+            256,
+            false,
+            0,
+            false,
+            0,
+            1.0,
+            IndexWriteParametersBuilder::new(50, 4).build(),
+        );
+        let new_index = |prefix: &str| {
+            let storage = DiskIndexStorage::<f32>::new(
+                get_test_file_path(TEST_DATA_FILE),
+                prefix.to_string(),
+            )
+            .unwrap();
+            DiskIndex::<f32, DIM_128>::new(None, config.clone(), storage)
+        };
+
+        // Three nodes of 2000B don't fit in a sector
+        write_relaid_index(&disk_index_file, 2000, 3);
+        let invalid = new_index(index_path_prefix).load(16).await;
+
+        // Nodes of 5000B take two sectors each
+        write_relaid_index(&disk_index_file, 5000, 0);
+        let mut index = new_index(index_path_prefix);
+        let loaded = index.load(16).await;
+        let (data, num_points, dim) = load_bin::<f32>(TEST_DATA_FILE, 0).unwrap();
+        let mut results = Vec::new();
+        if loaded.is_ok() {
+            for id in (0..num_points).step_by(17) {
+                let query = &data[id * dim..(id + 1) * dim];
+                results.push(index.search(query, 5, num_points, 4).await.unwrap());
+            }
+        }
+        drop(index);
+        fs::remove_file(&disk_index_file).unwrap();
+
+        for (_, index_file) in &index_files {
+            fs::remove_file(index_file).unwrap();
+        }
+
+        assert!(invalid.is_err());
+        loaded.unwrap();
+        for (id, (ids, distances)) in (0..num_points).step_by(17).zip(results) {
+            assert_eq!(ids[0], id as u32);
+            assert_eq!(distances[0], 0.0);
+        }
+    }
+}
@@ -6,4 +6,12 @@
 mod disk_index;
 pub use disk_index::DiskIndex;
 
+#[cfg(target_os = "linux")]
+mod disk_index_search;
+
+mod sharded_build;
+
+mod prefetch_window;
+pub use prefetch_window::{PrefetchWindow, DEFAULT_MAX_QUEUE_DEPTH};
+
 pub mod ann_disk_index;
@@ -0,0 +1,163 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Number of nodes a disk search reads per round trip, adapted to the device.
+//! The window is shared by the searches of an index, since they share the device. It grows by
+//! one node after every round whose reads completed close to the fastest latency seen, and is
+//! halved when the reads get slow or too many reads of all searches are in flight at once.
+//! A fast local NVMe drive thus ends up at the beam width, while a congested cloud disk stays
+//! at a few reads per round instead of queueing more requests behind the slow ones.
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Rounds slower than this many times the fastest round seen shrink the window
+const CONGESTION_LATENCY_FACTOR: f64 = 2.0;
+
+/// Weight of the latest round in the smoothed round latency
+const LATENCY_SMOOTHING: f64 = 0.25;
+
+/// Reads in flight over all searches of the index above which the window shrinks
+pub const DEFAULT_MAX_QUEUE_DEPTH: usize = 64;
+
+/// Prefetch window shared by the searches of a disk index
+#[derive(Debug)]
+pub struct PrefetchWindow {
+    window: AtomicUsize,
+
+    /// Reads issued and not completed yet, over all searches
+    in_flight: AtomicUsize,
+
+    /// Fastest round latency seen, in nanoseconds
+    fastest_ns: AtomicU64,
+
+    /// Smoothed round latency, in nanoseconds
+    smoothed_ns: AtomicU64,
+
+    max_queue_depth: usize,
+}
+
+impl PrefetchWindow {
+    /// Start with a window of initial_window nodes, shrinking it whenever more than
+    /// max_queue_depth reads are in flight
+    pub fn new(initial_window: usize, max_queue_depth: usize) -> Self {
+        Self {
+            window: AtomicUsize::new(initial_window.max(1)),
+            in_flight: AtomicUsize::new(0),
+            fastest_ns: AtomicU64::new(u64::MAX),
+            smoothed_ns: AtomicU64::new(0),
+            max_queue_depth: max_queue_depth.max(1),
+        }
+    }
+
+    /// Nodes to read in the next round, at most max_window
+    pub fn window(&self, max_window: usize) -> usize {
+        self.window
+            .load(Ordering::Relaxed)
+            .clamp(1, max_window.max(1))
+    }
+
+    /// Record num_reads reads being issued, returns the reads in flight including them
+    pub fn begin_reads(&self, num_reads: usize) -> usize {
+        self.in_flight.fetch_add(num_reads, Ordering::AcqRel) + num_reads
+    }
+
+    /// Record the completion of num_reads reads issued together with queue_depth reads in
+    /// flight, which took latency, and adapt the window up to max_window
+    pub fn complete_reads(
+        &self,
+        num_reads: usize,
+        queue_depth: usize,
+        latency: Duration,
+        max_window: usize,
+    ) {
+        self.in_flight.fetch_sub(num_reads, Ordering::AcqRel);
+        if num_reads == 0 {
+            return;
+        }
+
+        let latency_ns = u64::try_from(latency.as_nanos()).unwrap_or(u64::MAX).max(1);
+        let fastest_ns = self
+            .fastest_ns
+            .fetch_min(latency_ns, Ordering::AcqRel)
+            .min(latency_ns);
+        let previous_ns = self.smoothed_ns.load(Ordering::Relaxed);
+        let smoothed_ns = if previous_ns == 0 {
+            latency_ns
+        } else {
+            (previous_ns as f64 * (1.0 - LATENCY_SMOOTHING) + latency_ns as f64 * LATENCY_SMOOTHING)
+                as u64
+        };
+        self.smoothed_ns.store(smoothed_ns, Ordering::Relaxed);
+
+        let congested = queue_depth > self.max_queue_depth
+            || smoothed_ns as f64 > fastest_ns as f64 * CONGESTION_LATENCY_FACTOR;
+        let max_window = max_window.max(1);
+        let _ = self
+            .window
+            .fetch_update(Ordering::AcqRel, Ordering::Relaxed, |window| {
+                Some(if congested {
+                    (window / 2).max(1)
+                } else {
+                    (window + 1).min(max_window)
+                })
+            });
+    }
+
+    /// Reads in flight over all searches
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Acquire)
+    }
+}
+
+#[cfg(test)]
+mod prefetch_window_test {
+    use super::*;
+
+    fn round(window: &PrefetchWindow, latency_us: u64, max_window: usize) -> usize {
+        let num_reads = window.window(max_window);
+        let queue_depth = window.begin_reads(num_reads);
+        window.complete_reads(
+            num_reads,
+            queue_depth,
+            Duration::from_micros(latency_us),
+            max_window,
+        );
+        window.window(max_window)
+    }
+
+    #[test]
+    fn grows_on_fast_reads_and_shrinks_on_slow_ones() {
+        let window = PrefetchWindow::new(1, DEFAULT_MAX_QUEUE_DEPTH);
+        for expected in 2..=8 {
+            assert_eq!(round(&window, 100, 8), expected);
+        }
+        assert_eq!(round(&window, 100, 8), 8);
+        assert_eq!(window.window(4), 4);
+
+        // The smoothed latency crosses twice the fastest after a few slow rounds
+        let mut shrunk = 8;
+        for _ in 0..4 {
+            shrunk = round(&window, 1000, 8);
+        }
+        assert!(shrunk < 8);
+        assert_eq!(window.in_flight(), 0);
+    }
+
+    #[test]
+    fn shrinks_when_the_queue_is_deep() {
+        let window = PrefetchWindow::new(8, 4);
+
+        // Another search holds reads in flight
+        window.begin_reads(4);
+        assert_eq!(round(&window, 100, 8), 4);
+        assert_eq!(round(&window, 100, 8), 2);
+        window.complete_reads(4, 4, Duration::from_micros(100), 8);
+        assert_eq!(window.window(8), 3);
+        assert_eq!(round(&window, 100, 8), 4);
+        assert_eq!(window.in_flight(), 0);
+    }
+}
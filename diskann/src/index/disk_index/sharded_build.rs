@@ -0,0 +1,432 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Graph build in shards, for data whose graph doesn't fit the build RAM budget.
+//! The data is split into overlapping shards by k-means, a Vamana graph is built on one shard
+//! at a time, and the neighbors every point has in its shards are merged into the graph the
+//! disk layout is written from. The overlap is what connects the shard graphs to each other.
+
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Seek, SeekFrom, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use log::info;
+use platform::current_memory_usage;
+use vector::FullPrecisionDistance;
+
+use crate::common::{ANNError, ANNResult};
+use crate::index::{ANNInmemIndex, InmemIndex};
+use crate::model::IndexConfiguration;
+use crate::utils::{file_exists, load_bin, partition_into_shards, shard_data_file, shard_ids_file};
+
+/// Shards every point is written to
+const NUM_SHARDS_PER_POINT: usize = 2;
+
+/// Most points the shard centers are trained on
+const MAX_SHARD_TRAINING_POINTS: f64 = 100_000.0;
+
+/// Size in bytes of the header of a graph file: file size, max degree, start, frozen points
+const GRAPH_HEADER_SIZE: u64 = 24;
+
+/// Graph file of a shard
+fn shard_graph_file(graph_file: &str, shard_index: usize) -> String {
+    format!("{}_subshard-{}_mem.index", graph_file, shard_index)
+}
+
+/// Build the graph over the data in data_path in shards whose graphs each fit ram_budget bytes
+/// as estimated by estimate_ram_usage, and save it to graph_file in the in-memory index format.
+pub(super) fn build_sharded_graph<T, const N: usize>(
+    configuration: &IndexConfiguration,
+    data_path: &str,
+    graph_file: &str,
+    ram_budget: f64,
+    estimate_ram_usage: impl Fn(usize) -> f64,
+) -> ANNResult<()>
+where
+    T: Default + Copy + Sync + Send + Into<f32>,
+    [T; N]: FullPrecisionDistance<T, N>,
+{
+    let num_points = configuration.max_points;
+    let sampling_rate = MAX_SHARD_TRAINING_POINTS / num_points as f64;
+    // The estimate grows linearly with the number of points
+    let max_shard_size = ((ram_budget / estimate_ram_usage(1)).ceil() as usize).saturating_sub(1);
+
+    let num_shards = if max_shard_size == 0 {
+        usize::MAX
+    } else {
+        (num_points * NUM_SHARDS_PER_POINT)
+            .div_ceil(max_shard_size)
+            .max(NUM_SHARDS_PER_POINT)
+    };
+    if num_shards > num_points {
+        return Err(ANNError::log_index_error(format!(
+            "Insufficient memory budget for index build, can't split {} points into shards of under {}GB",
+            num_points,
+            ram_budget / (1024_f64 * 1024_f64 * 1024_f64)
+        )));
+    }
+
+    // The shards have room for every copy, so none of them outgrows the budget
+    let shard_sizes = partition_into_shards::<T>(
+        data_path,
+        graph_file,
+        sampling_rate,
+        num_shards,
+        NUM_SHARDS_PER_POINT,
+        max_shard_size,
+        configuration.random_seed,
+    )?;
+    info!("Building the graph in {} shards", num_shards);
+
+    let result = build_shard_graphs::<T, N>(configuration, graph_file, &shard_sizes);
+    remove_shard_files(graph_file, num_shards)?;
+    result
+}
+
+/// Build the graphs of the shards partition_into_shards wrote for graph_file and merge them
+fn build_shard_graphs<T, const N: usize>(
+    configuration: &IndexConfiguration,
+    graph_file: &str,
+    shard_sizes: &[usize],
+) -> ANNResult<()>
+where
+    T: Default + Copy + Sync + Send + Into<f32>,
+    [T; N]: FullPrecisionDistance<T, N>,
+{
+    // Each point has neighbors from several shards, so the shard graphs are kept sparser
+    let mut shard_configuration = configuration.clone();
+    shard_configuration.num_frozen_pts = 0;
+    shard_configuration.use_pq_dist = false;
+    shard_configuration.index_write_parameter.max_degree =
+        (2 * configuration.index_write_parameter.max_degree / 3).max(1);
+
+    let mut shard_graph_files = Vec::with_capacity(shard_sizes.len());
+    let mut shard_ids_files = Vec::with_capacity(shard_sizes.len());
+    for (shard_index, &shard_size) in shard_sizes.iter().enumerate() {
+        if shard_size == 0 {
+            continue;
+        }
+
+        // Every point of a shard within the max degree is a neighbor of all the others
+        let shard_graph = shard_graph_file(graph_file, shard_index);
+        if shard_size <= shard_configuration.index_write_parameter.max_degree as usize {
+            save_complete_graph(&shard_graph, shard_size)?;
+        } else {
+            shard_configuration.max_points = shard_size;
+            let mut index = InmemIndex::<T, N>::new(shard_configuration.clone())?;
+            index.build(&shard_data_file(graph_file, shard_index), shard_size)?;
+            index.save(&shard_graph)?;
+        }
+        info!("Built shard {} of {} points", shard_index, shard_size);
+        // A shard outgrowing the RAM budget shows as a jump of the peak
+        if let Some(memory) = current_memory_usage() {
+            info!(
+                "Peak resident memory after shard {}: {:.1} MB",
+                shard_index,
+                memory.peak_resident_bytes as f64 / (1024.0 * 1024.0)
+            );
+        }
+
+        shard_graph_files.push(shard_graph);
+        shard_ids_files.push(shard_ids_file(graph_file, shard_index));
+    }
+
+    merge_shard_graphs(
+        &shard_graph_files,
+        &shard_ids_files,
+        configuration.max_points,
+        configuration.index_write_parameter.max_degree,
+        graph_file,
+    )
+}
+
+/// Merge the graphs of the shards into one graph over num_points points and save it to
+/// graph_file. Shard graph i holds the points listed in ids file i in increasing order, so all
+/// shard graphs are read along with the points. Each point gets the neighbors it has in its
+/// shards, taken from the shards in turn without repeats, up to max_degree of them. The start
+/// of the merged graph is the start of the first shard graph.
+pub(super) fn merge_shard_graphs(
+    shard_graph_files: &[String],
+    shard_ids_files: &[String],
+    num_points: usize,
+    max_degree: u32,
+    graph_file: &str,
+) -> ANNResult<()> {
+    let mut shards = Vec::with_capacity(shard_graph_files.len());
+    for (shard_graph, shard_ids) in shard_graph_files.iter().zip(shard_ids_files) {
+        let (ids, _, _) = load_bin::<u32>(shard_ids, 0)?;
+        let mut reader = BufReader::new(File::open(shard_graph)?);
+        reader.seek(SeekFrom::Start(12))?;
+        let start = reader.read_u32::<LittleEndian>()?;
+        reader.seek(SeekFrom::Start(GRAPH_HEADER_SIZE))?;
+        shards.push((ids, reader, start, 0usize));
+    }
+
+    let start = match shards.first() {
+        Some((ids, _, start, _)) => *ids.get(*start as usize).ok_or_else(|| {
+            ANNError::log_index_error(format!("ERROR: Shard start {} is not in the shard.", start))
+        })?,
+        None => {
+            return Err(ANNError::log_index_error(
+                "ERROR: No shard graphs to merge.".to_string(),
+            ))
+        }
+    };
+
+    let mut writer = BufWriter::new(File::create(graph_file)?);
+    writer.write_all(&[0u8; GRAPH_HEADER_SIZE as usize])?;
+    let mut index_size = GRAPH_HEADER_SIZE;
+    let mut max_observed_degree = 0u32;
+
+    let mut shard_neighbors: Vec<Vec<u32>> = Vec::with_capacity(shards.len());
+    let mut neighbors = Vec::with_capacity(max_degree as usize);
+    for id in 0..num_points as u32 {
+        shard_neighbors.clear();
+        for (ids, reader, _, next) in shards.iter_mut() {
+            if ids.get(*next) != Some(&id) {
+                continue;
+            }
+            *next += 1;
+
+            let degree = reader.read_u32::<LittleEndian>()?;
+            let mut local_neighbors = Vec::with_capacity(degree as usize);
+            for _ in 0..degree {
+                let local_id = reader.read_u32::<LittleEndian>()? as usize;
+                local_neighbors.push(*ids.get(local_id).ok_or_else(|| {
+                    ANNError::log_index_error(format!(
+                        "ERROR: Shard neighbor {} is out of the shard of {} points.",
+                        local_id,
+                        ids.len()
+                    ))
+                })?);
+            }
+            shard_neighbors.push(local_neighbors);
+        }
+
+        if shard_neighbors.is_empty() {
+            return Err(ANNError::log_index_error(format!(
+                "ERROR: Point {} is in none of the shards.",
+                id
+            )));
+        }
+
+        neighbors.clear();
+        let longest = shard_neighbors.iter().map(Vec::len).max().unwrap_or(0);
+        for position in 0..longest {
+            for list in &shard_neighbors {
+                if neighbors.len() < max_degree as usize {
+                    if let Some(&neighbor) = list.get(position) {
+                        if neighbor != id && !neighbors.contains(&neighbor) {
+                            neighbors.push(neighbor);
+                        }
+                    }
+                }
+            }
+        }
+
+        writer.write_all(&(neighbors.len() as u32).to_le_bytes())?;
+        for neighbor in &neighbors {
+            writer.write_all(&neighbor.to_le_bytes())?;
+        }
+        max_observed_degree = max_observed_degree.max(neighbors.len() as u32);
+        index_size += (std::mem::size_of::<u32>() * (neighbors.len() + 1)) as u64;
+    }
+
+    writer.seek(SeekFrom::Start(0))?;
+    writer.write_all(&index_size.to_le_bytes())?;
+    writer.write_all(&max_observed_degree.to_le_bytes())?;
+    writer.write_all(&start.to_le_bytes())?;
+    writer.write_all(&0u64.to_le_bytes())?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Save the graph linking each of num_points points to all the others, starting at point 0
+fn save_complete_graph(graph_file: &str, num_points: usize) -> ANNResult<()> {
+    let degree = num_points as u32 - 1;
+    let mut writer = BufWriter::new(File::create(graph_file)?);
+    let index_size = GRAPH_HEADER_SIZE
+        + (num_points * (degree as usize + 1) * std::mem::size_of::<u32>()) as u64;
+    writer.write_all(&index_size.to_le_bytes())?;
+    writer.write_all(&degree.to_le_bytes())?;
+    writer.write_all(&0u32.to_le_bytes())?;
+    writer.write_all(&0u64.to_le_bytes())?;
+    for id in 0..num_points as u32 {
+        writer.write_all(&degree.to_le_bytes())?;
+        for neighbor in (0..num_points as u32).filter(|&neighbor| neighbor != id) {
+            writer.write_all(&neighbor.to_le_bytes())?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Remove whatever files the shards of a sharded build left
+fn remove_shard_files(graph_file: &str, num_shards: usize) -> ANNResult<()> {
+    for shard_index in 0..num_shards {
+        let shard_graph = shard_graph_file(graph_file, shard_index);
+        for file in [
+            shard_data_file(graph_file, shard_index),
+            shard_ids_file(graph_file, shard_index),
+            format!("{}.data", shard_graph),
+            format!("{}.delete", shard_graph),
+            format!("{}.lock", shard_graph),
+            shard_graph,
+        ] {
+            if file_exists(&file) {
+                fs::remove_file(file)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod sharded_build_test {
+    use vector::Metric;
+
+    use super::*;
+    use crate::model::vertex::DIM_128;
+    use crate::model::IndexWriteParametersBuilder;
+    use crate::test_utils::get_test_file_path;
+
+    const TEST_DATA_FILE: &str = "tests/data/siftsmall_learn_256pts.fbin";
+
+    fn write_graph(graph_file: &str, start: u32, adjacency: &[Vec<u32>]) {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&0u64.to_le_bytes());
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        bytes.extend_from_slice(&start.to_le_bytes());
+        bytes.extend_from_slice(&0u64.to_le_bytes());
+        for neighbors in adjacency {
+            bytes.extend_from_slice(&(neighbors.len() as u32).to_le_bytes());
+            for neighbor in neighbors {
+                bytes.extend_from_slice(&neighbor.to_le_bytes());
+            }
+        }
+        fs::write(graph_file, bytes).unwrap();
+    }
+
+    fn write_ids(ids_file: &str, ids: &[u32]) {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&(ids.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        for id in ids {
+            bytes.extend_from_slice(&id.to_le_bytes());
+        }
+        fs::write(ids_file, bytes).unwrap();
+    }
+
+    fn read_graph(graph_file: &str) -> (u32, u32, Vec<Vec<u32>>) {
+        let mut reader = BufReader::new(File::open(graph_file).unwrap());
+        let index_size = reader.read_u64::<LittleEndian>().unwrap();
+        assert_eq!(index_size, fs::metadata(graph_file).unwrap().len());
+        let max_degree = reader.read_u32::<LittleEndian>().unwrap();
+        let start = reader.read_u32::<LittleEndian>().unwrap();
+        assert_eq!(reader.read_u64::<LittleEndian>().unwrap(), 0);
+
+        let mut adjacency = Vec::new();
+        while let Ok(degree) = reader.read_u32::<LittleEndian>() {
+            adjacency.push(
+                (0..degree)
+                    .map(|_| reader.read_u32::<LittleEndian>().unwrap())
+                    .collect(),
+            );
+        }
+        (max_degree, start, adjacency)
+    }
+
+    #[test]
+    fn merge_interleaves_shard_neighbors() {
+        let prefix = "merge_interleaves_shard_neighbors";
+        let files: Vec<(String, String)> = (0..2)
+            .map(|i| {
+                (
+                    format!("{}_{}.index", prefix, i),
+                    format!("{}_{}_ids.bin", prefix, i),
+                )
+            })
+            .collect();
+
+        // Shard 0 holds points 0, 1, 2 and shard 1 points 1, 2, 3
+        write_ids(&files[0].1, &[0, 1, 2]);
+        write_graph(&files[0].0, 1, &[vec![1, 2], vec![0, 2], vec![1]]);
+        write_ids(&files[1].1, &[1, 2, 3]);
+        write_graph(&files[1].0, 0, &[vec![2, 1], vec![2], vec![0, 1]]);
+
+        let graph_files: Vec<String> = files.iter().map(|f| f.0.clone()).collect();
+        let ids_files: Vec<String> = files.iter().map(|f| f.1.clone()).collect();
+        let merged_file = format!("{}_merged.index", prefix);
+        merge_shard_graphs(&graph_files, &ids_files, 4, 3, &merged_file).unwrap();
+
+        let (max_degree, start, adjacency) = read_graph(&merged_file);
+        assert_eq!(start, 1);
+        assert_eq!(max_degree, 3);
+        assert_eq!(
+            adjacency,
+            vec![vec![1, 2], vec![0, 3, 2], vec![1, 3], vec![1, 2]]
+        );
+
+        // Capped to the max degree
+        merge_shard_graphs(&graph_files, &ids_files, 4, 1, &merged_file).unwrap();
+        let (_, _, adjacency) = read_graph(&merged_file);
+        assert_eq!(adjacency, vec![vec![1], vec![0], vec![1], vec![1]]);
+
+        // A point in no shard can't be merged
+        assert!(merge_shard_graphs(&graph_files, &ids_files, 5, 3, &merged_file).is_err());
+
+        for (graph_file, ids_file) in &files {
+            fs::remove_file(graph_file).unwrap();
+            fs::remove_file(ids_file).unwrap();
+        }
+        fs::remove_file(merged_file).unwrap();
+    }
+
+    #[test]
+    fn sharded_graph_covers_every_point() {
+        let graph_file = "sharded_graph_covers_every_point_mem.index";
+        let configuration = IndexConfiguration::new(
+            Metric::L2,
+            128,
+            DIM_128,
+            256,
+            false,
+            0,
+            false,
+            0,
+            1.0,
+            IndexWriteParametersBuilder::new(50, 12)
+                .with_alpha(1.2)
+                .with_num_threads(1)
+                .build(),
+        );
+
+        // A budget of a bit over the RAM of 100 points needs at least 6 shards
+        let estimate = |num_points: usize| (num_points * 1000) as f64;
+        build_sharded_graph::<f32, DIM_128>(
+            &configuration,
+            &get_test_file_path(TEST_DATA_FILE),
+            graph_file,
+            100_500.0,
+            estimate,
+        )
+        .unwrap();
+
+        let (max_degree, start, adjacency) = read_graph(graph_file);
+        assert_eq!(adjacency.len(), 256);
+        assert!(max_degree <= 12);
+        assert!(start < 256);
+        assert!(adjacency
+            .iter()
+            .enumerate()
+            .all(|(id, neighbors)| !neighbors.is_empty()
+                && neighbors.iter().all(|&n| n < 256 && n != id as u32)));
+        assert!(!file_exists(&shard_data_file(graph_file, 0)));
+
+        fs::remove_file(graph_file).unwrap();
+    }
+}
@@ -0,0 +1,160 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Notifications of index changes that invalidate results cached downstream.
+//! Every change bumps the index version. Subscribers either receive every event on a broadcast
+//! stream, e.g. to evict the cached results of deleted points, or watch the latest version and
+//! drop their whole cache when it moves. A subscriber that falls more than the stream capacity
+//! behind gets a lagged error from the stream and should treat it as a version change.
+
+use tokio::sync::{broadcast, watch};
+
+/// A change of the index
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IndexEvent {
+    /// The index was replaced by another version, e.g. loaded again from files
+    VersionSwapped {
+        /// Version after the swap
+        version: u64,
+    },
+
+    /// Other indices were merged into the index
+    Merged {
+        /// Version after the merge
+        version: u64,
+
+        /// Number of indices merged
+        num_merged_indices: usize,
+    },
+
+    /// Points were deleted from the index
+    Deleted {
+        /// Version after the deletion
+        version: u64,
+
+        /// Ids of the deleted points
+        vertex_ids: Vec<u32>,
+    },
+}
+
+impl IndexEvent {
+    /// Version of the index after the event
+    pub fn version(&self) -> u64 {
+        match self {
+            IndexEvent::VersionSwapped { version }
+            | IndexEvent::Merged { version, .. }
+            | IndexEvent::Deleted { version, .. } => *version,
+        }
+    }
+}
+
+/// Publishes the events of an index to its subscribers
+#[derive(Debug)]
+pub struct IndexEventNotifier {
+    events: broadcast::Sender<IndexEvent>,
+    version: watch::Sender<u64>,
+}
+
+impl IndexEventNotifier {
+    /// Create a notifier keeping up to capacity events for slow subscribers
+    pub fn new(capacity: usize) -> Self {
+        let (events, _) = broadcast::channel(capacity.max(1));
+        let (version, _) = watch::channel(0);
+        Self { events, version }
+    }
+
+    /// Receive every event published from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<IndexEvent> {
+        self.events.subscribe()
+    }
+
+    /// Watch the latest index version
+    pub fn watch_version(&self) -> watch::Receiver<u64> {
+        self.version.subscribe()
+    }
+
+    /// Current index version
+    pub fn version(&self) -> u64 {
+        *self.version.borrow()
+    }
+
+    /// Publish that the index was replaced by another version
+    pub fn notify_version_swap(&self) -> u64 {
+        self.publish(|version| IndexEvent::VersionSwapped { version })
+    }
+
+    /// Publish that other indices were merged into the index
+    pub fn notify_merge(&self, num_merged_indices: usize) -> u64 {
+        self.publish(|version| IndexEvent::Merged {
+            version,
+            num_merged_indices,
+        })
+    }
+
+    /// Publish that points were deleted from the index
+    pub fn notify_deletion(&self, vertex_ids: Vec<u32>) -> u64 {
+        self.publish(|version| IndexEvent::Deleted {
+            version,
+            vertex_ids,
+        })
+    }
+
+    /// Bump the version and send the event, there may be no subscriber to receive it
+    fn publish(&self, event: impl FnOnce(u64) -> IndexEvent) -> u64 {
+        let mut new_version = 0;
+        self.version.send_modify(|version| {
+            *version += 1;
+            new_version = *version;
+        });
+        let _ = self.events.send(event(new_version));
+        new_version
+    }
+}
+
+impl Default for IndexEventNotifier {
+    fn default() -> Self {
+        Self::new(1024)
+    }
+}
+
+#[cfg(test)]
+mod index_events_test {
+    use super::*;
+
+    #[test]
+    fn subscribers_receive_events_in_order() {
+        let notifier = IndexEventNotifier::new(2);
+        let version = notifier.watch_version();
+
+        // Events without subscribers still bump the version
+        assert_eq!(notifier.notify_version_swap(), 1);
+
+        let mut events = notifier.subscribe();
+        notifier.notify_deletion(vec![3, 5]);
+        notifier.notify_merge(2);
+        assert_eq!(
+            events.try_recv().unwrap(),
+            IndexEvent::Deleted {
+                version: 2,
+                vertex_ids: vec![3, 5]
+            }
+        );
+        assert_eq!(events.try_recv().unwrap().version(), 3);
+        assert!(events.try_recv().is_err());
+        assert_eq!(*version.borrow(), 3);
+        assert_eq!(notifier.version(), 3);
+
+        // A subscriber that falls behind is told it lagged
+        for _ in 0..3 {
+            notifier.notify_version_swap();
+        }
+        assert!(matches!(
+            events.try_recv(),
+            Err(broadcast::error::TryRecvError::Lagged(1))
+        ));
+        assert_eq!(events.try_recv().unwrap().version(), 5);
+    }
+}
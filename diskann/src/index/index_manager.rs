@@ -0,0 +1,391 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Serving an in-memory index while its next version loads in the background.
+//! The next version is read, its query scratch built and its entry region warmed up on a
+//! loader thread while searches keep going to the current version. Once it is ready it
+//! replaces the current version in one swap, which is published as a version swap event.
+//! Searches that started before the swap finish on the version they started with.
+
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use vector::FullPrecisionDistance;
+
+use crate::common::{ANNError, ANNResult};
+use crate::index::{create_inmem_index, ANNInmemIndex, IndexEventNotifier};
+use crate::model::vertex::{DIM_1024, DIM_104, DIM_128, DIM_1536, DIM_256, DIM_384, DIM_768};
+use crate::model::IndexConfiguration;
+use crate::utils::file_util::load_metadata_from_file;
+
+/// Step a background load is at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadStage {
+    /// No load was started
+    Idle,
+
+    /// Reading the number of points and dimension of the data file
+    ReadingMetadata,
+
+    /// Reading the vectors and the graph and building the query scratch
+    Loading,
+
+    /// Touching the nodes around the entry points
+    WarmingUp,
+
+    /// The loaded version replaced the current one
+    Swapped,
+
+    /// The load failed, the current version keeps serving
+    Failed,
+}
+
+/// Progress of the latest background load
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadProgress {
+    /// Step the load is at
+    pub stage: LoadStage,
+
+    /// Index files prefix being loaded
+    pub prefix: String,
+
+    /// Number of points of the version being loaded, once its metadata is read
+    pub num_points: Option<usize>,
+
+    /// Time since the load started
+    pub elapsed: Duration,
+
+    /// Index version published by the swap
+    pub version: Option<u64>,
+
+    /// Why the load failed
+    pub error: Option<String>,
+}
+
+impl LoadProgress {
+    /// Whether the load has ended, swapped or failed
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self.stage,
+            LoadStage::Idle | LoadStage::Swapped | LoadStage::Failed
+        )
+    }
+}
+
+/// Progress updated by the loader thread
+#[derive(Debug)]
+struct LoadState {
+    progress: LoadProgress,
+    started: Instant,
+}
+
+/// Serves the current version of an in-memory index and swaps in new versions loaded in the
+/// background
+pub struct IndexManager<T> {
+    current: Arc<RwLock<Arc<dyn ANNInmemIndex<T>>>>,
+    state: Arc<Mutex<LoadState>>,
+    notifier: Arc<IndexEventNotifier>,
+    loader: Mutex<Option<JoinHandle<()>>>,
+
+    /// Nodes touched around the entry points of a loaded version before it is swapped in
+    warm_up_nodes: usize,
+}
+
+impl<T> fmt::Debug for IndexManager<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IndexManager")
+            .field("notifier", &self.notifier)
+            .field("warm_up_nodes", &self.warm_up_nodes)
+            .finish()
+    }
+}
+
+impl<T> IndexManager<T>
+where
+    T: Default + Copy + Sync + Send + Into<f32> + 'static,
+    [T; DIM_104]: FullPrecisionDistance<T, DIM_104>,
+    [T; DIM_128]: FullPrecisionDistance<T, DIM_128>,
+    [T; DIM_256]: FullPrecisionDistance<T, DIM_256>,
+    [T; DIM_384]: FullPrecisionDistance<T, DIM_384>,
+    [T; DIM_768]: FullPrecisionDistance<T, DIM_768>,
+    [T; DIM_1024]: FullPrecisionDistance<T, DIM_1024>,
+    [T; DIM_1536]: FullPrecisionDistance<T, DIM_1536>,
+{
+    /// Serve index, warming up warm_up_nodes nodes of every version loaded later
+    pub fn new(index: Box<dyn ANNInmemIndex<T>>, warm_up_nodes: usize) -> Self {
+        Self {
+            current: Arc::new(RwLock::new(Arc::from(index))),
+            state: Arc::new(Mutex::new(LoadState {
+                progress: LoadProgress {
+                    stage: LoadStage::Idle,
+                    prefix: String::new(),
+                    num_points: None,
+                    elapsed: Duration::ZERO,
+                    version: None,
+                    error: None,
+                },
+                started: Instant::now(),
+            })),
+            notifier: Arc::new(IndexEventNotifier::default()),
+            loader: Mutex::new(None),
+            warm_up_nodes,
+        }
+    }
+
+    /// Version of the index serving searches now. Holding it keeps that version alive
+    /// across a swap.
+    pub fn current(&self) -> ANNResult<Arc<dyn ANNInmemIndex<T>>> {
+        self.current
+            .read()
+            .map(|current| current.clone())
+            .map_err(|_| {
+                ANNError::log_lock_poison_error("failed to read the current index".to_string())
+            })
+    }
+
+    /// Search the current version for K nearest neighbors of query
+    pub fn search(
+        &self,
+        query: &[T],
+        k_value: usize,
+        l_value: u32,
+        indices: &mut [u32],
+    ) -> ANNResult<u32> {
+        self.current()?.search(query, k_value, l_value, indices)
+    }
+
+    /// Notifier publishing a version swap event for every version swapped in
+    pub fn event_notifier(&self) -> Arc<IndexEventNotifier> {
+        self.notifier.clone()
+    }
+
+    /// Progress of the latest background load
+    pub fn progress(&self) -> ANNResult<LoadProgress> {
+        let state = lock(&self.state)?;
+        let mut progress = state.progress.clone();
+        if !progress.is_finished() {
+            progress.elapsed = state.started.elapsed();
+        }
+        Ok(progress)
+    }
+
+    /// Start loading the index saved at prefix with config in the background, it replaces the
+    /// current version once loaded and warmed up. Fails if another load is still running.
+    pub fn load_next_version(&self, config: IndexConfiguration, prefix: &str) -> ANNResult<()> {
+        let mut loader = lock(&self.loader)?;
+        if !self.progress()?.is_finished() {
+            return Err(ANNError::log_index_error(format!(
+                "Cannot load {} while another version is loading",
+                prefix
+            )));
+        }
+        if let Some(finished) = loader.take() {
+            let _ = finished.join();
+        }
+
+        *lock(&self.state)? = LoadState {
+            progress: LoadProgress {
+                stage: LoadStage::ReadingMetadata,
+                prefix: prefix.to_string(),
+                num_points: None,
+                elapsed: Duration::ZERO,
+                version: None,
+                error: None,
+            },
+            started: Instant::now(),
+        };
+
+        let current = self.current.clone();
+        let state = self.state.clone();
+        let notifier = self.notifier.clone();
+        let prefix = prefix.to_string();
+        let warm_up_nodes = self.warm_up_nodes;
+        *loader = Some(
+            thread::Builder::new()
+                .name("diskann-index-loader".to_string())
+                .spawn(move || {
+                    let result =
+                        load_version(config, &prefix, warm_up_nodes, &current, &state, &notifier);
+                    if let (Err(err), Ok(mut state)) = (result, state.lock()) {
+                        state.progress.stage = LoadStage::Failed;
+                        state.progress.elapsed = state.started.elapsed();
+                        state.progress.error = Some(err.to_string());
+                    }
+                })?,
+        );
+
+        Ok(())
+    }
+
+    /// Block until the latest background load has ended and return its final progress
+    pub fn wait_for_load(&self) -> ANNResult<LoadProgress> {
+        if let Some(loader) = lock(&self.loader)?.take() {
+            loader.join().map_err(|_| {
+                ANNError::log_index_error("The index loader thread panicked".to_string())
+            })?;
+        }
+        self.progress()
+    }
+}
+
+impl<T> Drop for IndexManager<T> {
+    fn drop(&mut self) {
+        // Let a running load finish, its swap only touches state shared with this manager
+        if let Some(loader) = self.loader.get_mut().ok().and_then(Option::take) {
+            let _ = loader.join();
+        }
+    }
+}
+
+/// Load, warm up and swap in the version saved at prefix, recording the stages in state
+fn load_version<T>(
+    config: IndexConfiguration,
+    prefix: &str,
+    warm_up_nodes: usize,
+    current: &RwLock<Arc<dyn ANNInmemIndex<T>>>,
+    state: &Mutex<LoadState>,
+    notifier: &IndexEventNotifier,
+) -> ANNResult<()>
+where
+    T: Default + Copy + Sync + Send + Into<f32> + 'static,
+    [T; DIM_104]: FullPrecisionDistance<T, DIM_104>,
+    [T; DIM_128]: FullPrecisionDistance<T, DIM_128>,
+    [T; DIM_256]: FullPrecisionDistance<T, DIM_256>,
+    [T; DIM_384]: FullPrecisionDistance<T, DIM_384>,
+    [T; DIM_768]: FullPrecisionDistance<T, DIM_768>,
+    [T; DIM_1024]: FullPrecisionDistance<T, DIM_1024>,
+    [T; DIM_1536]: FullPrecisionDistance<T, DIM_1536>,
+{
+    let (num_points, dim) = load_metadata_from_file(&format!("{}.data", prefix))?;
+    if dim != config.dim {
+        return Err(ANNError::log_index_config_error(
+            "dim".to_string(),
+            format!(
+                "Index {} has {} dimensions, the configuration {}",
+                prefix, dim, config.dim
+            ),
+        ));
+    }
+    {
+        let mut state = lock(state)?;
+        state.progress.num_points = Some(num_points);
+        state.progress.stage = LoadStage::Loading;
+    }
+
+    let mut index = create_inmem_index::<T>(config)?;
+    index.load(prefix, num_points)?;
+    lock(state)?.progress.stage = LoadStage::WarmingUp;
+
+    index.warm_up(warm_up_nodes)?;
+
+    let index: Arc<dyn ANNInmemIndex<T>> = Arc::from(index);
+    let previous = {
+        let mut current = current.write().map_err(|_| {
+            ANNError::log_lock_poison_error("failed to swap the current index".to_string())
+        })?;
+        std::mem::replace(&mut *current, index)
+    };
+    let version = notifier.notify_version_swap();
+
+    let mut state = lock(state)?;
+    state.progress.stage = LoadStage::Swapped;
+    state.progress.elapsed = state.started.elapsed();
+    state.progress.version = Some(version);
+    drop(state);
+
+    // The previous version is freed here unless searches still hold it
+    drop(previous);
+    Ok(())
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> ANNResult<MutexGuard<'_, T>> {
+    mutex
+        .lock()
+        .map_err(|_| ANNError::log_lock_poison_error("index manager state".to_string()))
+}
+
+#[cfg(test)]
+mod index_manager_test {
+    use std::fs;
+
+    use vector::Metric;
+
+    use super::*;
+    use crate::model::IndexWriteParametersBuilder;
+    use crate::test_utils::get_test_file_path;
+
+    const TEST_DATA_FILE: &str = "tests/data/siftsmall_learn_256pts.fbin";
+
+    fn config() -> IndexConfiguration {
+        let index_write_parameters = IndexWriteParametersBuilder::new(50, 4)
+            .with_alpha(1.2)
+            .with_num_threads(1)
+            .build();
+        IndexConfiguration::new(
+            Metric::L2,
+            128,
+            128,
+            256,
+            false,
+            0,
+            false,
+            0,
+            1f32,
+            index_write_parameters,
+        )
+    }
+
+    #[test]
+    fn loads_next_version_in_background() {
+        let prefix = "index_manager_test";
+        let mut index = create_inmem_index::<f32>(config()).unwrap();
+        index
+            .build(get_test_file_path(TEST_DATA_FILE).as_str(), 256)
+            .unwrap();
+        index.save(prefix).unwrap();
+
+        let query = vec![1.0f32; 128];
+        let mut expected = [0u32; 5];
+        index.search(&query, 5, 50, &mut expected).unwrap();
+
+        let manager = IndexManager::new(index, 64);
+        assert_eq!(manager.progress().unwrap().stage, LoadStage::Idle);
+        let first_version = manager.current().unwrap();
+        let mut version = manager.event_notifier().watch_version();
+
+        manager.load_next_version(config(), prefix).unwrap();
+        let progress = manager.wait_for_load().unwrap();
+        assert_eq!(progress.stage, LoadStage::Swapped);
+        assert_eq!(progress.num_points, Some(256));
+        assert_eq!(progress.version, Some(1));
+        assert!(version.has_changed().unwrap());
+        assert_eq!(*version.borrow_and_update(), 1);
+
+        // The old version stays usable by whoever holds it
+        assert!(!Arc::ptr_eq(&first_version, &manager.current().unwrap()));
+        let mut indices = [0u32; 5];
+        first_version.search(&query, 5, 50, &mut indices).unwrap();
+        manager.search(&query, 5, 50, &mut indices).unwrap();
+        assert_eq!(indices, expected);
+
+        // A failed load leaves the current version serving
+        let second_version = manager.current().unwrap();
+        manager
+            .load_next_version(config(), "index_manager_test_missing")
+            .unwrap();
+        let progress = manager.wait_for_load().unwrap();
+        assert_eq!(progress.stage, LoadStage::Failed);
+        assert!(progress.error.is_some());
+        assert!(Arc::ptr_eq(&second_version, &manager.current().unwrap()));
+        assert_eq!(manager.event_notifier().version(), 1);
+
+        for suffix in ["", ".data", ".delete", ".lock"] {
+            let _ = fs::remove_file(format!("{}{}", prefix, suffix));
+        }
+    }
+}
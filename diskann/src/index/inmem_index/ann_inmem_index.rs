@@ -6,12 +6,21 @@
 
 //! ANN in-memory index abstraction
 
-use vector::FullPrecisionDistance;
+use std::ops::Range;
+use std::sync::Arc;
 
-use crate::model::{vertex::{DIM_128, DIM_256, DIM_104}, IndexConfiguration};
+use vector::{Distance, FullPrecisionDistance};
+
+use crate::instrumentation::{ProgressNotifier, QueryStats};
+use crate::model::data_store::{DatasetSource, DocumentAggregation, LabelFilter, Tag};
+use crate::model::{graph::{GraphExportFormat, GraphExportSummary}, vertex::{specialized_dimension, DIM_104, DIM_1024, DIM_128, DIM_1536, DIM_256, DIM_384, DIM_768}, DocumentMatch, IndexConfiguration, SearchParams, SearchResult, SearchResultFields};
+use crate::algorithm::search::search::SearchVisitor;
 use crate::common::{ANNResult, ANNError};
+use crate::storage::StorageProvider;
+
+use crate::index::IndexEventNotifier;
 
-use super::InmemIndex;
+use super::{InmemIndex, SearchListCalibration};
 
 /// ANN inmem-index abstraction for custom <T, N>
 pub trait ANNInmemIndex<T> : Sync + Send
@@ -20,20 +29,182 @@ where T : Default + Copy + Sync + Send + Into<f32>
     /// Build index
     fn build(&mut self, filename: &str, num_points_to_load: usize) -> ANNResult<()>;
 
-    /// Save index
+    /// Save a snapshot of the index, points inserted so far included, that load restores.
+    /// The graph goes to filename in the Vamana format and the vectors to filename.data, the
+    /// frozen points after the active points in both. The deleted ids, entry points, labels,
+    /// tags and documents go to filename.delete, .entry_points, .labels, .tags and .documents
+    /// when there are any.
     fn save(&mut self, filename: &str) -> ANNResult<()>;
 
-    /// Load index
+    /// Load the snapshot saved under filename, of expected_num_points points counting the
+    /// frozen points, into an index configured like the saved one
     fn load(&mut self, filename: &str, expected_num_points: usize) -> ANNResult<()>;
 
+    /// Save the vectors and the graph of the index, points inserted so far included, as the
+    /// nodes of provider, the frozen points after the active points. The deleted ids, entry
+    /// points, labels, tags and documents only go to the files of save.
+    fn save_to_provider(&mut self, provider: &mut dyn StorageProvider<T>) -> ANNResult<()>;
+
+    /// Load the vectors and the graph save_to_provider wrote to provider into an index
+    /// configured like the saved one
+    fn load_from_provider(&mut self, provider: &dyn StorageProvider<T>) -> ANNResult<()>;
+
+    /// Write the vectors and the graph to store under their ids in the index, then write
+    /// through the nodes insert_point adds or links to. Changes taking the index mutably
+    /// aren't written through, set the store again after them.
+    fn set_node_store(&mut self, store: Box<dyn StorageProvider<T> + Send>) -> ANNResult<()>;
+
     /// insert index
     fn insert(&mut self, filename: &str, num_points_to_insert: usize) -> ANNResult<()>;
 
+    /// Replace the index with the union of the indexes saved at index_files, the points of each
+    /// one numbered after the points of the ones before it. Every neighborhood is re-pruned over
+    /// its old neighbors and the points found searching the union, which links the indexes.
+    fn merge(&mut self, index_files: &[&str]) -> ANNResult<()>;
+
+    /// Build index from vectors in memory of the configured dimension, the ids are their positions
+    fn build_from_vectors(&mut self, vectors: &[Vec<T>]) -> ANNResult<()>;
+
+    /// Build index from rows of the configured dimension stored one after the other, such as
+    /// the rows of a mapped NpyFile, the ids are their positions
+    fn build_from_rows(&mut self, rows: &[T]) -> ANNResult<()>;
+
+    /// Build index from the vectors of the configured dimension a source hands over batch by
+    /// batch, the ids are their positions in the order of the batches
+    fn build_from_source(&mut self, source: &mut dyn DatasetSource<T>) -> ANNResult<()>;
+
+    /// Export the graph to filename under the ids save gives the points, or sample_size of its
+    /// points drawn at random if given, to look at its connectivity in graph tools
+    fn export_graph(
+        &self,
+        filename: &str,
+        format: GraphExportFormat,
+        sample_size: Option<usize>,
+    ) -> ANNResult<GraphExportSummary>;
+
+    /// Insert vectors in memory of the configured dimension, their ids follow the existing points
+    fn insert_vectors(&mut self, vectors: &[Vec<T>]) -> ANNResult<()>;
+
+    /// Insert one vector of the configured dimension while other threads search or insert,
+    /// returns its id. The index must have been created with room for it in max_points.
+    fn insert_point(&self, vector: &[T]) -> ANNResult<u32>;
+
+    /// Delete the point of id vertex_id while other threads search or insert, searches started
+    /// after it returns don't return the point
+    fn delete_point(&self, vertex_id: u32) -> ANNResult<()>;
+
+    /// Replay the write-ahead log at wal_file onto the built or loaded index, then log the
+    /// streamed inserts and deletes to it until the next save. Returns the records replayed.
+    fn open_write_ahead_log(&mut self, wal_file: &str) -> ANNResult<usize>;
+
+    /// Sync the records of the write-ahead log its sync policy left unsynced
+    fn sync_write_ahead_log(&self) -> ANNResult<()>;
+
+    /// Insert one vector like insert_point and tag it, returns its id. Fails if another point
+    /// has the tag.
+    fn insert_point_with_tag(&self, vector: &[T], tag: Tag) -> ANNResult<u32>;
+
+    /// Tag the point of id vertex_id, replacing its existing tag
+    fn set_point_tag(&self, vertex_id: u32, tag: Tag) -> ANNResult<()>;
+
+    /// Delete the point with a tag like delete_point and free the tag, returns the point's id
+    fn delete_tag(&self, tag: &Tag) -> ANNResult<u32>;
+
+    /// Find the smallest search list size, up to max_l_value, at which the mean recall@K of the
+    /// sample queries against their ground truth neighbors reaches target_recall
+    fn calibrate_search_list_size(
+        &self,
+        queries: &[Vec<T>],
+        ground_truth: &[Vec<u32>],
+        k_value: usize,
+        target_recall: f32,
+        max_l_value: u32,
+    ) -> ANNResult<SearchListCalibration>;
+
+    /// Search the index for the K nearest neighbors of query with the calibrated search list
+    /// size, grown for hard queries, returning the size used
+    fn search_with_target_recall(
+        &self,
+        query: &[T],
+        calibration: &SearchListCalibration,
+        indices: &mut [u32],
+    ) -> ANNResult<u32>;
+
+    /// Search the index for K nearest neighbors of query and return their tags, nearest first
+    fn search_tags(&self, query: &[T], k_value: usize, l_value: u32) -> ANNResult<Vec<Tag>>;
+
+    /// Touch the vectors and adjacency lists of up to num_nodes nodes around the entry points,
+    /// returning the number of nodes touched
+    fn warm_up(&self, num_nodes: usize) -> ANNResult<usize>;
+
     /// Search the index for K nearest neighbors of query using given L value, for benchmarking purposes
     fn search(&self, query : &[T], k_value : usize, l_value : u32, indices : &mut[u32]) -> ANNResult<u32>;
 
+    /// Search the index for K nearest neighbors of query like search, calling visitor with the id, distance and hop
+    /// of every node the search expands
+    fn search_with_visitor(&self, query : &[T], k_value : usize, l_value : u32, visitor : &mut dyn SearchVisitor, indices : &mut[u32]) -> ANNResult<u32>;
+
+    /// Search the index for K nearest neighbors of query with a weight per dimension applied to the distances of this query
+    fn search_with_weights(&self, query : &[T], weights : &[f32], k_value : usize, l_value : u32, indices : &mut[u32]) -> ANNResult<u32>;
+
+    /// Search the index for K nearest neighbors of query, traversing with distances over the dimension range dims
+    /// and reranking the candidates with the full distance
+    fn search_in_subspace(&self, query : &[T], dims : Range<usize>, k_value : usize, l_value : u32, indices : &mut[u32]) -> ANNResult<u32>;
+
+    /// Search the index for K nearest neighbors of query with the given search parameters
+    fn search_with_params(&self, query : &[T], k_value : usize, params : &SearchParams, indices : &mut[u32]) -> ANNResult<u32>;
+
+    /// Search the index like search_with_params and return the statistics of the query, which
+    /// flag a search the IO limit or patience of params stopped early
+    fn search_with_params_and_stats(
+        &self,
+        query: &[T],
+        k_value: usize,
+        params: &SearchParams,
+        indices: &mut [u32],
+    ) -> ANNResult<QueryStats>;
+
+    /// Search the index for K nearest neighbors of query, populating the requested result fields
+    /// and returning the statistics of the query in one call
+    fn search_with_details(&self, query : &[T], k_value : usize, l_value : u32, fields : SearchResultFields) -> ANNResult<(Vec<SearchResult>, QueryStats)>;
+
+    /// Search the index for K nearest neighbors of query among the points whose labels satisfy filter,
+    /// returning the number of ids written
+    fn search_with_filter(&self, query : &[T], filter : &LabelFilter, k_value : usize, l_value : u32, indices : &mut[u32]) -> ANNResult<usize>;
+
+    /// Attach labels to a point
+    fn set_point_labels(&mut self, vertex_id: u32, labels: Vec<u32>) -> ANNResult<()>;
+
+    /// Attach payload bytes to a point
+    fn set_point_payload(&mut self, vertex_id: u32, payload: Vec<u8>) -> ANNResult<()>;
+
+    /// Make the point of id vertex_id one of the vectors of document
+    fn set_point_document(&mut self, vertex_id: u32, document: Tag) -> ANNResult<()>;
+
+    /// Search the index for the K documents nearest to query, their distances to the query
+    /// combined by aggregation, each document returned once
+    fn search_documents(
+        &self,
+        query: &[T],
+        k_value: usize,
+        l_value: u32,
+        aggregation: DocumentAggregation,
+    ) -> ANNResult<Vec<DocumentMatch>>;
+
+    /// Pack the graph into a compact arena for searching, it is unpacked again before the next change
+    fn compact_graph(&mut self) -> ANNResult<()>;
+
+    /// Publish the loads, merges and deletions of the index with the given notifier
+    fn set_event_notifier(&mut self, notifier: Arc<IndexEventNotifier>);
+
+    /// Publish the progress of builds, inserts and deletes with the given notifier
+    fn set_progress_notifier(&mut self, notifier: Arc<ProgressNotifier>);
+
     /// Soft deletes the nodes with the ids in the given array.
     fn soft_delete(&mut self, vertex_ids_to_delete: Vec<u32>,  num_points_to_delete: usize) -> ANNResult<()>;
+
+    /// Unlink the soft deleted points from the graph, returning how many were unlinked
+    fn consolidate_deletes(&mut self) -> ANNResult<usize>;
 }
 
 /// Create Index<T, N> based on configuration
@@ -43,7 +214,12 @@ where
     [T; DIM_104]: FullPrecisionDistance<T, DIM_104>,
     [T; DIM_128]: FullPrecisionDistance<T, DIM_128>,
     [T; DIM_256]: FullPrecisionDistance<T, DIM_256>,
+    [T; DIM_384]: FullPrecisionDistance<T, DIM_384>,
+    [T; DIM_768]: FullPrecisionDistance<T, DIM_768>,
+    [T; DIM_1024]: FullPrecisionDistance<T, DIM_1024>,
+    [T; DIM_1536]: FullPrecisionDistance<T, DIM_1536>,
 {
+    let config = with_specialized_dimension(config)?;
     match config.aligned_dim {
         DIM_104 => {
             let index = Box::new(InmemIndex::<T, DIM_104>::new(config)?);
@@ -57,15 +233,99 @@ where
             let index = Box::new(InmemIndex::<T, DIM_256>::new(config)?);
             Ok(index as Box<dyn ANNInmemIndex<T>>)
         },
+        DIM_384 => {
+            let index = Box::new(InmemIndex::<T, DIM_384>::new(config)?);
+            Ok(index as Box<dyn ANNInmemIndex<T>>)
+        },
+        DIM_768 => {
+            let index = Box::new(InmemIndex::<T, DIM_768>::new(config)?);
+            Ok(index as Box<dyn ANNInmemIndex<T>>)
+        },
+        DIM_1024 => {
+            let index = Box::new(InmemIndex::<T, DIM_1024>::new(config)?);
+            Ok(index as Box<dyn ANNInmemIndex<T>>)
+        },
+        DIM_1536 => {
+            let index = Box::new(InmemIndex::<T, DIM_1536>::new(config)?);
+            Ok(index as Box<dyn ANNInmemIndex<T>>)
+        },
+        _ => Err(ANNError::log_index_error(format!("Invalid dimension: {}", config.aligned_dim))),
+    }
+}
+
+/// Create Index<T, N> based on configuration, comparing vectors with a user-defined distance
+pub fn create_inmem_index_with_distance<'a, T, D>(
+    config: IndexConfiguration,
+    distance: D,
+) -> ANNResult<Box<dyn ANNInmemIndex<T> + 'a>>
+where
+    T: Default + Copy + Sync + Send + Into<f32> + 'a,
+    [T; DIM_104]: FullPrecisionDistance<T, DIM_104>,
+    [T; DIM_128]: FullPrecisionDistance<T, DIM_128>,
+    [T; DIM_256]: FullPrecisionDistance<T, DIM_256>,
+    [T; DIM_384]: FullPrecisionDistance<T, DIM_384>,
+    [T; DIM_768]: FullPrecisionDistance<T, DIM_768>,
+    [T; DIM_1024]: FullPrecisionDistance<T, DIM_1024>,
+    [T; DIM_1536]: FullPrecisionDistance<T, DIM_1536>,
+    D: Distance<T, DIM_104>
+        + Distance<T, DIM_128>
+        + Distance<T, DIM_256>
+        + Distance<T, DIM_384>
+        + Distance<T, DIM_768>
+        + Distance<T, DIM_1024>
+        + Distance<T, DIM_1536>
+        + 'a,
+{
+    let config = with_specialized_dimension(config)?;
+    match config.aligned_dim {
+        DIM_104 => {
+            let index = Box::new(InmemIndex::<T, DIM_104, D>::with_distance(config, distance)?);
+            Ok(index as Box<dyn ANNInmemIndex<T>>)
+        },
+        DIM_128 => {
+            let index = Box::new(InmemIndex::<T, DIM_128, D>::with_distance(config, distance)?);
+            Ok(index as Box<dyn ANNInmemIndex<T>>)
+        },
+        DIM_256 => {
+            let index = Box::new(InmemIndex::<T, DIM_256, D>::with_distance(config, distance)?);
+            Ok(index as Box<dyn ANNInmemIndex<T>>)
+        },
+        DIM_384 => {
+            let index = Box::new(InmemIndex::<T, DIM_384, D>::with_distance(config, distance)?);
+            Ok(index as Box<dyn ANNInmemIndex<T>>)
+        },
+        DIM_768 => {
+            let index = Box::new(InmemIndex::<T, DIM_768, D>::with_distance(config, distance)?);
+            Ok(index as Box<dyn ANNInmemIndex<T>>)
+        },
+        DIM_1024 => {
+            let index = Box::new(InmemIndex::<T, DIM_1024, D>::with_distance(config, distance)?);
+            Ok(index as Box<dyn ANNInmemIndex<T>>)
+        },
+        DIM_1536 => {
+            let index = Box::new(InmemIndex::<T, DIM_1536, D>::with_distance(config, distance)?);
+            Ok(index as Box<dyn ANNInmemIndex<T>>)
+        },
         _ => Err(ANNError::log_index_error(format!("Invalid dimension: {}", config.aligned_dim))),
     }
 }
 
+/// Round the aligned dimension up to the nearest specialized one, so dimensions without their
+/// own kernels fall back to the next larger kernel with zero padding
+fn with_specialized_dimension(mut config: IndexConfiguration) -> ANNResult<IndexConfiguration> {
+    config.aligned_dim = specialized_dimension(config.aligned_dim).ok_or_else(|| {
+        ANNError::log_index_error(format!("Invalid dimension: {}", config.aligned_dim))
+    })?;
+    Ok(config)
+}
+
 #[cfg(test)]
 mod dataset_test {
     use vector::Metric;
 
     use crate::model::configuration::index_write_parameters::IndexWriteParametersBuilder;
+    use crate::model::data_store::{ChannelSource, SliceSource};
+    use crate::model::IndexConfigurationBuilder;
 
     use super::*;
 
@@ -93,5 +353,465 @@ mod dataset_test {
         let mut index = create_inmem_index::<f32>(config).unwrap();
         index.build("fake_file", 100).unwrap();
     }
-}
 
+    #[test]
+    fn create_index_rounds_up_to_specialized_dimension() {
+        assert_eq!(specialized_dimension(128), Some(DIM_128));
+        assert_eq!(specialized_dimension(136), Some(DIM_256));
+        assert_eq!(specialized_dimension(1000), Some(DIM_1024));
+        assert_eq!(specialized_dimension(1544), None);
+
+        let config = |aligned_dim: usize| {
+            let index_write_parameters = IndexWriteParametersBuilder::new(50, 4).build();
+            IndexConfiguration::new(
+                Metric::L2,
+                aligned_dim,
+                aligned_dim,
+                100,
+                false,
+                0,
+                false,
+                0,
+                1f32,
+                index_write_parameters,
+            )
+        };
+        assert!(create_inmem_index::<f32>(config(136)).is_ok());
+        assert!(create_inmem_index::<f32>(config(1544)).is_err());
+    }
+
+    #[test]
+    fn build_insert_and_search_vectors() {
+        // Points on a 3-d grid padded to 10 dimensions, so each one is its own nearest neighbor
+        let vectors: Vec<Vec<f32>> = (0..250)
+            .map(|i| {
+                let mut vector = vec![(i % 5) as f32, ((i / 5) % 5) as f32, (i / 25) as f32];
+                vector.resize(10, 0.5);
+                vector
+            })
+            .collect();
+
+        let index_write_parameters = IndexWriteParametersBuilder::new(50, 16)
+            .with_num_threads(1)
+            .build();
+        let config = IndexConfigurationBuilder::new(Metric::L2, 10, 200)
+            .with_index_write_parameters(index_write_parameters)
+            .with_growth_potential(1.5)
+            .build();
+        let mut index = create_inmem_index::<f32>(config).unwrap();
+
+        index.build_from_vectors(&vectors[..200]).unwrap();
+        index.insert_vectors(&vectors[200..]).unwrap();
+
+        for (id, vector) in vectors.iter().enumerate() {
+            let mut indices = [0u32; 1];
+            index.search(vector, 1, 50, &mut indices).unwrap();
+            assert_eq!(indices[0], id as u32);
+        }
+
+        assert!(index.insert_vectors(&[vec![0.0; 9]]).is_err());
+
+        let export_file = "build_insert_and_search_vectors.graphml";
+        let summary = index
+            .export_graph(export_file, GraphExportFormat::GraphML, Some(20))
+            .unwrap();
+        let graphml = std::fs::read_to_string(export_file).unwrap();
+        std::fs::remove_file(export_file).unwrap();
+        assert_eq!(summary.num_vertices, 20);
+        assert!(summary.max_degree() <= 16);
+        assert_eq!(graphml.matches("<edge ").count(), summary.num_edges);
+    }
+
+    #[test]
+    fn build_from_streamed_batches() {
+        let vectors: Vec<Vec<f32>> = (0..125)
+            .map(|i| {
+                let mut vector = vec![(i % 5) as f32, ((i / 5) % 5) as f32, (i / 25) as f32];
+                vector.resize(10, 0.5);
+                vector
+            })
+            .collect();
+
+        let index_write_parameters = IndexWriteParametersBuilder::new(50, 16)
+            .with_num_threads(1)
+            .build();
+        let config = IndexConfigurationBuilder::new(Metric::L2, 10, 125)
+            .with_index_write_parameters(index_write_parameters)
+            .build();
+        let mut index = create_inmem_index::<f32>(config).unwrap();
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let producer_vectors = vectors.clone();
+        let producer = std::thread::spawn(move || {
+            for vector in producer_vectors {
+                sender.send(vector).unwrap();
+            }
+        });
+        let mut source = ChannelSource::new(receiver, 10, 16);
+        index.build_from_source(&mut source).unwrap();
+        producer.join().unwrap();
+
+        for (id, vector) in vectors.iter().enumerate() {
+            let mut indices = [0u32; 1];
+            index.search(vector, 1, 50, &mut indices).unwrap();
+            assert_eq!(indices[0], id as u32);
+        }
+
+        // A source of another dimension or with more vectors than max_points is rejected
+        let rows: Vec<f32> = vectors.concat();
+        let mut source = SliceSource::new(&rows, 5, 16).unwrap();
+        assert!(index.build_from_source(&mut source).is_err());
+        let rows: Vec<f32> = rows.iter().chain(&rows[..10]).copied().collect();
+        let mut source = SliceSource::new(&rows, 10, 16).unwrap();
+        assert!(index.build_from_source(&mut source).is_err());
+    }
+
+    #[test]
+    fn insert_points_while_searching() {
+        let vectors: Vec<Vec<f32>> = (0..250)
+            .map(|i| {
+                let mut vector = vec![(i % 5) as f32, ((i / 5) % 5) as f32, (i / 25) as f32];
+                vector.resize(10, 0.5);
+                vector
+            })
+            .collect();
+
+        let index_write_parameters = IndexWriteParametersBuilder::new(50, 16)
+            .with_num_threads(1)
+            .build();
+        let config = IndexConfigurationBuilder::new(Metric::L2, 10, 250)
+            .with_index_write_parameters(index_write_parameters)
+            .build();
+        let mut index = create_inmem_index::<f32>(config).unwrap();
+        assert!(index.insert_point(&vectors[0]).is_err());
+
+        index.build_from_vectors(&vectors[..150]).unwrap();
+
+        let index = &*index;
+        let inserted_ids = std::thread::scope(|scope| {
+            let inserters: Vec<_> = vectors[150..]
+                .chunks(50)
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        chunk
+                            .iter()
+                            .map(|vector| index.insert_point(vector).unwrap())
+                            .collect::<Vec<u32>>()
+                    })
+                })
+                .collect();
+
+            for (id, vector) in vectors[..150].iter().enumerate() {
+                let mut indices = [0u32; 1];
+                index.search(vector, 1, 50, &mut indices).unwrap();
+                assert_eq!(indices[0], id as u32);
+            }
+
+            inserters
+                .into_iter()
+                .flat_map(|inserter| inserter.join().unwrap())
+                .collect::<Vec<u32>>()
+        });
+
+        let mut sorted_ids = inserted_ids.clone();
+        sorted_ids.sort();
+        assert_eq!(sorted_ids, (150..250).collect::<Vec<u32>>());
+
+        // Each inserted point is found under the id it was given
+        let chunk_ids = inserted_ids.chunks(50);
+        for (chunk, ids) in vectors[150..].chunks(50).zip(chunk_ids) {
+            for (vector, &id) in chunk.iter().zip(ids) {
+                let mut indices = [0u32; 1];
+                index.search(vector, 1, 50, &mut indices).unwrap();
+                assert_eq!(indices[0], id);
+            }
+        }
+
+        assert!(index.insert_point(&vectors[0]).is_err());
+    }
+
+    #[test]
+    fn delete_points_while_inserting_and_searching() {
+        let vectors: Vec<Vec<f32>> = (0..250)
+            .map(|i| {
+                let mut vector = vec![(i % 5) as f32, ((i / 5) % 5) as f32, (i / 25) as f32];
+                vector.resize(10, 0.5);
+                vector
+            })
+            .collect();
+
+        let index_write_parameters = IndexWriteParametersBuilder::new(50, 16)
+            .with_num_threads(1)
+            .build();
+        let config = IndexConfigurationBuilder::new(Metric::L2, 10, 250)
+            .with_index_write_parameters(index_write_parameters)
+            .build();
+        let mut index = create_inmem_index::<f32>(config).unwrap();
+        index.build_from_vectors(&vectors[..150]).unwrap();
+
+        let index = &*index;
+        std::thread::scope(|scope| {
+            for chunk in vectors[150..].chunks(25) {
+                scope.spawn(move || {
+                    for vector in chunk {
+                        index.insert_point(vector).unwrap();
+                    }
+                });
+            }
+
+            // Once deleted, a point is never returned, not even for its own vector
+            for id in (0..150).step_by(3) {
+                index.delete_point(id).unwrap();
+                let mut indices = [0u32; 1];
+                let vector = &vectors[id as usize];
+                index.search(vector, 1, 50, &mut indices).unwrap();
+                assert_ne!(indices[0], id);
+            }
+        });
+
+        let mut indices = [0u32; 5];
+        for vector in &vectors {
+            index.search(vector, 5, 50, &mut indices).unwrap();
+            assert!(indices.iter().all(|&id| id >= 150 || id % 3 != 0));
+        }
+
+        assert!(index.delete_point(250).is_err());
+    }
+
+    #[test]
+    fn write_ahead_log_recovers_streamed_changes() {
+        let vectors: Vec<Vec<f32>> = (0..250)
+            .map(|i| {
+                let mut vector = vec![(i % 5) as f32, ((i / 5) % 5) as f32, (i / 25) as f32];
+                vector.resize(10, 0.5);
+                vector
+            })
+            .collect();
+        let config = || {
+            let index_write_parameters = IndexWriteParametersBuilder::new(50, 16)
+                .with_num_threads(1)
+                .build();
+            IndexConfigurationBuilder::new(Metric::L2, 10, 250)
+                .with_index_write_parameters(index_write_parameters)
+                .build()
+        };
+        let snapshot = "write_ahead_log_recovers_streamed_changes";
+        let wal_file = "write_ahead_log_recovers_streamed_changes.wal";
+
+        let mut index = create_inmem_index::<f32>(config()).unwrap();
+        index.build_from_vectors(&vectors[..150]).unwrap();
+        index.save(snapshot).unwrap();
+        assert_eq!(index.open_write_ahead_log(wal_file).unwrap(), 0);
+        assert!(index.insert_vectors(&vectors[150..]).is_err());
+        for vector in &vectors[150..] {
+            index.insert_point(vector).unwrap();
+        }
+        index.delete_point(3).unwrap();
+        index.delete_point(200).unwrap();
+        // The process dies before the next snapshot
+        drop(index);
+
+        let mut recovered = create_inmem_index::<f32>(config()).unwrap();
+        recovered.load(snapshot, 150).unwrap();
+        assert_eq!(recovered.open_write_ahead_log(wal_file).unwrap(), 102);
+        let mut indices = [0u32; 1];
+        for (id, vector) in vectors.iter().enumerate() {
+            recovered.search(vector, 1, 50, &mut indices).unwrap();
+            if id == 3 || id == 200 {
+                assert_ne!(indices[0], id as u32);
+            } else {
+                assert_eq!(indices[0], id as u32);
+            }
+        }
+
+        // Saving empties the log, the snapshot has all of it
+        recovered.save(snapshot).unwrap();
+        drop(recovered);
+        let mut reloaded = create_inmem_index::<f32>(config()).unwrap();
+        reloaded.load(snapshot, 250).unwrap();
+        assert_eq!(reloaded.open_write_ahead_log(wal_file).unwrap(), 0);
+
+        for file in [
+            snapshot.to_string(),
+            format!("{}.data", snapshot),
+            format!("{}.delete", snapshot),
+            format!("{}.lock", snapshot),
+            wal_file.to_string(),
+        ] {
+            if crate::utils::file_exists(&file) {
+                std::fs::remove_file(file).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn search_returns_tags_that_survive_save_and_load() {
+        let vectors: Vec<Vec<f32>> = (0..120)
+            .map(|i| {
+                let mut vector = vec![(i % 5) as f32, ((i / 5) % 5) as f32, (i / 25) as f32];
+                vector.resize(10, 0.5);
+                vector
+            })
+            .collect();
+        let config = || {
+            let index_write_parameters = IndexWriteParametersBuilder::new(50, 16)
+                .with_num_threads(1)
+                .build();
+            IndexConfigurationBuilder::new(Metric::L2, 10, 120)
+                .with_index_write_parameters(index_write_parameters)
+                .build()
+        };
+        let tag_of = |i: usize| Tag::Name(format!("doc-{}", i));
+
+        let mut index = create_inmem_index::<f32>(config()).unwrap();
+        index.build_from_vectors(&vectors[..100]).unwrap();
+        for i in 0..100 {
+            index.set_point_tag(i as u32, tag_of(i)).unwrap();
+        }
+        for (i, vector) in vectors.iter().enumerate().skip(100) {
+            index.insert_point_with_tag(vector, tag_of(i)).unwrap();
+        }
+        assert!(index.insert_point_with_tag(&vectors[0], tag_of(7)).is_err());
+        assert_eq!(index.delete_tag(&tag_of(110)).unwrap(), 110);
+        assert!(index.delete_tag(&tag_of(110)).is_err());
+
+        let snapshot = "search_returns_tags_that_survive_save_and_load";
+        index.save(snapshot).unwrap();
+        let mut loaded = create_inmem_index::<f32>(config()).unwrap();
+        loaded.load(snapshot, 120).unwrap();
+        for (i, vector) in vectors.iter().enumerate() {
+            let tags = loaded.search_tags(vector, 1, 50).unwrap();
+            if i == 110 {
+                assert_ne!(tags[0], tag_of(110));
+            } else {
+                assert_eq!(tags[0], tag_of(i));
+            }
+        }
+
+        for file in [
+            snapshot.to_string(),
+            format!("{}.data", snapshot),
+            format!("{}.delete", snapshot),
+            format!("{}.tags", snapshot),
+            format!("{}.lock", snapshot),
+        ] {
+            if crate::utils::file_exists(&file) {
+                std::fs::remove_file(file).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn labels_payloads_and_tags_survive_save_and_load() {
+        let vectors: Vec<Vec<f32>> = (0..100)
+            .map(|i| {
+                let mut vector = vec![(i % 5) as f32, ((i / 5) % 5) as f32, (i / 25) as f32];
+                vector.resize(10, 0.5);
+                vector
+            })
+            .collect();
+        let config = || {
+            let index_write_parameters = IndexWriteParametersBuilder::new(50, 16)
+                .with_num_threads(1)
+                .build();
+            IndexConfigurationBuilder::new(Metric::L2, 10, 100)
+                .with_index_write_parameters(index_write_parameters)
+                .build()
+        };
+
+        let mut index = create_inmem_index::<f32>(config()).unwrap();
+        index.build_from_vectors(&vectors).unwrap();
+        index.set_point_labels(42, vec![4, 2]).unwrap();
+        index.set_point_payload(42, b"doc-42".to_vec()).unwrap();
+        index.set_point_tag(42, Tag::Id(4200)).unwrap();
+
+        let snapshot = "labels_payloads_and_tags_survive_save_and_load";
+        index.save(snapshot).unwrap();
+        let mut loaded = create_inmem_index::<f32>(config()).unwrap();
+        loaded.set_point_payload(7, b"stale".to_vec()).unwrap();
+        loaded.load(snapshot, 100).unwrap();
+        let (results, _) = loaded
+            .search_with_details(&vectors[42], 1, 50, SearchResultFields::ALL)
+            .unwrap();
+        let (others, _) = loaded
+            .search_with_details(&vectors[7], 1, 50, SearchResultFields::ALL)
+            .unwrap();
+
+        for file in [
+            snapshot.to_string(),
+            format!("{}.data", snapshot),
+            format!("{}.delete", snapshot),
+            format!("{}.labels", snapshot),
+            format!("{}.payloads", snapshot),
+            format!("{}.tags", snapshot),
+            format!("{}.lock", snapshot),
+        ] {
+            if crate::utils::file_exists(&file) {
+                std::fs::remove_file(file).unwrap();
+            }
+        }
+        assert_eq!(results[0].id, 42);
+        assert_eq!(results[0].labels, Some(vec![4, 2]));
+        assert_eq!(results[0].payload, Some(b"doc-42".to_vec()));
+        assert_eq!(results[0].tag, Some(Tag::Id(4200)));
+        assert_eq!(others[0].id, 7);
+        assert_eq!(others[0].payload, Some(Vec::new()));
+        assert_eq!(others[0].tag, None);
+    }
+
+    #[test]
+    fn search_documents_aggregates_their_vectors() {
+        let vectors: Vec<Vec<f32>> = (0..100)
+            .map(|i| {
+                let mut vector = vec![(i % 5) as f32, ((i / 5) % 5) as f32, (i / 25) as f32];
+                vector.resize(10, 0.5);
+                vector
+            })
+            .collect();
+        let index_write_parameters = IndexWriteParametersBuilder::new(50, 16)
+            .with_num_threads(1)
+            .build();
+        let config = IndexConfigurationBuilder::new(Metric::L2, 10, 100)
+            .with_index_write_parameters(index_write_parameters)
+            .build();
+        let mut index = create_inmem_index::<f32>(config).unwrap();
+        index.build_from_vectors(&vectors).unwrap();
+
+        // Around point 62, "one" has a vector on it and one far away, "all" has four vectors
+        // next to it. Point 12 is a document of its own and the other points belong to none.
+        let one = Tag::from("one");
+        let all = Tag::from("all");
+        for vertex_id in [62, 0] {
+            index.set_point_document(vertex_id, one.clone()).unwrap();
+        }
+        for vertex_id in [61, 63, 57, 67] {
+            index.set_point_document(vertex_id, all.clone()).unwrap();
+        }
+        index.set_point_document(12, Tag::Id(12)).unwrap();
+
+        let query = &vectors[62];
+        let by_max = index
+            .search_documents(query, 2, 50, DocumentAggregation::Max)
+            .unwrap();
+        assert_eq!(by_max[0].document, one);
+        assert_eq!(by_max[0].distance, 0.0);
+        assert_eq!(by_max[0].best_vertex_id, 62);
+        assert_eq!(by_max[1].document, all);
+        assert_eq!(by_max[1].distance, 1.0);
+
+        let by_mean = index
+            .search_documents(query, 3, 50, DocumentAggregation::Mean)
+            .unwrap();
+        let documents: Vec<Tag> = by_mean.iter().map(|m| m.document.clone()).collect();
+        assert_eq!(documents, vec![all.clone(), Tag::Id(12), one.clone()]);
+        assert_eq!(by_mean[2].distance, 6.0);
+
+        // A deleted vector no longer counts towards its document
+        index.delete_point(62).unwrap();
+        let by_max = index
+            .search_documents(query, 2, 50, DocumentAggregation::Max)
+            .unwrap();
+        assert_eq!(by_max[0].document, all);
+        assert_eq!(by_max[1].document, Tag::Id(12));
+    }
+}
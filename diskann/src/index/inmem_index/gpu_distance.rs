@@ -0,0 +1,589 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Distances between the candidates of the prunes of a build, computed on a GPU through wgpu.
+//! Pruning the pool of a vertex compares each candidate with the candidates kept before it,
+//! which is most of the distance work of a build. The link phase searches for a batch of
+//! vertices, then the GPU computes the distances from every candidate of each pool to the
+//! candidates before it, for all the pools of the batch in one dispatch, and the prunes look
+//! them up. Which of the earlier candidates are kept depends on these distances, so the lower
+//! triangle of each pool is the least the GPU can compute ahead of the prune.
+//! Binary metrics stay on the CPU, as do searches, the prunes of inserts and builds pruning
+//! quantized prune vectors, which the GPU doesn't hold. The GPU sums in
+//! another order than the SIMD kernels, so a distance may differ in its last bits and break a
+//! tie the other way.
+
+use std::sync::{mpsc, Mutex};
+
+use log::info;
+use vector::Metric;
+
+use crate::common::{ANNError, ANNResult};
+
+/// Distances from each candidate of a pool to the candidates before it, one invocation per
+/// pair and one z slice per pool
+const POOL_TRIANGLE_SHADER: &str = r#"
+struct Params {
+    dim: u32,
+    metric: u32,
+    padding0: u32,
+    padding1: u32,
+}
+
+struct Pool {
+    // Index in vectors of the first element of the first candidate
+    vector_offset: u32,
+    size: u32,
+    // Index in distances of the distance from candidate 1 to candidate 0
+    triangle_offset: u32,
+    padding: u32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> pools: array<Pool>;
+@group(0) @binding(2) var<storage, read> vectors: array<f32>;
+@group(0) @binding(3) var<storage, read_write> distances: array<f32>;
+
+@compute @workgroup_size(8, 8)
+fn pool_triangle(@builtin(global_invocation_id) id: vec3<u32>) {
+    let pool = pools[id.z];
+    let i = id.x;
+    let j = id.y;
+    if (i >= pool.size || j >= i) {
+        return;
+    }
+
+    let a = pool.vector_offset + i * params.dim;
+    let b = pool.vector_offset + j * params.dim;
+    var sum = 0.0;
+    var dot = 0.0;
+    var norm_a = 0.0;
+    var norm_b = 0.0;
+    for (var k = 0u; k < params.dim; k++) {
+        let x = vectors[a + k];
+        let y = vectors[b + k];
+        let diff = abs(x - y);
+        switch params.metric {
+            case 0u: { sum += diff * diff; }
+            case 1u: { sum += diff; }
+            case 2u: { sum = max(sum, diff); }
+            default: {
+                dot += x * y;
+                norm_a += x * x;
+                norm_b += y * y;
+            }
+        }
+    }
+
+    // Cosine as vector::cosine_distance, 1 for the zero vector
+    if (params.metric == 3u) {
+        if (norm_a == 0.0 || norm_b == 0.0) {
+            sum = 1.0;
+        } else {
+            sum = 1.0 - dot / (sqrt(norm_a) * sqrt(norm_b));
+        }
+    }
+    distances[pool.triangle_offset + i * (i - 1u) / 2u + j] = sum;
+}
+"#;
+
+/// Side of the square of pairs a workgroup computes, the workgroup_size of the shader
+const WORKGROUP_SIDE: u32 = 8;
+
+/// Bytes of the Params and of each Pool of the shader
+const PARAMS_SIZE: u64 = 16;
+const POOL_SIZE: u64 = 16;
+
+/// Code of a metric in the shader, None for the ones it doesn't compute
+fn metric_code(metric: Metric) -> Option<u32> {
+    match metric {
+        Metric::L2 => Some(0),
+        Metric::L1 => Some(1),
+        Metric::Chebyshev => Some(2),
+        Metric::Cosine => Some(3),
+        Metric::Hamming | Metric::Tanimoto => None,
+    }
+}
+
+/// Number of distances in the lower triangle of a pool of the given size
+pub(crate) fn triangle_len(pool_size: usize) -> usize {
+    pool_size * pool_size.saturating_sub(1) / 2
+}
+
+/// Distance from candidate i to candidate j < i of a pool, in the triangle of the pool
+#[cfg(test)]
+fn triangle_distance(triangle: &[f32], i: usize, j: usize) -> f32 {
+    triangle[i * (i - 1) / 2 + j]
+}
+
+/// Buffers kept from one dispatch to the next, grown when a batch doesn't fit
+#[derive(Debug)]
+struct Buffers {
+    params: wgpu::Buffer,
+    pools: wgpu::Buffer,
+    vectors: wgpu::Buffer,
+    distances: wgpu::Buffer,
+    readback: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+/// A GPU computing the distances between the candidates of prune pools
+#[derive(Debug)]
+pub struct GpuDistance {
+    /// Name of the adapter, for the build logs
+    adapter_name: String,
+
+    device: wgpu::Device,
+
+    queue: wgpu::Queue,
+
+    pipeline: wgpu::ComputePipeline,
+
+    /// Largest storage buffer a shader can bind
+    max_binding_size: u64,
+
+    /// Largest number of pools of a dispatch, one z slice each
+    max_pools: usize,
+
+    buffers: Mutex<Option<Buffers>>,
+}
+
+impl GpuDistance {
+    /// Open the adapter wgpu prefers, None if the machine has none
+    pub fn new() -> ANNResult<Option<Self>> {
+        Self::open(false)
+    }
+
+    /// Open a software adapter, e.g. llvmpipe or WARP, None if the machine has none.
+    /// Slower than the CPU kernels, it lets machines without a GPU run the GPU path.
+    pub fn software() -> ANNResult<Option<Self>> {
+        Self::open(true)
+    }
+
+    fn open(force_fallback_adapter: bool) -> ANNResult<Option<Self>> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::from_env_or_default());
+        let adapter = match pollster::block_on(instance.request_adapter(
+            &wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                force_fallback_adapter,
+                compatible_surface: None,
+            },
+        )) {
+            Ok(adapter) => adapter,
+            Err(err) => {
+                info!("No GPU adapter, pruning distances stay on the CPU: {}", err);
+                return Ok(None);
+            }
+        };
+        let adapter_name = adapter.get_info().name;
+
+        let (device, queue) = pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor {
+            label: Some("diskann prune"),
+            required_limits: adapter.limits(),
+            ..Default::default()
+        }))
+        .map_err(|err| {
+            ANNError::log_index_error(format!(
+                "ERROR: Can't open GPU adapter {}: {}",
+                adapter_name, err
+            ))
+        })?;
+
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("pool triangles"),
+            source: wgpu::ShaderSource::Wgsl(POOL_TRIANGLE_SHADER.into()),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("pool triangles"),
+            layout: None,
+            module: &module,
+            entry_point: Some("pool_triangle"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+
+        let limits = device.limits();
+        Ok(Some(Self {
+            adapter_name,
+            max_binding_size: limits.max_storage_buffer_binding_size as u64,
+            max_pools: limits.max_compute_workgroups_per_dimension as usize,
+            device,
+            queue,
+            pipeline,
+            buffers: Mutex::new(None),
+        }))
+    }
+
+    /// Name of the adapter
+    pub fn adapter_name(&self) -> &str {
+        &self.adapter_name
+    }
+
+    /// Whether the GPU computes distances of the metric
+    pub fn supports(&self, metric: Metric) -> bool {
+        metric_code(metric).is_some()
+    }
+
+    /// Lower triangles of the distances between the candidates of each pool. The candidates of
+    /// all the pools are the vectors of dimension dim laid out one after the other, pool after
+    /// pool, and pool_sizes gives the number of candidates of each. The triangles follow each
+    /// other in pool order, the distance from candidate i to candidate j < i of a pool at
+    /// i * (i - 1) / 2 + j in its triangle. The pools are computed in as few dispatches as the
+    /// buffer limits of the device allow. None if a single pool is beyond them.
+    pub fn pool_distances(
+        &self,
+        vectors: &[f32],
+        pool_sizes: &[usize],
+        dim: usize,
+        metric: Metric,
+    ) -> ANNResult<Option<Vec<f32>>> {
+        if dim == 0 {
+            return Err(ANNError::log_index_error(
+                "ERROR: Can't compute the distances of vectors of dimension 0".to_string(),
+            ));
+        }
+        let metric = metric_code(metric).ok_or_else(|| {
+            ANNError::log_index_error(format!(
+                "ERROR: GPU doesn't compute {:?} distances",
+                metric
+            ))
+        })?;
+        if pool_sizes.iter().sum::<usize>() * dim != vectors.len() {
+            return Err(ANNError::log_index_error(format!(
+                "ERROR: {} pool elements for {} candidates of dimension {}",
+                vectors.len(),
+                pool_sizes.iter().sum::<usize>(),
+                dim
+            )));
+        }
+
+        let mut distances =
+            Vec::with_capacity(pool_sizes.iter().map(|&size| triangle_len(size)).sum());
+        let mut buffers = self
+            .buffers
+            .lock()
+            .map_err(|_| self.gpu_error("the buffers are poisoned"))?;
+        let mut first_pool = 0;
+        let mut vector_start = 0;
+        while first_pool < pool_sizes.len() {
+            // As many of the next pools as the buffers can bind
+            let mut num_pools = 0;
+            let mut num_vectors = 0;
+            let mut num_distances = 0;
+            for &size in &pool_sizes[first_pool..] {
+                let vectors_bytes = ((num_vectors + size) * dim * 4) as u64;
+                let distances_bytes = ((num_distances + triangle_len(size)) * 4) as u64;
+                if num_pools == self.max_pools
+                    || vectors_bytes > self.max_binding_size
+                    || distances_bytes > self.max_binding_size
+                {
+                    break;
+                }
+                num_pools += 1;
+                num_vectors += size;
+                num_distances += triangle_len(size);
+            }
+            if num_pools == 0 {
+                return Ok(None);
+            }
+
+            let pools = &pool_sizes[first_pool..first_pool + num_pools];
+            let batch_vectors = &vectors[vector_start..vector_start + num_vectors * dim];
+            self.dispatch(&mut buffers, batch_vectors, pools, dim, metric, &mut distances)?;
+            first_pool += num_pools;
+            vector_start += num_vectors * dim;
+        }
+
+        Ok(Some(distances))
+    }
+
+    /// Compute the triangles of pools that fit the buffers in one dispatch, appending them to
+    /// distances
+    fn dispatch(
+        &self,
+        buffers: &mut Option<Buffers>,
+        vectors: &[f32],
+        pool_sizes: &[usize],
+        dim: usize,
+        metric: u32,
+        distances: &mut Vec<f32>,
+    ) -> ANNResult<()> {
+        let mut pools = Vec::with_capacity(pool_sizes.len() * 4);
+        let (mut vector_offset, mut triangle_offset) = (0, 0);
+        for &size in pool_sizes {
+            pools.extend([vector_offset as u32, size as u32, triangle_offset as u32, 0]);
+            vector_offset += size * dim;
+            triangle_offset += triangle_len(size);
+        }
+        // The shader returns early on pools of fewer than 2 candidates, but each buffer binds
+        // at least one element
+        let distances_size = (triangle_offset.max(1) * 4) as u64;
+
+        let buffers = self.reserve(
+            buffers,
+            (pools.len() * 4) as u64,
+            (vectors.len().max(1) * 4) as u64,
+            distances_size,
+        );
+        self.queue.write_buffer(
+            &buffers.params,
+            0,
+            &to_bytes(&[dim as u32, metric, 0, 0], u32::to_le_bytes),
+        );
+        self.queue
+            .write_buffer(&buffers.pools, 0, &to_bytes(&pools, u32::to_le_bytes));
+        self.queue
+            .write_buffer(&buffers.vectors, 0, &to_bytes(vectors, f32::to_le_bytes));
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &buffers.bind_group, &[]);
+            let side = (*pool_sizes.iter().max().unwrap_or(&0) as u32).div_ceil(WORKGROUP_SIDE);
+            pass.dispatch_workgroups(side.max(1), side.max(1), pool_sizes.len() as u32);
+        }
+        encoder.copy_buffer_to_buffer(&buffers.distances, 0, &buffers.readback, 0, distances_size);
+        let submission = self.queue.submit(Some(encoder.finish()));
+
+        let (sender, receiver) = mpsc::channel();
+        let readback = buffers.readback.slice(..distances_size);
+        readback.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.device
+            .poll(wgpu::PollType::WaitForSubmissionIndex(submission))
+            .map_err(|err| self.gpu_error(err))?;
+        receiver
+            .recv()
+            .map_err(|err| self.gpu_error(err))?
+            .map_err(|err| self.gpu_error(err))?;
+
+        distances.extend(
+            readback
+                .get_mapped_range()
+                .chunks_exact(4)
+                .take(triangle_offset)
+                .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+        );
+        buffers.readback.unmap();
+        Ok(())
+    }
+
+    /// The buffers, grown to at least the given sizes
+    fn reserve<'a>(
+        &self,
+        buffers: &'a mut Option<Buffers>,
+        pools_size: u64,
+        vectors_size: u64,
+        distances_size: u64,
+    ) -> &'a Buffers {
+        let fits = |buffers: &Buffers| {
+            buffers.pools.size() >= pools_size
+                && buffers.vectors.size() >= vectors_size
+                && buffers.distances.size() >= distances_size
+        };
+        let reserved = match buffers.take() {
+            Some(current) if fits(&current) => current,
+            old => {
+                // Doubled past the request so batches growing one pool at a time don't
+                // reallocate on every dispatch
+                let grow = |current: Option<&wgpu::Buffer>, size: u64| {
+                    let size = size.max(current.map_or(0, |buffer| buffer.size()));
+                    (size.next_power_of_two()).min(self.max_binding_size.max(size))
+                };
+                let pools_size = grow(old.as_ref().map(|buffers| &buffers.pools), pools_size);
+                let vectors_size =
+                    grow(old.as_ref().map(|buffers| &buffers.vectors), vectors_size);
+                let distances_size =
+                    grow(old.as_ref().map(|buffers| &buffers.distances), distances_size);
+                self.create_buffers(pools_size, vectors_size, distances_size)
+            }
+        };
+        buffers.insert(reserved)
+    }
+
+    fn create_buffers(&self, pools_size: u64, vectors_size: u64, distances_size: u64) -> Buffers {
+        let buffer = |label, size, usage| {
+            self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size,
+                usage,
+                mapped_at_creation: false,
+            })
+        };
+        let storage = wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST;
+        let params = buffer(
+            "pool params",
+            PARAMS_SIZE,
+            wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        );
+        let pools = buffer("pools", pools_size.max(POOL_SIZE), storage);
+        let vectors = buffer("pool vectors", vectors_size, storage);
+        let distances = buffer(
+            "pool distances",
+            distances_size,
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        );
+        let readback = buffer(
+            "pool distances readback",
+            distances_size,
+            wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        );
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("pool triangles"),
+            layout: &self.pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: pools.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: vectors.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: distances.as_entire_binding(),
+                },
+            ],
+        });
+
+        Buffers {
+            params,
+            pools,
+            vectors,
+            distances,
+            readback,
+            bind_group,
+        }
+    }
+
+    fn gpu_error(&self, err: impl std::fmt::Display) -> ANNError {
+        ANNError::log_index_error(format!(
+            "ERROR: GPU {} failed to compute distances: {}",
+            self.adapter_name, err
+        ))
+    }
+}
+
+/// Little endian bytes of the values, as the shader reads them
+fn to_bytes<V: Copy>(values: &[V], value_bytes: fn(V) -> [u8; 4]) -> Vec<u8> {
+    values.iter().flat_map(|&value| value_bytes(value)).collect()
+}
+
+#[cfg(test)]
+mod gpu_distance_test {
+    use vector::{BuiltinDistance, Distance};
+
+    use super::*;
+
+    #[repr(C, align(32))]
+    struct F32Slice8([f32; 8]);
+
+    /// The software adapter, so machines without a GPU test the GPU path too. Builds with the
+    /// gpu feature need one, e.g. Mesa's llvmpipe.
+    fn software_gpu() -> GpuDistance {
+        GpuDistance::software()
+            .unwrap()
+            .expect("the gpu tests need a software adapter, e.g. Mesa's llvmpipe")
+    }
+
+    #[test]
+    fn shader_is_valid_wgsl() {
+        let module = wgpu::naga::front::wgsl::parse_str(POOL_TRIANGLE_SHADER).unwrap();
+        wgpu::naga::valid::Validator::new(
+            wgpu::naga::valid::ValidationFlags::all(),
+            wgpu::naga::valid::Capabilities::default(),
+        )
+        .validate(&module)
+        .unwrap();
+    }
+
+    #[test]
+    fn pool_distances_match_the_cpu() {
+        let gpu = software_gpu();
+
+        // Pools of 13, 1, 0 and 9 candidates, the empty and single ones without distances
+        let pool_sizes = [13, 1, 0, 9];
+        let pools: Vec<Vec<F32Slice8>> = pool_sizes
+            .iter()
+            .enumerate()
+            .map(|(pool, &size)| {
+                (0..size)
+                    .map(|i| {
+                        F32Slice8(std::array::from_fn(|k| {
+                            ((pool * 5 + i * 7 + k * 3) % 11) as f32 - 5.0
+                        }))
+                    })
+                    .collect()
+            })
+            .collect();
+        let flat: Vec<f32> = pools.iter().flatten().flat_map(|vector| vector.0).collect();
+
+        for metric in [Metric::L2, Metric::L1, Metric::Chebyshev, Metric::Cosine] {
+            let distances = gpu.pool_distances(&flat, &pool_sizes, 8, metric).unwrap().unwrap();
+            assert_eq!(distances.len(), triangle_len(13) + triangle_len(9));
+
+            let mut triangle_start = 0;
+            for pool in &pools {
+                let triangle = &distances[triangle_start..triangle_start + triangle_len(pool.len())];
+                for (i, a) in pool.iter().enumerate() {
+                    for (j, b) in pool.iter().enumerate().take(i) {
+                        let expected = BuiltinDistance.distance(&a.0, &b.0, metric);
+                        let actual = triangle_distance(triangle, i, j);
+                        assert!(
+                            (actual - expected).abs() <= 1e-4 * expected.abs().max(1.0),
+                            "{:?} distance of {} and {} is {}, the CPU's {}",
+                            metric,
+                            i,
+                            j,
+                            actual,
+                            expected
+                        );
+                    }
+                }
+                triangle_start += triangle_len(pool.len());
+            }
+        }
+    }
+
+    #[test]
+    fn pool_distances_reuse_and_grow_the_buffers() {
+        let gpu = software_gpu();
+        let vectors: Vec<f32> = (0..40 * 4).map(|value| value as f32).collect();
+
+        let small = gpu.pool_distances(&vectors[..8], &[2], 4, Metric::L1).unwrap().unwrap();
+        assert_eq!(small, vec![16.0]);
+        let size = gpu.buffers.lock().unwrap().as_ref().unwrap().distances.size();
+
+        // A larger batch grows the buffers, a smaller one after it reuses them
+        let large = gpu.pool_distances(&vectors, &[40], 4, Metric::L1).unwrap().unwrap();
+        assert_eq!(large.len(), triangle_len(40));
+        assert_eq!(triangle_distance(&large, 39, 0), 39.0 * 16.0);
+        let grown = gpu.buffers.lock().unwrap().as_ref().unwrap().distances.size();
+        assert!(grown > size);
+        let again = gpu.pool_distances(&vectors[..8], &[2], 4, Metric::L1).unwrap().unwrap();
+        assert_eq!(again, small);
+        assert_eq!(gpu.buffers.lock().unwrap().as_ref().unwrap().distances.size(), grown);
+    }
+
+    #[test]
+    fn pool_distances_reject_what_the_gpu_cant_compute() {
+        let gpu = software_gpu();
+        assert!(gpu.pool_distances(&[], &[0], 0, Metric::L2).is_err());
+        assert!(gpu.pool_distances(&[0.0; 8], &[2], 4, Metric::Hamming).is_err());
+        assert!(gpu.pool_distances(&[0.0; 8], &[3], 4, Metric::L2).is_err());
+        assert!(!gpu.supports(Metric::Tanimoto));
+        assert!(gpu.supports(Metric::Cosine));
+    }
+}
@@ -2,29 +2,84 @@
  * Copyright (c) Microsoft Corporation. All rights reserved.
  * Licensed under the MIT license.
  */
+//! In-memory Vamana index.
+//!
+//! # Concurrency
+//!
+//! Methods taking `&self` (the searches, [`InmemIndex::insert_point`],
+//! [`InmemIndex::delete_point`] and [`InmemIndex::warm_up`]) may run at the same time from any
+//! number of threads, so a live index serves queries without a read-only copy. Methods taking
+//! `&mut self` (builds, batch inserts and deletes, compaction, save and load) run alone.
+//!
+//! The adjacency lists share a fixed set of striped locks, list i taking stripe
+//! i % NUM_LOCK_STRIPES. A search copies a list out under its read lock and computes distances
+//! after releasing it, and a thread holds the lock of one list at a time, so threads never
+//! wait on each other in a cycle. The consistency model that follows:
+//! - Each list is seen either before or after any one update, never partly written, but a
+//!   search running alongside inserts may see different lists at different times.
+//! - A point's vector is written before any list refers to it, and searches reach the point
+//!   once the first list refers to it. A point whose insert_point returned before a search
+//!   started is reachable by that search, with the usual approximation of a graph search.
+//! - A search that starts after delete_point returned never returns the deleted point. The
+//!   point stays in the graph and keeps routing searches until the deletes are consolidated.
+//! - Concurrent inserts may each prune a shared neighbor's list from the same copy, the last
+//!   write wins and the other back edge is dropped, costing a little recall but no validity.
+
+use std::borrow::Cow;
 use std::cmp;
-use std::sync::RwLock;
+use std::collections::VecDeque;
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::Duration;
 
 use hashbrown::hash_set::Entry::*;
 use hashbrown::HashSet;
-use vector::FullPrecisionDistance;
+use log::{info, warn};
+use vector::{kernel_report, BuiltinDistance, Distance, FullPrecisionDistance};
 
+use crate::algorithm::search::search::{QueryComparison, SearchLimits, SearchVisitor};
 use crate::common::{ANNError, ANNResult};
-use crate::index::ANNInmemIndex;
-use crate::instrumentation::IndexLogger;
-use crate::model::graph::AdjacencyList;
+use crate::index::{
+    ANNInmemIndex, IndexEventNotifier, SearchListCalibration, WalRecord, WriteAheadLog,
+};
+use crate::index::inmem_index::WrittenSlots;
+#[cfg(feature = "gpu")]
+use crate::index::inmem_index::gpu_distance::triangle_len;
+#[cfg(feature = "gpu")]
+use crate::index::GpuDistance;
+use crate::instrumentation::{IndexLogger, ProgressNotifier};
+use crate::model::data_store::{
+    check_prune_quantization, DatasetSource, DocumentAggregation, DocumentStore, LabelFilter,
+    PointMetadataStore, QuantizedPruneVectors, Tag, TagStore,
+};
+use crate::model::graph::{
+    AdjacencyList, ArenaGraph, GraphExportFormat, GraphExportSummary, GraphExporter,
+};
+use crate::instrumentation::QueryStats;
+#[cfg(feature = "gpu")]
+use crate::model::neighbor::SortedNeighborVector;
 use crate::model::{
-    ArcConcurrentBoxedQueue, InMemQueryScratch, InMemoryGraph, IndexConfiguration, InmemDataset,
-    Neighbor, ScratchStoreManager, Vertex,
+    ArcConcurrentBoxedQueue, DocumentMatch, InMemQueryScratch, InMemoryGraph, IndexConfiguration,
+    InmemDataset, Neighbor, NeighborPriorityQueue, ScratchStoreManager, SearchParams, SearchResult,
+    SearchResultFields, Vertex,
+};
+
+use crate::storage::StorageProvider;
+use crate::utils::file_util::{
+    copy_aligned_data_from_file, file_exists, load_metadata_from_file, lock_index_input,
+    lock_index_output,
 };
+use crate::utils::rayon_util::{execute_with_rayon, install_on_pool};
+use crate::utils::{step_seed, RandomStep, Timer};
 
-use crate::utils::file_util::{file_exists, load_metadata_from_file};
-use crate::utils::rayon_util::execute_with_rayon;
-use crate::utils::{set_rayon_num_threads, Timer};
+/// Most vertices the GPU link phase searches before comparing their pools in one dispatch
+#[cfg(feature = "gpu")]
+const GPU_LINK_BATCH_SIZE: usize = 128;
 
-/// In-memory Index
-pub struct InmemIndex<T, const N: usize>
+/// In-memory Index, comparing vectors with the built-in metrics unless created with a
+/// user-defined Distance
+pub struct InmemIndex<T, const N: usize, D = BuiltinDistance>
 where
     [T; N]: FullPrecisionDistance<T, N>,
 {
@@ -42,16 +97,66 @@ where
     /// location of one of the points in index.
     pub start: u32,
 
+    /// Points other than start searches also start from, picked with start by the entry point
+    /// selection of the configuration
+    pub entry_points: Vec<u32>,
+
     /// Max observed out degree
     pub max_observed_degree: u32,
 
     /// Number of active points i.e. existing in the graph
     pub num_active_pts: usize,
 
+    /// Points inserted by insert_point after the active points, counted into num_active_pts
+    /// by the next change taking the index mutably
+    streamed_pts: AtomicUsize,
+
+    /// Streamed points whose vectors are copied in full. A slot is reserved before its vector
+    /// is copied, so scans over the points stop after the leading ones.
+    written_slots: WrittenSlots,
+
+    /// Whether the index is below the brute force threshold, its graph unlinked
+    brute_force: bool,
+
     /// query scratch queue.
     query_scratch_queue: ArcConcurrentBoxedQueue<InMemQueryScratch<T, N>>,
 
     pub delete_set: RwLock<HashSet<u32>>,
+
+    /// Labels and payload attached to points
+    pub point_metadata: PointMetadataStore,
+
+    /// External tags of the points, returned by search_tags in place of vertex ids
+    pub tag_store: RwLock<TagStore>,
+
+    /// Documents the points are vectors of, searched by search_documents
+    pub documents: DocumentStore,
+
+    /// Distance between two vectors
+    pub distance: D,
+
+    /// Compressed vectors the prune of a build compares, set while the graph is linked
+    pub(crate) prune_vectors: Option<QuantizedPruneVectors<N>>,
+
+    /// Packed graph searched instead of final_graph after compact_graph, until the index changes
+    pub arena_graph: Option<ArenaGraph>,
+
+    /// Publishes loads and deletions to the subscribers caching results of the index
+    pub event_notifier: Option<Arc<IndexEventNotifier>>,
+
+    /// Publishes the progress of builds, inserts and deletes to the subscribers tracking them
+    pub progress_notifier: Option<Arc<ProgressNotifier>>,
+
+    /// Log the streamed inserts and deletes are written to before they are applied
+    write_ahead_log: Option<Mutex<WriteAheadLog<T>>>,
+
+    /// Store the nodes insert_point adds or links to are written through to
+    pub(crate) node_store: Option<Mutex<Box<dyn StorageProvider<T> + Send>>>,
+
+    #[cfg(feature = "gpu")]
+    /// GPU the prune computes the distances between candidates on, only set on the built-in
+    /// distance since it computes the built-in metrics
+    pub(crate) gpu_distance: Option<Arc<GpuDistance>>,
 }
 
 impl<T, const N: usize> InmemIndex<T, N>
@@ -60,13 +165,45 @@ where
     [T; N]: FullPrecisionDistance<T, N>,
 {
     /// Create Index obj based on configuration
-    pub fn new(mut config: IndexConfiguration) -> ANNResult<Self> {
+    pub fn new(config: IndexConfiguration) -> ANNResult<Self> {
+        let index = Self::with_distance(config, BuiltinDistance)?;
+        #[cfg(feature = "gpu")]
+        let index = Self {
+            gpu_distance: index.configuration.gpu_distance.clone(),
+            ..index
+        };
+        Ok(index)
+    }
+}
+
+impl<T, const N: usize, D> InmemIndex<T, N, D>
+where
+    T: Default + Copy + Sync + Send + Into<f32>,
+    [T; N]: FullPrecisionDistance<T, N>,
+    D: Distance<T, N>,
+{
+    /// Create Index obj based on configuration, comparing vectors with the given distance
+    pub fn with_distance(mut config: IndexConfiguration, distance: D) -> ANNResult<Self> {
         // Sanity check. While logically it is correct, max_points = 0 causes
         // downstream problems.
         if config.max_points == 0 {
             config.max_points = 1;
         }
 
+        // Binary metrics work on packed bits, so each element must be a byte of the binary code
+        if config.dist_metric.is_binary() && std::mem::size_of::<T>() != 1 {
+            return Err(ANNError::log_index_config_error(
+                "dist_metric".to_string(),
+                format!(
+                    "{:?} distance requires packed binary vectors of u8 or i8",
+                    config.dist_metric
+                ),
+            ));
+        }
+
+        config.index_write_parameter.validate()?;
+        check_prune_quantization(&config)?;
+
         let total_internal_points = config.max_points + config.num_frozen_pts;
 
         if config.use_pq_dist {
@@ -85,26 +222,163 @@ where
                 total_internal_points,
                 config.index_write_parameter.max_degree,
             ),
+            point_metadata: PointMetadataStore::new(config.max_points),
+            tag_store: RwLock::new(TagStore::new(config.max_points)),
+            documents: DocumentStore::new(config.max_points),
+            written_slots: WrittenSlots::new(config.max_points),
             configuration: config,
             start,
+            entry_points: Vec::new(),
             max_observed_degree: 0,
             num_active_pts: 0,
+            streamed_pts: AtomicUsize::new(0),
+            brute_force: false,
             query_scratch_queue,
             delete_set,
+            distance,
+            prune_vectors: None,
+            arena_graph: None,
+            event_notifier: None,
+            progress_notifier: None,
+            write_ahead_log: None,
+            node_store: None,
+            #[cfg(feature = "gpu")]
+            gpu_distance: None,
         })
     }
 
+    /// Whether searches compare the query to every point instead of traversing the graph,
+    /// because the index holds fewer points than configuration.brute_force_threshold
+    pub fn is_brute_force(&self) -> bool {
+        self.brute_force
+    }
+
+    /// Number of points searches can return, including the ones being inserted by insert_point
+    pub(crate) fn num_searchable_pts(&self) -> usize {
+        self.num_active_pts + self.streamed_pts.load(Ordering::Acquire)
+    }
+
+    /// Number of leading points whose vectors can be read, the ones being inserted by
+    /// insert_point included once their vectors are copied
+    pub(crate) fn num_written_pts(&self) -> usize {
+        self.num_active_pts + self.written_slots.len()
+    }
+
+    /// Mark the vector of the streamed point vertex_id as copied. It counts as written once
+    /// the points streamed before it are, so the written points stay a prefix of the slots.
+    fn publish_written_vector(&self, vertex_id: u32) {
+        self.written_slots.publish(vertex_id as usize - self.num_active_pts);
+    }
+
+    /// Forget the points streamed by insert_point, returning how many there were, and make
+    /// room for as many as max_points
+    pub(super) fn reset_streamed_points(&mut self) -> usize {
+        self.written_slots.reset(self.configuration.max_points);
+        std::mem::take(self.streamed_pts.get_mut())
+    }
+
+    /// Publish the loads and deletions of the index with the given notifier
+    pub fn set_event_notifier(&mut self, notifier: Arc<IndexEventNotifier>) {
+        self.event_notifier = Some(notifier);
+    }
+
+    /// Publish the progress of builds, inserts and deletes with the given notifier
+    pub fn set_progress_notifier(&mut self, notifier: Arc<ProgressNotifier>) {
+        self.progress_notifier = Some(notifier);
+    }
+
+    /// Pack the graph into an arena for searching, releasing the per-vertex lists.
+    /// Building, inserting, deleting or loading unpacks it again first.
+    pub fn compact_graph(&mut self) -> ANNResult<()> {
+        self.absorb_streamed_points();
+        if self.arena_graph.is_none() {
+            self.arena_graph = Some(ArenaGraph::from_graph(&self.final_graph)?);
+            self.final_graph =
+                InMemoryGraph::new(0, self.configuration.index_write_parameter.max_degree);
+        }
+        Ok(())
+    }
+
+    /// Reset the points for a load of expected_num_points points counting the frozen points,
+    /// returns the number of active points
+    fn start_load(&mut self, expected_num_points: usize) -> ANNResult<usize> {
+        self.expand_graph()?;
+        self.reset_streamed_points();
+        let num_points = expected_num_points
+            .checked_sub(self.configuration.num_frozen_pts)
+            .ok_or_else(|| {
+                ANNError::log_index_config_error(
+                    "expected_num_points".to_string(),
+                    format!(
+                        "{} points can't include the {} frozen points",
+                        expected_num_points, self.configuration.num_frozen_pts
+                    ),
+                )
+            })?;
+        self.num_active_pts = num_points;
+        self.brute_force = num_points < self.configuration.brute_force_threshold;
+        Ok(num_points)
+    }
+
+    /// Make a loaded index ready to search
+    fn finish_load(&mut self) -> ANNResult<()> {
+        if self.query_scratch_queue.size()? == 0 {
+            self.initialize_query_scratch(
+                5 + self.configuration.index_write_parameter.num_threads,
+                self.configuration.index_write_parameter.search_list_size,
+            )?;
+        }
+
+        if let Some(notifier) = &self.event_notifier {
+            notifier.notify_version_swap();
+        }
+
+        Ok(())
+    }
+
+    /// Unpack the arena graph, if any, so the graph can be modified
+    fn expand_graph(&mut self) -> ANNResult<()> {
+        if let Some(arena_graph) = self.arena_graph.take() {
+            self.final_graph =
+                arena_graph.to_graph(self.configuration.index_write_parameter.max_degree)?;
+        }
+        Ok(())
+    }
+
+    /// Copy the out neighbors of a vertex from whichever graph layout is in use into
+    /// neighbors, so no lock is held while they are used
+    #[inline(always)]
+    pub(crate) fn copy_neighbors(&self, vertex_id: u32, neighbors: &mut Vec<u32>) -> ANNResult<()> {
+        match &self.arena_graph {
+            Some(arena_graph) => {
+                neighbors.clear();
+                neighbors.extend_from_slice(arena_graph.neighbors(vertex_id));
+                Ok(())
+            }
+            None => self.final_graph.copy_neighbors(vertex_id, neighbors),
+        }
+    }
+
     /// Get distance between two vertices.
     pub fn get_distance(&self, id1: u32, id2: u32) -> ANNResult<f32> {
-        self.dataset
-            .get_distance(id1, id2, self.configuration.dist_metric)
+        let vertex1 = self.dataset.get_vertex(id1)?;
+        let vertex2 = self.dataset.get_vertex(id2)?;
+        Ok(self.compare_vertices(&vertex1, &vertex2))
+    }
+
+    /// Distance between two vertices under the index distance and metric
+    #[inline(always)]
+    pub(crate) fn compare_vertices(&self, a: &Vertex<'_, T, N>, b: &Vertex<'_, T, N>) -> f32 {
+        self.distance
+            .distance(a.vector(), b.vector(), self.configuration.dist_metric)
     }
 
     fn build_with_data_populated(&mut self) -> ANNResult<()> {
-        println!(
+        info!(
             "Starting index build with {} points...",
             self.num_active_pts
         );
+        info!("Distance kernels: {}", kernel_report());
 
         if self.num_active_pts < 1 {
             return Err(ANNError::log_index_error(
@@ -119,6 +393,15 @@ where
             )?;
         }
 
+        self.brute_force = self.num_active_pts < self.configuration.brute_force_threshold;
+        if self.brute_force {
+            info!(
+                "Skipping graph build, {} points are below the brute force threshold of {}",
+                self.num_active_pts, self.configuration.brute_force_threshold
+            );
+            return Ok(());
+        }
+
         // TODO: generate_frozen_point()
 
         self.link()?;
@@ -128,169 +411,1133 @@ where
         Ok(())
     }
 
-    fn link(&mut self) -> ANNResult<()> {
-        // visit_order is a vector that is initialized to the entire graph
+    /// Link the points the dataset appended after the active points into the graph
+    fn insert_appended_points(&mut self, num_points_to_insert: usize) -> ANNResult<()> {
+        if self.query_scratch_queue.size()? == 0 {
+            self.initialize_query_scratch(
+                5 + self.configuration.index_write_parameter.num_threads,
+                self.configuration.index_write_parameter.search_list_size,
+            )?;
+        }
+
+        self.configuration.start_thread_pool()?;
+
+        self.final_graph.extend(
+            num_points_to_insert,
+            self.configuration.index_write_parameter.max_degree,
+        );
+
+        // TODO: this should not consider frozen points
+        let previous_last_pt = self.num_active_pts;
+        self.num_active_pts += num_points_to_insert;
+        self.configuration.max_points += num_points_to_insert;
+
+        if self.brute_force {
+            return self.link_if_above_brute_force_threshold();
+        }
+
+        // TODO: tag_lock
+        let logger =
+            IndexLogger::new(num_points_to_insert).with_progress(self.progress_notifier.clone());
+        let timer = Timer::new();
+        self.execute_parallel(previous_last_pt..self.num_active_pts, |idx| {
+            self.insert_vertex_id(idx as u32)?;
+            logger.vertex_processed()?;
+
+            Ok(())
+        })?;
+
         let mut visit_order =
             Vec::with_capacity(self.num_active_pts + self.configuration.num_frozen_pts);
         for i in 0..self.num_active_pts {
             visit_order.push(i as u32);
         }
 
-        // If there are any frozen points, add them all.
-        for frozen in self.configuration.max_points
-            ..(self.configuration.max_points + self.configuration.num_frozen_pts)
-        {
-            visit_order.push(frozen as u32);
-        }
+        self.cleanup_graph(&visit_order)?;
+        info!("{}", timer.elapsed_seconds_for_step("Insert time: "));
 
-        // if there are frozen points, the first such one is set to be the _start
-        if self.configuration.num_frozen_pts > 0 {
-            self.start = self.configuration.max_points as u32;
-        } else {
-            self.start = self.dataset.calculate_medoid_point_id()?;
-        }
+        self.print_stats()?;
 
-        let timer = Timer::new();
+        Ok(())
+    }
 
-        let range = visit_order.len();
-        let logger = IndexLogger::new(range);
 
-        execute_with_rayon(
-            0..range,
-            self.configuration.index_write_parameter.num_threads,
-            |idx| {
-                self.insert_vertex_id(visit_order[idx])?;
-                logger.vertex_processed()?;
 
-                Ok(())
-            },
-        )?;
+    /// Insert one point while other threads search or insert, returning its id.
+    /// The point takes the next free slot below max_points, its neighbors are found by the same
+    /// search and robust pruning as a build, and the adjacency lists it joins are each updated
+    /// under the lock of their stripe, so searches reach the point once the first back edge is
+    /// written.
+    pub fn insert_point(&self, vector: &[T]) -> ANNResult<u32> {
+        if vector.len() != self.configuration.dim {
+            return Err(ANNError::log_index_error(format!(
+                "ERROR: Vector has {} dimensions, but index has {} dimensions.",
+                vector.len(),
+                self.configuration.dim
+            )));
+        }
+        if self.arena_graph.is_some() {
+            return Err(ANNError::log_index_error(
+                "ERROR: Cannot insert points into a compacted graph.".to_string(),
+            ));
+        }
+        if self.num_active_pts == 0 || self.query_scratch_queue.size()? == 0 {
+            return Err(ANNError::log_index_error(
+                "ERROR: Index must be built or loaded before inserting points.".to_string(),
+            ));
+        }
 
-        self.cleanup_graph(&visit_order)?;
+        let vertex_id = self.reserve_vertex_id(vector)?;
 
-        if self.num_active_pts > 0 {
-            println!("{}", timer.elapsed_seconds_for_step("Link time: "));
+        // SAFETY: the slot was reserved above and no adjacency list refers to it yet
+        let written = unsafe { self.dataset.write_unpublished_vector(vertex_id, vector) };
+        // A slot that couldn't be written is deleted, and published all the same so the
+        // points streamed after it are
+        let deleted = match written {
+            Ok(()) => Ok(()),
+            Err(_) => self.soft_delete_vertex(vertex_id),
+        };
+        self.publish_written_vector(vertex_id);
+        written?;
+        deleted?;
+
+        // A brute force index links its points once a change taking it mutably finds it grown
+        if !self.brute_force {
+            self.insert_vertex_id(vertex_id)?;
+        } else {
+            self.write_through(vertex_id)?;
         }
 
-        Ok(())
+        Ok(vertex_id)
     }
 
-    fn insert_vertex_id(&self, vertex_id: u32) -> ANNResult<()> {
-        let mut scratch_manager =
-            ScratchStoreManager::new(self.query_scratch_queue.clone(), Duration::from_millis(10))?;
-        let scratch = scratch_manager.scratch_space().ok_or_else(|| {
-            ANNError::log_index_error(
-                "ScratchStoreManager doesn't have InMemQueryScratch instance available".to_string(),
-            )
-        })?;
-
-        let new_neighbors = self.search_for_point_and_prune(scratch, vertex_id)?;
-        self.update_vertex_with_neighbors(vertex_id, new_neighbors)?;
-        self.update_neighbors_of_vertex(vertex_id, scratch)?;
+    /// Take the next free slot for vector, logging the insert first when there is a log.
+    /// The log is locked across both, so it holds the inserts in the order of their ids.
+    fn reserve_vertex_id(&self, vector: &[T]) -> ANNResult<u32> {
+        let max_points = self.configuration.max_points;
+        let full = || {
+            ANNError::log_index_error(format!("ERROR: Index is full with {} points.", max_points))
+        };
 
-        Ok(())
+        match &self.write_ahead_log {
+            Some(write_ahead_log) => {
+                let mut write_ahead_log = write_ahead_log.lock().map_err(|_| {
+                    ANNError::log_lock_poison_error(
+                        "Poisoned lock on the write-ahead log. Can't insert point.".to_string(),
+                    )
+                })?;
+                let streamed_pts = self.streamed_pts.load(Ordering::Acquire);
+                if self.num_active_pts + streamed_pts >= max_points {
+                    return Err(full());
+                }
+                let vertex_id: u32 = (self.num_active_pts + streamed_pts).try_into()?;
+                write_ahead_log.append_insert(vertex_id, vector)?;
+                self.streamed_pts.store(streamed_pts + 1, Ordering::Release);
+                Ok(vertex_id)
+            }
+            None => {
+                let streamed_pts = self
+                    .streamed_pts
+                    .fetch_update(Ordering::AcqRel, Ordering::Acquire, |streamed_pts| {
+                        (self.num_active_pts + streamed_pts < max_points)
+                            .then_some(streamed_pts + 1)
+                    })
+                    .map_err(|_| full())?;
+                Ok((self.num_active_pts + streamed_pts).try_into()?)
+            }
+        }
     }
 
-    fn update_neighbors_of_vertex(
-        &self,
-        vertex_id: u32,
-        scratch: &mut InMemQueryScratch<T, N>,
-    ) -> Result<(), ANNError> {
-        let vertex = self.final_graph.read_vertex_and_neighbors(vertex_id)?;
-        assert!(vertex.size() <= self.configuration.index_write_parameter.max_degree as usize);
-        self.inter_insert(
-            vertex_id,
-            vertex.get_neighbors(),
-            self.configuration.index_write_parameter.max_degree,
-            scratch,
+    /// Replay the log at wal_file onto the index, built or loaded from the snapshot the log
+    /// continues, then log the streamed inserts and deletes to it until the next save, which
+    /// empties it. Inserts the snapshot already holds are skipped. Returns the number of
+    /// records replayed.
+    /// Batch changes aren't logged, so builds, batch inserts, merges and loads are refused
+    /// while the log is open. Records are synced as configuration.wal_sync_policy says.
+    pub fn open_write_ahead_log(&mut self, wal_file: &str) -> ANNResult<usize> {
+        self.check_no_write_ahead_log("open a write-ahead log")?;
+        if self.num_active_pts == 0 || self.query_scratch_queue.size()? == 0 {
+            return Err(ANNError::log_index_error(
+                "ERROR: Index must be built or loaded before its write-ahead log is opened."
+                    .to_string(),
+            ));
+        }
+
+        let (write_ahead_log, records) = WriteAheadLog::open(
+            wal_file,
+            self.configuration.dim,
+            self.configuration.wal_sync_policy,
         )?;
+        self.absorb_streamed_points();
+        let mut num_replayed = 0;
+        for record in records {
+            match record {
+                WalRecord::Insert { vertex_id, vector } => {
+                    let next_vertex_id = self.num_searchable_pts();
+                    if (vertex_id as usize) < next_vertex_id {
+                        continue;
+                    }
+                    if vertex_id as usize > next_vertex_id {
+                        return Err(ANNError::log_index_error(format!(
+                            "ERROR: Write-ahead log {} inserts point {} but the index has {} points, it doesn't continue this snapshot.",
+                            wal_file, vertex_id, next_vertex_id
+                        )));
+                    }
+                    self.insert_point(&vector)?;
+                }
+                WalRecord::Delete { vertex_id } => self.soft_delete_vertex(vertex_id)?,
+            }
+            num_replayed += 1;
+        }
+        self.absorb_streamed_points();
+
+        info!(
+            "Replayed {} records of write-ahead log {}",
+            num_replayed, wal_file
+        );
+        self.write_ahead_log = Some(Mutex::new(write_ahead_log));
+        Ok(num_replayed)
+    }
+
+    /// Sync the records of the write-ahead log its sync policy left unsynced, if a log is open
+    pub fn sync_write_ahead_log(&self) -> ANNResult<()> {
+        if let Some(write_ahead_log) = &self.write_ahead_log {
+            write_ahead_log
+                .lock()
+                .map_err(|_| {
+                    ANNError::log_lock_poison_error(
+                        "Poisoned lock on the write-ahead log. Can't sync it.".to_string(),
+                    )
+                })?
+                .sync()?;
+        }
         Ok(())
     }
 
-    fn update_vertex_with_neighbors(
-        &self,
-        vertex_id: u32,
-        new_neighbors: AdjacencyList,
-    ) -> Result<(), ANNError> {
-        let vertex = &mut self.final_graph.write_vertex_and_neighbors(vertex_id)?;
-        vertex.set_neighbors(new_neighbors);
-        assert!(vertex.size() <= self.configuration.index_write_parameter.max_degree as usize);
+    /// Refuse a change the write-ahead log can't record while it is open
+    fn check_no_write_ahead_log(&self, operation: &str) -> ANNResult<()> {
+        if self.write_ahead_log.is_some() {
+            return Err(ANNError::log_index_error(format!(
+                "ERROR: Can't {} while a write-ahead log is open.",
+                operation
+            )));
+        }
         Ok(())
     }
 
-    fn search_for_point_and_prune(
-        &self,
-        scratch: &mut InMemQueryScratch<T, N>,
-        vertex_id: u32,
-    ) -> ANNResult<AdjacencyList> {
-        let mut pruned_list =
-            AdjacencyList::for_range(self.configuration.index_write_parameter.max_degree as usize);
-        let vertex = self.dataset.get_vertex(vertex_id)?;
-        let mut visited_nodes = self.search_for_point(&vertex, scratch)?;
+    /// Delete one point while other threads search or insert. Searches starting after it
+    /// returns skip the point, which stays in the graph until the deletes are consolidated.
+    pub fn delete_point(&self, vertex_id: u32) -> ANNResult<()> {
+        self.soft_delete_vertex(vertex_id)?;
+        if let Some(notifier) = &self.event_notifier {
+            notifier.notify_deletion(vec![vertex_id]);
+        }
+        Ok(())
+    }
 
-        self.prune_neighbors(vertex_id, &mut visited_nodes, &mut pruned_list, scratch)?;
+    /// Insert one point like insert_point and tag it, returning its id. Fails before inserting
+    /// if another point has the tag. The write-ahead log doesn't record tags, so tagged inserts
+    /// are refused while one is open.
+    pub fn insert_point_with_tag(&self, vector: &[T], tag: Tag) -> ANNResult<u32> {
+        self.check_no_write_ahead_log("insert a tagged point")?;
+        if let Some(owner) = self.read_tag_store()?.vertex_id(&tag) {
+            return Err(ANNError::log_index_error(format!(
+                "ERROR: Tag {} already belongs to point {}.",
+                tag, owner
+            )));
+        }
 
-        if pruned_list.is_empty() {
-            return Err(ANNError::log_index_error(
-                "pruned_list is empty.".to_string(),
-            ));
+        let vertex_id = self.insert_point(vector)?;
+        // Another insert may have taken the tag since the check, then the point goes again
+        if let Err(err) = self.write_tag_store()?.set_tag(vertex_id, tag) {
+            self.delete_point(vertex_id)?;
+            return Err(err);
         }
+        Ok(vertex_id)
+    }
 
-        if self.final_graph.size()
-            != self.configuration.max_points + self.configuration.num_frozen_pts
-        {
+    /// Tag a point of the index, replacing its existing tag
+    pub fn set_point_tag(&self, vertex_id: u32, tag: Tag) -> ANNResult<()> {
+        if vertex_id as usize >= self.num_searchable_pts() {
             return Err(ANNError::log_index_error(format!(
-                "final_graph has {} vertices instead of {}",
-                self.final_graph.size(),
-                self.configuration.max_points + self.configuration.num_frozen_pts,
+                "vertex_id {} is out of valid range of points {}",
+                vertex_id,
+                self.num_searchable_pts()
             )));
         }
+        self.write_tag_store()?.set_tag(vertex_id, tag)
+    }
 
-        Ok(pruned_list)
+    /// Delete the point with a tag like delete_point and free the tag, returning the point's id
+    pub fn delete_tag(&self, tag: &Tag) -> ANNResult<u32> {
+        let mut tag_store = self.write_tag_store()?;
+        let vertex_id = tag_store.vertex_id(tag).ok_or_else(|| {
+            ANNError::log_index_error(format!("ERROR: No point has tag {}.", tag))
+        })?;
+        self.delete_point(vertex_id)?;
+        tag_store.remove_tag(vertex_id);
+        Ok(vertex_id)
     }
 
-    fn search(
+    /// Search the index for K nearest neighbors of query and return their tags, nearest first.
+    /// Fails if a neighbor found has no tag.
+    pub fn search_tags(
         &self,
         query: &Vertex<T, N>,
         k_value: usize,
         l_value: u32,
-        indices: &mut [u32],
-    ) -> ANNResult<u32> {
-        if k_value > l_value as usize {
-            return Err(ANNError::log_index_error(format!(
-                "Set L: {} to a value of at least K: {}",
-                l_value, k_value
-            )));
-        }
+    ) -> ANNResult<Vec<Tag>> {
+        let (neighbors, _) = self.search_neighbors(
+            query,
+            k_value,
+            l_value,
+            &QueryComparison::Full,
+            None,
+            SearchLimits::default(),
+            None,
+        )?;
 
-        let mut scratch_manager =
-            ScratchStoreManager::new(self.query_scratch_queue.clone(), Duration::from_millis(10))?;
+        let tag_store = self.read_tag_store()?;
+        neighbors
+            .iter()
+            .map(|neighbor| {
+                tag_store.tag(neighbor.id).cloned().ok_or_else(|| {
+                    ANNError::log_index_error(format!(
+                        "ERROR: Point {} found by the search has no tag.",
+                        neighbor.id
+                    ))
+                })
+            })
+            .collect()
+    }
 
-        let scratch = scratch_manager.scratch_space().ok_or_else(|| {
-            ANNError::log_index_error(
-                "ScratchStoreManager doesn't have InMemQueryScratch instance available".to_string(),
+    /// Search the index for the K documents nearest to query, each ranked by its distances to
+    /// the query combined by aggregation. The L nearest points found pick the candidate
+    /// documents, which are then scored over all their points not deleted, so each document is
+    /// returned once. Points of no document are skipped, and fewer than K documents are
+    /// returned when the L points span fewer.
+    pub fn search_documents(
+        &self,
+        query: &Vertex<T, N>,
+        k_value: usize,
+        l_value: u32,
+        aggregation: DocumentAggregation,
+    ) -> ANNResult<Vec<DocumentMatch>> {
+        let (neighbors, _) = self.search_neighbors(
+            query,
+            l_value as usize,
+            l_value,
+            &QueryComparison::Full,
+            None,
+            SearchLimits::default(),
+            None,
+        )?;
+
+        let delete_set = self.delete_set.read().map_err(|_| {
+            ANNError::log_lock_poison_error(
+                "failed to acquire the lock for delete_set.".to_string(),
             )
         })?;
+        let mut scored = HashSet::new();
+        let mut matches = Vec::new();
+        for neighbor in neighbors.iter() {
+            let document = match self.documents.document(neighbor.id) {
+                Some(document) => document,
+                None => continue,
+            };
+            if !scored.insert(document) {
+                continue;
+            }
 
-        if l_value > scratch.candidate_size {
-            println!("Attempting to expand query scratch_space. Was created with Lsize: {} but search L is: {}", scratch.candidate_size, l_value);
-            scratch.resize_for_new_candidate_size(l_value);
-            println!(
+            let mut best = Neighbor::new(neighbor.id, f32::MAX);
+            let mut sum = 0.0;
+            let mut count = 0;
+            for &member in self.documents.members(document) {
+                if delete_set.contains(&member) {
+                    continue;
+                }
+                let distance = self.compare_vertices(query, &self.dataset.get_vertex(member)?);
+                if distance < best.distance {
+                    best = Neighbor::new(member, distance);
+                }
+                sum += distance;
+                count += 1;
+            }
+
+            let distance = match aggregation {
+                DocumentAggregation::Max => best.distance,
+                DocumentAggregation::Mean => sum / count as f32,
+            };
+            matches.push(DocumentMatch {
+                document: document.clone(),
+                distance,
+                best_vertex_id: best.id,
+            });
+        }
+
+        matches.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        matches.truncate(k_value);
+        Ok(matches)
+    }
+
+    fn read_tag_store(&self) -> ANNResult<std::sync::RwLockReadGuard<'_, TagStore>> {
+        self.tag_store.read().map_err(|_| {
+            ANNError::log_lock_poison_error("Poisoned lock on the tag store.".to_string())
+        })
+    }
+
+    fn write_tag_store(&self) -> ANNResult<std::sync::RwLockWriteGuard<'_, TagStore>> {
+        self.tag_store.write().map_err(|_| {
+            ANNError::log_lock_poison_error("Poisoned lock on the tag store.".to_string())
+        })
+    }
+
+    /// Build the graph of a brute force index that grew to the brute force threshold
+    fn link_if_above_brute_force_threshold(&mut self) -> ANNResult<()> {
+        if self.brute_force && self.num_active_pts >= self.configuration.brute_force_threshold {
+            info!(
+                "Building the graph, {} points reached the brute force threshold of {}",
+                self.num_active_pts, self.configuration.brute_force_threshold
+            );
+            self.brute_force = false;
+            self.link()?;
+            self.print_stats()?;
+        }
+        Ok(())
+    }
+
+    /// Touch the vectors and adjacency lists of up to num_nodes nodes breadth-first from the
+    /// start point, so the first searches don't fault in the pages around the entry region,
+    /// e.g. of a graph mapped from a CSR file. Returns the number of nodes touched.
+    pub fn warm_up(&self, num_nodes: usize) -> ANNResult<usize> {
+        let num_nodes = num_nodes.min(self.num_active_pts);
+        if self.brute_force {
+            for id in 0..num_nodes {
+                std::hint::black_box(self.dataset.get_vertex(id.try_into()?)?.vector()[0]);
+            }
+            return Ok(num_nodes);
+        }
+
+        let max_vertex_id = self.configuration.max_points + self.configuration.num_frozen_pts;
+        let mut visited = HashSet::new();
+        visited.insert(self.start);
+        let mut queue = VecDeque::from([self.start]);
+        let mut neighbors = Vec::new();
+        let mut touched = 0;
+        while let Some(id) = queue.pop_front() {
+            if touched == num_nodes {
+                break;
+            }
+
+            std::hint::black_box(self.dataset.get_vertex(id)?.vector()[0]);
+            self.copy_neighbors(id, &mut neighbors)?;
+            for &neighbor in &neighbors {
+                if (neighbor as usize) < max_vertex_id && visited.insert(neighbor) {
+                    queue.push_back(neighbor);
+                }
+            }
+            touched += 1;
+        }
+
+        Ok(touched)
+    }
+
+    /// Count the points inserted by insert_point as active points
+    fn absorb_streamed_points(&mut self) {
+        let streamed_pts = self.reset_streamed_points();
+        self.num_active_pts += streamed_pts;
+        self.dataset.num_active_pts += streamed_pts;
+    }
+
+    /// Check that every vector has the configured dimension
+    fn check_vector_dimensions(&self, vectors: &[Vec<T>]) -> ANNResult<()> {
+        match vectors
+            .iter()
+            .position(|vector| vector.len() != self.configuration.dim)
+        {
+            Some(i) => Err(ANNError::log_index_error(format!(
+                "ERROR: Vector {} has {} dimensions, but index has {} dimensions.",
+                i,
+                vectors[i].len(),
+                self.configuration.dim
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Pick start and the other entry points among the active points
+    fn select_entry_points(&mut self) -> ANNResult<()> {
+        let seed = step_seed(self.configuration.random_seed, RandomStep::EntryPoints);
+        let mut entry_points = self
+            .dataset
+            .select_entry_points(self.configuration.entry_point_selection, seed)?;
+        self.start = entry_points.remove(0);
+        self.entry_points = entry_points;
+        Ok(())
+    }
+
+    fn link(&mut self) -> ANNResult<()> {
+        // visit_order is a vector that is initialized to the entire graph
+        let mut visit_order =
+            Vec::with_capacity(self.num_active_pts + self.configuration.num_frozen_pts);
+        for i in 0..self.num_active_pts {
+            visit_order.push(i as u32);
+        }
+
+        // If there are any frozen points, add them all.
+        for frozen in self.configuration.max_points
+            ..(self.configuration.max_points + self.configuration.num_frozen_pts)
+        {
+            visit_order.push(frozen as u32);
+        }
+
+        // if there are frozen points, the first such one is set to be the _start
+        if self.configuration.num_frozen_pts > 0 {
+            self.start = self.configuration.max_points as u32;
+            self.entry_points.clear();
+        } else {
+            self.select_entry_points()?;
+        }
+
+        let timer = Timer::new();
+
+        self.prune_vectors = self.quantize_prune_vectors(
+            self.configuration.max_points + self.configuration.num_frozen_pts,
+        )?;
+        if let Some(prune_vectors) = &self.prune_vectors {
+            info!(
+                "Pruning with {:?} distances over {} bytes, refining {} of each pool",
+                self.configuration.prune_quantization,
+                prune_vectors.memory_bytes(),
+                self.configuration.prune_refine_fraction
+            );
+        }
+
+        let range = visit_order.len();
+        let logger = IndexLogger::new(range).with_progress(self.progress_notifier.clone());
+
+        #[cfg(feature = "gpu")]
+        let linked = self.link_on_gpu(&visit_order, &logger)?;
+        #[cfg(not(feature = "gpu"))]
+        let linked = false;
+
+        if !linked {
+            self.execute_parallel(0..range, |idx| {
+                self.insert_vertex_id(visit_order[idx])?;
+                logger.vertex_processed()?;
+
+                Ok(())
+            })?;
+        }
+
+        self.cleanup_graph(&visit_order)?;
+        self.prune_vectors = None;
+
+        if self.num_active_pts > 0 {
+            info!("{}", timer.elapsed_seconds_for_step("Link time: "));
+        }
+
+        Ok(())
+    }
+
+    /// Link the vertices in batches whose pools a GPU compares in one dispatch per batch.
+    /// Returns false without linking when the build prunes on the CPU: without a GPU, with
+    /// quantized prune vectors, or for a metric the GPU doesn't compute.
+    #[cfg(feature = "gpu")]
+    fn link_on_gpu(&self, visit_order: &[u32], logger: &IndexLogger) -> ANNResult<bool> {
+        let Some(gpu_distance) = &self.gpu_distance else {
+            return Ok(false);
+        };
+        let metric = self.configuration.dist_metric;
+        if self.prune_vectors.is_some() {
+            info!("Pruning the quantized prune vectors on the CPU instead of the GPU");
+            return Ok(false);
+        }
+        if !gpu_distance.supports(metric) {
+            info!("Pruning {:?} distances on the CPU instead of the GPU", metric);
+            return Ok(false);
+        }
+        info!("Pruning distances on GPU {}", gpu_distance.adapter_name());
+
+        fn lock_pool(pool: &Mutex<Vec<Neighbor>>) -> ANNResult<std::sync::MutexGuard<'_, Vec<Neighbor>>> {
+            pool.lock().map_err(|_| {
+                ANNError::log_lock_poison_error(
+                    "Poisoned lock on a pool of the GPU link phase.".to_string(),
+                )
+            })
+        }
+
+        let dim = self.configuration.dim;
+        let max_occlusion_size =
+            self.configuration.index_write_parameter.max_occlusion_size as usize;
+        let mut num_linked = 0;
+        while num_linked < visit_order.len() {
+            // The vertices of a batch are searched before any of them is linked, a batch of at
+            // most a quarter of the graph already linked finds the neighbors a serial build does
+            let batch_size = (num_linked / 4)
+                .clamp(1, GPU_LINK_BATCH_SIZE)
+                .min(visit_order.len() - num_linked);
+            let batch = &visit_order[num_linked..num_linked + batch_size];
+
+            let pools: Vec<Mutex<Vec<Neighbor>>> =
+                (0..batch_size).map(|_| Mutex::default()).collect();
+            let pools_ref = &pools;
+            self.execute_parallel(0..batch_size, |idx| {
+                let mut scratch_manager = ScratchStoreManager::new(
+                    self.query_scratch_queue.clone(),
+                    Duration::from_millis(10),
+                )?;
+                let scratch = scratch_manager.scratch_space().ok_or_else(|| {
+                    ANNError::log_index_error(
+                        "ScratchStoreManager doesn't have InMemQueryScratch instance available"
+                            .to_string(),
+                    )
+                })?;
+
+                let vertex = self.dataset.get_vertex(batch[idx])?;
+                let mut pool = self.search_for_point(&vertex, scratch)?;
+                SortedNeighborVector::new(&mut pool);
+                pool.truncate(max_occlusion_size);
+                *lock_pool(&pools_ref[idx])? = pool;
+                Ok(())
+            })?;
+
+            let mut pool_sizes = Vec::with_capacity(batch_size);
+            let mut vectors = Vec::new();
+            for pool in &pools {
+                let pool = lock_pool(pool)?;
+                pool_sizes.push(pool.len());
+                for neighbor in pool.iter() {
+                    let vertex = self.dataset.get_vertex(neighbor.id)?;
+                    vectors.extend(vertex.vector()[..dim].iter().map(|&value| value.into()));
+                }
+            }
+            // None when a pool is beyond what the GPU binds, the batch is pruned on the CPU
+            let distances = gpu_distance.pool_distances(&vectors, &pool_sizes, dim, metric)?;
+            let mut triangle_offsets = vec![0];
+            for &pool_size in &pool_sizes {
+                let offset = triangle_offsets[triangle_offsets.len() - 1] + triangle_len(pool_size);
+                triangle_offsets.push(offset);
+            }
+
+            let (distances_ref, triangle_offsets_ref) = (&distances, &triangle_offsets);
+            self.execute_parallel(0..batch_size, |idx| {
+                let mut scratch_manager = ScratchStoreManager::new(
+                    self.query_scratch_queue.clone(),
+                    Duration::from_millis(10),
+                )?;
+                let scratch = scratch_manager.scratch_space().ok_or_else(|| {
+                    ANNError::log_index_error(
+                        "ScratchStoreManager doesn't have InMemQueryScratch instance available"
+                            .to_string(),
+                    )
+                })?;
+
+                let vertex_id = batch[idx];
+                let mut pool = lock_pool(&pools_ref[idx])?;
+                let mut pruned_list = AdjacencyList::for_range(
+                    self.configuration.index_write_parameter.max_degree as usize,
+                );
+                match distances_ref {
+                    Some(distances) => self.prune_neighbors_with_pool_distances(
+                        vertex_id,
+                        &mut pool,
+                        &distances[triangle_offsets_ref[idx]..triangle_offsets_ref[idx + 1]],
+                        &mut pruned_list,
+                        scratch,
+                    )?,
+                    None => self.prune_neighbors(vertex_id, &mut pool, &mut pruned_list, scratch)?,
+                }
+                self.check_pruned_list(vertex_id, &pruned_list)?;
+                self.link_vertex(vertex_id, pruned_list, scratch)?;
+                logger.vertex_processed()?;
+
+                Ok(())
+            })?;
+
+            num_linked += batch_size;
+        }
+
+        Ok(true)
+    }
+
+    /// Run f over range in parallel on the thread pool of the configuration, serially when it
+    /// asks for one thread and on the global pool when it has no pool
+    fn execute_parallel<F>(&self, range: Range<usize>, f: F) -> ANNResult<()>
+    where
+        F: Fn(usize) -> ANNResult<()> + Sync + Send + Copy,
+    {
+        let num_threads = match self.configuration.thread_pool {
+            Some(_) => 0,
+            None => self.configuration.index_write_parameter.num_threads,
+        };
+        install_on_pool(self.configuration.thread_pool.as_deref(), || {
+            execute_with_rayon(range, num_threads, f)
+        })
+    }
+
+    /// Quantize the vectors pruning compares on the thread pool of the configuration
+    fn quantize_prune_vectors(
+        &self,
+        num_points: usize,
+    ) -> ANNResult<Option<QuantizedPruneVectors<N>>> {
+        install_on_pool(self.configuration.thread_pool.as_deref(), || {
+            QuantizedPruneVectors::new(
+                &self.dataset,
+                num_points,
+                self.configuration.dim,
+                self.configuration.prune_quantization,
+                step_seed(
+                    self.configuration.random_seed,
+                    RandomStep::PruneQuantization,
+                ),
+            )
+        })
+    }
+
+    fn insert_vertex_id(&self, vertex_id: u32) -> ANNResult<()> {
+        let mut scratch_manager =
+            ScratchStoreManager::new(self.query_scratch_queue.clone(), Duration::from_millis(10))?;
+        let scratch = scratch_manager.scratch_space().ok_or_else(|| {
+            ANNError::log_index_error(
+                "ScratchStoreManager doesn't have InMemQueryScratch instance available".to_string(),
+            )
+        })?;
+
+        let new_neighbors = self.search_for_point_and_prune(scratch, vertex_id)?;
+        self.link_vertex(vertex_id, new_neighbors, scratch)
+    }
+
+    /// Set the pruned neighbors of a vertex and add it to theirs
+    fn link_vertex(
+        &self,
+        vertex_id: u32,
+        new_neighbors: AdjacencyList,
+        scratch: &mut InMemQueryScratch<T, N>,
+    ) -> ANNResult<()> {
+        // The start linked before any other point only finds itself, the points linked
+        // after it add themselves to its neighbors
+        if new_neighbors.is_empty() {
+            return Ok(());
+        }
+        self.update_vertex_with_neighbors(vertex_id, new_neighbors)?;
+        self.write_through(vertex_id)?;
+        self.update_neighbors_of_vertex(vertex_id, scratch)?;
+
+        Ok(())
+    }
+
+    /// Re-prune the neighbors of a vertex over its current ones and the points found searching
+    /// for it, then add it to the neighbors it keeps
+    fn merge_vertex_id(&self, vertex_id: u32) -> ANNResult<()> {
+        let mut scratch_manager =
+            ScratchStoreManager::new(self.query_scratch_queue.clone(), Duration::from_millis(10))?;
+        let scratch = scratch_manager.scratch_space().ok_or_else(|| {
+            ANNError::log_index_error(
+                "ScratchStoreManager doesn't have InMemQueryScratch instance available".to_string(),
+            )
+        })?;
+
+        let vertex = self.dataset.get_vertex(vertex_id)?;
+        let mut pool = self.search_for_point(&vertex, scratch)?;
+        let found: HashSet<u32> = pool.iter().map(|neighbor| neighbor.id).collect();
+        for neighbor in self.get_neighbors_for_vertex(vertex_id)? {
+            if !found.contains(&neighbor.id) {
+                pool.push(neighbor);
+            }
+        }
+
+        let mut pruned_list =
+            AdjacencyList::for_range(self.configuration.index_write_parameter.max_degree as usize);
+        self.prune_neighbors(vertex_id, &mut pool, &mut pruned_list, scratch)?;
+        if pruned_list.is_empty() {
+            return Ok(());
+        }
+        self.update_vertex_with_neighbors(vertex_id, pruned_list)?;
+        self.update_neighbors_of_vertex(vertex_id, scratch)?;
+
+        Ok(())
+    }
+
+    fn update_neighbors_of_vertex(
+        &self,
+        vertex_id: u32,
+        scratch: &mut InMemQueryScratch<T, N>,
+    ) -> Result<(), ANNError> {
+        // Copied so no lock is held while the neighbors are locked for write in turn
+        let neighbors = self
+            .final_graph
+            .read_vertex_and_neighbors(vertex_id)?
+            .get_neighbors()
+            .to_vec();
+        assert!(neighbors.len() <= self.configuration.index_write_parameter.max_degree as usize);
+        self.inter_insert(
+            vertex_id,
+            &neighbors,
+            self.configuration.index_write_parameter.max_degree,
+            scratch,
+        )?;
+        Ok(())
+    }
+
+    fn update_vertex_with_neighbors(
+        &self,
+        vertex_id: u32,
+        new_neighbors: AdjacencyList,
+    ) -> Result<(), ANNError> {
+        let vertex = &mut self.final_graph.write_vertex_and_neighbors(vertex_id)?;
+        vertex.set_neighbors(new_neighbors);
+        assert!(vertex.size() <= self.configuration.index_write_parameter.max_degree as usize);
+        Ok(())
+    }
+
+    fn search_for_point_and_prune(
+        &self,
+        scratch: &mut InMemQueryScratch<T, N>,
+        vertex_id: u32,
+    ) -> ANNResult<AdjacencyList> {
+        let mut pruned_list =
+            AdjacencyList::for_range(self.configuration.index_write_parameter.max_degree as usize);
+        let vertex = self.dataset.get_vertex(vertex_id)?;
+        let mut visited_nodes = self.search_for_point(&vertex, scratch)?;
+
+        self.prune_neighbors(vertex_id, &mut visited_nodes, &mut pruned_list, scratch)?;
+        self.check_pruned_list(vertex_id, &pruned_list)?;
+
+        Ok(pruned_list)
+    }
+
+    /// Check the pruned neighbors of a vertex being linked, only the start may have none
+    fn check_pruned_list(&self, vertex_id: u32, pruned_list: &AdjacencyList) -> ANNResult<()> {
+        if pruned_list.is_empty() && vertex_id != self.start {
+            return Err(ANNError::log_index_error(
+                "pruned_list is empty.".to_string(),
+            ));
+        }
+
+        if self.final_graph.size()
+            != self.configuration.max_points + self.configuration.num_frozen_pts
+        {
+            return Err(ANNError::log_index_error(format!(
+                "final_graph has {} vertices instead of {}",
+                self.final_graph.size(),
+                self.configuration.max_points + self.configuration.num_frozen_pts,
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn search(
+        &self,
+        query: &Vertex<T, N>,
+        k_value: usize,
+        l_value: u32,
+        indices: &mut [u32],
+    ) -> ANNResult<u32> {
+        let (neighbors, query_stats) = self.search_neighbors(query, k_value, l_value, &QueryComparison::Full, None, SearchLimits::default(), None)?;
+        for (index, neighbor) in indices.iter_mut().zip(neighbors.iter()) {
+            *index = neighbor.id;
+        }
+
+        Ok(query_stats.n_cmps)
+    }
+
+    /// Search the index for K nearest neighbors of query like search, calling visitor for every
+    /// node the search expands
+    pub fn search_with_visitor(
+        &self,
+        query: &Vertex<T, N>,
+        k_value: usize,
+        l_value: u32,
+        visitor: &mut dyn SearchVisitor,
+        indices: &mut [u32],
+    ) -> ANNResult<u32> {
+        let (neighbors, query_stats) = self.search_neighbors(
+            query,
+            k_value,
+            l_value,
+            &QueryComparison::Full,
+            None,
+            SearchLimits::default(),
+            Some(visitor),
+        )?;
+        for (index, neighbor) in indices.iter_mut().zip(neighbors.iter()) {
+            *index = neighbor.id;
+        }
+
+        Ok(query_stats.n_cmps)
+    }
+
+    /// Search the index for K nearest neighbors of query, weighting each dimension of the
+    /// distance by weights. Weights are given for the dim dimensions of the data (or the
+    /// aligned dimensions) and must be finite and non-negative.
+    pub fn search_with_weights(
+        &self,
+        query: &Vertex<T, N>,
+        weights: &[f32],
+        k_value: usize,
+        l_value: u32,
+        indices: &mut [u32],
+    ) -> ANNResult<u32> {
+        if self.configuration.dist_metric.is_binary() {
+            return Err(ANNError::log_index_config_error(
+                "weights".to_string(),
+                format!(
+                    "Dimension weights are not supported with the {:?} metric",
+                    self.configuration.dist_metric
+                ),
+            ));
+        }
+
+        if weights.len() != self.configuration.dim && weights.len() != N {
+            return Err(ANNError::log_index_error(format!(
+                "Got {} weights, expected one per dimension ({})",
+                weights.len(),
+                self.configuration.dim
+            )));
+        }
+
+        if let Some(weight) = weights.iter().find(|w| !(w.is_finite() && **w >= 0.0)) {
+            return Err(ANNError::log_index_error(format!(
+                "Dimension weights must be finite and non-negative, got {}",
+                weight
+            )));
+        }
+
+        // The padding dimensions are 0 in every vector, their weight doesn't matter
+        let mut padded_weights = [0f32; N];
+        padded_weights[..weights.len()].copy_from_slice(weights);
+
+        let (neighbors, query_stats) = self.search_neighbors(
+            query,
+            k_value,
+            l_value,
+            &QueryComparison::Weighted(&padded_weights),
+            None,
+            SearchLimits::default(),
+            None,
+        )?;
+        for (index, neighbor) in indices.iter_mut().zip(neighbors.iter()) {
+            *index = neighbor.id;
+        }
+
+        Ok(query_stats.n_cmps)
+    }
+
+    /// Search the index for K nearest neighbors of query, traversing the graph with distances
+    /// over the dimensions in dims only (e.g. the leading dimensions of a Matryoshka embedding).
+    /// The L candidates found are reranked with the full distance before the K closest are kept.
+    pub fn search_in_subspace(
+        &self,
+        query: &Vertex<T, N>,
+        dims: Range<usize>,
+        k_value: usize,
+        l_value: u32,
+        indices: &mut [u32],
+    ) -> ANNResult<u32> {
+        if self.configuration.dist_metric.is_binary() {
+            return Err(ANNError::log_index_config_error(
+                "dims".to_string(),
+                format!(
+                    "Subspace search is not supported with the {:?} metric",
+                    self.configuration.dist_metric
+                ),
+            ));
+        }
+
+        if dims.is_empty() || dims.end > self.configuration.dim {
+            return Err(ANNError::log_index_error(format!(
+                "Dimension range {:?} is empty or exceeds the {} dimensions of the data",
+                dims, self.configuration.dim
+            )));
+        }
+
+        let (neighbors, query_stats) = self.search_neighbors(
+            query,
+            k_value,
+            l_value,
+            &QueryComparison::Subspace(dims),
+            None,
+            SearchLimits::default(),
+            None,
+        )?;
+        for (index, neighbor) in indices.iter_mut().zip(neighbors.iter()) {
+            *index = neighbor.id;
+        }
+
+        Ok(query_stats.n_cmps)
+    }
+
+    /// Search the index for K nearest neighbors of query among the points whose labels satisfy
+    /// filter. Fewer than K ids are written if fewer points match.
+    pub fn search_with_filter(
+        &self,
+        query: &Vertex<T, N>,
+        filter: &LabelFilter,
+        k_value: usize,
+        l_value: u32,
+        indices: &mut [u32],
+    ) -> ANNResult<usize> {
+        let (neighbors, _) = self.search_neighbors(
+            query,
+            k_value,
+            l_value,
+            &QueryComparison::Full,
+            Some(filter),
+            SearchLimits::default(),
+            None,
+        )?;
+        for (index, neighbor) in indices.iter_mut().zip(neighbors.iter()) {
+            *index = neighbor.id;
+        }
+
+        Ok(neighbors.len())
+    }
+
+    /// Search the index for K nearest neighbors of query with the search list size, IO limit and
+    /// patience of params, the IO limit capping the number of nodes expanded. The beam width and
+    /// reorder apply to disk indices only.
+    pub fn search_with_params(
+        &self,
+        query: &Vertex<T, N>,
+        k_value: usize,
+        params: &SearchParams,
+        indices: &mut [u32],
+    ) -> ANNResult<u32> {
+        let query_stats = self.search_with_params_and_stats(query, k_value, params, indices)?;
+        Ok(query_stats.n_cmps)
+    }
+
+    /// Search the index like search_with_params and return the statistics of the query, which
+    /// flag a search the IO limit or patience stopped early
+    pub fn search_with_params_and_stats(
+        &self,
+        query: &Vertex<T, N>,
+        k_value: usize,
+        params: &SearchParams,
+        indices: &mut [u32],
+    ) -> ANNResult<QueryStats> {
+        params.check_k(k_value)?;
+        let limits = SearchLimits {
+            max_hops: params.max_ios(),
+            patience: params.patience(),
+        };
+        let (neighbors, query_stats) = self.search_neighbors(
+            query,
+            k_value,
+            params.l_value(),
+            &QueryComparison::Full,
+            None,
+            limits,
+            None,
+        )?;
+        for (index, neighbor) in indices.iter_mut().zip(neighbors.iter()) {
+            *index = neighbor.id;
+        }
+
+        Ok(query_stats)
+    }
+
+    /// Search the index for K nearest neighbors of query and populate the requested fields of each
+    /// result in the same pass, together with the statistics of the query.
+    pub fn search_with_details(
+        &self,
+        query: &Vertex<T, N>,
+        k_value: usize,
+        l_value: u32,
+        fields: SearchResultFields,
+    ) -> ANNResult<(Vec<SearchResult>, QueryStats)> {
+        let (neighbors, query_stats) = self.search_neighbors(query, k_value, l_value, &QueryComparison::Full, None, SearchLimits::default(), None)?;
+
+        let tag_store = if fields.tags {
+            Some(self.read_tag_store()?)
+        } else {
+            None
+        };
+        let results = neighbors
+            .iter()
+            .map(|neighbor| SearchResult {
+                id: neighbor.id,
+                distance: neighbor.distance,
+                labels: fields
+                    .labels
+                    .then(|| self.point_metadata.labels(neighbor.id).to_vec()),
+                payload: fields
+                    .payload
+                    .then(|| self.point_metadata.payload(neighbor.id).to_vec()),
+                tag: tag_store
+                    .as_ref()
+                    .and_then(|tag_store| tag_store.tag(neighbor.id).cloned()),
+            })
+            .collect();
+
+        Ok((results, query_stats))
+    }
+
+    /// Search for up to K nearest non-deleted neighbors of query, among the points satisfying
+    /// filter if one is given, stopping early as limits say. Visitor is told about the nodes an
+    /// unfiltered search expands.
+    #[allow(clippy::too_many_arguments)]
+    fn search_neighbors(
+        &self,
+        query: &Vertex<T, N>,
+        k_value: usize,
+        l_value: u32,
+        comparison: &QueryComparison<N>,
+        filter: Option<&LabelFilter>,
+        limits: SearchLimits,
+        visitor: Option<&mut dyn SearchVisitor>,
+    ) -> ANNResult<(Vec<Neighbor>, QueryStats)> {
+        if k_value > l_value as usize {
+            return Err(ANNError::log_index_error(format!(
+                "Set L: {} to a value of at least K: {}",
+                l_value, k_value
+            )));
+        }
+
+        let mut scratch_manager =
+            ScratchStoreManager::new(self.query_scratch_queue.clone(), Duration::from_millis(10))?;
+
+        let scratch = scratch_manager.scratch_space().ok_or_else(|| {
+            ANNError::log_index_error(
+                "ScratchStoreManager doesn't have InMemQueryScratch instance available".to_string(),
+            )
+        })?;
+
+        if l_value > scratch.candidate_size {
+            info!("Attempting to expand query scratch_space. Was created with Lsize: {} but search L is: {}", scratch.candidate_size, l_value);
+            scratch.resize_for_new_candidate_size(l_value);
+            info!(
                 "Resize completed. New scratch size is: {}",
                 scratch.candidate_size
             );
         }
 
-        let cmp = self.search_with_l_override(query, scratch, l_value as usize)?;
-        let mut pos = 0;
+        let query_stats = match filter {
+            Some(filter) => {
+                self.search_with_label_filter(query, scratch, l_value as usize, filter)?
+            }
+            None => self.search_with_comparison(
+                query,
+                scratch,
+                l_value as usize,
+                comparison,
+                limits,
+                visitor,
+            )?,
+        };
+        if let QueryComparison::Subspace(_) = comparison {
+            self.rerank_with_full_distance(query, &mut scratch.best_candidates)?;
+        }
+        let mut neighbors = Vec::with_capacity(k_value);
 
         for i in 0..scratch.best_candidates.size() {
             if scratch.best_candidates[i].id < self.configuration.max_points as u32 {
                 // Filter out the deleted points.
                 if let Ok(delete_set_guard) = self.delete_set.read() {
                     if !delete_set_guard.contains(&scratch.best_candidates[i].id) {
-                        indices[pos] = scratch.best_candidates[i].id;
-                        pos += 1;
+                        neighbors.push(scratch.best_candidates[i]);
                     }
                 } else {
                     return Err(ANNError::log_lock_poison_error(
@@ -299,63 +1546,82 @@ where
                 }
             }
 
-            if pos == k_value {
+            if neighbors.len() == k_value {
                 break;
             }
         }
 
-        if pos < k_value {
-            eprintln!(
+        if neighbors.len() < k_value {
+            warn!(
                 "Found fewer than K elements for query! Found: {} but K: {}",
-                pos, k_value
+                neighbors.len(), k_value
             );
         }
 
-        Ok(cmp)
+        Ok((neighbors, query_stats))
+    }
+
+    /// Replace the distances of the candidates with full distances to the query and re-sort them
+    fn rerank_with_full_distance(
+        &self,
+        query: &Vertex<T, N>,
+        candidates: &mut NeighborPriorityQueue,
+    ) -> ANNResult<()> {
+        let mut reranked = Vec::with_capacity(candidates.size());
+        for i in 0..candidates.size() {
+            let vertex = self.dataset.get_vertex(candidates[i].id)?;
+            reranked.push(Neighbor::new(
+                candidates[i].id,
+                self.compare_vertices(query, &vertex),
+            ));
+        }
+
+        candidates.clear();
+        for neighbor in reranked {
+            candidates.insert(neighbor);
+        }
+
+        Ok(())
     }
 
     fn cleanup_graph(&mut self, visit_order: &Vec<u32>) -> ANNResult<()> {
         if self.num_active_pts > 0 {
-            println!("Starting final cleanup..");
+            info!("Starting final cleanup..");
         }
 
-        execute_with_rayon(
-            0..visit_order.len(),
-            self.configuration.index_write_parameter.num_threads,
-            |idx| {
-                let vertex_id = visit_order[idx];
-                let num_nbrs = self.get_neighbor_count(vertex_id)?;
+        self.execute_parallel(0..visit_order.len(), |idx| {
+            let vertex_id = visit_order[idx];
+            let num_nbrs = self.get_neighbor_count(vertex_id)?;
 
-                if num_nbrs <= self.configuration.index_write_parameter.max_degree as usize {
-                    // Neighbor list is already small enough.
-                    return Ok(());
-                }
+            if num_nbrs <= self.configuration.index_write_parameter.max_degree as usize {
+                // Neighbor list is already small enough.
+                return Ok(());
+            }
 
-                let mut scratch_manager = ScratchStoreManager::new(
-                    self.query_scratch_queue.clone(),
-                    Duration::from_millis(10),
-                )?;
-                let scratch = scratch_manager.scratch_space().ok_or_else(|| {
-                    ANNError::log_index_error(
-                        "ScratchStoreManager doesn't have InMemQueryScratch instance available"
-                            .to_string(),
-                    )
-                })?;
+            let mut scratch_manager = ScratchStoreManager::new(
+                self.query_scratch_queue.clone(),
+                Duration::from_millis(10),
+            )?;
+            let scratch = scratch_manager.scratch_space().ok_or_else(|| {
+                ANNError::log_index_error(
+                    "ScratchStoreManager doesn't have InMemQueryScratch instance available"
+                        .to_string(),
+                )
+            })?;
 
-                let mut dummy_pool = self.get_neighbors_for_vertex(vertex_id)?;
+            let mut dummy_pool = self.get_neighbors_for_vertex(vertex_id)?;
 
-                let mut new_out_neighbors = AdjacencyList::for_range(
-                    self.configuration.index_write_parameter.max_degree as usize,
-                );
-                self.prune_neighbors(vertex_id, &mut dummy_pool, &mut new_out_neighbors, scratch)?;
+            let mut new_out_neighbors = AdjacencyList::for_range(
+                self.configuration.index_write_parameter.max_degree as usize,
+            );
+            self.prune_neighbors(vertex_id, &mut dummy_pool, &mut new_out_neighbors, scratch)?;
 
-                self.final_graph
-                    .write_vertex_and_neighbors(vertex_id)?
-                    .set_neighbors(new_out_neighbors);
+            self.final_graph
+                .write_vertex_and_neighbors(vertex_id)?
+                .set_neighbors(new_out_neighbors);
 
-                Ok(())
-            },
-        )
+            Ok(())
+        })
     }
 
     /// Get the unique neighbors for a vertex.
@@ -366,9 +1632,9 @@ where
     ///
     /// This function will return an error if we are not able to get the read lock.
     fn get_neighbors_for_vertex(&self, vertex_id: u32) -> ANNResult<Vec<Neighbor>> {
-        let binding = self.final_graph.read_vertex_and_neighbors(vertex_id)?;
-        let neighbors = binding.get_neighbors();
-        let dummy_pool = self.get_unique_neighbors(neighbors, vertex_id)?;
+        let mut neighbors = Vec::new();
+        self.final_graph.copy_neighbors(vertex_id, &mut neighbors)?;
+        let dummy_pool = self.get_unique_neighbors(&neighbors, vertex_id)?;
 
         Ok(dummy_pool)
     }
@@ -443,7 +1709,7 @@ where
         if current != vertex_id {
             if let Vacant(entry) = dummy_visited.entry(current) {
                 let cur_nbr_vertex = self.dataset.get_vertex(current)?;
-                let dist = vertex.compare(&cur_nbr_vertex, self.configuration.dist_metric);
+                let dist = self.compare_vertices(vertex, &cur_nbr_vertex);
                 dummy_pool.push(Neighbor::new(current, dist));
                 entry.insert();
             }
@@ -465,26 +1731,135 @@ where
         Ok(num_nbrs)
     }
 
-    fn soft_delete_vertex(&self, vertex_id_to_delete: u32) -> ANNResult<()> {
-        if vertex_id_to_delete as usize > self.num_active_pts {
-            return Err(ANNError::log_index_error(format!(
-                "vertex_id_to_delete: {} is greater than the number of active points in the graph: {}",
-                vertex_id_to_delete, self.num_active_pts
-            )));
+    fn soft_delete_vertex(&self, vertex_id_to_delete: u32) -> ANNResult<()> {
+        let num_searchable_pts = self.num_searchable_pts();
+        if vertex_id_to_delete as usize >= num_searchable_pts {
+            return Err(ANNError::log_index_error(format!(
+                "vertex_id_to_delete: {} is not less than the number of active points in the graph: {}",
+                vertex_id_to_delete, num_searchable_pts
+            )));
+        }
+
+        if let Some(write_ahead_log) = &self.write_ahead_log {
+            write_ahead_log
+                .lock()
+                .map_err(|_| {
+                    ANNError::log_lock_poison_error(
+                        "Poisoned lock on the write-ahead log. Can't delete point.".to_string(),
+                    )
+                })?
+                .append_delete(vertex_id_to_delete)?;
+        }
+
+        let mut delete_set_guard = match self.delete_set.write() {
+            Ok(guard) => guard,
+            Err(_) => {
+                return Err(ANNError::log_index_error(format!(
+                    "Failed to acquire delete_set lock, cannot delete vertex {}",
+                    vertex_id_to_delete
+                )));
+            }
+        };
+
+        delete_set_guard.insert(vertex_id_to_delete);
+        Ok(())
+    }
+
+    /// Unlink the soft deleted points from the graph. Each list that refers to a deleted point
+    /// is pruned again over its other neighbors and the neighbors of the deleted ones, then the
+    /// lists of the deleted points are dropped. Deleted entry points keep routing searches.
+    /// Returns the number of points unlinked.
+    pub fn consolidate_deletes(&mut self) -> ANNResult<usize> {
+        self.absorb_streamed_points();
+        self.expand_graph()?;
+
+        let deleted = self
+            .delete_set
+            .read()
+            .map_err(|_| {
+                ANNError::log_lock_poison_error(
+                    "Failed to acquire delete_set lock, cannot consolidate deletes".to_string(),
+                )
+            })?
+            .clone();
+        if deleted.is_empty() {
+            return Ok(0);
+        }
+
+        let is_entry_point =
+            |vertex_id: u32| vertex_id == self.start || self.entry_points.contains(&vertex_id);
+        let frozen_pts = self.configuration.max_points
+            ..self.configuration.max_points + self.configuration.num_frozen_pts;
+        let visit_order: Vec<u32> = (0..self.num_active_pts)
+            .chain(frozen_pts)
+            .map(|vertex_id| vertex_id as u32)
+            .filter(|&vertex_id| !deleted.contains(&vertex_id) || is_entry_point(vertex_id))
+            .collect();
+
+        info!("Consolidating {} deleted vectors.", deleted.len());
+        let logger =
+            IndexLogger::new(visit_order.len()).with_progress(self.progress_notifier.clone());
+        let timer = Timer::new();
+
+        self.execute_parallel(0..visit_order.len(), |idx| {
+            let vertex_id = visit_order[idx];
+            let mut neighbors = Vec::new();
+            self.final_graph.copy_neighbors(vertex_id, &mut neighbors)?;
+
+            if neighbors.iter().any(|neighbor| deleted.contains(neighbor)) {
+                let mut candidates = Vec::with_capacity(neighbors.len());
+                let mut deleted_neighbors = Vec::new();
+                for &neighbor in &neighbors {
+                    if !deleted.contains(&neighbor) {
+                        candidates.push(neighbor);
+                        continue;
+                    }
+                    self.final_graph
+                        .copy_neighbors(neighbor, &mut deleted_neighbors)?;
+                    candidates.extend(
+                        deleted_neighbors
+                            .iter()
+                            .filter(|&candidate| !deleted.contains(candidate)),
+                    );
+                }
+
+                let mut pool = self.get_unique_neighbors(&candidates, vertex_id)?;
+                let mut scratch_manager = ScratchStoreManager::new(
+                    self.query_scratch_queue.clone(),
+                    Duration::from_millis(10),
+                )?;
+                let scratch = scratch_manager.scratch_space().ok_or_else(|| {
+                    ANNError::log_index_error(
+                        "ScratchStoreManager doesn't have InMemQueryScratch instance available"
+                            .to_string(),
+                    )
+                })?;
+                let mut pruned_list = AdjacencyList::for_range(
+                    self.configuration.index_write_parameter.max_degree as usize,
+                );
+                self.prune_neighbors(vertex_id, &mut pool, &mut pruned_list, scratch)?;
+                self.update_vertex_with_neighbors(vertex_id, pruned_list)?;
+            }
+            logger.vertex_processed()?;
+
+            Ok(())
+        })?;
+
+        let mut num_unlinked = 0;
+        for &vertex_id in deleted.iter().filter(|&&vertex_id| !is_entry_point(vertex_id)) {
+            self.update_vertex_with_neighbors(
+                vertex_id,
+                AdjacencyList::for_range(
+                    self.configuration.index_write_parameter.max_degree as usize,
+                ),
+            )?;
+            num_unlinked += 1;
         }
 
-        let mut delete_set_guard = match self.delete_set.write() {
-            Ok(guard) => guard,
-            Err(_) => {
-                return Err(ANNError::log_index_error(format!(
-                    "Failed to acquire delete_set lock, cannot delete vertex {}",
-                    vertex_id_to_delete
-                )));
-            }
-        };
+        info!("{}", timer.elapsed_seconds_for_step("Consolidate time: "));
+        self.print_stats()?;
 
-        delete_set_guard.insert(vertex_id_to_delete);
-        Ok(())
+        Ok(num_unlinked)
     }
 
     fn initialize_query_scratch(
@@ -494,11 +1869,14 @@ where
     ) -> ANNResult<()> {
         self.query_scratch_queue.reserve(num_threads as usize)?;
         for _ in 0..num_threads {
-            let scratch = Box::new(InMemQueryScratch::<T, N>::new(
+            let mut scratch = Box::new(InMemQueryScratch::<T, N>::new(
                 search_candidate_size,
                 &self.configuration.index_write_parameter,
                 false,
             )?);
+            scratch
+                .best_candidates
+                .set_tie_epsilon(self.configuration.distance_tie_epsilon);
 
             self.query_scratch_queue.push(scratch)?;
         }
@@ -526,7 +1904,7 @@ where
             }
         }
 
-        println!(
+        info!(
             "Index built with degree: max: {} avg: {} min: {} count(deg<2): {}",
             max,
             (total as f32) / ((self.num_active_pts + self.configuration.num_frozen_pts) as f32),
@@ -536,7 +1914,7 @@ where
 
         match self.delete_set.read() {
             Ok(guard) => {
-                println!(
+                info!(
                     "Number of soft deleted vertices {}, soft deleted percentage: {}",
                     guard.len(),
                     (guard.len() as f32)
@@ -557,12 +1935,38 @@ where
     }
 }
 
-impl<T, const N: usize> ANNInmemIndex<T> for InmemIndex<T, N>
+/// The query as a vector of the index dimension. Queries of the aligned data dimension are padded
+/// with zeros when the index was created for a larger specialized dimension.
+pub(super) fn padded_query<T: Default + Copy, const N: usize>(
+    query: &[T],
+) -> ANNResult<Cow<'_, [T; N]>> {
+    if let Ok(query) = <&[T; N]>::try_from(query) {
+        return Ok(Cow::Borrowed(query));
+    }
+
+    if query.len() > N {
+        return Err(ANNError::log_index_error(format!(
+            "Query has dimension {}, the index holds vectors of dimension {}",
+            query.len(),
+            N
+        )));
+    }
+
+    let mut padded = [T::default(); N];
+    padded[..query.len()].copy_from_slice(query);
+    Ok(Cow::Owned(padded))
+}
+
+impl<T, const N: usize, D> ANNInmemIndex<T> for InmemIndex<T, N, D>
 where
     T: Default + Copy + Sync + Send + Into<f32>,
     [T; N]: FullPrecisionDistance<T, N>,
+    D: Distance<T, N>,
 {
     fn build(&mut self, filename: &str, num_points_to_load: usize) -> ANNResult<()> {
+        self.check_no_write_ahead_log("build")?;
+        self.expand_graph()?;
+        self.reset_streamed_points();
         // TODO: fresh-diskANN
         // std::unique_lock<std::shared_timed_mutex> ul(_update_lock);
 
@@ -601,13 +2005,11 @@ where
             todo!("PQ is not supported now");
         }
 
-        if self.configuration.index_write_parameter.num_threads > 0 {
-            set_rayon_num_threads(self.configuration.index_write_parameter.num_threads);
-        }
+        self.configuration.start_thread_pool()?;
 
         self.dataset.build_from_file(filename, num_points_to_load)?;
 
-        println!("Using only first {} from file.", num_points_to_load);
+        info!("Using only first {} from file.", num_points_to_load);
 
         // TODO: tag_lock
 
@@ -618,6 +2020,9 @@ where
     }
 
     fn insert(&mut self, filename: &str, num_points_to_insert: usize) -> ANNResult<()> {
+        self.check_no_write_ahead_log("insert a batch")?;
+        self.expand_graph()?;
+        self.absorb_streamed_points();
         // fresh-diskANN
         if !file_exists(filename) {
             return Err(ANNError::log_index_error(format!(
@@ -647,6 +2052,132 @@ where
             todo!("PQ is not supported now");
         }
 
+        self.dataset
+            .append_from_file(filename, num_points_to_insert)?;
+
+        info!("Inserting {} vectors from file.", num_points_to_insert);
+        self.insert_appended_points(num_points_to_insert)
+    }
+
+    fn merge(&mut self, index_files: &[&str]) -> ANNResult<()> {
+        self.check_no_write_ahead_log("merge")?;
+        self.expand_graph()?;
+        self.reset_streamed_points();
+
+        if index_files.is_empty() {
+            return Err(ANNError::log_index_config_error(
+                "index_files".to_string(),
+                "No indexes to merge".to_string(),
+            ));
+        }
+        if self.configuration.num_frozen_pts != 0 {
+            return Err(ANNError::log_index_config_error(
+                "num_frozen_pts".to_string(),
+                "Indexes with frozen points can't be merged".to_string(),
+            ));
+        }
+        if index_files.len() > self.configuration.index_write_parameter.max_degree as usize {
+            return Err(ANNError::log_index_config_error(
+                "index_files".to_string(),
+                format!(
+                    "Can't link the starts of {} indexes to a point of max degree {}",
+                    index_files.len(),
+                    self.configuration.index_write_parameter.max_degree
+                ),
+            ));
+        }
+
+        let mut num_points = Vec::with_capacity(index_files.len());
+        for index_file in index_files {
+            let (file_num_points, file_dim) =
+                load_metadata_from_file(&format!("{}.data", index_file))?;
+            if file_dim != self.configuration.dim {
+                return Err(ANNError::log_index_error(format!(
+                    "ERROR: Index {} has {} dimensions, but index has {} dimensions.",
+                    index_file, file_dim, self.configuration.dim
+                )));
+            }
+            num_points.push(file_num_points);
+        }
+        let total_num_points: usize = num_points.iter().sum();
+        if total_num_points > self.configuration.max_points {
+            return Err(ANNError::log_index_error(format!(
+                "ERROR: Merging {} points, but index can support only {} points as specified in configuration.",
+                total_num_points, self.configuration.max_points
+            )));
+        }
+
+        self.configuration.start_thread_pool()?;
+
+        match self.delete_set.write() {
+            Ok(mut delete_set) => delete_set.clear(),
+            Err(_) => {
+                return Err(ANNError::log_lock_poison_error(
+                    "Poisoned lock on delete set. Can't merge indexes.".to_string(),
+                ))
+            }
+        }
+        self.point_metadata = PointMetadataStore::new(self.configuration.max_points);
+        *self.tag_store.get_mut().map_err(|_| {
+            ANNError::log_lock_poison_error(
+                "Poisoned lock on the tag store. Can't merge indexes.".to_string(),
+            )
+        })? = TagStore::new(self.configuration.max_points);
+        self.documents = DocumentStore::new(self.configuration.max_points);
+
+        // Each index goes after the ones before it, its ids shifted by their number of points
+        let mut starts = Vec::with_capacity(index_files.len());
+        let mut id_offset = 0;
+        for (index_file, &index_num_points) in index_files.iter().zip(&num_points) {
+            copy_aligned_data_from_file(
+                &format!("{}.data", index_file),
+                self.dataset.into_dto(),
+                id_offset,
+            )?;
+
+            // Before the graph, whose deleted points may have no neighbors
+            self.append_delete_list(&format!("{}.delete", index_file), id_offset.try_into()?)?;
+            let (start, num_vertices) = self.append_graph(index_file, id_offset.try_into()?)?;
+            if num_vertices != index_num_points {
+                return Err(ANNError::log_index_error(format!(
+                    "ERROR: Index {} has {} points, but its graph has {} vertices.",
+                    index_file, index_num_points, num_vertices
+                )));
+            }
+            starts.push(start);
+
+            let labels_file = format!("{}.labels", index_file);
+            if file_exists(&labels_file) {
+                self.point_metadata
+                    .append_labels(&labels_file, id_offset.try_into()?)?;
+            }
+            let payloads_file = format!("{}.payloads", index_file);
+            if file_exists(&payloads_file) {
+                self.point_metadata
+                    .append_payloads(&payloads_file, id_offset.try_into()?)?;
+            }
+            let tags_file = format!("{}.tags", index_file);
+            if file_exists(&tags_file) {
+                self.write_tag_store()?
+                    .append(&tags_file, id_offset.try_into()?)?;
+            }
+            let documents_file = format!("{}.documents", index_file);
+            if file_exists(&documents_file) {
+                self.documents
+                    .append(&documents_file, id_offset.try_into()?)?;
+            }
+
+            id_offset += index_num_points;
+        }
+        info!(
+            "Merging {} indexes of {:?} points",
+            index_files.len(),
+            num_points
+        );
+
+        self.num_active_pts = total_num_points;
+        self.dataset.num_active_pts = total_num_points;
+        self.brute_force = total_num_points < self.configuration.brute_force_threshold;
         if self.query_scratch_queue.size()? == 0 {
             self.initialize_query_scratch(
                 5 + self.configuration.index_write_parameter.num_threads,
@@ -654,97 +2185,547 @@ where
             )?;
         }
 
-        if self.configuration.index_write_parameter.num_threads > 0 {
-            // set the thread count of Rayon, otherwise it will use threads as many as logical cores.
-            std::env::set_var(
-                "RAYON_NUM_THREADS",
-                self.configuration
-                    .index_write_parameter
-                    .num_threads
-                    .to_string(),
-            );
+        // The start of the union links to the start of every index, so searching from it
+        // reaches all of them
+        self.select_entry_points()?;
+        let mut start_neighbors: Vec<u32> = starts
+            .iter()
+            .copied()
+            .filter(|&start| start != self.start)
+            .collect();
+        let mut neighbors = Vec::new();
+        self.final_graph
+            .copy_neighbors(self.start, &mut neighbors)?;
+        for neighbor in neighbors {
+            if !start_neighbors.contains(&neighbor) {
+                start_neighbors.push(neighbor);
+            }
+        }
+        start_neighbors.truncate(self.configuration.index_write_parameter.max_degree as usize);
+        self.final_graph
+            .write_vertex_and_neighbors(self.start)?
+            .set_neighbors(AdjacencyList::from(start_neighbors));
+
+        let timer = Timer::new();
+        self.prune_vectors = self.quantize_prune_vectors(self.configuration.max_points)?;
+
+        let logger =
+            IndexLogger::new(total_num_points).with_progress(self.progress_notifier.clone());
+        self.execute_parallel(0..total_num_points, |idx| {
+            self.merge_vertex_id(idx as u32)?;
+            logger.vertex_processed()?;
+
+            Ok(())
+        })?;
+
+        let visit_order: Vec<u32> = (0..total_num_points as u32).collect();
+        self.cleanup_graph(&visit_order)?;
+        self.prune_vectors = None;
+        info!("{}", timer.elapsed_seconds_for_step("Merge time: "));
+
+        self.print_stats()?;
+
+        if let Some(notifier) = &self.event_notifier {
+            notifier.notify_merge(index_files.len());
+        }
+
+        Ok(())
+    }
+
+    fn build_from_vectors(&mut self, vectors: &[Vec<T>]) -> ANNResult<()> {
+        self.check_no_write_ahead_log("build")?;
+        self.expand_graph()?;
+        self.reset_streamed_points();
+
+        if vectors.len() > self.configuration.max_points {
+            return Err(ANNError::log_index_error(format!(
+                "ERROR: Requested building with {} vectors, but index can support only {} points as specified in configuration.",
+                vectors.len(), self.configuration.max_points
+            )));
+        }
+        self.check_vector_dimensions(vectors)?;
+
+        if self.configuration.use_pq_dist {
+            return Err(ANNError::log_index_config_error(
+                "use_pq_dist".to_string(),
+                "PQ distance is not supported when building from vectors".to_string(),
+            ));
+        }
+
+        self.configuration.start_thread_pool()?;
+
+        self.dataset.build_from_vectors(vectors)?;
+
+        self.num_active_pts = vectors.len();
+        self.build_with_data_populated()
+    }
+
+    fn build_from_rows(&mut self, rows: &[T]) -> ANNResult<()> {
+        self.check_no_write_ahead_log("build")?;
+        self.expand_graph()?;
+        self.reset_streamed_points();
+
+        let dim = self.configuration.dim;
+        if !rows.len().is_multiple_of(dim) {
+            return Err(ANNError::log_index_error(format!(
+                "ERROR: {} values aren't whole rows of the {} dimensions of the index.",
+                rows.len(),
+                dim
+            )));
+        }
+        let num_points = rows.len() / dim;
+        if num_points > self.configuration.max_points {
+            return Err(ANNError::log_index_error(format!(
+                "ERROR: Requested building with {} vectors, but index can support only {} points as specified in configuration.",
+                num_points, self.configuration.max_points
+            )));
+        }
+
+        if self.configuration.use_pq_dist {
+            return Err(ANNError::log_index_config_error(
+                "use_pq_dist".to_string(),
+                "PQ distance is not supported when building from rows".to_string(),
+            ));
+        }
+
+        self.configuration.start_thread_pool()?;
+
+        self.dataset.build_from_rows(rows, dim)?;
+
+        self.num_active_pts = num_points;
+        self.build_with_data_populated()
+    }
+
+    fn build_from_source(&mut self, source: &mut dyn DatasetSource<T>) -> ANNResult<()> {
+        self.check_no_write_ahead_log("build")?;
+        self.expand_graph()?;
+        self.reset_streamed_points();
+
+        let dim = self.configuration.dim;
+        if source.dimension() != dim {
+            return Err(ANNError::log_index_error(format!(
+                "ERROR: Source has {} dimensions, but index has {} dimensions.",
+                source.dimension(),
+                dim
+            )));
+        }
+        let max_points = self.configuration.max_points;
+        if let Some(len) = source.len_hint().filter(|&len| len > max_points) {
+            return Err(ANNError::log_index_error(format!(
+                "ERROR: Source has {} vectors, but index can support only {} points as specified in configuration.",
+                len, self.configuration.max_points
+            )));
+        }
+
+        if self.configuration.use_pq_dist {
+            return Err(ANNError::log_index_config_error(
+                "use_pq_dist".to_string(),
+                "PQ distance is not supported when building from a source".to_string(),
+            ));
+        }
+
+        self.configuration.start_thread_pool()?;
+
+        let mut num_points = 0;
+        while let Some(batch) = source.next_batch()? {
+            if batch.dim != dim {
+                return Err(ANNError::log_index_error(format!(
+                    "ERROR: Source handed over a batch of {} dimensions, but index has {} dimensions.",
+                    batch.dim, dim
+                )));
+            }
+            if num_points + batch.num_points() > self.configuration.max_points {
+                return Err(ANNError::log_index_error(format!(
+                    "ERROR: Source has more than {} vectors, but index can support only {} points as specified in configuration.",
+                    num_points + batch.num_points() - 1, self.configuration.max_points
+                )));
+            }
+            num_points += self.dataset.copy_rows(&batch.rows, batch.dim, num_points)?;
+        }
+        info!("Streamed {} vectors into dataset.", num_points);
+
+        self.dataset.num_active_pts = num_points;
+        self.num_active_pts = num_points;
+        self.build_with_data_populated()
+    }
+
+    fn export_graph(
+        &self,
+        filename: &str,
+        format: GraphExportFormat,
+        sample_size: Option<usize>,
+    ) -> ANNResult<GraphExportSummary> {
+        let num_points = self.num_active_pts + self.configuration.num_frozen_pts;
+        let start = self.saved_vertex_id(self.start);
+        let mut exporter = GraphExporter::create(filename, format, num_points, start, sample_size)?;
+
+        // The frozen points are exported right after the active points, as save_graph does
+        let max_points = self.configuration.max_points;
+        let frozen_pts = max_points..max_points + self.configuration.num_frozen_pts;
+        let mut neighbors = Vec::new();
+        for i in (0..self.num_active_pts).chain(frozen_pts) {
+            self.copy_neighbors(i as u32, &mut neighbors)?;
+            for neighbor in neighbors.iter_mut() {
+                *neighbor = self.saved_vertex_id(*neighbor);
+            }
+            exporter.add_vertex(self.saved_vertex_id(i as u32), &neighbors)?;
+        }
+        exporter.finish()
+    }
+
+    fn insert_vectors(&mut self, vectors: &[Vec<T>]) -> ANNResult<()> {
+        self.check_no_write_ahead_log("insert a batch")?;
+        self.expand_graph()?;
+        self.absorb_streamed_points();
+        self.check_vector_dimensions(vectors)?;
+
+        if self.configuration.use_pq_dist {
+            return Err(ANNError::log_index_config_error(
+                "use_pq_dist".to_string(),
+                "PQ distance is not supported when inserting vectors".to_string(),
+            ));
+        }
+
+        self.dataset.append_from_vectors(vectors)?;
+
+        info!("Inserting {} vectors.", vectors.len());
+        self.insert_appended_points(vectors.len())
+    }
+
+    fn save(&mut self, filename: &str) -> ANNResult<()> {
+        let data_file = filename.to_string() + ".data";
+        let delete_file = filename.to_string() + ".delete";
+        let _output_lock = lock_index_output(filename)?;
+        self.absorb_streamed_points();
+        self.link_if_above_brute_force_threshold()?;
+
+        self.save_graph(filename)?;
+        if self.configuration.csr_graph {
+            self.save_csr_graph(&format!("{}.csr", filename))?;
+        }
+        self.save_data(data_file.as_str())?;
+        if self.save_delete_list(delete_file.as_str())? == 0 && file_exists(&delete_file) {
+            std::fs::remove_file(&delete_file)?;
+        }
+        self.save_entry_points(&format!("{}.entry_points", filename))?;
+        let labels_file = format!("{}.labels", filename);
+        if self.point_metadata.has_labels() {
+            self.point_metadata.save_labels(&labels_file)?;
+        } else if file_exists(&labels_file) {
+            std::fs::remove_file(&labels_file)?;
+        }
+        let payloads_file = format!("{}.payloads", filename);
+        if self.point_metadata.has_payloads() {
+            self.point_metadata.save_payloads(&payloads_file)?;
+        } else if file_exists(&payloads_file) {
+            std::fs::remove_file(&payloads_file)?;
         }
+        let tags_file = format!("{}.tags", filename);
+        let tag_store = self.read_tag_store()?;
+        if !tag_store.is_empty() {
+            tag_store.save(&tags_file)?;
+        } else if file_exists(&tags_file) {
+            std::fs::remove_file(&tags_file)?;
+        }
+        drop(tag_store);
+        let documents_file = format!("{}.documents", filename);
+        if self.documents.has_documents() {
+            self.documents.save(&documents_file)?;
+        } else if file_exists(&documents_file) {
+            std::fs::remove_file(&documents_file)?;
+        }
+
+        // The snapshot holds every logged change. Replaying the log after a crash before it
+        // is emptied skips the inserts the snapshot has.
+        if let Some(write_ahead_log) = self.write_ahead_log.as_mut() {
+            write_ahead_log
+                .get_mut()
+                .map_err(|_| {
+                    ANNError::log_lock_poison_error(
+                        "Poisoned lock on the write-ahead log. Can't empty it.".to_string(),
+                    )
+                })?
+                .truncate()?;
+        }
+
+        Ok(())
+    }
 
+    fn load(&mut self, filename: &str, expected_num_points: usize) -> ANNResult<()> {
+        self.check_no_write_ahead_log("load")?;
+        let _input_lock = lock_index_input(filename)?;
+        let num_points = self.start_load(expected_num_points)?;
         self.dataset
-            .append_from_file(filename, num_points_to_insert)?;
-        self.final_graph.extend(
-            num_points_to_insert,
-            self.configuration.index_write_parameter.max_degree,
-        );
+            .build_from_file(&format!("{}.data", filename), expected_num_points)?;
+        self.dataset.num_active_pts = num_points;
+
+        // Before the graph, whose deleted points may have no neighbors
+        self.load_delete_list(&format!("{}.delete", filename))?;
+        let csr_file = format!("{}.csr", filename);
+        if self.configuration.csr_graph && file_exists(&csr_file) {
+            self.load_csr_graph(&csr_file, expected_num_points)?;
+        } else {
+            self.load_graph(filename, expected_num_points)?;
+        }
+        self.relocate_loaded_frozen_points(num_points)?;
+        self.load_entry_points(&format!("{}.entry_points", filename))?;
+        let labels_file = format!("{}.labels", filename);
+        self.point_metadata = PointMetadataStore::new(self.configuration.max_points);
+        if file_exists(&labels_file) {
+            self.point_metadata.load_labels(&labels_file)?;
+        }
+        let payloads_file = format!("{}.payloads", filename);
+        if file_exists(&payloads_file) {
+            self.point_metadata.load_payloads(&payloads_file)?;
+        }
+        let tags_file = format!("{}.tags", filename);
+        let mut tag_store = self.write_tag_store()?;
+        *tag_store = TagStore::new(self.configuration.max_points);
+        if file_exists(&tags_file) {
+            tag_store.load(&tags_file)?;
+        }
+        drop(tag_store);
+        let documents_file = format!("{}.documents", filename);
+        self.documents = DocumentStore::new(self.configuration.max_points);
+        if file_exists(&documents_file) {
+            self.documents.load(&documents_file)?;
+        }
+
+        self.finish_load()
+    }
+
+    fn save_to_provider(&mut self, provider: &mut dyn StorageProvider<T>) -> ANNResult<()> {
+        self.absorb_streamed_points();
+        self.link_if_above_brute_force_threshold()?;
+        self.save_nodes(provider)?;
+        Ok(())
+    }
+
+    fn set_node_store(&mut self, store: Box<dyn StorageProvider<T> + Send>) -> ANNResult<()> {
+        self.absorb_streamed_points();
+        self.link_if_above_brute_force_threshold()?;
+        InmemIndex::set_node_store(self, store)?;
+        Ok(())
+    }
+
+    fn load_from_provider(&mut self, provider: &dyn StorageProvider<T>) -> ANNResult<()> {
+        self.check_no_write_ahead_log("load")?;
+        let num_points = self.start_load(provider.metadata()?.num_points)?;
+        self.load_nodes(provider)?;
+        self.dataset.num_active_pts = num_points;
+        self.relocate_loaded_frozen_points(num_points)?;
+        self.entry_points.clear();
+        *self.write_tag_store()? = TagStore::new(self.configuration.max_points);
+        self.documents = DocumentStore::new(self.configuration.max_points);
+
+        self.finish_load()
+    }
+
+    fn search(
+        &self,
+        query: &[T],
+        k_value: usize,
+        l_value: u32,
+        indices: &mut [u32],
+    ) -> ANNResult<u32> {
+        let query = padded_query::<T, N>(query)?;
+        let query_vector = Vertex::new(&query, 0);
+        InmemIndex::search(self, &query_vector, k_value, l_value, indices)
+    }
+
+    fn search_with_visitor(
+        &self,
+        query: &[T],
+        k_value: usize,
+        l_value: u32,
+        visitor: &mut dyn SearchVisitor,
+        indices: &mut [u32],
+    ) -> ANNResult<u32> {
+        let query = padded_query::<T, N>(query)?;
+        let query_vector = Vertex::new(&query, 0);
+        InmemIndex::search_with_visitor(self, &query_vector, k_value, l_value, visitor, indices)
+    }
+
+    fn search_with_weights(
+        &self,
+        query: &[T],
+        weights: &[f32],
+        k_value: usize,
+        l_value: u32,
+        indices: &mut [u32],
+    ) -> ANNResult<u32> {
+        let query = padded_query::<T, N>(query)?;
+        let query_vector = Vertex::new(&query, 0);
+        InmemIndex::search_with_weights(self, &query_vector, weights, k_value, l_value, indices)
+    }
+
+    fn search_in_subspace(
+        &self,
+        query: &[T],
+        dims: Range<usize>,
+        k_value: usize,
+        l_value: u32,
+        indices: &mut [u32],
+    ) -> ANNResult<u32> {
+        let query = padded_query::<T, N>(query)?;
+        let query_vector = Vertex::new(&query, 0);
+        InmemIndex::search_in_subspace(self, &query_vector, dims, k_value, l_value, indices)
+    }
+
+    fn search_with_params(
+        &self,
+        query: &[T],
+        k_value: usize,
+        params: &SearchParams,
+        indices: &mut [u32],
+    ) -> ANNResult<u32> {
+        let query = padded_query::<T, N>(query)?;
+        let query_vector = Vertex::new(&query, 0);
+        InmemIndex::search_with_params(self, &query_vector, k_value, params, indices)
+    }
+
+    fn search_with_details(
+        &self,
+        query: &[T],
+        k_value: usize,
+        l_value: u32,
+        fields: SearchResultFields,
+    ) -> ANNResult<(Vec<SearchResult>, QueryStats)> {
+        let query = padded_query::<T, N>(query)?;
+        let query_vector = Vertex::new(&query, 0);
+        InmemIndex::search_with_details(self, &query_vector, k_value, l_value, fields)
+    }
+
+    fn search_with_params_and_stats(
+        &self,
+        query: &[T],
+        k_value: usize,
+        params: &SearchParams,
+        indices: &mut [u32],
+    ) -> ANNResult<QueryStats> {
+        let query = padded_query::<T, N>(query)?;
+        let query_vector = Vertex::new(&query, 0);
+        InmemIndex::search_with_params_and_stats(self, &query_vector, k_value, params, indices)
+    }
+
+    fn search_with_filter(
+        &self,
+        query: &[T],
+        filter: &LabelFilter,
+        k_value: usize,
+        l_value: u32,
+        indices: &mut [u32],
+    ) -> ANNResult<usize> {
+        let query = padded_query::<T, N>(query)?;
+        let query_vector = Vertex::new(&query, 0);
+        InmemIndex::search_with_filter(self, &query_vector, filter, k_value, l_value, indices)
+    }
+
+    fn insert_point(&self, vector: &[T]) -> ANNResult<u32> {
+        InmemIndex::insert_point(self, vector)
+    }
+
+    fn delete_point(&self, vertex_id: u32) -> ANNResult<()> {
+        InmemIndex::delete_point(self, vertex_id)
+    }
 
-        // TODO: this should not consider frozen points
-        let previous_last_pt = self.num_active_pts;
-        self.num_active_pts += num_points_to_insert;
-        self.configuration.max_points += num_points_to_insert;
+    fn open_write_ahead_log(&mut self, wal_file: &str) -> ANNResult<usize> {
+        InmemIndex::open_write_ahead_log(self, wal_file)
+    }
 
-        println!("Inserting {} vectors from file.", num_points_to_insert);
+    fn sync_write_ahead_log(&self) -> ANNResult<()> {
+        InmemIndex::sync_write_ahead_log(self)
+    }
 
-        // TODO: tag_lock
-        let logger = IndexLogger::new(num_points_to_insert);
-        let timer = Timer::new();
-        execute_with_rayon(
-            previous_last_pt..self.num_active_pts,
-            self.configuration.index_write_parameter.num_threads,
-            |idx| {
-                self.insert_vertex_id(idx as u32)?;
-                logger.vertex_processed()?;
+    fn insert_point_with_tag(&self, vector: &[T], tag: Tag) -> ANNResult<u32> {
+        InmemIndex::insert_point_with_tag(self, vector, tag)
+    }
 
-                Ok(())
-            },
-        )?;
+    fn set_point_tag(&self, vertex_id: u32, tag: Tag) -> ANNResult<()> {
+        InmemIndex::set_point_tag(self, vertex_id, tag)
+    }
 
-        let mut visit_order =
-            Vec::with_capacity(self.num_active_pts + self.configuration.num_frozen_pts);
-        for i in 0..self.num_active_pts {
-            visit_order.push(i as u32);
-        }
+    fn delete_tag(&self, tag: &Tag) -> ANNResult<u32> {
+        InmemIndex::delete_tag(self, tag)
+    }
 
-        self.cleanup_graph(&visit_order)?;
-        println!("{}", timer.elapsed_seconds_for_step("Insert time: "));
+    fn calibrate_search_list_size(
+        &self,
+        queries: &[Vec<T>],
+        ground_truth: &[Vec<u32>],
+        k_value: usize,
+        target_recall: f32,
+        max_l_value: u32,
+    ) -> ANNResult<SearchListCalibration> {
+        InmemIndex::calibrate_search_list_size(
+            self,
+            queries,
+            ground_truth,
+            k_value,
+            target_recall,
+            max_l_value,
+        )
+    }
 
-        self.print_stats()?;
+    fn search_with_target_recall(
+        &self,
+        query: &[T],
+        calibration: &SearchListCalibration,
+        indices: &mut [u32],
+    ) -> ANNResult<u32> {
+        let query = padded_query::<T, N>(query)?;
+        let query_vector = Vertex::new(&query, 0);
+        InmemIndex::search_with_target_recall(self, &query_vector, calibration, indices)
+    }
 
-        Ok(())
+    fn search_tags(&self, query: &[T], k_value: usize, l_value: u32) -> ANNResult<Vec<Tag>> {
+        let query = padded_query::<T, N>(query)?;
+        let query_vector = Vertex::new(&query, 0);
+        InmemIndex::search_tags(self, &query_vector, k_value, l_value)
     }
 
-    fn save(&mut self, filename: &str) -> ANNResult<()> {
-        let data_file = filename.to_string() + ".data";
-        let delete_file = filename.to_string() + ".delete";
+    fn warm_up(&self, num_nodes: usize) -> ANNResult<usize> {
+        InmemIndex::warm_up(self, num_nodes)
+    }
 
-        self.save_graph(filename)?;
-        self.save_data(data_file.as_str())?;
-        self.save_delete_list(delete_file.as_str())?;
+    fn compact_graph(&mut self) -> ANNResult<()> {
+        InmemIndex::compact_graph(self)
+    }
 
-        Ok(())
+    fn set_event_notifier(&mut self, notifier: Arc<IndexEventNotifier>) {
+        InmemIndex::set_event_notifier(self, notifier)
     }
 
-    fn load(&mut self, filename: &str, expected_num_points: usize) -> ANNResult<()> {
-        self.num_active_pts = expected_num_points;
-        self.dataset
-            .build_from_file(&format!("{}.data", filename), expected_num_points)?;
+    fn set_progress_notifier(&mut self, notifier: Arc<ProgressNotifier>) {
+        InmemIndex::set_progress_notifier(self, notifier)
+    }
 
-        self.load_graph(filename, expected_num_points)?;
-        self.load_delete_list(&format!("{}.delete", filename))?;
+    fn set_point_labels(&mut self, vertex_id: u32, labels: Vec<u32>) -> ANNResult<()> {
+        self.point_metadata.set_labels(vertex_id, labels)
+    }
 
-        if self.query_scratch_queue.size()? == 0 {
-            self.initialize_query_scratch(
-                5 + self.configuration.index_write_parameter.num_threads,
-                self.configuration.index_write_parameter.search_list_size,
-            )?;
-        }
+    fn set_point_payload(&mut self, vertex_id: u32, payload: Vec<u8>) -> ANNResult<()> {
+        self.point_metadata.set_payload(vertex_id, payload)
+    }
 
-        Ok(())
+    fn set_point_document(&mut self, vertex_id: u32, document: Tag) -> ANNResult<()> {
+        self.documents.set_document(vertex_id, document)
     }
 
-    fn search(
+    fn search_documents(
         &self,
         query: &[T],
         k_value: usize,
         l_value: u32,
-        indices: &mut [u32],
-    ) -> ANNResult<u32> {
-        let query_vector = Vertex::new(<&[T; N]>::try_from(query)?, 0);
-        InmemIndex::search(self, &query_vector, k_value, l_value, indices)
+        aggregation: DocumentAggregation,
+    ) -> ANNResult<Vec<DocumentMatch>> {
+        let query = padded_query::<T, N>(query)?;
+        let query_vector = Vertex::new(&query, 0);
+        InmemIndex::search_documents(self, &query_vector, k_value, l_value, aggregation)
+    }
+
+    fn consolidate_deletes(&mut self) -> ANNResult<usize> {
+        InmemIndex::consolidate_deletes(self)
     }
 
     fn soft_delete(
@@ -752,40 +2733,46 @@ where
         vertex_ids_to_delete: Vec<u32>,
         num_points_to_delete: usize,
     ) -> ANNResult<()> {
-        println!("Deleting {} vectors from file.", num_points_to_delete);
+        info!("Deleting {} vectors from file.", num_points_to_delete);
+        self.absorb_streamed_points();
 
-        let logger = IndexLogger::new(num_points_to_delete);
+        let logger =
+            IndexLogger::new(num_points_to_delete).with_progress(self.progress_notifier.clone());
         let timer = Timer::new();
 
-        execute_with_rayon(
-            0..num_points_to_delete,
-            self.configuration.index_write_parameter.num_threads,
-            |idx: usize| {
-                self.soft_delete_vertex(vertex_ids_to_delete[idx])?;
-                logger.vertex_processed()?;
+        self.execute_parallel(0..num_points_to_delete, |idx: usize| {
+            self.soft_delete_vertex(vertex_ids_to_delete[idx])?;
+            logger.vertex_processed()?;
 
-                Ok(())
-            },
-        )?;
+            Ok(())
+        })?;
 
-        println!("{}", timer.elapsed_seconds_for_step("Delete time: "));
+        info!("{}", timer.elapsed_seconds_for_step("Delete time: "));
         self.print_stats()?;
 
+        if let Some(notifier) = &self.event_notifier {
+            notifier.notify_deletion(vertex_ids_to_delete[..num_points_to_delete].to_vec());
+        }
+
         Ok(())
     }
 }
 
 #[cfg(test)]
 mod index_test {
-    use vector::Metric;
+    use vector::{HaversineDistance, Half, Metric};
 
     use super::*;
     use crate::{
         model::{
-            configuration::index_write_parameters::IndexWriteParametersBuilder, vertex::DIM_128,
+            configuration::index_write_parameters::IndexWriteParametersBuilder,
+            configuration::{EntryPointSelection, IndexConfigurationBuilder, PruneQuantization},
+            data_store::SliceSource,
+            vertex::{DIM_104, DIM_128},
         },
         test_utils::get_test_file_path,
-        utils::file_util::load_ids_to_delete_from_file,
+        test_utils::inmem_index_initialization::create_index_with_test_data,
+        utils::file_util::{load_ids_to_delete_from_file, save_data_in_base_dimensions},
         utils::round_up,
     };
 
@@ -798,69 +2785,590 @@ mod index_test {
     const L: u32 = 50;
     const ALPHA: f32 = 1.2;
 
-    /// Build the index with TEST_DATA_FILE and compare the index graph with truth graph TRUTH_GRAPH
-    /// Change above constants if you want to test with different dataset
-    macro_rules! index_end_to_end_test_singlethread {
-        ($saturate_graph:expr, $truth_graph:expr) => {{
-            let (data_num, dim) =
-                load_metadata_from_file(get_test_file_path(TEST_DATA_FILE).as_str()).unwrap();
+    /// Build the index with TEST_DATA_FILE and compare the index graph with truth graph TRUTH_GRAPH
+    /// Change above constants if you want to test with different dataset
+    macro_rules! index_end_to_end_test_singlethread {
+        ($saturate_graph:expr, $truth_graph:expr) => {{
+            let (data_num, dim) =
+                load_metadata_from_file(get_test_file_path(TEST_DATA_FILE).as_str()).unwrap();
+
+            let index_write_parameters = IndexWriteParametersBuilder::new(L, R)
+                .with_alpha(ALPHA)
+                .with_num_threads(1)
+                .with_saturate_graph($saturate_graph)
+                .build();
+            let config = IndexConfiguration::new(
+                Metric::L2,
+                dim,
+                round_up(dim as u64, 16_u64) as usize,
+                data_num,
+                false,
+                0,
+                false,
+                0,
+                1.0f32,
+                index_write_parameters,
+            );
+            let mut index: InmemIndex<f32, DIM_128> = InmemIndex::new(config.clone()).unwrap();
+
+            index
+                .build(get_test_file_path(TEST_DATA_FILE).as_str(), data_num)
+                .unwrap();
+
+            let mut truth_index: InmemIndex<f32, DIM_128> = InmemIndex::new(config).unwrap();
+            truth_index
+                .load_graph(get_test_file_path($truth_graph).as_str(), data_num)
+                .unwrap();
+
+            compare_graphs(&index, &truth_index);
+        }};
+    }
+
+    #[test]
+    fn index_end_to_end_test_singlethread() {
+        index_end_to_end_test_singlethread!(false, TRUTH_GRAPH);
+    }
+
+    #[test]
+    fn index_end_to_end_test_singlethread_with_saturate_graph() {
+        index_end_to_end_test_singlethread!(true, TRUTH_GRAPH_WITH_SATURATED);
+    }
+
+    #[test]
+    fn build_from_npy_rows_matches_truth_graph() {
+        let (data, data_num, dim) =
+            crate::utils::load_bin::<f32>(get_test_file_path(TEST_DATA_FILE).as_str(), 0).unwrap();
+        let npy_file = "build_from_npy_rows_matches_truth_graph.npy";
+        crate::utils::save_npy(npy_file, &data, data_num, dim).unwrap();
+        let npy = crate::utils::NpyFile::open(npy_file).unwrap();
+
+        let index_write_parameters = IndexWriteParametersBuilder::new(L, R)
+            .with_alpha(ALPHA)
+            .with_num_threads(1)
+            .build();
+        let config = IndexConfiguration::new(
+            Metric::L2,
+            dim,
+            round_up(dim as u64, 16_u64) as usize,
+            data_num,
+            false,
+            0,
+            false,
+            0,
+            1.0f32,
+            index_write_parameters,
+        );
+        let mut index: InmemIndex<f32, DIM_128> = InmemIndex::new(config.clone()).unwrap();
+        let rows = npy.rows::<f32>().unwrap();
+        assert!(index.build_from_rows(&rows[1..]).is_err());
+        index.build_from_rows(rows).unwrap();
+        drop(npy);
+        std::fs::remove_file(npy_file).unwrap();
+
+        let mut truth_index: InmemIndex<f32, DIM_128> = InmemIndex::new(config).unwrap();
+        truth_index
+            .load_graph(get_test_file_path(TRUTH_GRAPH).as_str(), data_num)
+            .unwrap();
+        compare_graphs(&index, &truth_index);
+    }
+
+    #[cfg(feature = "gpu")]
+    fn sift_config_on_the_gpu(num_threads: u32) -> (IndexConfiguration, usize) {
+        let (data_num, dim) =
+            load_metadata_from_file(get_test_file_path(TEST_DATA_FILE).as_str()).unwrap();
+        let index_write_parameters = IndexWriteParametersBuilder::new(L, R)
+            .with_alpha(ALPHA)
+            .with_num_threads(num_threads)
+            .build();
+        let config = IndexConfiguration::new(
+            Metric::L2,
+            dim,
+            round_up(dim as u64, 16_u64) as usize,
+            data_num,
+            false,
+            0,
+            false,
+            0,
+            1.0f32,
+            index_write_parameters,
+        );
+        // The CI machines have no GPU, Mesa's llvmpipe runs the shader on the CPU
+        let gpu_distance = crate::index::GpuDistance::software()
+            .unwrap()
+            .expect("the gpu tests need a software adapter, e.g. Mesa's llvmpipe");
+        (config.with_gpu_distance(Arc::new(gpu_distance)), data_num)
+    }
+
+    #[cfg(feature = "gpu")]
+    #[test]
+    fn pruning_with_gpu_pool_distances_matches_the_cpu_prune() {
+        let (config, data_num) = sift_config_on_the_gpu(1);
+        let mut index: InmemIndex<f32, DIM_128> = InmemIndex::new(config.clone()).unwrap();
+        index
+            .build(get_test_file_path(TEST_DATA_FILE).as_str(), data_num)
+            .unwrap();
+        let gpu_distance = index.gpu_distance.clone().unwrap();
+
+        let mut scratch_manager =
+            ScratchStoreManager::new(index.query_scratch_queue.clone(), Duration::from_millis(10))
+                .unwrap();
+        let scratch = scratch_manager.scratch_space().unwrap();
+        let max_occlusion_size = config.index_write_parameter.max_occlusion_size as usize;
+        for vertex_id in 0..data_num as u32 {
+            crate::model::Scratch::clear(scratch);
+            let vertex = index.dataset.get_vertex(vertex_id).unwrap();
+            let mut pool = index.search_for_point(&vertex, scratch).unwrap();
+            SortedNeighborVector::new(&mut pool);
+            pool.truncate(max_occlusion_size);
+
+            let mut vectors = Vec::new();
+            for neighbor in &pool {
+                vectors.extend_from_slice(index.dataset.get_vertex(neighbor.id).unwrap().vector());
+            }
+            // The SIFT vectors are integers, so the GPU sums their distances exactly
+            let distances = gpu_distance
+                .pool_distances(&vectors, &[pool.len()], DIM_128, Metric::L2)
+                .unwrap()
+                .unwrap();
+
+            let mut cpu_pruned = AdjacencyList::for_range(R as usize);
+            index
+                .prune_neighbors(vertex_id, &mut pool.clone(), &mut cpu_pruned, scratch)
+                .unwrap();
+            let mut gpu_pruned = AdjacencyList::for_range(R as usize);
+            index
+                .prune_neighbors_with_pool_distances(
+                    vertex_id,
+                    &mut pool,
+                    &distances,
+                    &mut gpu_pruned,
+                    scratch,
+                )
+                .unwrap();
+            assert_eq!(*gpu_pruned, *cpu_pruned, "vertex {}", vertex_id);
+        }
+    }
+
+    #[cfg(feature = "gpu")]
+    #[test]
+    fn build_pruning_on_the_gpu_finds_the_points_like_the_cpu() {
+        fn points_found(config: IndexConfiguration, data_num: usize) -> usize {
+            let mut index: InmemIndex<f32, DIM_128> = InmemIndex::new(config).unwrap();
+            index
+                .build(get_test_file_path(TEST_DATA_FILE).as_str(), data_num)
+                .unwrap();
+            (0..data_num as u32)
+                .filter(|&vertex_id| {
+                    let query = index.dataset.get_vertex(vertex_id).unwrap();
+                    let mut indices = [0u32; 1];
+                    index.search(&query, 1, L, &mut indices).unwrap();
+                    indices[0] == vertex_id
+                })
+                .count()
+        }
+
+        let (config, data_num) = sift_config_on_the_gpu(4);
+        let found_on_gpu = points_found(config.clone(), data_num);
+        let found_on_cpu = points_found(
+            IndexConfiguration {
+                gpu_distance: None,
+                ..config
+            },
+            data_num,
+        );
+        // The batches and the threads of the CPU build each move a few points
+        assert!(
+            found_on_gpu + data_num / 20 >= found_on_cpu,
+            "found {} points on the GPU, {} on the CPU",
+            found_on_gpu,
+            found_on_cpu
+        );
+    }
+
+    #[test]
+    fn index_end_to_end_test_multithread() {
+        let (data_num, dim) =
+            load_metadata_from_file(get_test_file_path(TEST_DATA_FILE).as_str()).unwrap();
+
+        let index_write_parameters = IndexWriteParametersBuilder::new(L, R)
+            .with_alpha(ALPHA)
+            .with_num_threads(8)
+            .build();
+        let config = IndexConfiguration::new(
+            Metric::L2,
+            dim,
+            round_up(dim as u64, 16_u64) as usize,
+            data_num,
+            false,
+            0,
+            false,
+            0,
+            1f32,
+            index_write_parameters,
+        );
+        let mut index: InmemIndex<f32, DIM_128> = InmemIndex::new(config).unwrap();
+
+        index
+            .build(get_test_file_path(TEST_DATA_FILE).as_str(), data_num)
+            .unwrap();
+
+        for i in 0..index.final_graph.size() {
+            assert_ne!(
+                index
+                    .final_graph
+                    .read_vertex_and_neighbors(i as u32)
+                    .unwrap()
+                    .size(),
+                0
+            );
+        }
+    }
+
+    #[test]
+    fn search_with_details_populates_requested_fields() {
+        let mut index = create_index_with_test_data();
+        index.initialize_query_scratch(1, L).unwrap();
+
+        let start = index.start;
+        let neighbors: Vec<u32> = (0..4).filter(|id| *id != start).collect();
+        index
+            .final_graph
+            .write_vertex_and_neighbors(start)
+            .unwrap()
+            .set_neighbors(AdjacencyList::from(neighbors.clone()));
+        index.point_metadata.set_labels(start, vec![3]).unwrap();
+        index.point_metadata.set_payload(start, b"medoid".to_vec()).unwrap();
+        for &id in &neighbors {
+            index
+                .write_tag_store()
+                .unwrap()
+                .set_tag(id, Tag::Id(100 + id as u64))
+                .unwrap();
+        }
+
+        let query = index.dataset.get_vertex(start).unwrap();
+        let (results, query_stats) = index
+            .search_with_details(&query, 2, L, SearchResultFields::ALL)
+            .unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].id, start);
+        assert_eq!(results[0].distance, 0.0);
+        assert_eq!(results[0].labels, Some(vec![3]));
+        assert_eq!(results[0].payload, Some(b"medoid".to_vec()));
+        assert_eq!(results[1].labels, Some(vec![]));
+        // Ids are vertex ids, the tags resolve them to the external ids
+        assert_eq!(results[0].tag, None);
+        assert_eq!(results[1].tag, Some(Tag::Id(100 + results[1].id as u64)));
+        assert!(query_stats.n_cmps as usize >= neighbors.len());
+        assert!(query_stats.n_hops >= 1);
+
+        let (results, _) = index
+            .search_with_details(&query, 2, L, SearchResultFields::NONE)
+            .unwrap();
+        assert_eq!(results[0].labels, None);
+        assert_eq!(results[0].payload, None);
+        assert_eq!(results[1].tag, None);
+    }
+
+    #[test]
+    fn search_with_visitor_sees_every_expansion() {
+        struct ExpansionLog(Vec<(u32, f32, usize)>);
+
+        impl SearchVisitor for ExpansionLog {
+            fn node_expanded(&mut self, id: u32, distance: f32, hop: usize) {
+                self.0.push((id, distance, hop));
+            }
+        }
+
+        let mut index = create_index_with_test_data();
+        index.initialize_query_scratch(1, L).unwrap();
+
+        let start = index.start;
+        let neighbors: Vec<u32> = (0..4).filter(|id| *id != start).collect();
+        index
+            .final_graph
+            .write_vertex_and_neighbors(start)
+            .unwrap()
+            .set_neighbors(AdjacencyList::from(neighbors));
+
+        let query = index.dataset.get_vertex(start).unwrap();
+        let mut log = ExpansionLog(Vec::new());
+        let mut indices = [0u32; 2];
+        index
+            .search_with_visitor(&query, 2, L, &mut log, &mut indices)
+            .unwrap();
+
+        assert_eq!(indices[0], start);
+        assert_eq!(log.0.first(), Some(&(start, 0.0, 0)));
+        for (hop, &(_, _, visited_hop)) in log.0.iter().enumerate() {
+            assert_eq!(visited_hop, hop);
+        }
+
+        let mut indices_without_visitor = [0u32; 2];
+        index
+            .search(&query, 2, L, &mut indices_without_visitor)
+            .unwrap();
+        assert_eq!(indices, indices_without_visitor);
+    }
+
+    #[test]
+    fn search_with_filter_returns_matching_points() {
+        let mut index = create_index_with_test_data();
+        index
+            .load_graph(get_test_file_path(TRUTH_GRAPH).as_str(), 256)
+            .unwrap();
+        index.initialize_query_scratch(1, L).unwrap();
+        for id in 0..256u32 {
+            let mut labels = vec![id % 4];
+            if id % 16 == 0 {
+                labels.push(10);
+            }
+            index.point_metadata.set_labels(id, labels).unwrap();
+        }
+
+        let filters = [
+            LabelFilter::Label(1),
+            LabelFilter::all_of(&[0, 10]),
+            LabelFilter::any_of(&[2, 10]),
+        ];
+        let (mut found, mut expected) = (0, 0);
+        for filter in filters.iter() {
+            for query_id in (0..256).step_by(16) {
+                let mut truth: Vec<(f32, u32)> = (0..256)
+                    .filter(|&id| filter.matches(index.point_metadata.labels(id)))
+                    .map(|id| (index.get_distance(query_id, id).unwrap(), id))
+                    .collect();
+                truth.sort_by(|a, b| a.0.total_cmp(&b.0));
+                truth.truncate(5);
+
+                let query = index.dataset.get_vertex(query_id).unwrap();
+                let mut indices = [u32::MAX; 5];
+                let num_results = index
+                    .search_with_filter(&query, filter, 5, L, &mut indices)
+                    .unwrap();
+                assert_eq!(num_results, 5);
+                for id in indices {
+                    assert!(filter.matches(index.point_metadata.labels(id)));
+                }
 
-            let index_write_parameters = IndexWriteParametersBuilder::new(L, R)
-                .with_alpha(ALPHA)
-                .with_num_threads(1)
-                .with_saturate_graph($saturate_graph)
-                .build();
-            let config = IndexConfiguration::new(
-                Metric::L2,
-                dim,
-                round_up(dim as u64, 16_u64) as usize,
-                data_num,
-                false,
-                0,
-                false,
-                0,
-                1.0f32,
-                index_write_parameters,
-            );
-            let mut index: InmemIndex<f32, DIM_128> = InmemIndex::new(config.clone()).unwrap();
+                found += indices
+                    .iter()
+                    .filter(|id| truth.iter().any(|t| t.1 == **id))
+                    .count();
+                expected += truth.len();
+            }
+        }
+        // The R=4 graph mostly links matching points through other points
+        assert!(found as f32 / expected as f32 > 0.9);
 
-            index
-                .build(get_test_file_path(TEST_DATA_FILE).as_str(), data_num)
-                .unwrap();
+        let query = index.dataset.get_vertex(0).unwrap();
+        let mut indices = [0u32; 5];
+        let num_results = index
+            .search_with_filter(&query, &LabelFilter::Label(99), 5, L, &mut indices)
+            .unwrap();
+        assert_eq!(num_results, 0);
+    }
 
-            let mut truth_index: InmemIndex<f32, DIM_128> = InmemIndex::new(config).unwrap();
-            truth_index
-                .load_graph(get_test_file_path($truth_graph).as_str(), data_num)
+    #[test]
+    fn geo_index_with_haversine_distance() {
+        // 1 degree grid around the antimeridian, where lat/lon as plane coordinates mislead L2
+        let points: Vec<Vec<f32>> = (0..20)
+            .flat_map(|i| {
+                (0..20).map(move |j| vec![i as f32 - 10.0, ((350 + j) % 360) as f32 - 180.0])
+            })
+            .collect();
+
+        let index_write_parameters = IndexWriteParametersBuilder::new(L, R)
+            .with_alpha(ALPHA)
+            .with_num_threads(1)
+            .build();
+        let config = IndexConfiguration::new(
+            Metric::L2,
+            2,
+            8,
+            points.len(),
+            false,
+            0,
+            false,
+            0,
+            1f32,
+            index_write_parameters,
+        );
+        let distance = HaversineDistance::default();
+        let mut index = InmemIndex::<f32, 8, _>::with_distance(config, distance).unwrap();
+        index.build_from_vectors(&points).unwrap();
+
+        for (lat, lon) in [(0.3, 179.9), (-4.6, -175.2), (8.8, 171.4)] {
+            let distance_to = |p: &[f32]| distance.between(lat, lon, p[0], p[1]);
+            let mut truth: Vec<f32> = points.iter().map(|p| distance_to(p)).collect();
+            truth.sort_by(|a, b| a.total_cmp(b));
+
+            let mut indices = [0u32; 4];
+            ANNInmemIndex::search(&index, &[lat, lon], 4, L, &mut indices).unwrap();
+            for (id, truth_distance) in indices.iter().zip(truth.iter()) {
+                assert_eq!(distance_to(&points[*id as usize]), *truth_distance);
+            }
+        }
+    }
+
+    #[test]
+    fn brute_force_below_threshold_then_graph() {
+        let vectors: Vec<Vec<f32>> = (0..96)
+            .map(|i| (0..DIM_128).map(|d| ((i * 31 + d * 17) % 97) as f32).collect())
+            .collect();
+        let index_write_parameters = IndexWriteParametersBuilder::new(L, R)
+            .with_alpha(ALPHA)
+            .with_num_threads(1)
+            .build();
+        let config = IndexConfigurationBuilder::new(Metric::L2, DIM_128, vectors.len())
+            .with_index_write_parameters(index_write_parameters)
+            .with_brute_force_threshold(64)
+            .build();
+        let mut index = InmemIndex::<f32, DIM_128>::new(config).unwrap();
+        index.build_from_vectors(&vectors[..48]).unwrap();
+        assert!(index.is_brute_force());
+        assert_eq!(index.final_graph.read_vertex_and_neighbors(0).unwrap().size(), 0);
+
+        // Brute force results are exact
+        let inserted = index.insert_point(&vectors[48]).unwrap();
+        assert_eq!(inserted, 48);
+        for query_id in [0, 21, 48] {
+            let mut truth: Vec<f32> = (0..49)
+                .map(|id| index.get_distance(query_id, id).unwrap())
+                .collect();
+            truth.sort_by(|a, b| a.total_cmp(b));
+
+            let query = index.dataset.get_vertex(query_id).unwrap();
+            let (results, query_stats) = index
+                .search_with_details(&query, 5, 10, SearchResultFields::NONE)
                 .unwrap();
+            assert_eq!(results[0].id, query_id);
+            for (result, truth_distance) in results.iter().zip(truth.iter()) {
+                assert_eq!(result.distance, *truth_distance);
+            }
+            assert_eq!(query_stats.n_cmps, 49);
+        }
 
-            compare_graphs(&index, &truth_index);
-        }};
+        // Growing past the threshold builds the graph
+        index.insert_vectors(&vectors[49..]).unwrap();
+        assert!(!index.is_brute_force());
+        assert!(index.final_graph.read_vertex_and_neighbors(0).unwrap().size() > 0);
+        let query = index.dataset.get_vertex(90).unwrap();
+        let (results, query_stats) = index
+            .search_with_details(&query, 1, L, SearchResultFields::NONE)
+            .unwrap();
+        assert_eq!(results[0].id, 90);
+        assert!(query_stats.n_hops > 0);
     }
 
     #[test]
-    fn index_end_to_end_test_singlethread() {
-        index_end_to_end_test_singlethread!(false, TRUTH_GRAPH);
+    fn pq_distance_is_rejected_when_building_or_inserting() {
+        let index_write_parameters = IndexWriteParametersBuilder::new(L, R)
+            .with_alpha(ALPHA)
+            .with_num_threads(1)
+            .build();
+        let config = IndexConfigurationBuilder::new(Metric::L2, DIM_128, 32)
+            .with_index_write_parameters(index_write_parameters)
+            .build();
+        let mut index = InmemIndex::<f32, DIM_128>::new(config).unwrap();
+        index.configuration.use_pq_dist = true;
+        let vectors = vec![vec![1.0f32; DIM_128]; 4];
+
+        assert!(matches!(
+            index.build_from_vectors(&vectors),
+            Err(ANNError::IndexConfigError { .. })
+        ));
+        assert!(matches!(
+            index.insert_vectors(&vectors),
+            Err(ANNError::IndexConfigError { .. })
+        ));
+        assert!(matches!(
+            index.build_from_rows(&vectors.concat()),
+            Err(ANNError::IndexConfigError { .. })
+        ));
+        let rows = vectors.concat();
+        let mut source = SliceSource::new(&rows, DIM_128, 2).unwrap();
+        assert!(matches!(
+            index.build_from_source(&mut source),
+            Err(ANNError::IndexConfigError { .. })
+        ));
     }
 
     #[test]
-    fn index_end_to_end_test_singlethread_with_saturate_graph() {
-        index_end_to_end_test_singlethread!(true, TRUTH_GRAPH_WITH_SATURATED);
+    fn brute_force_search_skips_vectors_being_inserted() {
+        let index_write_parameters = IndexWriteParametersBuilder::new(L, R)
+            .with_alpha(ALPHA)
+            .with_num_threads(1)
+            .build();
+        let config = IndexConfigurationBuilder::new(Metric::L2, DIM_128, 32)
+            .with_index_write_parameters(index_write_parameters)
+            .with_brute_force_threshold(64)
+            .build();
+        let mut index = InmemIndex::<f32, DIM_128>::new(config).unwrap();
+        let vectors = vec![vec![2.0f32; DIM_128]; 16];
+        index.build_from_vectors(&vectors).unwrap();
+
+        // A reserved slot still holds zeros, the closest vector to the origin
+        let origin = [0.0f32; DIM_128];
+        let query = Vertex::<f32, DIM_128>::new(&origin, 0);
+        let inserted = vec![1.0f32; DIM_128];
+        let vertex_id = index.reserve_vertex_id(&inserted).unwrap();
+        assert_eq!(index.num_searchable_pts(), 17);
+        assert_eq!(index.num_written_pts(), 16);
+        let (results, _) = index
+            .search_with_details(&query, 4, 4, SearchResultFields::NONE)
+            .unwrap();
+        assert!(results.iter().all(|result| result.distance == 512.0));
+
+        // Once written and published it is found
+        let written = unsafe { index.dataset.write_unpublished_vector(vertex_id, &inserted) };
+        written.unwrap();
+        index.publish_written_vector(vertex_id);
+        let (results, query_stats) = index
+            .search_with_details(&query, 4, 4, SearchResultFields::NONE)
+            .unwrap();
+        assert_eq!(results[0].id, vertex_id);
+        assert_eq!(results[0].distance, 128.0);
+        assert_eq!(query_stats.n_cmps, 17);
+
+        // Points inserted concurrently with searches are only found whole
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                for _ in 0..15 {
+                    index.insert_point(&inserted).unwrap();
+                }
+            });
+            for _ in 0..100 {
+                let (results, _) = index
+                    .search_with_details(&query, 4, 4, SearchResultFields::NONE)
+                    .unwrap();
+                for result in results {
+                    assert!(result.distance == 128.0 || result.distance == 512.0);
+                }
+            }
+        });
+        assert_eq!(index.num_written_pts(), 32);
     }
 
     #[test]
-    fn index_end_to_end_test_multithread() {
-        let (data_num, dim) =
-            load_metadata_from_file(get_test_file_path(TEST_DATA_FILE).as_str()).unwrap();
+    fn binary_index_with_hamming_metric() {
+        let data_file = "binary_index_with_hamming_metric.bin";
+        let num_points = 64;
+        let mut codes: Vec<u8> = (0..num_points * DIM_128)
+            .map(|i| ((i * 2654435761) >> 7) as u8)
+            .collect();
+        save_data_in_base_dimensions(data_file, &mut codes, num_points, DIM_128, DIM_128, 0)
+            .unwrap();
 
         let index_write_parameters = IndexWriteParametersBuilder::new(L, R)
             .with_alpha(ALPHA)
-            .with_num_threads(8)
+            .with_num_threads(1)
             .build();
         let config = IndexConfiguration::new(
-            Metric::L2,
-            dim,
-            round_up(dim as u64, 16_u64) as usize,
-            data_num,
+            Metric::Hamming,
+            DIM_128,
+            DIM_128,
+            num_points,
             false,
             0,
             false,
@@ -868,22 +3376,106 @@ mod index_test {
             1f32,
             index_write_parameters,
         );
-        let mut index: InmemIndex<f32, DIM_128> = InmemIndex::new(config).unwrap();
+        let mut index = InmemIndex::<u8, DIM_128>::new(config.clone()).unwrap();
+        index.build(data_file, num_points).unwrap();
+        std::fs::remove_file(data_file).unwrap();
 
-        index
-            .build(get_test_file_path(TEST_DATA_FILE).as_str(), data_num)
-            .unwrap();
+        let query = &codes[5 * DIM_128..6 * DIM_128];
+        let mut indices = [0u32; 1];
+        ANNInmemIndex::search(&index, query, 1, L, &mut indices).unwrap();
+        assert_eq!(indices[0], 5);
 
-        for i in 0..index.final_graph.size() {
-            assert_ne!(
-                index
-                    .final_graph
-                    .read_vertex_and_neighbors(i as u32)
-                    .unwrap()
-                    .size(),
-                0
-            );
+        // Hamming needs byte-packed codes
+        assert!(InmemIndex::<f32, DIM_128>::new(config).is_err());
+    }
+
+    #[test]
+    fn fingerprint_index_with_tanimoto_metric() {
+        let data_file = "fingerprint_index_with_tanimoto_metric.bin";
+        let num_points = 64;
+
+        // Sparse fingerprints, about one bit in eight set
+        let mut fingerprints: Vec<u8> = (0..num_points * DIM_128)
+            .map(|i| match ((i * 2654435761) >> 13) % 8 {
+                0 => 1u8 << (((i * 40503) >> 3) % 8),
+                _ => 0,
+            })
+            .collect();
+        save_data_in_base_dimensions(
+            data_file,
+            &mut fingerprints,
+            num_points,
+            DIM_128,
+            DIM_128,
+            0,
+        )
+        .unwrap();
+
+        let index_write_parameters = IndexWriteParametersBuilder::new(L, R)
+            .with_alpha(ALPHA)
+            .with_num_threads(1)
+            .build();
+        let config = IndexConfiguration::new(
+            Metric::Tanimoto,
+            DIM_128,
+            DIM_128,
+            num_points,
+            false,
+            0,
+            false,
+            0,
+            1f32,
+            index_write_parameters,
+        );
+        let mut index = InmemIndex::<u8, DIM_128>::new(config.clone()).unwrap();
+        index.build(data_file, num_points).unwrap();
+        std::fs::remove_file(data_file).unwrap();
+
+        for id in [0, 17, 63] {
+            let query = &fingerprints[id * DIM_128..(id + 1) * DIM_128];
+            let mut indices = [0u32; 1];
+            ANNInmemIndex::search(&index, query, 1, L, &mut indices).unwrap();
+            assert_eq!(indices[0], id as u32);
         }
+
+        // Tanimoto needs byte-packed fingerprints
+        assert!(InmemIndex::<Half, DIM_128>::new(config).is_err());
+    }
+
+    #[test]
+    fn f16_index_with_16_bytes_aligned_rows() {
+        // 104 halves per row, so every other row is only 16 bytes aligned
+        let data_file = "f16_index_with_16_bytes_aligned_rows.bin";
+        let (num_points, dim) = (64, 100);
+        let mut data: Vec<Half> = (0..num_points * dim)
+            .map(|i| Half::from_f32(((i * 37) % 101) as f32 / 10.0 - 5.0))
+            .collect();
+        save_data_in_base_dimensions(data_file, &mut data, num_points, dim, dim, 0).unwrap();
+
+        let index_write_parameters = IndexWriteParametersBuilder::new(L, R)
+            .with_alpha(ALPHA)
+            .with_num_threads(1)
+            .build();
+        let config = IndexConfiguration::new(
+            Metric::L2,
+            dim,
+            DIM_104,
+            num_points,
+            false,
+            0,
+            false,
+            0,
+            1f32,
+            index_write_parameters,
+        );
+        let mut index = InmemIndex::<Half, DIM_104>::new(config).unwrap();
+        index.build(data_file, num_points).unwrap();
+        std::fs::remove_file(data_file).unwrap();
+
+        let query = index.dataset.get_vertex(7).unwrap();
+        let mut indices = [0u32; 1];
+        index.search(&query, 1, L, &mut indices).unwrap();
+        assert_eq!(indices[0], 7);
     }
 
     const TEST_DATA_FILE_2: &str = "tests/data/siftsmall_learn_256pts_2.fbin";
@@ -1030,4 +3622,456 @@ mod index_test {
             );
         }
     }
+
+    #[test]
+    fn search_with_weights_applies_dimension_weights() {
+        let mut index = create_index_with_test_data();
+        index.initialize_query_scratch(1, L).unwrap();
+        index
+            .load_graph(get_test_file_path(TRUTH_GRAPH).as_str(), 256)
+            .unwrap();
+        let query = index.dataset.get_vertex(14).unwrap();
+
+        let mut unweighted = vec![0u32; 10];
+        let mut unit_weighted = vec![0u32; 10];
+        index.search(&query, 10, L, &mut unweighted).unwrap();
+        index
+            .search_with_weights(&query, &[1.0; 128], 10, L, &mut unit_weighted)
+            .unwrap();
+        assert_eq!(unweighted, unit_weighted);
+
+        // Only the first 16 dimensions count
+        let weights: Vec<f32> = (0..128).map(|i| if i < 16 { 1.0 } else { 0.0 }).collect();
+        let mut weighted = vec![0u32; 10];
+        index
+            .search_with_weights(&query, &weights, 10, L, &mut weighted)
+            .unwrap();
+        let partial_distance = |id: u32| -> f32 {
+            let vector = index.dataset.get_vertex(id).unwrap().vector();
+            (0..16).map(|d| (query.vector()[d] - vector[d]).powi(2)).sum()
+        };
+        assert_eq!(partial_distance(weighted[0]), 0.0);
+        assert!(weighted
+            .windows(2)
+            .all(|pair| partial_distance(pair[0]) <= partial_distance(pair[1])));
+        assert_ne!(weighted, unweighted);
+
+        let mut indices = vec![0u32; 10];
+        assert!(index
+            .search_with_weights(&query, &[1.0; 3], 10, L, &mut indices)
+            .is_err());
+        assert!(index
+            .search_with_weights(&query, &[-1.0; 128], 10, L, &mut indices)
+            .is_err());
+    }
+
+    #[test]
+    fn search_in_subspace_reranks_with_full_distance() {
+        let mut index = create_index_with_test_data();
+        index.initialize_query_scratch(1, L).unwrap();
+        index
+            .load_graph(get_test_file_path(TRUTH_GRAPH).as_str(), 256)
+            .unwrap();
+        let query = index.dataset.get_vertex(14).unwrap();
+
+        let mut full = vec![0u32; 10];
+        let mut whole_range = vec![0u32; 10];
+        index.search(&query, 10, L, &mut full).unwrap();
+        index
+            .search_in_subspace(&query, 0..128, 10, L, &mut whole_range)
+            .unwrap();
+        assert_eq!(full, whole_range);
+
+        // Traverse with the first 32 dimensions, the results are still ordered by full distance
+        let mut leading = vec![0u32; 10];
+        index
+            .search_in_subspace(&query, 0..32, 10, L, &mut leading)
+            .unwrap();
+        assert_eq!(leading[0], 14);
+        let full_distance = |id: u32| {
+            index.compare_vertices(&query, &index.dataset.get_vertex(id).unwrap())
+        };
+        assert!(leading
+            .windows(2)
+            .all(|pair| full_distance(pair[0]) <= full_distance(pair[1])));
+
+        let mut indices = vec![0u32; 10];
+        assert!(index
+            .search_in_subspace(&query, 16..16, 10, L, &mut indices)
+            .is_err());
+        assert!(index
+            .search_in_subspace(&query, 0..129, 10, L, &mut indices)
+            .is_err());
+    }
+
+    #[test]
+    fn quantized_prune_distances_keep_most_neighbors() {
+        let prune = |index: &InmemIndex<f32, DIM_128>, location: u32| {
+            let mut pool: Vec<Neighbor> = (0..index.num_active_pts as u32)
+                .filter(|&id| id != location)
+                .map(|id| Neighbor::new(id, index.get_distance(location, id).unwrap()))
+                .collect();
+            let mut scratch =
+                InMemQueryScratch::new(L, &index.configuration.index_write_parameter, false)
+                    .unwrap();
+            let mut pruned_list = AdjacencyList::for_range(R as usize);
+            index
+                .prune_neighbors(location, &mut pool, &mut pruned_list, &mut scratch)
+                .unwrap();
+            pruned_list
+        };
+        let locations = (0..256).step_by(5);
+
+        let cpu_index = create_index_with_test_data();
+        let expected: Vec<AdjacencyList> =
+            locations.clone().map(|id| prune(&cpu_index, id)).collect();
+
+        for quantization in [
+            PruneQuantization::Half,
+            PruneQuantization::SQ8,
+            PruneQuantization::PQ { num_chunks: 32 },
+        ] {
+            let mut index = create_index_with_test_data();
+            index.prune_vectors =
+                QuantizedPruneVectors::new(&index.dataset, 256, 128, quantization, None).unwrap();
+
+            // Refining the whole pool prunes at full precision
+            index.configuration.prune_refine_fraction = 1.0;
+            for (location, expected) in locations.clone().zip(&expected) {
+                assert_eq!(&prune(&index, location), expected);
+            }
+
+            index.configuration.prune_refine_fraction = 0.0;
+            let mut kept = 0;
+            let mut total = 0;
+            for (location, expected) in locations.clone().zip(&expected) {
+                let pruned = prune(&index, location);
+                kept += expected.iter().filter(|id| pruned.contains(id)).count();
+                total += expected.len();
+            }
+            assert!(kept * 10 >= total * 8);
+        }
+    }
+
+    #[test]
+    fn search_with_params_limits_expansions() {
+        let mut index = create_index_with_test_data();
+        index.initialize_query_scratch(1, L).unwrap();
+        index
+            .load_graph(get_test_file_path(TRUTH_GRAPH).as_str(), 256)
+            .unwrap();
+        let query = index.dataset.get_vertex(42).unwrap();
+
+        let mut expected = vec![0u32; 5];
+        let expected_cmps = index.search(&query, 5, L, &mut expected).unwrap();
+        let mut indices = vec![0u32; 5];
+        let params = SearchParams::new(L, 4, None, true).unwrap();
+        assert_eq!(
+            index
+                .search_with_params(&query, 5, &params, &mut indices)
+                .unwrap(),
+            expected_cmps
+        );
+        assert_eq!(indices, expected);
+
+        // A single expansion compares the query to the neighbors of the start point only
+        let params = SearchParams::new(L, 4, Some(1), true).unwrap();
+        let cmps = index
+            .search_with_params(&query, 1, &params, &mut indices)
+            .unwrap();
+        assert!(cmps <= R);
+        assert!(index
+            .search_with_params(&query, L as usize + 1, &params, &mut indices)
+            .is_err());
+
+        // Stopping with candidates left is flagged, finishing the search list isn't
+        let limited = index
+            .search_with_params_and_stats(&query, 1, &params, &mut indices)
+            .unwrap();
+        assert!(limited.early_terminated);
+        let params = SearchParams::new(L, 4, None, true).unwrap();
+        let full = index
+            .search_with_params_and_stats(&query, 5, &params, &mut indices)
+            .unwrap();
+        assert!(!full.early_terminated);
+
+        // With patience the search stops soon after reaching the query point, a full search
+        // keeps expanding the rest of its search list
+        let impatient = params.with_patience(8).unwrap();
+        let stopped = index
+            .search_with_params_and_stats(&query, 1, &impatient, &mut indices)
+            .unwrap();
+        assert!(stopped.early_terminated);
+        assert!(stopped.n_hops < full.n_hops);
+        assert_eq!(indices[0], 42);
+    }
+
+    #[test]
+    fn compact_graph_searches_like_vec_graph() {
+        let mut index = create_index_with_test_data();
+        index.initialize_query_scratch(1, L).unwrap();
+        index
+            .load_graph(get_test_file_path(TRUTH_GRAPH).as_str(), 256)
+            .unwrap();
+
+        let search_all = |index: &InmemIndex<f32, DIM_128>| -> Vec<u32> {
+            let mut all_results = Vec::new();
+            for id in (0..256).step_by(17) {
+                let query = index.dataset.get_vertex(id).unwrap();
+                let mut indices = vec![0u32; 5];
+                index.search(&query, 5, L, &mut indices).unwrap();
+                all_results.extend(indices);
+            }
+            all_results
+        };
+
+        let vec_graph_bytes = index.final_graph.memory_bytes();
+        let expected = search_all(&index);
+        index.compact_graph().unwrap();
+        assert_eq!(index.final_graph.size(), 0);
+        assert!(index.arena_graph.as_ref().unwrap().memory_bytes() < vec_graph_bytes);
+        assert_eq!(search_all(&index), expected);
+
+        // Saving the packed graph writes the same file
+        let saved_file = "compact_graph_searches_like_vec_graph.index";
+        index.save_graph(saved_file).unwrap();
+        assert_eq!(
+            std::fs::read(saved_file).unwrap(),
+            std::fs::read(get_test_file_path(TRUTH_GRAPH)).unwrap()
+        );
+        std::fs::remove_file(saved_file).unwrap();
+
+        index.expand_graph().unwrap();
+        assert!(index.arena_graph.is_none());
+        assert_eq!(search_all(&index), expected);
+    }
+
+    #[test]
+    fn csr_graph_searches_like_loaded_graph() {
+        let mut index = create_index_with_test_data();
+        index.initialize_query_scratch(1, L).unwrap();
+        index
+            .load_graph(get_test_file_path(TRUTH_GRAPH).as_str(), 256)
+            .unwrap();
+
+        let csr_file = "csr_graph_searches_like_loaded_graph.csr";
+        index.save_csr_graph(csr_file).unwrap();
+
+        let mut mapped_index = create_index_with_test_data();
+        mapped_index.initialize_query_scratch(1, L).unwrap();
+        assert_eq!(mapped_index.load_csr_graph(csr_file, 256).unwrap(), 256);
+        assert!(mapped_index.arena_graph.as_ref().unwrap().is_mapped());
+        assert_eq!(mapped_index.start, index.start);
+        assert_eq!(mapped_index.max_observed_degree, index.max_observed_degree);
+
+        for id in (0..256).step_by(13) {
+            let query = index.dataset.get_vertex(id).unwrap();
+            let mut expected = vec![0u32; 5];
+            let mut indices = vec![0u32; 5];
+            index.search(&query, 5, L, &mut expected).unwrap();
+            mapped_index.search(&query, 5, L, &mut indices).unwrap();
+            assert_eq!(indices, expected);
+        }
+
+        // Saving the mapped graph writes the Vamana file it came from
+        let saved_file = "csr_graph_searches_like_loaded_graph.index";
+        mapped_index.save_graph(saved_file).unwrap();
+        assert_eq!(
+            std::fs::read(saved_file).unwrap(),
+            std::fs::read(get_test_file_path(TRUTH_GRAPH)).unwrap()
+        );
+        std::fs::remove_file(saved_file).unwrap();
+        std::fs::remove_file(csr_file).unwrap();
+    }
+
+    #[test]
+    fn merge_links_saved_indexes() {
+        let (data, num_points, dim) =
+            crate::utils::load_bin::<f32>(get_test_file_path(TEST_DATA_FILE).as_str(), 0).unwrap();
+        let vectors: Vec<Vec<f32>> = data.chunks_exact(dim).map(|row| row.to_vec()).collect();
+        let config = |max_points| {
+            IndexConfiguration::new(
+                Metric::L2,
+                dim,
+                dim,
+                max_points,
+                false,
+                0,
+                false,
+                0,
+                1.0f32,
+                IndexWriteParametersBuilder::new(L, 16)
+                    .with_alpha(ALPHA)
+                    .with_num_threads(1)
+                    .build(),
+            )
+        };
+
+        // Each half is indexed on its own, the second one with a deleted point
+        let index_files = ["merge_links_saved_indexes_0", "merge_links_saved_indexes_1"];
+        for (part, index_file) in index_files.iter().enumerate() {
+            let part_vectors = &vectors[part * num_points / 2..(part + 1) * num_points / 2];
+            let mut index: InmemIndex<f32, DIM_128> =
+                InmemIndex::new(config(part_vectors.len())).unwrap();
+            index.build_from_vectors(part_vectors).unwrap();
+            if part == 1 {
+                index.soft_delete(vec![5], 1).unwrap();
+            }
+            index.save(index_file).unwrap();
+        }
+
+        let mut merged: InmemIndex<f32, DIM_128> = InmemIndex::new(config(num_points)).unwrap();
+        let notifier = Arc::new(IndexEventNotifier::default());
+        merged.set_event_notifier(notifier.clone());
+        let mut events = notifier.subscribe();
+        assert!(merged.merge(&[]).is_err());
+        merged.merge(&index_files).unwrap();
+        assert_eq!(
+            events.try_recv().unwrap(),
+            crate::index::IndexEvent::Merged {
+                version: 1,
+                num_merged_indices: 2
+            }
+        );
+        assert_eq!(merged.num_active_pts, num_points);
+        let deleted: Vec<u32> = merged.delete_set.read().unwrap().iter().copied().collect();
+        assert_eq!(deleted, vec![num_points as u32 / 2 + 5]);
+
+        // Searching from the single start finds the points of both indexes
+        let mut indices = [0u32; 1];
+        for (id, vector) in vectors.iter().enumerate().step_by(7) {
+            if id == num_points / 2 + 5 {
+                continue;
+            }
+            ANNInmemIndex::search(&merged, vector, 1, L, &mut indices).unwrap();
+            assert_eq!(indices[0], id as u32);
+        }
+
+        let mut too_small: InmemIndex<f32, DIM_128> =
+            InmemIndex::new(config(num_points - 1)).unwrap();
+        assert!(too_small.merge(&index_files).is_err());
+
+        for index_file in index_files {
+            for suffix in ["", ".data", ".delete", ".lock"] {
+                let file = format!("{}{}", index_file, suffix);
+                if file_exists(&file) {
+                    std::fs::remove_file(file).unwrap();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn clustered_entry_points_are_saved_and_searched() {
+        // Four far apart clusters, point id belongs to cluster id % 4
+        let corners = [(0.0, 0.0), (1000.0, 0.0), (0.0, 1000.0), (1000.0, 1000.0)];
+        let points: Vec<Vec<f32>> = (0..200)
+            .map(|i| {
+                let (x, y) = corners[i % 4];
+                vec![x + (i / 4 % 7) as f32, y + (i / 28) as f32]
+            })
+            .collect();
+        let config = || {
+            IndexConfigurationBuilder::new(Metric::L2, 2, points.len())
+                .with_index_write_parameters(
+                    IndexWriteParametersBuilder::new(L, R)
+                        .with_alpha(ALPHA)
+                        .with_num_threads(1)
+                        .build(),
+                )
+                .with_entry_point_selection(EntryPointSelection::Clustered {
+                    num_entry_points: 4,
+                })
+                .build()
+        };
+
+        let mut index = InmemIndex::<f32, 8>::new(config()).unwrap();
+        index.build_from_vectors(&points).unwrap();
+        let mut clusters: Vec<u32> = std::iter::once(index.start)
+            .chain(index.entry_points.iter().copied())
+            .map(|id| id % 4)
+            .collect();
+        clusters.sort();
+        assert_eq!(clusters, vec![0, 1, 2, 3]);
+
+        let index_file = "clustered_entry_points_are_saved_and_searched";
+        index.save(index_file).unwrap();
+        let mut loaded = InmemIndex::<f32, 8>::new(config()).unwrap();
+        loaded.load(index_file, points.len()).unwrap();
+        assert_eq!(loaded.start, index.start);
+        assert_eq!(loaded.entry_points, index.entry_points);
+
+        let mut indices = [0u32; 1];
+        for (id, point) in points.iter().enumerate().step_by(3) {
+            ANNInmemIndex::search(&loaded, point, 1, L, &mut indices).unwrap();
+            assert_eq!(indices[0], id as u32);
+        }
+
+        for suffix in ["", ".data", ".entry_points", ".lock"] {
+            let file = format!("{}{}", index_file, suffix);
+            if file_exists(&file) {
+                std::fs::remove_file(file).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn consolidate_deletes_unlinks_deleted_points() {
+        // A 20 x 10 grid, so every point has neighbors on all sides
+        let points: Vec<Vec<f32>> = (0..200)
+            .map(|i| vec![(i % 20) as f32, (i / 20) as f32])
+            .collect();
+        let config = IndexConfigurationBuilder::new(Metric::L2, 2, points.len())
+            .with_index_write_parameters(
+                IndexWriteParametersBuilder::new(L, R)
+                    .with_alpha(ALPHA)
+                    .with_num_threads(1)
+                    .build(),
+            )
+            .build();
+        let mut index = InmemIndex::<f32, 8>::new(config).unwrap();
+        index.build_from_vectors(&points).unwrap();
+
+        let deleted: Vec<u32> = (0..200)
+            .filter(|&id| id % 3 == 0 && id != index.start)
+            .collect();
+        index.soft_delete(deleted.clone(), deleted.len()).unwrap();
+        assert_eq!(index.consolidate_deletes().unwrap(), deleted.len());
+
+        let mut neighbors = Vec::new();
+        for id in 0..200 {
+            index.copy_neighbors(id, &mut neighbors).unwrap();
+            if deleted.contains(&id) {
+                assert!(neighbors.is_empty());
+            } else {
+                assert!(!neighbors.is_empty());
+                assert!(neighbors.iter().all(|neighbor| !deleted.contains(neighbor)));
+            }
+        }
+
+        let mut indices = [0u32; 1];
+        for id in (0..200).filter(|id| !deleted.contains(id)) {
+            ANNInmemIndex::search(&index, &points[id as usize], 1, L, &mut indices).unwrap();
+            assert_eq!(indices[0], id);
+        }
+    }
+
+    #[test]
+    fn soft_delete_notifies_subscribers() {
+        let mut index = create_index_with_test_data();
+        let notifier = Arc::new(IndexEventNotifier::default());
+        index.set_event_notifier(notifier.clone());
+        let mut events = notifier.subscribe();
+
+        index.soft_delete(vec![3, 7, 11], 2).unwrap();
+
+        assert_eq!(
+            events.try_recv().unwrap(),
+            crate::index::IndexEvent::Deleted {
+                version: 1,
+                vertex_ids: vec![3, 7]
+            }
+        );
+        assert_eq!(notifier.version(), 1);
+    }
 }
@@ -2,24 +2,29 @@
  * Copyright (c) Microsoft Corporation. All rights reserved.
  * Licensed under the MIT license.
  */
+use std::cmp;
 use std::fs::File;
 use std::io::{BufReader, BufWriter, Seek, SeekFrom, Write};
 use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
 
 use byteorder::{LittleEndian, ReadBytesExt};
-use vector::FullPrecisionDistance;
+use log::info;
+use vector::{Distance, FullPrecisionDistance};
 
 use crate::common::{ANNError, ANNResult};
-use crate::model::graph::AdjacencyList;
-use crate::model::InMemoryGraph;
+use crate::model::graph::{AdjacencyList, ArenaGraph, CsrGraphHeader};
+use crate::model::{InMemoryGraph, GRAPH_SLACK_FACTOR};
+use crate::storage::{StorageMetadata, StorageProvider, StoredNode};
 use crate::utils::{file_exists, save_data_in_base_dimensions};
 
 use super::InmemIndex;
 
-impl<T, const N: usize> InmemIndex<T, N>
+impl<T, const N: usize, D> InmemIndex<T, N, D>
 where
     T: Default + Copy + Sync + Send + Into<f32>,
     [T; N]: FullPrecisionDistance<T, N>,
+    D: Distance<T, N>,
 {
     pub fn load_graph(&mut self, filename: &str, expected_num_points: usize) -> ANNResult<usize> {
         // let file_offset = 0; // will need this for single file format support
@@ -32,8 +37,6 @@ where
         self.start = in_file.read_u32::<LittleEndian>()?;
         let file_frozen_pts: usize = in_file.read_u64::<LittleEndian>()? as usize;
 
-        let vamana_metadata_size = 24;
-
         println!("From graph header, expected_file_size: {}, max_observed_degree: {}, start: {}, file_frozen_pts: {}",
             expected_file_size, self.max_observed_degree, self.start, file_frozen_pts);
 
@@ -65,14 +68,68 @@ where
                 self.configuration.max_points + self.configuration.num_frozen_pts,
                 self.configuration.index_write_parameter.max_degree,
             );
+            self.reset_streamed_points();
         }
 
+        let (nodes_read, num_edges, max_observed_degree) =
+            self.read_adjacency_lists(&mut in_file, expected_file_size, 0)?;
+
+        info!(
+            "Done. Index has {} nodes and {} out-edges, _start is set to {}",
+            nodes_read, num_edges, self.start
+        );
+
+        self.max_observed_degree = max_observed_degree;
+        Ok(nodes_read)
+    }
+
+    /// Load the graph saved by save_graph of an index without frozen points as the vertices
+    /// from id_offset on, shifting its neighbor ids by id_offset.
+    /// Returns the start of the graph, shifted, and its number of vertices.
+    pub(crate) fn append_graph(
+        &mut self,
+        filename: &str,
+        id_offset: u32,
+    ) -> ANNResult<(u32, usize)> {
+        let mut in_file = BufReader::new(File::open(Path::new(filename))?);
+        let expected_file_size: usize = in_file.read_u64::<LittleEndian>()? as usize;
+        in_file.read_u32::<LittleEndian>()?;
+        let start = in_file.read_u32::<LittleEndian>()?;
+        let file_frozen_pts = in_file.read_u64::<LittleEndian>()?;
+        if file_frozen_pts != 0 {
+            return Err(ANNError::log_index_error(format!(
+                "ERROR: Graph {} has {} frozen points, only graphs without them can be appended.",
+                filename, file_frozen_pts
+            )));
+        }
+
+        let (nodes_read, _, max_observed_degree) =
+            self.read_adjacency_lists(&mut in_file, expected_file_size, id_offset)?;
+        self.max_observed_degree = cmp::max(self.max_observed_degree, max_observed_degree);
+        Ok((start + id_offset, nodes_read))
+    }
+
+    /// Read the adjacency lists following the header of a graph file of file_size bytes into
+    /// the vertices from id_offset on, shifting the neighbor ids by id_offset.
+    /// Returns the number of vertices and edges read and the largest degree.
+    fn read_adjacency_lists(
+        &mut self,
+        in_file: &mut BufReader<File>,
+        file_size: usize,
+        id_offset: u32,
+    ) -> ANNResult<(usize, u32, u32)> {
+        let vamana_metadata_size = 24;
         let mut bytes_read = vamana_metadata_size;
         let mut num_edges = 0;
         let mut nodes_read = 0;
         let mut max_observed_degree = 0;
+        let delete_set = self.delete_set.read().map_err(|_| {
+            ANNError::log_lock_poison_error(
+                "Poisoned lock on delete set. Can't load graph.".to_string(),
+            )
+        })?;
 
-        while bytes_read != expected_file_size {
+        while bytes_read != file_size {
             let num_nbrs = in_file.read_u32::<LittleEndian>()?;
             max_observed_degree = if num_nbrs > max_observed_degree {
                 num_nbrs
@@ -80,7 +137,8 @@ where
                 max_observed_degree
             };
 
-            if num_nbrs == 0 {
+            // Consolidated deletes leave their points without neighbors
+            if num_nbrs == 0 && !delete_set.contains(&(id_offset + nodes_read)) {
                 return Err(ANNError::log_index_error(format!(
                     "ERROR: Point found with no out-neighbors, point# {}",
                     nodes_read
@@ -89,24 +147,22 @@ where
 
             num_edges += num_nbrs;
             nodes_read += 1;
-            let mut tmp: Vec<u32> = Vec::with_capacity(num_nbrs as usize);
+            // Leave the slack of built lists, so inserts can add back edges
+            let mut tmp = AdjacencyList::for_range(cmp::max(
+                num_nbrs,
+                self.configuration.index_write_parameter.max_degree,
+            ) as usize);
             for _ in 0..num_nbrs {
-                tmp.push(in_file.read_u32::<LittleEndian>()?);
+                tmp.push(in_file.read_u32::<LittleEndian>()? + id_offset);
             }
 
             self.final_graph
-                .write_vertex_and_neighbors(nodes_read - 1)?
-                .set_neighbors(AdjacencyList::from(tmp));
+                .write_vertex_and_neighbors(id_offset + nodes_read - 1)?
+                .set_neighbors(tmp);
             bytes_read += 4 * (num_nbrs as usize + 1);
         }
 
-        println!(
-            "Done. Index has {} nodes and {} out-edges, _start is set to {}",
-            nodes_read, num_edges, self.start
-        );
-
-        self.max_observed_degree = max_observed_degree;
-        Ok(nodes_read as usize)
+        Ok((nodes_read as usize, num_edges, max_observed_degree))
     }
 
     /// Save the graph index on a file as an adjacency list.
@@ -122,30 +178,22 @@ where
         let mut max_degree: u32 = 0;
         out.write_all(&index_size.to_le_bytes())?;
         out.write_all(&self.max_observed_degree.to_le_bytes())?;
-        out.write_all(&self.start.to_le_bytes())?;
+        out.write_all(&self.saved_vertex_id(self.start).to_le_bytes())?;
         out.write_all(&(self.configuration.num_frozen_pts as u64).to_le_bytes())?;
 
-        // At this point, either nd == max_points or any frozen points have
-        // been temporarily moved to nd, so nd + num_frozen_points is the valid
-        // location limit
-        for i in 0..self.num_active_pts + self.configuration.num_frozen_pts {
+        // The frozen points are saved right after the active points
+        let frozen_pts = self.configuration.max_points
+            ..self.configuration.max_points + self.configuration.num_frozen_pts;
+        let mut neighbors = Vec::new();
+        for i in (0..self.num_active_pts).chain(frozen_pts) {
             let idx = i as u32;
-            let gk: u32 = self.final_graph.read_vertex_and_neighbors(idx)?.size() as u32;
+            self.copy_neighbors(idx, &mut neighbors)?;
+            let gk: u32 = neighbors.len() as u32;
             out.write_all(&gk.to_le_bytes())?;
-            for neighbor in self
-                .final_graph
-                .read_vertex_and_neighbors(idx)?
-                .get_neighbors()
-                .iter()
-            {
-                out.write_all(&neighbor.to_le_bytes())?;
+            for neighbor in neighbors.iter() {
+                out.write_all(&self.saved_vertex_id(*neighbor).to_le_bytes())?;
             }
-            max_degree =
-                if self.final_graph.read_vertex_and_neighbors(idx)?.size() as u32 > max_degree {
-                    self.final_graph.read_vertex_and_neighbors(idx)?.size() as u32
-                } else {
-                    max_degree
-                };
+            max_degree = cmp::max(gk, max_degree);
             index_size += (std::mem::size_of::<u32>() * (gk as usize + 1)) as u64;
         }
         out.seek(SeekFrom::Start(file_offset))?;
@@ -155,11 +203,317 @@ where
         Ok(index_size)
     }
 
+    /// Save the vectors and the graph to provider, the frozen points after the active points
+    /// like save_graph. Returns the number of nodes written.
+    pub fn save_nodes(&self, provider: &mut dyn StorageProvider<T>) -> ANNResult<usize> {
+        let dim = self.configuration.dim;
+        let num_frozen_pts = self.configuration.num_frozen_pts;
+        let num_nodes = self.num_active_pts + num_frozen_pts;
+        let frozen_pts =
+            self.configuration.max_points..self.configuration.max_points + num_frozen_pts;
+        let saved_ids = || (0..self.num_active_pts).chain(frozen_pts.clone());
+
+        // Inserted points may have more neighbors than max_observed_degree
+        let mut max_degree = self.configuration.index_write_parameter.max_degree as usize;
+        let mut neighbors = Vec::new();
+        for i in saved_ids() {
+            self.copy_neighbors(i as u32, &mut neighbors)?;
+            max_degree = cmp::max(max_degree, neighbors.len());
+        }
+        provider.put_metadata(&StorageMetadata {
+            num_points: num_nodes,
+            dim,
+            medoid: self.saved_vertex_id(self.start),
+            max_degree,
+            frozen_point: (num_frozen_pts > 0).then_some(self.num_active_pts as u32),
+        })?;
+
+        for (saved_id, i) in saved_ids().enumerate() {
+            self.copy_neighbors(i as u32, &mut neighbors)?;
+            let node = StoredNode {
+                vector: self.stored_vector(i as u32)?,
+                neighbors: neighbors
+                    .iter()
+                    .map(|&neighbor| self.saved_vertex_id(neighbor))
+                    .collect(),
+            };
+            provider.put_node(saved_id as u32, &node)?;
+        }
+        Ok(num_nodes)
+    }
+
+    /// Write the vectors and the graph to store under their ids in the index, the frozen points
+    /// at max_points, and keep it to write through the nodes insert_point adds or links to.
+    /// Returns the number of nodes written.
+    pub fn set_node_store(
+        &mut self,
+        mut store: Box<dyn StorageProvider<T> + Send>,
+    ) -> ANNResult<usize> {
+        let num_frozen_pts = self.configuration.num_frozen_pts;
+        let max_points = self.configuration.max_points;
+        let node_ids = || (0..self.num_active_pts).chain(max_points..max_points + num_frozen_pts);
+
+        // Back edges grow a list by the slack before it is pruned
+        let mut max_degree = (GRAPH_SLACK_FACTOR
+            * self.configuration.index_write_parameter.max_degree as f64)
+            as usize;
+        let mut neighbors = Vec::new();
+        for i in node_ids() {
+            self.copy_neighbors(i as u32, &mut neighbors)?;
+            max_degree = cmp::max(max_degree, neighbors.len());
+        }
+        store.put_metadata(&StorageMetadata {
+            num_points: max_points + num_frozen_pts,
+            dim: self.configuration.dim,
+            medoid: self.start,
+            max_degree,
+            frozen_point: (num_frozen_pts > 0).then_some(max_points as u32),
+        })?;
+
+        let mut num_nodes = 0;
+        for i in node_ids() {
+            let mut neighbors = Vec::new();
+            self.copy_neighbors(i as u32, &mut neighbors)?;
+            let node = StoredNode {
+                vector: self.stored_vector(i as u32)?,
+                neighbors,
+            };
+            store.put_node(i as u32, &node)?;
+            num_nodes += 1;
+        }
+        self.node_store = Some(Mutex::new(store));
+        Ok(num_nodes)
+    }
+
+    /// Write vertex_id to the node store, if there is one. The store stays locked from the copy
+    /// of the node to its write, so the last write of a node holds its latest list, while the
+    /// list itself is only locked to be copied.
+    pub(crate) fn write_through(&self, vertex_id: u32) -> ANNResult<()> {
+        let Some(node_store) = &self.node_store else {
+            return Ok(());
+        };
+        let mut node_store = self.lock_node_store(node_store)?;
+        let mut neighbors = Vec::new();
+        self.final_graph.copy_neighbors(vertex_id, &mut neighbors)?;
+        let node = StoredNode {
+            vector: self.stored_vector(vertex_id)?,
+            neighbors,
+        };
+        node_store.put_node(vertex_id, &node)
+    }
+
+    pub(crate) fn lock_node_store<'a>(
+        &self,
+        node_store: &'a Mutex<Box<dyn StorageProvider<T> + Send>>,
+    ) -> ANNResult<MutexGuard<'a, Box<dyn StorageProvider<T> + Send>>> {
+        node_store.lock().map_err(|_| {
+            ANNError::log_lock_poison_error(
+                "Poisoned lock on the node store. Can't write through.".to_string(),
+            )
+        })
+    }
+
+    fn stored_vector(&self, vertex_id: u32) -> ANNResult<Vec<T>> {
+        Ok(self.dataset.get_vertex(vertex_id)?.vector()[..self.configuration.dim].to_vec())
+    }
+
+    /// Load the vectors and the graph save_nodes wrote to provider, the frozen points after
+    /// the active points like load_graph. Returns the number of nodes read.
+    pub fn load_nodes(&mut self, provider: &dyn StorageProvider<T>) -> ANNResult<usize> {
+        let metadata = provider.metadata()?;
+        let dim = self.configuration.dim;
+        if metadata.dim != dim {
+            return Err(ANNError::log_index_config_error(
+                "dim".to_string(),
+                format!(
+                    "ERROR: The store has vectors of dimension {}, the index {}.",
+                    metadata.dim, dim
+                ),
+            ));
+        }
+        if metadata.frozen_point.is_some() != (self.configuration.num_frozen_pts > 0) {
+            return Err(ANNError::log_index_config_error(
+                "num_frozen_pts".to_string(),
+                format!(
+                    "ERROR: The store has frozen point {:?}, but the index is configured with {} frozen points.",
+                    metadata.frozen_point, self.configuration.num_frozen_pts
+                ),
+            ));
+        }
+        if metadata.num_points > self.dataset.capacity {
+            return Err(ANNError::log_index_error(format!(
+                "ERROR: The store has {} points, more than the {} the index holds.",
+                metadata.num_points, self.dataset.capacity
+            )));
+        }
+
+        let max_degree = self.configuration.index_write_parameter.max_degree;
+        let mut max_observed_degree = 0;
+        for id in 0..metadata.num_points as u32 {
+            let node = provider.get_node(id)?;
+            if node.vector.len() != dim {
+                return Err(ANNError::log_index_error(format!(
+                    "ERROR: Node {} of the store has a vector of dimension {}, expecting {}.",
+                    id,
+                    node.vector.len(),
+                    dim
+                )));
+            }
+            let vector = &mut self.dataset.data[id as usize * N..(id as usize + 1) * N];
+            vector[..dim].copy_from_slice(&node.vector);
+            vector[dim..].fill(T::default());
+
+            let num_nbrs = node.neighbors.len() as u32;
+            max_observed_degree = cmp::max(max_observed_degree, num_nbrs);
+            // Leave the slack of built lists, so inserts can add back edges
+            let mut list = AdjacencyList::for_range(cmp::max(num_nbrs, max_degree) as usize);
+            for neighbor in node.neighbors {
+                list.push(neighbor);
+            }
+            self.final_graph
+                .write_vertex_and_neighbors(id)?
+                .set_neighbors(list);
+        }
+
+        self.start = metadata.medoid;
+        self.max_observed_degree = max_observed_degree;
+        Ok(metadata.num_points)
+    }
+
+    /// Id vertex_id is saved under, the frozen points following the active points
+    pub(super) fn saved_vertex_id(&self, vertex_id: u32) -> u32 {
+        let max_points = self.configuration.max_points as u32;
+        if vertex_id >= max_points {
+            vertex_id - max_points + self.num_active_pts as u32
+        } else {
+            vertex_id
+        }
+    }
+
+    /// Move the frozen points, loaded right after the num_points active points, back to their
+    /// slots after max_points, and point the adjacency lists and start at them there.
+    /// A mapped CSR graph already has them there, only their vectors are moved.
+    pub(crate) fn relocate_loaded_frozen_points(&mut self, num_points: usize) -> ANNResult<()> {
+        let max_points = self.configuration.max_points;
+        let num_frozen_pts = self.configuration.num_frozen_pts;
+        if num_frozen_pts == 0 || num_points == max_points {
+            return Ok(());
+        }
+
+        self.dataset.data.copy_within(
+            num_points * N..(num_points + num_frozen_pts) * N,
+            max_points * N,
+        );
+        if self.arena_graph.is_some() {
+            return Ok(());
+        }
+
+        let relocated = |id: u32| {
+            if id as usize >= num_points {
+                id - num_points as u32 + max_points as u32
+            } else {
+                id
+            }
+        };
+        let max_degree = self.configuration.index_write_parameter.max_degree as usize;
+        let relocated_list = |neighbors: &[u32]| {
+            let mut list = AdjacencyList::for_range(cmp::max(neighbors.len(), max_degree));
+            for &neighbor in neighbors {
+                list.push(relocated(neighbor));
+            }
+            list
+        };
+
+        let mut frozen_lists = Vec::with_capacity(num_frozen_pts);
+        for i in 0..num_frozen_pts {
+            let mut vertex = self
+                .final_graph
+                .write_vertex_and_neighbors((num_points + i) as u32)?;
+            frozen_lists.push(relocated_list(vertex.get_neighbors()));
+            vertex.set_neighbors(AdjacencyList::for_range(max_degree));
+        }
+        for id in 0..num_points {
+            let mut vertex = self.final_graph.write_vertex_and_neighbors(id as u32)?;
+            let list = relocated_list(vertex.get_neighbors());
+            vertex.set_neighbors(list);
+        }
+        for (i, list) in frozen_lists.into_iter().enumerate() {
+            self.final_graph
+                .write_vertex_and_neighbors((max_points + i) as u32)?
+                .set_neighbors(list);
+        }
+        self.start = relocated(self.start);
+        Ok(())
+    }
+
+    /// Save the graph as a CSR file that load_csr_graph maps back without parsing
+    pub fn save_csr_graph(&self, csr_file: &str) -> ANNResult<()> {
+        let header = CsrGraphHeader {
+            start: self.start,
+            max_observed_degree: self.max_observed_degree,
+            num_frozen_pts: self.configuration.num_frozen_pts as u64,
+        };
+
+        match &self.arena_graph {
+            Some(arena_graph) => arena_graph.save_csr(csr_file, &header),
+            None => ArenaGraph::from_graph(&self.final_graph)?.save_csr(csr_file, &header),
+        }
+    }
+
+    /// Map a CSR graph file written by save_csr_graph. The index searches the mapped graph
+    /// until it is modified, which copies the graph into memory.
+    pub fn load_csr_graph(&mut self, csr_file: &str, expected_num_points: usize) -> ANNResult<usize> {
+        let (arena_graph, header) = ArenaGraph::map_csr(csr_file)?;
+
+        if header.num_frozen_pts as usize != self.configuration.num_frozen_pts {
+            return Err(ANNError::log_index_config_error(
+                "num_frozen_pts".to_string(),
+                format!(
+                    "ERROR: CSR graph {} has {} frozen points, but the index is configured with {}",
+                    csr_file, header.num_frozen_pts, self.configuration.num_frozen_pts
+                ),
+            ));
+        }
+
+        if arena_graph.size() < expected_num_points {
+            return Err(ANNError::log_index_error(format!(
+                "ERROR: CSR graph {} has {} vertices, expected at least {}",
+                csr_file,
+                arena_graph.size(),
+                expected_num_points
+            )));
+        }
+
+        info!(
+            "Mapped CSR graph {} with {} nodes and {} out-edges, _start is set to {}",
+            csr_file,
+            arena_graph.size(),
+            arena_graph.num_edges(),
+            header.start
+        );
+
+        let num_vertices = arena_graph.size();
+        self.start = header.start;
+        self.max_observed_degree = header.max_observed_degree;
+        self.arena_graph = Some(arena_graph);
+        self.final_graph =
+            InMemoryGraph::new(0, self.configuration.index_write_parameter.max_degree);
+        Ok(num_vertices)
+    }
+
     /// Save the data on a file.
     pub fn save_data(&mut self, data_file: &str) -> ANNResult<usize> {
-        // Note: at this point, either _nd == _max_points or any frozen points have
-        // been temporarily moved to _nd, so _nd + _num_frozen_points is the valid
-        // location limit.
+        // The frozen points are copied to the free slots right after the active points, so
+        // _nd + _num_frozen_points is the valid location limit.
+        let max_points = self.configuration.max_points;
+        let num_frozen_pts = self.configuration.num_frozen_pts;
+        if num_frozen_pts > 0 && self.num_active_pts < max_points {
+            self.dataset.data.copy_within(
+                max_points * N..(max_points + num_frozen_pts) * N,
+                self.num_active_pts * N,
+            );
+        }
+
         Ok(save_data_in_base_dimensions(
             data_file,
             &mut self.dataset.data,
@@ -186,7 +540,7 @@ where
 
                 // Write the elements of the set.
                 for &item in delete_set.iter() {
-                    writer.write_all(&item.to_be_bytes())?;
+                    writer.write_all(&item.to_le_bytes())?;
                     delete_file_size += std::mem::size_of::<u32>();
                 }
 
@@ -203,6 +557,15 @@ where
 
     // load the deleted list from the delete file if it exists.
     pub fn load_delete_list(&mut self, delete_list_file: &str) -> ANNResult<usize> {
+        self.append_delete_list(delete_list_file, 0)
+    }
+
+    /// Add the ids of the delete file, if it exists, shifted by id_offset to the delete list
+    pub(crate) fn append_delete_list(
+        &mut self,
+        delete_list_file: &str,
+        id_offset: u32,
+    ) -> ANNResult<usize> {
         let mut len = 0;
 
         if file_exists(delete_list_file) {
@@ -214,7 +577,7 @@ where
             if let Ok(mut delete_set) = self.delete_set.write() {
                 for _ in 0..len {
                     let item = reader.read_u32::<LittleEndian>()?;
-                    delete_set.insert(item);
+                    delete_set.insert(item + id_offset);
                 }
             } else {
                 return Err(ANNError::log_lock_poison_error(
@@ -225,6 +588,46 @@ where
 
         Ok(len)
     }
+
+    /// Save the entry points besides start, or remove a stale file when there are none
+    pub fn save_entry_points(&self, entry_points_file: &str) -> ANNResult<()> {
+        if self.entry_points.is_empty() {
+            if file_exists(entry_points_file) {
+                std::fs::remove_file(entry_points_file)?;
+            }
+            return Ok(());
+        }
+
+        let mut writer = BufWriter::new(File::create(entry_points_file)?);
+        writer.write_all(&(self.entry_points.len() as u32).to_le_bytes())?;
+        for &entry_point in &self.entry_points {
+            writer.write_all(&entry_point.to_le_bytes())?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Load the entry points besides start from the file if it exists, none otherwise
+    pub fn load_entry_points(&mut self, entry_points_file: &str) -> ANNResult<()> {
+        self.entry_points.clear();
+        if !file_exists(entry_points_file) {
+            return Ok(());
+        }
+
+        let mut reader = BufReader::new(File::open(entry_points_file)?);
+        let len = reader.read_u32::<LittleEndian>()?;
+        for _ in 0..len {
+            let entry_point = reader.read_u32::<LittleEndian>()?;
+            if entry_point as usize >= self.num_active_pts {
+                return Err(ANNError::log_index_error(format!(
+                    "Entry point {} of {} is not one of the {} points of the index.",
+                    entry_point, entry_points_file, self.num_active_pts
+                )));
+            }
+            self.entry_points.push(entry_point);
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -240,6 +643,7 @@ mod index_test {
             configuration::index_write_parameters::IndexWriteParametersBuilder, vertex::DIM_128,
             IndexConfiguration,
         },
+        storage::InMemoryStorageProvider,
         utils::{load_metadata_from_file, round_up},
     };
 
@@ -256,7 +660,7 @@ mod index_test {
         let config =
             IndexConfiguration::new(Metric::L2, 10, 16, 16, false, 0, false, 8, 1f32, parameters);
         let mut index = InmemIndex::<f32, 3>::new(config).unwrap();
-        let final_graph = InMemoryGraph::new(10, 3);
+        let final_graph = InMemoryGraph::new(24, 3);
         let num_active_pts = 2_usize;
         index.final_graph = final_graph;
         index.num_active_pts = num_active_pts;
@@ -301,4 +705,184 @@ mod index_test {
         );
         fs::remove_file(data_file).expect("Failed to delete file");
     }
+
+    #[test]
+    fn dynamic_index_snapshot_round_trip() {
+        let (data_num, dim) = load_metadata_from_file(TEST_DATA_FILE).unwrap();
+        let (data, _, _) = crate::utils::load_bin::<f32>(TEST_DATA_FILE, 0).unwrap();
+        let vectors: Vec<Vec<f32>> = data.chunks_exact(dim).map(|row| row.to_vec()).collect();
+        let config = || {
+            let index_write_parameters = IndexWriteParametersBuilder::new(L, 16)
+                .with_alpha(ALPHA)
+                .with_num_threads(1)
+                .build();
+            IndexConfiguration::new(
+                Metric::L2,
+                dim,
+                DIM_128,
+                data_num + 16,
+                false,
+                0,
+                false,
+                1,
+                1f32,
+                index_write_parameters,
+            )
+        };
+
+        // A frozen point searches start from, some points streamed in and one deleted
+        let mut index: InmemIndex<f32, DIM_128> = InmemIndex::new(config()).unwrap();
+        index.build_from_vectors(&vectors[..200]).unwrap();
+        for vector in &vectors[200..] {
+            index.insert_point(vector).unwrap();
+        }
+        index.delete_point(7).unwrap();
+        let snapshot = "dynamic_index_snapshot_round_trip";
+        index.save(snapshot).unwrap();
+
+        let mut loaded: InmemIndex<f32, DIM_128> = InmemIndex::new(config()).unwrap();
+        loaded.load(snapshot, data_num + 1).unwrap();
+        assert_eq!(loaded.num_active_pts, data_num);
+        assert_eq!(loaded.start, index.start);
+        assert_eq!(loaded.start as usize, data_num + 16);
+        for id in (0..data_num + 16 + 1).filter(|&id| id < data_num || id >= data_num + 16) {
+            let id = id as u32;
+            let loaded_vertex = loaded.final_graph.read_vertex_and_neighbors(id).unwrap();
+            let vertex = index.final_graph.read_vertex_and_neighbors(id).unwrap();
+            assert_eq!(loaded_vertex.get_neighbors(), vertex.get_neighbors());
+            assert_eq!(
+                loaded.dataset.get_vertex(id).unwrap().vector(),
+                index.dataset.get_vertex(id).unwrap().vector()
+            );
+        }
+
+        let mut expected = [0u32; 5];
+        let mut indices = [0u32; 5];
+        for vector in vectors.iter().step_by(9) {
+            ANNInmemIndex::search(&index, vector, 5, L, &mut expected).unwrap();
+            ANNInmemIndex::search(&loaded, vector, 5, L, &mut indices).unwrap();
+            assert_eq!(indices, expected);
+            assert!(!indices.contains(&7));
+        }
+
+        // The restored index keeps growing, and a later snapshot drops the old delete list
+        assert_eq!(loaded.insert_point(&vectors[7]).unwrap() as usize, data_num);
+        loaded.delete_set.write().unwrap().clear();
+        loaded.save(snapshot).unwrap();
+        assert!(!file_exists(&format!("{}.delete", snapshot)));
+
+        for suffix in ["", ".data", ".delete", ".lock"] {
+            let file = format!("{}{}", snapshot, suffix);
+            if file_exists(&file) {
+                fs::remove_file(file).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn nodes_round_trip_through_a_storage_provider() {
+        let (data_num, dim) = load_metadata_from_file(TEST_DATA_FILE).unwrap();
+        let (data, _, _) = crate::utils::load_bin::<f32>(TEST_DATA_FILE, 0).unwrap();
+        let vectors: Vec<Vec<f32>> = data.chunks_exact(dim).map(|row| row.to_vec()).collect();
+        let config = || {
+            let index_write_parameters = IndexWriteParametersBuilder::new(L, 16)
+                .with_alpha(ALPHA)
+                .with_num_threads(1)
+                .build();
+            IndexConfiguration::new(
+                Metric::L2,
+                dim,
+                DIM_128,
+                data_num + 16,
+                false,
+                0,
+                false,
+                1,
+                1f32,
+                index_write_parameters,
+            )
+        };
+
+        // The frozen point is stored after the points, the streamed points included
+        let mut index: InmemIndex<f32, DIM_128> = InmemIndex::new(config()).unwrap();
+        index.build_from_vectors(&vectors[..200]).unwrap();
+        for vector in &vectors[200..] {
+            index.insert_point(vector).unwrap();
+        }
+        let mut provider = InMemoryStorageProvider::new(dim, 0, 0);
+        index.save_to_provider(&mut provider).unwrap();
+        let metadata = provider.metadata().unwrap();
+        assert_eq!(metadata.num_points, data_num + 1);
+        assert_eq!(metadata.frozen_point, Some(data_num as u32));
+        assert_eq!(metadata.medoid, data_num as u32);
+        assert_eq!(provider.get_node(3).unwrap().vector, vectors[3]);
+
+        let mut loaded: InmemIndex<f32, DIM_128> = InmemIndex::new(config()).unwrap();
+        loaded.load_from_provider(&provider).unwrap();
+        assert_eq!(loaded.num_active_pts, data_num);
+        assert_eq!(loaded.start, index.start);
+        let mut expected = [0u32; 5];
+        let mut indices = [0u32; 5];
+        for vector in vectors.iter().step_by(9) {
+            ANNInmemIndex::search(&index, vector, 5, L, &mut expected).unwrap();
+            ANNInmemIndex::search(&loaded, vector, 5, L, &mut indices).unwrap();
+            assert_eq!(indices, expected);
+        }
+
+        // A store of another dimension or without the frozen point doesn't fit the index
+        let mut static_index: InmemIndex<f32, DIM_128> = InmemIndex::new(IndexConfiguration {
+            num_frozen_pts: 0,
+            ..config()
+        })
+        .unwrap();
+        assert!(static_index.load_from_provider(&provider).is_err());
+        let other_dim = InMemoryStorageProvider::<f32>::new(dim - 1, 16, 0);
+        assert!(loaded.load_from_provider(&other_dim).is_err());
+    }
+
+    #[test]
+    fn node_store_holds_the_nodes_inserts_link() {
+        let (data_num, dim) = load_metadata_from_file(TEST_DATA_FILE).unwrap();
+        let (data, _, _) = crate::utils::load_bin::<f32>(TEST_DATA_FILE, 0).unwrap();
+        let vectors: Vec<Vec<f32>> = data.chunks_exact(dim).map(|row| row.to_vec()).collect();
+        let index_write_parameters = IndexWriteParametersBuilder::new(L, 16)
+            .with_alpha(ALPHA)
+            .with_num_threads(1)
+            .build();
+        let config = IndexConfiguration::new(
+            Metric::L2,
+            dim,
+            DIM_128,
+            data_num + 16,
+            false,
+            0,
+            false,
+            1,
+            1f32,
+            index_write_parameters,
+        );
+        let max_points = config.max_points as u32;
+
+        let mut index: InmemIndex<f32, DIM_128> = InmemIndex::new(config).unwrap();
+        index.build_from_vectors(&vectors[..200]).unwrap();
+        let store = InMemoryStorageProvider::<f32>::new(dim, 0, 0);
+        assert_eq!(index.set_node_store(Box::new(store)).unwrap(), 201);
+        for vector in &vectors[200..] {
+            index.insert_point(vector).unwrap();
+        }
+
+        let node_store = index
+            .lock_node_store(index.node_store.as_ref().unwrap())
+            .unwrap();
+        let metadata = node_store.metadata().unwrap();
+        assert_eq!(metadata.frozen_point, Some(max_points));
+        assert_eq!(metadata.medoid, index.start);
+        for id in (0..data_num as u32).chain([max_points]) {
+            let node = node_store.get_node(id).unwrap();
+            let mut neighbors = Vec::new();
+            index.copy_neighbors(id, &mut neighbors).unwrap();
+            assert_eq!(node.neighbors, neighbors);
+        }
+        assert_eq!(node_store.get_node(250).unwrap().vector, vectors[250]);
+    }
 }
@@ -8,5 +8,19 @@ pub use inmem_index::InmemIndex;
 
 mod inmem_index_storage;
 
+mod recall_tuning;
+pub use recall_tuning::SearchListCalibration;
+
+mod write_ahead_log;
+pub use write_ahead_log::{WalRecord, WriteAheadLog};
+
+mod written_slots;
+use written_slots::WrittenSlots;
+
+#[cfg(feature = "gpu")]
+mod gpu_distance;
+#[cfg(feature = "gpu")]
+pub use gpu_distance::GpuDistance;
+
 pub mod ann_inmem_index;
 
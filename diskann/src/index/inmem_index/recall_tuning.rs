@@ -0,0 +1,281 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Search list size chosen for a target recall instead of guessed.
+//!
+//! Calibration searches a sample of queries with known nearest neighbors at growing search
+//! list sizes and keeps the smallest one whose mean recall meets the target. A search for the
+//! target recall then starts at half the calibrated size and doubles it until the K results
+//! stop changing, never stopping below the calibrated size, so the queries that are harder
+//! than the sample get the larger search lists they need.
+
+use hashbrown::HashSet;
+use log::info;
+use vector::{Distance, FullPrecisionDistance};
+
+use crate::common::{ANNError, ANNResult};
+use crate::model::{SearchParams, Vertex};
+
+use super::inmem_index::padded_query;
+use super::InmemIndex;
+
+/// Search list size found by calibrate_search_list_size
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SearchListCalibration {
+    /// Number of neighbors searched for
+    pub k_value: usize,
+
+    /// Mean recall@K the size was calibrated for
+    pub target_recall: f32,
+
+    /// Smallest search list size reaching the target recall on the sample
+    pub l_value: u32,
+
+    /// Mean recall@K of the sample at l_value
+    pub recall: f32,
+
+    /// Largest search list size a search for the target recall grows to
+    pub max_l_value: u32,
+}
+
+impl<T, const N: usize, D> InmemIndex<T, N, D>
+where
+    T: Default + Copy + Sync + Send + Into<f32>,
+    [T; N]: FullPrecisionDistance<T, N>,
+    D: Distance<T, N>,
+{
+    /// Find the smallest search list size, up to max_l_value, at which the mean recall@K of
+    /// queries against their ground truth nearest neighbors, nearest first, reaches
+    /// target_recall. Fails if max_l_value doesn't reach it.
+    pub fn calibrate_search_list_size(
+        &self,
+        queries: &[Vec<T>],
+        ground_truth: &[Vec<u32>],
+        k_value: usize,
+        target_recall: f32,
+        max_l_value: u32,
+    ) -> ANNResult<SearchListCalibration> {
+        if queries.is_empty() || queries.len() != ground_truth.len() {
+            return Err(ANNError::log_index_config_error(
+                "ground_truth".to_string(),
+                format!(
+                    "{} sample queries need as many ground truth lists, got {}",
+                    queries.len(),
+                    ground_truth.len()
+                ),
+            ));
+        }
+        if let Some(truth) = ground_truth.iter().find(|truth| truth.len() < k_value) {
+            return Err(ANNError::log_index_config_error(
+                "ground_truth".to_string(),
+                format!(
+                    "Ground truth lists {} neighbors, recall@{} needs {}",
+                    truth.len(),
+                    k_value,
+                    k_value
+                ),
+            ));
+        }
+        if !(target_recall > 0.0 && target_recall <= 1.0) {
+            return Err(ANNError::log_index_config_error(
+                "target_recall".to_string(),
+                format!("Target recall {} should be in (0, 1]", target_recall),
+            ));
+        }
+        if k_value == 0 || max_l_value < k_value as u32 {
+            return Err(ANNError::log_index_config_error(
+                "max_l_value".to_string(),
+                format!(
+                    "Largest search list size {} should be at least K: {}, and K > 0",
+                    max_l_value, k_value
+                ),
+            ));
+        }
+
+        let queries = queries
+            .iter()
+            .map(|query| padded_query::<T, N>(query))
+            .collect::<ANNResult<Vec<_>>>()?;
+        let sample_recall = |l_value: u32| -> ANNResult<f32> {
+            let params = SearchParams::new(l_value, 1, None, false)?;
+            let mut indices = vec![0u32; k_value];
+            let mut total = 0.0;
+            for (query, truth) in queries.iter().zip(ground_truth) {
+                let query = Vertex::new(query, 0);
+                self.search_with_params(&query, k_value, &params, &mut indices)?;
+                total += recall_at_k(&indices, &truth[..k_value]);
+            }
+            Ok(total / queries.len() as f32)
+        };
+
+        // Double the size until it reaches the target, then bisect between the last two
+        let mut low = k_value as u32 - 1;
+        let mut high = k_value as u32;
+        let mut recall = sample_recall(high)?;
+        while recall < target_recall {
+            if high == max_l_value {
+                return Err(ANNError::log_index_error(format!(
+                    "ERROR: Recall@{} of the sample is {} at the largest search list size {}, below the target {}.",
+                    k_value, recall, max_l_value, target_recall
+                )));
+            }
+            low = high;
+            high = (high * 2).min(max_l_value);
+            recall = sample_recall(high)?;
+        }
+        while high - low > 1 {
+            let mid = low + (high - low) / 2;
+            let mid_recall = sample_recall(mid)?;
+            if mid_recall >= target_recall {
+                high = mid;
+                recall = mid_recall;
+            } else {
+                low = mid;
+            }
+        }
+
+        info!(
+            "Search list size {} reaches recall@{} {} for target {}",
+            high, k_value, recall, target_recall
+        );
+        Ok(SearchListCalibration {
+            k_value,
+            target_recall,
+            l_value: high,
+            recall,
+            max_l_value,
+        })
+    }
+
+    /// Search the index for the K nearest neighbors of query with the calibrated search list
+    /// size, growing it for this query while the results keep changing. Returns the search
+    /// list size the results were found with.
+    pub fn search_with_target_recall(
+        &self,
+        query: &Vertex<T, N>,
+        calibration: &SearchListCalibration,
+        indices: &mut [u32],
+    ) -> ANNResult<u32> {
+        let k_value = calibration.k_value;
+        if indices.len() < k_value {
+            return Err(ANNError::log_index_error(format!(
+                "ERROR: {} indices can't hold the {} results.",
+                indices.len(),
+                k_value
+            )));
+        }
+
+        let search = |l_value: u32, indices: &mut [u32]| -> ANNResult<()> {
+            let params = SearchParams::new(l_value, 1, None, false)?;
+            self.search_with_params(query, k_value, &params, indices)?;
+            Ok(())
+        };
+
+        let mut l_value = (calibration.l_value / 2).max(k_value as u32);
+        search(l_value, indices)?;
+        let mut previous: HashSet<u32> = indices[..k_value].iter().copied().collect();
+        while l_value < calibration.max_l_value {
+            l_value = (l_value * 2)
+                .max(calibration.l_value)
+                .min(calibration.max_l_value);
+            search(l_value, indices)?;
+
+            let current: HashSet<u32> = indices[..k_value].iter().copied().collect();
+            if l_value >= calibration.l_value && current == previous {
+                break;
+            }
+            previous = current;
+        }
+
+        Ok(l_value)
+    }
+}
+
+/// Fraction of the truth ids among the found ids
+fn recall_at_k(found: &[u32], truth: &[u32]) -> f32 {
+    let truth: HashSet<u32> = truth.iter().copied().collect();
+    let hits = found.iter().filter(|id| truth.contains(*id)).count();
+    hits as f32 / truth.len() as f32
+}
+
+#[cfg(test)]
+mod recall_tuning_test {
+    use vector::Metric;
+
+    use super::*;
+    use crate::model::configuration::index_write_parameters::IndexWriteParametersBuilder;
+    use crate::model::{vertex::DIM_104, IndexConfigurationBuilder};
+
+    #[test]
+    fn calibrated_size_reaches_target_recall() {
+        let points: Vec<Vec<f32>> = (0..400)
+            .map(|i| {
+                let mut point: Vec<f32> = (0..8)
+                    .map(|d| ((i * 7919 + d * 104_729) % 1000) as f32 / 100.0)
+                    .collect();
+                point.resize(100, 0.0);
+                point
+            })
+            .collect();
+        let index_write_parameters = IndexWriteParametersBuilder::new(20, 8)
+            .with_num_threads(1)
+            .build();
+        let config = IndexConfigurationBuilder::new(Metric::L2, 100, 400)
+            .with_index_write_parameters(index_write_parameters)
+            .build();
+        let mut index = InmemIndex::<f32, DIM_104>::new(config).unwrap();
+        crate::index::ANNInmemIndex::build_from_vectors(&mut index, &points).unwrap();
+
+        // Exact neighbors of queries halfway between pairs of points
+        let queries: Vec<Vec<f32>> = (0..20)
+            .map(|i| {
+                let (a, b) = (&points[i * 3], &points[i * 5 + 100]);
+                a.iter().zip(b).map(|(x, y)| (x + y) / 2.0).collect()
+            })
+            .collect();
+        let ground_truth: Vec<Vec<u32>> = queries
+            .iter()
+            .map(|query| {
+                let mut ids: Vec<u32> = (0..points.len() as u32).collect();
+                let distance = |id: &u32| -> f32 {
+                    query
+                        .iter()
+                        .zip(&points[*id as usize])
+                        .map(|(x, y)| (x - y) * (x - y))
+                        .sum()
+                };
+                ids.sort_by(|a, b| distance(a).total_cmp(&distance(b)));
+                ids.truncate(10);
+                ids
+            })
+            .collect();
+
+        let calibration = index
+            .calibrate_search_list_size(&queries, &ground_truth, 10, 0.95, 400)
+            .unwrap();
+        assert!(calibration.recall >= 0.95);
+        assert!(calibration.l_value >= 10 && calibration.l_value <= 400);
+
+        let mut total = 0.0;
+        let mut indices = [0u32; 10];
+        for (query, truth) in queries.iter().zip(&ground_truth) {
+            let query = padded_query::<f32, DIM_104>(query).unwrap();
+            let l_value = index
+                .search_with_target_recall(&Vertex::new(&query, 0), &calibration, &mut indices)
+                .unwrap();
+            assert!(l_value >= calibration.l_value);
+            total += recall_at_k(&indices, truth);
+        }
+        assert!(total / queries.len() as f32 >= 0.95);
+
+        assert!(index
+            .calibrate_search_list_size(&queries, &ground_truth[1..], 10, 0.95, 400)
+            .is_err());
+        assert!(index
+            .calibrate_search_list_size(&queries, &ground_truth, 10, 1.5, 400)
+            .is_err());
+    }
+}
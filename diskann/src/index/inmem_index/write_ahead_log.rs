@@ -0,0 +1,463 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Write-ahead log of the changes streamed into an in-memory index between snapshots.
+//!
+//! The file starts with a header of the magic bytes, the size of a vector element and the
+//! dimension. Each record follows as its kind byte (1 insert, 2 delete), the vertex id, the
+//! number of vector elements (0 for deletes), the elements and an FNV-1a checksum of the
+//! record bytes before it, all little endian. Records are written with a single write each,
+//! so a process crash leaves at most one torn record at the end, which opening cuts off. A
+//! corrupt record followed by intact ones fails the opening instead, as cutting it off would
+//! lose them.
+//! The records are synced to stable storage as the WalSyncPolicy of the log says, so the ones
+//! synced survive an OS crash or a power loss too.
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::mem;
+use std::time::Instant;
+
+use byteorder::{ByteOrder, LittleEndian};
+use log::warn;
+
+use crate::common::{ANNError, ANNResult};
+use crate::model::WalSyncPolicy;
+
+/// First bytes of a log file
+const MAGIC: &[u8; 8] = b"DANNWAL1";
+
+/// Bytes of the header: magic, element size and dimension
+const HEADER_SIZE: usize = MAGIC.len() + 2 * mem::size_of::<u32>();
+
+/// Bytes of a record without its vector: kind, vertex id, number of elements and checksum
+const RECORD_OVERHEAD: usize = 1 + 3 * mem::size_of::<u32>();
+
+const INSERT: u8 = 1;
+const DELETE: u8 = 2;
+
+/// A change recorded in the log
+#[derive(Debug, Clone, PartialEq)]
+pub enum WalRecord<T> {
+    /// vector was inserted as vertex_id
+    Insert {
+        /// Id the point was given
+        vertex_id: u32,
+
+        /// Vector of the point, of the dimension of the log
+        vector: Vec<T>,
+    },
+
+    /// vertex_id was deleted
+    Delete {
+        /// Id of the deleted point
+        vertex_id: u32,
+    },
+}
+
+/// Log file the inserts and deletes of an index are appended to before they are applied
+#[derive(Debug)]
+pub struct WriteAheadLog<T> {
+    file: File,
+
+    /// Number of elements of the logged vectors
+    dim: usize,
+
+    /// When appended records are synced
+    sync_policy: WalSyncPolicy,
+
+    /// Records written since the last sync
+    num_unsynced_records: usize,
+
+    /// When the oldest unsynced record was written
+    oldest_unsynced: Option<Instant>,
+
+    _element: PhantomData<T>,
+}
+
+impl<T: Copy + Default> WriteAheadLog<T> {
+    /// Open the log of vectors of dim elements at path, creating it if it doesn't exist, and
+    /// return it with the records it holds, oldest first. Appended records are synced as
+    /// sync_policy says.
+    pub fn open(
+        path: &str,
+        dim: usize,
+        sync_policy: WalSyncPolicy,
+    ) -> ANNResult<(Self, Vec<WalRecord<T>>)> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+
+        let header = Self::header(dim);
+        if contents.is_empty() {
+            file.write_all(&header)?;
+            file.sync_all()?;
+            sync_parent_dir(path)?;
+            contents = header.clone();
+        }
+        if contents.len() < HEADER_SIZE || contents[..HEADER_SIZE] != header[..] {
+            return Err(ANNError::log_index_error(format!(
+                "ERROR: {} is not a write-ahead log of vectors of {} elements of {} bytes.",
+                path,
+                dim,
+                mem::size_of::<T>()
+            )));
+        }
+
+        let (records, valid_len) = Self::parse_records(&contents[HEADER_SIZE..], dim);
+        let valid_len = HEADER_SIZE + valid_len;
+        let num_later_records = Self::count_records_after(&contents[valid_len..], dim);
+        if num_later_records > 0 {
+            return Err(ANNError::log_index_error(format!(
+                "ERROR: Write-ahead log {} has a corrupt record at byte {} followed by {} intact records, which cutting it off would lose.",
+                path, valid_len, num_later_records
+            )));
+        }
+
+        let valid_len = valid_len as u64;
+        if valid_len < contents.len() as u64 {
+            warn!(
+                "Cutting a torn record of {} bytes off the end of write-ahead log {}",
+                contents.len() as u64 - valid_len,
+                path
+            );
+            file.set_len(valid_len)?;
+            file.sync_data()?;
+        }
+        file.seek(SeekFrom::Start(valid_len))?;
+
+        Ok((
+            Self {
+                file,
+                dim,
+                sync_policy,
+                num_unsynced_records: 0,
+                oldest_unsynced: None,
+                _element: PhantomData,
+            },
+            records,
+        ))
+    }
+
+    /// Record that vector is inserted as vertex_id
+    pub fn append_insert(&mut self, vertex_id: u32, vector: &[T]) -> ANNResult<()> {
+        if vector.len() != self.dim {
+            return Err(ANNError::log_index_error(format!(
+                "ERROR: Can't log a vector of {} elements in a log of {} elements.",
+                vector.len(),
+                self.dim
+            )));
+        }
+
+        // SAFETY: the elements are plain Copy values, read as the bytes they are made of
+        let bytes = unsafe {
+            std::slice::from_raw_parts(vector.as_ptr() as *const u8, mem::size_of_val(vector))
+        };
+        self.append(INSERT, vertex_id, vector.len() as u32, bytes)
+    }
+
+    /// Record that vertex_id is deleted
+    pub fn append_delete(&mut self, vertex_id: u32) -> ANNResult<()> {
+        self.append(DELETE, vertex_id, 0, &[])
+    }
+
+    /// Drop every record, once a snapshot of the index holds their changes
+    pub fn truncate(&mut self) -> ANNResult<()> {
+        self.file.set_len(HEADER_SIZE as u64)?;
+        self.file.seek(SeekFrom::Start(HEADER_SIZE as u64))?;
+        self.file.sync_data()?;
+        self.num_unsynced_records = 0;
+        self.oldest_unsynced = None;
+        Ok(())
+    }
+
+    /// Sync the records written since the last sync to stable storage
+    pub fn sync(&mut self) -> ANNResult<()> {
+        if self.num_unsynced_records > 0 {
+            self.file.sync_data()?;
+            self.num_unsynced_records = 0;
+            self.oldest_unsynced = None;
+        }
+        Ok(())
+    }
+
+    /// Number of records written since the last sync, which an OS crash or a power loss can
+    /// lose
+    pub fn num_unsynced_records(&self) -> usize {
+        self.num_unsynced_records
+    }
+
+    fn append(&mut self, kind: u8, vertex_id: u32, len: u32, values: &[u8]) -> ANNResult<()> {
+        let mut record = Vec::with_capacity(RECORD_OVERHEAD + values.len());
+        record.push(kind);
+        record.extend_from_slice(&vertex_id.to_le_bytes());
+        record.extend_from_slice(&len.to_le_bytes());
+        record.extend_from_slice(values);
+        let checksum = fnv1a(&record);
+        record.extend_from_slice(&checksum.to_le_bytes());
+        self.file.write_all(&record)?;
+
+        self.num_unsynced_records += 1;
+        let oldest_unsynced = *self.oldest_unsynced.get_or_insert_with(Instant::now);
+        let sync_due = match self.sync_policy {
+            WalSyncPolicy::EveryRecord => true,
+            WalSyncPolicy::Batch {
+                max_records,
+                max_delay,
+            } => self.num_unsynced_records >= max_records || oldest_unsynced.elapsed() >= max_delay,
+        };
+        if sync_due {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    fn header(dim: usize) -> Vec<u8> {
+        let mut header = MAGIC.to_vec();
+        header.extend_from_slice(&(mem::size_of::<T>() as u32).to_le_bytes());
+        header.extend_from_slice(&(dim as u32).to_le_bytes());
+        header
+    }
+
+    /// Records of bytes up to the first incomplete or corrupt one, and the bytes they span
+    fn parse_records(bytes: &[u8], dim: usize) -> (Vec<WalRecord<T>>, usize) {
+        let read_u32 = |at: usize| LittleEndian::read_u32(&bytes[at..at + 4]);
+        let mut records = Vec::new();
+        let mut offset = 0;
+        while offset + RECORD_OVERHEAD <= bytes.len() {
+            let kind = bytes[offset];
+            let vertex_id = read_u32(offset + 1);
+            let len = read_u32(offset + 5) as usize;
+            let values_size = len * mem::size_of::<T>();
+            let end = offset + RECORD_OVERHEAD + values_size;
+            if end > bytes.len() {
+                break;
+            }
+            if read_u32(end - 4) != fnv1a(&bytes[offset..end - 4]) {
+                break;
+            }
+
+            let record = match (kind, len) {
+                (INSERT, len) if len == dim => {
+                    let mut vector = vec![T::default(); len];
+                    // SAFETY: vector holds len elements, values_size bytes, of a Copy type
+                    unsafe {
+                        std::ptr::copy_nonoverlapping(
+                            bytes[offset + 9..].as_ptr(),
+                            vector.as_mut_ptr() as *mut u8,
+                            values_size,
+                        );
+                    }
+                    WalRecord::Insert { vertex_id, vector }
+                }
+                (DELETE, 0) => WalRecord::Delete { vertex_id },
+                _ => break,
+            };
+            records.push(record);
+            offset = end;
+        }
+        (records, offset)
+    }
+
+    /// Number of intact records following the incomplete or corrupt record bytes start with,
+    /// skipping it by the number of elements it declares. None when it is the last record,
+    /// e.g. torn by a crash.
+    fn count_records_after(bytes: &[u8], dim: usize) -> usize {
+        if bytes.len() < RECORD_OVERHEAD {
+            return 0;
+        }
+        let len = LittleEndian::read_u32(&bytes[5..9]) as usize;
+        let end = len
+            .saturating_mul(mem::size_of::<T>())
+            .saturating_add(RECORD_OVERHEAD);
+        if end >= bytes.len() {
+            return 0;
+        }
+        Self::parse_records(&bytes[end..], dim).0.len()
+    }
+}
+
+impl<T> Drop for WriteAheadLog<T> {
+    fn drop(&mut self) {
+        if self.num_unsynced_records > 0 {
+            if let Err(err) = self.file.sync_data() {
+                warn!("Failed to sync the write-ahead log on close: {}", err);
+            }
+        }
+    }
+}
+
+/// Sync the directory of a newly created file, so its entry survives a power loss too
+#[cfg(unix)]
+fn sync_parent_dir(path: &str) -> ANNResult<()> {
+    use std::path::Path;
+
+    let parent = match Path::new(path).parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    File::open(parent)?.sync_all()?;
+    Ok(())
+}
+
+/// Directories can't be opened for syncing on other platforms, whose file systems journal
+/// the entries of created files
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &str) -> ANNResult<()> {
+    Ok(())
+}
+
+/// 32 bit FNV-1a hash of bytes
+fn fnv1a(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c9dc5, |hash, &byte| {
+        (hash ^ byte as u32).wrapping_mul(0x01000193)
+    })
+}
+
+#[cfg(test)]
+mod write_ahead_log_test {
+    use super::*;
+
+    #[test]
+    fn records_survive_reopen_and_torn_tail_is_cut() {
+        let path = "records_survive_reopen_and_torn_tail_is_cut.wal";
+        let (mut log, records) = WriteAheadLog::<f32>::open(path, 3, WalSyncPolicy::EveryRecord).unwrap();
+        assert!(records.is_empty());
+        log.append_insert(10, &[1.0, 2.0, 3.0]).unwrap();
+        log.append_delete(4).unwrap();
+        log.append_insert(11, &[4.0, 5.0, 6.0]).unwrap();
+        assert!(log.append_insert(12, &[1.0]).is_err());
+        drop(log);
+
+        // A crash in the middle of the last record leaves part of it
+        let full_len = std::fs::metadata(path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_len(full_len - 5)
+            .unwrap();
+
+        let (mut log, records) = WriteAheadLog::<f32>::open(path, 3, WalSyncPolicy::EveryRecord).unwrap();
+        assert_eq!(
+            records,
+            vec![
+                WalRecord::Insert {
+                    vertex_id: 10,
+                    vector: vec![1.0, 2.0, 3.0]
+                },
+                WalRecord::Delete { vertex_id: 4 },
+            ]
+        );
+        log.append_delete(5).unwrap();
+        drop(log);
+        let (mut log, records) = WriteAheadLog::<f32>::open(path, 3, WalSyncPolicy::EveryRecord).unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[2], WalRecord::Delete { vertex_id: 5 });
+
+        log.truncate().unwrap();
+        drop(log);
+        let (_, records) = WriteAheadLog::<f32>::open(path, 3, WalSyncPolicy::EveryRecord).unwrap();
+        assert!(records.is_empty());
+        assert!(WriteAheadLog::<u8>::open(path, 3, WalSyncPolicy::EveryRecord).is_err());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn only_a_corrupt_last_record_is_cut() {
+        let path = "only_a_corrupt_last_record_is_cut.wal";
+        let write_records = || {
+            let _ = std::fs::remove_file(path);
+            let (mut log, _) =
+                WriteAheadLog::<f32>::open(path, 3, WalSyncPolicy::EveryRecord).unwrap();
+            log.append_insert(10, &[1.0, 2.0, 3.0]).unwrap();
+            log.append_delete(4).unwrap();
+            log.append_insert(11, &[4.0, 5.0, 6.0]).unwrap();
+        };
+        let flip_byte = |at: u64| {
+            let mut file = OpenOptions::new().read(true).write(true).open(path).unwrap();
+            let mut byte = [0u8];
+            file.seek(SeekFrom::Start(at)).unwrap();
+            file.read_exact(&mut byte).unwrap();
+            file.seek(SeekFrom::Start(at)).unwrap();
+            file.write_all(&[byte[0] ^ 0xff]).unwrap();
+        };
+        // Inserts of 3 f32 take 25 bytes and deletes 13, after the 16 bytes of the header
+        let first_vector = (HEADER_SIZE + 9) as u64;
+        let last_vector = (HEADER_SIZE + 25 + 13 + 9) as u64;
+
+        // Corrupting the first record would drop the two after it
+        write_records();
+        flip_byte(first_vector);
+        let err = WriteAheadLog::<f32>::open(path, 3, WalSyncPolicy::EveryRecord).unwrap_err();
+        assert!(err.to_string().contains("followed by 2 intact records"));
+
+        // The last record is cut off
+        write_records();
+        flip_byte(last_vector);
+        let (_, records) = WriteAheadLog::<f32>::open(path, 3, WalSyncPolicy::EveryRecord).unwrap();
+        assert_eq!(records.len(), 2);
+
+        // So are zeros the file system left after the records
+        write_records();
+        let mut file = OpenOptions::new().append(true).open(path).unwrap();
+        file.write_all(&[0; 40]).unwrap();
+        drop(file);
+        let (_, records) = WriteAheadLog::<f32>::open(path, 3, WalSyncPolicy::EveryRecord).unwrap();
+        assert_eq!(records.len(), 3);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn records_are_synced_by_the_policy() {
+        let path = "records_are_synced_by_the_policy.wal";
+        let (mut log, _) =
+            WriteAheadLog::<f32>::open(path, 2, WalSyncPolicy::EveryRecord).unwrap();
+        log.append_insert(0, &[1.0, 2.0]).unwrap();
+        assert_eq!(log.num_unsynced_records(), 0);
+        drop(log);
+
+        let batch = WalSyncPolicy::Batch {
+            max_records: 3,
+            max_delay: std::time::Duration::from_secs(3600),
+        };
+        let (mut log, records) = WriteAheadLog::<f32>::open(path, 2, batch).unwrap();
+        assert_eq!(records.len(), 1);
+        log.append_insert(1, &[3.0, 4.0]).unwrap();
+        log.append_delete(0).unwrap();
+        assert_eq!(log.num_unsynced_records(), 2);
+        log.append_delete(1).unwrap();
+        assert_eq!(log.num_unsynced_records(), 0);
+        log.append_delete(1).unwrap();
+        log.sync().unwrap();
+        assert_eq!(log.num_unsynced_records(), 0);
+
+        // A batch whose oldest record is past the delay is synced by the next record
+        let (mut log, _) = WriteAheadLog::<f32>::open(
+            path,
+            2,
+            WalSyncPolicy::Batch {
+                max_records: 100,
+                max_delay: std::time::Duration::ZERO,
+            },
+        )
+        .unwrap();
+        log.append_delete(0).unwrap();
+        assert_eq!(log.num_unsynced_records(), 0);
+        log.truncate().unwrap();
+        drop(log);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
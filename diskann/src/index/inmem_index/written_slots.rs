@@ -0,0 +1,113 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Publication of the vectors streamed into an in-memory index.
+//! Concurrent inserts reserve consecutive slots, then copy their vectors in any order. Scans
+//! over the points only read the leading slots whose vectors are all copied, so each insert
+//! marks its slot ready in a bitmap and moves the count of leading ready slots past every
+//! ready slot it finds. An insert never waits for the ones before it: the last of them to
+//! finish moves the count past the slots finished out of order.
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// Slots after the active points whose vectors are copied
+#[derive(Debug)]
+pub(crate) struct WrittenSlots {
+    /// Number of leading slots whose vectors are copied
+    len: AtomicUsize,
+
+    /// One bit per slot, set once its vector is copied
+    ready: Vec<AtomicU64>,
+}
+
+impl WrittenSlots {
+    /// Create the slots of up to capacity streamed points, none written
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            len: AtomicUsize::new(0),
+            ready: (0..capacity.div_ceil(64))
+                .map(|_| AtomicU64::new(0))
+                .collect(),
+        }
+    }
+
+    /// Number of leading slots whose vectors are copied
+    pub(crate) fn len(&self) -> usize {
+        self.len.load(Ordering::Acquire)
+    }
+
+    /// Mark the vector of slot as copied and count every leading slot that now is
+    pub(crate) fn publish(&self, slot: usize) {
+        let Some(word) = self.ready.get(slot / 64) else {
+            return;
+        };
+        // Sequentially consistent, so that of an insert setting its bit and another moving
+        // the count up to that slot, at least one sees what the other did
+        word.fetch_or(1 << (slot % 64), Ordering::SeqCst);
+
+        let mut len = self.len.load(Ordering::SeqCst);
+        while self.is_ready(len) {
+            match self
+                .len
+                .compare_exchange(len, len + 1, Ordering::SeqCst, Ordering::SeqCst)
+            {
+                Ok(_) => len += 1,
+                Err(current) => len = current,
+            }
+        }
+    }
+
+    /// Forget every slot and make room for capacity of them
+    pub(crate) fn reset(&mut self, capacity: usize) {
+        *self.len.get_mut() = 0;
+        self.ready
+            .resize_with(capacity.div_ceil(64), || AtomicU64::new(0));
+        for word in self.ready.iter_mut() {
+            *word.get_mut() = 0;
+        }
+    }
+
+    fn is_ready(&self, slot: usize) -> bool {
+        self.ready
+            .get(slot / 64)
+            .is_some_and(|word| word.load(Ordering::SeqCst) & (1 << (slot % 64)) != 0)
+    }
+}
+
+#[cfg(test)]
+mod written_slots_test {
+    use super::*;
+
+    #[test]
+    fn slots_written_out_of_order_are_counted_once_the_gap_closes() {
+        let mut slots = WrittenSlots::new(130);
+        slots.publish(1);
+        slots.publish(2);
+        assert_eq!(slots.len(), 0);
+        slots.publish(0);
+        assert_eq!(slots.len(), 3);
+
+        // Across words, from many threads
+        std::thread::scope(|scope| {
+            for first in 3..7 {
+                let slots = &slots;
+                scope.spawn(move || {
+                    for slot in (first..130).step_by(4).rev() {
+                        slots.publish(slot);
+                    }
+                });
+            }
+        });
+        assert_eq!(slots.len(), 130);
+
+        slots.reset(200);
+        assert_eq!(slots.len(), 0);
+        slots.publish(1);
+        assert_eq!(slots.len(), 0);
+        slots.publish(0);
+        assert_eq!(slots.len(), 2);
+    }
+}
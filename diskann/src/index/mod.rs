@@ -5,7 +5,24 @@
 mod inmem_index;
 pub use inmem_index::ann_inmem_index::*;
 pub use inmem_index::InmemIndex;
+pub use inmem_index::{SearchListCalibration, WalRecord, WriteAheadLog};
+#[cfg(feature = "gpu")]
+pub use inmem_index::GpuDistance;
 
+#[cfg(feature = "disk-index")]
 mod disk_index;
+#[cfg(feature = "disk-index")]
 pub use disk_index::*;
 
+
+mod ann_benchmarks;
+pub use ann_benchmarks::*;
+
+mod query_batcher;
+pub use query_batcher::*;
+
+mod index_events;
+pub use index_events::*;
+
+mod index_manager;
+pub use index_manager::*;
@@ -0,0 +1,482 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Adaptive micro-batching of single queries.
+//! Queries submitted one at a time from many threads, e.g. the request handlers of a server,
+//! are grouped into small batches that are searched together: on the rayon pool for an
+//! in-memory index, and by one batched beam search sharing its sector reads for a disk index.
+//! A batch is closed when it is full or when the batching window has elapsed since its first query, so
+//! no query waits longer than the window before its batch starts. The window adapts to load:
+//! it shrinks when batches fill up before it ends or when no other query arrives during it,
+//! and grows while it keeps collecting partial batches.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "disk-index")]
+use std::collections::BTreeMap;
+
+use rayon::prelude::*;
+#[cfg(feature = "disk-index")]
+use tokio::runtime::Handle;
+#[cfg(feature = "disk-index")]
+use vector::FullPrecisionDistance;
+
+use crate::common::{ANNError, ANNResult};
+use crate::index::ANNInmemIndex;
+#[cfg(feature = "disk-index")]
+use crate::index::DiskIndex;
+#[cfg(feature = "disk-index")]
+use crate::model::SearchParams;
+use crate::model::{SearchResult, SearchResultFields};
+
+/// Limits of the batching window and of the batch size
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueryBatcherConfig {
+    /// Largest number of queries searched together
+    pub max_batch_size: usize,
+
+    /// Shortest batching window
+    pub min_window: Duration,
+
+    /// Longest batching window, i.e. the longest a query waits for its batch to start
+    pub max_window: Duration,
+}
+
+impl Default for QueryBatcherConfig {
+    fn default() -> Self {
+        Self {
+            max_batch_size: 32,
+            min_window: Duration::from_micros(50),
+            max_window: Duration::from_millis(2),
+        }
+    }
+}
+
+/// A query of a batch
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchQuery<T> {
+    /// Query vector
+    pub query: Vec<T>,
+
+    /// Number of neighbors to return
+    pub k_value: usize,
+
+    /// Size of the search list
+    pub l_value: u32,
+}
+
+/// Searches a batch of queries, returning the results of each query in the order of the batch
+pub type BatchSearchFn<T> =
+    dyn Fn(&[BatchQuery<T>]) -> Vec<ANNResult<Vec<SearchResult>>> + Send + Sync;
+
+/// A query waiting for its batch and the channel its results are sent back on
+struct PendingQuery<T> {
+    query: BatchQuery<T>,
+    reply: Sender<ANNResult<Vec<SearchResult>>>,
+}
+
+/// State shared by the callers and the batching thread
+#[derive(Debug, Default)]
+struct BatcherStats {
+    window_micros: AtomicU64,
+    batches: AtomicUsize,
+    queries: AtomicUsize,
+}
+
+/// Groups single queries into batches with a bounded, load-adaptive delay
+pub struct QueryBatcher<T> {
+    sender: Option<Sender<PendingQuery<T>>>,
+    worker: Option<JoinHandle<()>>,
+    stats: Arc<BatcherStats>,
+}
+
+impl<T> fmt::Debug for QueryBatcher<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QueryBatcher")
+            .field("stats", &self.stats)
+            .finish()
+    }
+}
+
+impl<T> QueryBatcher<T>
+where
+    T: Default + Copy + Sync + Send + Into<f32> + 'static,
+{
+    /// Batch the searches of an in-memory index, the queries of a batch searched in parallel
+    pub fn for_index(
+        index: Arc<dyn ANNInmemIndex<T>>,
+        config: QueryBatcherConfig,
+    ) -> ANNResult<Self> {
+        Self::new(
+            Arc::new(move |batch: &[BatchQuery<T>]| {
+                batch
+                    .par_iter()
+                    .map(|query| search_inmem_index(index.as_ref(), query))
+                    .collect()
+            }),
+            config,
+        )
+    }
+
+    /// Batch the searches of a loaded disk index, expanding up to beam_width nodes per round
+    /// trip. The queries of a batch asking for the same k and search list size are searched
+    /// by one search_batch, on the runtime of the index configuration or else the runtime the
+    /// batcher is created in.
+    #[cfg(feature = "disk-index")]
+    pub fn for_disk_index<const N: usize>(
+        index: Arc<DiskIndex<T, N>>,
+        beam_width: usize,
+        config: QueryBatcherConfig,
+    ) -> ANNResult<Self>
+    where
+        [T; N]: FullPrecisionDistance<T, N>,
+        DiskIndex<T, N>: Send + Sync,
+    {
+        let runtime = match &index.index_configuration().runtime {
+            Some(runtime) => runtime.clone(),
+            None => Handle::try_current().map_err(|_| {
+                ANNError::log_index_config_error(
+                    "runtime".to_string(),
+                    "A disk index batcher needs a tokio runtime for its reads".to_string(),
+                )
+            })?,
+        };
+
+        Self::new(
+            Arc::new(move |batch: &[BatchQuery<T>]| {
+                runtime.block_on(search_disk_index(&index, beam_width, batch))
+            }),
+            config,
+        )
+    }
+
+    /// Batch the queries passed to search_fn
+    pub fn new(search_fn: Arc<BatchSearchFn<T>>, config: QueryBatcherConfig) -> ANNResult<Self> {
+        if config.max_batch_size == 0 {
+            return Err(ANNError::log_index_config_error(
+                "max_batch_size".to_string(),
+                "The batch size must be at least 1".to_string(),
+            ));
+        }
+
+        if config.min_window > config.max_window {
+            return Err(ANNError::log_index_config_error(
+                "min_window".to_string(),
+                format!(
+                    "The minimum window {:?} is longer than the maximum window {:?}",
+                    config.min_window, config.max_window
+                ),
+            ));
+        }
+
+        let stats = Arc::new(BatcherStats::default());
+        stats
+            .window_micros
+            .store(config.max_window.as_micros() as u64, Ordering::Relaxed);
+
+        let (sender, receiver) = mpsc::channel();
+        let worker_stats = stats.clone();
+        let worker = thread::Builder::new()
+            .name("diskann-query-batcher".to_string())
+            .spawn(move || run_batches(receiver, search_fn, config, &worker_stats))?;
+
+        Ok(Self {
+            sender: Some(sender),
+            worker: Some(worker),
+            stats,
+        })
+    }
+
+    /// Search a query as part of the next batch and return its neighbors nearest first,
+    /// blocking until its batch has been searched
+    pub fn search(
+        &self,
+        query: &[T],
+        k_value: usize,
+        l_value: u32,
+    ) -> ANNResult<Vec<SearchResult>> {
+        let (reply, result) = mpsc::channel();
+        let pending = PendingQuery {
+            query: BatchQuery {
+                query: query.to_vec(),
+                k_value,
+                l_value,
+            },
+            reply,
+        };
+
+        let stopped = || ANNError::log_index_error("The query batcher has stopped".to_string());
+        self.sender
+            .as_ref()
+            .ok_or_else(stopped)?
+            .send(pending)
+            .map_err(|_| stopped())?;
+        result.recv().map_err(|_| stopped())?
+    }
+
+    /// Current batching window
+    pub fn window(&self) -> Duration {
+        Duration::from_micros(self.stats.window_micros.load(Ordering::Relaxed))
+    }
+
+    /// Number of batches and of queries searched so far
+    pub fn counts(&self) -> (usize, usize) {
+        (
+            self.stats.batches.load(Ordering::Relaxed),
+            self.stats.queries.load(Ordering::Relaxed),
+        )
+    }
+}
+
+impl<T> Drop for QueryBatcher<T> {
+    fn drop(&mut self) {
+        // Closing the channel ends the batching thread once the pending queries are searched
+        self.sender.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Search a query of a batch on an in-memory index
+fn search_inmem_index<T>(
+    index: &dyn ANNInmemIndex<T>,
+    query: &BatchQuery<T>,
+) -> ANNResult<Vec<SearchResult>>
+where
+    T: Default + Copy + Sync + Send + Into<f32>,
+{
+    let (results, _) = index.search_with_details(
+        &query.query,
+        query.k_value,
+        query.l_value,
+        SearchResultFields::NONE,
+    )?;
+    Ok(results)
+}
+
+/// Search a batch on a disk index, one search_batch per k and search list size. When a
+/// search_batch fails its queries are searched one by one, so each gets its own error.
+#[cfg(feature = "disk-index")]
+async fn search_disk_index<T, const N: usize>(
+    index: &DiskIndex<T, N>,
+    beam_width: usize,
+    batch: &[BatchQuery<T>],
+) -> Vec<ANNResult<Vec<SearchResult>>>
+where
+    T: Default + Copy + Sync + Send + Into<f32>,
+    [T; N]: FullPrecisionDistance<T, N>,
+{
+    let mut groups: BTreeMap<(usize, u32), Vec<usize>> = BTreeMap::new();
+    for (i, query) in batch.iter().enumerate() {
+        groups
+            .entry((query.k_value, query.l_value))
+            .or_default()
+            .push(i);
+    }
+
+    let mut results: Vec<Option<ANNResult<Vec<SearchResult>>>> =
+        batch.iter().map(|_| None).collect();
+    for ((k_value, l_value), members) in groups {
+        let params = match SearchParams::new(l_value, beam_width.min(l_value as usize), None, true)
+        {
+            Ok(params) => params,
+            Err(err) => {
+                let message = err.to_string();
+                results[members[0]] = Some(Err(err));
+                for &i in &members[1..] {
+                    results[i] = Some(Err(ANNError::log_index_error(message.clone())));
+                }
+                continue;
+            }
+        };
+
+        let queries: Vec<&[T]> = members.iter().map(|&i| batch[i].query.as_slice()).collect();
+        match index.search_batch(&queries, k_value, &params).await {
+            Ok(found) => {
+                for (&i, (ids, distances, _)) in members.iter().zip(found) {
+                    results[i] = Some(Ok(to_search_results(ids, distances)));
+                }
+            }
+            Err(_) => {
+                for (&i, query) in members.iter().zip(queries) {
+                    let found = index.search_with_params(query, k_value, &params).await;
+                    results[i] =
+                        Some(found.map(|(ids, distances)| to_search_results(ids, distances)));
+                }
+            }
+        }
+    }
+
+    results.into_iter().flatten().collect()
+}
+
+#[cfg(feature = "disk-index")]
+fn to_search_results(ids: Vec<u32>, distances: Vec<f32>) -> Vec<SearchResult> {
+    ids.into_iter()
+        .zip(distances)
+        .map(|(id, distance)| SearchResult::new(id, distance))
+        .collect()
+}
+
+/// Collect and search batches until every sender is dropped
+fn run_batches<T: Send + Sync>(
+    receiver: Receiver<PendingQuery<T>>,
+    search_fn: Arc<BatchSearchFn<T>>,
+    config: QueryBatcherConfig,
+    stats: &BatcherStats,
+) {
+    let mut window = config.max_window;
+    while let Ok(first) = receiver.recv() {
+        let deadline = Instant::now() + window;
+        let mut batch = vec![first];
+        let mut disconnected = false;
+        while batch.len() < config.max_batch_size {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+
+            match receiver.recv_timeout(deadline - now) {
+                Ok(pending) => batch.push(pending),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => {
+                    disconnected = true;
+                    break;
+                }
+            }
+        }
+
+        window = next_window(window, batch.len(), &config);
+        stats
+            .window_micros
+            .store(window.as_micros() as u64, Ordering::Relaxed);
+        stats.batches.fetch_add(1, Ordering::Relaxed);
+        stats.queries.fetch_add(batch.len(), Ordering::Relaxed);
+
+        let (queries, replies): (Vec<_>, Vec<_>) = batch
+            .into_iter()
+            .map(|pending| (pending.query, pending.reply))
+            .unzip();
+        let mut results = search_fn(&queries).into_iter();
+        for reply in replies {
+            let result = results.next().unwrap_or_else(|| {
+                Err(ANNError::log_index_error(
+                    "The batch search returned fewer results than queries".to_string(),
+                ))
+            });
+            // The caller may have given up waiting
+            let _ = reply.send(result);
+        }
+
+        if disconnected {
+            break;
+        }
+    }
+}
+
+/// Adjust the window after collecting a batch of batch_size queries.
+/// A full batch means queries arrive faster than the window needs, and a lone query means
+/// waiting bought nothing, so both halve it. A partial batch means waiting longer would
+/// group more queries, so the window grows by a quarter.
+fn next_window(window: Duration, batch_size: usize, config: &QueryBatcherConfig) -> Duration {
+    let next = if batch_size >= config.max_batch_size || batch_size <= 1 {
+        window / 2
+    } else {
+        window + window / 4
+    };
+    next.clamp(config.min_window, config.max_window)
+}
+
+#[cfg(test)]
+mod query_batcher_test {
+    use std::sync::Barrier;
+
+    use super::*;
+
+    #[test]
+    fn batches_concurrent_queries() {
+        let config = QueryBatcherConfig {
+            max_batch_size: 4,
+            min_window: Duration::from_micros(100),
+            max_window: Duration::from_millis(50),
+        };
+        // Each query is answered with its first k values plus one
+        let search_fn: Arc<BatchSearchFn<f32>> = Arc::new(|batch: &[BatchQuery<f32>]| {
+            batch
+                .iter()
+                .map(|query| {
+                    Ok(query
+                        .query
+                        .iter()
+                        .take(query.k_value)
+                        .map(|value| SearchResult::new(*value as u32 + 1, 0.0))
+                        .collect())
+                })
+                .collect()
+        });
+        let batcher = QueryBatcher::new(search_fn, config).unwrap();
+
+        let barrier = Barrier::new(8);
+        thread::scope(|s| {
+            for i in 0..8 {
+                let (batcher, barrier) = (&batcher, &barrier);
+                s.spawn(move || {
+                    barrier.wait();
+                    let query = [i as f32, 10.0, 20.0];
+                    let ids = |results: Vec<SearchResult>| {
+                        results.iter().map(|result| result.id).collect::<Vec<_>>()
+                    };
+                    assert_eq!(ids(batcher.search(&query, 2, 10).unwrap()), vec![i + 1, 11]);
+                    assert_eq!(
+                        ids(batcher.search(&query, 5, 10).unwrap()),
+                        vec![i + 1, 11, 21]
+                    );
+                });
+            }
+        });
+
+        let (batches, queries) = batcher.counts();
+        assert_eq!(queries, 16);
+        assert!(batches < queries);
+        assert!(batcher.window() >= config.min_window && batcher.window() <= config.max_window);
+    }
+
+    #[test]
+    fn window_adapts_to_batch_size() {
+        let config = QueryBatcherConfig {
+            max_batch_size: 8,
+            min_window: Duration::from_micros(100),
+            max_window: Duration::from_micros(1000),
+        };
+        let window = Duration::from_micros(400);
+        assert_eq!(next_window(window, 8, &config), Duration::from_micros(200));
+        assert_eq!(next_window(window, 1, &config), Duration::from_micros(200));
+        assert_eq!(next_window(window, 4, &config), Duration::from_micros(500));
+        assert_eq!(
+            next_window(Duration::from_micros(900), 4, &config),
+            config.max_window
+        );
+        assert_eq!(
+            next_window(Duration::from_micros(150), 1, &config),
+            config.min_window
+        );
+
+        assert!(QueryBatcher::<f32>::new(
+            Arc::new(|batch: &[BatchQuery<f32>]| batch.iter().map(|_| Ok(Vec::new())).collect()),
+            QueryBatcherConfig {
+                max_batch_size: 0,
+                ..config
+            }
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,135 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Progress of long builds for programs tracking them, such as GUIs and orchestrators.
+//! The IndexLogger of a stage publishes an event each time the stage completes another percent
+//! of its items. Subscribers watch the latest event, so a slow subscriber skips to the current
+//! progress instead of queueing the events it missed.
+
+use tokio::sync::watch;
+
+/// Progress of a stage of a build
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProgressEvent {
+    /// Name of the stage, e.g. Index Construction
+    pub stage: &'static str,
+
+    /// Items of the stage processed so far
+    pub items_done: usize,
+
+    /// Items of the stage
+    pub total_items: usize,
+
+    /// Percentage of the items processed
+    pub percent: f32,
+
+    /// Time since the stage started in seconds
+    pub elapsed_secs: f32,
+
+    /// Items processed per second over the last events, None before time passed
+    pub throughput: Option<f32>,
+
+    /// Estimated time to process the remaining items at the current throughput in seconds
+    pub eta_secs: Option<f32>,
+}
+
+impl ProgressEvent {
+    /// Whether every item of the stage was processed
+    pub fn is_complete(&self) -> bool {
+        self.items_done >= self.total_items
+    }
+}
+
+/// Publishes the progress of builds to the subscribers watching it
+#[derive(Debug)]
+pub struct ProgressNotifier {
+    progress: watch::Sender<Option<ProgressEvent>>,
+}
+
+impl ProgressNotifier {
+    /// Create a notifier without progress to report yet
+    pub fn new() -> Self {
+        let (progress, _) = watch::channel(None);
+        Self { progress }
+    }
+
+    /// Watch the latest progress, None until a stage reports some
+    pub fn subscribe(&self) -> watch::Receiver<Option<ProgressEvent>> {
+        self.progress.subscribe()
+    }
+
+    /// Latest progress
+    pub fn latest(&self) -> Option<ProgressEvent> {
+        self.progress.borrow().clone()
+    }
+
+    /// Replace the latest progress, there may be no subscriber to see it. Threads of a stage
+    /// may publish out of order, so an event behind the latest one of its stage is dropped
+    /// until that stage completes.
+    pub fn publish(&self, event: ProgressEvent) {
+        self.progress.send_if_modified(|latest| {
+            let is_behind = latest.as_ref().is_some_and(|latest| {
+                latest.stage == event.stage
+                    && !latest.is_complete()
+                    && latest.items_done >= event.items_done
+            });
+            if !is_behind {
+                *latest = Some(event);
+            }
+            !is_behind
+        });
+    }
+}
+
+impl Default for ProgressNotifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod build_progress_test {
+    use super::*;
+
+    fn event(stage: &'static str, items_done: usize) -> ProgressEvent {
+        ProgressEvent {
+            stage,
+            items_done,
+            total_items: 10,
+            percent: 10.0 * items_done as f32,
+            elapsed_secs: items_done as f32,
+            throughput: Some(1.0),
+            eta_secs: Some((10 - items_done) as f32),
+        }
+    }
+
+    #[test]
+    fn subscribers_see_the_latest_progress() {
+        let notifier = ProgressNotifier::new();
+        let mut progress = notifier.subscribe();
+        assert_eq!(*progress.borrow_and_update(), None);
+
+        notifier.publish(event("Index Construction", 4));
+        // A thread reporting late doesn't move the progress back
+        notifier.publish(event("Index Construction", 3));
+        assert!(progress.has_changed().unwrap());
+        assert_eq!(
+            progress.borrow_and_update().as_ref(),
+            Some(&event("Index Construction", 4))
+        );
+
+        notifier.publish(event("Index Construction", 10));
+        assert!(notifier.latest().unwrap().is_complete());
+        // A new stage, or the same stage run again, starts over
+        notifier.publish(event("Index Construction", 1));
+        assert_eq!(notifier.latest(), Some(event("Index Construction", 1)));
+        notifier.publish(event("PQ Training", 0));
+        assert_eq!(
+            progress.borrow_and_update().as_ref(),
+            Some(&event("PQ Training", 0))
+        );
+    }
+}
@@ -0,0 +1,167 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Machine-readable report of a disk index build, for CI pipelines tracking build regressions.
+
+use std::fs;
+
+use serde::{Deserialize, Serialize};
+
+use crate::common::{ANNError, ANNResult};
+use crate::model::IndexWriteParameters;
+
+/// Duration of a step of the build, between two checkpoints
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PhaseReport {
+    /// Name of the checkpoint ending the step
+    pub name: String,
+
+    /// Time spent in the step in seconds
+    pub duration_secs: f64,
+
+    /// Memory resident at the end of the step in bytes
+    pub resident_bytes: Option<u64>,
+
+    /// Most memory resident from the start of the build to the end of the step in bytes
+    pub peak_resident_bytes: Option<u64>,
+}
+
+/// Size of a file the build wrote
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutputFileReport {
+    /// Path of the file
+    pub path: String,
+
+    /// Size of the file in bytes
+    pub size_bytes: u64,
+}
+
+/// Parameters the index was built with
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BuildReportParameters {
+    /// Number of points
+    pub num_points: usize,
+
+    /// Dimension of the vectors
+    pub dim: usize,
+
+    /// Distance metric
+    pub metric: String,
+
+    /// Graph build parameters
+    pub index_write: IndexWriteParameters,
+
+    /// Number of PQ chunks the vectors were compressed into
+    pub num_pq_chunks: usize,
+
+    /// Width of the PQ code of a chunk in bits
+    pub pq_code_bits: usize,
+
+    /// RAM budget of the search in bytes, less the space for cached nodes
+    pub search_ram_limit_bytes: f64,
+
+    /// RAM budget of the build in bytes
+    pub index_build_ram_limit_bytes: f64,
+}
+
+/// Report of a disk index build
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BuildReport {
+    /// Steps of the build in order
+    pub phases: Vec<PhaseReport>,
+
+    /// Time of the whole build in seconds
+    pub total_duration_secs: f64,
+
+    /// Most memory resident during the build in bytes, None where it can't be read
+    pub peak_memory_bytes: Option<u64>,
+
+    /// Parameters of the build
+    pub parameters: BuildReportParameters,
+
+    /// Files the build wrote
+    pub output_files: Vec<OutputFileReport>,
+}
+
+impl BuildReport {
+    /// Write the report to filename as JSON
+    pub fn save_json(&self, filename: &str) -> ANNResult<()> {
+        let contents = serde_json::to_string_pretty(self).map_err(|err| {
+            ANNError::log_index_error(format!(
+                "ERROR: Failed to serialize the build report to {}: {}",
+                filename, err
+            ))
+        })?;
+        fs::write(filename, contents)?;
+        Ok(())
+    }
+
+    /// Read a report written by save_json
+    pub fn load_json(filename: &str) -> ANNResult<Self> {
+        let contents = fs::read_to_string(filename)?;
+        serde_json::from_str(&contents).map_err(|err| {
+            ANNError::log_index_error(format!(
+                "ERROR: Failed to parse the build report {}: {}",
+                filename, err
+            ))
+        })
+    }
+}
+
+/// Sizes of the files that exist among paths
+#[cfg_attr(not(feature = "disk-index"), allow(dead_code))]
+pub(crate) fn output_file_reports(paths: &[String]) -> Vec<OutputFileReport> {
+    paths
+        .iter()
+        .filter_map(|path| {
+            fs::metadata(path).ok().map(|metadata| OutputFileReport {
+                path: path.clone(),
+                size_bytes: metadata.len(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod build_report_test {
+    use super::*;
+
+    #[test]
+    fn report_round_trips_through_json() {
+        let file = "report_round_trips_through_json.json";
+        let report = BuildReport {
+            phases: vec![PhaseReport {
+                name: "PQ construction".to_string(),
+                duration_secs: 1.5,
+                resident_bytes: Some(1 << 20),
+                peak_resident_bytes: Some(1 << 21),
+            }],
+            total_duration_secs: 1.5,
+            peak_memory_bytes: Some(1 << 21),
+            parameters: BuildReportParameters {
+                num_points: 256,
+                dim: 128,
+                metric: "L2".to_string(),
+                index_write: IndexWriteParameters::default(),
+                num_pq_chunks: 16,
+                pq_code_bits: 8,
+                search_ram_limit_bytes: 0.03 * 1024.0 * 1024.0 * 1024.0,
+                index_build_ram_limit_bytes: 1024.0 * 1024.0 * 1024.0,
+            },
+            output_files: output_file_reports(&[
+                "tests/data/siftsmall_learn_256pts.fbin".to_string(),
+                "missing_output.bin".to_string(),
+            ]),
+        };
+        assert_eq!(report.output_files.len(), 1);
+        assert_eq!(report.output_files[0].size_bytes, 8 + 256 * 128 * 4);
+
+        report.save_json(file).unwrap();
+        let loaded = BuildReport::load_json(file);
+        fs::remove_file(file).unwrap();
+        assert_eq!(loaded.unwrap(), report);
+    }
+}
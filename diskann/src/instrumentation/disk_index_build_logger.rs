@@ -1,22 +1,110 @@
-use log::{info, error};
+use tracing::info;
+use platform::MemoryTracker;
 use crate::utils::Timer;
 use crate::common::ANNResult;
 
+use super::build_report::output_file_reports;
+use super::{BuildReport, BuildReportParameters, PhaseReport, BUILD_SPAN_TARGET};
+
+const BYTES_PER_MB: f64 = 1024.0 * 1024.0;
+
 pub struct DiskIndexBuildLogger {
     timer: Timer,
+    build_timer: Timer,
+    num_points: usize,
+    phases: Vec<PhaseReport>,
+    memory: MemoryTracker,
 }
 
 impl DiskIndexBuildLogger {
     pub fn new() -> Self {
-        Self { timer: Timer::new() }
+        Self::for_points(0)
+    }
+
+    /// Logger of the build of num_points points, whose checkpoints report the points processed
+    /// per second by the step and by the build so far
+    pub fn for_points(num_points: usize) -> Self {
+        Self {
+            timer: Timer::new(),
+            build_timer: Timer::new(),
+            num_points,
+            phases: Vec::new(),
+            memory: MemoryTracker::new(),
+        }
     }
 
+    /// Log the step since the previous checkpoint as info events of the build target, with the
+    /// checkpoint, elapsed_secs, throughput and build_throughput fields and then the
+    /// checkpoint, resident_bytes and peak_resident_bytes fields
     pub fn log_checkpoint(&mut self, message: &str) -> ANNResult<()> {
         let elapsed_time = self.timer.elapsed().as_secs_f32();
-        info!("Checkpoint: {}, Time Spent: {:.2} seconds", message, elapsed_time);
+        let build_time = self.build_timer.elapsed().as_secs_f32();
+        if self.num_points > 0 && elapsed_time > 0.0 {
+            let throughput = self.num_points as f32 / elapsed_time;
+            let build_throughput = self.num_points as f32 / build_time;
+            info!(
+                target: BUILD_SPAN_TARGET,
+                checkpoint = message,
+                elapsed_secs = elapsed_time,
+                throughput,
+                build_throughput,
+                "Checkpoint: {}, Time Spent: {:.2} seconds, Throughput: {:.0} points/sec, Build Throughput: {:.0} points/sec",
+                message,
+                elapsed_time,
+                throughput,
+                build_throughput
+            );
+        } else {
+            info!(
+                target: BUILD_SPAN_TARGET,
+                checkpoint = message,
+                elapsed_secs = elapsed_time,
+                "Checkpoint: {}, Time Spent: {:.2} seconds",
+                message,
+                elapsed_time
+            );
+        }
+
+        let memory = self.memory.sample();
+        let peak_resident_bytes = self.memory.peak_resident_bytes();
+        if let (Some(memory), Some(peak)) = (memory, peak_resident_bytes) {
+            info!(
+                target: BUILD_SPAN_TARGET,
+                checkpoint = message,
+                resident_bytes = memory.resident_bytes,
+                peak_resident_bytes = peak,
+                "Checkpoint: {}, Resident Memory: {:.1} MB, Peak Resident Memory: {:.1} MB",
+                message,
+                memory.resident_bytes as f64 / BYTES_PER_MB,
+                peak as f64 / BYTES_PER_MB
+            );
+        }
+        self.phases.push(PhaseReport {
+            name: message.to_string(),
+            duration_secs: elapsed_time as f64,
+            resident_bytes: memory.map(|memory| memory.resident_bytes),
+            peak_resident_bytes,
+        });
         self.timer.reset();
         Ok(())
     }
+
+    /// Report of the build: the steps between the checkpoints, the peak memory, the parameters
+    /// and the sizes of the output files that exist
+    pub fn finalize(
+        self,
+        parameters: BuildReportParameters,
+        output_files: &[String],
+    ) -> BuildReport {
+        self.memory.sample();
+        BuildReport {
+            phases: self.phases,
+            total_duration_secs: self.build_timer.elapsed().as_secs_f64(),
+            peak_memory_bytes: self.memory.peak_resident_bytes(),
+            parameters,
+            output_files: output_file_reports(output_files),
+        }
+    }
 }
 
 #[cfg(test)]
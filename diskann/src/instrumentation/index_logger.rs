@@ -1,34 +1,179 @@
 use std::sync::atomic::{AtomicUsize, Ordering};
-use log::{info, error};
+use std::sync::{Arc, Mutex};
+use tracing::info;
 use crate::utils::Timer;
-use crate::common::ANNResult;
+use crate::common::{ANNError, ANNResult};
+
+use super::{ProgressEvent, ProgressNotifier, RollingThroughput, BUILD_SPAN_TARGET};
 
 pub struct IndexLogger {
     items_processed: AtomicUsize,
     timer: Timer,
     range: usize,
+    stage: &'static str,
+    log_every: usize,
+    progress: Option<Arc<ProgressNotifier>>,
+    throughput: Mutex<RollingThroughput>,
 }
 
 impl IndexLogger {
     pub fn new(range: usize) -> Self {
+        Self::for_stage("Index Construction", range, 100_000)
+    }
+
+    /// Logger of a stage of range items, logging every log_every items
+    pub fn for_stage(stage: &'static str, range: usize, log_every: usize) -> Self {
         Self {
             items_processed: AtomicUsize::new(0),
             timer: Timer::new(),
             range,
+            stage,
+            log_every: log_every.max(1),
+            progress: None,
+            throughput: Mutex::new(RollingThroughput::default()),
         }
     }
 
+    /// Publish the progress of the stage to notifier, if given, each time another percent of
+    /// its items is processed
+    pub fn with_progress(mut self, notifier: Option<Arc<ProgressNotifier>>) -> Self {
+        self.progress = notifier;
+        self
+    }
+
     pub fn vertex_processed(&self) -> ANNResult<()> {
+        self.item_processed()
+    }
+
+    /// Count an item of the stage, logging the progress before it every log_every items
+    pub fn item_processed(&self) -> ANNResult<()> {
         let count = self.items_processed.fetch_add(1, Ordering::Relaxed);
-        if count % 100_000 == 0 {
-            let percentage_complete = (100_f32 * count as f32) / (self.range as f32);
-            let elapsed_time = self.timer.elapsed().as_secs_f32();
-            info!(
-                "Index Construction: {}% complete, Time Spent: {:.2} seconds",
-                percentage_complete, elapsed_time
-            );
+        if count.is_multiple_of(self.log_every) {
+            self.log_progress(count)?;
         }
+        self.publish_progress(count, count + 1)
+    }
 
+    /// Count count items of the stage at once, logging the progress after them when they
+    /// cross a multiple of log_every or complete the stage
+    pub fn items_processed(&self, count: usize) -> ANNResult<()> {
+        let before = self.items_processed.fetch_add(count, Ordering::Relaxed);
+        let after = before + count;
+        if after / self.log_every > before / self.log_every || after == self.range {
+            self.log_progress(after)?;
+        }
+        self.publish_progress(before, after)
+    }
+
+    /// Publish the progress when going from before to after items completes another percent
+    fn publish_progress(&self, before: usize, after: usize) -> ANNResult<()> {
+        let Some(progress) = &self.progress else {
+            return Ok(());
+        };
+        let range = self.range.max(1);
+        if after * 100 / range == before * 100 / range {
+            return Ok(());
+        }
+
+        let (elapsed_secs, throughput, eta_secs) = self.rates(after)?;
+        progress.publish(ProgressEvent {
+            stage: self.stage,
+            items_done: after,
+            total_items: self.range,
+            percent: (100_f32 * after as f32) / (range as f32),
+            elapsed_secs,
+            throughput,
+            eta_secs,
+        });
         Ok(())
     }
-}
\ No newline at end of file
+
+    /// Log the progress after count items as an info event of the build target, with the
+    /// stage, items_done, total_items, percent, elapsed_secs, throughput and eta_secs fields
+    fn log_progress(&self, count: usize) -> ANNResult<()> {
+        let percentage_complete = (100_f32 * count as f32) / (self.range as f32);
+        let (elapsed_time, throughput, eta_secs) = self.rates(count)?;
+        let rates = match (throughput, eta_secs) {
+            (Some(throughput), Some(eta_secs)) => format!(
+                ", Throughput: {:.0} items/sec, ETA: {:.2} seconds",
+                throughput, eta_secs
+            ),
+            _ => String::new(),
+        };
+        info!(
+            target: BUILD_SPAN_TARGET,
+            stage = self.stage,
+            items_done = count,
+            total_items = self.range,
+            percent = percentage_complete,
+            elapsed_secs = elapsed_time,
+            throughput,
+            eta_secs,
+            "{}: {}% complete, Time Spent: {:.2} seconds{}",
+            self.stage, percentage_complete, elapsed_time, rates
+        );
+        Ok(())
+    }
+
+    /// Time spent, items per second lately and estimated seconds left after count items
+    fn rates(&self, count: usize) -> ANNResult<(f32, Option<f32>, Option<f32>)> {
+        let elapsed_secs = self.timer.elapsed().as_secs_f32();
+        let mut throughput = self.throughput.lock().map_err(|_| {
+            ANNError::log_lock_poison_error(format!(
+                "failed to acquire the lock for the throughput of {}.",
+                self.stage
+            ))
+        })?;
+        let items_per_sec = throughput.sample(elapsed_secs, count);
+        let eta_secs = throughput.eta_secs(self.range.saturating_sub(count));
+        Ok((elapsed_secs, items_per_sec, eta_secs))
+    }
+}
+
+#[cfg(test)]
+mod index_logger_test {
+    use std::sync::{Arc, Mutex};
+
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+
+    /// Fields of the events, formatted
+    #[derive(Clone, Default)]
+    struct EventFields(Arc<Mutex<Vec<(&'static str, String)>>>);
+
+    impl tracing::field::Visit for EventFields {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            self.0.lock().unwrap().push((field.name(), format!("{:?}", value)));
+        }
+    }
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for EventFields {
+        fn on_event(&self, event: &tracing::Event<'_>, _: tracing_subscriber::layer::Context<'_, S>) {
+            if event.metadata().target() == BUILD_SPAN_TARGET {
+                event.record(&mut self.clone());
+            }
+        }
+    }
+
+    #[test]
+    fn progress_is_logged_with_its_fields() {
+        let events = EventFields::default();
+        let subscriber = tracing_subscriber::registry().with(events.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            let logger = IndexLogger::for_stage("Reading data", 10, 4);
+            logger.items_processed(4).unwrap();
+        });
+
+        let fields = events.0.lock().unwrap();
+        let value = |name: &str| {
+            fields.iter().find(|(field, _)| *field == name).map(|(_, value)| value.clone())
+        };
+        assert_eq!(value("stage").as_deref(), Some("\"Reading data\""));
+        assert_eq!(value("items_done").as_deref(), Some("4"));
+        assert_eq!(value("total_items").as_deref(), Some("10"));
+        assert_eq!(value("percent").as_deref(), Some("40.0"));
+        assert!(value("elapsed_secs").is_some());
+        assert!(value("message").unwrap().starts_with("Reading data: 40% complete"));
+    }
+}
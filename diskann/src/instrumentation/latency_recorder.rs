@@ -0,0 +1,257 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Histograms of the latency of queries, for serving dashboards.
+//!
+//! The histograms are HDR-style: the values below SUB_BUCKETS microseconds have a bucket each,
+//! and every power of two range above is split into SUB_BUCKETS equal buckets, so a percentile
+//! is off by less than 1 / SUB_BUCKETS of its value whatever its magnitude. Recording is a few
+//! atomic additions, so concurrent searches share a recorder without locking.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::QueryStats;
+
+/// Buckets per power of two range, a power of two
+const SUB_BUCKETS: u64 = 64;
+
+/// log2(SUB_BUCKETS)
+const SUB_BUCKET_BITS: u32 = SUB_BUCKETS.trailing_zeros();
+
+/// Buckets covering every u64 value
+const NUM_BUCKETS: usize = ((u64::BITS - SUB_BUCKET_BITS + 1) as u64 * SUB_BUCKETS) as usize;
+
+/// Counts of microsecond values in log-linear buckets
+#[derive(Debug)]
+struct LatencyHistogram {
+    counts: Vec<AtomicU64>,
+    count: AtomicU64,
+    sum: AtomicU64,
+    max: AtomicU64,
+}
+
+impl LatencyHistogram {
+    fn new() -> Self {
+        Self {
+            counts: (0..NUM_BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+            max: AtomicU64::new(0),
+        }
+    }
+
+    fn bucket(value: u64) -> usize {
+        if value < SUB_BUCKETS {
+            return value as usize;
+        }
+
+        let shift = u64::BITS - 1 - value.leading_zeros() - SUB_BUCKET_BITS;
+        let sub_bucket = (value >> shift) - SUB_BUCKETS;
+        ((shift as u64 + 1) * SUB_BUCKETS + sub_bucket) as usize
+    }
+
+    /// Largest value of a bucket
+    fn bucket_max(bucket: usize) -> u64 {
+        let bucket = bucket as u64;
+        if bucket < SUB_BUCKETS {
+            return bucket;
+        }
+
+        let shift = bucket / SUB_BUCKETS - 1;
+        let lowest = (SUB_BUCKETS + bucket % SUB_BUCKETS) << shift;
+        lowest + ((1 << shift) - 1)
+    }
+
+    fn record(&self, value_us: f64) {
+        let value = value_us.max(0.0).round() as u64;
+        self.counts[Self::bucket(value)].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value, Ordering::Relaxed);
+        self.max.fetch_max(value, Ordering::Relaxed);
+    }
+
+    fn reset(&self) {
+        for count in self.counts.iter() {
+            count.store(0, Ordering::Relaxed);
+        }
+        self.count.store(0, Ordering::Relaxed);
+        self.sum.store(0, Ordering::Relaxed);
+        self.max.store(0, Ordering::Relaxed);
+    }
+
+    fn percentiles(&self) -> LatencyPercentiles {
+        let counts: Vec<u64> = self
+            .counts
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed))
+            .collect();
+        let count: u64 = counts.iter().sum();
+        let max = self.max.load(Ordering::Relaxed);
+        let percentile = |quantile: f64| {
+            let rank = ((quantile * count as f64).ceil() as u64).max(1);
+            let mut seen = 0;
+            for (bucket, bucket_count) in counts.iter().enumerate() {
+                seen += bucket_count;
+                if seen >= rank {
+                    return Self::bucket_max(bucket).min(max) as f64;
+                }
+            }
+            0.0
+        };
+
+        LatencyPercentiles {
+            mean_us: self.sum.load(Ordering::Relaxed) as f64 / count.max(1) as f64,
+            p50_us: percentile(0.50),
+            p95_us: percentile(0.95),
+            p99_us: percentile(0.99),
+            max_us: max as f64,
+        }
+    }
+}
+
+/// Percentiles of a latency in micros, to within 1.6% of the recorded values
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct LatencyPercentiles {
+    /// Mean latency
+    pub mean_us: f64,
+
+    /// Median latency
+    pub p50_us: f64,
+
+    /// 95th percentile latency
+    pub p95_us: f64,
+
+    /// 99th percentile latency
+    pub p99_us: f64,
+
+    /// Largest latency
+    pub max_us: f64,
+}
+
+/// Latency percentiles of the queries recorded so far
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct LatencySnapshot {
+    /// Number of queries recorded
+    pub num_queries: u64,
+
+    /// End to end time of a query
+    pub total: LatencyPercentiles,
+
+    /// Time a query waited for IO
+    pub io: LatencyPercentiles,
+
+    /// Time a query spent computing
+    pub cpu: LatencyPercentiles,
+}
+
+/// Histograms of the end to end, IO and compute time of the queries it's given
+#[derive(Debug)]
+pub struct LatencyRecorder {
+    total: LatencyHistogram,
+    io: LatencyHistogram,
+    cpu: LatencyHistogram,
+}
+
+impl Default for LatencyRecorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LatencyRecorder {
+    /// Create a recorder without any query
+    pub fn new() -> Self {
+        Self {
+            total: LatencyHistogram::new(),
+            io: LatencyHistogram::new(),
+            cpu: LatencyHistogram::new(),
+        }
+    }
+
+    /// Record the times of a query
+    pub fn record(&self, stats: &QueryStats) {
+        self.total.record(stats.total_us);
+        self.io.record(stats.io_us);
+        self.cpu.record(stats.cpu_us);
+    }
+
+    /// Percentiles of the queries recorded since the recorder was created or reset.
+    /// Queries recorded while the snapshot is taken may count in some histograms only.
+    pub fn snapshot(&self) -> LatencySnapshot {
+        LatencySnapshot {
+            num_queries: self.total.count.load(Ordering::Relaxed),
+            total: self.total.percentiles(),
+            io: self.io.percentiles(),
+            cpu: self.cpu.percentiles(),
+        }
+    }
+
+    /// Forget the queries recorded so far, e.g. at the start of a dashboard interval
+    pub fn reset(&self) {
+        self.total.reset();
+        self.io.reset();
+        self.cpu.reset();
+    }
+}
+
+#[cfg(test)]
+mod latency_recorder_test {
+    use super::*;
+
+    #[test]
+    fn buckets_bound_the_relative_error() {
+        for value in [0, 1, 63, 64, 65, 127, 128, 1000, 123_456_789, u64::MAX] {
+            let bucket = LatencyHistogram::bucket(value);
+            assert!(bucket < NUM_BUCKETS);
+            let bucket_max = LatencyHistogram::bucket_max(bucket);
+            assert!(
+                bucket_max >= value,
+                "{} in bucket up to {}",
+                value,
+                bucket_max
+            );
+            assert!(
+                (bucket_max - value) as f64 <= value as f64 / SUB_BUCKETS as f64,
+                "{} in bucket up to {}",
+                value,
+                bucket_max
+            );
+            if bucket > 0 {
+                assert!(LatencyHistogram::bucket_max(bucket - 1) < value);
+            }
+        }
+    }
+
+    #[test]
+    fn snapshot_reports_percentiles_of_each_time() {
+        let recorder = LatencyRecorder::new();
+        for total_us in 1..=1000 {
+            recorder.record(&QueryStats {
+                total_us: total_us as f64,
+                io_us: 10.0,
+                cpu_us: total_us as f64 - 10.0,
+                ..Default::default()
+            });
+        }
+
+        let snapshot = recorder.snapshot();
+        assert_eq!(snapshot.num_queries, 1000);
+        for (percentile, expected) in [
+            (snapshot.total.p50_us, 500.0),
+            (snapshot.total.p95_us, 950.0),
+            (snapshot.total.p99_us, 990.0),
+        ] {
+            assert!(percentile >= expected && percentile <= expected * 1.02);
+        }
+        assert_eq!(snapshot.total.max_us, 1000.0);
+        assert_eq!(snapshot.total.mean_us, 500.5);
+        assert_eq!(snapshot.io.p99_us, 10.0);
+        assert!(snapshot.cpu.p50_us < snapshot.total.p50_us);
+
+        recorder.reset();
+        assert_eq!(recorder.snapshot(), LatencySnapshot::default());
+    }
+}
@@ -5,5 +5,28 @@
 mod index_logger;
 pub use index_logger::IndexLogger;
 
+mod build_progress;
+pub use build_progress::*;
+
+mod rolling_throughput;
+pub use rolling_throughput::*;
+
+#[cfg(feature = "disk-index")]
 mod disk_index_build_logger;
+#[cfg(feature = "disk-index")]
 pub use disk_index_build_logger::DiskIndexBuildLogger;
+
+mod build_report;
+pub use build_report::{BuildReport, BuildReportParameters, OutputFileReport, PhaseReport};
+
+mod query_stats;
+pub use query_stats::QueryStats;
+
+mod latency_recorder;
+pub use latency_recorder::*;
+
+mod query_log;
+pub use query_log::*;
+
+mod span_ids;
+pub use span_ids::*;
@@ -0,0 +1,257 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Capture of live queries and comparison of their replay, to check a change of parameters or
+//! of index against the traffic it will serve.
+//!
+//! A query log has one JSON object per line, a RecordedQuery, so a log cut short by a crash
+//! only loses its last line. The vectors are stored as f32, which holds every element type
+//! exactly.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::common::{ANNError, ANNResult};
+use crate::model::SearchParams;
+
+use super::{LatencyPercentiles, LatencyRecorder, QueryStats};
+
+/// A query and what the index answered
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedQuery {
+    /// Query vector
+    pub query: Vec<f32>,
+
+    /// Number of results asked for
+    pub k: usize,
+
+    /// Parameters of the search
+    pub params: SearchParams,
+
+    /// Ids of the results, nearest first
+    pub ids: Vec<u32>,
+
+    /// Distances of the results to the query
+    pub distances: Vec<f32>,
+
+    /// Statistics of the search
+    pub stats: QueryStats,
+}
+
+/// Appends a sample of the queries it's given to a query log
+#[derive(Debug)]
+pub struct QueryRecorder {
+    writer: Mutex<BufWriter<File>>,
+    sample_rate: f64,
+    num_seen: AtomicU64,
+}
+
+impl QueryRecorder {
+    /// Write the queries to a new log at filename, keeping sample_rate of them, evenly spread
+    pub fn create(filename: &str, sample_rate: f64) -> ANNResult<Self> {
+        if !(sample_rate > 0.0 && sample_rate <= 1.0) {
+            return Err(ANNError::log_index_config_error(
+                "sample_rate".to_string(),
+                format!("Sample rate {} should be in (0, 1]", sample_rate),
+            ));
+        }
+
+        Ok(Self {
+            writer: Mutex::new(BufWriter::new(File::create(filename)?)),
+            sample_rate,
+            num_seen: AtomicU64::new(0),
+        })
+    }
+
+    /// Whether the next query is in the sample
+    fn sample_next(&self) -> bool {
+        let seen = self.num_seen.fetch_add(1, Ordering::Relaxed);
+        // The query is kept when it brings the number of queries to keep to the next integer
+        (seen as f64 * self.sample_rate).floor() < ((seen + 1) as f64 * self.sample_rate).floor()
+    }
+
+    /// Append the query to the log if it's in the sample
+    pub fn record<T: Copy + Into<f32>>(
+        &self,
+        query: &[T],
+        k: usize,
+        params: &SearchParams,
+        ids: &[u32],
+        distances: &[f32],
+        stats: &QueryStats,
+    ) -> ANNResult<()> {
+        if !self.sample_next() {
+            return Ok(());
+        }
+
+        let recorded = RecordedQuery {
+            query: query.iter().map(|&value| value.into()).collect(),
+            k,
+            params: *params,
+            ids: ids.to_vec(),
+            distances: distances.to_vec(),
+            stats: *stats,
+        };
+        let line = serde_json::to_string(&recorded).map_err(|err| {
+            ANNError::log_index_error(format!("ERROR: Failed to serialize a query: {}", err))
+        })?;
+
+        let mut writer = self.writer.lock().map_err(|err| {
+            ANNError::log_lock_poison_error(format!(
+                "PoisonError: Lock poisoned when recording a query, err={}",
+                err
+            ))
+        })?;
+        writeln!(writer, "{}", line)?;
+        Ok(())
+    }
+
+    /// Write the buffered queries to the log
+    pub fn flush(&self) -> ANNResult<()> {
+        self.writer
+            .lock()
+            .map_err(|err| {
+                ANNError::log_lock_poison_error(format!(
+                    "PoisonError: Lock poisoned when flushing the query log, err={}",
+                    err
+                ))
+            })?
+            .flush()?;
+        Ok(())
+    }
+}
+
+/// Read the queries of a query log
+pub fn load_query_log(filename: &str) -> ANNResult<Vec<RecordedQuery>> {
+    let reader = BufReader::new(File::open(filename)?);
+    let mut queries = Vec::new();
+    for (line_number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let query = serde_json::from_str(&line).map_err(|err| {
+            ANNError::log_index_error(format!(
+                "ERROR: Line {} of query log {} isn't a query: {}",
+                line_number + 1,
+                filename,
+                err
+            ))
+        })?;
+        queries.push(query);
+    }
+    Ok(queries)
+}
+
+/// Recall and latency of a replay of recorded queries, against the recorded ones
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReplayComparison {
+    /// Number of queries replayed
+    pub num_queries: usize,
+
+    /// Fraction of the recorded results the replay found, in percent
+    pub recall: f64,
+
+    /// Latency of the recorded searches
+    pub recorded_latency: LatencyPercentiles,
+
+    /// Latency of the replayed searches
+    pub replayed_latency: LatencyPercentiles,
+}
+
+/// Compare the ids and stats of the replay of each query with its recording
+pub fn compare_replay(
+    recorded: &[RecordedQuery],
+    replayed: &[(Vec<u32>, QueryStats)],
+) -> ANNResult<ReplayComparison> {
+    if recorded.len() != replayed.len() {
+        return Err(ANNError::log_index_error(format!(
+            "ERROR: {} queries were replayed out of {} recorded.",
+            replayed.len(),
+            recorded.len()
+        )));
+    }
+
+    let recorded_latency = LatencyRecorder::new();
+    let replayed_latency = LatencyRecorder::new();
+    let mut num_recorded_ids = 0;
+    let mut num_found = 0;
+    for (recorded, (ids, stats)) in recorded.iter().zip(replayed) {
+        num_recorded_ids += recorded.ids.len();
+        num_found += recorded.ids.iter().filter(|id| ids.contains(id)).count();
+        recorded_latency.record(&recorded.stats);
+        replayed_latency.record(stats);
+    }
+
+    Ok(ReplayComparison {
+        num_queries: recorded.len(),
+        recall: 100.0 * num_found as f64 / num_recorded_ids.max(1) as f64,
+        recorded_latency: recorded_latency.snapshot().total,
+        replayed_latency: replayed_latency.snapshot().total,
+    })
+}
+
+#[cfg(test)]
+mod query_log_test {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn sampled_queries_are_replayed_against_their_recording() {
+        let file = "sampled_queries_are_replayed_against_their_recording.jsonl";
+        let params = SearchParams::new(20, 4, None, true).unwrap();
+        let recorder = QueryRecorder::create(file, 0.5).unwrap();
+        for i in 0..10u8 {
+            let stats = QueryStats {
+                total_us: 100.0 * (i + 1) as f64,
+                ..Default::default()
+            };
+            recorder
+                .record(
+                    &[i, i + 1],
+                    2,
+                    &params,
+                    &[i as u32, 10],
+                    &[0.0, 1.0],
+                    &stats,
+                )
+                .unwrap();
+        }
+        recorder.flush().unwrap();
+        assert!(QueryRecorder::create(file, 0.0).is_err());
+
+        let recorded = load_query_log(file);
+        fs::remove_file(file).unwrap();
+        let recorded = recorded.unwrap();
+        // Every other query, starting with the second
+        assert_eq!(recorded.len(), 5);
+        assert_eq!(recorded[0].query, vec![1.0, 2.0]);
+        assert_eq!(recorded[0].params, params);
+        assert_eq!(recorded[4].ids, vec![9, 10]);
+
+        let replayed: Vec<(Vec<u32>, QueryStats)> = recorded
+            .iter()
+            .map(|query| {
+                let stats = QueryStats {
+                    total_us: query.stats.total_us / 2.0,
+                    ..Default::default()
+                };
+                (vec![query.ids[0], 11], stats)
+            })
+            .collect();
+        let comparison = compare_replay(&recorded, &replayed).unwrap();
+        assert_eq!(comparison.num_queries, 5);
+        assert_eq!(comparison.recall, 50.0);
+        assert_eq!(comparison.recorded_latency.max_us, 1000.0);
+        assert_eq!(comparison.replayed_latency.max_us, 500.0);
+        assert!(compare_replay(&recorded, &replayed[1..]).is_err());
+    }
+}
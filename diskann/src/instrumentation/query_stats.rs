@@ -0,0 +1,38 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Per-query search statistics
+
+use serde::{Deserialize, Serialize};
+
+/// Statistics collected while answering a single query
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct QueryStats {
+    /// Total time to process the query in micros
+    pub total_us: f64,
+
+    /// Time spent waiting for IO in micros
+    pub io_us: f64,
+
+    /// Time spent on CPU (distance computation, candidate bookkeeping) in micros
+    pub cpu_us: f64,
+
+    /// Number of sectors read
+    pub n_ios: u32,
+
+    /// Number of distance comparisons
+    pub n_cmps: u32,
+
+    /// Number of hops (nodes expanded) during the search
+    pub n_hops: u32,
+
+    /// Nodes a disk search read per round trip to the disk in its last round
+    pub prefetch_window: u32,
+
+    /// Whether the IO limit or the patience of the search stopped it with candidates left
+    /// to expand, so its results may be less accurate
+    pub early_terminated: bool,
+}
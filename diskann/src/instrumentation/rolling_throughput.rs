@@ -0,0 +1,84 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Throughput of a stage over its last few progress samples, so the estimated time remaining
+//! follows the stage speeding up or slowing down instead of averaging from its start.
+
+use std::collections::VecDeque;
+
+/// Samples the throughput is measured over by default
+pub const DEFAULT_THROUGHPUT_WINDOW: usize = 8;
+
+/// Items processed per second over the last samples of a stage
+#[derive(Debug, Clone)]
+pub struct RollingThroughput {
+    /// Seconds since the stage started and items processed by then, oldest first
+    samples: VecDeque<(f32, usize)>,
+    window: usize,
+}
+
+impl RollingThroughput {
+    /// Measure over the last window samples, the start of the stage being the first one
+    pub fn new(window: usize) -> Self {
+        Self {
+            samples: VecDeque::from([(0.0, 0)]),
+            window: window.max(1),
+        }
+    }
+
+    /// Record that items were processed elapsed_secs after the start of the stage and return
+    /// the items per second over the window, None until some time passed. Samples from threads
+    /// reporting late, with fewer items than the last one, are ignored.
+    pub fn sample(&mut self, elapsed_secs: f32, items: usize) -> Option<f32> {
+        if self.samples.back().is_some_and(|&(_, last)| items > last) {
+            self.samples.push_back((elapsed_secs, items));
+            if self.samples.len() > self.window + 1 {
+                self.samples.pop_front();
+            }
+        }
+        self.throughput()
+    }
+
+    /// Items per second over the window
+    pub fn throughput(&self) -> Option<f32> {
+        let (&(first_secs, first_items), &(last_secs, last_items)) =
+            (self.samples.front()?, self.samples.back()?);
+        let secs = last_secs - first_secs;
+        (secs > 0.0).then(|| (last_items - first_items) as f32 / secs)
+    }
+
+    /// Seconds to process remaining_items more items at the current throughput
+    pub fn eta_secs(&self, remaining_items: usize) -> Option<f32> {
+        self.throughput()
+            .filter(|&throughput| throughput > 0.0)
+            .map(|throughput| remaining_items as f32 / throughput)
+    }
+}
+
+impl Default for RollingThroughput {
+    fn default() -> Self {
+        Self::new(DEFAULT_THROUGHPUT_WINDOW)
+    }
+}
+
+#[cfg(test)]
+mod rolling_throughput_test {
+    use super::*;
+
+    #[test]
+    fn throughput_follows_the_last_samples() {
+        let mut throughput = RollingThroughput::new(2);
+        assert_eq!(throughput.sample(0.0, 0), None);
+        assert_eq!(throughput.sample(1.0, 100), Some(100.0));
+        assert_eq!(throughput.sample(2.0, 200), Some(100.0));
+        // The start of the stage falls out of the window
+        assert_eq!(throughput.sample(4.0, 250), Some(50.0));
+        assert_eq!(throughput.sample(6.0, 300), Some(25.0));
+        // A late report doesn't count
+        assert_eq!(throughput.sample(5.0, 290), Some(25.0));
+        assert_eq!(throughput.eta_secs(100), Some(4.0));
+    }
+}
@@ -0,0 +1,29 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Targets and ids of the tracing spans of disk searches and builds.
+//! A search opens info spans pq_table_compute, beam_search, io_wait and rerank, with the
+//! query_id field and the sectors_read recorded during the phase; a build opens
+//! pq_construction, graph_build, disk_layout and query_warmup, with the build_id and
+//! num_points fields. Subscribers time the spans, e.g. tracing-subscriber's fmt layer with
+//! FmtSpan::CLOSE. The progress of the build stages and the build checkpoints are info events
+//! of the build target with their counts and timings as fields, e.g. items_done or checkpoint
+//! and elapsed_secs. Without a subscriber the spans and events are logged through the log crate.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Target of the search spans
+pub const SEARCH_SPAN_TARGET: &str = "diskann::search";
+
+/// Target of the build spans
+pub const BUILD_SPAN_TARGET: &str = "diskann::build";
+
+static NEXT_SPAN_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Id telling apart the spans of concurrent queries and builds, unique within the process
+pub fn next_span_id() -> u64 {
+    NEXT_SPAN_ID.fetch_add(1, Ordering::Relaxed)
+}
@@ -52,3 +52,80 @@ impl<T> AlignedRead<T> {
         &self.aligned_buf
     }
 }
+
+/// The aligned range of a file covering an arbitrary range, for reading ranges that aren't
+/// aligned, e.g. metadata regions, with readers that only read aligned ranges
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AlignedRange {
+    /// Start of the aligned range, the requested offset rounded down
+    pub offset: u64,
+
+    /// Length of the aligned range in bytes, up to the requested end rounded up
+    pub len: usize,
+
+    /// Start of the requested bytes in the aligned range
+    pub start: usize,
+
+    /// Number of requested bytes
+    pub requested_len: usize,
+}
+
+impl AlignedRange {
+    /// The aligned range covering len bytes at offset
+    pub fn covering(offset: u64, len: usize) -> Self {
+        let aligned_offset = offset - offset % DISK_IO_ALIGNMENT as u64;
+        let start = (offset - aligned_offset) as usize;
+        let aligned_end = (start + len).div_ceil(DISK_IO_ALIGNMENT) * DISK_IO_ALIGNMENT;
+        Self {
+            offset: aligned_offset,
+            len: aligned_end,
+            start,
+            requested_len: len,
+        }
+    }
+
+    /// Whether the requested range is aligned itself, so it can be read without copying
+    pub fn is_exact(&self) -> bool {
+        self.start == 0 && self.len == self.requested_len
+    }
+
+    /// The requested bytes out of the bytes read from the aligned range, which may stop short
+    /// of its end at the end of the file
+    pub fn requested<'a>(&self, buf: &'a [u8]) -> ANNResult<&'a [u8]> {
+        buf.get(self.start..self.start + self.requested_len)
+            .ok_or_else(|| {
+                ANNError::log_io_error(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    format!(
+                        "Read of {} bytes at offset {} ends {} bytes past the end of the file",
+                        self.requested_len,
+                        self.offset + self.start as u64,
+                        self.start + self.requested_len - buf.len()
+                    ),
+                ))
+            })
+    }
+}
+
+#[cfg(test)]
+mod aligned_file_reader_test {
+    use super::*;
+
+    #[test]
+    fn aligned_range_covers_the_requested_bytes() {
+        let range = AlignedRange::covering(1000, 100);
+        assert_eq!(range.offset, 512);
+        assert_eq!(range.len, 1024);
+        assert_eq!(range.start, 488);
+        assert!(!range.is_exact());
+
+        let buf: Vec<u8> = (0..1024).map(|i| (i % 251) as u8).collect();
+        assert_eq!(range.requested(&buf).unwrap(), &buf[488..588]);
+        // The file ends before the requested bytes do
+        assert!(range.requested(&buf[..500]).is_err());
+
+        let range = AlignedRange::covering(4096, 4096);
+        assert_eq!((range.offset, range.len, range.start), (4096, 4096, 0));
+        assert!(range.is_exact());
+    }
+}
@@ -6,10 +6,90 @@
 
 //! Index configuration.
 
+use std::sync::Arc;
+use std::time::Duration;
+
+use rayon::ThreadPool;
+#[cfg(feature = "disk-index")]
+use tokio::runtime::Handle;
 use vector::Metric;
 
+use crate::common::ANNResult;
+#[cfg(feature = "gpu")]
+use crate::index::GpuDistance;
+use crate::model::{PQCodeBits, PQRotation};
+use crate::utils::{build_thread_pool, round_up};
+
 use super::index_write_parameters::IndexWriteParameters;
 
+/// Compressed copy of the vectors the prune step of a build compares candidates with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PruneQuantization {
+    /// Compare the full precision vectors
+    #[default]
+    None,
+
+    /// Compare f16 copies of the vectors
+    Half,
+
+    /// Compare one byte per dimension codes, each dimension scaled from its min/max range
+    SQ8,
+
+    /// Compare product quantization codes of num_chunks chunks, each of up to 256 centroids
+    /// trained on the data, by looking up centroid to centroid distances. L2 only.
+    PQ {
+        /// Number of chunks the dimensions are split in, one code byte per chunk
+        num_chunks: usize,
+    },
+}
+
+/// When the write-ahead log of an index forces its records to stable storage
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WalSyncPolicy {
+    /// Sync every record before the change it logs returns, so an acknowledged change
+    /// survives an OS crash or a power loss
+    #[default]
+    EveryRecord,
+
+    /// Sync once max_records records are unsynced, or on the first record logged max_delay
+    /// or more after the oldest unsynced one. An OS crash or a power loss can lose the
+    /// changes of the last batch, a process crash loses none.
+    Batch {
+        /// Most records left unsynced
+        max_records: usize,
+
+        /// Longest a record is left unsynced while records keep coming
+        max_delay: Duration,
+    },
+}
+
+/// How a build picks the points searches start from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EntryPointSelection {
+    /// The point closest to the mean of the points
+    #[default]
+    Centroid,
+
+    /// The point with the smallest sum of distances to all the others, in a number of
+    /// distance computations quadratic in the number of points
+    Medoid,
+
+    /// The medoid of sample_size points drawn at random, close to the true medoid for a
+    /// fraction of its cost
+    SampledMedoid {
+        /// Number of points drawn
+        sample_size: usize,
+    },
+
+    /// The points closest to the centers of num_entry_points k-means clusters, the one of the
+    /// largest cluster first. Searches start from all of them, so every cluster of clustered
+    /// data is reached without crossing the few edges between clusters.
+    Clustered {
+        /// Number of clusters, and of entry points
+        num_entry_points: usize,
+    },
+}
+
 /// The index configuration
 #[derive(Debug, Clone)]
 pub struct IndexConfiguration {
@@ -40,13 +120,84 @@ pub struct IndexConfiguration {
     /// Number of PQ chunks
     pub num_pq_chunks: usize,
 
-    /// Use optimized product quantization
-    /// Currently not supported
+    /// Use optimized product quantization: the disk index build learns a rotation of the
+    /// vectors that balances their variance across the PQ chunks before training the pivots
     pub use_opq: bool,
 
+    /// Fixed transform of the vectors before PQ chunking when OPQ is off, saved with the
+    /// pivots. Defaults to None.
+    pub pq_rotation: PQRotation,
+
+    /// Width of the PQ code of a chunk of the disk index. 4-bit codes take half the memory
+    /// of 8-bit ones for the same number of chunks, with 16 instead of 256 centroids per
+    /// chunk. Defaults to Eight.
+    pub pq_code_bits: PQCodeBits,
+
+    /// Number of vectors the disk index build trains the PQ pivots on, drawn by reservoir
+    /// sampling. 0 samples each vector with a probability that averages 256000 of them.
+    pub pq_training_set_size: usize,
+
     /// potential for growth. 1.2 means the index can grow by up to 20%.
     pub growth_potential: f32,
 
+    /// Relative tolerance under which candidate distances are treated as equal and ordered by id.
+    /// A small positive value makes build and search results identical across platforms whose
+    /// SIMD reductions differ in the last bits. Defaults to 0 (exact comparison).
+    pub distance_tie_epsilon: f32,
+
+    /// Number of independent frontiers the query searches of the in-memory and the disk index
+    /// start from, merged every few rounds of expansions. More frontiers visit more of the graph,
+    /// trading latency for recall on graphs where a single beam gets stuck. On disk every
+    /// frontier adds a beam to the reads of each round. Defaults to 1 (plain beam search).
+    pub num_search_frontiers: usize,
+
+    /// Also save the graph as a CSR file next to the index, and load the graph by mapping that
+    /// file when it exists, so a large index is searchable right after a restart.
+    /// Defaults to false.
+    pub csr_graph: bool,
+
+    /// Indices holding fewer points than this skip the graph build and answer searches by
+    /// comparing the query to every point, the graph is built once they grow past it.
+    /// Defaults to 0 (always use the graph).
+    pub brute_force_threshold: usize,
+
+    /// Compressed vectors the occlusion test of the build prune compares candidates with,
+    /// trading a few graph edges for less memory traffic on large builds.
+    /// Defaults to None (full precision).
+    pub prune_quantization: PruneQuantization,
+
+    /// Fraction of each prune pool, closest candidates first, still compared at full
+    /// precision when prune_quantization is set, as those mostly become the kept neighbors.
+    /// Between 0 and 1, defaults to 0.
+    pub prune_refine_fraction: f32,
+
+    /// Points searches start from when the index has no frozen points, saved with the index.
+    /// Defaults to Centroid.
+    pub entry_point_selection: EntryPointSelection,
+
+    /// Seed of the random choices of a build: the PQ training sample and pivots, the shard
+    /// assignment, the warm-up queries and the sampled or clustered entry points. The same
+    /// data, parameters and seed give the same index when the graph is built by one thread.
+    /// Defaults to None (seeded from the OS on every build).
+    pub random_seed: Option<u64>,
+
+    /// Pool the parallel phases of builds run on, in place of a pool of
+    /// index_write_parameter.num_threads threads. Defaults to None.
+    pub thread_pool: Option<Arc<ThreadPool>>,
+
+    /// When the write-ahead log syncs its records. Defaults to EveryRecord.
+    pub wal_sync_policy: WalSyncPolicy,
+
+    #[cfg(feature = "disk-index")]
+    /// Runtime the disk reads of searches are spawned on, in place of the runtime of the
+    /// caller. Defaults to None.
+    pub runtime: Option<Handle>,
+
+    #[cfg(feature = "gpu")]
+    /// GPU the prune of builds computes the distances between candidates on, for indexes on
+    /// the built-in distance. Defaults to None (on the CPU).
+    pub gpu_distance: Option<Arc<GpuDistance>>,
+
     // TODO: below settings are not supported in current iteration
     // pub concurrent_consolidate: bool,
     // pub has_built: bool,
@@ -81,12 +232,373 @@ impl IndexConfiguration {
             use_pq_dist,
             num_pq_chunks,
             use_opq,
+            pq_rotation: PQRotation::None,
+            pq_code_bits: PQCodeBits::Eight,
+            pq_training_set_size: 0,
             growth_potential,
+            distance_tie_epsilon: 0.0,
+            num_search_frontiers: 1,
+            csr_graph: false,
+            brute_force_threshold: 0,
+            prune_quantization: PruneQuantization::None,
+            prune_refine_fraction: 0.0,
+            entry_point_selection: EntryPointSelection::Centroid,
+            random_seed: None,
+            thread_pool: None,
+            wal_sync_policy: WalSyncPolicy::EveryRecord,
+            #[cfg(feature = "disk-index")]
+            runtime: None,
+            #[cfg(feature = "gpu")]
+            gpu_distance: None,
         }
     }
 
+    /// Set the relative tolerance used to break distance ties by id
+    pub fn with_distance_tie_epsilon(mut self, distance_tie_epsilon: f32) -> Self {
+        self.distance_tie_epsilon = distance_tie_epsilon;
+        self
+    }
+
+    /// Set the number of frontiers query searches start from
+    pub fn with_num_search_frontiers(mut self, num_search_frontiers: usize) -> Self {
+        self.num_search_frontiers = num_search_frontiers;
+        self
+    }
+
+    /// Set whether the graph is saved and loaded as a mapped CSR file
+    pub fn with_csr_graph(mut self, csr_graph: bool) -> Self {
+        self.csr_graph = csr_graph;
+        self
+    }
+
+    /// Set the number of points below which searches scan every point instead of the graph
+    pub fn with_brute_force_threshold(mut self, brute_force_threshold: usize) -> Self {
+        self.brute_force_threshold = brute_force_threshold;
+        self
+    }
+
+    /// Set the compressed vectors the build prune compares, and the fraction of each pool
+    /// compared at full precision
+    pub fn with_prune_quantization(
+        mut self,
+        prune_quantization: PruneQuantization,
+        prune_refine_fraction: f32,
+    ) -> Self {
+        self.prune_quantization = prune_quantization;
+        self.prune_refine_fraction = prune_refine_fraction;
+        self
+    }
+
+    /// Set the fixed transform of the vectors before PQ chunking
+    pub fn with_pq_rotation(mut self, pq_rotation: PQRotation) -> Self {
+        self.pq_rotation = pq_rotation;
+        self
+    }
+
+    /// Set the width of the PQ codes of the disk index
+    pub fn with_pq_code_bits(mut self, pq_code_bits: PQCodeBits) -> Self {
+        self.pq_code_bits = pq_code_bits;
+        self
+    }
+
+    /// Set the number of vectors the PQ pivots of the disk index are trained on
+    pub fn with_pq_training_set_size(mut self, pq_training_set_size: usize) -> Self {
+        self.pq_training_set_size = pq_training_set_size;
+        self
+    }
+
+    /// Set how the build picks the points searches start from
+    pub fn with_entry_point_selection(
+        mut self,
+        entry_point_selection: EntryPointSelection,
+    ) -> Self {
+        self.entry_point_selection = entry_point_selection;
+        self
+    }
+
+    /// Set the seed of the random choices of a build
+    pub fn with_random_seed(mut self, random_seed: u64) -> Self {
+        self.random_seed = Some(random_seed);
+        self
+    }
+
+    /// Set the pool the parallel phases of builds run on
+    pub fn with_thread_pool(mut self, thread_pool: Arc<ThreadPool>) -> Self {
+        self.thread_pool = Some(thread_pool);
+        self
+    }
+
+    /// Set when the write-ahead log syncs its records
+    pub fn with_wal_sync_policy(mut self, wal_sync_policy: WalSyncPolicy) -> Self {
+        self.wal_sync_policy = wal_sync_policy;
+        self
+    }
+
+    #[cfg(feature = "disk-index")]
+    /// Set the runtime the disk reads of searches are spawned on
+    pub fn with_runtime(mut self, runtime: Handle) -> Self {
+        self.runtime = Some(runtime);
+        self
+    }
+
+    #[cfg(feature = "gpu")]
+    /// Set the GPU the prune of builds computes the distances between candidates on
+    pub fn with_gpu_distance(mut self, gpu_distance: Arc<GpuDistance>) -> Self {
+        self.gpu_distance = Some(gpu_distance);
+        self
+    }
+
     /// Get the size of adjacency list that we build out.
     pub fn write_range(&self) -> usize {
         self.index_write_parameter.max_degree as usize
     }
+
+    /// Start a pool of index_write_parameter.num_threads threads for the parallel phases when
+    /// no pool is set and more than one thread is asked for. The configuration owns the pool,
+    /// so it lives as long as the index it configures.
+    pub fn start_thread_pool(&mut self) -> ANNResult<()> {
+        let num_threads = self.index_write_parameter.num_threads;
+        if self.thread_pool.is_none() && num_threads > 1 {
+            self.thread_pool = Some(Arc::new(build_thread_pool(num_threads)?));
+        }
+        Ok(())
+    }
+}
+
+/// The builder for IndexConfiguration. The aligned dimension is the dimension rounded up to a
+/// multiple of 8, and the index has no PQ and no frozen points unless set otherwise.
+#[derive(Debug)]
+pub struct IndexConfigurationBuilder {
+    dist_metric: Metric,
+    dim: usize,
+    max_points: usize,
+    index_write_parameter: Option<IndexWriteParameters>,
+    num_frozen_pts: Option<usize>,
+    growth_potential: Option<f32>,
+    distance_tie_epsilon: Option<f32>,
+    num_search_frontiers: Option<usize>,
+    csr_graph: Option<bool>,
+    brute_force_threshold: Option<usize>,
+    prune_quantization: Option<(PruneQuantization, f32)>,
+    entry_point_selection: Option<EntryPointSelection>,
+    random_seed: Option<u64>,
+    thread_pool: Option<Arc<ThreadPool>>,
+    wal_sync_policy: Option<WalSyncPolicy>,
+    #[cfg(feature = "disk-index")]
+    runtime: Option<Handle>,
+    #[cfg(feature = "gpu")]
+    gpu_distance: Option<Arc<GpuDistance>>,
+}
+
+impl IndexConfigurationBuilder {
+    /// Initialize IndexConfigurationBuilder
+    pub fn new(dist_metric: Metric, dim: usize, max_points: usize) -> Self {
+        Self {
+            dist_metric,
+            dim,
+            max_points,
+            index_write_parameter: None,
+            num_frozen_pts: None,
+            growth_potential: None,
+            distance_tie_epsilon: None,
+            num_search_frontiers: None,
+            csr_graph: None,
+            brute_force_threshold: None,
+            prune_quantization: None,
+            entry_point_selection: None,
+            random_seed: None,
+            thread_pool: None,
+            wal_sync_policy: None,
+            #[cfg(feature = "disk-index")]
+            runtime: None,
+            #[cfg(feature = "gpu")]
+            gpu_distance: None,
+        }
+    }
+
+    /// Set index write parameters.
+    pub fn with_index_write_parameters(mut self, index_write_parameter: IndexWriteParameters) -> Self {
+        self.index_write_parameter = Some(index_write_parameter);
+        self
+    }
+
+    /// Set number of frozen points.
+    pub fn with_num_frozen_pts(mut self, num_frozen_pts: usize) -> Self {
+        self.num_frozen_pts = Some(num_frozen_pts);
+        self
+    }
+
+    /// Set growth potential.
+    pub fn with_growth_potential(mut self, growth_potential: f32) -> Self {
+        self.growth_potential = Some(growth_potential);
+        self
+    }
+
+    /// Set distance tie epsilon.
+    pub fn with_distance_tie_epsilon(mut self, distance_tie_epsilon: f32) -> Self {
+        self.distance_tie_epsilon = Some(distance_tie_epsilon);
+        self
+    }
+
+    /// Set number of search frontiers.
+    pub fn with_num_search_frontiers(mut self, num_search_frontiers: usize) -> Self {
+        self.num_search_frontiers = Some(num_search_frontiers);
+        self
+    }
+
+    /// Set CSR graph.
+    pub fn with_csr_graph(mut self, csr_graph: bool) -> Self {
+        self.csr_graph = Some(csr_graph);
+        self
+    }
+
+    /// Set brute force threshold.
+    pub fn with_brute_force_threshold(mut self, brute_force_threshold: usize) -> Self {
+        self.brute_force_threshold = Some(brute_force_threshold);
+        self
+    }
+
+    /// Set prune quantization and refine fraction.
+    pub fn with_prune_quantization(
+        mut self,
+        prune_quantization: PruneQuantization,
+        prune_refine_fraction: f32,
+    ) -> Self {
+        self.prune_quantization = Some((prune_quantization, prune_refine_fraction));
+        self
+    }
+
+    /// Set entry point selection.
+    pub fn with_entry_point_selection(
+        mut self,
+        entry_point_selection: EntryPointSelection,
+    ) -> Self {
+        self.entry_point_selection = Some(entry_point_selection);
+        self
+    }
+
+    /// Set random seed.
+    pub fn with_random_seed(mut self, random_seed: u64) -> Self {
+        self.random_seed = Some(random_seed);
+        self
+    }
+
+    /// Set thread pool.
+    pub fn with_thread_pool(mut self, thread_pool: Arc<ThreadPool>) -> Self {
+        self.thread_pool = Some(thread_pool);
+        self
+    }
+
+    /// Set write-ahead log sync policy.
+    pub fn with_wal_sync_policy(mut self, wal_sync_policy: WalSyncPolicy) -> Self {
+        self.wal_sync_policy = Some(wal_sync_policy);
+        self
+    }
+
+    #[cfg(feature = "disk-index")]
+    /// Set runtime.
+    pub fn with_runtime(mut self, runtime: Handle) -> Self {
+        self.runtime = Some(runtime);
+        self
+    }
+
+    #[cfg(feature = "gpu")]
+    /// Set GPU distance.
+    pub fn with_gpu_distance(mut self, gpu_distance: Arc<GpuDistance>) -> Self {
+        self.gpu_distance = Some(gpu_distance);
+        self
+    }
+
+    /// Build IndexConfiguration from IndexConfigurationBuilder.
+    pub fn build(self) -> IndexConfiguration {
+        let config = IndexConfiguration::new(
+            self.dist_metric,
+            self.dim,
+            round_up(self.dim, 8),
+            self.max_points,
+            false,
+            0,
+            false,
+            self.num_frozen_pts.unwrap_or(0),
+            self.growth_potential.unwrap_or(1.0),
+            self.index_write_parameter.unwrap_or_default(),
+        );
+
+        IndexConfiguration {
+            distance_tie_epsilon: self.distance_tie_epsilon.unwrap_or(config.distance_tie_epsilon),
+            num_search_frontiers: self.num_search_frontiers.unwrap_or(config.num_search_frontiers),
+            csr_graph: self.csr_graph.unwrap_or(config.csr_graph),
+            brute_force_threshold: self
+                .brute_force_threshold
+                .unwrap_or(config.brute_force_threshold),
+            prune_quantization: self
+                .prune_quantization
+                .map_or(config.prune_quantization, |(quantization, _)| quantization),
+            prune_refine_fraction: self
+                .prune_quantization
+                .map_or(config.prune_refine_fraction, |(_, fraction)| fraction),
+            entry_point_selection: self
+                .entry_point_selection
+                .unwrap_or(config.entry_point_selection),
+            random_seed: self.random_seed,
+            thread_pool: self.thread_pool,
+            wal_sync_policy: self.wal_sync_policy.unwrap_or(config.wal_sync_policy),
+            #[cfg(feature = "disk-index")]
+            runtime: self.runtime,
+            #[cfg(feature = "gpu")]
+            gpu_distance: self.gpu_distance,
+            ..config
+        }
+    }
+}
+
+#[cfg(test)]
+mod index_configuration_test {
+    use super::*;
+    use crate::model::IndexWriteParametersBuilder;
+
+    #[test]
+    fn builder_fills_defaults() {
+        let config = IndexConfigurationBuilder::new(Metric::Cosine, 100, 1000).build();
+        assert_eq!(config.dist_metric, Metric::Cosine);
+        assert_eq!(config.dim, 100);
+        assert_eq!(config.aligned_dim, 104);
+        assert_eq!(config.max_points, 1000);
+        assert_eq!(config.num_frozen_pts, 0);
+        assert!(!config.use_pq_dist);
+        assert_eq!(config.growth_potential, 1.0);
+        assert_eq!(config.index_write_parameter, IndexWriteParameters::default());
+        assert_eq!(config.num_search_frontiers, 1);
+        assert!(!config.csr_graph);
+        assert_eq!(config.brute_force_threshold, 0);
+        assert_eq!(config.prune_quantization, PruneQuantization::None);
+        assert_eq!(config.entry_point_selection, EntryPointSelection::Centroid);
+        assert_eq!(config.random_seed, None);
+        assert!(config.thread_pool.is_none());
+        #[cfg(feature = "disk-index")]
+        assert!(config.runtime.is_none());
+
+        let write_parameters = IndexWriteParametersBuilder::new(50, 16).build();
+        let config = IndexConfigurationBuilder::new(Metric::L2, 128, 10)
+            .with_index_write_parameters(write_parameters)
+            .with_growth_potential(1.5)
+            .with_num_search_frontiers(2)
+            .with_csr_graph(true)
+            .with_brute_force_threshold(64)
+            .with_prune_quantization(PruneQuantization::PQ { num_chunks: 32 }, 0.25)
+            .with_random_seed(42)
+            .build();
+        assert_eq!(config.aligned_dim, 128);
+        assert_eq!(config.index_write_parameter, write_parameters);
+        assert_eq!(config.growth_potential, 1.5);
+        assert_eq!(config.num_search_frontiers, 2);
+        assert!(config.csr_graph);
+        assert_eq!(config.brute_force_threshold, 64);
+        assert_eq!(
+            config.prune_quantization,
+            PruneQuantization::PQ { num_chunks: 32 }
+        );
+        assert_eq!(config.prune_refine_fraction, 0.25);
+        assert_eq!(config.random_seed, Some(42));
+    }
 }
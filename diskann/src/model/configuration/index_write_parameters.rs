@@ -6,6 +6,10 @@
 
 //! Index write parameters.
 
+use serde::{Deserialize, Serialize};
+
+use crate::common::{ANNError, ANNResult};
+
 /// Default parameter values.
 pub mod default_param_vals {
     /// Default value of alpha.
@@ -39,8 +43,9 @@ pub mod default_param_vals {
     pub const SEARCH_LIST_SIZE: u32 = 100;
 }
 
-/// Index write parameters.
-#[derive(Clone, Copy, PartialEq, Debug)]
+/// Index write parameters. Missing fields of a parameter file take their default values.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct IndexWriteParameters {
     /// Search list size - L.
     pub search_list_size: u32,
@@ -60,7 +65,8 @@ pub struct IndexWriteParameters {
     /// Number of rounds.
     pub num_rounds: u32,
 
-    /// Number of threads.
+    /// Number of threads linking and pruning the points of a build in parallel, on a pool of
+    /// their own. 0 uses the global rayon pool, of one thread per logical core by default.
     pub num_threads: u32,
     
     /// Number of frozen points.
@@ -83,6 +89,45 @@ impl Default for IndexWriteParameters {
     }
 }
 
+impl IndexWriteParameters {
+    /// Check that the parameters can build a graph: R and L must be positive, alpha at least 1
+    /// so pruning keeps the closest candidate, and C must hold R candidates.
+    pub fn validate(&self) -> ANNResult<()> {
+        if self.max_degree == 0 {
+            return Err(ANNError::log_index_config_error(
+                "max_degree".to_string(),
+                "R should be > 0".to_string(),
+            ));
+        }
+
+        if self.search_list_size == 0 {
+            return Err(ANNError::log_index_config_error(
+                "search_list_size".to_string(),
+                "L should be > 0".to_string(),
+            ));
+        }
+
+        if !(self.alpha >= 1.0 && self.alpha.is_finite()) {
+            return Err(ANNError::log_index_config_error(
+                "alpha".to_string(),
+                format!("alpha should be finite and >= 1, got {}", self.alpha),
+            ));
+        }
+
+        if self.max_occlusion_size < self.max_degree {
+            return Err(ANNError::log_index_config_error(
+                "max_occlusion_size".to_string(),
+                format!(
+                    "C {} should be >= R {}",
+                    self.max_occlusion_size, self.max_degree
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
 /// The builder for IndexWriteParameters.
 #[derive(Debug)]
 pub struct IndexWriteParametersBuilder {
@@ -172,6 +217,15 @@ impl IndexWriteParametersBuilder {
     }
 }
 
+impl IndexWriteParametersBuilder {
+    /// Build IndexWriteParameters, failing with an IndexConfigError if they can't build a graph
+    pub fn try_build(self) -> ANNResult<IndexWriteParameters> {
+        let parameters = self.build();
+        parameters.validate()?;
+        Ok(parameters)
+    }
+}
+
 /// Construct IndexWriteParametersBuilder from IndexWriteParameters.
 impl From<IndexWriteParameters> for IndexWriteParametersBuilder {
     fn from(param: IndexWriteParameters) -> Self {
@@ -241,5 +295,31 @@ mod parameters_test {
         let wp3 = IndexWriteParametersBuilder::from(wp2).build();
         assert_eq!(wp3, wp2);
     }
+
+    #[test]
+    fn try_build_validates_parameters() {
+        let wp = IndexWriteParametersBuilder::new(100, 64)
+            .with_alpha(1.2)
+            .with_saturate_graph(true)
+            .try_build()
+            .unwrap();
+        assert_eq!(wp, IndexWriteParametersBuilder::from(wp).build());
+        assert!(IndexWriteParameters::default().validate().is_ok());
+
+        let invalid_parameter = |builder: IndexWriteParametersBuilder| match builder.try_build() {
+            Err(ANNError::IndexConfigError { parameter, .. }) => parameter,
+            other => panic!("expected an IndexConfigError, got {:?}", other),
+        };
+        let builder = || IndexWriteParametersBuilder::new(100, 64);
+        let no_degree = IndexWriteParametersBuilder::new(100, 0);
+        assert_eq!(invalid_parameter(no_degree), "max_degree");
+        let no_list = IndexWriteParametersBuilder::new(0, 64);
+        assert_eq!(invalid_parameter(no_list), "search_list_size");
+        for alpha in [0.5, f32::NAN, f32::INFINITY] {
+            assert_eq!(invalid_parameter(builder().with_alpha(alpha)), "alpha");
+        }
+        let small_occlusion = builder().with_max_occlusion_size(32);
+        assert_eq!(invalid_parameter(small_occlusion), "max_occlusion_size");
+    }
 }
 
@@ -3,10 +3,22 @@
  * Licensed under the MIT license.
  */
 pub mod index_configuration;
-pub use index_configuration::IndexConfiguration;
+pub use index_configuration::{
+    EntryPointSelection, IndexConfiguration, IndexConfigurationBuilder, PruneQuantization,
+    WalSyncPolicy,
+};
 
 pub mod index_write_parameters;
 pub use index_write_parameters::*;
 
+pub mod search_params;
+pub use search_params::SearchParams;
+
 pub mod disk_index_build_parameter;
 pub use disk_index_build_parameter::DiskIndexBuildParameters;
+
+pub mod pq_parameters;
+pub use pq_parameters::PQParameters;
+
+pub mod parameter_file;
+pub use parameter_file::ParameterFile;
@@ -0,0 +1,285 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Build and search parameters of an experiment kept in a JSON or TOML file.
+//!
+//! The file has a section per parameter type, index_write, search and pq, each optional and
+//! each field defaulting like the type does. Unknown sections and fields are rejected so a
+//! misspelled parameter doesn't silently fall back to its default, and the parameters are
+//! validated as their constructors would. TOML files are read as flat tables of numbers,
+//! booleans and strings, which is all the parameters need.
+
+use std::fs;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Number, Value};
+
+use crate::common::{ANNError, ANNResult};
+
+use super::{IndexWriteParameters, PQParameters, SearchParams};
+
+/// Parameters of an index build and of its searches
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ParameterFile {
+    /// Parameters of the graph build
+    pub index_write: IndexWriteParameters,
+
+    /// Parameters of the searches
+    pub search: SearchParams,
+
+    /// PQ parameters of a disk index build
+    pub pq: PQParameters,
+}
+
+impl ParameterFile {
+    /// Read and validate the parameters of a .json or .toml file
+    pub fn load(filename: &str) -> ANNResult<Self> {
+        let contents = fs::read_to_string(filename)?;
+        let value = match file_extension(filename)? {
+            FileExtension::Json => serde_json::from_str(&contents)
+                .map_err(|err| config_error(filename, err.to_string()))?,
+            FileExtension::Toml => parse_toml(filename, &contents)?,
+        };
+        let parameters: Self =
+            serde_json::from_value(value).map_err(|err| config_error(filename, err.to_string()))?;
+
+        parameters.validate()?;
+        Ok(parameters)
+    }
+
+    /// Write the parameters to a .json or .toml file
+    pub fn save(&self, filename: &str) -> ANNResult<()> {
+        let value =
+            serde_json::to_value(self).map_err(|err| config_error(filename, err.to_string()))?;
+        let contents = match file_extension(filename)? {
+            FileExtension::Json => serde_json::to_string_pretty(&value)
+                .map_err(|err| config_error(filename, err.to_string()))?,
+            FileExtension::Toml => write_toml(&value),
+        };
+        fs::write(filename, contents)?;
+        Ok(())
+    }
+
+    /// Check every section of the parameters
+    pub fn validate(&self) -> ANNResult<()> {
+        self.index_write.validate()?;
+        self.search.validate()?;
+        self.pq.validate()
+    }
+}
+
+enum FileExtension {
+    Json,
+    Toml,
+}
+
+fn file_extension(filename: &str) -> ANNResult<FileExtension> {
+    match filename
+        .rsplit('.')
+        .next()
+        .map(str::to_ascii_lowercase)
+        .as_deref()
+    {
+        Some("json") => Ok(FileExtension::Json),
+        Some("toml") => Ok(FileExtension::Toml),
+        _ => Err(config_error(
+            filename,
+            "expecting a .json or .toml file".to_string(),
+        )),
+    }
+}
+
+fn config_error(filename: &str, err: String) -> ANNError {
+    ANNError::log_index_config_error(filename.to_string(), err)
+}
+
+/// Tables of key = value lines under [section] headers, as the JSON object they stand for
+fn parse_toml(filename: &str, contents: &str) -> ANNResult<Value> {
+    let mut root = Map::new();
+    let mut section: Option<String> = None;
+    for (index, line) in contents.lines().enumerate() {
+        let line_error = |msg: &str| config_error(filename, format!("line {}: {}", index + 1, msg));
+        let line = strip_toml_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(name) = line
+            .strip_prefix('[')
+            .and_then(|line| line.strip_suffix(']'))
+        {
+            let name = name.trim();
+            if root.contains_key(name) {
+                return Err(line_error(&format!("section {} is defined twice", name)));
+            }
+            root.insert(name.to_string(), Value::Object(Map::new()));
+            section = Some(name.to_string());
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| line_error("expecting key = value"))?;
+        let key = key.trim().trim_matches('"').to_string();
+        let value = parse_toml_value(value.trim()).ok_or_else(|| {
+            line_error(&format!(
+                "{} isn't a number, a boolean or a string",
+                value.trim()
+            ))
+        })?;
+        let table = match &section {
+            Some(name) => root.get_mut(name).and_then(Value::as_object_mut),
+            None => Some(&mut root),
+        }
+        .ok_or_else(|| line_error("no table to hold the key"))?;
+        if table.insert(key.clone(), value).is_some() {
+            return Err(line_error(&format!("{} is defined twice", key)));
+        }
+    }
+
+    Ok(Value::Object(root))
+}
+
+/// Line without its comment, keeping the # inside strings
+fn strip_toml_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse_toml_value(value: &str) -> Option<Value> {
+    match value {
+        "true" => return Some(Value::Bool(true)),
+        "false" => return Some(Value::Bool(false)),
+        _ => {}
+    }
+    if let Some(string) = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+    {
+        return Some(Value::String(
+            string.replace("\\\"", "\"").replace("\\\\", "\\"),
+        ));
+    }
+
+    let number = value.replace('_', "");
+    if let Ok(integer) = number.parse::<i64>() {
+        return Some(Value::Number(integer.into()));
+    }
+    number
+        .parse::<f64>()
+        .ok()
+        .and_then(Number::from_f64)
+        .map(Value::Number)
+}
+
+/// The sections of the parameters as TOML tables, leaving out the unset options
+fn write_toml(value: &Value) -> String {
+    let mut contents = String::new();
+    for (name, table) in value.as_object().into_iter().flatten() {
+        contents.push_str(&format!("[{}]\n", name));
+        for (key, value) in table.as_object().into_iter().flatten() {
+            if !value.is_null() {
+                contents.push_str(&format!("{} = {}\n", key, value));
+            }
+        }
+        contents.push('\n');
+    }
+    contents
+}
+
+#[cfg(test)]
+mod parameter_file_test {
+    use crate::model::{PQCodeBits, PQRotation};
+
+    use super::*;
+
+    fn load(filename: &str, contents: &str) -> ANNResult<ParameterFile> {
+        fs::write(filename, contents).unwrap();
+        let parameters = ParameterFile::load(filename);
+        fs::remove_file(filename).unwrap();
+        parameters
+    }
+
+    #[test]
+    fn parameters_round_trip_through_json_and_toml() {
+        let toml = "# experiment 12\n\
+                    [index_write]\n\
+                    max_degree = 32\n\
+                    search_list_size = 75 # L\n\
+                    alpha = 1.4\n\
+                    \n\
+                    [search]\n\
+                    l_value = 40\n\
+                    max_ios = 1_000\n\
+                    \n\
+                    [pq]\n\
+                    num_pq_chunks = 16\n\
+                    code_bits = \"four\"\n";
+        let parameters = load("parameters_round_trip.toml", toml).unwrap();
+        assert_eq!(parameters.index_write.max_degree, 32);
+        assert_eq!(parameters.index_write.search_list_size, 75);
+        assert_eq!(parameters.index_write.alpha, 1.4);
+        assert_eq!(
+            parameters.index_write.num_rounds,
+            IndexWriteParameters::default().num_rounds
+        );
+        assert_eq!(parameters.search.l_value(), 40);
+        assert_eq!(parameters.search.max_ios(), Some(1000));
+        assert_eq!(parameters.search.patience(), None);
+        assert_eq!(parameters.pq.code_bits, PQCodeBits::Four);
+        assert_eq!(parameters.pq.rotation, PQRotation::None);
+
+        for file in ["parameters_round_trip.json", "parameters_round_trip.toml"] {
+            parameters.save(file).unwrap();
+            let loaded = ParameterFile::load(file);
+            fs::remove_file(file).unwrap();
+            assert_eq!(loaded.unwrap(), parameters);
+        }
+    }
+
+    #[test]
+    fn invalid_parameters_are_config_errors() {
+        for (file, contents, parameter) in [
+            (
+                "invalid_parameters.json",
+                r#"{"search": {"l_vaule": 40}}"#,
+                "l_vaule",
+            ),
+            (
+                "invalid_parameters.json",
+                r#"{"index_write": {"alpha": 0.5}}"#,
+                "alpha",
+            ),
+            (
+                "invalid_parameters.toml",
+                "[search]\nl_value = 2\nbeam_width = 4\n",
+                "beam_width",
+            ),
+            (
+                "invalid_parameters.toml",
+                "[pq]\nuse_opq = true\nrotation = \"random\"\n",
+                "rotation",
+            ),
+            (
+                "invalid_parameters.toml",
+                "[search]\nl_value = forty\n",
+                "line 2",
+            ),
+        ] {
+            let err = load(file, contents).unwrap_err();
+            assert!(matches!(err, ANNError::IndexConfigError { .. }));
+            assert!(err.to_string().contains(parameter), "{}", err);
+        }
+    }
+}
@@ -0,0 +1,65 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Product quantization parameters of a disk index build.
+
+use serde::{Deserialize, Serialize};
+
+use crate::common::{ANNError, ANNResult};
+use crate::model::{PQCodeBits, PQRotation};
+
+use super::IndexConfiguration;
+
+/// How a disk index build compresses the vectors kept in memory. Missing fields of a
+/// parameter file take their default values.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PQParameters {
+    /// Number of PQ chunks, derived from the search RAM budget if 0
+    pub num_pq_chunks: usize,
+
+    /// Learn a rotation balancing the variance of the chunks, see IndexConfiguration::use_opq
+    pub use_opq: bool,
+
+    /// Fixed transform of the vectors before chunking when OPQ is off
+    pub rotation: PQRotation,
+
+    /// Width of the PQ code of a chunk
+    pub code_bits: PQCodeBits,
+
+    /// Number of vectors the pivots are trained on, 0 to sample about 256000
+    pub training_set_size: usize,
+}
+
+impl PQParameters {
+    /// Check that the parameters don't ask for two transforms at once
+    pub fn validate(&self) -> ANNResult<()> {
+        if self.use_opq && self.rotation != PQRotation::None {
+            return Err(ANNError::log_index_config_error(
+                "rotation".to_string(),
+                format!(
+                    "Rotation {:?} can't be combined with OPQ, which learns its own",
+                    self.rotation
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl IndexConfiguration {
+    /// Set the PQ parameters of a disk index build
+    pub fn with_pq_parameters(mut self, pq_parameters: &PQParameters) -> Self {
+        self.use_pq_dist = pq_parameters.num_pq_chunks > 0;
+        self.num_pq_chunks = pq_parameters.num_pq_chunks;
+        self.use_opq = pq_parameters.use_opq;
+        self.pq_rotation = pq_parameters.rotation;
+        self.pq_code_bits = pq_parameters.code_bits;
+        self.pq_training_set_size = pq_parameters.training_set_size;
+        self
+    }
+}
@@ -0,0 +1,243 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Parameters of a query search.
+
+use serde::{Deserialize, Serialize};
+
+use crate::common::{ANNError, ANNResult};
+
+use super::index_write_parameters::default_param_vals;
+
+/// Default number of nodes a disk search reads per round trip to the disk
+const DEFAULT_BEAM_WIDTH: usize = 4;
+
+/// Parameters of a query search, shared by the in-memory and disk indices. Missing fields of
+/// a parameter file take their default values.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SearchParams {
+    /// Search list size - L, the number of closest candidates kept while searching
+    l_value: u32,
+
+    /// Number of nodes a disk search expands per round trip to the disk.
+    /// An in-memory search expands one node at a time.
+    beam_width: usize,
+
+    /// Most nodes read from the disk by a disk search, or expanded by an in-memory search,
+    /// before the search stops with the candidates found so far. None for no limit.
+    max_ios: Option<usize>,
+
+    /// Whether a disk search ranks the expanded nodes by the full precision distance instead
+    /// of the PQ distance. An in-memory search always compares full precision vectors.
+    reorder: bool,
+
+    /// Whether a disk search adapts the nodes it reads per round trip, up to beam_width, to
+    /// the latency and queue depth of the device
+    adaptive_prefetch: bool,
+
+    /// Number of closest candidates by PQ distance in the search list of a disk search that
+    /// are compared to the query at full precision before returning. None to rank by the
+    /// distances found during the search.
+    rerank_size: Option<usize>,
+
+    /// Most nodes expanded in a row without any of them bringing a candidate closer than the
+    /// closest one so far, before the search stops. None to search until the candidates run
+    /// out.
+    patience: Option<usize>,
+}
+
+impl SearchParams {
+    /// Create SearchParams instance
+    pub fn new(
+        l_value: u32,
+        beam_width: usize,
+        max_ios: Option<usize>,
+        reorder: bool,
+    ) -> ANNResult<Self> {
+        let params = Self {
+            l_value,
+            beam_width,
+            max_ios,
+            reorder,
+            adaptive_prefetch: false,
+            rerank_size: None,
+            patience: None,
+        };
+        params.validate()?;
+        Ok(params)
+    }
+
+    /// Check the parameters as new and with_patience do, for parameters read from a file
+    pub fn validate(&self) -> ANNResult<()> {
+        if self.l_value == 0 {
+            return Err(ANNError::log_index_config_error(
+                "l_value".to_string(),
+                "Search list size should be > 0".to_string(),
+            ));
+        }
+
+        if self.beam_width == 0 || self.beam_width > self.l_value as usize {
+            return Err(ANNError::log_index_config_error(
+                "beam_width".to_string(),
+                format!(
+                    "Beam width {} should be > 0 and at most the search list size {}",
+                    self.beam_width, self.l_value
+                ),
+            ));
+        }
+
+        if self.max_ios == Some(0) {
+            return Err(ANNError::log_index_config_error(
+                "max_ios".to_string(),
+                "IO limit should be > 0".to_string(),
+            ));
+        }
+
+        if self.patience == Some(0) {
+            return Err(ANNError::log_index_config_error(
+                "patience".to_string(),
+                "Patience should be > 0".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Set whether a disk search adapts its prefetch window to the device, the beam width
+    /// becoming the largest window
+    pub fn with_adaptive_prefetch(mut self, adaptive_prefetch: bool) -> Self {
+        self.adaptive_prefetch = adaptive_prefetch;
+        self
+    }
+
+    /// Set the number of closest candidates by PQ distance that a disk search reranks by full
+    /// precision distance before returning, at least the k of the search
+    pub fn with_rerank(mut self, rerank_size: usize) -> Self {
+        self.rerank_size = Some(rerank_size);
+        self
+    }
+
+    /// Set the number of expansions in a row without improving the closest candidate after
+    /// which the search stops, bounding the time spent on hard queries
+    pub fn with_patience(mut self, patience: usize) -> ANNResult<Self> {
+        if patience == 0 {
+            return Err(ANNError::log_index_config_error(
+                "patience".to_string(),
+                "Patience should be > 0".to_string(),
+            ));
+        }
+        self.patience = Some(patience);
+        Ok(self)
+    }
+
+    /// Get l_value
+    pub fn l_value(&self) -> u32 {
+        self.l_value
+    }
+
+    /// Get beam_width
+    pub fn beam_width(&self) -> usize {
+        self.beam_width
+    }
+
+    /// Get max_ios
+    pub fn max_ios(&self) -> Option<usize> {
+        self.max_ios
+    }
+
+    /// Get reorder
+    pub fn reorder(&self) -> bool {
+        self.reorder
+    }
+
+    /// Get adaptive_prefetch
+    pub fn adaptive_prefetch(&self) -> bool {
+        self.adaptive_prefetch
+    }
+
+    /// Get rerank_size
+    pub fn rerank_size(&self) -> Option<usize> {
+        self.rerank_size
+    }
+
+    /// Get patience
+    pub fn patience(&self) -> Option<usize> {
+        self.patience
+    }
+
+    /// Check that k results can be taken from the search list
+    pub fn check_k(&self, k_value: usize) -> ANNResult<()> {
+        if k_value == 0 || k_value > self.l_value as usize {
+            return Err(ANNError::log_index_error(format!(
+                "Set L: {} to a value of at least K: {}, and K > 0",
+                self.l_value, k_value
+            )));
+        }
+
+        if let Some(rerank_size) = self.rerank_size {
+            if k_value > rerank_size {
+                return Err(ANNError::log_index_error(format!(
+                    "Set the rerank size: {} to a value of at least K: {}",
+                    rerank_size, k_value
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for SearchParams {
+    /// Create SearchParams with default values, without IO limit and reordering
+    fn default() -> Self {
+        Self {
+            l_value: default_param_vals::SEARCH_LIST_SIZE,
+            beam_width: DEFAULT_BEAM_WIDTH,
+            max_ios: None,
+            reorder: true,
+            adaptive_prefetch: false,
+            rerank_size: None,
+            patience: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod search_params_test {
+    use super::*;
+
+    #[test]
+    fn validates_at_construction() {
+        let params = SearchParams::new(50, 4, Some(100), false).unwrap();
+        assert_eq!(params.l_value(), 50);
+        assert_eq!(params.beam_width(), 4);
+        assert_eq!(params.max_ios(), Some(100));
+        assert!(!params.reorder());
+        assert!(!params.adaptive_prefetch());
+        assert!(params.with_adaptive_prefetch(true).adaptive_prefetch());
+        assert!(params.check_k(50).is_ok());
+        assert!(params.check_k(51).is_err());
+        assert!(params.check_k(0).is_err());
+        assert_eq!(params.rerank_size(), None);
+        assert_eq!(params.with_rerank(20).rerank_size(), Some(20));
+        assert!(params.with_rerank(20).check_k(20).is_ok());
+        assert!(params.with_rerank(20).check_k(21).is_err());
+        assert_eq!(params.patience(), None);
+        assert_eq!(params.with_patience(8).unwrap().patience(), Some(8));
+        assert!(params.with_patience(0).is_err());
+
+        assert!(SearchParams::new(0, 1, None, true).is_err());
+        assert!(SearchParams::new(50, 0, None, true).is_err());
+        assert!(SearchParams::new(50, 51, None, true).is_err());
+        assert!(SearchParams::new(50, 4, Some(0), true).is_err());
+
+        let params = SearchParams::default();
+        assert_eq!(
+            SearchParams::new(params.l_value(), params.beam_width(), None, true).unwrap(),
+            params
+        );
+    }
+}
@@ -0,0 +1,213 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Sources handing the vectors of an index build over batch by batch.
+//!
+//! The in-memory index copies each batch into its dataset as it arrives, so the vectors can
+//! come from a file read a batch at a time, from a channel fed by another thread, or from a
+//! database cursor without first being gathered into one allocation.
+
+use std::sync::mpsc::Receiver;
+
+use crate::common::{ANNError, ANNResult};
+use crate::utils::ChunkedBinReader;
+
+/// Vectors of a batch, stored one after the other
+#[derive(Debug, Clone, PartialEq)]
+pub struct Batch<T> {
+    /// Values of the vectors, row after row
+    pub rows: Vec<T>,
+
+    /// Dimension of the vectors
+    pub dim: usize,
+}
+
+impl<T> Batch<T> {
+    /// Number of vectors of the batch
+    pub fn num_points(&self) -> usize {
+        self.rows.len().checked_div(self.dim).unwrap_or(0)
+    }
+}
+
+/// Vectors of an index build, read a batch at a time. Their ids are their positions in the
+/// order of the batches.
+pub trait DatasetSource<T> {
+    /// Dimension of the vectors
+    fn dimension(&self) -> usize;
+
+    /// Number of vectors the source expects to hand over, if known in advance
+    fn len_hint(&self) -> Option<usize> {
+        None
+    }
+
+    /// Next batch of vectors, None once the source is exhausted
+    fn next_batch(&mut self) -> ANNResult<Option<Batch<T>>>;
+}
+
+/// Source of rows in memory, handed over batch_size at a time
+#[derive(Debug)]
+pub struct SliceSource<'a, T> {
+    rows: &'a [T],
+    dim: usize,
+    batch_size: usize,
+}
+
+impl<'a, T> SliceSource<'a, T> {
+    /// Create the source of rows of dim values stored one after the other
+    pub fn new(rows: &'a [T], dim: usize, batch_size: usize) -> ANNResult<Self> {
+        if dim == 0 || batch_size == 0 || !rows.len().is_multiple_of(dim) {
+            return Err(ANNError::log_index_error(format!(
+                "ERROR: {} values aren't batches of {} rows of {} dimensions.",
+                rows.len(),
+                batch_size,
+                dim
+            )));
+        }
+        Ok(Self {
+            rows,
+            dim,
+            batch_size,
+        })
+    }
+}
+
+impl<T: Copy> DatasetSource<T> for SliceSource<'_, T> {
+    fn dimension(&self) -> usize {
+        self.dim
+    }
+
+    fn len_hint(&self) -> Option<usize> {
+        Some(self.rows.len() / self.dim)
+    }
+
+    fn next_batch(&mut self) -> ANNResult<Option<Batch<T>>> {
+        if self.rows.is_empty() {
+            return Ok(None);
+        }
+        let (batch, rest) = self
+            .rows
+            .split_at((self.batch_size * self.dim).min(self.rows.len()));
+        self.rows = rest;
+        Ok(Some(Batch {
+            rows: batch.to_vec(),
+            dim: self.dim,
+        }))
+    }
+}
+
+/// Source of vectors sent one by one through a channel, exhausted once every sender is dropped
+#[derive(Debug)]
+pub struct ChannelSource<T> {
+    receiver: Receiver<Vec<T>>,
+    dim: usize,
+    batch_size: usize,
+}
+
+impl<T> ChannelSource<T> {
+    /// Create the source of the vectors of dim values received from receiver, gathering up
+    /// to batch_size of them in a batch
+    pub fn new(receiver: Receiver<Vec<T>>, dim: usize, batch_size: usize) -> Self {
+        Self {
+            receiver,
+            dim,
+            batch_size: batch_size.max(1),
+        }
+    }
+}
+
+impl<T> DatasetSource<T> for ChannelSource<T> {
+    fn dimension(&self) -> usize {
+        self.dim
+    }
+
+    fn next_batch(&mut self) -> ANNResult<Option<Batch<T>>> {
+        let mut rows = Vec::with_capacity(self.batch_size * self.dim);
+        for vector in self.receiver.iter().take(self.batch_size) {
+            if vector.len() != self.dim {
+                return Err(ANNError::log_index_error(format!(
+                    "ERROR: Received a vector of {} dimensions from a source of {} dimensions.",
+                    vector.len(),
+                    self.dim
+                )));
+            }
+            rows.extend(vector);
+        }
+
+        Ok((!rows.is_empty()).then_some(Batch {
+            rows,
+            dim: self.dim,
+        }))
+    }
+}
+
+/// Source of the vectors of a bin file, read batch_size at a time instead of all at once
+#[derive(Debug)]
+pub struct BinFileSource<T> {
+    reader: ChunkedBinReader<T>,
+}
+
+impl<T: Default + Copy> BinFileSource<T> {
+    /// Open the bin file at filename, handing over its vectors batch_size at a time
+    pub fn open(filename: &str, batch_size: usize) -> ANNResult<Self> {
+        Ok(Self {
+            reader: ChunkedBinReader::open(filename, batch_size)?,
+        })
+    }
+}
+
+impl<T: Default + Copy> DatasetSource<T> for BinFileSource<T> {
+    fn dimension(&self) -> usize {
+        self.reader.dim()
+    }
+
+    fn len_hint(&self) -> Option<usize> {
+        Some(self.reader.num_points())
+    }
+
+    fn next_batch(&mut self) -> ANNResult<Option<Batch<T>>> {
+        let dim = self.reader.dim();
+        Ok(self.reader.next_chunk()?.map(|rows| Batch { rows, dim }))
+    }
+}
+
+#[cfg(test)]
+mod dataset_source_test {
+    use std::sync::mpsc;
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn sources_hand_over_every_vector_in_order() {
+        let rows: Vec<f32> = (0..21).map(|i| i as f32).collect();
+        let mut source = SliceSource::new(&rows, 3, 4).unwrap();
+        assert_eq!(source.len_hint(), Some(7));
+        let mut batch_sizes = Vec::new();
+        let mut received = Vec::new();
+        while let Some(batch) = source.next_batch().unwrap() {
+            batch_sizes.push(batch.num_points());
+            received.extend(batch.rows);
+        }
+        assert_eq!(batch_sizes, vec![4, 3]);
+        assert_eq!(received, rows);
+        assert!(SliceSource::new(&rows, 4, 4).is_err());
+
+        let (sender, receiver) = mpsc::channel();
+        let producer = thread::spawn(move || {
+            for vector in rows.chunks_exact(3) {
+                sender.send(vector.to_vec()).unwrap();
+            }
+        });
+        let mut source = ChannelSource::new(receiver, 3, 5);
+        assert_eq!(source.next_batch().unwrap().unwrap().num_points(), 5);
+        assert_eq!(
+            source.next_batch().unwrap().unwrap().rows[..3],
+            [15.0, 16.0, 17.0]
+        );
+        assert_eq!(source.next_batch().unwrap(), None);
+        producer.join().unwrap();
+    }
+}
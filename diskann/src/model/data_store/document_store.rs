@@ -0,0 +1,169 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Grouping of points into documents, each document owning several vectors
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use hashbrown::HashMap;
+
+use super::tag_store::{read_tag, write_tag};
+use super::Tag;
+use crate::common::{ANNError, ANNResult};
+
+/// How the distances from a query to the vectors of a document combine into the distance
+/// the document is ranked by
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DocumentAggregation {
+    /// Distance to the nearest vector of the document, its most similar one
+    #[default]
+    Max,
+
+    /// Mean distance to the vectors of the document
+    Mean,
+}
+
+/// Document of every point, indexed by vertex id, and the points of every document
+#[derive(Debug, Default)]
+pub struct DocumentStore {
+    documents: Vec<Option<Tag>>,
+    members: HashMap<Tag, Vec<u32>>,
+    capacity: usize,
+}
+
+impl DocumentStore {
+    /// Create a store able to hold the documents of `capacity` points
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            documents: Vec::new(),
+            members: HashMap::new(),
+            capacity,
+        }
+    }
+
+    /// Make a point one of the vectors of document, moving it out of its existing document
+    pub fn set_document(&mut self, vertex_id: u32, document: Tag) -> ANNResult<()> {
+        let idx = vertex_id as usize;
+        if idx >= self.capacity {
+            return Err(ANNError::log_index_error(format!(
+                "vertex_id {} is out of valid range of points {}",
+                vertex_id, self.capacity
+            )));
+        }
+        if self.documents.len() <= idx {
+            self.documents.resize(idx + 1, None);
+        }
+
+        if let Some(old) = self.documents[idx].take() {
+            if let Some(points) = self.members.get_mut(&old) {
+                points.retain(|&point| point != vertex_id);
+                if points.is_empty() {
+                    self.members.remove(&old);
+                }
+            }
+        }
+        let points = self.members.entry(document.clone()).or_default();
+        if let Err(pos) = points.binary_search(&vertex_id) {
+            points.insert(pos, vertex_id);
+        }
+        self.documents[idx] = Some(document);
+        Ok(())
+    }
+
+    /// Document of a point, None if it belongs to none
+    pub fn document(&self, vertex_id: u32) -> Option<&Tag> {
+        self.documents.get(vertex_id as usize)?.as_ref()
+    }
+
+    /// Points of a document, in ascending id order
+    pub fn members(&self, document: &Tag) -> &[u32] {
+        self.members
+            .get(document)
+            .map_or(&[], |points| points.as_slice())
+    }
+
+    /// Whether any point belongs to a document
+    pub fn has_documents(&self) -> bool {
+        !self.members.is_empty()
+    }
+
+    /// Save the document of every point: the number of points as a little endian u64, then
+    /// the document of each point written like a tag
+    pub fn save(&self, filename: &str) -> ANNResult<()> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        writer.write_u64::<LittleEndian>(self.documents.len() as u64)?;
+        for document in self.documents.iter() {
+            write_tag(&mut writer, document.as_ref())?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Replace the documents with the ones saved by save
+    pub fn load(&mut self, filename: &str) -> ANNResult<()> {
+        self.documents.clear();
+        self.members.clear();
+        self.append(filename, 0)
+    }
+
+    /// Assign the points from id_offset on to the documents saved by save
+    pub fn append(&mut self, filename: &str, id_offset: u32) -> ANNResult<()> {
+        let mut reader = BufReader::new(File::open(filename)?);
+        let num_points = reader.read_u64::<LittleEndian>()? as usize;
+        if id_offset as usize + num_points > self.capacity {
+            return Err(ANNError::log_index_error(format!(
+                "Documents file {} has {} points, more than the {} the index holds from point {}",
+                filename,
+                num_points,
+                self.capacity.saturating_sub(id_offset as usize),
+                id_offset
+            )));
+        }
+
+        for vertex_id in id_offset..id_offset + num_points as u32 {
+            if let Some(document) = read_tag(&mut reader, filename)? {
+                self.set_document(vertex_id, document)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod document_store_test {
+    use super::*;
+
+    #[test]
+    fn points_group_into_documents_and_are_saved() {
+        let mut store = DocumentStore::new(10);
+        store.set_document(4, Tag::Id(1)).unwrap();
+        store.set_document(2, Tag::Id(1)).unwrap();
+        store.set_document(3, "b".into()).unwrap();
+        assert_eq!(store.members(&Tag::Id(1)), &[2, 4]);
+        assert_eq!(store.document(3), Some(&"b".into()));
+
+        // Moving the last point of a document out drops the document
+        store.set_document(3, Tag::Id(1)).unwrap();
+        assert_eq!(store.members(&Tag::Id(1)), &[2, 3, 4]);
+        assert!(store.members(&"b".into()).is_empty());
+
+        let file = "points_group_into_documents_and_are_saved.documents";
+        store.save(file).unwrap();
+        let mut loaded = DocumentStore::new(10);
+        loaded.load(file).unwrap();
+        std::fs::remove_file(file).unwrap();
+
+        for vertex_id in 0..10 {
+            assert_eq!(loaded.document(vertex_id), store.document(vertex_id));
+        }
+        assert_eq!(loaded.members(&Tag::Id(1)), &[2, 3, 4]);
+        assert!(loaded.has_documents());
+        assert!(!DocumentStore::new(10).has_documents());
+        assert!(loaded.set_document(10, Tag::Id(1)).is_err());
+    }
+}
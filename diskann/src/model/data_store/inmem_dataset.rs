@@ -6,13 +6,22 @@
 
 //! In-memory Dataset
 
+use rand::seq::index::sample;
 use rayon::prelude::*;
 use std::mem;
 use vector::{FullPrecisionDistance, Metric};
 
 use crate::common::{ANNError, ANNResult, AlignedBoxWithSlice};
-use crate::model::Vertex;
-use crate::utils::copy_aligned_data_from_file;
+use crate::model::{EntryPointSelection, Vertex};
+use crate::utils::{
+    copy_aligned_data_from_file, k_means_clustering_with_params, seeded_rng, KMeansParams,
+};
+
+/// Most points the k-means of a clustered entry point selection is trained on
+const MAX_ENTRY_POINT_TRAINING_POINTS: usize = 65536;
+
+/// Lloyd iterations of the k-means of a clustered entry point selection
+const NUM_ENTRY_POINT_KMEANS_REPS: usize = 10;
 
 /// Dataset of all in-memory FP points
 #[derive(Debug)]
@@ -20,7 +29,9 @@ pub struct InmemDataset<T, const N: usize>
 where
     [T; N]: FullPrecisionDistance<T, N>,
 {
-    /// All in-memory points
+    /// All in-memory points. While points are inserted they are written through a shared
+    /// reference, so the points are only read through get_vertex then, never through the slice
+    /// of all points.
     pub data: AlignedBoxWithSlice<T>,
 
     /// Number of points we anticipate to have
@@ -52,7 +63,7 @@ where
         })
     }
 
-    /// get immutable data slice
+    /// get immutable data slice, not while points are inserted
     pub fn get_data(&self) -> &[T] {
         &self.data
     }
@@ -98,13 +109,117 @@ where
         Ok(())
     }
 
+    /// Build the dataset from vectors in memory, padding each one with zeros to N values
+    pub fn build_from_vectors(&mut self, vectors: &[Vec<T>]) -> ANNResult<()> {
+        self.copy_from_vectors(vectors, 0)?;
+        self.num_active_pts = vectors.len();
+        Ok(())
+    }
+
+    /// Build the dataset from rows of dim values stored one after the other, padding each
+    /// one with zeros to N values
+    pub fn build_from_rows(&mut self, rows: &[T], dim: usize) -> ANNResult<()> {
+        self.num_active_pts = self.copy_rows(rows, dim, 0)?;
+        Ok(())
+    }
+
+    /// Copy rows of dim values stored one after the other into the slots from point
+    /// pts_offset on, padding each one with zeros to N values. Returns the number of rows.
+    pub fn copy_rows(&mut self, rows: &[T], dim: usize, pts_offset: usize) -> ANNResult<usize> {
+        if dim == 0 || dim > N || !rows.len().is_multiple_of(dim) {
+            return Err(ANNError::log_index_error(format!(
+                "Cannot copy {} values as rows of {} dimensions into a dataset of {} dimensions",
+                rows.len(),
+                dim,
+                N
+            )));
+        }
+        let num_points = rows.len() / dim;
+        if (pts_offset + num_points) * N > self.data.len() {
+            return Err(ANNError::log_index_error(format!(
+                "Cannot copy {} vectors at point {} into dataset of {} points",
+                num_points,
+                pts_offset,
+                self.data.len() / N
+            )));
+        }
+
+        let slots = self.data[pts_offset * N..].chunks_exact_mut(N);
+        for (row, slot) in rows.chunks_exact(dim).zip(slots) {
+            slot[..dim].copy_from_slice(row);
+            slot[dim..].fill(T::default());
+        }
+        Ok(num_points)
+    }
+
+    /// Append vectors in memory after the active points, padding each one with zeros to N values
+    pub fn append_from_vectors(&mut self, vectors: &[Vec<T>]) -> ANNResult<()> {
+        self.copy_from_vectors(vectors, self.num_active_pts)?;
+        self.num_active_pts += vectors.len();
+        self.num_points += vectors.len();
+        Ok(())
+    }
+
+    fn copy_from_vectors(&mut self, vectors: &[Vec<T>], pts_offset: usize) -> ANNResult<()> {
+        if (pts_offset + vectors.len()) * N > self.data.len() {
+            return Err(ANNError::log_index_error(format!(
+                "Cannot copy {} vectors at point {} into dataset of {} points",
+                vectors.len(),
+                pts_offset,
+                self.data.len() / N
+            )));
+        }
+
+        for (i, vector) in vectors.iter().enumerate() {
+            if vector.len() > N {
+                return Err(ANNError::log_index_error(format!(
+                    "Vector {} has {} dimensions, more than the {} the dataset holds",
+                    i,
+                    vector.len(),
+                    N
+                )));
+            }
+
+            let start = (pts_offset + i) * N;
+            self.data[start..start + vector.len()].copy_from_slice(vector);
+            self.data[start + vector.len()..start + N].fill(T::default());
+        }
+
+        Ok(())
+    }
+
+    /// Copy a vector into the slot of a point, padding it with zeros to N values, while other
+    /// threads keep reading the points already in the dataset
+    ///
+    /// # Safety
+    ///
+    /// The caller must own the slot: no other thread may read or write the point until it is
+    /// published, e.g. linked into the graph under the locks of the adjacency lists.
+    pub unsafe fn write_unpublished_vector(&self, id: u32, vector: &[T]) -> ANNResult<()> {
+        let start = id as usize * N;
+        if vector.len() > N || start + N > self.data.len() {
+            return Err(ANNError::log_index_error(format!(
+                "Cannot write vector of {} dimensions to point {} of dataset of {} points",
+                vector.len(),
+                id,
+                self.data.len() / N
+            )));
+        }
+
+        // The readers of the other points only borrow the ranges of those points, never the
+        // slot written here
+        self.data.write_shared(start, vector)?;
+        self.data
+            .write_shared(start + vector.len(), &vec![T::default(); N - vector.len()])
+    }
+
     /// Get vertex by id
     pub fn get_vertex(&'a self, id: u32) -> ANNResult<Vertex<'a, T, N>> {
         let start = id as usize * N;
         let end = start + N;
 
-        if end <= self.data.len() {
-            let val = <&[T; N]>::try_from(&self.data[start..end]).map_err(|err| {
+        if let Some(vector) = self.data.get_range(start..end) {
+            let val = <&[T; N]>::try_from(vector).map_err(|err| {
                 ANNError::log_index_error(format!("Failed to get vertex {}, err={}", id, err))
             })?;
             Ok(Vertex::new(val, id))
@@ -126,7 +241,52 @@ where
 
     /// find out the medoid, the vertex in the dataset that is closest to the centroid
     pub fn calculate_medoid_point_id(&self) -> ANNResult<u32> {
-        Ok(self.find_nearest_point_id(self.calculate_centroid_point()?))
+        Ok(self.find_nearest_point_id(&self.calculate_centroid_point()?))
+    }
+
+    /// Points searches start from as selection picks them, the start of the graph first. The
+    /// points drawn by the sampled and clustered selections are drawn with seed, or from the
+    /// OS when it is None.
+    pub fn select_entry_points(
+        &self,
+        selection: EntryPointSelection,
+        seed: Option<u64>,
+    ) -> ANNResult<Vec<u32>> {
+        let num_points = self.num_active_pts;
+        match selection {
+            EntryPointSelection::Centroid => Ok(vec![self.calculate_medoid_point_id()?]),
+            EntryPointSelection::Medoid => {
+                let candidates: Vec<u32> = (0..num_points as u32).collect();
+                Ok(vec![self.find_medoid_point_id(&candidates)])
+            }
+            EntryPointSelection::SampledMedoid { sample_size } => {
+                if sample_size == 0 {
+                    return Err(ANNError::log_index_config_error(
+                        "entry_point_selection".to_string(),
+                        "The medoid can't be sampled from 0 points".to_string(),
+                    ));
+                }
+
+                let candidates: Vec<u32> = sample(
+                    &mut seeded_rng(seed),
+                    num_points,
+                    sample_size.min(num_points),
+                )
+                .iter()
+                .map(|id| id as u32)
+                .collect();
+                Ok(vec![self.find_medoid_point_id(&candidates)])
+            }
+            EntryPointSelection::Clustered { num_entry_points } => {
+                if num_entry_points == 0 {
+                    return Err(ANNError::log_index_config_error(
+                        "entry_point_selection".to_string(),
+                        "At least one entry point is needed".to_string(),
+                    ));
+                }
+                self.find_cluster_point_ids(num_entry_points.min(num_points), seed)
+            }
+        }
     }
 
     /// calculate centroid, average of all vertices in the dataset
@@ -152,16 +312,98 @@ where
         Ok(center)
     }
 
+    /// Candidate with the smallest sum of L2 distances to the other candidates
+    fn find_medoid_point_id(&self, candidates: &[u32]) -> u32 {
+        let vectors: Vec<Vec<f32>> = candidates
+            .iter()
+            .map(|&id| {
+                let start = id as usize * N;
+                self.data
+                    .get_range(start..start + N)
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|&x| x.into())
+                    .collect()
+            })
+            .collect();
+
+        let distance_sums: Vec<f32> = vectors
+            .par_iter()
+            .map(|a| {
+                vectors
+                    .iter()
+                    .map(|b| {
+                        let squared: f32 = a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum();
+                        squared.sqrt()
+                    })
+                    .sum()
+            })
+            .collect();
+
+        let mut medoid = 0;
+        for (i, sum) in distance_sums.iter().enumerate() {
+            if *sum < distance_sums[medoid] {
+                medoid = i;
+            }
+        }
+        candidates[medoid]
+    }
+
+    /// Points closest to the centers of num_clusters k-means clusters of the points, the one
+    /// of the largest cluster first, without repeats
+    fn find_cluster_point_ids(
+        &self,
+        num_clusters: usize,
+        seed: Option<u64>,
+    ) -> ANNResult<Vec<u32>> {
+        let num_points = self.num_active_pts;
+        let stride = num_points.div_ceil(MAX_ENTRY_POINT_TRAINING_POINTS).max(1);
+        let train_data: Vec<f32> = self
+            .data
+            .get_range(0..num_points * N)
+            .unwrap_or_default()
+            .chunks_exact(N)
+            .step_by(stride)
+            .flat_map(|row| row.iter().map(|&x| x.into()))
+            .collect();
+        let num_train = train_data.len() / N;
+
+        let mut centers = vec![0.0f32; num_clusters * N];
+        let (closest_docs, _, _) = k_means_clustering_with_params(
+            &train_data,
+            num_train,
+            N,
+            &mut centers,
+            num_clusters,
+            KMeansParams::new(NUM_ENTRY_POINT_KMEANS_REPS).with_seed(seed),
+        )?;
+
+        let mut clusters: Vec<usize> = (0..num_clusters).collect();
+        clusters.sort_by_key(|&cluster| std::cmp::Reverse(closest_docs[cluster].len()));
+
+        let mut entry_points = Vec::with_capacity(num_clusters);
+        for cluster in clusters {
+            let point = self.find_nearest_point_id(&centers[cluster * N..(cluster + 1) * N]);
+            if !entry_points.contains(&point) {
+                entry_points.push(point);
+            }
+        }
+        Ok(entry_points)
+    }
+
     /// find out the vertex closest to the given point
-    fn find_nearest_point_id(&self, point: [f32; N]) -> u32 {
+    fn find_nearest_point_id(&self, point: &[f32]) -> u32 {
         // compute all to one distance
         let mut distances = vec![0f32; self.num_active_pts];
-        let slice = &self.data[..];
+        let slice = self
+            .data
+            .get_range(0..self.num_active_pts * N)
+            .unwrap_or_default();
         distances.par_iter_mut().enumerate().for_each(|(i, dist)| {
             let start = i * N;
             for j in 0..N {
-                let diff: f32 = (point.as_slice()[j] - slice[start + j].into())
-                    * (point.as_slice()[j] - slice[start + j].into());
+                let diff: f32 =
+                    (point[j] - slice[start + j].into()) * (point[j] - slice[start + j].into());
                 *dist += diff;
             }
         });
@@ -184,8 +426,7 @@ where
         let start = id as usize * N;
         let end = start + N;
 
-        if end <= self.data.len() {
-            let vec = &self.data[start..end];
+        if let Some(vec) = self.data.get_range(start..end) {
             vector::prefetch_vector(vec);
         }
     }
@@ -281,5 +522,65 @@ mod dataset_test {
             }
         }
     }
-}
 
+    #[test]
+    fn select_entry_points_test() {
+        // The outlier pulls the centroid away from the medoid of the line
+        let line = [0.0, 1.0, 2.0, 3.0, 100.0];
+        let vectors: Vec<Vec<f32>> = line.iter().map(|&x| vec![x]).collect();
+        let mut dataset = InmemDataset::<f32, 8>::new(vectors.len(), 1f32).unwrap();
+        dataset.build_from_vectors(&vectors).unwrap();
+
+        let centroid = dataset.select_entry_points(EntryPointSelection::Centroid, None);
+        assert_eq!(centroid.unwrap(), vec![3]);
+        let medoid = dataset.select_entry_points(EntryPointSelection::Medoid, None);
+        assert_eq!(medoid.unwrap(), vec![2]);
+        let sampled = EntryPointSelection::SampledMedoid { sample_size: 10 };
+        assert_eq!(dataset.select_entry_points(sampled, None).unwrap(), vec![2]);
+        let empty_sample = EntryPointSelection::SampledMedoid { sample_size: 0 };
+        assert!(dataset.select_entry_points(empty_sample, None).is_err());
+
+        let clustered = EntryPointSelection::Clustered {
+            num_entry_points: 2,
+        };
+        let mut entry_points = dataset.select_entry_points(clustered, None).unwrap();
+        assert_eq!(entry_points.len(), 2);
+        assert!([0, 1, 2, 3].contains(&entry_points[0]));
+        entry_points.sort();
+        assert_eq!(entry_points[1], 4);
+    }
+
+    #[test]
+    fn unpublished_vectors_are_written_while_published_ones_are_read() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let vectors: Vec<Vec<f32>> = (0..4).map(|i| vec![i as f32; 8]).collect();
+        let mut dataset = InmemDataset::<f32, 8>::new(8, 1f32).unwrap();
+        dataset.build_from_vectors(&vectors).unwrap();
+
+        // The writer owns each slot until the count of published points reaches it
+        let published = AtomicUsize::new(4);
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                for id in 4..8 {
+                    let vector = vec![id as f32; 5];
+                    unsafe { dataset.write_unpublished_vector(id, &vector).unwrap() };
+                    published.store(id as usize + 1, Ordering::Release);
+                }
+            });
+            while published.load(Ordering::Acquire) < 8 {
+                for id in 0..published.load(Ordering::Acquire) as u32 {
+                    let vertex = dataset.get_vertex(id).unwrap();
+                    assert_eq!(vertex.vector()[0], id as f32);
+                }
+            }
+        });
+
+        for id in 4..8 {
+            let vertex = dataset.get_vertex(id).unwrap();
+            assert_eq!(vertex.vector()[..5], [id as f32; 5]);
+            assert_eq!(vertex.vector()[5..], [0.0; 3]);
+        }
+        assert!(unsafe { dataset.write_unpublished_vector(8, &vectors[0]) }.is_err());
+    }
+}
@@ -0,0 +1,94 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Predicate over the labels of a point, for filtered search
+
+/// Condition the labels of a point must satisfy for the point to be returned by a filtered search
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LabelFilter {
+    /// The point carries the label
+    Label(u32),
+
+    /// Every filter holds, true if there is none
+    And(Vec<LabelFilter>),
+
+    /// At least one filter holds, false if there is none
+    Or(Vec<LabelFilter>),
+}
+
+impl LabelFilter {
+    /// Points carrying every one of the labels
+    pub fn all_of(labels: &[u32]) -> Self {
+        LabelFilter::And(
+            labels
+                .iter()
+                .map(|&label| LabelFilter::Label(label))
+                .collect(),
+        )
+    }
+
+    /// Points carrying at least one of the labels
+    pub fn any_of(labels: &[u32]) -> Self {
+        LabelFilter::Or(
+            labels
+                .iter()
+                .map(|&label| LabelFilter::Label(label))
+                .collect(),
+        )
+    }
+
+    /// Whether a point with the given labels satisfies the filter
+    pub fn matches(&self, labels: &[u32]) -> bool {
+        match self {
+            LabelFilter::Label(label) => labels.contains(label),
+            LabelFilter::And(filters) => filters.iter().all(|filter| filter.matches(labels)),
+            LabelFilter::Or(filters) => filters.iter().any(|filter| filter.matches(labels)),
+        }
+    }
+
+    /// Every label the filter mentions, in order of appearance
+    pub fn labels(&self) -> Vec<u32> {
+        let mut labels = Vec::new();
+        self.collect_labels(&mut labels);
+        labels
+    }
+
+    fn collect_labels(&self, labels: &mut Vec<u32>) {
+        match self {
+            LabelFilter::Label(label) => {
+                if !labels.contains(label) {
+                    labels.push(*label);
+                }
+            }
+            LabelFilter::And(filters) | LabelFilter::Or(filters) => {
+                for filter in filters {
+                    filter.collect_labels(labels);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod label_filter_test {
+    use super::*;
+
+    #[test]
+    fn nested_filters_match_labels() {
+        // 1 AND (2 OR 3)
+        let filter = LabelFilter::And(vec![LabelFilter::Label(1), LabelFilter::any_of(&[2, 3])]);
+
+        assert!(filter.matches(&[3, 1]));
+        assert!(filter.matches(&[1, 2, 3]));
+        assert!(!filter.matches(&[1]));
+        assert!(!filter.matches(&[2, 3]));
+        assert_eq!(filter.labels(), vec![1, 2, 3]);
+
+        assert!(LabelFilter::all_of(&[]).matches(&[]));
+        assert!(!LabelFilter::any_of(&[]).matches(&[5]));
+        assert!(LabelFilter::all_of(&[4, 5]).matches(&[5, 6, 4]));
+    }
+}
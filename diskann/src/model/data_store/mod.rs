@@ -9,3 +9,28 @@ pub use inmem_dataset::DatasetDto;
 
 mod disk_scratch_dataset;
 pub use disk_scratch_dataset::*;
+
+mod label_filter;
+pub use label_filter::LabelFilter;
+
+mod point_metadata_store;
+pub use point_metadata_store::PointMetadataStore;
+
+mod quantized_prune_vectors;
+pub use quantized_prune_vectors::QuantizedPruneVectors;
+pub(crate) use quantized_prune_vectors::check_prune_quantization;
+
+mod sq8_vectors;
+pub use sq8_vectors::{SQ8Codec, SQ8Vectors};
+
+mod sparse_dataset;
+pub use sparse_dataset::{SparseDataset, SparseVector};
+
+mod tag_store;
+pub use tag_store::{Tag, TagStore};
+
+mod document_store;
+pub use document_store::{DocumentAggregation, DocumentStore};
+
+mod dataset_source;
+pub use dataset_source::*;
@@ -0,0 +1,281 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Per-point labels and opaque payload bytes stored next to the vectors
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use hashbrown::HashMap;
+
+use crate::common::{ANNError, ANNResult};
+
+/// Labels and payload of every point, indexed by vertex id.
+/// Points without metadata cost one empty Vec each.
+#[derive(Debug, Default)]
+pub struct PointMetadataStore {
+    labels: Vec<Vec<u32>>,
+    payloads: Vec<Vec<u8>>,
+    capacity: usize,
+
+    /// Points carrying each label, in ascending id order
+    label_points: HashMap<u32, Vec<u32>>,
+}
+
+impl PointMetadataStore {
+    /// Create a store able to hold metadata for `capacity` points
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            labels: Vec::new(),
+            payloads: Vec::new(),
+            capacity,
+            label_points: HashMap::new(),
+        }
+    }
+
+    /// Attach labels to a point, replacing any existing ones
+    pub fn set_labels(&mut self, vertex_id: u32, labels: Vec<u32>) -> ANNResult<()> {
+        let idx = self.check_range(vertex_id)?;
+        if self.labels.len() <= idx {
+            self.labels.resize_with(idx + 1, Vec::new);
+        }
+
+        for label in self.labels[idx].iter() {
+            if let Some(points) = self.label_points.get_mut(label) {
+                points.retain(|&point| point != vertex_id);
+            }
+        }
+        for &label in labels.iter() {
+            let points = self.label_points.entry(label).or_default();
+            if let Err(pos) = points.binary_search(&vertex_id) {
+                points.insert(pos, vertex_id);
+            }
+        }
+
+        self.labels[idx] = labels;
+        Ok(())
+    }
+
+    /// Attach a payload to a point, replacing any existing one
+    pub fn set_payload(&mut self, vertex_id: u32, payload: Vec<u8>) -> ANNResult<()> {
+        let idx = self.check_range(vertex_id)?;
+        if self.payloads.len() <= idx {
+            self.payloads.resize_with(idx + 1, Vec::new);
+        }
+        self.payloads[idx] = payload;
+        Ok(())
+    }
+
+    /// Labels of a point, empty if none were set
+    pub fn labels(&self, vertex_id: u32) -> &[u32] {
+        self.labels
+            .get(vertex_id as usize)
+            .map_or(&[], |labels| labels.as_slice())
+    }
+
+    /// Points carrying a label, in ascending id order
+    pub fn points_with_label(&self, label: u32) -> &[u32] {
+        self.label_points
+            .get(&label)
+            .map_or(&[], |points| points.as_slice())
+    }
+
+    /// Whether any point carries labels
+    pub fn has_labels(&self) -> bool {
+        self.labels.iter().any(|labels| !labels.is_empty())
+    }
+
+    /// Save the labels of every point: the number of points, then for each point the number
+    /// of its labels followed by the labels, all little endian
+    pub fn save_labels(&self, filename: &str) -> ANNResult<()> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        writer.write_u64::<LittleEndian>(self.labels.len() as u64)?;
+        for labels in self.labels.iter() {
+            writer.write_u32::<LittleEndian>(labels.len() as u32)?;
+            for &label in labels {
+                writer.write_u32::<LittleEndian>(label)?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Replace the labels with the ones saved by save_labels
+    pub fn load_labels(&mut self, filename: &str) -> ANNResult<()> {
+        self.labels.clear();
+        self.label_points.clear();
+        self.append_labels(filename, 0)
+    }
+
+    /// Attach the labels saved by save_labels to the points from id_offset on
+    pub fn append_labels(&mut self, filename: &str, id_offset: u32) -> ANNResult<()> {
+        let mut reader = BufReader::new(File::open(filename)?);
+        let num_points = reader.read_u64::<LittleEndian>()? as usize;
+        if id_offset as usize + num_points > self.capacity {
+            return Err(ANNError::log_index_error(format!(
+                "Labels file {} has {} points, more than the {} the index holds from point {}",
+                filename,
+                num_points,
+                self.capacity.saturating_sub(id_offset as usize),
+                id_offset
+            )));
+        }
+
+        for vertex_id in id_offset..id_offset + num_points as u32 {
+            let num_labels = reader.read_u32::<LittleEndian>()? as usize;
+            let mut labels = Vec::with_capacity(num_labels);
+            for _ in 0..num_labels {
+                labels.push(reader.read_u32::<LittleEndian>()?);
+            }
+            self.set_labels(vertex_id, labels)?;
+        }
+        Ok(())
+    }
+
+    /// Payload of a point, empty if none was set
+    pub fn payload(&self, vertex_id: u32) -> &[u8] {
+        self.payloads
+            .get(vertex_id as usize)
+            .map_or(&[], |payload| payload.as_slice())
+    }
+
+    /// Whether any point carries a payload
+    pub fn has_payloads(&self) -> bool {
+        self.payloads.iter().any(|payload| !payload.is_empty())
+    }
+
+    /// Save the payload of every point: the number of points, then for each point the length
+    /// of its payload followed by its bytes, lengths little endian
+    pub fn save_payloads(&self, filename: &str) -> ANNResult<()> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        writer.write_u64::<LittleEndian>(self.payloads.len() as u64)?;
+        for payload in self.payloads.iter() {
+            writer.write_u32::<LittleEndian>(payload.len() as u32)?;
+            writer.write_all(payload)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Replace the payloads with the ones saved by save_payloads
+    pub fn load_payloads(&mut self, filename: &str) -> ANNResult<()> {
+        self.payloads.clear();
+        self.append_payloads(filename, 0)
+    }
+
+    /// Attach the payloads saved by save_payloads to the points from id_offset on
+    pub fn append_payloads(&mut self, filename: &str, id_offset: u32) -> ANNResult<()> {
+        let mut reader = BufReader::new(File::open(filename)?);
+        let num_points = reader.read_u64::<LittleEndian>()? as usize;
+        if id_offset as usize + num_points > self.capacity {
+            return Err(ANNError::log_index_error(format!(
+                "Payloads file {} has {} points, more than the {} the index holds from point {}",
+                filename,
+                num_points,
+                self.capacity.saturating_sub(id_offset as usize),
+                id_offset
+            )));
+        }
+
+        for vertex_id in id_offset..id_offset + num_points as u32 {
+            let len = reader.read_u32::<LittleEndian>()? as usize;
+            let mut payload = vec![0u8; len];
+            reader.read_exact(&mut payload)?;
+            self.set_payload(vertex_id, payload)?;
+        }
+        Ok(())
+    }
+
+    fn check_range(&self, vertex_id: u32) -> ANNResult<usize> {
+        let idx = vertex_id as usize;
+        if idx >= self.capacity {
+            return Err(ANNError::log_index_error(format!(
+                "vertex_id {} is out of valid range of points {}",
+                vertex_id, self.capacity
+            )));
+        }
+        Ok(idx)
+    }
+}
+
+#[cfg(test)]
+mod point_metadata_store_test {
+    use super::*;
+
+    #[test]
+    fn set_and_get_metadata() {
+        let mut store = PointMetadataStore::new(10);
+        store.set_labels(3, vec![1, 7]).unwrap();
+        store.set_payload(5, b"doc-5".to_vec()).unwrap();
+
+        assert_eq!(store.labels(3), &[1, 7]);
+        assert!(store.labels(5).is_empty());
+        assert_eq!(store.payload(5), b"doc-5");
+        assert!(store.payload(9).is_empty());
+    }
+
+    #[test]
+    fn labels_are_indexed_and_saved() {
+        let mut store = PointMetadataStore::new(10);
+        store.set_labels(4, vec![2, 1]).unwrap();
+        store.set_labels(1, vec![2]).unwrap();
+        store.set_labels(7, vec![1]).unwrap();
+        assert_eq!(store.points_with_label(2), &[1, 4]);
+
+        // Replacing the labels of a point moves it out of the old postings
+        store.set_labels(4, vec![3]).unwrap();
+        assert_eq!(store.points_with_label(1), &[7]);
+        assert_eq!(store.points_with_label(2), &[1]);
+        assert_eq!(store.points_with_label(3), &[4]);
+
+        let file = "labels_are_indexed_and_saved.labels";
+        store.save_labels(file).unwrap();
+        let mut loaded = PointMetadataStore::new(10);
+        loaded.load_labels(file).unwrap();
+        std::fs::remove_file(file).unwrap();
+
+        for vertex_id in 0..10 {
+            assert_eq!(loaded.labels(vertex_id), store.labels(vertex_id));
+        }
+        assert_eq!(loaded.points_with_label(1), &[7]);
+        assert!(loaded.has_labels());
+        assert!(!PointMetadataStore::new(10).has_labels());
+    }
+
+    #[test]
+    fn payloads_are_saved() {
+        let mut store = PointMetadataStore::new(10);
+        assert!(!store.has_payloads());
+        store.set_payload(2, b"doc-2".to_vec()).unwrap();
+        store.set_payload(6, Vec::new()).unwrap();
+        store.set_payload(7, b"doc-7".to_vec()).unwrap();
+        assert!(store.has_payloads());
+
+        let file = "payloads_are_saved.payloads";
+        store.save_payloads(file).unwrap();
+        let mut loaded = PointMetadataStore::new(10);
+        loaded.set_payload(9, b"stale".to_vec()).unwrap();
+        loaded.load_payloads(file).unwrap();
+        let mut appended = PointMetadataStore::new(10);
+        let too_many = appended.append_payloads(file, 3);
+        appended.append_payloads(file, 2).unwrap();
+        std::fs::remove_file(file).unwrap();
+
+        for vertex_id in 0..10 {
+            assert_eq!(loaded.payload(vertex_id), store.payload(vertex_id));
+        }
+        assert_eq!(appended.payload(9), b"doc-7");
+        assert!(too_many.is_err());
+    }
+
+    #[test]
+    fn out_of_range_is_error() {
+        let mut store = PointMetadataStore::new(10);
+        assert!(store.set_labels(10, vec![1]).is_err());
+        assert!(store.set_payload(11, vec![1]).is_err());
+    }
+}
@@ -0,0 +1,419 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Compressed copies of the vectors compared by the prune step of a build.
+//! The occlusion test of a prune compares every candidate with the neighbors kept before it,
+//! so on large builds it mostly waits on vector reads. An f16 copy halves those reads, SQ8
+//! codes quarter them, and product quantization codes shrink them to one byte per chunk with
+//! the distance between two codes looked up from per chunk tables of centroid to centroid
+//! distances.
+
+use rayon::prelude::{IndexedParallelIterator, ParallelIterator, ParallelSliceMut};
+use vector::{FullPrecisionDistance, Half, Metric};
+
+use crate::common::{ANNError, ANNResult};
+use crate::model::data_store::SQ8Vectors;
+use crate::model::{IndexConfiguration, InmemDataset, PruneQuantization, NUM_PQ_CENTROIDS};
+use crate::utils::{k_means_clustering_with_params, KMeansParams};
+
+/// Most points the PQ centroids are trained on, sampled evenly from the dataset
+const MAX_PQ_TRAINING_POINTS: usize = 32768;
+
+/// Lloyd iterations of each chunk's k-means
+const NUM_KMEANS_REPS: usize = 12;
+
+/// f16 copy of a vector, aligned for the f16 distance kernels
+#[repr(C, align(32))]
+#[derive(Debug)]
+struct HalfVector<const N: usize>([Half; N]);
+
+#[derive(Debug)]
+enum PruneCodes<const N: usize> {
+    Half(Vec<HalfVector<N>>),
+
+    SQ8(SQ8Vectors),
+
+    PQ {
+        /// num_chunks codes per point
+        codes: Vec<u8>,
+        num_chunks: usize,
+        num_centers: usize,
+
+        /// Squared distances between the centroids of each chunk, num_centers * num_centers per chunk
+        tables: Vec<f32>,
+    },
+}
+
+/// Compressed vectors of the points of an index being built
+#[derive(Debug)]
+pub struct QuantizedPruneVectors<const N: usize> {
+    codes: PruneCodes<N>,
+}
+
+/// Check the prune quantization of config applies to its metric and dimension
+pub(crate) fn check_prune_quantization(config: &IndexConfiguration) -> ANNResult<()> {
+    if !(0.0..=1.0).contains(&config.prune_refine_fraction) {
+        return Err(ANNError::log_index_config_error(
+            "prune_refine_fraction".to_string(),
+            format!(
+                "{} is not a fraction between 0 and 1",
+                config.prune_refine_fraction
+            ),
+        ));
+    }
+
+    match config.prune_quantization {
+        PruneQuantization::None => Ok(()),
+        PruneQuantization::Half | PruneQuantization::SQ8 if config.dist_metric.is_binary() => {
+            Err(ANNError::log_index_config_error(
+                "prune_quantization".to_string(),
+                format!(
+                    "{:?} distance can't be computed over {:?} vectors",
+                    config.dist_metric, config.prune_quantization
+                ),
+            ))
+        }
+        PruneQuantization::Half | PruneQuantization::SQ8 => Ok(()),
+        PruneQuantization::PQ { .. } if config.dist_metric != Metric::L2 => {
+            Err(ANNError::log_index_config_error(
+                "prune_quantization".to_string(),
+                format!(
+                    "PQ prune distances are L2 only, the index metric is {:?}",
+                    config.dist_metric
+                ),
+            ))
+        }
+        PruneQuantization::PQ { num_chunks } if num_chunks == 0 || num_chunks > config.dim => {
+            Err(ANNError::log_index_config_error(
+                "prune_quantization".to_string(),
+                format!(
+                    "{} PQ chunks can't split {} dimensions",
+                    num_chunks, config.dim
+                ),
+            ))
+        }
+        PruneQuantization::PQ { .. } => Ok(()),
+    }
+}
+
+impl<const N: usize> QuantizedPruneVectors<N> {
+    /// Compress the first num_points vectors of dataset, whose first dim dimensions are set,
+    /// as quantization says, training PQ centroids with seed or from the OS when it is None.
+    /// None when quantization is PruneQuantization::None.
+    pub fn new<T>(
+        dataset: &InmemDataset<T, N>,
+        num_points: usize,
+        dim: usize,
+        quantization: PruneQuantization,
+        seed: Option<u64>,
+    ) -> ANNResult<Option<Self>>
+    where
+        T: Default + Copy + Sync + Send + Into<f32>,
+        [T; N]: FullPrecisionDistance<T, N>,
+    {
+        let codes = match quantization {
+            PruneQuantization::None => return Ok(None),
+            PruneQuantization::Half => {
+                let mut vectors = Vec::with_capacity(num_points);
+                for id in 0..num_points {
+                    let vector = dataset.get_vertex(id.try_into()?)?.vector();
+                    vectors.push(HalfVector(std::array::from_fn(|d| {
+                        Half::from_f32(vector[d].into())
+                    })));
+                }
+                PruneCodes::Half(vectors)
+            }
+            PruneQuantization::SQ8 => PruneCodes::SQ8(SQ8Vectors::new(dataset, num_points, dim)?),
+            PruneQuantization::PQ { num_chunks } => {
+                Self::product_quantize(dataset, num_points, dim, num_chunks, seed)?
+            }
+        };
+
+        Ok(Some(Self { codes }))
+    }
+
+    /// Train the centroids of each chunk on a sample of the points, then encode every point
+    fn product_quantize<T>(
+        dataset: &InmemDataset<T, N>,
+        num_points: usize,
+        dim: usize,
+        num_chunks: usize,
+        seed: Option<u64>,
+    ) -> ANNResult<PruneCodes<N>>
+    where
+        T: Default + Copy + Sync + Send + Into<f32>,
+        [T; N]: FullPrecisionDistance<T, N>,
+    {
+        if num_points == 0 {
+            return Err(ANNError::log_pq_error(
+                "Error: no points to train the PQ prune distances on.".to_string(),
+            ));
+        }
+
+        let mut data = vec![0.0f32; num_points * dim];
+        for (id, row) in data.chunks_exact_mut(dim).enumerate() {
+            let vector = dataset.get_vertex(id.try_into()?)?.vector();
+            for (value, &element) in row.iter_mut().zip(vector.iter()) {
+                *value = element.into();
+            }
+        }
+
+        // Chunks of dim / num_chunks dimensions, the first dim % num_chunks one larger
+        let mut chunk_offsets = vec![0; num_chunks + 1];
+        for chunk_index in 0..num_chunks {
+            chunk_offsets[chunk_index + 1] = chunk_offsets[chunk_index]
+                + dim / num_chunks
+                + usize::from(chunk_index < dim % num_chunks);
+        }
+
+        let stride = num_points.div_ceil(MAX_PQ_TRAINING_POINTS);
+        let num_train = num_points.div_ceil(stride);
+        let num_centers = NUM_PQ_CENTROIDS.min(num_train);
+
+        let mut pivots = Vec::with_capacity(num_chunks);
+        let mut tables = vec![0.0f32; num_chunks * num_centers * num_centers];
+        for (chunk_index, table) in tables
+            .chunks_exact_mut(num_centers * num_centers)
+            .enumerate()
+        {
+            let chunk = chunk_offsets[chunk_index]..chunk_offsets[chunk_index + 1];
+            let chunk_size = chunk.len();
+
+            let train_data: Vec<f32> = data
+                .chunks_exact(dim)
+                .step_by(stride)
+                .flat_map(|row| row[chunk.clone()].iter().copied())
+                .collect();
+            let mut centers = vec![0.0f32; num_centers * chunk_size];
+            k_means_clustering_with_params(
+                &train_data,
+                num_train,
+                chunk_size,
+                &mut centers,
+                num_centers,
+                KMeansParams::new(NUM_KMEANS_REPS).with_seed(seed),
+            )?;
+
+            for (i, row) in table.chunks_exact_mut(num_centers).enumerate() {
+                let center_i = &centers[i * chunk_size..(i + 1) * chunk_size];
+                for (j, distance) in row.iter_mut().enumerate() {
+                    *distance =
+                        squared_l2(center_i, &centers[j * chunk_size..(j + 1) * chunk_size]);
+                }
+            }
+            pivots.push(centers);
+        }
+
+        let mut codes = vec![0u8; num_points * num_chunks];
+        codes
+            .par_chunks_mut(num_chunks)
+            .enumerate()
+            .for_each(|(id, point_codes)| {
+                let row = &data[id * dim..(id + 1) * dim];
+                for (chunk_index, code) in point_codes.iter_mut().enumerate() {
+                    let sub_vector =
+                        &row[chunk_offsets[chunk_index]..chunk_offsets[chunk_index + 1]];
+                    let closest = pivots[chunk_index]
+                        .chunks_exact(sub_vector.len())
+                        .map(|center| squared_l2(sub_vector, center))
+                        .enumerate()
+                        .fold(
+                            (0, f32::MAX),
+                            |closest, (i, d)| {
+                                if d < closest.1 {
+                                    (i, d)
+                                } else {
+                                    closest
+                                }
+                            },
+                        );
+                    *code = closest.0 as u8;
+                }
+            });
+
+        Ok(PruneCodes::PQ {
+            codes,
+            num_chunks,
+            num_centers,
+            tables,
+        })
+    }
+
+    /// Approximate distance between points a and b
+    #[inline(always)]
+    pub fn distance(&self, a: u32, b: u32, metric: Metric) -> f32 {
+        match &self.codes {
+            PruneCodes::Half(vectors) => <[Half; N]>::distance_compare(
+                &vectors[a as usize].0,
+                &vectors[b as usize].0,
+                metric,
+            ),
+            PruneCodes::SQ8(vectors) => vectors.distance(a, b, metric),
+            PruneCodes::PQ {
+                codes,
+                num_chunks,
+                num_centers,
+                tables,
+            } => {
+                let codes_a = &codes[a as usize * num_chunks..(a as usize + 1) * num_chunks];
+                let codes_b = &codes[b as usize * num_chunks..(b as usize + 1) * num_chunks];
+                codes_a
+                    .iter()
+                    .zip(codes_b)
+                    .enumerate()
+                    .map(|(chunk_index, (&code_a, &code_b))| {
+                        tables[(chunk_index * num_centers + code_a as usize) * num_centers
+                            + code_b as usize]
+                    })
+                    .sum()
+            }
+        }
+    }
+
+    /// Position and approximate distance of the candidate closest to point a, the first one
+    /// on ties. None if there are no candidates.
+    pub fn argmin(&self, a: u32, candidates: &[u32], metric: Metric) -> Option<(usize, f32)> {
+        candidates
+            .iter()
+            .map(|&b| self.distance(a, b, metric))
+            .enumerate()
+            .fold(None, |closest, (i, d)| match closest {
+                Some((_, best)) if best <= d => closest,
+                _ => Some((i, d)),
+            })
+    }
+
+    /// Bytes held by the compressed vectors
+    pub fn memory_bytes(&self) -> usize {
+        match &self.codes {
+            PruneCodes::Half(vectors) => std::mem::size_of_val(vectors.as_slice()),
+            PruneCodes::SQ8(vectors) => vectors.memory_bytes(),
+            PruneCodes::PQ { codes, tables, .. } => {
+                codes.len() + std::mem::size_of_val(tables.as_slice())
+            }
+        }
+    }
+}
+
+fn squared_l2(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum()
+}
+
+#[cfg(test)]
+mod quantized_prune_vectors_test {
+    use vector::BuiltinDistance;
+    use vector::Distance;
+
+    use super::*;
+    use crate::model::IndexWriteParametersBuilder;
+    use crate::test_utils::get_test_file_path;
+
+    const TEST_DATA_FILE: &str = "tests/data/siftsmall_learn_256pts.fbin";
+
+    fn load_dataset() -> InmemDataset<f32, 128> {
+        let mut dataset = InmemDataset::<f32, 128>::new(256, 1.0).unwrap();
+        dataset
+            .build_from_file(get_test_file_path(TEST_DATA_FILE).as_str(), 256)
+            .unwrap();
+        dataset
+    }
+
+    #[test]
+    fn compressed_distances_track_full_precision() {
+        let dataset = load_dataset();
+        let exact = |a: u32, b: u32| {
+            BuiltinDistance.distance(
+                dataset.get_vertex(a).unwrap().vector(),
+                dataset.get_vertex(b).unwrap().vector(),
+                Metric::L2,
+            )
+        };
+
+        let half = QuantizedPruneVectors::new(&dataset, 256, 128, PruneQuantization::Half, None)
+            .unwrap()
+            .unwrap();
+        let pq = QuantizedPruneVectors::new(
+            &dataset,
+            256,
+            128,
+            PruneQuantization::PQ { num_chunks: 32 },
+            None,
+        )
+        .unwrap()
+        .unwrap();
+        let sq8 = QuantizedPruneVectors::new(&dataset, 256, 128, PruneQuantization::SQ8, None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(half.memory_bytes(), 256 * 128 * 2);
+        assert!(sq8.memory_bytes() < half.memory_bytes());
+
+        for (a, b) in [(0, 1), (3, 200), (17, 42), (100, 255)] {
+            let distance = exact(a, b);
+            assert!((half.distance(a, b, Metric::L2) - distance).abs() <= distance * 1e-3);
+            assert!((sq8.distance(a, b, Metric::L2) - distance).abs() <= distance * 0.05);
+            assert!((pq.distance(a, b, Metric::L2) - distance).abs() <= distance * 0.5);
+        }
+
+        // The closest of a few candidates is mostly the same one
+        let candidates: Vec<u32> = (1..64).collect();
+        let (closest, _) = half.argmin(0, &candidates, Metric::L2).unwrap();
+        let exact_closest = candidates
+            .iter()
+            .map(|&b| exact(0, b))
+            .enumerate()
+            .fold((0, f32::MAX), |c, (i, d)| if d < c.1 { (i, d) } else { c });
+        assert_eq!(closest, exact_closest.0);
+        assert!(half.argmin(0, &[], Metric::L2).is_none());
+
+        assert!(
+            QuantizedPruneVectors::new(&dataset, 256, 128, PruneQuantization::None, None)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn checks_quantization_against_metric() {
+        let config = |metric, quantization, fraction| {
+            IndexConfiguration::new(
+                metric,
+                128,
+                128,
+                256,
+                false,
+                0,
+                false,
+                0,
+                1.0,
+                IndexWriteParametersBuilder::new(50, 4).build(),
+            )
+            .with_prune_quantization(quantization, fraction)
+        };
+
+        let pq = PruneQuantization::PQ { num_chunks: 16 };
+        assert!(check_prune_quantization(&config(Metric::L2, pq, 0.1)).is_ok());
+        assert!(check_prune_quantization(&config(Metric::Cosine, pq, 0.1)).is_err());
+        assert!(check_prune_quantization(&config(Metric::L2, pq, 1.5)).is_err());
+        assert!(check_prune_quantization(&config(
+            Metric::L2,
+            PruneQuantization::PQ { num_chunks: 129 },
+            0.0
+        ))
+        .is_err());
+        assert!(
+            check_prune_quantization(&config(Metric::Cosine, PruneQuantization::Half, 0.0)).is_ok()
+        );
+        assert!(
+            check_prune_quantization(&config(Metric::Hamming, PruneQuantization::Half, 0.0))
+                .is_err()
+        );
+        assert!(check_prune_quantization(&config(Metric::L1, PruneQuantization::SQ8, 0.0)).is_ok());
+        assert!(
+            check_prune_quantization(&config(Metric::Tanimoto, PruneQuantization::SQ8, 0.0))
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,271 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Sparse f32 vectors in CSR form, for hybrid sparse-dense retrieval.
+//! Row i holds the non-zeros indptr[i]..indptr[i + 1] of indices and values, and the indices
+//! of a row are strictly increasing so rows can be merged by the sparse kernels.
+//! The file format is the one of the big-ann-benchmarks sparse track: nrow, ncol and nnz as
+//! i64, then indptr as nrow + 1 i64, indices as nnz i32 and values as nnz f32.
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+
+use vector::{sparse_dense_dot, sparse_dot, sparse_l2};
+
+use crate::common::{ANNError, ANNResult};
+
+/// A sparse vector borrowed from a dataset or built by the caller
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SparseVector<'a> {
+    /// Dimensions of the non-zeros, strictly increasing
+    pub indices: &'a [u32],
+
+    /// Values of the non-zeros
+    pub values: &'a [f32],
+}
+
+impl<'a> SparseVector<'a> {
+    /// Check the indices are strictly increasing and match the values one to one
+    pub fn new(indices: &'a [u32], values: &'a [f32]) -> ANNResult<Self> {
+        if indices.len() != values.len() {
+            return Err(ANNError::log_index_error(format!(
+                "Sparse vector has {} indices but {} values",
+                indices.len(),
+                values.len()
+            )));
+        }
+
+        if indices.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err(ANNError::log_index_error(
+                "Sparse vector indices must be strictly increasing".to_string(),
+            ));
+        }
+
+        Ok(Self { indices, values })
+    }
+
+    /// Number of non-zeros
+    pub fn nnz(&self) -> usize {
+        self.indices.len()
+    }
+
+    /// Dot product with another sparse vector
+    pub fn dot(&self, other: &SparseVector) -> f32 {
+        sparse_dot(self.indices, self.values, other.indices, other.values)
+    }
+
+    /// Squared L2 distance to another sparse vector
+    pub fn distance_l2(&self, other: &SparseVector) -> f32 {
+        sparse_l2(self.indices, self.values, other.indices, other.values)
+    }
+
+    /// Dot product with a dense vector covering every index of this one
+    pub fn dot_dense(&self, dense: &[f32]) -> ANNResult<f32> {
+        if let Some(last) = self.indices.last() {
+            if *last as usize >= dense.len() {
+                return Err(ANNError::log_index_error(format!(
+                    "Sparse index {} is out of range of a dense vector of dimension {}",
+                    last,
+                    dense.len()
+                )));
+            }
+        }
+
+        Ok(sparse_dense_dot(self.indices, self.values, dense))
+    }
+}
+
+/// Sparse vectors stored row by row in CSR form
+#[derive(Debug, Clone, PartialEq)]
+pub struct SparseDataset {
+    dim: usize,
+    indptr: Vec<usize>,
+    indices: Vec<u32>,
+    values: Vec<f32>,
+}
+
+impl SparseDataset {
+    /// Create an empty dataset of dimension dim
+    pub fn new(dim: usize) -> Self {
+        Self {
+            dim,
+            indptr: vec![0],
+            indices: Vec::new(),
+            values: Vec::new(),
+        }
+    }
+
+    /// Append a row, checking its indices are within the dimension
+    pub fn push(&mut self, row: SparseVector) -> ANNResult<u32> {
+        if let Some(last) = row.indices.last() {
+            if *last as usize >= self.dim {
+                return Err(ANNError::log_index_error(format!(
+                    "Sparse index {} is out of range of dimension {}",
+                    last, self.dim
+                )));
+            }
+        }
+
+        let id = self.num_points() as u32;
+        self.indices.extend_from_slice(row.indices);
+        self.values.extend_from_slice(row.values);
+        self.indptr.push(self.indices.len());
+        Ok(id)
+    }
+
+    /// Row of a point
+    pub fn get(&self, id: u32) -> Option<SparseVector<'_>> {
+        let id = id as usize;
+        if id >= self.num_points() {
+            return None;
+        }
+
+        let range = self.indptr[id]..self.indptr[id + 1];
+        Some(SparseVector {
+            indices: &self.indices[range.clone()],
+            values: &self.values[range],
+        })
+    }
+
+    /// Number of rows
+    pub fn num_points(&self) -> usize {
+        self.indptr.len() - 1
+    }
+
+    /// Dimension of the rows
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// Total number of non-zeros
+    pub fn nnz(&self) -> usize {
+        self.indices.len()
+    }
+
+    /// Ids and dot products of the k rows with the largest dot product with the query,
+    /// largest first, by scanning every row
+    pub fn top_k_by_dot(&self, query: &SparseVector, k: usize) -> Vec<(u32, f32)> {
+        let mut scored: Vec<(u32, f32)> = (0..self.num_points() as u32)
+            .filter_map(|id| self.get(id).map(|row| (id, row.dot(query))))
+            .collect();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        scored.truncate(k);
+        scored
+    }
+
+    /// Load a CSR file, validating every row
+    pub fn load(filename: &str) -> ANNResult<Self> {
+        let mut reader = BufReader::new(File::open(filename)?);
+        let nrow = reader.read_i64::<LittleEndian>()? as usize;
+        let ncol = reader.read_i64::<LittleEndian>()? as usize;
+        let nnz = reader.read_i64::<LittleEndian>()? as usize;
+
+        let mut indptr = vec![0i64; nrow + 1];
+        reader.read_i64_into::<LittleEndian>(&mut indptr)?;
+        let mut indices = vec![0u32; nnz];
+        reader.read_u32_into::<LittleEndian>(&mut indices)?;
+        let mut values = vec![0f32; nnz];
+        reader.read_f32_into::<LittleEndian>(&mut values)?;
+
+        let indptr: Vec<usize> = indptr.into_iter().map(|offset| offset as usize).collect();
+        if indptr[0] != 0 || indptr[nrow] != nnz || indptr.windows(2).any(|pair| pair[0] > pair[1])
+        {
+            return Err(ANNError::log_index_error(format!(
+                "Row offsets of {} don't cover its {} non-zeros",
+                filename, nnz
+            )));
+        }
+
+        let dataset = Self {
+            dim: ncol,
+            indptr,
+            indices,
+            values,
+        };
+        for id in 0..nrow {
+            let range = dataset.indptr[id]..dataset.indptr[id + 1];
+            let row = SparseVector::new(&dataset.indices[range.clone()], &dataset.values[range])?;
+            if row
+                .indices
+                .last()
+                .is_some_and(|last| *last as usize >= ncol)
+            {
+                return Err(ANNError::log_index_error(format!(
+                    "Row {} of {} has an index beyond dimension {}",
+                    id, filename, ncol
+                )));
+            }
+        }
+
+        Ok(dataset)
+    }
+
+    /// Save in the format read by load
+    pub fn save(&self, filename: &str) -> ANNResult<()> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        writer.write_i64::<LittleEndian>(self.num_points() as i64)?;
+        writer.write_i64::<LittleEndian>(self.dim as i64)?;
+        writer.write_i64::<LittleEndian>(self.nnz() as i64)?;
+        for offset in &self.indptr {
+            writer.write_i64::<LittleEndian>(*offset as i64)?;
+        }
+        for idx in &self.indices {
+            writer.write_u32::<LittleEndian>(*idx)?;
+        }
+        for value in &self.values {
+            writer.write_f32::<LittleEndian>(*value)?;
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod sparse_dataset_test {
+    use std::fs;
+
+    use approx::assert_abs_diff_eq;
+
+    use super::*;
+
+    #[test]
+    fn push_search_and_round_trip() {
+        let mut dataset = SparseDataset::new(10);
+        let rows: [(&[u32], &[f32]); 3] = [
+            (&[0, 4, 9], &[1.0, 2.0, 3.0]),
+            (&[], &[]),
+            (&[4, 5], &[-1.0, 5.0]),
+        ];
+        for (indices, values) in rows {
+            dataset
+                .push(SparseVector::new(indices, values).unwrap())
+                .unwrap();
+        }
+        assert_eq!(dataset.num_points(), 3);
+        assert_eq!(dataset.nnz(), 5);
+        assert!(dataset.get(3).is_none());
+        assert!(dataset
+            .push(SparseVector::new(&[10], &[1.0]).unwrap())
+            .is_err());
+        assert!(SparseVector::new(&[3, 3], &[1.0, 1.0]).is_err());
+        assert!(SparseVector::new(&[3], &[]).is_err());
+
+        let query = SparseVector::new(&[4, 5], &[1.0, 1.0]).unwrap();
+        assert_eq!(dataset.top_k_by_dot(&query, 2), vec![(2, 4.0), (0, 2.0)]);
+        let row0 = dataset.get(0).unwrap();
+        assert_abs_diff_eq!(row0.distance_l2(&query), 1.0 + 1.0 + 1.0 + 9.0);
+        let dense: Vec<f32> = (0..10).map(|i| i as f32).collect();
+        assert_eq!(row0.dot_dense(&dense).unwrap(), 8.0 + 27.0);
+        assert!(row0.dot_dense(&dense[..9]).is_err());
+
+        let file = "sparse_dataset_test.csr";
+        dataset.save(file).unwrap();
+        assert_eq!(SparseDataset::load(file).unwrap(), dataset);
+        fs::remove_file(file).unwrap();
+    }
+}
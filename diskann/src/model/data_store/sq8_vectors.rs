@@ -0,0 +1,333 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Scalar quantization to one byte per dimension.
+//! Each dimension d is mapped from [min_d, max_d] onto 0..=255 with its own step
+//! scale_d = (max_d - min_d) / 255, so a value x is stored as round((x - min_d) / scale_d).
+//! Unlike PQ there is nothing to train but the ranges, and two codes are compared directly:
+//! the difference of two values is scale_d * (code_a - code_b), whatever min_d is.
+
+use rayon::prelude::{IndexedParallelIterator, ParallelIterator, ParallelSliceMut};
+use vector::{FullPrecisionDistance, Metric};
+
+use crate::common::{ANNError, ANNResult};
+use crate::model::InmemDataset;
+
+/// Largest code of a dimension
+const MAX_CODE: f32 = u8::MAX as f32;
+
+/// Per dimension ranges mapping values to u8 codes and back
+#[derive(Debug, Clone, PartialEq)]
+pub struct SQ8Codec {
+    /// Value of code 0 of each dimension
+    min: Vec<f32>,
+
+    /// Value of one code step of each dimension, 0 for constant dimensions
+    scale: Vec<f32>,
+}
+
+impl SQ8Codec {
+    /// Fit the ranges to num_points vectors of dim values stored one after another in data
+    pub fn train(data: &[f32], num_points: usize, dim: usize) -> ANNResult<Self> {
+        if num_points == 0 || dim == 0 || data.len() < num_points * dim {
+            return Err(ANNError::log_index_error(format!(
+                "Error: can't fit SQ8 ranges to {} points of {} dimensions from {} values.",
+                num_points,
+                dim,
+                data.len()
+            )));
+        }
+
+        let mut min = vec![f32::MAX; dim];
+        let mut max = vec![f32::MIN; dim];
+        for row in data[..num_points * dim].chunks_exact(dim) {
+            for (d, &value) in row.iter().enumerate() {
+                if !value.is_finite() {
+                    return Err(ANNError::log_index_error(format!(
+                        "Error: can't quantize the non finite value {} of dimension {}.",
+                        value, d
+                    )));
+                }
+                min[d] = min[d].min(value);
+                max[d] = max[d].max(value);
+            }
+        }
+
+        let scale = min
+            .iter()
+            .zip(&max)
+            .map(|(min, max)| (max - min) / MAX_CODE)
+            .collect();
+        Ok(Self { min, scale })
+    }
+
+    /// Number of dimensions of the vectors
+    pub fn dim(&self) -> usize {
+        self.min.len()
+    }
+
+    /// Value of one code step of each dimension
+    pub fn scale(&self) -> &[f32] {
+        &self.scale
+    }
+
+    /// Codes of vector, values outside the trained ranges are clamped to them
+    pub fn encode(&self, vector: &[f32], codes: &mut [u8]) {
+        for (((code, &value), min), scale) in
+            codes.iter_mut().zip(vector).zip(&self.min).zip(&self.scale)
+        {
+            *code = if *scale > 0.0 {
+                ((value - min) / scale).round().clamp(0.0, MAX_CODE) as u8
+            } else {
+                0
+            };
+        }
+    }
+
+    /// Values the codes stand for
+    pub fn decode(&self, codes: &[u8], vector: &mut [f32]) {
+        for (((value, &code), min), scale) in
+            vector.iter_mut().zip(codes).zip(&self.min).zip(&self.scale)
+        {
+            *value = min + scale * code as f32;
+        }
+    }
+}
+
+/// SQ8 codes of the points of an index, with the distance kernels working on them
+#[derive(Debug)]
+pub struct SQ8Vectors {
+    codec: SQ8Codec,
+
+    /// dim codes per point
+    codes: Vec<u8>,
+
+    /// scale_d squared, the weight of a squared code difference under L2
+    squared_scale: Vec<f32>,
+}
+
+impl SQ8Vectors {
+    /// Fit the ranges to the first num_points vectors of dataset, whose first dim dimensions
+    /// are set, and encode them
+    pub fn new<T, const N: usize>(
+        dataset: &InmemDataset<T, N>,
+        num_points: usize,
+        dim: usize,
+    ) -> ANNResult<Self>
+    where
+        T: Default + Copy + Sync + Send + Into<f32>,
+        [T; N]: FullPrecisionDistance<T, N>,
+    {
+        let mut data = vec![0.0f32; num_points * dim];
+        for (id, row) in data.chunks_exact_mut(dim).enumerate() {
+            let vector = dataset.get_vertex(id.try_into()?)?.vector();
+            for (value, &element) in row.iter_mut().zip(vector.iter()) {
+                *value = element.into();
+            }
+        }
+
+        let codec = SQ8Codec::train(&data, num_points, dim)?;
+        let mut codes = vec![0u8; num_points * dim];
+        codes
+            .par_chunks_mut(dim)
+            .enumerate()
+            .for_each(|(id, point_codes)| {
+                codec.encode(&data[id * dim..(id + 1) * dim], point_codes)
+            });
+
+        Ok(Self::from_codes(codec, codes))
+    }
+
+    /// Store codes encoded with codec, dim of them per point
+    pub fn from_codes(codec: SQ8Codec, codes: Vec<u8>) -> Self {
+        let squared_scale = codec.scale().iter().map(|scale| scale * scale).collect();
+        Self {
+            codec,
+            codes,
+            squared_scale,
+        }
+    }
+
+    /// Codec the points are encoded with
+    pub fn codec(&self) -> &SQ8Codec {
+        &self.codec
+    }
+
+    /// Number of points stored
+    pub fn len(&self) -> usize {
+        self.codes.len() / self.codec.dim()
+    }
+
+    /// Whether no points are stored
+    pub fn is_empty(&self) -> bool {
+        self.codes.is_empty()
+    }
+
+    /// Codes of point id
+    #[inline(always)]
+    pub fn codes(&self, id: u32) -> &[u8] {
+        let dim = self.codec.dim();
+        &self.codes[id as usize * dim..(id as usize + 1) * dim]
+    }
+
+    /// Approximate distance between points a and b under metric, which can't be binary
+    #[inline(always)]
+    pub fn distance(&self, a: u32, b: u32, metric: Metric) -> f32 {
+        let (codes_a, codes_b) = (self.codes(a), self.codes(b));
+        match metric {
+            Metric::L2 => codes_a
+                .iter()
+                .zip(codes_b)
+                .zip(&self.squared_scale)
+                .map(|((&x, &y), weight)| {
+                    let diff = x as f32 - y as f32;
+                    weight * diff * diff
+                })
+                .sum(),
+            Metric::L1 => codes_a
+                .iter()
+                .zip(codes_b)
+                .zip(self.codec.scale())
+                .map(|((&x, &y), scale)| scale * x.abs_diff(y) as f32)
+                .sum(),
+            Metric::Chebyshev => codes_a
+                .iter()
+                .zip(codes_b)
+                .zip(self.codec.scale())
+                .map(|((&x, &y), scale)| scale * x.abs_diff(y) as f32)
+                .fold(0.0, f32::max),
+            _ => self.decoded_distance(codes_a, codes_b, metric),
+        }
+    }
+
+    /// Approximate distance between a full precision query and point id under metric
+    pub fn distance_to_query(&self, query: &[f32], id: u32, metric: Metric) -> f32 {
+        let mut vector = vec![0.0f32; self.codec.dim()];
+        self.codec.decode(self.codes(id), &mut vector);
+        distance_f32(query, &vector, metric)
+    }
+
+    /// Position and approximate distance of the candidate closest to point a, the first one
+    /// on ties. None if there are no candidates.
+    pub fn argmin(&self, a: u32, candidates: &[u32], metric: Metric) -> Option<(usize, f32)> {
+        candidates
+            .iter()
+            .map(|&b| self.distance(a, b, metric))
+            .enumerate()
+            .fold(None, |closest, (i, d)| match closest {
+                Some((_, best)) if best <= d => closest,
+                _ => Some((i, d)),
+            })
+    }
+
+    /// Bytes held by the codes and ranges
+    pub fn memory_bytes(&self) -> usize {
+        self.codes.len() + 3 * std::mem::size_of_val(self.squared_scale.as_slice())
+    }
+
+    fn decoded_distance(&self, codes_a: &[u8], codes_b: &[u8], metric: Metric) -> f32 {
+        let mut a = vec![0.0f32; self.codec.dim()];
+        let mut b = vec![0.0f32; self.codec.dim()];
+        self.codec.decode(codes_a, &mut a);
+        self.codec.decode(codes_b, &mut b);
+        distance_f32(&a, &b, metric)
+    }
+}
+
+/// Distance between two f32 slices, for the metrics without a kernel over codes
+fn distance_f32(a: &[f32], b: &[f32], metric: Metric) -> f32 {
+    let pairs = a.iter().zip(b);
+    match metric {
+        Metric::L2 => pairs.map(|(x, y)| (x - y) * (x - y)).sum(),
+        Metric::L1 => pairs.map(|(x, y)| (x - y).abs()).sum(),
+        Metric::Chebyshev => pairs.map(|(x, y)| (x - y).abs()).fold(0.0, f32::max),
+        Metric::Cosine => {
+            let (dot, norm_a, norm_b) = pairs.fold((0.0, 0.0, 0.0), |(dot, na, nb), (x, y)| {
+                (dot + x * y, na + x * x, nb + y * y)
+            });
+            if norm_a == 0.0 || norm_b == 0.0 {
+                1.0
+            } else {
+                1.0 - dot / (norm_a * norm_b).sqrt()
+            }
+        }
+        Metric::Hamming | Metric::Tanimoto => f32::MAX,
+    }
+}
+
+#[cfg(test)]
+mod sq8_vectors_test {
+    use vector::{BuiltinDistance, Distance};
+
+    use super::*;
+    use crate::test_utils::get_test_file_path;
+
+    const TEST_DATA_FILE: &str = "tests/data/siftsmall_learn_256pts.fbin";
+
+    #[test]
+    fn codec_round_trips_within_half_a_step() {
+        let data = [0.0, -1.0, 5.0, 1.0, 1.0, 5.0, 0.5, 0.25, 5.0];
+        let codec = SQ8Codec::train(&data, 3, 3).unwrap();
+        assert_eq!(codec.dim(), 3);
+        assert_eq!(codec.scale()[2], 0.0);
+
+        let mut codes = [0u8; 3];
+        let mut decoded = [0.0f32; 3];
+        for row in data.chunks_exact(3) {
+            codec.encode(row, &mut codes);
+            codec.decode(&codes, &mut decoded);
+            for ((value, decoded), scale) in row.iter().zip(&decoded).zip(codec.scale()) {
+                assert!((value - decoded).abs() <= scale / 2.0 + 1e-6);
+            }
+        }
+
+        // Out of range values are clamped to the ends of the range
+        codec.encode(&[2.0, -3.0, 5.0], &mut codes);
+        assert_eq!(codes, [255, 0, 0]);
+
+        assert!(SQ8Codec::train(&data, 0, 3).is_err());
+        assert!(SQ8Codec::train(&[f32::NAN, 0.0], 1, 2).is_err());
+    }
+
+    #[test]
+    fn code_distances_track_full_precision() {
+        let mut dataset = InmemDataset::<f32, 128>::new(256, 1.0).unwrap();
+        dataset
+            .build_from_file(get_test_file_path(TEST_DATA_FILE).as_str(), 256)
+            .unwrap();
+        let vectors = SQ8Vectors::new(&dataset, 256, 128).unwrap();
+        assert_eq!(vectors.len(), 256);
+        assert!(vectors.memory_bytes() < 256 * 128 * 4 / 3);
+
+        for metric in [Metric::L2, Metric::L1, Metric::Chebyshev, Metric::Cosine] {
+            for (a, b) in [(0, 1), (3, 200), (17, 42), (100, 255)] {
+                let vector_a = dataset.get_vertex(a).unwrap();
+                let vector_b = dataset.get_vertex(b).unwrap();
+                let exact = BuiltinDistance.distance(vector_a.vector(), vector_b.vector(), metric);
+                let tolerance = exact * 0.05 + 1e-3;
+                assert!((vectors.distance(a, b, metric) - exact).abs() <= tolerance);
+                assert!(
+                    (vectors.distance_to_query(vector_a.vector(), b, metric) - exact).abs()
+                        <= tolerance
+                );
+            }
+        }
+
+        let candidates: Vec<u32> = (1..64).collect();
+        let (closest, _) = vectors.argmin(0, &candidates, Metric::L2).unwrap();
+        let exact_closest = (1..64u32)
+            .map(|b| {
+                BuiltinDistance.distance(
+                    dataset.get_vertex(0).unwrap().vector(),
+                    dataset.get_vertex(b).unwrap().vector(),
+                    Metric::L2,
+                )
+            })
+            .enumerate()
+            .fold((0, f32::MAX), |c, (i, d)| if d < c.1 { (i, d) } else { c });
+        assert_eq!(closest, exact_closest.0);
+    }
+}
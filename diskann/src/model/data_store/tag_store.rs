@@ -0,0 +1,259 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Mapping between the tags users know their points by and the vertex ids of the index
+
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use hashbrown::HashMap;
+
+use crate::common::{ANNError, ANNResult};
+
+const NO_TAG: u8 = 0;
+const ID_TAG: u8 = 1;
+const NAME_TAG: u8 = 2;
+
+/// External identifier of a point
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Tag {
+    /// Numeric id, such as a database key
+    Id(u64),
+
+    /// String id, such as a document name
+    Name(String),
+}
+
+impl From<u64> for Tag {
+    fn from(id: u64) -> Self {
+        Tag::Id(id)
+    }
+}
+
+impl From<&str> for Tag {
+    fn from(name: &str) -> Self {
+        Tag::Name(name.to_string())
+    }
+}
+
+impl From<String> for Tag {
+    fn from(name: String) -> Self {
+        Tag::Name(name)
+    }
+}
+
+impl fmt::Display for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Tag::Id(id) => write!(f, "{}", id),
+            Tag::Name(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// Tag of every point, indexed by vertex id, and the point of every tag.
+/// Each tag belongs to at most one point.
+#[derive(Debug, Default)]
+pub struct TagStore {
+    tags: Vec<Option<Tag>>,
+    vertex_ids: HashMap<Tag, u32>,
+    capacity: usize,
+}
+
+impl TagStore {
+    /// Create a store able to hold tags for `capacity` points
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            tags: Vec::new(),
+            vertex_ids: HashMap::new(),
+            capacity,
+        }
+    }
+
+    /// Tag a point, replacing its existing tag. Fails if another point has the tag.
+    pub fn set_tag(&mut self, vertex_id: u32, tag: Tag) -> ANNResult<()> {
+        let idx = vertex_id as usize;
+        if idx >= self.capacity {
+            return Err(ANNError::log_index_error(format!(
+                "vertex_id {} is out of valid range of points {}",
+                vertex_id, self.capacity
+            )));
+        }
+        match self.vertex_ids.get(&tag) {
+            Some(&owner) if owner == vertex_id => return Ok(()),
+            Some(&owner) => {
+                return Err(ANNError::log_index_error(format!(
+                    "Tag {} already belongs to point {}",
+                    tag, owner
+                )))
+            }
+            None => {}
+        }
+
+        self.remove_tag(vertex_id);
+        if self.tags.len() <= idx {
+            self.tags.resize(idx + 1, None);
+        }
+        self.vertex_ids.insert(tag.clone(), vertex_id);
+        self.tags[idx] = Some(tag);
+        Ok(())
+    }
+
+    /// Untag a point, returning the tag it had
+    pub fn remove_tag(&mut self, vertex_id: u32) -> Option<Tag> {
+        let tag = self.tags.get_mut(vertex_id as usize)?.take()?;
+        self.vertex_ids.remove(&tag);
+        Some(tag)
+    }
+
+    /// Tag of a point, None if it has none
+    pub fn tag(&self, vertex_id: u32) -> Option<&Tag> {
+        self.tags.get(vertex_id as usize)?.as_ref()
+    }
+
+    /// Point with a tag, None if no point has it
+    pub fn vertex_id(&self, tag: &Tag) -> Option<u32> {
+        self.vertex_ids.get(tag).copied()
+    }
+
+    /// Number of tagged points
+    pub fn len(&self) -> usize {
+        self.vertex_ids.len()
+    }
+
+    /// Whether no point is tagged
+    pub fn is_empty(&self) -> bool {
+        self.vertex_ids.is_empty()
+    }
+
+    /// Save the tag of every point: the number of points as a little endian u64, then the
+    /// tag of each point as write_tag writes it
+    pub fn save(&self, filename: &str) -> ANNResult<()> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        writer.write_u64::<LittleEndian>(self.tags.len() as u64)?;
+        for tag in self.tags.iter() {
+            write_tag(&mut writer, tag.as_ref())?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Replace the tags with the ones saved by save
+    pub fn load(&mut self, filename: &str) -> ANNResult<()> {
+        self.tags.clear();
+        self.vertex_ids.clear();
+        self.append(filename, 0)
+    }
+
+    /// Tag the points from id_offset on with the tags saved by save
+    pub fn append(&mut self, filename: &str, id_offset: u32) -> ANNResult<()> {
+        let mut reader = BufReader::new(File::open(filename)?);
+        let num_points = reader.read_u64::<LittleEndian>()? as usize;
+        if id_offset as usize + num_points > self.capacity {
+            return Err(ANNError::log_index_error(format!(
+                "Tags file {} has {} points, more than the {} the index holds from point {}",
+                filename,
+                num_points,
+                self.capacity.saturating_sub(id_offset as usize),
+                id_offset
+            )));
+        }
+
+        for vertex_id in id_offset..id_offset + num_points as u32 {
+            if let Some(tag) = read_tag(&mut reader, filename)? {
+                self.set_tag(vertex_id, tag)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Write an optional tag as its kind byte (0 untagged, 1 numeric, 2 string) followed by the
+/// u64 id or by the u32 length and UTF-8 bytes of the name, all little endian
+pub(crate) fn write_tag(writer: &mut impl Write, tag: Option<&Tag>) -> ANNResult<()> {
+    match tag {
+        None => writer.write_u8(NO_TAG)?,
+        Some(Tag::Id(id)) => {
+            writer.write_u8(ID_TAG)?;
+            writer.write_u64::<LittleEndian>(*id)?;
+        }
+        Some(Tag::Name(name)) => {
+            writer.write_u8(NAME_TAG)?;
+            writer.write_u32::<LittleEndian>(name.len() as u32)?;
+            writer.write_all(name.as_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+/// Read an optional tag written by write_tag to the file of filename
+pub(crate) fn read_tag(reader: &mut impl Read, filename: &str) -> ANNResult<Option<Tag>> {
+    let tag = match reader.read_u8()? {
+        NO_TAG => return Ok(None),
+        ID_TAG => Tag::Id(reader.read_u64::<LittleEndian>()?),
+        NAME_TAG => {
+            let len = reader.read_u32::<LittleEndian>()? as usize;
+            let mut bytes = vec![0u8; len];
+            reader.read_exact(&mut bytes)?;
+            Tag::Name(String::from_utf8(bytes).map_err(|err| {
+                ANNError::log_index_error(format!(
+                    "File {} has a tag name that isn't UTF-8: {}",
+                    filename, err
+                ))
+            })?)
+        }
+        kind => {
+            return Err(ANNError::log_index_error(format!(
+                "File {} has a tag of unknown kind {}",
+                filename, kind
+            )))
+        }
+    };
+    Ok(Some(tag))
+}
+
+#[cfg(test)]
+mod tag_store_test {
+    use super::*;
+
+    #[test]
+    fn tags_map_both_ways_and_are_saved() {
+        let mut store = TagStore::new(10);
+        store.set_tag(2, Tag::Id(42)).unwrap();
+        store.set_tag(5, "doc-5".into()).unwrap();
+        assert_eq!(store.tag(2), Some(&Tag::Id(42)));
+        assert_eq!(store.vertex_id(&"doc-5".into()), Some(5));
+        assert_eq!(store.tag(3), None);
+
+        // A tag belongs to one point, and retagging a point frees its old tag
+        assert!(store.set_tag(7, Tag::Id(42)).is_err());
+        store.set_tag(2, Tag::Id(43)).unwrap();
+        assert_eq!(store.vertex_id(&Tag::Id(42)), None);
+        store.set_tag(7, Tag::Id(42)).unwrap();
+        assert_eq!(store.len(), 3);
+
+        let file = "tags_map_both_ways_and_are_saved.tags";
+        store.save(file).unwrap();
+        let mut loaded = TagStore::new(10);
+        loaded.load(file).unwrap();
+        let mut shifted = TagStore::new(20);
+        shifted.append(file, 10).unwrap();
+        std::fs::remove_file(file).unwrap();
+
+        for vertex_id in 0..10 {
+            assert_eq!(loaded.tag(vertex_id), store.tag(vertex_id));
+            assert_eq!(shifted.tag(vertex_id + 10), store.tag(vertex_id));
+        }
+        assert_eq!(loaded.vertex_id(&"doc-5".into()), Some(5));
+        assert_eq!(shifted.vertex_id(&Tag::Id(42)), Some(17));
+
+        assert_eq!(loaded.remove_tag(5), Some("doc-5".into()));
+        assert_eq!(loaded.vertex_id(&"doc-5".into()), None);
+        assert!(loaded.set_tag(10, Tag::Id(1)).is_err());
+    }
+}
@@ -0,0 +1,379 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Read-only in-memory graph with every adjacency list packed into one arena.
+//! Vertices are grouped in blocks of 2^16; each block has a u64 base into the arena and each
+//! vertex a u32 offset from its block base, so the arena can hold more than 2^32 edges while
+//! the per-vertex overhead stays at 4 bytes, instead of a lock and a Vec with slack per vertex.
+//!
+//! The arena can be saved as a CSR file holding these arrays as they are in memory, and the
+//! file memory-mapped back without parsing. The layout is little-endian:
+//! a 64 byte header (magic, version, start, max observed degree, number of frozen points,
+//! vertices, edges and blocks), then the block bases, the offsets and the edges.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::ops::Range;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::common::{ANNError, ANNResult};
+use crate::utils::MmapFile;
+
+use super::{AdjacencyList, InMemoryGraph, VertexAndNeighbors};
+
+/// log2 of the number of vertices sharing a block base
+const BLOCK_BITS: usize = 16;
+
+/// "DANNCSR" followed by a zero byte, read as a little-endian u64
+const CSR_MAGIC: u64 = u64::from_le_bytes(*b"DANNCSR\0");
+
+const CSR_VERSION: u32 = 1;
+
+const CSR_HEADER_SIZE: usize = 64;
+
+/// Graph metadata saved in the header of a CSR file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CsrGraphHeader {
+    /// Start point of the search
+    pub start: u32,
+
+    /// Max observed out degree
+    pub max_observed_degree: u32,
+
+    /// Number of frozen points
+    pub num_frozen_pts: u64,
+}
+
+/// Adjacency lists of all vertices, packed
+#[derive(Debug)]
+pub struct ArenaGraph {
+    storage: ArenaStorage,
+}
+
+/// The arrays of the arena, owned or in a mapped CSR file
+#[derive(Debug)]
+enum ArenaStorage {
+    Owned {
+        /// Position in edges of the first vertex of every block, plus one past the last vertex
+        block_bases: Vec<u64>,
+
+        /// Position of every vertex's list relative to its block base, plus one past the last vertex
+        offsets: Vec<u32>,
+
+        /// The concatenated adjacency lists
+        edges: Vec<u32>,
+    },
+    Mapped {
+        file: MmapFile,
+
+        /// Byte ranges of the arrays in the file
+        block_bases: Range<usize>,
+        offsets: Range<usize>,
+        edges: Range<usize>,
+    },
+}
+
+impl ArenaGraph {
+    /// Pack the adjacency lists of a graph
+    pub fn from_graph(graph: &InMemoryGraph) -> ANNResult<Self> {
+        let num_vertices = graph.size();
+        let mut block_bases = Vec::with_capacity((num_vertices >> BLOCK_BITS) + 1);
+        let mut offsets = Vec::with_capacity(num_vertices + 1);
+
+        let mut total_edges = 0;
+        for id in 0..num_vertices {
+            total_edges += graph.read_vertex_and_neighbors(id as u32)?.size();
+        }
+        let mut edges = Vec::with_capacity(total_edges);
+
+        for id in 0..=num_vertices {
+            if id & ((1 << BLOCK_BITS) - 1) == 0 {
+                block_bases.push(edges.len() as u64);
+            }
+
+            let base = block_bases[id >> BLOCK_BITS] as usize;
+            offsets.push(u32::try_from(edges.len() - base).map_err(|_| {
+                ANNError::log_index_error(format!(
+                    "Too many edges in the block of vertex {} to pack the graph",
+                    id
+                ))
+            })?);
+
+            if id < num_vertices {
+                edges
+                    .extend_from_slice(graph.read_vertex_and_neighbors(id as u32)?.get_neighbors());
+            }
+        }
+
+        Ok(Self {
+            storage: ArenaStorage::Owned {
+                block_bases,
+                offsets,
+                edges,
+            },
+        })
+    }
+
+    /// Unpack into a graph that can be modified, with room for max_degree neighbors per vertex
+    pub fn to_graph(&self, max_degree: u32) -> ANNResult<InMemoryGraph> {
+        let mut vertices = Vec::with_capacity(self.size());
+        for id in 0..self.size() {
+            let mut neighbors = AdjacencyList::for_range(max_degree as usize);
+            neighbors.extend_from_slice(self.neighbors(id as u32));
+            vertices.push(VertexAndNeighbors::new(id as u32, neighbors));
+        }
+        Ok(InMemoryGraph::from_vertices(vertices))
+    }
+
+    /// Number of vertices
+    pub fn size(&self) -> usize {
+        self.offsets().len().saturating_sub(1)
+    }
+
+    /// Total number of edges
+    pub fn num_edges(&self) -> usize {
+        self.edges().len()
+    }
+
+    /// Out neighbors of a vertex
+    #[inline(always)]
+    pub fn neighbors(&self, vertex_id: u32) -> &[u32] {
+        let id = vertex_id as usize;
+        &self.edges()[self.start(id)..self.start(id + 1)]
+    }
+
+    /// Whether the arrays are read from a mapped file
+    pub fn is_mapped(&self) -> bool {
+        matches!(self.storage, ArenaStorage::Mapped { .. })
+    }
+
+    /// Bytes used by the offsets and the edges, or mapped from the file
+    pub fn memory_bytes(&self) -> usize {
+        match &self.storage {
+            ArenaStorage::Owned {
+                block_bases,
+                offsets,
+                edges,
+            } => {
+                block_bases.capacity() * std::mem::size_of::<u64>()
+                    + (offsets.capacity() + edges.capacity()) * std::mem::size_of::<u32>()
+            }
+            ArenaStorage::Mapped { file, .. } => file.as_bytes().len(),
+        }
+    }
+
+    /// Save the arrays and the header as a CSR file
+    pub fn save_csr(&self, filename: &str, header: &CsrGraphHeader) -> ANNResult<()> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        writer.write_u64::<LittleEndian>(CSR_MAGIC)?;
+        writer.write_u32::<LittleEndian>(CSR_VERSION)?;
+        writer.write_u32::<LittleEndian>(header.start)?;
+        writer.write_u32::<LittleEndian>(header.max_observed_degree)?;
+        writer.write_u32::<LittleEndian>(0)?;
+        writer.write_u64::<LittleEndian>(header.num_frozen_pts)?;
+        writer.write_u64::<LittleEndian>(self.size() as u64)?;
+        writer.write_u64::<LittleEndian>(self.num_edges() as u64)?;
+        writer.write_u64::<LittleEndian>(self.block_bases().len() as u64)?;
+        writer.write_u64::<LittleEndian>(0)?;
+
+        for base in self.block_bases() {
+            writer.write_u64::<LittleEndian>(*base)?;
+        }
+        for offset in self.offsets() {
+            writer.write_u32::<LittleEndian>(*offset)?;
+        }
+        for edge in self.edges() {
+            writer.write_u32::<LittleEndian>(*edge)?;
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Map a CSR file written by save_csr. Only the header is read, the lists are paged in
+    /// as the searches reach them.
+    pub fn map_csr(filename: &str) -> ANNResult<(Self, CsrGraphHeader)> {
+        if cfg!(target_endian = "big") {
+            return Err(ANNError::log_index_error(
+                "CSR graph files are little-endian and can't be mapped on this platform"
+                    .to_string(),
+            ));
+        }
+
+        let file = MmapFile::open(filename)?;
+        let bytes = file.as_bytes();
+        let invalid = |reason: &str| {
+            ANNError::log_index_error(format!("{} is not a CSR graph file: {}", filename, reason))
+        };
+        if bytes.len() < CSR_HEADER_SIZE {
+            return Err(invalid("the header is truncated"));
+        }
+
+        let mut reader = &bytes[..CSR_HEADER_SIZE];
+        if reader.read_u64::<LittleEndian>()? != CSR_MAGIC {
+            return Err(invalid("wrong magic number"));
+        }
+        let version = reader.read_u32::<LittleEndian>()?;
+        if version != CSR_VERSION {
+            return Err(invalid(&format!("unsupported version {}", version)));
+        }
+        let start = reader.read_u32::<LittleEndian>()?;
+        let max_observed_degree = reader.read_u32::<LittleEndian>()?;
+        reader.read_u32::<LittleEndian>()?;
+        let num_frozen_pts = reader.read_u64::<LittleEndian>()?;
+        let num_vertices = reader.read_u64::<LittleEndian>()? as usize;
+        let num_edges = reader.read_u64::<LittleEndian>()? as usize;
+        let num_blocks = reader.read_u64::<LittleEndian>()? as usize;
+
+        if num_blocks != (num_vertices >> BLOCK_BITS) + 1 {
+            return Err(invalid(
+                "the number of blocks doesn't match the number of vertices",
+            ));
+        }
+
+        let block_bases = CSR_HEADER_SIZE..CSR_HEADER_SIZE + num_blocks * 8;
+        let offsets = block_bases.end..block_bases.end + (num_vertices + 1) * 4;
+        let edges = offsets.end..offsets.end + num_edges * 4;
+        if bytes.len() != edges.end {
+            return Err(invalid(&format!(
+                "expected {} bytes, found {}",
+                edges.end,
+                bytes.len()
+            )));
+        }
+
+        // The mapping is page aligned and the arrays are at multiples of their element size
+        debug_assert_eq!(bytes.as_ptr() as usize % std::mem::align_of::<u64>(), 0);
+
+        let graph = Self {
+            storage: ArenaStorage::Mapped {
+                file,
+                block_bases,
+                offsets,
+                edges,
+            },
+        };
+        if graph.start(num_vertices) != num_edges {
+            return Err(invalid("the offsets don't cover the edges"));
+        }
+
+        Ok((
+            graph,
+            CsrGraphHeader {
+                start,
+                max_observed_degree,
+                num_frozen_pts,
+            },
+        ))
+    }
+
+    #[inline(always)]
+    fn start(&self, id: usize) -> usize {
+        self.block_bases()[id >> BLOCK_BITS] as usize + self.offsets()[id] as usize
+    }
+
+    #[inline(always)]
+    fn block_bases(&self) -> &[u64] {
+        match &self.storage {
+            ArenaStorage::Owned { block_bases, .. } => block_bases,
+            ArenaStorage::Mapped {
+                file, block_bases, ..
+            } => mapped_slice(file, block_bases),
+        }
+    }
+
+    #[inline(always)]
+    fn offsets(&self) -> &[u32] {
+        match &self.storage {
+            ArenaStorage::Owned { offsets, .. } => offsets,
+            ArenaStorage::Mapped { file, offsets, .. } => mapped_slice(file, offsets),
+        }
+    }
+
+    #[inline(always)]
+    fn edges(&self) -> &[u32] {
+        match &self.storage {
+            ArenaStorage::Owned { edges, .. } => edges,
+            ArenaStorage::Mapped { file, edges, .. } => mapped_slice(file, edges),
+        }
+    }
+}
+
+/// View a byte range of a mapped file as an array. The range was checked against the file
+/// length and starts at a multiple of the element size from the page aligned mapping.
+#[inline(always)]
+fn mapped_slice<'a, E>(file: &'a MmapFile, range: &Range<usize>) -> &'a [E] {
+    let bytes = &file.as_bytes()[range.clone()];
+    unsafe {
+        std::slice::from_raw_parts(
+            bytes.as_ptr() as *const E,
+            bytes.len() / std::mem::size_of::<E>(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod arena_graph_test {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn pack_and_unpack() {
+        // More vertices than a block, with empty lists in between
+        let num_vertices = (1 << BLOCK_BITS) + 10;
+        let graph = InMemoryGraph::new(num_vertices, 4);
+        for id in (0..num_vertices as u32).step_by(3) {
+            let neighbors: Vec<u32> = (1..=id % 5)
+                .map(|i| (id + i) % num_vertices as u32)
+                .collect();
+            graph
+                .write_vertex_and_neighbors(id)
+                .unwrap()
+                .set_neighbors(AdjacencyList::from(neighbors));
+        }
+
+        let arena = ArenaGraph::from_graph(&graph).unwrap();
+        assert_eq!(arena.size(), num_vertices);
+        assert!(arena.memory_bytes() < graph.memory_bytes());
+
+        let file = "arena_graph_test.csr";
+        let header = CsrGraphHeader {
+            start: 7,
+            max_observed_degree: 4,
+            num_frozen_pts: 0,
+        };
+        arena.save_csr(file, &header).unwrap();
+        let (mapped, mapped_header) = ArenaGraph::map_csr(file).unwrap();
+        assert!(mapped.is_mapped());
+        assert_eq!(mapped_header, header);
+        assert_eq!(mapped.size(), num_vertices);
+
+        let unpacked = arena.to_graph(4).unwrap();
+        for id in 0..num_vertices as u32 {
+            let expected = graph.read_vertex_and_neighbors(id).unwrap();
+            assert_eq!(arena.neighbors(id), expected.get_neighbors().as_slice());
+            assert_eq!(mapped.neighbors(id), expected.get_neighbors().as_slice());
+            assert_eq!(
+                unpacked
+                    .read_vertex_and_neighbors(id)
+                    .unwrap()
+                    .get_neighbors(),
+                expected.get_neighbors()
+            );
+        }
+
+        // A truncated file is rejected
+        let bytes = fs::read(file).unwrap();
+        fs::write(file, &bytes[..bytes.len() - 4]).unwrap();
+        assert!(ArenaGraph::map_csr(file).is_err());
+        fs::remove_file(file).unwrap();
+
+        let empty = ArenaGraph::from_graph(&InMemoryGraph::new(0, 4)).unwrap();
+        assert_eq!(empty.size(), 0);
+    }
+}
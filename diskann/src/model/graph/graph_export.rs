@@ -0,0 +1,285 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Export of the adjacency lists of an index to files graph tools read.
+//!
+//! The exporter is handed the vertices one at a time with their neighbors, by the in-memory
+//! index from its graph or by the disk index storage as it reads the sectors, and writes
+//! them as an edge list or as GraphML. A sample of the vertices can be exported instead of
+//! the whole graph, with the out edges of each sampled vertex, so large indexes stay small
+//! enough to lay out. The degrees of the exported vertices are summed up on the way.
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use log::info;
+use rand::seq::index::sample;
+use rand::thread_rng;
+
+use crate::common::ANNResult;
+
+/// File format of an exported graph
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum GraphExportFormat {
+    /// A line "source target" per edge, after comment lines starting with #
+    #[default]
+    EdgeList,
+
+    /// GraphML document of directed edges, the vertices carrying their degree
+    GraphML,
+}
+
+/// Counts of an exported graph
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct GraphExportSummary {
+    /// Number of vertices exported with their neighbors
+    pub num_vertices: usize,
+
+    /// Number of edges exported
+    pub num_edges: usize,
+
+    /// Number of exported vertices of each out degree, indexed by the degree
+    pub degree_counts: Vec<usize>,
+}
+
+impl GraphExportSummary {
+    /// Largest out degree of the exported vertices
+    pub fn max_degree(&self) -> usize {
+        self.degree_counts.len().saturating_sub(1)
+    }
+
+    /// Mean out degree of the exported vertices
+    pub fn mean_degree(&self) -> f64 {
+        self.num_edges as f64 / self.num_vertices.max(1) as f64
+    }
+}
+
+/// Writer of the vertices of a graph handed over one by one
+#[derive(Debug)]
+pub struct GraphExporter {
+    writer: BufWriter<File>,
+    format: GraphExportFormat,
+
+    /// Entry point of the graph
+    start: u32,
+
+    /// Vertices to export, all of them if None
+    sampled: Option<HashSet<u32>>,
+
+    /// Vertices written as GraphML nodes
+    declared: HashSet<u32>,
+
+    /// Neighbors of the exported vertices, declared as nodes when the export finishes
+    referenced: HashSet<u32>,
+
+    summary: GraphExportSummary,
+}
+
+impl GraphExporter {
+    /// Create filename to export a graph of num_vertices vertices entered from start, keeping
+    /// sample_size vertices drawn at random if given
+    pub fn create(
+        filename: &str,
+        format: GraphExportFormat,
+        num_vertices: usize,
+        start: u32,
+        sample_size: Option<usize>,
+    ) -> ANNResult<Self> {
+        let sampled = sample_size.map(|sample_size| {
+            sample(
+                &mut thread_rng(),
+                num_vertices,
+                sample_size.min(num_vertices),
+            )
+            .into_iter()
+            .map(|id| id as u32)
+            .collect()
+        });
+
+        let mut writer = BufWriter::new(File::create(filename)?);
+        match format {
+            GraphExportFormat::EdgeList => {
+                writeln!(writer, "# vertices {} start {}", num_vertices, start)?;
+                writeln!(writer, "# source target")?;
+            }
+            GraphExportFormat::GraphML => {
+                writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+                writeln!(
+                    writer,
+                    r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#
+                )?;
+                writeln!(
+                    writer,
+                    r#"  <key id="degree" for="node" attr.name="degree" attr.type="int"/>"#
+                )?;
+                writeln!(
+                    writer,
+                    r#"  <key id="start" for="node" attr.name="start" attr.type="boolean"/>"#
+                )?;
+                writeln!(writer, r#"  <graph id="index" edgedefault="directed">"#)?;
+            }
+        }
+
+        Ok(Self {
+            writer,
+            format,
+            start,
+            sampled,
+            declared: HashSet::new(),
+            referenced: HashSet::from([start]),
+            summary: GraphExportSummary::default(),
+        })
+    }
+
+    /// Write a vertex and its out edges, unless it is left out of the sample
+    pub fn add_vertex(&mut self, id: u32, neighbors: &[u32]) -> ANNResult<()> {
+        if self
+            .sampled
+            .as_ref()
+            .is_some_and(|sampled| !sampled.contains(&id))
+        {
+            return Ok(());
+        }
+
+        match self.format {
+            GraphExportFormat::EdgeList => {
+                for neighbor in neighbors {
+                    writeln!(self.writer, "{} {}", id, neighbor)?;
+                }
+            }
+            GraphExportFormat::GraphML => {
+                if self.declared.insert(id) {
+                    writeln!(
+                        self.writer,
+                        r#"    <node id="n{}"><data key="degree">{}</data>{}</node>"#,
+                        id,
+                        neighbors.len(),
+                        self.start_data(id)
+                    )?;
+                }
+                for neighbor in neighbors {
+                    writeln!(
+                        self.writer,
+                        r#"    <edge source="n{}" target="n{}"/>"#,
+                        id, neighbor
+                    )?;
+                }
+                self.referenced.extend(neighbors);
+            }
+        }
+
+        let summary = &mut self.summary;
+        summary.num_vertices += 1;
+        summary.num_edges += neighbors.len();
+        if summary.degree_counts.len() <= neighbors.len() {
+            summary.degree_counts.resize(neighbors.len() + 1, 0);
+        }
+        summary.degree_counts[neighbors.len()] += 1;
+        Ok(())
+    }
+
+    /// Declare the neighbors that weren't exported themselves, close the document and return
+    /// the counts of the export
+    pub fn finish(mut self) -> ANNResult<GraphExportSummary> {
+        if self.format == GraphExportFormat::GraphML {
+            let mut undeclared: Vec<u32> = self
+                .referenced
+                .difference(&self.declared)
+                .copied()
+                .collect();
+            undeclared.sort_unstable();
+            for id in undeclared {
+                writeln!(
+                    self.writer,
+                    r#"    <node id="n{}">{}</node>"#,
+                    id,
+                    self.start_data(id)
+                )?;
+            }
+            writeln!(self.writer, "  </graph>")?;
+            writeln!(self.writer, "</graphml>")?;
+        }
+        self.writer.flush()?;
+
+        info!(
+            "Exported {} vertices and {} edges, degree mean {:.2} max {}",
+            self.summary.num_vertices,
+            self.summary.num_edges,
+            self.summary.mean_degree(),
+            self.summary.max_degree()
+        );
+        Ok(self.summary)
+    }
+
+    /// GraphML data marking the entry point
+    fn start_data(&self, id: u32) -> &'static str {
+        if id == self.start {
+            r#"<data key="start">true</data>"#
+        } else {
+            ""
+        }
+    }
+}
+
+#[cfg(test)]
+mod graph_export_test {
+    use super::*;
+
+    fn export(
+        format: GraphExportFormat,
+        sample_size: Option<usize>,
+    ) -> (String, GraphExportSummary) {
+        let file = format!("graph_export_test_{:?}_{:?}.txt", format, sample_size);
+        let mut exporter = GraphExporter::create(&file, format, 4, 0, sample_size).unwrap();
+        exporter.add_vertex(0, &[1, 2]).unwrap();
+        exporter.add_vertex(1, &[0]).unwrap();
+        exporter.add_vertex(2, &[0, 1, 3]).unwrap();
+        exporter.add_vertex(3, &[]).unwrap();
+        let summary = exporter.finish().unwrap();
+        let contents = std::fs::read_to_string(&file).unwrap();
+        std::fs::remove_file(&file).unwrap();
+        (contents, summary)
+    }
+
+    #[test]
+    fn exports_edges_and_degree_distribution() {
+        let (edge_list, summary) = export(GraphExportFormat::EdgeList, None);
+        let edges: Vec<&str> = edge_list
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .collect();
+        assert_eq!(edges, vec!["0 1", "0 2", "1 0", "2 0", "2 1", "2 3"]);
+        assert_eq!(summary.degree_counts, vec![1, 1, 1, 1]);
+        assert_eq!((summary.num_vertices, summary.max_degree()), (4, 3));
+        assert_eq!(summary.mean_degree(), 1.5);
+
+        let (graphml, _) = export(GraphExportFormat::GraphML, None);
+        assert_eq!(graphml.matches("<node ").count(), 4);
+        assert_eq!(graphml.matches("<edge ").count(), 6);
+        assert!(graphml.contains(r#"<node id="n2"><data key="degree">3</data></node>"#));
+        assert_eq!(
+            graphml.matches(r#"<data key="start">true</data>"#).count(),
+            1
+        );
+        assert!(graphml.trim_end().ends_with("</graphml>"));
+
+        // Neighbors of the sampled vertices are declared even when they aren't sampled
+        let (graphml, summary) = export(GraphExportFormat::GraphML, Some(1));
+        assert_eq!(summary.num_vertices, 1);
+        assert_eq!(graphml.matches("<edge ").count(), summary.num_edges);
+        for edge in graphml.lines().filter(|line| line.contains("<edge ")) {
+            let target = edge
+                .split("target=")
+                .nth(1)
+                .unwrap()
+                .split('"')
+                .nth(1)
+                .unwrap();
+            assert!(graphml.contains(&format!(r#"<node id="{}""#, target)));
+        }
+    }
+}
@@ -6,17 +6,81 @@
 
 //! In-memory graph
 
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
 use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 
-use crate::common::ANNError;
+use crate::common::{ANNError, ANNResult};
 
 use super::VertexAndNeighbors;
 
-/// The entire graph of in-memory index
-#[derive(Debug)]
+/// Number of locks the adjacency lists share, list i taking lock i % NUM_LOCK_STRIPES
+pub const NUM_LOCK_STRIPES: usize = 1024;
+
+/// The entire graph of in-memory index. The adjacency lists share a fixed set of striped
+/// locks rather than holding one each, and a thread holds one of them at a time: lists i and
+/// i + NUM_LOCK_STRIPES share a lock, which isn't reentrant, so a thread holding a guard
+/// while locking another list may wait on itself. Lists that are used while other lists are
+/// locked are copied out with copy_neighbors.
 pub struct InMemoryGraph {
-    /// The entire graph
-    pub final_graph: Vec<RwLock<VertexAndNeighbors>>,
+    /// The entire graph, each list guarded by the lock of its stripe
+    final_graph: Vec<UnsafeCell<VertexAndNeighbors>>,
+
+    /// Locks of the lists
+    stripes: Vec<RwLock<()>>,
+}
+
+// SAFETY: a list is only reached through a guard holding the lock of its stripe, or through
+// &mut self. Every list of a stripe is behind the same lock, so a write guard on one list
+// excludes every reference to the lists it collides with as well as to itself.
+// Invariant: a thread holds at most one guard at a time, dropping it before another list is
+// locked. Soundness doesn't rest on it, but two guards on colliding lists deadlock.
+unsafe impl Sync for InMemoryGraph {}
+
+impl fmt::Debug for InMemoryGraph {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InMemoryGraph")
+            .field("size", &self.final_graph.len())
+            .field("stripes", &self.stripes.len())
+            .finish()
+    }
+}
+
+/// An adjacency list, read under the read lock of its stripe
+#[derive(Debug)]
+pub struct VertexReadGuard<'a> {
+    _stripe: RwLockReadGuard<'a, ()>,
+    vertex: &'a VertexAndNeighbors,
+}
+
+impl Deref for VertexReadGuard<'_> {
+    type Target = VertexAndNeighbors;
+
+    fn deref(&self) -> &VertexAndNeighbors {
+        self.vertex
+    }
+}
+
+/// An adjacency list, written under the write lock of its stripe
+#[derive(Debug)]
+pub struct VertexWriteGuard<'a> {
+    _stripe: RwLockWriteGuard<'a, ()>,
+    vertex: &'a mut VertexAndNeighbors,
+}
+
+impl Deref for VertexWriteGuard<'_> {
+    type Target = VertexAndNeighbors;
+
+    fn deref(&self) -> &VertexAndNeighbors {
+        self.vertex
+    }
+}
+
+impl DerefMut for VertexWriteGuard<'_> {
+    fn deref_mut(&mut self) -> &mut VertexAndNeighbors {
+        self.vertex
+    }
 }
 
 impl InMemoryGraph {
@@ -24,12 +88,20 @@ impl InMemoryGraph {
     pub fn new(size: usize, max_degree: u32) -> Self {
         let mut graph = Vec::with_capacity(size);
         for id in 0..size {
-            graph.push(RwLock::new(VertexAndNeighbors::for_range(
+            graph.push(VertexAndNeighbors::for_range(
                 id as u32,
                 max_degree as usize,
-            )));
+            ));
+        }
+        Self::from_vertices(graph)
+    }
+
+    /// Graph of the given adjacency lists, list i being the list of vertex i
+    pub fn from_vertices(vertices: Vec<VertexAndNeighbors>) -> Self {
+        Self {
+            final_graph: vertices.into_iter().map(UnsafeCell::new).collect(),
+            stripes: (0..NUM_LOCK_STRIPES).map(|_| RwLock::new(())).collect(),
         }
-        Self { final_graph: graph }
     }
 
     /// Size of graph
@@ -37,11 +109,26 @@ impl InMemoryGraph {
         self.final_graph.len()
     }
 
+    /// Bytes used by the locks, the lists and their spare capacity
+    pub fn memory_bytes(&self) -> usize {
+        let mut lists = 0;
+        for id in 0..self.final_graph.len() {
+            lists += self
+                .read_vertex_and_neighbors(id as u32)
+                .map_or(0, |vertex| {
+                    vertex.get_neighbors().capacity() * std::mem::size_of::<u32>()
+                });
+        }
+        self.final_graph.capacity() * std::mem::size_of::<VertexAndNeighbors>()
+            + self.stripes.len() * std::mem::size_of::<RwLock<()>>()
+            + lists
+    }
+
     /// Extend the graph by size vectors
     pub fn extend(&mut self, size: usize, max_degree: u32) {
         for id in 0..size {
             self.final_graph
-                .push(RwLock::new(VertexAndNeighbors::for_range(
+                .push(UnsafeCell::new(VertexAndNeighbors::for_range(
                     id as u32,
                     max_degree as usize,
                 )));
@@ -52,31 +139,59 @@ impl InMemoryGraph {
     pub fn read_vertex_and_neighbors(
         &self,
         vertex_id: u32,
-    ) -> Result<RwLockReadGuard<VertexAndNeighbors>, ANNError> {
-        self.final_graph[vertex_id as usize].read().map_err(|err| {
-            ANNError::log_lock_poison_error(format!(
-                "PoisonError: Lock poisoned when reading final_graph for vertex_id {}, err={}",
-                vertex_id, err
-            ))
+    ) -> Result<VertexReadGuard<'_>, ANNError> {
+        let vertex = &self.final_graph[vertex_id as usize];
+        let stripe = self.stripes[vertex_id as usize % NUM_LOCK_STRIPES]
+            .read()
+            .map_err(|err| {
+                ANNError::log_lock_poison_error(format!(
+                    "PoisonError: Lock poisoned when reading final_graph for vertex_id {}, err={}",
+                    vertex_id, err
+                ))
+            })?;
+        Ok(VertexReadGuard {
+            _stripe: stripe,
+            // SAFETY: the read lock of the stripe keeps the writers of the list out
+            vertex: unsafe { &*vertex.get() },
         })
     }
 
+    /// Copy the neighbors of vertex_id into neighbors, releasing the lock before returning
+    pub fn copy_neighbors(&self, vertex_id: u32, neighbors: &mut Vec<u32>) -> ANNResult<()> {
+        let vertex = self.read_vertex_and_neighbors(vertex_id)?;
+        neighbors.clear();
+        neighbors.extend_from_slice(vertex.get_neighbors());
+        Ok(())
+    }
+
     /// Get write guard of vertex_id
     pub fn write_vertex_and_neighbors(
         &self,
         vertex_id: u32,
-    ) -> Result<RwLockWriteGuard<VertexAndNeighbors>, ANNError> {
-        self.final_graph[vertex_id as usize].write().map_err(|err| {
-            ANNError::log_lock_poison_error(format!(
-                "PoisonError: Lock poisoned when writing final_graph for vertex_id {}, err={}",
-                vertex_id, err
-            ))
+    ) -> Result<VertexWriteGuard<'_>, ANNError> {
+        let vertex = &self.final_graph[vertex_id as usize];
+        let stripe = self.stripes[vertex_id as usize % NUM_LOCK_STRIPES]
+            .write()
+            .map_err(|err| {
+                ANNError::log_lock_poison_error(format!(
+                    "PoisonError: Lock poisoned when writing final_graph for vertex_id {}, err={}",
+                    vertex_id, err
+                ))
+            })?;
+        Ok(VertexWriteGuard {
+            _stripe: stripe,
+            // SAFETY: the write lock of the stripe keeps every other user of the list out
+            vertex: unsafe { &mut *vertex.get() },
         })
     }
 }
 
 #[cfg(test)]
 mod graph_tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
     use crate::model::{graph::AdjacencyList, GRAPH_SLACK_FACTOR};
 
     use super::*;
@@ -86,10 +201,10 @@ mod graph_tests {
         let graph = InMemoryGraph::new(10, 10);
         let capacity = (GRAPH_SLACK_FACTOR * 10_f64).ceil() as usize;
 
-        assert_eq!(graph.final_graph.len(), 10);
+        assert_eq!(graph.size(), 10);
         for i in 0..10 {
-            let neighbor = graph.final_graph[i].read().unwrap();
-            assert_eq!(neighbor.vertex_id, i as u32);
+            let neighbor = graph.read_vertex_and_neighbors(i).unwrap();
+            assert_eq!(neighbor.vertex_id, i);
             assert_eq!(neighbor.get_neighbors().capacity(), capacity);
         }
     }
@@ -111,7 +226,7 @@ mod graph_tests {
         let mut id: u32 = 0;
 
         for i in 10..20 {
-            let neighbor = graph.final_graph[i].read().unwrap();
+            let neighbor = graph.read_vertex_and_neighbors(i).unwrap();
             assert_eq!(neighbor.vertex_id, id);
             assert_eq!(neighbor.get_neighbors().capacity(), capacity);
             id += 1;
@@ -138,4 +253,38 @@ mod graph_tests {
         let neighbor = graph.read_vertex_and_neighbors(0).unwrap();
         assert_eq!(neighbor.get_neighbors(), &AdjacencyList::from(vec![10_u32]));
     }
+
+    #[test]
+    fn colliding_lists_share_a_lock_but_not_their_neighbors() {
+        let graph = InMemoryGraph::new(NUM_LOCK_STRIPES + 2, 10);
+        let colliding = NUM_LOCK_STRIPES as u32 + 1;
+        let written = AtomicBool::new(false);
+        thread::scope(|scope| {
+            let mut vertex = graph.write_vertex_and_neighbors(1).unwrap();
+            let writer = scope.spawn(|| {
+                graph
+                    .write_vertex_and_neighbors(colliding)
+                    .unwrap()
+                    .add_to_neighbors(7, 10);
+                written.store(true, Ordering::SeqCst);
+            });
+
+            // The writer of the colliding list waits for the guard of list 1
+            thread::sleep(Duration::from_millis(50));
+            assert!(!written.load(Ordering::SeqCst));
+            vertex.add_to_neighbors(3, 10);
+            drop(vertex);
+            writer.join().unwrap();
+        });
+        assert!(written.load(Ordering::SeqCst));
+
+        let mut neighbors = Vec::new();
+        graph.copy_neighbors(1, &mut neighbors).unwrap();
+        assert_eq!(neighbors, [3]);
+        graph.copy_neighbors(colliding, &mut neighbors).unwrap();
+        assert_eq!(neighbors, [7]);
+
+        // Nothing is locked once the lists are copied out
+        graph.write_vertex_and_neighbors(colliding).unwrap();
+    }
 }
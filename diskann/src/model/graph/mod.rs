@@ -4,17 +4,27 @@
  */
 #[allow(clippy::module_inception)]
 mod inmem_graph;
-pub use inmem_graph::InMemoryGraph;
+pub use inmem_graph::{InMemoryGraph, VertexReadGuard, VertexWriteGuard, NUM_LOCK_STRIPES};
 
 pub mod vertex_and_neighbors;
 pub use vertex_and_neighbors::VertexAndNeighbors;
 
+mod arena_graph;
+pub use arena_graph::{ArenaGraph, CsrGraphHeader};
+
 mod adjacency_list;
 pub use adjacency_list::AdjacencyList;
 
+#[cfg(feature = "disk-index")]
 mod sector_graph;
+#[cfg(feature = "disk-index")]
 pub use sector_graph::*;
 
+#[cfg(feature = "disk-index")]
 mod disk_graph;
+#[cfg(feature = "disk-index")]
 pub use disk_graph::*;
 
+
+mod graph_export;
+pub use graph_export::*;
@@ -1,27 +1,102 @@
+#[cfg(target_os = "linux")]
+use std::cell::RefCell;
+use std::os::unix::fs::FileExt;
 use std::sync::Arc;
 use tokio::fs::File;
-use tokio::io::{self, AsyncReadExt, AsyncSeekExt};
-use crate::{model::AlignedRead, common::ANNError, common::ANNResult};
+use tokio::runtime::Handle;
+use tokio::task::JoinHandle;
+use crate::{model::AlignedRange, model::AlignedRead, common::ANNError, common::ANNResult, common::ANNResultExt};
+
+#[cfg(target_os = "linux")]
+use platform::IoUring;
+
+#[cfg(target_os = "linux")]
+use crate::model::MAX_N_SECTOR_READS;
+
+#[cfg(target_os = "linux")]
+thread_local! {
+    /// Ring of the blocking thread for batched reads, None where io_uring can't be set up
+    static IO_URING: RefCell<Option<IoUring>> =
+        RefCell::new(IoUring::new(MAX_N_SECTOR_READS as u32).ok());
+}
+
+/// The bytes of buf
+///
+/// # Safety
+///
+/// Every byte pattern must be a valid T, as for the plain-old-data types read from disk
+unsafe fn as_bytes_mut<T>(buf: &mut [T]) -> &mut [u8] {
+    std::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, std::mem::size_of_val(buf))
+}
+
+/// Fill each buffer with the bytes of file at its offset as one batch, through the io_uring of
+/// the calling thread, or with a pread each where io_uring can't be set up
+#[cfg(target_os = "linux")]
+pub fn read_exact_at_batch(
+    file: &std::fs::File,
+    reads: &mut [(u64, &mut [u8])],
+) -> std::io::Result<()> {
+    IO_URING.with(|ring| match ring.borrow_mut().as_mut() {
+        Some(ring) => ring.read_exact_at(file, reads),
+        None => reads
+            .iter_mut()
+            .try_for_each(|(offset, buf)| file.read_exact_at(buf, *offset)),
+    })
+}
 
 pub struct LinuxAlignedFileReader {
     pub file: Arc<File>,
+
+    /// Same file for positional reads, clones of a file share the cursor so concurrent
+    /// seek and read would race
+    std_file: Arc<std::fs::File>,
+
+    /// Runtime the blocking reads are spawned on, the runtime of the caller when None
+    runtime: Option<Handle>,
 }
 
 impl LinuxAlignedFileReader {
     pub async fn new(fname: &str) -> ANNResult<Self> {
-        // Open the file asynchronously and wrap it in an Arc.
-        let file = Arc::new(
-            File::open(fname)
-                .await
-                .map_err(ANNError::log_io_error)?,
-        );
-        Ok(Self { file })
+        Self::new_with_runtime(fname, None).await
+    }
+
+    /// Open the file and spawn its reads on runtime, or on the runtime of the caller when it
+    /// is None, so an embedder decides which executor and how many blocking threads the
+    /// reads use
+    pub async fn new_with_runtime(fname: &str, runtime: Option<Handle>) -> ANNResult<Self> {
+        let path = fname.to_string();
+        let open = move || std::fs::File::open(path);
+        let std_file = match &runtime {
+            Some(runtime) => runtime.spawn_blocking(open).await?,
+            None => tokio::task::spawn_blocking(open).await?,
+        }
+        .map_err(ANNError::log_io_error)
+        .with_context(|| format!("Opening disk index file {}", fname))?;
+        let file = File::from_std(std_file.try_clone().map_err(ANNError::log_io_error)?);
+        Ok(Self {
+            file: Arc::new(file),
+            std_file: Arc::new(std_file),
+            runtime,
+        })
+    }
+
+    /// Run the blocking task f on the runtime of the reader
+    fn spawn_blocking<F, R>(&self, f: F) -> JoinHandle<R>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        match &self.runtime {
+            Some(runtime) => runtime.spawn_blocking(f),
+            None => tokio::task::spawn_blocking(f),
+        }
     }
 
     /// Reads concurrently into each provided read request.
     ///
     /// This API takes ownership of the read requests (each of which owns its buffer)
     /// and returns a vector of the updated read requests after the reads complete.
+    /// With the io-uring feature the requests are read as one batch through io_uring.
     ///
     /// # Safety
     ///
@@ -35,35 +110,40 @@ impl LinuxAlignedFileReader {
         &self,
         read_requests: Vec<AlignedRead<T>>,
     ) -> ANNResult<Vec<AlignedRead<T>>>
+    where
+        T: Send + 'static,
+    {
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        let reads = self.read_with_io_uring(read_requests).await;
+        #[cfg(not(all(feature = "io-uring", target_os = "linux")))]
+        let reads = self.read_concurrently(read_requests).await;
+        reads
+    }
+
+    /// Read the requests with one blocking task each
+    #[cfg(not(all(feature = "io-uring", target_os = "linux")))]
+    async fn read_concurrently<T>(
+        &self,
+        read_requests: Vec<AlignedRead<T>>,
+    ) -> ANNResult<Vec<AlignedRead<T>>>
     where
         T: Send + 'static,
     {
         let mut handles = Vec::new();
 
         for req in read_requests.into_iter() {
-            let file = self.file.clone();
+            let file = self.std_file.clone();
             let offset = req.offset;
-            // Move the entire `req` (which owns its buffer) into the async task.
-            let handle = tokio::spawn(async move {
-                // Clone the file handle so we can obtain a mutable one.
-                let mut file = file
-                    .try_clone()
-                    .await.map_err(ANNError::log_io_error)?;
+            // Move the entire `req` (which owns its buffer) into the blocking task.
+            let handle = self.spawn_blocking(move || {
                 let mut req = req;
                 // Convert the buffer from a slice of T to a slice of u8.
                 // This conversion is unsafe because it reinterprets the underlying bytes.
-                let buf = unsafe {
-                    std::slice::from_raw_parts_mut(
-                        req.aligned_buf.as_mut_ptr() as *mut u8,
-                        req.aligned_buf.len() * std::mem::size_of::<T>(),
-                    )
-                };
-                file.seek(std::io::SeekFrom::Start(offset))
-                    .await
-                    .map_err(ANNError::log_io_error)?;
-                file.read_exact(buf)
-                    .await
-                    .map_err(ANNError::log_io_error)?;
+                let buf = unsafe { as_bytes_mut(&mut req.aligned_buf) };
+                let len = buf.len();
+                file.read_exact_at(buf, offset)
+                    .map_err(ANNError::log_io_error)
+                    .with_context(|| format!("Reading {} bytes at offset {}", len, offset))?;
                 Ok::<AlignedRead<T>, ANNError>(req)
             });
             handles.push(handle);
@@ -78,4 +158,139 @@ impl LinuxAlignedFileReader {
 
         Ok(results)
     }
+
+    /// Read the requests as one batch in a single blocking task, through the io_uring of the
+    /// blocking thread, or with a pread each where io_uring can't be set up
+    #[cfg(target_os = "linux")]
+    pub async fn read_with_io_uring<T>(
+        &self,
+        read_requests: Vec<AlignedRead<T>>,
+    ) -> ANNResult<Vec<AlignedRead<T>>>
+    where
+        T: Send + 'static,
+    {
+        let file = self.std_file.clone();
+        self.spawn_blocking(move || {
+            let mut read_requests = read_requests;
+            let num_reads = read_requests.len();
+            let mut reads: Vec<(u64, &mut [u8])> = read_requests
+                .iter_mut()
+                .map(|req| (req.offset, unsafe { as_bytes_mut(&mut req.aligned_buf) }))
+                .collect();
+            read_exact_at_batch(&file, &mut reads)
+                .map_err(ANNError::log_io_error)
+                .with_context(|| format!("Reading a batch of {} aligned reads", num_reads))?;
+            Ok(read_requests)
+        })
+        .await?
+    }
+
+    /// Read len bytes at offset, neither of which need be aligned. Aligned ranges take the
+    /// aligned path, others are widened to the aligned range around them, which is read and
+    /// the requested bytes copied out of.
+    pub async fn read_range(&self, offset: u64, len: usize) -> ANNResult<Vec<u8>> {
+        let range = AlignedRange::covering(offset, len);
+        if range.is_exact() {
+            let mut reads = self
+                .read(vec![AlignedRead::new(offset, vec![0u8; len])?])
+                .await?;
+            return Ok(reads.pop().map(|read| read.aligned_buf).unwrap_or_default());
+        }
+
+        let file = self.std_file.clone();
+        self.spawn_blocking(move || {
+            // The widened range may run past the end of the file, only the requested bytes
+            // have to be there
+            let mut buf = vec![0u8; range.len];
+            let mut filled = 0;
+            while filled < buf.len() {
+                match file.read_at(&mut buf[filled..], range.offset + filled as u64) {
+                    Ok(0) => break,
+                    Ok(n) => filled += n,
+                    Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                    Err(err) => return Err(ANNError::log_io_error(err)),
+                }
+            }
+            range.requested(&buf[..filled]).map(<[u8]>::to_vec)
+        })
+        .await?
+        .with_context(|| format!("Reading {} bytes at offset {}", len, offset))
+    }
+}
+
+#[cfg(test)]
+mod linux_aligned_file_reader_test {
+    use std::fs;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn unaligned_ranges_are_read_through_the_aligned_range() {
+        let file = "unaligned_ranges_are_read_through_the_aligned_range.bin";
+        let contents: Vec<u8> = (0..3000).map(|i| (i % 251) as u8).collect();
+        fs::write(file, &contents).unwrap();
+        let reader = LinuxAlignedFileReader::new(file).await.unwrap();
+
+        let unaligned = reader.read_range(1000, 100).await;
+        let aligned = reader.read_range(512, 1024).await;
+        // The aligned range around the last bytes runs past the end of the file
+        let tail = reader.read_range(2990, 10).await;
+        let past_end = reader.read_range(2990, 20).await;
+        fs::remove_file(file).unwrap();
+
+        assert_eq!(unaligned.unwrap(), &contents[1000..1100]);
+        assert_eq!(aligned.unwrap(), &contents[512..1536]);
+        assert_eq!(tail.unwrap(), &contents[2990..]);
+        assert!(past_end.is_err());
+    }
+
+    #[tokio::test]
+    async fn batched_reads_fill_every_request() {
+        let file = "batched_reads_fill_every_request.bin";
+        let contents: Vec<u8> = (0..4096).map(|i| (i % 251) as u8).collect();
+        fs::write(file, &contents).unwrap();
+        let reader = LinuxAlignedFileReader::new(file).await.unwrap();
+
+        let offsets = [3072u64, 0, 1024];
+        let requests = offsets
+            .iter()
+            .map(|&offset| AlignedRead::new(offset, vec![0u8; 1024]).unwrap())
+            .collect();
+        let reads = reader.read_with_io_uring(requests).await;
+        let past_end = reader
+            .read_with_io_uring(vec![AlignedRead::new(3584, vec![0u8; 1024]).unwrap()])
+            .await;
+        fs::remove_file(file).unwrap();
+
+        for (read, &offset) in reads.unwrap().iter().zip(&offsets) {
+            assert_eq!(read.offset, offset);
+            assert_eq!(
+                read.aligned_buf,
+                &contents[offset as usize..offset as usize + 1024]
+            );
+        }
+        assert!(past_end.is_err());
+    }
+
+    #[test]
+    fn reads_run_on_the_given_runtime() {
+        let file = "reads_run_on_the_given_runtime.bin";
+        let contents: Vec<u8> = (0..1024).map(|i| (i % 251) as u8).collect();
+        fs::write(file, &contents).unwrap();
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .max_blocking_threads(2)
+            .build()
+            .unwrap();
+
+        // The executor polling the reads is no tokio runtime, the reads are spawned on runtime
+        let read = futures::executor::block_on(async {
+            let runtime = Some(runtime.handle().clone());
+            let reader = LinuxAlignedFileReader::new_with_runtime(file, runtime).await?;
+            reader.read_range(100, 200).await
+        });
+        fs::remove_file(file).unwrap();
+
+        assert_eq!(read.unwrap(), &contents[100..300]);
+    }
 }
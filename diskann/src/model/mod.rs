@@ -25,11 +25,20 @@ pub use vertex::Vertex;
 pub mod pq;
 pub use pq::*;
 
+pub mod search_result;
+pub use search_result::*;
+
+#[cfg(feature = "disk-index")]
 pub mod windows_aligned_file_reader;
+#[cfg(feature = "disk-index")]
 pub use windows_aligned_file_reader::*;
 
+#[cfg(feature = "disk-index")]
 pub mod linux_aligned_file_reader;
+#[cfg(feature = "disk-index")]
 pub use linux_aligned_file_reader::*;
 
+#[cfg(feature = "disk-index")]
 pub mod aligned_file_reader;
+#[cfg(feature = "disk-index")]
 pub use aligned_file_reader::*;
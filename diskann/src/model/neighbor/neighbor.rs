@@ -26,6 +26,39 @@ impl Neighbor {
             visited: false
         }
     }
+
+    /// Number of low bits of the distance order key that `epsilon` treats as noise: distances
+    /// whose keys only differ in those bits are within about `epsilon` of each other relative
+    /// to their magnitude. 0 for epsilon = 0.
+    pub fn tie_bits(epsilon: f32) -> u32 {
+        if epsilon.is_nan() || epsilon <= 0.0 {
+            return 0;
+        }
+        // A relative step of 2^-k is the weight of the k-th mantissa bit
+        let k = (-epsilon.log2()).ceil().max(0.0) as u32;
+        f32::MANTISSA_DIGITS.saturating_sub(1).saturating_sub(k)
+    }
+
+    /// Total order key of the distance: the bits of the float mapped so that they order like the
+    /// distances, with the low `tie_bits` dropped so that near-equal distances share a bucket.
+    #[inline]
+    pub fn distance_bucket(&self, tie_bits: u32) -> u32 {
+        let bits = self.distance.to_bits();
+        let key = if bits & 0x8000_0000 != 0 {
+            !bits
+        } else {
+            bits | 0x8000_0000
+        };
+        key >> tie_bits
+    }
+
+    /// Whether self ranks before other, comparing the distance buckets of `tie_bits` then the ids.
+    /// This is a total order, so where a neighbor ranks doesn't depend on the order the others
+    /// were compared in. With tie_bits = 0 this is the same order as `<`.
+    #[inline]
+    pub fn lt_in_buckets(&self, other: &Self, tie_bits: u32) -> bool {
+        (self.distance_bucket(tie_bits), self.id) < (other.distance_bucket(tie_bits), other.id)
+    }
 }
 
 impl Default for Neighbor {
@@ -83,6 +116,27 @@ mod neighbor_test {
         assert!(n1 == n3);
     }
 
+    #[test]
+    fn lt_in_buckets_breaks_near_ties_by_id() {
+        let n1 = Neighbor::new(5, 1.0);
+        let n2 = Neighbor::new(2, 1.000_000_1);
+        let tie_bits = Neighbor::tie_bits(1e-5);
+
+        assert_eq!(Neighbor::tie_bits(0.0), 0);
+        assert!(n1 < n2);
+        assert!(n1.lt_in_buckets(&n2, 0));
+        assert!(n2.lt_in_buckets(&n1, tie_bits));
+        assert!(!n1.lt_in_buckets(&n2, tie_bits));
+
+        let far = Neighbor::new(1, 2.0);
+        assert!(n1.lt_in_buckets(&far, tie_bits));
+
+        // Buckets keep the order of negative distances
+        let negative = Neighbor::new(9, -1.0);
+        assert!(negative.lt_in_buckets(&n1, tie_bits));
+        assert!(Neighbor::new(9, -2.0).lt_in_buckets(&negative, tie_bits));
+    }
+
     #[test]
     #[should_panic]
     fn gt_should_panic() {
@@ -18,6 +18,12 @@ pub struct NeighborPriorityQueue {
 
     /// The neighbor collection
     data: Vec<Neighbor>,
+
+    /// Relative tolerance under which two distances are considered tied and ordered by id
+    tie_epsilon: f32,
+
+    /// Low bits of the distance keys the tolerance drops, see Neighbor::tie_bits
+    tie_bits: u32,
 }
 
 impl Default for NeighborPriorityQueue {
@@ -34,6 +40,8 @@ impl NeighborPriorityQueue {
             capacity: 0,
             cur: 0,
             data: Vec::new(),
+            tie_epsilon: 0.0,
+            tie_bits: 0,
         }
     }
 
@@ -44,6 +52,8 @@ impl NeighborPriorityQueue {
             capacity,
             cur: 0,
             data: vec![Neighbor::default(); capacity + 1],
+            tie_epsilon: 0.0,
+            tie_bits: 0,
         }
     }
 
@@ -51,7 +61,7 @@ impl NeighborPriorityQueue {
     /// The item will be dropped if queue is full / already exist in queue / it has a greater distance than the last item.
     /// The set cursor that is used to pop() the next item will be set to the lowest index of an uncheck item.
     pub fn insert(&mut self, nbr: Neighbor) {
-        if self.size == self.capacity && self.get_at(self.size - 1) < &nbr {
+        if self.size == self.capacity && self.get_at(self.size - 1).lt_in_buckets(&nbr, self.tie_bits) {
             return;
         }
 
@@ -59,7 +69,7 @@ impl NeighborPriorityQueue {
         let mut hi = self.size;
         while lo < hi {
             let mid = (lo + hi) >> 1;
-            if &nbr < self.get_at(mid) {
+            if nbr.lt_in_buckets(self.get_at(mid), self.tie_bits) {
                 hi = mid;
             } else if self.get_at(mid).id == nbr.id {
                 // Make sure the same neighbor isn't inserted into the set
@@ -126,11 +136,32 @@ impl NeighborPriorityQueue {
         }
     }
 
+    /// Set the relative tolerance under which candidate distances are considered tied and ordered by id,
+    /// so results don't depend on last-bit differences between SIMD reductions.
+    /// 0 (the default) only treats exactly equal distances as ties.
+    pub fn set_tie_epsilon(&mut self, tie_epsilon: f32) {
+        self.tie_epsilon = tie_epsilon;
+        self.tie_bits = Neighbor::tie_bits(tie_epsilon);
+    }
+
+    /// Get the tie epsilon of the NeighborPriorityQueue
+    pub fn tie_epsilon(&self) -> f32 {
+        self.tie_epsilon
+    }
+
     /// Set size and cur to 0
     pub fn clear(&mut self) {
         self.size = 0;
         self.cur = 0;
     }
+
+    /// Keep the closest len neighbors and drop the rest
+    pub fn truncate(&mut self, len: usize) {
+        if len < self.size {
+            self.size = len;
+            self.cur = self.cur.min(len);
+        }
+    }
 }
 
 impl std::ops::Index<usize> for NeighborPriorityQueue {
@@ -173,6 +204,62 @@ mod neighbor_priority_queue_test {
         println!("{:?}", queue);
     }
 
+    #[test]
+    fn test_insert_with_tie_epsilon() {
+        let mut exact = NeighborPriorityQueue::with_capacity(3);
+        let mut tolerant = NeighborPriorityQueue::with_capacity(3);
+        tolerant.set_tie_epsilon(1e-5);
+
+        for queue in [&mut exact, &mut tolerant] {
+            queue.insert(Neighbor::new(7, 1.0));
+            queue.insert(Neighbor::new(3, 1.000_000_1));
+            queue.insert(Neighbor::new(9, 0.5));
+        }
+
+        // node id in exact queue is [9,7,3], near-tied distances are ordered by id in tolerant queue [9,3,7]
+        assert_eq!([exact[0].id, exact[1].id, exact[2].id], [9, 7, 3]);
+        assert_eq!([tolerant[0].id, tolerant[1].id, tolerant[2].id], [9, 3, 7]);
+        assert_eq!(tolerant.tie_epsilon(), 1e-5);
+    }
+
+    #[test]
+    fn test_insert_order_does_not_change_near_ties() {
+        // Each distance is within 1e-6 of the next one but not of the one after, with ids
+        // going the other way, so a relative comparison ranks them in a cycle
+        let neighbors: Vec<Neighbor> = [
+            (5, 1.0),
+            (4, 1.000_000_7),
+            (3, 1.000_001_4),
+            (2, 1.000_002_1),
+            (1, 1.000_002_8),
+            (0, 1.000_003_5),
+            (6, 2.0),
+        ]
+        .into_iter()
+        .map(|(id, distance)| Neighbor::new(id, distance))
+        .collect();
+
+        let contents = |order: &[usize]| {
+            let mut queue = NeighborPriorityQueue::with_capacity(5);
+            queue.set_tie_epsilon(1e-6);
+            for &i in order {
+                queue.insert(neighbors[i]);
+            }
+            (0..queue.size()).map(|i| queue[i].id).collect::<Vec<_>>()
+        };
+
+        let expected = contents(&[0, 1, 2, 3, 4, 5, 6]);
+        for order in [
+            [6, 5, 4, 3, 2, 1, 0],
+            [3, 0, 6, 2, 5, 1, 4],
+            [1, 4, 0, 5, 3, 6, 2],
+            [5, 2, 6, 0, 4, 3, 1],
+        ] {
+            assert_eq!(contents(&order), expected);
+        }
+        assert_eq!(expected.len(), 5);
+    }
+
     #[test]
     fn test_index() {
         let mut queue = NeighborPriorityQueue::with_capacity(3);
@@ -219,6 +306,22 @@ mod neighbor_priority_queue_test {
         assert!(!queue.has_notvisited_node());
     }
 
+    #[test]
+    fn test_truncate() {
+        let mut queue = NeighborPriorityQueue::with_capacity(4);
+        queue.insert(Neighbor::new(1, 1.0));
+        queue.insert(Neighbor::new(2, 0.5));
+        queue.insert(Neighbor::new(3, 1.5));
+        queue.closest_notvisited();
+        queue.closest_notvisited();
+        queue.truncate(1);
+        assert_eq!(queue.size(), 1);
+        assert_eq!(queue[0].id, 2);
+        assert!(!queue.has_notvisited_node());
+        queue.truncate(3);
+        assert_eq!(queue.size(), 1);
+    }
+
     #[test]
     fn test_reserve() {
         let mut queue = NeighborPriorityQueue::new();
@@ -8,13 +8,17 @@ use hashbrown::HashMap;
 use rayon::prelude::{
     IndexedParallelIterator, IntoParallelRefMutIterator, ParallelIterator, ParallelSliceMut,
 };
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
+use vector::{pq_dist_lookup_packed4_vector, pq_dist_lookup_vector};
 
 use crate::{
     common::{ANNError, ANNResult},
-    model::NUM_PQ_CENTROIDS,
+    model::PQCodeBits,
 };
 
+use super::opq::rotate;
+
 /// PQ Pivot table loading and calculate distance
 #[derive(Debug)]
 pub struct FixedChunkPQTable {
@@ -47,6 +51,12 @@ pub struct FixedChunkPQTable {
     /// Map dim offset to chunk index e.g., 8 dims in to 2 chunks
     /// then would be [(0,0), (1,0), (2,0), (3,0), (4,1), (5,1), (6,1), (7,1)]
     dimoffset_chunk_mapping: HashMap<usize, usize>,
+
+    /// OPQ rotation matrix: dim * dim, applied to the centered vectors before PQ
+    rotation: Option<Vec<f32>>,
+
+    /// Width of the codes, which sets the number of centroids per chunk
+    code_bits: PQCodeBits,
 }
 
 impl FixedChunkPQTable {
@@ -72,19 +82,42 @@ impl FixedChunkPQTable {
             chunk_offsets,
             centroids,
             dimoffset_chunk_mapping,
+            rotation: None,
+            code_bits: PQCodeBits::Eight,
         }
     }
 
+    /// Set the width of the codes the pivots were trained for
+    pub fn with_code_bits(mut self, code_bits: PQCodeBits) -> Self {
+        self.code_bits = code_bits;
+        self
+    }
+
+    /// Get the width of the codes
+    pub fn code_bits(&self) -> PQCodeBits {
+        self.code_bits
+    }
+
+    /// Set the OPQ rotation matrix the pivots were trained with
+    pub fn with_rotation(mut self, rotation: Vec<f32>) -> Self {
+        self.rotation = Some(rotation);
+        self
+    }
+
     /// Get chunk number
     pub fn get_num_chunks(&self) -> usize {
         self.num_pq_chunks
     }
 
-    /// Shifting the query according to mean or the whole corpus
+    /// Shifting the query according to mean or the whole corpus, then rotating it with OPQ
     pub fn preprocess_query(&self, query_vec: &mut [f32]) {
         for (query, &centroid) in query_vec.iter_mut().zip(self.centroids.iter()) {
             *query -= centroid;
         }
+        if let Some(rotation) = &self.rotation {
+            let rotated = rotate(&query_vec[..self.dim], 1, self.dim, rotation);
+            query_vec[..self.dim].copy_from_slice(&rotated);
+        }
     }
 
     /// Pre-calculated the distance between query and each centroid by l2 distance
@@ -92,15 +125,16 @@ impl FixedChunkPQTable {
     /// * `dist_vec` - pre-calculated the distance between query and each centroid: chunk_size * num_centroids
     #[allow(clippy::needless_range_loop)]
     pub fn populate_chunk_distances(&self, query_vec: &[f32]) -> Vec<f32> {
-        let mut dist_vec = vec![0.0; self.num_pq_chunks * NUM_PQ_CENTROIDS];
-        for centroid_index in 0..NUM_PQ_CENTROIDS {
+        let num_centers = self.code_bits.num_centers();
+        let mut dist_vec = vec![0.0; self.num_pq_chunks * num_centers];
+        for centroid_index in 0..num_centers {
             for chunk_index in 0..self.num_pq_chunks {
                 for dim_offset in
                     self.chunk_offsets[chunk_index]..self.chunk_offsets[chunk_index + 1]
                 {
                     let diff: f32 = self.pq_table[self.dim * centroid_index + dim_offset]
                         - query_vec[dim_offset];
-                    dist_vec[chunk_index * NUM_PQ_CENTROIDS + centroid_index] += diff * diff;
+                    dist_vec[chunk_index * num_centers + centroid_index] += diff * diff;
                 }
             }
         }
@@ -118,8 +152,9 @@ impl FixedChunkPQTable {
     /// Thus, using indexing might be the most straightforward way to express this logic.
     #[allow(clippy::needless_range_loop)]
     pub fn populate_chunk_inner_products(&self, query_vec: &[f32]) -> Vec<f32> {
-        let mut dist_vec = vec![0.0; self.num_pq_chunks * NUM_PQ_CENTROIDS];
-        for centroid_index in 0..NUM_PQ_CENTROIDS {
+        let num_centers = self.code_bits.num_centers();
+        let mut dist_vec = vec![0.0; self.num_pq_chunks * num_centers];
+        for centroid_index in 0..num_centers {
             for chunk_index in 0..self.num_pq_chunks {
                 for dim_offset in
                     self.chunk_offsets[chunk_index]..self.chunk_offsets[chunk_index + 1]
@@ -129,7 +164,7 @@ impl FixedChunkPQTable {
                     // clean (max inner product vs min distance)
                     let diff: f32 = self.pq_table[self.dim * centroid_index + dim_offset]
                         * query_vec[dim_offset];
-                    dist_vec[chunk_index * NUM_PQ_CENTROIDS + centroid_index] -= diff;
+                    dist_vec[chunk_index * num_centers + centroid_index] -= diff;
                 }
             }
         }
@@ -198,10 +233,24 @@ impl FixedChunkPQTable {
                     .ok_or(ANNError::log_pq_error(
                         "ERROR: dim_offset not found in dimoffset_chunk_mapping".to_string(),
                     ))?;
-            *value = self.pq_table[self.dim * base_vec[*chunk_index] as usize + dim_offset]
-                + self.centroids[dim_offset];
+            *value = self.pq_table[self.dim * base_vec[*chunk_index] as usize + dim_offset];
         }
 
+        // Rotate back with the transpose of the rotation
+        if let Some(rotation) = &self.rotation {
+            let rotated = out_vec;
+            out_vec = (0..self.dim)
+                .map(|i| {
+                    (0..self.dim)
+                        .map(|j| rotation[i * self.dim + j] * rotated[j])
+                        .sum()
+                })
+                .collect();
+        }
+
+        for (value, &centroid) in out_vec.iter_mut().zip(self.centroids.iter()) {
+            *value += centroid;
+        }
         Ok(out_vec)
     }
 }
@@ -219,31 +268,62 @@ pub fn pq_dist_lookup(
     pq_dists: &[f32],
 ) -> Vec<f32> {
     let mut dists_out: Vec<f32> = vec![0.0; n_pts];
+    #[cfg(target_arch = "x86_64")]
     unsafe {
-        _mm_prefetch(dists_out.as_ptr() as *const i8, _MM_HINT_T0);
         _mm_prefetch(pq_ids.as_ptr() as *const i8, _MM_HINT_T0);
         _mm_prefetch(pq_ids.as_ptr().add(64) as *const i8, _MM_HINT_T0);
         _mm_prefetch(pq_ids.as_ptr().add(128) as *const i8, _MM_HINT_T0);
     }
-    for chunk in 0..pq_nchunks {
-        let chunk_dists = &pq_dists[256 * chunk..];
-        if chunk < pq_nchunks - 1 {
-            unsafe {
-                _mm_prefetch(
-                    chunk_dists.as_ptr().offset(256 * chunk as isize).add(256) as *const i8,
-                    _MM_HINT_T0,
-                );
-            }
+    pq_dist_lookup_vector(&pq_ids[..n_pts * pq_nchunks], pq_nchunks, pq_dists, &mut dists_out);
+    dists_out
+}
+
+/// Given a batch input nodes with packed 4-bit codes, return a batch of PQ distance
+/// * `pq_ids` - batch nodes: n_pts * (pq_nchunks + 1) / 2, two codes per byte
+/// * `n_pts` - batch number
+/// * `pq_nchunks` - pq chunk number number
+/// * `pq_dists` - pre-calculated the distance between query and each centroid: chunk_size * 16
+pub fn pq_dist_lookup_packed4(
+    pq_ids: &[u8],
+    n_pts: usize,
+    pq_nchunks: usize,
+    pq_dists: &[f32],
+) -> Vec<f32> {
+    let mut dists_out: Vec<f32> = vec![0.0; n_pts];
+    let code_bytes = PQCodeBits::Four.code_bytes(pq_nchunks);
+    pq_dist_lookup_packed4_vector(
+        &pq_ids[..n_pts * code_bytes],
+        pq_nchunks,
+        pq_dists,
+        &mut dists_out,
+    );
+    dists_out
+}
+
+/// Pack 4-bit codes, num_chunks per point, two per byte with the even chunk in the low nibble
+pub fn pack_4bit_codes(codes: &[u8], num_chunks: usize) -> Vec<u8> {
+    let code_bytes = PQCodeBits::Four.code_bytes(num_chunks);
+    let mut packed = vec![0u8; codes.len() / num_chunks * code_bytes];
+    for (point_codes, point_packed) in codes
+        .chunks_exact(num_chunks)
+        .zip(packed.chunks_exact_mut(code_bytes))
+    {
+        for (chunk, &code) in point_codes.iter().enumerate() {
+            point_packed[chunk / 2] |= (code & 0x0f) << (4 * (chunk % 2));
         }
-        dists_out
-            .par_iter_mut()
-            .enumerate()
-            .for_each(|(n_iter, dist)| {
-                let pq_centerid = pq_ids[pq_nchunks * n_iter + chunk];
-                *dist += chunk_dists[pq_centerid as usize];
-            });
     }
-    dists_out
+    packed
+}
+
+/// Unpack the 4-bit codes packed by pack_4bit_codes, one byte per code
+pub fn unpack_4bit_codes(packed: &[u8], num_chunks: usize) -> Vec<u8> {
+    let code_bytes = PQCodeBits::Four.code_bytes(num_chunks);
+    packed
+        .chunks_exact(code_bytes)
+        .flat_map(|point_packed| {
+            (0..num_chunks).map(move |chunk| (point_packed[chunk / 2] >> (4 * (chunk % 2))) & 0x0f)
+        })
+        .collect()
 }
 
 pub fn aggregate_coords(ids: &[u32], all_coords: &[u8], ndims: usize) -> Vec<u8> {
@@ -267,6 +347,7 @@ mod fixed_chunk_pq_table_test {
 
     use super::*;
     use crate::common::{ANNError, ANNResult};
+    use crate::model::NUM_PQ_CENTROIDS;
     use crate::utils::{convert_types_u32_usize, convert_types_u64_usize, file_exists, load_bin};
 
     const DIM: usize = 128;
@@ -480,4 +561,31 @@ mod pq_index_prune_query_test {
         assert_eq!(dists_out[0], pq_dists[0 + 1] + pq_dists[256 + 3]);
         assert_eq!(dists_out[1], pq_dists[0 + 2] + pq_dists[256 + 2]);
     }
+
+    #[test]
+    fn packed4_codes_round_trip_and_look_up() {
+        // Three chunks leave the high nibble of the second byte of each point unused
+        let codes: Vec<u8> = vec![1, 15, 7, 0, 9, 12];
+        let packed = pack_4bit_codes(&codes, 3);
+        assert_eq!(packed, vec![0xf1, 0x07, 0x90, 0x0c]);
+        assert_eq!(unpack_4bit_codes(&packed, 3), codes);
+
+        let pq_dists: Vec<f32> = (0..16 * 3).map(|i| i as f32 * 0.5).collect();
+        let dists_out = pq_dist_lookup_packed4(&packed, 2, 3, &pq_dists);
+        let first = pq_dists[1] + pq_dists[16 + 15] + pq_dists[32 + 7];
+        let second = pq_dists[0] + pq_dists[16 + 9] + pq_dists[32 + 12];
+        assert_eq!(dists_out, vec![first, second]);
+
+        // 4-bit tables hold 16 centroid distances per chunk
+        let dim = 4;
+        let pq_table: Vec<f32> = (0..16 * dim).map(|i| i as f32).collect();
+        let pq_table = FixedChunkPQTable::new(dim, 2, pq_table, vec![0.0; dim], vec![0, 2, 4])
+            .with_code_bits(PQCodeBits::Four);
+        let query = vec![1.0, 2.0, 3.0, 4.0];
+        let chunk_dists = pq_table.populate_chunk_distances(&query);
+        assert_eq!(chunk_dists.len(), 2 * 16);
+        let codes = vec![5u8, 11u8];
+        let dist = pq_dist_lookup_packed4(&pack_4bit_codes(&codes, 2), 1, 2, &chunk_dists)[0];
+        assert_eq!(dist, pq_table.l2_distance(&query, &codes));
+    }
 }
@@ -7,3 +7,8 @@ pub use fixed_chunk_pq_table::*;
 
 mod pq_construction;
 pub use pq_construction::*;
+
+mod opq;
+
+mod pq_rotation;
+pub use pq_rotation::*;
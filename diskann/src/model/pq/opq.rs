@@ -0,0 +1,425 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Optimized product quantization (OPQ).
+//! PQ quantizes each chunk of dimensions on its own, so it loses the most on data whose
+//! variance sits in a few dimensions or is correlated across chunks, as with learned
+//! embeddings. OPQ learns a rotation R of the centered vectors that makes them cheaper to
+//! quantize, alternating between training the pivots on the rotated vectors X * R and
+//! rotating the vectors towards their reconstruction Y, the orthogonal R minimizing
+//! |X * R - Y| being the polar factor of X^T * Y. The training starts from a random rotation,
+//! which already spreads the variance over all chunks.
+//! The pivots live in the rotated space: the vectors and the queries are centered then
+//! rotated before they are encoded or compared to the pivots.
+
+use rand::distributions::{Distribution, Uniform};
+use rand::rngs::SmallRng;
+use rand::SeedableRng;
+
+use crate::common::{ANNError, ANNResult};
+use crate::storage::PQStorage;
+use crate::utils::blas::{sgemm, Layout, Transpose};
+use crate::utils::KMeansParams;
+
+use super::pq_construction::{calculate_chunk_offsets, center_train_data, train_chunk_pivots};
+
+/// Rounds of pivot training and rotation update
+const NUM_OPQ_ROUNDS: usize = 8;
+
+/// Most Newton-Schulz iterations computing the polar factor of a matrix
+const MAX_POLAR_ITERATIONS: usize = 100;
+
+/// Distance of X^T * X to the identity under which the Newton-Schulz iteration stops
+const POLAR_TOLERANCE: f32 = 1e-4;
+
+/// Seed of the initial random rotation, so that the same data gets the same rotation
+const INITIAL_ROTATION_SEED: u64 = 0x0970_7a7e;
+
+/// Column norm under which the orthonormalized rotation is considered degenerate
+const MIN_COLUMN_NORM: f32 = 1e-6;
+
+/// Generate the OPQ pivots and rotation matrix of the training data train_data of dimensions
+/// num_train * dim, and store them in the pivot file and the rotation matrix file of
+/// pq_storage. The pivot file has the same layout as the PQ pivot file, with the centroid in
+/// the original space and the pivots in the rotated space.
+pub(super) fn generate_opq_pivots(
+    train_data: &mut [f32],
+    num_train: usize,
+    dim: usize,
+    num_centers: usize,
+    num_pq_chunks: usize,
+    kmeans_params: KMeansParams,
+    pq_storage: &mut PQStorage,
+) -> ANNResult<()> {
+    if num_pq_chunks > dim {
+        return Err(ANNError::log_pq_error(
+            "Error: number of chunks more than dimension.".to_string(),
+        ));
+    }
+
+    if pq_storage.pivot_data_exist() && pq_storage.rotation_matrix_exist() {
+        let (file_num_centers, file_dim) = pq_storage.read_pivot_metadata()?;
+        if file_dim == dim && file_num_centers == num_centers {
+            // OPQ pivot and rotation files exist. Not generating again.
+            return Ok(());
+        }
+    }
+
+    let centroid = center_train_data(train_data, num_train, dim);
+    let chunk_offsets = calculate_chunk_offsets(dim, num_pq_chunks);
+    let (full_pivot_data, rotation) = train_opq(
+        train_data,
+        num_train,
+        dim,
+        num_centers,
+        &chunk_offsets,
+        kmeans_params,
+    )?;
+
+    pq_storage.write_pivot_data(
+        &full_pivot_data,
+        &centroid,
+        &chunk_offsets,
+        num_centers,
+        dim,
+    )?;
+    pq_storage.write_rotation_matrix(&rotation, dim)?;
+
+    Ok(())
+}
+
+/// Learn the rotation and the pivots of the centered training data. Returns the pivots,
+/// num_centers * dim row major in the rotated space, and the dim * dim rotation matrix.
+fn train_opq(
+    train_data: &[f32],
+    num_train: usize,
+    dim: usize,
+    num_centers: usize,
+    chunk_offsets: &[usize],
+    kmeans_params: KMeansParams,
+) -> ANNResult<(Vec<f32>, Vec<f32>)> {
+    let mut rotation = random_rotation(dim).ok_or_else(|| {
+        ANNError::log_pq_error("Error: failed to generate a random rotation.".to_string())
+    })?;
+    let mut rotated_data = rotate(train_data, num_train, dim, &rotation);
+    for _ in 0..NUM_OPQ_ROUNDS {
+        let (pivot_data, train_codes) = train_chunk_pivots(
+            &rotated_data,
+            num_train,
+            dim,
+            num_centers,
+            chunk_offsets,
+            kmeans_params,
+        )?;
+        let reconstructed = reconstruct(&train_codes, num_train, dim, &pivot_data, chunk_offsets);
+
+        let mut cross = vec![0.0; dim * dim];
+        unsafe {
+            sgemm(
+                Layout::RowMajor,
+                Transpose::Ordinary,
+                Transpose::None,
+                dim as i32,
+                dim as i32,
+                num_train as i32,
+                1.0,
+                train_data,
+                dim as i32,
+                &reconstructed,
+                dim as i32,
+                0.0,
+                &mut cross,
+                dim as i32,
+            );
+        }
+
+        match polar_factor(&cross, dim) {
+            Some(next_rotation) => rotation = next_rotation,
+            // The data spans too few dimensions to pin down a rotation, keep the last one
+            None => break,
+        }
+        rotated_data = rotate(train_data, num_train, dim, &rotation);
+    }
+
+    let (full_pivot_data, _) = train_chunk_pivots(
+        &rotated_data,
+        num_train,
+        dim,
+        num_centers,
+        chunk_offsets,
+        kmeans_params,
+    )?;
+    Ok((full_pivot_data, rotation))
+}
+
+/// Rotate num_points vectors of dimension dim, row major, by the dim * dim rotation matrix
+pub(crate) fn rotate(data: &[f32], num_points: usize, dim: usize, rotation: &[f32]) -> Vec<f32> {
+    let mut rotated = vec![0.0; num_points * dim];
+    unsafe {
+        sgemm(
+            Layout::RowMajor,
+            Transpose::None,
+            Transpose::None,
+            num_points as i32,
+            dim as i32,
+            dim as i32,
+            1.0,
+            data,
+            dim as i32,
+            rotation,
+            dim as i32,
+            0.0,
+            &mut rotated,
+            dim as i32,
+        );
+    }
+    rotated
+}
+
+/// Random dim * dim rotation matrix, orthonormalizing random columns
+pub(super) fn random_rotation(dim: usize) -> Option<Vec<f32>> {
+    let mut rng = SmallRng::seed_from_u64(INITIAL_ROTATION_SEED);
+    let range = Uniform::new(-1.0f32, 1.0f32);
+    let mut matrix: Vec<f32> = (0..dim * dim).map(|_| range.sample(&mut rng)).collect();
+    orthonormalize_columns(&mut matrix, dim).then_some(matrix)
+}
+
+/// Replace every training vector by the pivots of its chunks
+pub(super) fn reconstruct(
+    train_codes: &[u32],
+    num_train: usize,
+    dim: usize,
+    pivot_data: &[f32],
+    chunk_offsets: &[usize],
+) -> Vec<f32> {
+    let num_pq_chunks = chunk_offsets.len() - 1;
+    let mut reconstructed = vec![0.0; num_train * dim];
+    for (train_data_index, vector) in reconstructed.chunks_exact_mut(dim).enumerate() {
+        for chunk_index in 0..num_pq_chunks {
+            let center = train_codes[train_data_index * num_pq_chunks + chunk_index] as usize;
+            let dims = chunk_offsets[chunk_index]..chunk_offsets[chunk_index + 1];
+            vector[dims.clone()]
+                .copy_from_slice(&pivot_data[center * dim + dims.start..center * dim + dims.end]);
+        }
+    }
+    reconstructed
+}
+
+/// Orthogonal polar factor U * V^T of the dim * dim matrix U * S * V^T, by Newton-Schulz
+/// iterations on the scaled matrix followed by a Gram-Schmidt pass on the columns.
+/// None if the matrix is too close to singular.
+fn polar_factor(matrix: &[f32], dim: usize) -> Option<Vec<f32>> {
+    let norm = matrix.iter().map(|value| value * value).sum::<f32>().sqrt();
+    if norm == 0.0 || !norm.is_finite() {
+        return None;
+    }
+
+    // With the Frobenius norm every singular value is at most 1, the iteration
+    // X <- X * (3 * I - X^T * X) / 2 then drives them all to 1
+    let mut polar: Vec<f32> = matrix.iter().map(|value| value / norm).collect();
+    let mut gram = vec![0.0; dim * dim];
+    let mut next = vec![0.0; dim * dim];
+    for _ in 0..MAX_POLAR_ITERATIONS {
+        unsafe {
+            sgemm(
+                Layout::RowMajor,
+                Transpose::Ordinary,
+                Transpose::None,
+                dim as i32,
+                dim as i32,
+                dim as i32,
+                1.0,
+                &polar,
+                dim as i32,
+                &polar,
+                dim as i32,
+                0.0,
+                &mut gram,
+                dim as i32,
+            );
+        }
+
+        let mut residual = 0.0;
+        for i in 0..dim {
+            for j in 0..dim {
+                let identity = if i == j { 1.0 } else { 0.0 };
+                let diff = gram[i * dim + j] - identity;
+                residual += diff * diff;
+                gram[i * dim + j] = 3.0 * identity - gram[i * dim + j];
+            }
+        }
+        if residual.sqrt() < POLAR_TOLERANCE {
+            break;
+        }
+
+        unsafe {
+            sgemm(
+                Layout::RowMajor,
+                Transpose::None,
+                Transpose::None,
+                dim as i32,
+                dim as i32,
+                dim as i32,
+                0.5,
+                &polar,
+                dim as i32,
+                &gram,
+                dim as i32,
+                0.0,
+                &mut next,
+                dim as i32,
+            );
+        }
+        std::mem::swap(&mut polar, &mut next);
+    }
+
+    orthonormalize_columns(&mut polar, dim).then_some(polar)
+}
+
+/// Modified Gram-Schmidt on the columns of the dim * dim row major matrix, false if a column
+/// is degenerate
+fn orthonormalize_columns(matrix: &mut [f32], dim: usize) -> bool {
+    for j in 0..dim {
+        for k in 0..j {
+            let dot: f32 = (0..dim)
+                .map(|i| matrix[i * dim + j] * matrix[i * dim + k])
+                .sum();
+            for i in 0..dim {
+                matrix[i * dim + j] -= dot * matrix[i * dim + k];
+            }
+        }
+
+        let norm = (0..dim)
+            .map(|i| matrix[i * dim + j] * matrix[i * dim + j])
+            .sum::<f32>()
+            .sqrt();
+        if norm < MIN_COLUMN_NORM {
+            return false;
+        }
+        for i in 0..dim {
+            matrix[i * dim + j] /= norm;
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod opq_test {
+    use rand::Rng;
+
+    use super::*;
+
+    fn assert_orthogonal(matrix: &[f32], dim: usize) {
+        for j in 0..dim {
+            for k in 0..dim {
+                let dot: f32 = (0..dim)
+                    .map(|i| matrix[i * dim + j] * matrix[i * dim + k])
+                    .sum();
+                let expected = if j == k { 1.0 } else { 0.0 };
+                assert!((dot - expected).abs() < 1e-4);
+            }
+        }
+    }
+
+    fn quantization_error(
+        data: &[f32],
+        pivot_data: &[f32],
+        num_centers: usize,
+        chunk_offsets: &[usize],
+    ) -> f32 {
+        let dim = *chunk_offsets.last().unwrap();
+        let num_points = data.len() / dim;
+        let num_pq_chunks = chunk_offsets.len() - 1;
+        let mut codes = vec![0; num_points * num_pq_chunks];
+        for (point, vector) in data.chunks_exact(dim).enumerate() {
+            for chunk_index in 0..num_pq_chunks {
+                let dims = chunk_offsets[chunk_index]..chunk_offsets[chunk_index + 1];
+                let distance = |center: usize| -> f32 {
+                    dims.clone()
+                        .map(|d| (vector[d] - pivot_data[center * dim + d]).powi(2))
+                        .sum()
+                };
+                codes[point * num_pq_chunks + chunk_index] = (0..num_centers)
+                    .min_by(|&a, &b| distance(a).total_cmp(&distance(b)))
+                    .unwrap() as u32;
+            }
+        }
+        let reconstructed = reconstruct(&codes, num_points, dim, pivot_data, chunk_offsets);
+        data.iter()
+            .zip(reconstructed.iter())
+            .map(|(a, b)| (a - b) * (a - b))
+            .sum::<f32>()
+            / num_points as f32
+    }
+
+    #[test]
+    fn polar_factor_is_orthogonal() {
+        let dim = 6;
+        let mut rng = rand::thread_rng();
+        let matrix: Vec<f32> = (0..dim * dim).map(|_| rng.gen_range(-1.0..1.0)).collect();
+        let polar = polar_factor(&matrix, dim).unwrap();
+        assert_orthogonal(&polar, dim);
+
+        // The polar factor of an orthogonal matrix is itself
+        let again = polar_factor(&polar, dim).unwrap();
+        for (a, b) in polar.iter().zip(again.iter()) {
+            assert!((a - b).abs() < 1e-4);
+        }
+
+        assert!(polar_factor(&vec![0.0; dim * dim], dim).is_none());
+        assert_orthogonal(&random_rotation(dim).unwrap(), dim);
+    }
+
+    #[test]
+    fn opq_quantizes_unbalanced_data_better_than_pq() {
+        // All the variance sits in the first chunk, plain PQ wastes the centers of the second
+        let (num_train, dim, num_centers) = (512, 8, 8);
+        let mut rng = rand::thread_rng();
+        let mut data: Vec<f32> = (0..num_train * dim)
+            .map(|i| {
+                if i % dim < dim / 2 {
+                    rng.gen_range(-10.0..10.0)
+                } else {
+                    rng.gen_range(-0.01..0.01)
+                }
+            })
+            .collect();
+        center_train_data(&mut data, num_train, dim);
+        let chunk_offsets = calculate_chunk_offsets(dim, 2);
+
+        let kmeans_params = KMeansParams::new(12);
+        let (pq_pivots, _) = train_chunk_pivots(
+            &data,
+            num_train,
+            dim,
+            num_centers,
+            &chunk_offsets,
+            kmeans_params,
+        )
+        .unwrap();
+        let pq_error = quantization_error(&data, &pq_pivots, num_centers, &chunk_offsets);
+
+        let (opq_pivots, rotation) = train_opq(
+            &data,
+            num_train,
+            dim,
+            num_centers,
+            &chunk_offsets,
+            kmeans_params,
+        )
+        .unwrap();
+        assert_orthogonal(&rotation, dim);
+        let rotated_data = rotate(&data, num_train, dim, &rotation);
+        let opq_error = quantization_error(&rotated_data, &opq_pivots, num_centers, &chunk_offsets);
+
+        assert!(
+            opq_error < pq_error * 0.5,
+            "OPQ error {} isn't below PQ error {}",
+            opq_error,
+            pq_error
+        );
+    }
+}
@@ -6,10 +6,20 @@
 
 use rayon::prelude::{IndexedParallelIterator, ParallelIterator};
 use rayon::slice::ParallelSliceMut;
+use serde::{Deserialize, Serialize};
 
 use crate::common::{ANNError, ANNResult};
+use crate::instrumentation::IndexLogger;
 use crate::storage::PQStorage;
-use crate::utils::{compute_closest_centers, file_exists, k_means_clustering};
+use crate::utils::{
+    compute_closest_centers, file_exists, k_means_clustering_with_params, step_seed, KMeansParams,
+    RandomStep,
+};
+
+use super::fixed_chunk_pq_table::pack_4bit_codes;
+
+use super::opq::{generate_opq_pivots, rotate};
+use super::pq_rotation::{generate_fixed_rotation_pivots, PQRotation};
 
 /// Max size of PQ training set
 pub const MAX_PQ_TRAINING_SET_SIZE: f64 = 256_000f64;
@@ -18,9 +28,90 @@ pub const MAX_PQ_TRAINING_SET_SIZE: f64 = 256_000f64;
 pub const MAX_PQ_CHUNKS: usize = 512;
 
 pub const NUM_PQ_CENTROIDS: usize = 256;
+
+/// Number of PQ centroids per chunk of 4-bit codes
+pub const NUM_PQ_4BIT_CENTROIDS: usize = 16;
+
+/// How the vectors the PQ pivots are trained on are drawn from the dataset
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PQTrainingSample {
+    /// Each vector with the given probability, for a sample whose size varies around
+    /// rate times the number of vectors
+    Rate(f64),
+
+    /// Exactly this many vectors, chosen uniformly by reservoir sampling in one pass over
+    /// the dataset, or all of them if it has fewer
+    Reservoir(usize),
+}
+
+impl PQTrainingSample {
+    /// Draw the training vectors from the dataset of pq_storage with seed, or from the OS when
+    /// it is None, returning them as floating point rows with their number and dimension
+    pub fn draw<T: Default + Copy + Into<f32>>(
+        self,
+        pq_storage: &PQStorage,
+        seed: Option<u64>,
+    ) -> ANNResult<(Vec<f32>, usize, usize)> {
+        match self {
+            PQTrainingSample::Rate(p_val) => pq_storage.gen_random_slice::<T>(p_val, seed),
+            PQTrainingSample::Reservoir(sample_size) => {
+                pq_storage.gen_reservoir_sample::<T>(sample_size, seed)
+            }
+        }
+    }
+}
+
+/// Width of the PQ code of a chunk
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PQCodeBits {
+    /// 256 centroids per chunk, one code per byte
+    #[default]
+    Eight,
+
+    /// 16 centroids per chunk, two codes per byte: the even chunk in the low nibble and the
+    /// odd chunk in the high nibble
+    Four,
+}
+
+impl PQCodeBits {
+    /// Code width of pivots trained with num_centers centroids per chunk
+    pub fn from_num_centers(num_centers: usize) -> ANNResult<Self> {
+        match num_centers {
+            NUM_PQ_CENTROIDS => Ok(PQCodeBits::Eight),
+            NUM_PQ_4BIT_CENTROIDS => Ok(PQCodeBits::Four),
+            _ => Err(ANNError::log_pq_error(format!(
+                "ERROR: {} PQ centers per chunk, expecting {} or {}.",
+                num_centers, NUM_PQ_CENTROIDS, NUM_PQ_4BIT_CENTROIDS
+            ))),
+        }
+    }
+
+    /// Centroids per chunk
+    pub fn num_centers(self) -> usize {
+        match self {
+            PQCodeBits::Eight => NUM_PQ_CENTROIDS,
+            PQCodeBits::Four => NUM_PQ_4BIT_CENTROIDS,
+        }
+    }
+
+    /// Chunks whose codes fit in a byte
+    pub fn codes_per_byte(self) -> usize {
+        match self {
+            PQCodeBits::Eight => 1,
+            PQCodeBits::Four => 2,
+        }
+    }
+
+    /// Bytes of the codes of a point
+    pub fn code_bytes(self, num_pq_chunks: usize) -> usize {
+        num_pq_chunks.div_ceil(self.codes_per_byte())
+    }
+}
 /// block size for reading/processing large files and matrices in blocks
 const BLOCK_SIZE: usize = 5000000;
-const NUM_KMEANS_REPS_PQ: usize = 12;
+/// Lloyd's iterations run on each chunk of the PQ training data
+const PQ_KMEANS_PARAMS: KMeansParams = KMeansParams::new(12);
 
 /// given training data in train_data of dimensions num_train * dim, generate
 /// PQ pivots using k-means algorithm to partition the co-ordinates into
@@ -34,7 +125,7 @@ fn generate_pq_pivots(
     dim: usize,
     num_centers: usize,
     num_pq_chunks: usize,
-    max_k_means_reps: usize,
+    kmeans_params: KMeansParams,
     pq_storage: &mut PQStorage,
 ) -> ANNResult<()> {
     if num_pq_chunks > dim {
@@ -43,7 +134,7 @@ fn generate_pq_pivots(
         ));
     }
 
-    if pq_storage.pivot_data_exist() {
+    if pq_storage.pivot_data_exist() && !pq_storage.rotation_matrix_exist() {
         let (file_num_centers, file_dim) = pq_storage.read_pivot_metadata()?;
         if file_dim == dim && file_num_centers == num_centers {
             // PQ pivot file exists. Not generating again.
@@ -51,6 +142,32 @@ fn generate_pq_pivots(
         }
     }
 
+    let centroid = center_train_data(train_data, num_train, dim);
+    let chunk_offsets = calculate_chunk_offsets(dim, num_pq_chunks);
+    let (full_pivot_data, _) = train_chunk_pivots(
+        train_data,
+        num_train,
+        dim,
+        num_centers,
+        &chunk_offsets,
+        kmeans_params,
+    )?;
+
+    // Pivots trained without rotation replace those of an earlier OPQ training
+    pq_storage.remove_rotation_matrix()?;
+    pq_storage.write_pivot_data(
+        &full_pivot_data,
+        &centroid,
+        &chunk_offsets,
+        num_centers,
+        dim,
+    )?;
+
+    Ok(())
+}
+
+/// Center the training data around its centroid, which is returned
+pub(super) fn center_train_data(train_data: &mut [f32], num_train: usize, dim: usize) -> Vec<f32> {
     // Calculate centroid and center the training data
     // If we use L2 distance, there is an option to
     // translate all vectors to make them centered and
@@ -70,7 +187,11 @@ fn generate_pq_pivots(
             train_data[train_data_index * dim + dim_index] -= centroid[dim_index];
         }
     }
+    centroid
+}
 
+/// Offsets of the dimensions of each chunk, splitting dim dimensions into num_pq_chunks chunks
+pub(super) fn calculate_chunk_offsets(dim: usize, num_pq_chunks: usize) -> Vec<usize> {
     // Calculate each chunk's offset
     // If we have 8 dimension and 3 chunk then offsets would be [0,3,6,8]
     let mut chunk_offsets: Vec<usize> = vec![0; num_pq_chunks + 1];
@@ -82,9 +203,26 @@ fn generate_pq_pivots(
         }
         chunk_offsets[chunk_index + 1] = chunk_offset;
     }
+    chunk_offsets
+}
 
+/// Run k-means in each chunk of the training data, chunk after chunk with each k-means
+/// parallel over the points. Returns the pivots, num_centers * dim row major, and the closest
+/// pivot of each training point in each chunk, num_train * num_pq_chunks row major.
+pub(super) fn train_chunk_pivots(
+    train_data: &[f32],
+    num_train: usize,
+    dim: usize,
+    num_centers: usize,
+    chunk_offsets: &[usize],
+    kmeans_params: KMeansParams,
+) -> ANNResult<(Vec<f32>, Vec<u32>)> {
+    let num_pq_chunks = chunk_offsets.len() - 1;
+    let logger = IndexLogger::for_stage("PQ Training", num_pq_chunks, 1);
     let mut full_pivot_data: Vec<f32> = vec![0.0; num_centers * dim];
+    let mut train_codes: Vec<u32> = vec![0; num_train * num_pq_chunks];
     for chunk_index in 0..num_pq_chunks {
+        logger.item_processed()?;
         let chunk_size = chunk_offsets[chunk_index + 1] - chunk_offsets[chunk_index];
 
         let mut cur_train_data: Vec<f32> = vec![0.0; num_train * chunk_size];
@@ -101,13 +239,13 @@ fn generate_pq_pivots(
             });
 
         // Run kmeans to get the centroids of this chunk.
-        let (_closest_docs, _closest_center, _residual) = k_means_clustering(
+        let (_closest_docs, closest_center, _residual) = k_means_clustering_with_params(
             &cur_train_data,
             num_train,
             chunk_size,
             &mut cur_pivot_data,
             num_centers,
-            max_k_means_reps,
+            kmeans_params,
         )?;
 
         // Copy centroids from this chunk table to full table
@@ -118,28 +256,23 @@ fn generate_pq_pivots(
                     &cur_pivot_data[center_index * chunk_size..(center_index + 1) * chunk_size],
                 );
         }
+        for (train_data_index, &center) in closest_center.iter().enumerate() {
+            train_codes[train_data_index * num_pq_chunks + chunk_index] = center;
+        }
     }
-
-    pq_storage.write_pivot_data(
-        &full_pivot_data,
-        &centroid,
-        &chunk_offsets,
-        num_centers,
-        dim,
-    )?;
-
-    Ok(())
+    Ok((full_pivot_data, train_codes))
 }
 
 /// streams the base file (data_file), and computes the closest centers in each
 /// chunk to generate the compressed data_file and stores it in
 /// pq_compressed_vectors_path.
 /// If the numbber of centers is < 256, it stores as byte vector, else as
-/// 4-byte vector in binary format.
+/// 4-byte vector in binary format. 4-bit codes are packed two per byte.
 /// Compressed PQ table layout: {num_points: usize}{num_chunks: usize}{compressed pq table: [num_points; num_chunks]}
 fn generate_pq_data_from_pivots<T: Copy + Into<f32>>(
     num_centers: usize,
     num_pq_chunks: usize,
+    code_bits: PQCodeBits,
     pq_storage: &mut PQStorage,
 ) -> ANNResult<()> {
     let (num_points, dim) = pq_storage.read_pq_data_metadata()?;
@@ -157,6 +290,8 @@ fn generate_pq_data_from_pivots<T: Copy + Into<f32>>(
             pq_storage.load_pivot_data(&num_pq_chunks, &num_centers, &dim)?;
     }
 
+    let rotation = pq_storage.load_rotation_matrix(dim)?;
+
     pq_storage.write_compressed_pivot_metadata(num_points as i32, num_pq_chunks as i32)?;
 
     let block_size = if num_points <= BLOCK_SIZE {
@@ -183,6 +318,9 @@ fn generate_pq_data_from_pivots<T: Copy + Into<f32>>(
                     block_data[block_data_index * dim + dim_index].into() - centroid[dim_index];
             }
         }
+        if let Some(rotation) = &rotation {
+            adjusted_block_data = rotate(&adjusted_block_data, cur_block_size, dim, rotation);
+        }
 
         for chunk_index in 0..num_pq_chunks {
             let cur_chunk_size = chunk_offsets[chunk_index + 1] - chunk_offsets[chunk_index];
@@ -236,27 +374,42 @@ fn generate_pq_data_from_pivots<T: Copy + Into<f32>>(
                 });
         }
 
-        _ = pq_storage.write_compressed_pivot_data(
-            &block_compressed_base,
-            num_centers,
-            cur_block_size,
-            num_pq_chunks,
-        );
+        if code_bits == PQCodeBits::Four {
+            let codes: Vec<u8> = block_compressed_base
+                .iter()
+                .map(|&code| code as u8)
+                .collect();
+            pq_storage.write_compressed_codes(&pack_4bit_codes(&codes, num_pq_chunks))?;
+        } else {
+            _ = pq_storage.write_compressed_pivot_data(
+                &block_compressed_base,
+                num_centers,
+                cur_block_size,
+                num_pq_chunks,
+            );
+        }
     }
     Ok(())
 }
 
 /// Save the data on a file.
 /// # Arguments
-/// * `p_val` - choose how many ratio sample data as trained data to get pivot
+/// * `training_sample` - how the vectors the pivots are trained on are sampled
 /// * `num_pq_chunks` - pq chunk number
+/// * `pq_rotation` - transform of the data before the pivots, learned or fixed
+/// * `code_bits` - width of the code of a chunk, 4-bit codes halve the compressed vectors
 /// * `codebook_prefix` - predefined pivots file named
 /// * `pq_storage` - pq file access
+/// * `seed` - seed of the build the sample and the pivots are drawn with, None draws from the OS
+#[allow(clippy::too_many_arguments)]
 pub fn generate_quantized_data<T: Default + Copy + Into<f32>>(
-    p_val: f64,
+    training_sample: PQTrainingSample,
     num_pq_chunks: usize,
+    pq_rotation: PQRotation,
+    code_bits: PQCodeBits,
     codebook_prefix: &str,
     pq_storage: &mut PQStorage,
+    seed: Option<u64>,
 ) -> ANNResult<()> {
     // If predefined pivots already exists, skip training.
     if !file_exists(codebook_prefix) {
@@ -264,19 +417,46 @@ pub fn generate_quantized_data<T: Default + Copy + Into<f32>>(
         // Training data with train_size samples loaded.
         // Each sampled file has train_dim.
         let (mut train_data_vector, train_size, train_dim) =
-            pq_storage.gen_random_slice::<T>(p_val)?;
-
-        generate_pq_pivots(
-            &mut train_data_vector,
-            train_size,
-            train_dim,
-            NUM_PQ_CENTROIDS,
-            num_pq_chunks,
-            NUM_KMEANS_REPS_PQ,
-            pq_storage,
-        )?;
+            training_sample.draw::<T>(pq_storage, step_seed(seed, RandomStep::PQTrainingSample))?;
+        let kmeans_params = PQ_KMEANS_PARAMS.with_seed(step_seed(seed, RandomStep::PQPivots));
+
+        match pq_rotation {
+            PQRotation::None => generate_pq_pivots(
+                &mut train_data_vector,
+                train_size,
+                train_dim,
+                code_bits.num_centers(),
+                num_pq_chunks,
+                kmeans_params,
+                pq_storage,
+            )?,
+            PQRotation::Learned => generate_opq_pivots(
+                &mut train_data_vector,
+                train_size,
+                train_dim,
+                code_bits.num_centers(),
+                num_pq_chunks,
+                kmeans_params,
+                pq_storage,
+            )?,
+            PQRotation::Random | PQRotation::Permutation => generate_fixed_rotation_pivots(
+                &mut train_data_vector,
+                train_size,
+                train_dim,
+                code_bits.num_centers(),
+                num_pq_chunks,
+                kmeans_params,
+                pq_rotation,
+                pq_storage,
+            )?,
+        }
     }
-    generate_pq_data_from_pivots::<T>(NUM_PQ_CENTROIDS, num_pq_chunks, pq_storage)?;
+    generate_pq_data_from_pivots::<T>(
+        code_bits.num_centers(),
+        num_pq_chunks,
+        code_bits,
+        pq_storage,
+    )?;
     Ok(())
 }
 
@@ -287,7 +467,12 @@ mod pq_test {
     use std::io::Write;
 
     use super::*;
-    use crate::utils::{convert_types_u32_usize, convert_types_u64_usize, load_bin, METADATA_SIZE};
+    use crate::model::{
+        pq_dist_lookup, pq_dist_lookup_packed4, unpack_4bit_codes, FixedChunkPQTable,
+    };
+    use crate::utils::{
+        calc_distance, convert_types_u32_usize, convert_types_u64_usize, load_bin, METADATA_SIZE,
+    };
 
     #[test]
     fn generate_pq_pivots_test() {
@@ -302,7 +487,16 @@ mod pq_test {
             2.1f32, 2.1f32, 2.2f32, 2.2f32, 2.2f32, 2.2f32, 2.2f32, 2.2f32, 2.2f32, 2.2f32,
             100.0f32, 100.0f32, 100.0f32, 100.0f32, 100.0f32, 100.0f32, 100.0f32, 100.0f32,
         ];
-        generate_pq_pivots(&mut train_data, 5, 8, 2, 2, 5, &mut pq_storage).unwrap();
+        generate_pq_pivots(
+            &mut train_data,
+            5,
+            8,
+            2,
+            2,
+            KMeansParams::new(5),
+            &mut pq_storage,
+        )
+        .unwrap();
 
         let (data, nr, nc) = load_bin::<u64>(pivot_file_name, 0).unwrap();
         let file_offset_data = convert_types_u64_usize(&data, nr, nc);
@@ -363,8 +557,17 @@ mod pq_test {
         let pq_compressed_vectors_path = "generate_pq_data_from_pivots_test.bin";
         let mut pq_storage =
             PQStorage::new(pq_pivots_path, pq_compressed_vectors_path, data_file).unwrap();
-        generate_pq_pivots(&mut train_data, 5, 8, 2, 2, 5, &mut pq_storage).unwrap();
-        generate_pq_data_from_pivots::<f32>(2, 2, &mut pq_storage).unwrap();
+        generate_pq_pivots(
+            &mut train_data,
+            5,
+            8,
+            2,
+            2,
+            KMeansParams::new(5),
+            &mut pq_storage,
+        )
+        .unwrap();
+        generate_pq_data_from_pivots::<f32>(2, 2, PQCodeBits::Eight, &mut pq_storage).unwrap();
         let (data, nr, nc) = load_bin::<u8>(pq_compressed_vectors_path, 0).unwrap();
         assert_eq!(nr, 5);
         assert_eq!(nc, 2);
@@ -376,6 +579,146 @@ mod pq_test {
         std::fs::remove_file(pq_compressed_vectors_path).unwrap();
     }
 
+    #[test]
+    fn generate_opq_quantized_data_test() {
+        let data_file = "generate_opq_quantized_data_test_data.bin";
+        let pq_pivots_path = "generate_opq_quantized_data_test_pivots.bin";
+        let pq_compressed_vectors_path = "generate_opq_quantized_data_test.bin";
+        let (num_points, dim) = (300, 8);
+        let data: Vec<f32> = (0..num_points * dim)
+            .map(|i| ((i * 37 % 101) as f32) * if i % dim < 2 { 1.0 } else { 0.01 })
+            .collect();
+        crate::utils::save_bin_f32(data_file, &data, num_points, dim, 0).unwrap();
+
+        let mut pq_storage =
+            PQStorage::new(pq_pivots_path, pq_compressed_vectors_path, data_file).unwrap();
+        generate_quantized_data::<f32>(
+            PQTrainingSample::Rate(1.0),
+            2,
+            PQRotation::Learned,
+            PQCodeBits::Eight,
+            "",
+            &mut pq_storage,
+            None,
+        )
+        .unwrap();
+        let rotation = pq_storage.load_rotation_matrix(dim).unwrap().unwrap();
+        assert_eq!(rotation.len(), dim * dim);
+
+        // Codes decode back close to the vectors, rotated back to the original space
+        let (pivots, centroid, chunk_offsets) = pq_storage
+            .load_pivot_data(&2, &NUM_PQ_CENTROIDS, &dim)
+            .unwrap();
+        let pq_table =
+            FixedChunkPQTable::new(dim, 2, pivots, centroid, chunk_offsets).with_rotation(rotation);
+        let (codes, nr, nc) = load_bin::<u8>(pq_compressed_vectors_path, 0).unwrap();
+        assert_eq!((nr, nc), (num_points, 2));
+        let mut error = 0.0;
+        for point in 0..num_points {
+            let code = &codes[point * 2..point * 2 + 2];
+            let inflated = pq_table.inflate_vector(code).unwrap();
+            let vector = &data[point * dim..(point + 1) * dim];
+            error += calc_distance(&inflated, vector, dim);
+
+            let mut query = vector.to_vec();
+            pq_table.preprocess_query(&mut query);
+            let distances = pq_table.populate_chunk_distances(&query);
+            let pq_distance = pq_dist_lookup(code, 1, 2, &distances)[0];
+            assert!((pq_distance - calc_distance(&inflated, vector, dim)).abs() < 1e-2);
+        }
+        assert!(error / (num_points as f32) < 1.0);
+
+        // Plain PQ training drops the rotation
+        let mut pq_storage =
+            PQStorage::new(pq_pivots_path, pq_compressed_vectors_path, data_file).unwrap();
+        generate_quantized_data::<f32>(
+            PQTrainingSample::Rate(1.0),
+            2,
+            PQRotation::None,
+            PQCodeBits::Eight,
+            "",
+            &mut pq_storage,
+            None,
+        )
+        .unwrap();
+        assert!(!pq_storage.rotation_matrix_exist());
+
+        // A permutation of the dimensions is saved as the rotation of the pivots
+        let mut pq_storage =
+            PQStorage::new(pq_pivots_path, pq_compressed_vectors_path, data_file).unwrap();
+        generate_quantized_data::<f32>(
+            PQTrainingSample::Rate(1.0),
+            2,
+            PQRotation::Permutation,
+            PQCodeBits::Eight,
+            "",
+            &mut pq_storage,
+            None,
+        )
+        .unwrap();
+        let permutation = pq_storage.load_rotation_matrix(dim).unwrap().unwrap();
+        for row in permutation.chunks_exact(dim) {
+            assert_eq!(row.iter().filter(|&&value| value == 1.0).count(), 1);
+            assert_eq!(row.iter().sum::<f32>(), 1.0);
+        }
+
+        std::fs::remove_file(data_file).unwrap();
+        std::fs::remove_file(pq_pivots_path).unwrap();
+        std::fs::remove_file(pq_compressed_vectors_path).unwrap();
+        pq_storage.remove_rotation_matrix().unwrap();
+    }
+
+    #[test]
+    fn generate_4bit_quantized_data_test() {
+        let data_file = "generate_4bit_quantized_data_test_data.bin";
+        let pq_pivots_path = "generate_4bit_quantized_data_test_pivots.bin";
+        let pq_compressed_vectors_path = "generate_4bit_quantized_data_test.bin";
+        let (num_points, dim, num_chunks) = (200, 6, 3);
+        let data: Vec<f32> = (0..num_points * dim)
+            .map(|i| ((i * 37 % 101) as f32) / 10.0)
+            .collect();
+        crate::utils::save_bin_f32(data_file, &data, num_points, dim, 0).unwrap();
+
+        let mut pq_storage =
+            PQStorage::new(pq_pivots_path, pq_compressed_vectors_path, data_file).unwrap();
+        generate_quantized_data::<f32>(
+            PQTrainingSample::Reservoir(150),
+            num_chunks,
+            PQRotation::None,
+            PQCodeBits::Four,
+            "",
+            &mut pq_storage,
+            None,
+        )
+        .unwrap();
+
+        // Three chunks take two bytes per point
+        let compressed = std::fs::read(pq_compressed_vectors_path).unwrap();
+        assert_eq!(compressed.len(), 8 + num_points * 2);
+        let codes = unpack_4bit_codes(&compressed[8..], num_chunks);
+        assert!(codes.iter().all(|&code| code < 16));
+
+        let (pivots, centroid, chunk_offsets) = pq_storage
+            .load_pivot_data(&num_chunks, &NUM_PQ_4BIT_CENTROIDS, &dim)
+            .unwrap();
+        let pq_table = FixedChunkPQTable::new(dim, num_chunks, pivots, centroid, chunk_offsets)
+            .with_code_bits(PQCodeBits::Four);
+        let mut query = data[..dim].to_vec();
+        pq_table.preprocess_query(&mut query);
+        let distances = pq_table.populate_chunk_distances(&query);
+        let pq_distances =
+            pq_dist_lookup_packed4(&compressed[8..], num_points, num_chunks, &distances);
+        for point in 0..num_points {
+            let code = &codes[point * num_chunks..(point + 1) * num_chunks];
+            let expected = pq_table.l2_distance(&query, code);
+            assert!((pq_distances[point] - expected).abs() <= 1e-3 * expected.max(1.0));
+        }
+
+        std::fs::remove_file(data_file).unwrap();
+        std::fs::remove_file(pq_pivots_path).unwrap();
+        std::fs::remove_file(pq_compressed_vectors_path).unwrap();
+    }
+
     #[test]
     fn pq_end_to_end_validation_with_codebook_test() {
         let data_file = "tests/data/siftsmall_learn.bin";
@@ -384,7 +727,16 @@ mod pq_test {
         let pq_compressed_vectors_path = "validation.bin";
         let mut pq_storage =
             PQStorage::new(pq_pivots_path, pq_compressed_vectors_path, data_file).unwrap();
-        generate_quantized_data::<f32>(0.5, 1, pq_pivots_path, &mut pq_storage).unwrap();
+        generate_quantized_data::<f32>(
+            PQTrainingSample::Rate(0.5),
+            1,
+            PQRotation::None,
+            PQCodeBits::Eight,
+            pq_pivots_path,
+            &mut pq_storage,
+            None,
+        )
+        .unwrap();
 
         let (data, nr, nc) = load_bin::<u8>(pq_compressed_vectors_path, 0).unwrap();
         let (gt_data, gt_nr, gt_nc) = load_bin::<u8>(gound_truth_path, 0).unwrap();
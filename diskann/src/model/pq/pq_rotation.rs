@@ -0,0 +1,235 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Fixed orthogonal transforms of the vectors applied before PQ chunking.
+//!
+//! PQ gives every chunk the same number of centers, so chunks of low variance waste theirs
+//! while chunks holding most of the variance quantize poorly. Instead of learning a rotation
+//! like OPQ, the vectors can be spread over the chunks in a single pass: a random rotation
+//! mixes every dimension into every chunk, and a permutation deals the dimensions out so
+//! that the chunks get about the same total variance while keeping each dimension intact.
+//! Both are saved as the rotation matrix of the pivots, which encoding and search apply to
+//! the centered vectors.
+
+use serde::{Deserialize, Serialize};
+
+use crate::common::{ANNError, ANNResult};
+use crate::storage::PQStorage;
+use crate::utils::KMeansParams;
+
+use super::opq::{random_rotation, rotate};
+use super::pq_construction::{calculate_chunk_offsets, center_train_data, train_chunk_pivots};
+
+/// Transform of the centered vectors before they are split into PQ chunks
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PQRotation {
+    /// Chunk the vectors as they are
+    #[default]
+    None,
+
+    /// Learn the rotation with the pivots, see generate_opq_pivots
+    Learned,
+
+    /// Seeded random rotation
+    Random,
+
+    /// Permutation of the dimensions balancing the variance of the chunks
+    Permutation,
+}
+
+/// Generate the pivots of the training data train_data of dimensions num_train * dim after a
+/// random rotation or a dimension permutation, and store them with the dim * dim matrix of
+/// the transform in the pivot and rotation matrix files of pq_storage
+#[allow(clippy::too_many_arguments)]
+pub(super) fn generate_fixed_rotation_pivots(
+    train_data: &mut [f32],
+    num_train: usize,
+    dim: usize,
+    num_centers: usize,
+    num_pq_chunks: usize,
+    kmeans_params: KMeansParams,
+    pq_rotation: PQRotation,
+    pq_storage: &mut PQStorage,
+) -> ANNResult<()> {
+    if num_pq_chunks > dim {
+        return Err(ANNError::log_pq_error(
+            "Error: number of chunks more than dimension.".to_string(),
+        ));
+    }
+
+    if pq_storage.pivot_data_exist() && pq_storage.rotation_matrix_exist() {
+        let (file_num_centers, file_dim) = pq_storage.read_pivot_metadata()?;
+        if file_dim == dim && file_num_centers == num_centers {
+            // Pivot and rotation files exist. Not generating again.
+            return Ok(());
+        }
+    }
+
+    let centroid = center_train_data(train_data, num_train, dim);
+    let chunk_offsets = calculate_chunk_offsets(dim, num_pq_chunks);
+    let rotation = match pq_rotation {
+        PQRotation::Random => random_rotation(dim).ok_or_else(|| {
+            ANNError::log_pq_error(format!(
+                "Error: no random rotation of dimension {} could be orthonormalized.",
+                dim
+            ))
+        })?,
+        PQRotation::Permutation => {
+            let order = variance_balanced_permutation(train_data, num_train, dim, &chunk_offsets);
+            permutation_matrix(&order)
+        }
+        PQRotation::None | PQRotation::Learned => {
+            return Err(ANNError::log_pq_error(format!(
+                "Error: {:?} isn't a fixed rotation.",
+                pq_rotation
+            )))
+        }
+    };
+
+    let rotated = rotate(train_data, num_train, dim, &rotation);
+    let (full_pivot_data, _) = train_chunk_pivots(
+        &rotated,
+        num_train,
+        dim,
+        num_centers,
+        &chunk_offsets,
+        kmeans_params,
+    )?;
+
+    pq_storage.write_pivot_data(
+        &full_pivot_data,
+        &centroid,
+        &chunk_offsets,
+        num_centers,
+        dim,
+    )?;
+    pq_storage.write_rotation_matrix(&rotation, dim)?;
+
+    Ok(())
+}
+
+/// Order of the dimensions of the centered training data placing each dimension, from the
+/// highest variance down, in the chunk with the lowest total variance that has room left.
+/// Position j of the permuted vectors holds dimension order[j].
+fn variance_balanced_permutation(
+    train_data: &[f32],
+    num_train: usize,
+    dim: usize,
+    chunk_offsets: &[usize],
+) -> Vec<usize> {
+    let mut variances = vec![0.0f64; dim];
+    for vector in train_data.chunks_exact(dim).take(num_train) {
+        for (variance, &value) in variances.iter_mut().zip(vector) {
+            *variance += (value as f64) * (value as f64);
+        }
+    }
+    let mut dims: Vec<usize> = (0..dim).collect();
+    dims.sort_by(|&a, &b| variances[b].total_cmp(&variances[a]).then(a.cmp(&b)));
+
+    let num_pq_chunks = chunk_offsets.len() - 1;
+    let mut chunk_dims: Vec<Vec<usize>> = vec![Vec::new(); num_pq_chunks];
+    let mut chunk_variances = vec![0.0f64; num_pq_chunks];
+    for d in dims {
+        let chunk_index = (0..num_pq_chunks)
+            .filter(|&chunk| {
+                chunk_dims[chunk].len() < chunk_offsets[chunk + 1] - chunk_offsets[chunk]
+            })
+            .min_by(|&a, &b| chunk_variances[a].total_cmp(&chunk_variances[b]))
+            .unwrap_or(0);
+        chunk_dims[chunk_index].push(d);
+        chunk_variances[chunk_index] += variances[d];
+    }
+
+    chunk_dims.into_iter().flatten().collect()
+}
+
+/// dim * dim matrix R moving dimension order[j] of a vector to position j of the vector * R
+fn permutation_matrix(order: &[usize]) -> Vec<f32> {
+    let dim = order.len();
+    let mut matrix = vec![0.0; dim * dim];
+    for (j, &d) in order.iter().enumerate() {
+        matrix[d * dim + j] = 1.0;
+    }
+    matrix
+}
+
+#[cfg(test)]
+mod pq_rotation_test {
+    use rand::Rng;
+
+    use super::*;
+    use crate::model::pq::opq::reconstruct;
+
+    /// Mean squared distance of the training vectors to their reconstruction by the pivots
+    fn quantization_error(
+        data: &[f32],
+        num_train: usize,
+        dim: usize,
+        num_centers: usize,
+        chunk_offsets: &[usize],
+    ) -> f32 {
+        let (pivots, codes) = train_chunk_pivots(
+            data,
+            num_train,
+            dim,
+            num_centers,
+            chunk_offsets,
+            KMeansParams::new(12),
+        )
+        .unwrap();
+        let reconstructed = reconstruct(&codes, num_train, dim, &pivots, chunk_offsets);
+        data.iter()
+            .zip(reconstructed.iter())
+            .map(|(a, b)| (a - b) * (a - b))
+            .sum::<f32>()
+            / num_train as f32
+    }
+
+    #[test]
+    fn permutation_balances_chunk_variance() {
+        // The first half of the dimensions has all the variance and fills the first chunk
+        let (num_train, dim, num_centers) = (512, 8, 8);
+        let mut rng = rand::thread_rng();
+        let mut data: Vec<f32> = (0..num_train * dim)
+            .map(|i| {
+                if i % dim < dim / 2 {
+                    rng.gen_range(-10.0..10.0)
+                } else {
+                    rng.gen_range(-0.01..0.01)
+                }
+            })
+            .collect();
+        center_train_data(&mut data, num_train, dim);
+        let chunk_offsets = calculate_chunk_offsets(dim, 2);
+
+        let order = variance_balanced_permutation(&data, num_train, dim, &chunk_offsets);
+        let mut sorted = order.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, (0..dim).collect::<Vec<_>>());
+        for chunk in order.chunks_exact(dim / 2) {
+            assert_eq!(chunk.iter().filter(|&&d| d < dim / 2).count(), 2);
+        }
+
+        let permutation = permutation_matrix(&order);
+        let permuted = rotate(&data, num_train, dim, &permutation);
+        for (vector, permuted_vector) in data.chunks_exact(dim).zip(permuted.chunks_exact(dim)) {
+            for (j, &d) in order.iter().enumerate() {
+                assert_eq!(permuted_vector[j], vector[d]);
+            }
+        }
+
+        let pq_error = quantization_error(&data, num_train, dim, num_centers, &chunk_offsets);
+        let permuted_error =
+            quantization_error(&permuted, num_train, dim, num_centers, &chunk_offsets);
+        assert!(
+            permuted_error < pq_error,
+            "Permuted PQ error {} isn't below PQ error {}",
+            permuted_error,
+            pq_error
+        );
+    }
+}
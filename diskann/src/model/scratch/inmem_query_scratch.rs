@@ -62,6 +62,9 @@ pub struct InMemQueryScratch<T, const N: usize> {
     /// Visited neighbor id
     pub id_scratch: Vec<u32>,
 
+    /// Neighbors of the node being expanded, copied out of its lock
+    pub neighbor_scratch: Vec<u32>,
+
     /// The distance between visited neighbor and query node
     pub dist_scratch: Vec<f32>,
 
@@ -109,6 +112,7 @@ impl<T: Default + Copy, const N: usize> InMemQueryScratch<T, N> {
 
         let capacity = (1.5 * GRAPH_SLACK_FACTOR * (max_degree as f64)).ceil() as usize;
         let id_scratch = Vec::with_capacity(capacity);
+        let neighbor_scratch = Vec::with_capacity(capacity);
         let dist_scratch = Vec::with_capacity(capacity);
 
         let expanded_nodes_set = HashSet::<u32>::new();
@@ -125,6 +129,7 @@ impl<T: Default + Copy, const N: usize> InMemQueryScratch<T, N> {
             best_candidates: NeighborPriorityQueue::with_capacity(candidate_size as usize),
             occlude_factor,
             id_scratch,
+            neighbor_scratch,
             dist_scratch,
             pq_scratch,
             expanded_nodes_set,
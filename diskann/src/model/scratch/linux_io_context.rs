@@ -2,7 +2,7 @@ use std::sync::Arc;
 use tokio::fs::File;
 use tokio::sync::Mutex;
 
-use crate::common::ANNError;
+use crate::common::{ANNError, ANNResult};
 
 /// LinuxIOContext holds a shared file handle (an Arc<File>)
 /// guarded by a mutex so that seek/read operations can be serialized.
@@ -11,21 +11,6 @@ pub struct LinuxIOContext {
     pub file: Mutex<Arc<File>>,
 }
 
-impl Default for LinuxIOContext {
-    fn default() -> Self {
-        // Because File::open is async, we create a temporary Tokio runtime to open "/dev/null".
-        let rt = tokio::runtime::Runtime::new().expect("Failed to create Tokio runtime");
-        let default_file = rt
-            .block_on(File::open("/dev/null"))
-            .expect("Failed to open /dev/null");
-        LinuxIOContext {
-            status: Status::ReadWait,
-            // Wrap the file (now a File) in an Arc and then in a Mutex.
-            file: Mutex::new(Arc::new(default_file)),
-        }
-    }
-}
-
 impl LinuxIOContext {
     /// Accepts an Arc<File> (as produced by LinuxAlignedFileReader) and stores it in a mutex.
     pub fn new(file: Arc<File>) -> Self {
@@ -34,6 +19,13 @@ impl LinuxIOContext {
             file: Mutex::new(file),
         }
     }
+
+    /// A context over /dev/null, for scratch that has no file yet. Opened synchronously, so no
+    /// runtime is needed or created.
+    pub fn null() -> ANNResult<Self> {
+        let file = std::fs::File::open("/dev/null").map_err(ANNError::log_io_error)?;
+        Ok(Self::new(Arc::new(File::from_std(file))))
+    }
 }
 
 /// The various statuses for the IO context.
@@ -43,3 +35,14 @@ pub enum Status {
     ReadFailed(ANNError),
     ProcessComplete,
 }
+
+#[cfg(test)]
+mod linux_io_context_test {
+    use super::*;
+
+    #[test]
+    fn null_context_waits_to_read() {
+        let context = LinuxIOContext::null().unwrap();
+        assert!(matches!(context.status, Status::ReadWait));
+    }
+}
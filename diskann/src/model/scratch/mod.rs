@@ -18,14 +18,22 @@ pub use inmem_query_scratch::*;
 pub mod scratch_store_manager;
 pub use scratch_store_manager::*;
 
+#[cfg(feature = "disk-index")]
 pub mod ssd_query_scratch;
+#[cfg(feature = "disk-index")]
 pub use ssd_query_scratch::*;
 
+#[cfg(feature = "disk-index")]
 pub mod ssd_thread_data;
+#[cfg(feature = "disk-index")]
 pub use ssd_thread_data::*;
 
+#[cfg(feature = "disk-index")]
 pub mod ssd_io_context;
+#[cfg(feature = "disk-index")]
 pub use ssd_io_context::*;
 
+#[cfg(feature = "disk-index")]
 pub mod linux_io_context;
+#[cfg(feature = "disk-index")]
 pub use linux_io_context::*;
@@ -0,0 +1,84 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Rich search result returned by a single search call
+
+use crate::model::data_store::Tag;
+
+/// Optional fields to populate in each SearchResult.
+/// Id and distance are always populated.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SearchResultFields {
+    /// Populate the labels attached to each result point
+    pub labels: bool,
+
+    /// Populate the payload bytes attached to each result point
+    pub payload: bool,
+
+    /// Populate the tag of each result point
+    pub tags: bool,
+}
+
+impl SearchResultFields {
+    /// Only ids and distances
+    pub const NONE: Self = Self {
+        labels: false,
+        payload: false,
+        tags: false,
+    };
+
+    /// Every optional field
+    pub const ALL: Self = Self {
+        labels: true,
+        payload: true,
+        tags: true,
+    };
+}
+
+/// One result of a search
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchResult {
+    /// Vertex id of the point in the index, not its external id, which is its tag
+    pub id: u32,
+
+    /// Distance from the query to the point
+    pub distance: f32,
+
+    /// Labels attached to the point, None if not requested
+    pub labels: Option<Vec<u32>>,
+
+    /// Payload attached to the point, None if not requested
+    pub payload: Option<Vec<u8>>,
+
+    /// Tag of the point from the tag store, None if not requested or the point has none
+    pub tag: Option<Tag>,
+}
+
+impl SearchResult {
+    /// Create a result with only id and distance populated
+    pub fn new(id: u32, distance: f32) -> Self {
+        Self {
+            id,
+            distance,
+            labels: None,
+            payload: None,
+            tag: None,
+        }
+    }
+}
+
+/// One document returned by a document search
+#[derive(Debug, Clone, PartialEq)]
+pub struct DocumentMatch {
+    /// External id of the document
+    pub document: Tag,
+
+    /// Distance from the query to the document, aggregated over its vectors
+    pub distance: f32,
+
+    /// Id of the vector of the document nearest to the query
+    pub best_vertex_id: u32,
+}
@@ -20,3 +20,25 @@ pub const DIM_128: usize = 128;
 
 /// 256 vertex dimension
 pub const DIM_256: usize = 256;
+
+/// 384 vertex dimension
+pub const DIM_384: usize = 384;
+
+/// 768 vertex dimension
+pub const DIM_768: usize = 768;
+
+/// 1024 vertex dimension
+pub const DIM_1024: usize = 1024;
+
+/// 1536 vertex dimension
+pub const DIM_1536: usize = 1536;
+
+/// Dimensions the in-memory index and its distance kernels are compiled for, in increasing order
+pub const SPECIALIZED_DIMS: [usize; 7] =
+    [DIM_104, DIM_128, DIM_256, DIM_384, DIM_768, DIM_1024, DIM_1536];
+
+/// Smallest specialized dimension that can hold vectors of aligned_dim, None if it's too large.
+/// The extra dimensions are zero padding, which doesn't change L2, L1, Chebyshev or cosine distances.
+pub fn specialized_dimension(aligned_dim: usize) -> Option<usize> {
+    SPECIALIZED_DIMS.iter().copied().find(|dim| *dim >= aligned_dim)
+}
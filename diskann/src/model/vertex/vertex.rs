@@ -43,7 +43,7 @@ where
 
     /// Get the vector associated with the vertex.
     #[inline]
-    pub fn vector(&self) -> &[T; N] {
+    pub fn vector(&self) -> &'a [T; N] {
         self.val
     }
 
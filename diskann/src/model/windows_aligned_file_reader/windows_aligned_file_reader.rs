@@ -26,6 +26,8 @@ use winapi::{
 #[cfg(target_os = "windows")]
 use crate::common::{ANNError, ANNResult};
 use crate::model::IOContext;
+#[cfg(target_os = "windows")]
+use crate::model::{AlignedRange, AlignedRead};
 
 #[cfg(target_os = "windows")]
 pub const MAX_IO_CONCURRENCY: usize = 128; // To do: explore the optimal value for this. The current value is taken from C++ code.
@@ -135,7 +137,11 @@ impl WindowsAlignedFileReader {
                 } {
                     Ok(_) => {}
                     Err(error) => {
-                        return Err(ANNError::IOError { err: (error) });
+                        return Err(ANNError::IOError { err: (error) }.wrap(format!(
+                            "Reading {} bytes at offset {}",
+                            req.aligned_buf.len(),
+                            req.offset
+                        )));
                     }
                 }
             }
@@ -168,6 +174,15 @@ impl WindowsAlignedFileReader {
 
         Ok(())
     }
+
+    // Read len bytes at offset, neither of which need be aligned, by reading the aligned range
+    // around them and copying the requested bytes out of it.
+    pub fn read_range(&self, offset: u64, len: usize, ctx: &IOContext) -> ANNResult<Vec<u8>> {
+        let range = AlignedRange::covering(offset, len);
+        let mut read_requests = [AlignedRead::new(range.offset, vec![0u8; range.len])?];
+        self.read(&mut read_requests, ctx)?;
+        Ok(range.requested(&read_requests[0].aligned_buf)?.to_vec())
+    }
 }
 
 #[cfg(target_os = "windows")]
@@ -56,4 +56,16 @@ impl DiskGraphStorage {
         self.disk_graph_reader.read(read_requests).await?;
         Ok(())
     }
+
+    /// Read len bytes at offset, neither of which need be aligned
+    #[cfg(target_os = "windows")]
+    pub async fn read_range(&self, offset: u64, len: usize) -> ANNResult<Vec<u8>> {
+        self.disk_graph_reader.read_range(offset, len, &self.ctx)
+    }
+
+    /// Read len bytes at offset, neither of which need be aligned
+    #[cfg(target_os = "linux")]
+    pub async fn read_range(&self, offset: u64, len: usize) -> ANNResult<Vec<u8>> {
+        self.disk_graph_reader.read_range(offset, len).await
+    }
 }
@@ -6,11 +6,13 @@ use byteorder::{ByteOrder, LittleEndian, ReadBytesExt};
 use std::fs::File;
 use std::io::Read;
 use std::marker::PhantomData;
+use std::ops::Range;
 use std::{fs, mem};
 
-use crate::common::{ANNError, ANNResult};
-use crate::model::NUM_PQ_CENTROIDS;
-use crate::storage::PQStorage;
+use crate::common::{ANNError, ANNResult, ANNResultExt};
+use crate::model::graph::{GraphExportFormat, GraphExportSummary, GraphExporter};
+use crate::model::{FixedChunkPQTable, PQCodeBits};
+use crate::storage::{PQStorage, SectorUsageStats};
 use crate::utils::{convert_types_u32_usize, convert_types_u64_usize, load_bin, save_bin_u64};
 use crate::utils::{
     file_exists, gen_sample_data, get_file_size, round_up, CachedReader, CachedWriter,
@@ -18,13 +20,119 @@ use crate::utils::{
 
 const SECTOR_LEN: usize = 4096;
 
-/// Todo: Remove the allow(dead_code) when the disk search code is complete
-#[allow(dead_code)]
 pub struct PQPivotData {
     dim: usize,
     pq_table: Vec<f32>,
     centroids: Vec<f32>,
     chunk_offsets: Vec<usize>,
+    rotation: Option<Vec<f32>>,
+    code_bits: PQCodeBits,
+}
+
+impl PQPivotData {
+    /// Create the PQ table computing query distances from the pivots
+    pub fn into_pq_table(self, num_pq_chunks: usize) -> FixedChunkPQTable {
+        let pq_table = FixedChunkPQTable::new(
+            self.dim,
+            num_pq_chunks,
+            self.pq_table,
+            self.centroids,
+            self.chunk_offsets,
+        )
+        .with_code_bits(self.code_bits);
+        match self.rotation {
+            Some(rotation) => pq_table.with_rotation(rotation),
+            None => pq_table,
+        }
+    }
+}
+
+/// Metadata at the start of the first sector of the disk index
+#[derive(Debug, Clone, Copy)]
+pub struct DiskLayoutMeta {
+    /// Number of points
+    pub num_pts: usize,
+
+    /// Dimension of the full precision vectors
+    pub dim: usize,
+
+    /// Id of the medoid, the entry point of the search
+    pub medoid: u32,
+
+    /// Size of a node in bytes: vector, number of neighbors and the neighbors
+    pub max_node_len: usize,
+
+    /// Number of nodes stored in one sector, 0 for nodes larger than a sector, each of which
+    /// starts a sector and takes as many sectors as it needs
+    pub num_nodes_per_sector: usize,
+
+    /// Id of the frozen point, which is not a data point
+    pub frozen_point: Option<u32>,
+}
+
+impl DiskLayoutMeta {
+    /// Number of sectors including the metadata sector 0
+    pub fn num_sectors(&self) -> usize {
+        match self.num_nodes_per_sector {
+            0 => self.num_pts * self.sectors_per_node() + 1,
+            per_sector => round_up(self.num_pts, per_sector) / per_sector + 1,
+        }
+    }
+
+    /// Number of sectors read to get a node, more than one for nodes larger than a sector
+    pub fn sectors_per_node(&self) -> usize {
+        match self.num_nodes_per_sector {
+            0 => self.max_node_len.div_ceil(SECTOR_LEN),
+            _ => 1,
+        }
+    }
+
+    /// First sector holding a node
+    pub fn node_sector(&self, id: u32) -> usize {
+        match self.num_nodes_per_sector {
+            0 => 1 + id as usize * self.sectors_per_node(),
+            per_sector => 1 + id as usize / per_sector,
+        }
+    }
+
+    /// Byte offset of a node in its first sector
+    pub fn node_offset_in_sector(&self, id: u32) -> usize {
+        match self.num_nodes_per_sector {
+            0 => 0,
+            per_sector => (id as usize % per_sector) * self.max_node_len,
+        }
+    }
+
+    /// Nodes starting in a sector, none for the metadata sector and the sectors continuing a
+    /// node larger than a sector
+    pub fn sector_nodes(&self, sector: usize) -> Range<u32> {
+        let (first, len) = match self.num_nodes_per_sector {
+            _ if sector == 0 => (0, 0),
+            0 if !(sector - 1).is_multiple_of(self.sectors_per_node()) => (0, 0),
+            0 => ((sector - 1) / self.sectors_per_node(), 1),
+            per_sector => ((sector - 1) * per_sector, per_sector),
+        };
+        let first = first.min(self.num_pts);
+        first as u32..(first + len).min(self.num_pts) as u32
+    }
+
+    /// Check that the nodes fit where the layout places them: num_nodes_per_sector nodes of
+    /// max_node_len bytes in a sector, or one node larger than a sector per run of sectors
+    pub fn validate(&self) -> ANNResult<()> {
+        let fits = match self.num_nodes_per_sector {
+            0 => self.max_node_len > SECTOR_LEN,
+            per_sector => per_sector
+                .checked_mul(self.max_node_len)
+                .is_some_and(|len| len > 0 && len <= SECTOR_LEN),
+        };
+        if !fits {
+            return Err(ANNError::log_index_error(format!(
+                "ERROR: {} nodes of {}B per sector don't fit the layout of sectors of {}B.",
+                self.num_nodes_per_sector, self.max_node_len, SECTOR_LEN
+            )));
+        }
+        Ok(())
+    }
 }
 
 pub struct DiskIndexStorage<T> {
@@ -71,7 +179,8 @@ impl<T> DiskIndexStorage<T> {
 
     /// Create disk layout
     /// Sector #1: disk_layout_meta
-    /// Sector #n: num_nodes_per_sector nodes
+    /// Sector #n: num_nodes_per_sector nodes, or a node larger than a sector spanning sectors
+    /// from #n on
     /// Each node's layout: {full precision vector:[T; DIM]}{num_nbrs: u32}{neighbors: [u32; num_nbrs]}
     /// # Arguments
     /// * `dataset_file` - dataset file containing full precision vectors
@@ -121,15 +230,23 @@ impl<T> DiskIndexStorage<T> {
         println!("max_node_len: {}B", max_node_len);
         println!("num_nodes_per_sector: {}B", num_nodes_per_sector);
 
-        // SECTOR_LEN buffer for each sector
-        let mut sector_buf = vec![0u8; SECTOR_LEN];
+        // Sectors are written in blocks of the sectors a node starts, one sector unless the
+        // nodes are larger than a sector
+        let sectors_per_block = if num_nodes_per_sector == 0 {
+            max_node_len.div_ceil(SECTOR_LEN as u64)
+        } else {
+            1
+        };
+        let nodes_per_block = num_nodes_per_sector.max(1);
+        let mut sector_buf = vec![0u8; sectors_per_block as usize * SECTOR_LEN];
         let mut node_buf = vec![0u8; max_node_len as usize];
 
         let num_nbrs_start = (dims as usize) * mem::size_of::<T>();
         let nbrs_buf_start = num_nbrs_start + mem::size_of::<u32>();
 
         // number of sectors (1 for meta data)
-        let num_sectors = round_up(num_pts, num_nodes_per_sector) / num_nodes_per_sector;
+        let num_blocks = round_up(num_pts, nodes_per_block) / nodes_per_block;
+        let num_sectors = num_blocks * sectors_per_block;
         let disk_index_file_size = (num_sectors + 1) * (SECTOR_LEN as u64);
 
         let disk_layout_meta = vec![
@@ -147,18 +264,18 @@ impl<T> DiskIndexStorage<T> {
             disk_index_file_size,
         ];
 
-        diskann_writer.write(&sector_buf)?;
+        diskann_writer.write(&sector_buf[..SECTOR_LEN])?;
 
         let mut cur_node_coords = vec![0u8; (dims as usize) * mem::size_of::<T>()];
         let mut cur_node_id = 0u64;
 
-        for sector in 0..num_sectors {
-            if sector % 100_000 == 0 {
-                println!("Sector #{} written", sector);
+        for block in 0..num_blocks {
+            if block % 100_000 == 0 {
+                println!("Sector #{} written", block * sectors_per_block);
             }
             sector_buf.fill(0);
 
-            for sector_node_id in 0..num_nodes_per_sector {
+            for sector_node_id in 0..nodes_per_block {
                 if cur_node_id >= num_pts {
                     break;
                 }
@@ -212,16 +329,22 @@ impl<T> DiskIndexStorage<T> {
         Ok(())
     }
 
+    /// Remove the intermediate in-memory index files
     pub fn index_build_cleanup(&self) -> ANNResult<()> {
         fs::remove_file(self.mem_index_file())?;
+        let mem_index_data_file = self.mem_index_file() + ".data";
+        if file_exists(&mem_index_data_file) {
+            fs::remove_file(mem_index_data_file)?;
+        }
         Ok(())
     }
 
-    pub fn gen_query_warmup_data(&self, sampling_rate: f64) -> ANNResult<()> {
+    pub fn gen_query_warmup_data(&self, sampling_rate: f64, seed: Option<u64>) -> ANNResult<()> {
         gen_sample_data::<T>(
             &self.dataset_file,
             &self.warmup_query_prefix(),
             sampling_rate,
+            seed,
         )?;
         Ok(())
     }
@@ -247,13 +370,12 @@ impl<T> DiskIndexStorage<T> {
 
         let (data, pivot_num, dim) = load_bin::<f32>(pq_pivots_path, file_offset_data[0])?;
         let pq_table = data.to_vec();
-        if pivot_num != NUM_PQ_CENTROIDS {
-            let error_message = format!(
-                "Error reading pq_pivots file {}. file_num_centers = {}, but expecting {} centers.",
-                pq_pivots_path, pivot_num, NUM_PQ_CENTROIDS
-            );
-            return Err(ANNError::log_pq_error(error_message));
-        }
+        let code_bits = PQCodeBits::from_num_centers(pivot_num).map_err(|_| {
+            ANNError::log_pq_error(format!(
+                "Error reading pq_pivots file {}. file_num_centers = {}, but expecting 256 centers, or 16 for 4-bit codes.",
+                pq_pivots_path, pivot_num
+            ))
+        })?;
 
         let (data, centroid_dim, nc) = load_bin::<f32>(pq_pivots_path, file_offset_data[1])?;
         let centroids = data.to_vec();
@@ -269,19 +391,170 @@ impl<T> DiskIndexStorage<T> {
             return Err(ANNError::log_pq_error(error_message));
         }
 
+        let rotation = self.pq_storage.load_rotation_matrix(dim)?;
+
         Ok(PQPivotData {
             dim, 
             pq_table, 
             centroids, 
-            chunk_offsets
+            chunk_offsets,
+            rotation,
+            code_bits,
         })
     }
 
+    /// Nodes to cache at warm-up, from the most accessed sectors in the usage statistics saved
+    /// next to the index. None if no statistics were saved.
+    pub fn hot_nodes_for_warmup(&self, max_nodes: usize) -> ANNResult<Option<Vec<u32>>> {
+        let layout_meta = self.load_disk_layout_meta()?;
+        Ok(self
+            .load_sector_usage(&layout_meta)?
+            .map(|stats| stats.hottest_nodes(max_nodes, &layout_meta)))
+    }
+
+    /// Load the usage statistics saved next to the index for the sectors of layout_meta.
+    /// None if no statistics were saved.
+    pub fn load_sector_usage(
+        &self,
+        layout_meta: &DiskLayoutMeta,
+    ) -> ANNResult<Option<SectorUsageStats>> {
+        let usage_file = self.sector_usage_file();
+        if !file_exists(&usage_file) {
+            return Ok(None);
+        }
+
+        let stats = SectorUsageStats::load(&usage_file)?;
+        let num_sectors = layout_meta.num_sectors();
+        if stats.num_sectors() != num_sectors {
+            return Err(ANNError::log_index_error(format!(
+                "ERROR: Sector usage statistics {} cover {} sectors, but the disk index has {} sectors.",
+                usage_file,
+                stats.num_sectors(),
+                num_sectors
+            )));
+        }
+
+        Ok(Some(stats))
+    }
+
+    /// Load the metadata written at the start of the disk index by create_disk_layout
+    pub fn load_disk_layout_meta(&self) -> ANNResult<DiskLayoutMeta> {
+        let disk_index_file = self.disk_index_file();
+        let (disk_layout_meta, num_values, _) = load_bin::<u64>(&disk_index_file, 0)?;
+        if num_values < 5 {
+            return Err(ANNError::log_index_error(format!(
+                "ERROR: Disk index {} has {} metadata values, expecting at least 5.",
+                disk_index_file, num_values
+            )));
+        }
+
+        let layout_meta = DiskLayoutMeta {
+            num_pts: disk_layout_meta[0] as usize,
+            dim: disk_layout_meta[1] as usize,
+            medoid: disk_layout_meta[2] as u32,
+            max_node_len: disk_layout_meta[3] as usize,
+            num_nodes_per_sector: disk_layout_meta[4] as usize,
+            frozen_point: match disk_layout_meta.get(5..7) {
+                Some([1, frozen_loc]) => Some(*frozen_loc as u32),
+                _ => None,
+            },
+        };
+        layout_meta
+            .validate()
+            .with_context(|| format!("Loading the layout of disk index {}", disk_index_file))?;
+
+        Ok(layout_meta)
+    }
+
+    /// Call f with the id and the neighbors of each node of the disk index, in id order,
+    /// reading the index a sector at a time, or a node at a time for nodes larger than a sector
+    pub fn for_each_adjacency_list<F>(&self, mut f: F) -> ANNResult<()>
+    where
+        F: FnMut(u32, &[u32]) -> ANNResult<()>,
+    {
+        let layout_meta = self.load_disk_layout_meta()?;
+        let num_neighbors_start = layout_meta.dim * mem::size_of::<T>();
+        let mut reader = File::open(self.disk_index_file())?;
+        let mut sector_buf = vec![0u8; layout_meta.sectors_per_node() * SECTOR_LEN];
+        let mut neighbors = Vec::new();
+
+        reader.read_exact(&mut sector_buf[..SECTOR_LEN])?;
+        for id in 0..layout_meta.num_pts as u32 {
+            if layout_meta.node_offset_in_sector(id) == 0 {
+                reader.read_exact(&mut sector_buf)?;
+            }
+            let node_offset = layout_meta.node_offset_in_sector(id);
+            let node = &sector_buf[node_offset..node_offset + layout_meta.max_node_len];
+            let num_neighbors = LittleEndian::read_u32(&node[num_neighbors_start..]) as usize;
+            let neighbors_buf = node
+                .get(num_neighbors_start + 4..num_neighbors_start + 4 + num_neighbors * 4)
+                .ok_or_else(|| {
+                    ANNError::log_index_error(format!(
+                        "ERROR: Node {} of disk index {} has {} neighbors, more than fit in it.",
+                        id,
+                        self.disk_index_file(),
+                        num_neighbors
+                    ))
+                })?;
+
+            neighbors.clear();
+            neighbors.extend(neighbors_buf.chunks_exact(4).map(LittleEndian::read_u32));
+            f(id, &neighbors)?;
+        }
+
+        Ok(())
+    }
+
+    /// Export the graph of the disk index to filename, or sample_size of its nodes drawn at
+    /// random if given
+    pub fn export_graph(
+        &self,
+        filename: &str,
+        format: GraphExportFormat,
+        sample_size: Option<usize>,
+    ) -> ANNResult<GraphExportSummary> {
+        let layout_meta = self.load_disk_layout_meta()?;
+        let mut exporter = GraphExporter::create(
+            filename,
+            format,
+            layout_meta.num_pts,
+            layout_meta.medoid,
+            sample_size,
+        )?;
+        self.for_each_adjacency_list(|id, neighbors| exporter.add_vertex(id, neighbors))?;
+        exporter.finish()
+    }
+
+    /// Load the PQ codes of all points, returns the codes, number of points and number of chunks.
+    /// The codes take a byte per chunk, or half of one when the pivots are for 4-bit codes.
+    pub fn load_pq_compressed_data(&self) -> ANNResult<(Vec<u8>, usize, usize)> {
+        let compressed_file = self.compressed_pq_pivot_file();
+        if !file_exists(&compressed_file) {
+            return Err(ANNError::log_pq_error(format!(
+                "ERROR: PQ compressed file {} not found.",
+                compressed_file
+            )));
+        }
+
+        let mut reader = File::open(&compressed_file)?;
+        let num_points = reader.read_i32::<LittleEndian>()? as usize;
+        let num_chunks = reader.read_i32::<LittleEndian>()? as usize;
+        let mut codes = Vec::new();
+        reader.read_to_end(&mut codes)?;
+        Ok((codes, num_points, num_chunks))
+    }
+
+    /// Sector usage statistics file
+    pub fn sector_usage_file(&self) -> String {
+        self.index_path_prefix.clone() + "_sector_usage.bin"
+    }
+
     fn mem_index_file(&self) -> String {
         self.index_path_prefix.clone() + "_mem.index"
     }
 
-    fn disk_index_file(&self) -> String {
+    /// Disk index file holding the layout metadata and the nodes
+    pub fn disk_index_file(&self) -> String {
         self.index_path_prefix.clone() + "_disk.index"
     }
 
@@ -296,12 +569,24 @@ impl<T> DiskIndexStorage<T> {
     pub fn compressed_pq_pivot_file(&self) -> String {
         self.index_path_prefix.clone() + ".bin_pq_compressed.bin"
     }
+
+    /// Files a disk index build leaves behind
+    pub fn output_files(&self) -> Vec<String> {
+        vec![
+            self.disk_index_file(),
+            self.pq_pivot_file(),
+            self.compressed_pq_pivot_file(),
+            self.warmup_query_prefix() + "_data.bin",
+            self.warmup_query_prefix() + "_ids.bin",
+        ]
+    }
 }
 
 #[cfg(test)]
 mod disk_index_storage_test {
     use std::fs;
 
+    use crate::model::NUM_PQ_CENTROIDS;
     use crate::test_utils::get_test_file_path;
 
     use super::*;
@@ -328,6 +613,77 @@ mod disk_index_storage_test {
         fs::remove_file(disk_layout_file.as_str()).expect("Failed to delete file");
     }
 
+    #[test]
+    fn hot_nodes_for_warmup_test() {
+        let storage = DiskIndexStorage::<f32>::new(
+            get_test_file_path(TEST_DATA_FILE),
+            get_test_file_path("tests/data/truth_disk_index_siftsmall_learn_256pts_R4_L50_A1.2"),
+        ).unwrap();
+        assert!(storage.hot_nodes_for_warmup(10).unwrap().is_none());
+
+        // 256 nodes, 7 per sector, in 37 sectors after the metadata sector
+        let stats = SectorUsageStats::new(38, 0.9).unwrap();
+        stats.record_access(37);
+        stats.record_access(2);
+        stats.record_access(2);
+        stats.save(&storage.sector_usage_file()).unwrap();
+        let hot_nodes = storage.hot_nodes_for_warmup(10).unwrap();
+
+        SectorUsageStats::new(5, 0.9).unwrap().save(&storage.sector_usage_file()).unwrap();
+        let mismatched = storage.hot_nodes_for_warmup(10);
+        fs::remove_file(storage.sector_usage_file()).unwrap();
+
+        assert_eq!(hot_nodes.unwrap(), vec![7, 8, 9, 10, 11, 12, 13, 252, 253, 254]);
+        assert!(mismatched.is_err());
+    }
+
+    #[test]
+    fn export_graph_matches_mem_index() {
+        let storage = DiskIndexStorage::<f32>::new(
+            get_test_file_path(TEST_DATA_FILE),
+            get_test_file_path("tests/data/truth_disk_index_siftsmall_learn_256pts_R4_L50_A1.2"),
+        )
+        .unwrap();
+
+        // The disk layout was written from the graph of the mem index, which follows its
+        // 24 byte header as a neighbor count and the neighbors per node
+        let mem_index =
+            fs::read(get_test_file_path(DISK_INDEX_PATH_PREFIX) + "_mem.index").unwrap();
+        let values: Vec<u32> = mem_index[24..]
+            .chunks_exact(4)
+            .map(LittleEndian::read_u32)
+            .collect();
+        let mut expected = Vec::new();
+        let mut pos = 0;
+        while pos < values.len() {
+            let num_neighbors = values[pos] as usize;
+            expected.push(values[pos + 1..pos + 1 + num_neighbors].to_vec());
+            pos += 1 + num_neighbors;
+        }
+
+        let mut adjacency_lists = Vec::new();
+        storage
+            .for_each_adjacency_list(|id, neighbors| {
+                assert_eq!(id as usize, adjacency_lists.len());
+                adjacency_lists.push(neighbors.to_vec());
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(adjacency_lists, expected);
+
+        let export_file = "export_graph_matches_mem_index.txt";
+        let summary = storage
+            .export_graph(export_file, GraphExportFormat::EdgeList, None)
+            .unwrap();
+        let edge_list = fs::read_to_string(export_file).unwrap();
+        fs::remove_file(export_file).unwrap();
+        assert_eq!(summary.num_vertices, 256);
+        assert!(summary.max_degree() <= 4);
+        let num_edges: usize = expected.iter().map(Vec::len).sum();
+        let edges = edge_list.lines().filter(|line| !line.starts_with('#'));
+        assert_eq!((summary.num_edges, edges.count()), (num_edges, num_edges));
+    }
+
     #[test]
     fn load_pivot_test() {
         let dim: usize = 128;
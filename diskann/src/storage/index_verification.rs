@@ -0,0 +1,509 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Integrity check of the files of a disk index, to catch truncated or corrupt indexes before
+//! they serve wrong results. Every problem found goes in the report rather than failing the
+//! check, so one run lists all of them.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::mem;
+
+use serde::Serialize;
+
+use crate::common::ANNResult;
+use crate::utils::{file_exists, get_file_size, load_bin, load_metadata_from_file};
+
+use super::{DiskIndexStorage, DiskLayoutMeta};
+
+const SECTOR_LEN: usize = 4096;
+
+/// Ids kept as examples in the issues that can concern many nodes
+const MAX_SAMPLE_IDS: usize = 10;
+
+/// A problem found in the files of an index
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum VerificationIssue {
+    /// A file is missing or can't be read
+    Unreadable {
+        /// The file
+        file: String,
+        /// Why it can't be read
+        err: String,
+    },
+
+    /// A file doesn't have the size its header implies, e.g. it was truncated
+    FileSize {
+        /// The file
+        file: String,
+        /// Size implied by the header
+        expected_bytes: u64,
+        /// Size on disk
+        actual_bytes: u64,
+    },
+
+    /// A header field is out of range or inconsistent with the other fields
+    InvalidHeader {
+        /// The file
+        file: String,
+        /// Name of the field
+        field: String,
+        /// What is wrong with it
+        err: String,
+    },
+
+    /// The adjacency lists can't be read, e.g. a node claims more neighbors than fit in it
+    InvalidGraph {
+        /// Why they can't be read
+        err: String,
+    },
+
+    /// Edges to ids past the last point
+    NeighborsOutOfRange {
+        /// Number of such edges
+        num_edges: u64,
+        /// Some of them, as (node, neighbor)
+        sample: Vec<(u32, u32)>,
+    },
+
+    /// Nodes the search can't reach from the medoid
+    Unreachable {
+        /// Number of such nodes
+        num_nodes: usize,
+        /// Some of them
+        sample: Vec<u32>,
+    },
+
+    /// The PQ files don't match each other or the disk index
+    PQMismatch {
+        /// The file
+        file: String,
+        /// What doesn't match
+        err: String,
+    },
+}
+
+impl fmt::Display for VerificationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerificationIssue::Unreadable { file, err } => {
+                write!(f, "{} can't be read: {}", file, err)
+            }
+            VerificationIssue::FileSize {
+                file,
+                expected_bytes,
+                actual_bytes,
+            } => write!(
+                f,
+                "{} has {} bytes, its header implies {}",
+                file, actual_bytes, expected_bytes
+            ),
+            VerificationIssue::InvalidHeader { file, field, err } => {
+                write!(f, "{} of {} is invalid: {}", field, file, err)
+            }
+            VerificationIssue::InvalidGraph { err } => {
+                write!(f, "The graph can't be read: {}", err)
+            }
+            VerificationIssue::NeighborsOutOfRange { num_edges, sample } => write!(
+                f,
+                "{} edges lead past the last point, e.g. (node, neighbor) {:?}",
+                num_edges, sample
+            ),
+            VerificationIssue::Unreachable { num_nodes, sample } => write!(
+                f,
+                "{} nodes can't be reached from the medoid, e.g. {:?}",
+                num_nodes, sample
+            ),
+            VerificationIssue::PQMismatch { file, err } => write!(f, "{}: {}", file, err),
+        }
+    }
+}
+
+/// Outcome of the integrity check of an index
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct IndexVerificationReport {
+    /// Number of points in the header of the disk index
+    pub num_points: usize,
+
+    /// Dimension of the points
+    pub dim: usize,
+
+    /// Entry point of the search
+    pub medoid: u32,
+
+    /// Number of edges of the graph
+    pub num_edges: u64,
+
+    /// Most neighbors of a node
+    pub max_degree: usize,
+
+    /// Number of nodes reachable from the medoid, the medoid included
+    pub num_reachable: usize,
+
+    /// Problems found, none if the index is sound
+    pub issues: Vec<VerificationIssue>,
+}
+
+impl IndexVerificationReport {
+    /// Whether no problem was found
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+impl<T> DiskIndexStorage<T> {
+    /// Check the header, size and graph of the disk index and the PQ files next to it. Errors
+    /// are only returned for failures of the check itself, problems of the index are in the
+    /// report.
+    pub fn verify(&self) -> ANNResult<IndexVerificationReport> {
+        let mut report = IndexVerificationReport::default();
+        let disk_index_file = self.disk_index_file();
+
+        let layout_meta = match self.load_disk_layout_meta() {
+            Ok(layout_meta) => layout_meta,
+            Err(err) => {
+                report.issues.push(VerificationIssue::Unreadable {
+                    file: disk_index_file,
+                    err: err.to_string(),
+                });
+                return Ok(report);
+            }
+        };
+        report.num_points = layout_meta.num_pts;
+        report.dim = layout_meta.dim;
+        report.medoid = layout_meta.medoid;
+
+        let header_ok = self.verify_header(&layout_meta, &mut report);
+        if header_ok && self.verify_disk_index_size(&layout_meta, &mut report) {
+            self.verify_graph(&layout_meta, &mut report);
+        }
+        self.verify_pq_files(&layout_meta, &mut report);
+
+        Ok(report)
+    }
+
+    /// Check the fields of the layout metadata, true if the nodes can be located with them
+    fn verify_header(
+        &self,
+        layout_meta: &DiskLayoutMeta,
+        report: &mut IndexVerificationReport,
+    ) -> bool {
+        let file = self.disk_index_file();
+        let mut invalid = |field: &str, err: String| {
+            report.issues.push(VerificationIssue::InvalidHeader {
+                file: file.clone(),
+                field: field.to_string(),
+                err,
+            })
+        };
+        let mut located = true;
+
+        if layout_meta.num_pts == 0 {
+            invalid("num_pts", "The index has no points".to_string());
+            located = false;
+        } else if layout_meta.medoid as usize >= layout_meta.num_pts {
+            invalid(
+                "medoid",
+                format!(
+                    "Medoid {} of {} points",
+                    layout_meta.medoid, layout_meta.num_pts
+                ),
+            );
+        }
+        if let Some(frozen_point) = layout_meta.frozen_point {
+            if frozen_point as usize >= layout_meta.num_pts {
+                invalid(
+                    "frozen_point",
+                    format!(
+                        "Frozen point {} of {} points",
+                        frozen_point, layout_meta.num_pts
+                    ),
+                );
+            }
+        }
+
+        // A node holds the vector, the number of neighbors and at least one neighbor
+        let min_node_len = layout_meta.dim * mem::size_of::<T>() + 2 * mem::size_of::<u32>();
+        if layout_meta.max_node_len < min_node_len {
+            invalid(
+                "max_node_len",
+                format!(
+                    "Nodes of {}B, vectors of dimension {} need at least {}B",
+                    layout_meta.max_node_len, layout_meta.dim, min_node_len
+                ),
+            );
+            located = false;
+        } else if layout_meta.num_nodes_per_sector != SECTOR_LEN / layout_meta.max_node_len {
+            invalid(
+                "num_nodes_per_sector",
+                format!(
+                    "{} nodes of {}B per sector, {} fit",
+                    layout_meta.num_nodes_per_sector,
+                    layout_meta.max_node_len,
+                    SECTOR_LEN / layout_meta.max_node_len
+                ),
+            );
+            located = false;
+        }
+
+        located
+    }
+
+    /// Check the size of the disk index against its header, true if all the nodes are there
+    fn verify_disk_index_size(
+        &self,
+        layout_meta: &DiskLayoutMeta,
+        report: &mut IndexVerificationReport,
+    ) -> bool {
+        let file = self.disk_index_file();
+        let actual_bytes = match get_file_size(&file) {
+            Ok(size) => size,
+            Err(err) => {
+                report.issues.push(VerificationIssue::Unreadable {
+                    file,
+                    err: err.to_string(),
+                });
+                return false;
+            }
+        };
+
+        let expected_bytes = (layout_meta.num_sectors() * SECTOR_LEN) as u64;
+        if actual_bytes < expected_bytes {
+            report.issues.push(VerificationIssue::FileSize {
+                file,
+                expected_bytes,
+                actual_bytes,
+            });
+            return false;
+        }
+
+        // The size the build recorded, after the frozen point and reorder fields
+        if let Ok((meta, num_values, _)) = load_bin::<u64>(&file, 0) {
+            if num_values >= 9 && meta[8] != actual_bytes {
+                report.issues.push(VerificationIssue::FileSize {
+                    file,
+                    expected_bytes: meta[8],
+                    actual_bytes,
+                });
+            }
+        }
+        true
+    }
+
+    /// Check the neighbor ids and that every node is reachable from the medoid
+    fn verify_graph(&self, layout_meta: &DiskLayoutMeta, report: &mut IndexVerificationReport) {
+        let num_pts = layout_meta.num_pts;
+        let mut offsets = Vec::with_capacity(num_pts + 1);
+        let mut edges = Vec::new();
+        let mut num_out_of_range = 0u64;
+        let mut out_of_range_sample = Vec::new();
+
+        offsets.push(0);
+        let walk = self.for_each_adjacency_list(|id, neighbors| {
+            report.max_degree = report.max_degree.max(neighbors.len());
+            for &neighbor in neighbors {
+                if neighbor as usize >= num_pts {
+                    num_out_of_range += 1;
+                    if out_of_range_sample.len() < MAX_SAMPLE_IDS {
+                        out_of_range_sample.push((id, neighbor));
+                    }
+                } else {
+                    edges.push(neighbor);
+                }
+            }
+            offsets.push(edges.len());
+            Ok(())
+        });
+        report.num_edges = edges.len() as u64 + num_out_of_range;
+
+        if let Err(err) = walk {
+            report.issues.push(VerificationIssue::InvalidGraph {
+                err: err.to_string(),
+            });
+            return;
+        }
+        if num_out_of_range > 0 {
+            report.issues.push(VerificationIssue::NeighborsOutOfRange {
+                num_edges: num_out_of_range,
+                sample: out_of_range_sample,
+            });
+        }
+
+        let medoid = layout_meta.medoid as usize;
+        if medoid >= num_pts {
+            return;
+        }
+        let mut visited = vec![false; num_pts];
+        let mut queue = VecDeque::from([medoid]);
+        visited[medoid] = true;
+        while let Some(node) = queue.pop_front() {
+            for &neighbor in &edges[offsets[node]..offsets[node + 1]] {
+                if !visited[neighbor as usize] {
+                    visited[neighbor as usize] = true;
+                    queue.push_back(neighbor as usize);
+                }
+            }
+        }
+
+        report.num_reachable = visited.iter().filter(|&&visited| visited).count();
+        if report.num_reachable < num_pts {
+            report.issues.push(VerificationIssue::Unreachable {
+                num_nodes: num_pts - report.num_reachable,
+                sample: (0..num_pts as u32)
+                    .filter(|&id| !visited[id as usize])
+                    .take(MAX_SAMPLE_IDS)
+                    .collect(),
+            });
+        }
+    }
+
+    /// Check the PQ codes cover every point with the chunks of the pivots
+    fn verify_pq_files(&self, layout_meta: &DiskLayoutMeta, report: &mut IndexVerificationReport) {
+        let compressed_file = self.compressed_pq_pivot_file();
+        let pivot_file = self.pq_pivot_file();
+        for file in [&compressed_file, &pivot_file] {
+            if !file_exists(file) {
+                report.issues.push(VerificationIssue::Unreadable {
+                    file: file.clone(),
+                    err: "File not found".to_string(),
+                });
+            }
+        }
+        if !file_exists(&compressed_file) || !file_exists(&pivot_file) {
+            return;
+        }
+
+        let (num_points, num_chunks) = match load_metadata_from_file(&compressed_file) {
+            Ok(metadata) => metadata,
+            Err(err) => {
+                report.issues.push(VerificationIssue::Unreadable {
+                    file: compressed_file,
+                    err: err.to_string(),
+                });
+                return;
+            }
+        };
+        if num_points != layout_meta.num_pts {
+            report.issues.push(VerificationIssue::PQMismatch {
+                file: compressed_file.clone(),
+                err: format!(
+                    "Codes of {} points, the disk index has {}",
+                    num_points, layout_meta.num_pts
+                ),
+            });
+        }
+
+        let code_bits = match self.load_pq_pivots_bin(&num_chunks) {
+            Ok(pivots) => pivots.into_pq_table(num_chunks).code_bits(),
+            Err(err) => {
+                report.issues.push(VerificationIssue::PQMismatch {
+                    file: pivot_file,
+                    err: err.to_string(),
+                });
+                return;
+            }
+        };
+
+        let expected_bytes =
+            (2 * mem::size_of::<i32>() + num_points * code_bits.code_bytes(num_chunks)) as u64;
+        match get_file_size(&compressed_file) {
+            Ok(actual_bytes) if actual_bytes != expected_bytes => {
+                report.issues.push(VerificationIssue::FileSize {
+                    file: compressed_file,
+                    expected_bytes,
+                    actual_bytes,
+                })
+            }
+            Ok(_) => {}
+            Err(err) => report.issues.push(VerificationIssue::Unreadable {
+                file: compressed_file,
+                err: err.to_string(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod index_verification_test {
+    use std::fs;
+
+    use byteorder::{ByteOrder, LittleEndian};
+
+    use crate::test_utils::get_test_file_path;
+
+    use super::*;
+
+    const TRUTH_INDEX_PREFIX: &str =
+        "tests/data/truth_disk_index_siftsmall_learn_256pts_R4_L50_A1.2";
+
+    fn verify_copy(prefix: &str, corrupt: impl FnOnce(&mut Vec<u8>)) -> IndexVerificationReport {
+        let mut disk_index =
+            fs::read(get_test_file_path(TRUTH_INDEX_PREFIX) + "_disk.index").unwrap();
+        corrupt(&mut disk_index);
+        let disk_index_file = format!("{}_disk.index", prefix);
+        fs::write(&disk_index_file, disk_index).unwrap();
+
+        let storage = DiskIndexStorage::<f32>::new(
+            get_test_file_path("tests/data/siftsmall_learn_256pts.fbin"),
+            prefix.to_string(),
+        )
+        .unwrap();
+        let report = storage.verify();
+        fs::remove_file(&disk_index_file).unwrap();
+        report.unwrap()
+    }
+
+    #[test]
+    fn test_index_has_unreachable_nodes_and_no_pq_files() {
+        let report = verify_copy("test_index_has_unreachable_nodes_and_no_pq_files", |_| {});
+        assert_eq!(report.num_points, 256);
+        assert_eq!(report.dim, 128);
+        assert_eq!(report.medoid, 72);
+        assert!(report.max_degree <= 4);
+        assert!(report.num_edges > 0);
+
+        // The graph of degree 4 leaves a few nodes out of reach, and the test data has no PQ
+        // files next to the disk index
+        assert_eq!(report.num_reachable, 249);
+        assert!(report.issues.contains(&VerificationIssue::Unreachable {
+            num_nodes: 7,
+            sample: vec![33, 96, 99, 105, 107, 125, 181],
+        }));
+        let num_unreadable = report
+            .issues
+            .iter()
+            .filter(|issue| matches!(issue, VerificationIssue::Unreadable { .. }))
+            .count();
+        assert_eq!(num_unreadable, 2);
+        assert_eq!(report.issues.len(), 3);
+    }
+
+    #[test]
+    fn truncation_and_bad_neighbors_are_reported() {
+        let report = verify_copy("truncation_is_reported", |disk_index| {
+            disk_index.truncate(disk_index.len() - SECTOR_LEN)
+        });
+        assert!(!report.is_ok());
+        assert!(report.issues.iter().any(|issue| matches!(
+            issue,
+            VerificationIssue::FileSize { expected_bytes, actual_bytes, .. }
+                if actual_bytes < expected_bytes
+        )));
+        assert_eq!(report.num_edges, 0);
+
+        // The first neighbor of node 0 follows its vector and number of neighbors
+        let report = verify_copy("bad_neighbor_is_reported", |disk_index| {
+            let first_neighbor = SECTOR_LEN + 128 * mem::size_of::<f32>() + 4;
+            LittleEndian::write_u32(&mut disk_index[first_neighbor..], 1000);
+        });
+        assert!(report
+            .issues
+            .contains(&VerificationIssue::NeighborsOutOfRange {
+                num_edges: 1,
+                sample: vec![(0, 1000)],
+            }));
+    }
+}
@@ -0,0 +1,419 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Coordinator of the search reads and the insert/merge writes of a disk index file.
+//! Reads run together, a write runs alone, so a search never reads a node half written by the
+//! write through of an insert. When both classes are waiting, the turns go to the classes in
+//! proportion to their weights, and a waiter older than max_wait is served next regardless of
+//! the weights so neither class starves.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::common::{ANNError, ANNResult};
+
+/// Class of an IO operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoClass {
+    /// Search reads, run together
+    Read,
+
+    /// Insert or merge writes, run alone
+    Write,
+}
+
+impl IoClass {
+    fn index(self) -> usize {
+        match self {
+            IoClass::Read => 0,
+            IoClass::Write => 1,
+        }
+    }
+}
+
+/// Fairness settings of the coordinator
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IoFairnessConfig {
+    /// Turns given to reads while both classes are waiting
+    pub read_weight: u32,
+
+    /// Turns given to writes while both classes are waiting
+    pub write_weight: u32,
+
+    /// A waiter older than this is served next whatever the weights say
+    pub max_wait: Duration,
+}
+
+impl Default for IoFairnessConfig {
+    fn default() -> Self {
+        Self {
+            read_weight: 4,
+            write_weight: 1,
+            max_wait: Duration::from_millis(50),
+        }
+    }
+}
+
+/// Wait time metrics of one IO class
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IoWaitStats {
+    /// Operations admitted
+    pub granted: u64,
+
+    /// Operations admitted through starvation protection
+    pub promoted: u64,
+
+    /// Total time spent waiting for a turn, in microseconds
+    pub total_wait_us: u64,
+
+    /// Longest time spent waiting for a turn, in microseconds
+    pub max_wait_us: u64,
+}
+
+impl IoWaitStats {
+    /// Average time spent waiting for a turn, in microseconds
+    pub fn mean_wait_us(&self) -> f64 {
+        if self.granted == 0 {
+            0.0
+        } else {
+            self.total_wait_us as f64 / self.granted as f64
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct CoordinatorState {
+    /// Waiters of each class in arrival order, as (ticket, arrival time)
+    waiting: [VecDeque<(u64, Instant)>; 2],
+
+    /// Turns granted to each class in the current weighted round
+    served: [u64; 2],
+
+    /// Reads running
+    num_reading: usize,
+
+    /// Whether a write is running
+    writing: bool,
+
+    next_ticket: u64,
+
+    stats: [IoWaitStats; 2],
+}
+
+impl CoordinatorState {
+    /// Whether an operation of class can start next to those running
+    fn can_start(&self, class: IoClass) -> bool {
+        match class {
+            IoClass::Read => !self.writing,
+            IoClass::Write => !self.writing && self.num_reading == 0,
+        }
+    }
+}
+
+/// Coordinator of the reads and writes of a disk index file, shared by the searches of a
+/// DiskIndex and the writes of an AlignedFileStorageProvider
+#[derive(Debug)]
+pub struct IoCoordinator {
+    config: IoFairnessConfig,
+    state: Mutex<CoordinatorState>,
+    turn_cv: Condvar,
+}
+
+/// Turn of an admitted operation, released when dropped
+#[derive(Debug)]
+pub struct IoPermit {
+    coordinator: Arc<IoCoordinator>,
+    class: IoClass,
+}
+
+impl IoPermit {
+    /// Class the permit was acquired for
+    pub fn class(&self) -> IoClass {
+        self.class
+    }
+}
+
+impl Drop for IoPermit {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.coordinator.state.lock() {
+            match self.class {
+                IoClass::Read => state.num_reading -= 1,
+                IoClass::Write => state.writing = false,
+            }
+        }
+        self.coordinator.turn_cv.notify_all();
+    }
+}
+
+impl IoCoordinator {
+    /// Create a coordinator
+    pub fn new(config: IoFairnessConfig) -> ANNResult<Self> {
+        if config.read_weight == 0 || config.write_weight == 0 {
+            return Err(ANNError::log_index_config_error(
+                "IoFairnessConfig".to_string(),
+                "read_weight and write_weight must be greater than 0".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            config,
+            state: Mutex::new(CoordinatorState::default()),
+            turn_cv: Condvar::new(),
+        })
+    }
+
+    /// Block until an operation of class may run
+    pub fn acquire(self: &Arc<Self>, class: IoClass) -> ANNResult<IoPermit> {
+        let arrival = Instant::now();
+        let mut state = self.lock_state()?;
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+        state.waiting[class.index()].push_back((ticket, arrival));
+
+        loop {
+            let (next_class, promoted) = self.next_class(&state);
+            let is_next = next_class == Some(class)
+                && state.waiting[class.index()].front().map(|(t, _)| *t) == Some(ticket);
+            if is_next && state.can_start(class) {
+                self.grant(&mut state, class, arrival.elapsed(), promoted);
+                state.waiting[class.index()].pop_front();
+                drop(state);
+
+                // The next reader may start too
+                self.turn_cv.notify_all();
+                return Ok(IoPermit {
+                    coordinator: self.clone(),
+                    class,
+                });
+            }
+
+            // Wake up in time to notice a waiter crossing max_wait
+            state = self
+                .turn_cv
+                .wait_timeout(state, self.config.max_wait)
+                .map_err(|err| {
+                    ANNError::log_lock_poison_error(format!(
+                        "IoCoordinator lock is poisoned, err={}",
+                        err
+                    ))
+                })?
+                .0;
+        }
+    }
+
+    /// Start an operation of class without waiting, if nothing is waiting and it can run
+    /// next to the running operations
+    pub fn try_acquire(self: &Arc<Self>, class: IoClass) -> ANNResult<Option<IoPermit>> {
+        let mut state = self.lock_state()?;
+        if state.waiting.iter().any(|waiting| !waiting.is_empty()) || !state.can_start(class) {
+            return Ok(None);
+        }
+
+        self.grant(&mut state, class, Duration::ZERO, false);
+        Ok(Some(IoPermit {
+            coordinator: self.clone(),
+            class,
+        }))
+    }
+
+    /// Wait time metrics of class
+    pub fn wait_stats(&self, class: IoClass) -> ANNResult<IoWaitStats> {
+        Ok(self.lock_state()?.stats[class.index()])
+    }
+
+    /// Class to serve next and whether it was picked by starvation protection
+    fn next_class(&self, state: &CoordinatorState) -> (Option<IoClass>, bool) {
+        let read = state.waiting[IoClass::Read.index()].front();
+        let write = state.waiting[IoClass::Write.index()].front();
+
+        match (read, write) {
+            (None, None) => (None, false),
+            (Some(_), None) => (Some(IoClass::Read), false),
+            (None, Some(_)) => (Some(IoClass::Write), false),
+            (Some((_, read_arrival)), Some((_, write_arrival))) => {
+                let read_starved = read_arrival.elapsed() >= self.config.max_wait;
+                let write_starved = write_arrival.elapsed() >= self.config.max_wait;
+                if read_starved || write_starved {
+                    let oldest = if read_arrival <= write_arrival {
+                        IoClass::Read
+                    } else {
+                        IoClass::Write
+                    };
+                    return (Some(oldest), true);
+                }
+
+                // Serve the class furthest behind its weighted share:
+                // served_read / read_weight <= served_write / write_weight
+                let read_share = state.served[0] * self.config.write_weight as u64;
+                let write_share = state.served[1] * self.config.read_weight as u64;
+                if read_share <= write_share {
+                    (Some(IoClass::Read), false)
+                } else {
+                    (Some(IoClass::Write), false)
+                }
+            }
+        }
+    }
+
+    fn grant(
+        &self,
+        state: &mut CoordinatorState,
+        class: IoClass,
+        waited: Duration,
+        promoted: bool,
+    ) {
+        match class {
+            IoClass::Read => state.num_reading += 1,
+            IoClass::Write => state.writing = true,
+        }
+
+        // Only turns taken while both classes wait count towards the weighted round
+        let contended = state.waiting.iter().all(|waiting| !waiting.is_empty());
+        if contended {
+            state.served[class.index()] += 1;
+        } else {
+            state.served = [0; 2];
+        }
+        if state.served[0] >= self.config.read_weight as u64
+            && state.served[1] >= self.config.write_weight as u64
+        {
+            state.served = [0; 2];
+        }
+
+        let waited_us = waited.as_micros() as u64;
+        let stats = &mut state.stats[class.index()];
+        stats.granted += 1;
+        stats.total_wait_us += waited_us;
+        stats.max_wait_us = stats.max_wait_us.max(waited_us);
+        if promoted {
+            stats.promoted += 1;
+        }
+    }
+
+    fn lock_state(&self) -> ANNResult<MutexGuard<'_, CoordinatorState>> {
+        self.state.lock().map_err(|err| {
+            ANNError::log_lock_poison_error(format!("IoCoordinator lock is poisoned, err={}", err))
+        })
+    }
+}
+
+#[cfg(test)]
+mod io_coordinator_test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn invalid_config_is_rejected() {
+        let config = IoFairnessConfig {
+            write_weight: 0,
+            ..IoFairnessConfig::default()
+        };
+        assert!(IoCoordinator::new(config).is_err());
+    }
+
+    #[test]
+    fn weights_split_contended_turns() {
+        let config = IoFairnessConfig {
+            read_weight: 3,
+            write_weight: 1,
+            max_wait: Duration::from_secs(60),
+        };
+        let mut state = CoordinatorState::default();
+        let coordinator = IoCoordinator::new(config).unwrap();
+        let now = Instant::now();
+        for ticket in 0..8 {
+            state.waiting[0].push_back((ticket, now));
+            state.waiting[1].push_back((ticket + 8, now));
+        }
+
+        let mut order = Vec::new();
+        for _ in 0..8 {
+            let (class, promoted) = coordinator.next_class(&state);
+            let class = class.unwrap();
+            assert!(!promoted);
+            coordinator.grant(&mut state, class, Duration::ZERO, false);
+            state.waiting[class.index()].pop_front();
+            state.num_reading = 0;
+            state.writing = false;
+            order.push(class);
+        }
+
+        let reads = order
+            .iter()
+            .filter(|class| **class == IoClass::Read)
+            .count();
+        assert_eq!(reads, 6);
+        assert_eq!(state.stats[IoClass::Write.index()].granted, 2);
+    }
+
+    #[test]
+    fn starved_waiter_is_promoted() {
+        let config = IoFairnessConfig {
+            read_weight: 100,
+            write_weight: 1,
+            max_wait: Duration::from_millis(1),
+        };
+        let coordinator = IoCoordinator::new(config).unwrap();
+        let mut state = CoordinatorState::default();
+        let old = Instant::now() - Duration::from_millis(10);
+        state.waiting[1].push_back((0, old));
+        state.waiting[0].push_back((1, Instant::now()));
+        state.served = [0, 1];
+
+        assert_eq!(coordinator.next_class(&state), (Some(IoClass::Write), true));
+    }
+
+    #[test]
+    fn reads_run_together_and_writes_alone() {
+        let coordinator = Arc::new(IoCoordinator::new(IoFairnessConfig::default()).unwrap());
+        let first = coordinator.try_acquire(IoClass::Read).unwrap().unwrap();
+        let second = coordinator.try_acquire(IoClass::Read).unwrap().unwrap();
+        assert_eq!(second.class(), IoClass::Read);
+        assert!(coordinator.try_acquire(IoClass::Write).unwrap().is_none());
+        drop((first, second));
+        let write = coordinator.try_acquire(IoClass::Write).unwrap().unwrap();
+        assert!(coordinator.try_acquire(IoClass::Read).unwrap().is_none());
+        drop(write);
+
+        let reading = AtomicUsize::new(0);
+        let writing = AtomicUsize::new(0);
+        thread::scope(|scope| {
+            for i in 0..8 {
+                let (coordinator, reading, writing) = (&coordinator, &reading, &writing);
+                scope.spawn(move || {
+                    let class = if i % 4 == 0 {
+                        IoClass::Write
+                    } else {
+                        IoClass::Read
+                    };
+                    for _ in 0..10 {
+                        let _permit = coordinator.acquire(class).unwrap();
+                        let (running, others) = match class {
+                            IoClass::Read => (reading, writing),
+                            IoClass::Write => (writing, reading),
+                        };
+                        let concurrent = running.fetch_add(1, Ordering::SeqCst);
+                        assert_eq!(others.load(Ordering::SeqCst), 0);
+                        if class == IoClass::Write {
+                            assert_eq!(concurrent, 0);
+                        }
+                        thread::sleep(Duration::from_micros(200));
+                        running.fetch_sub(1, Ordering::SeqCst);
+                    }
+                });
+            }
+        });
+
+        let reads = coordinator.wait_stats(IoClass::Read).unwrap();
+        let writes = coordinator.wait_stats(IoClass::Write).unwrap();
+        assert_eq!(reads.granted, 2 + 60);
+        assert_eq!(writes.granted, 1 + 20);
+        assert!(writes.max_wait_us as f64 >= writes.mean_wait_us());
+    }
+}
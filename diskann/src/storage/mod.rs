@@ -5,8 +5,22 @@
 mod disk_index_storage;
 pub use disk_index_storage::*;
 
+mod index_verification;
+pub use index_verification::*;
+
+#[cfg(feature = "disk-index")]
 mod disk_graph_storage;
+#[cfg(feature = "disk-index")]
 pub use disk_graph_storage::*;
 
+mod io_coordinator;
+pub use io_coordinator::*;
+
 mod pq_storage;
 pub use pq_storage::*;
+
+mod sector_usage_stats;
+pub use sector_usage_stats::{SectorUsageStats, DEFAULT_SECTOR_USAGE_DECAY};
+
+mod storage_provider;
+pub use storage_provider::*;
@@ -14,7 +14,9 @@ use crate::utils::{
     convert_types_u32_usize, convert_types_u64_usize, convert_types_usize_u32,
     convert_types_usize_u64, convert_types_usize_u8, save_bin_f32, save_bin_u32, save_bin_u64,
 };
-use crate::utils::{file_exists, load_bin, open_file_to_write, METADATA_SIZE};
+use crate::utils::{
+    file_exists, load_bin, open_file_to_write, reservoir_sample, seeded_rng, METADATA_SIZE,
+};
 
 #[derive(Debug)]
 pub struct PQStorage {
@@ -77,6 +79,14 @@ impl PQStorage {
         Ok(())
     }
 
+    /// Write codes already packed into bytes, such as 4-bit codes, after the metadata
+    pub fn write_compressed_codes(&self, codes: &[u8]) -> std::io::Result<()> {
+        let mut writer = open_file_to_write(&self.compressed_pivot_file)?;
+        writer.seek(SeekFrom::Start((std::mem::size_of::<i32>() * 2) as u64))?;
+        writer.write_all(codes)?;
+        Ok(())
+    }
+
     pub fn write_pivot_data(
         &self,
         full_pivot_data: &[f32],
@@ -119,6 +129,42 @@ impl PQStorage {
         file_exists(&self.pivot_file)
     }
 
+    /// Rotation matrix of OPQ, saved next to the pivot file
+    pub fn rotation_matrix_file(&self) -> String {
+        self.pivot_file.clone() + "_rotation_matrix.bin"
+    }
+
+    pub fn rotation_matrix_exist(&self) -> bool {
+        file_exists(&self.rotation_matrix_file())
+    }
+
+    /// Save the dim * dim rotation matrix applied to the centered vectors before PQ
+    pub fn write_rotation_matrix(&self, rotation: &[f32], dim: usize) -> std::io::Result<()> {
+        save_bin_f32(&self.rotation_matrix_file(), rotation, dim, dim, 0)?;
+        Ok(())
+    }
+
+    /// Load the rotation matrix of dimension dim, None if the pivots were trained without one
+    pub fn load_rotation_matrix(&self, dim: usize) -> ANNResult<Option<Vec<f32>>> {
+        if !self.rotation_matrix_exist() {
+            return Ok(None);
+        }
+
+        let (rotation, nr, nc) = load_bin::<f32>(&self.rotation_matrix_file(), 0)?;
+        if nr != dim || nc != dim {
+            let error_message = format!("Error reading rotation matrix file {}. file_rows = {}, file_cols = {} but expecting {} * {}.", self.rotation_matrix_file(), nr, nc, dim, dim);
+            return Err(ANNError::log_pq_error(error_message));
+        }
+        Ok(Some(rotation))
+    }
+
+    pub fn remove_rotation_matrix(&self) -> std::io::Result<()> {
+        if self.rotation_matrix_exist() {
+            std::fs::remove_file(self.rotation_matrix_file())?;
+        }
+        Ok(())
+    }
+
     pub fn read_pivot_metadata(&self) -> std::io::Result<(usize, usize)> {
         let (_, file_num_centers, file_dim) = load_bin::<f32>(&self.pivot_file, METADATA_SIZE)?;
         Ok((file_num_centers, file_dim))
@@ -193,9 +239,11 @@ impl PQStorage {
     /// * `sampled_vectors` - sampled vector chose by p_val possibility
     /// * `slice_size` - how many sampled data return
     /// * `dim` - each sample data dimension
+    /// * `seed` - seed of the sampling, drawn from the OS when None
     pub fn gen_random_slice<T: Default + Copy + Into<f32>>(
         &self,
         mut p_val: f64,
+        seed: Option<u64>,
     ) -> ANNResult<(Vec<f32>, usize, usize)> {
         let read_blk_size = 64 * 1024 * 1024;
         let mut reader = CachedReader::new(&self.pq_data_file, read_blk_size)?;
@@ -206,7 +254,7 @@ impl PQStorage {
         let mut slice_size = 0;
         p_val = if p_val < 1f64 { p_val } else { 1f64 };
 
-        let mut generator = rand::thread_rng();
+        let mut generator = seeded_rng(seed);
         let distribution = Uniform::from(0.0..1.0);
 
         for _ in 0..npts {
@@ -223,6 +271,16 @@ impl PQStorage {
 
         Ok((sampled_vectors, slice_size, dim))
     }
+
+    /// Uniform random sample of exactly sample_size vectors of the data file, or all of them
+    /// if it has fewer, see reservoir_sample
+    pub fn gen_reservoir_sample<T: Default + Copy + Into<f32>>(
+        &self,
+        sample_size: usize,
+        seed: Option<u64>,
+    ) -> ANNResult<(Vec<f32>, usize, usize)> {
+        reservoir_sample::<T>(&self.pq_data_file, sample_size, seed)
+    }
 }
 
 #[cfg(test)]
@@ -346,7 +404,7 @@ mod pq_storage_tests {
         std::fs::write(file_name, data).expect("Failed to write sample file");
 
         let (sampled_vectors, slice_size, ndims) =
-            gen_random_slice::<f32>(file_name, 1f64).unwrap();
+            gen_random_slice::<f32>(file_name, 1f64, None).unwrap();
         let mut start = 8;
         (0..sampled_vectors.len()).for_each(|i| {
             assert_eq!(sampled_vectors[i].to_le_bytes(), data[start..start + 4]);
@@ -357,7 +415,7 @@ mod pq_storage_tests {
         assert_eq!(ndims, 8);
 
         let (sampled_vectors, slice_size, ndims) =
-            gen_random_slice::<f32>(file_name, 0f64).unwrap();
+            gen_random_slice::<f32>(file_name, 0f64, None).unwrap();
         assert_eq!(sampled_vectors.len(), 0);
         assert_eq!(slice_size, 0);
         assert_eq!(ndims, 8);
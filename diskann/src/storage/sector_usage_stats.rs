@@ -0,0 +1,188 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Long-term access frequency of the sectors of a disk index.
+//! Searches count the sectors they read; at the end of every epoch the counts are folded into
+//! exponentially decayed scores, so the scores follow the workload as it shifts while one busy
+//! epoch doesn't outweigh a long history. The scores are saved next to the index and pick the
+//! nodes to cache at warm-up and the order to place the nodes in when the index is laid out again.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::common::{ANNError, ANNResult};
+use crate::storage::DiskLayoutMeta;
+
+/// Weight of the previous scores when an epoch ends for the statistics of a newly loaded index
+pub const DEFAULT_SECTOR_USAGE_DECAY: f32 = 0.9;
+
+/// Access statistics of the sectors of a disk index
+#[derive(Debug)]
+pub struct SectorUsageStats {
+    /// Decayed access counts of the previous epochs
+    scores: Vec<f32>,
+
+    /// Accesses of the current epoch
+    counts: Vec<AtomicU32>,
+
+    /// Weight of the previous scores when an epoch ends, in [0, 1]
+    decay: f32,
+}
+
+impl SectorUsageStats {
+    /// Create empty statistics for num_sectors sectors, including the metadata sector 0
+    pub fn new(num_sectors: usize, decay: f32) -> ANNResult<Self> {
+        if !(0.0..=1.0).contains(&decay) {
+            return Err(ANNError::log_index_config_error(
+                "decay".to_string(),
+                format!("Decay should be in [0, 1], got {}", decay),
+            ));
+        }
+
+        Ok(Self {
+            scores: vec![0.0; num_sectors],
+            counts: (0..num_sectors).map(|_| AtomicU32::new(0)).collect(),
+            decay,
+        })
+    }
+
+    /// Number of sectors
+    pub fn num_sectors(&self) -> usize {
+        self.scores.len()
+    }
+
+    /// Count an access to a sector, safe to call from concurrent searches
+    #[inline]
+    pub fn record_access(&self, sector: usize) {
+        if let Some(count) = self.counts.get(sector) {
+            count.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Fold the accesses of the current epoch into the decayed scores and start a new epoch
+    pub fn end_epoch(&mut self) {
+        for (score, count) in self.scores.iter_mut().zip(self.counts.iter_mut()) {
+            *score = *score * self.decay + std::mem::take(count.get_mut()) as f32;
+        }
+    }
+
+    /// Score of a sector, counting the accesses of the current epoch in full
+    pub fn score(&self, sector: usize) -> f32 {
+        match (self.scores.get(sector), self.counts.get(sector)) {
+            (Some(score), Some(count)) => *score + count.load(Ordering::Relaxed) as f32,
+            _ => 0.0,
+        }
+    }
+
+    /// Sectors holding nodes ordered from the most to the least accessed, ties by sector.
+    /// This is the order to place the nodes in when the index is laid out again.
+    pub fn placement_order(&self) -> Vec<usize> {
+        let mut sectors: Vec<usize> = (1..self.num_sectors()).collect();
+        sectors.sort_by(|&a, &b| self.score(b).total_cmp(&self.score(a)).then(a.cmp(&b)));
+        sectors
+    }
+
+    /// Ids of the nodes in the most accessed sectors, at most max_nodes of them, for the
+    /// warm-up cache, the nodes of a sector being those starting in it in layout_meta
+    pub fn hottest_nodes(&self, max_nodes: usize, layout_meta: &DiskLayoutMeta) -> Vec<u32> {
+        let mut nodes = Vec::with_capacity(max_nodes.min(layout_meta.num_pts));
+        for sector in self.placement_order() {
+            if nodes.len() >= max_nodes || self.score(sector) <= 0.0 {
+                break;
+            }
+
+            for node in layout_meta.sector_nodes(sector) {
+                if nodes.len() >= max_nodes {
+                    break;
+                }
+                nodes.push(node);
+            }
+        }
+        nodes
+    }
+
+    /// Save the scores, with the current epoch folded in
+    pub fn save(&self, filename: &str) -> ANNResult<()> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        writer.write_u64::<LittleEndian>(self.num_sectors() as u64)?;
+        writer.write_f32::<LittleEndian>(self.decay)?;
+        for sector in 0..self.num_sectors() {
+            writer.write_f32::<LittleEndian>(self.score(sector))?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Load scores saved by save
+    pub fn load(filename: &str) -> ANNResult<Self> {
+        let mut reader = BufReader::new(File::open(filename)?);
+        let num_sectors = reader.read_u64::<LittleEndian>()? as usize;
+        let decay = reader.read_f32::<LittleEndian>()?;
+
+        let mut stats = Self::new(num_sectors, decay)?;
+        for score in stats.scores.iter_mut() {
+            *score = reader.read_f32::<LittleEndian>()?;
+        }
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod sector_usage_stats_test {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn decayed_scores_order_sectors() {
+        let mut stats = SectorUsageStats::new(5, 0.5).unwrap();
+        for _ in 0..8 {
+            stats.record_access(1);
+        }
+        stats.end_epoch();
+        for _ in 0..5 {
+            stats.record_access(3);
+        }
+        stats.record_access(4);
+        stats.record_access(100);
+        stats.end_epoch();
+
+        // Sector 1 decayed from 8 to 4 while sector 3 got 5 accesses
+        assert_eq!(stats.score(1), 4.0);
+        assert_eq!(stats.placement_order(), vec![3, 1, 4, 2]);
+
+        // 2 nodes per sector, 7 nodes: sector 4 holds only node 6
+        let mut layout_meta = DiskLayoutMeta {
+            num_pts: 7,
+            dim: 8,
+            medoid: 0,
+            max_node_len: 2048,
+            num_nodes_per_sector: 2,
+            frozen_point: None,
+        };
+        assert_eq!(stats.hottest_nodes(3, &layout_meta), vec![4, 5, 0]);
+        assert_eq!(stats.hottest_nodes(10, &layout_meta), vec![4, 5, 0, 1, 6]);
+
+        // Nodes of two sectors start in the odd sectors
+        layout_meta.max_node_len = 6000;
+        layout_meta.num_nodes_per_sector = 0;
+        assert_eq!(stats.hottest_nodes(10, &layout_meta), vec![1, 0]);
+
+        let file = "sector_usage_stats_test.bin";
+        stats.record_access(2);
+        stats.save(file).unwrap();
+        let loaded = SectorUsageStats::load(file).unwrap();
+        for sector in 0..5 {
+            assert_eq!(loaded.score(sector), stats.score(sector));
+        }
+        fs::remove_file(file).unwrap();
+
+        assert!(SectorUsageStats::new(5, 1.5).is_err());
+    }
+}
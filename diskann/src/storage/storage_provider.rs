@@ -0,0 +1,436 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Persistence of the vectors and the graph of an index behind a trait, so an index can be kept
+//! in a key-value store or a map instead of the aligned disk index file.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::common::{ANNError, ANNResult};
+
+use super::{DiskIndexStorage, DiskLayoutMeta, IoClass, IoCoordinator};
+
+const SECTOR_LEN: usize = 4096;
+
+/// A point of the index: its full precision vector and its neighbors
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredNode<T> {
+    /// Full precision vector of dim values
+    pub vector: Vec<T>,
+
+    /// Ids of the neighbors in the graph
+    pub neighbors: Vec<u32>,
+}
+
+/// What a storage provider knows of the index besides its nodes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StorageMetadata {
+    /// Number of points, whose ids are 0..num_points
+    pub num_points: usize,
+
+    /// Dimension of the vectors
+    pub dim: usize,
+
+    /// Id of the entry point of the search
+    pub medoid: u32,
+
+    /// Most neighbors a node can have
+    pub max_degree: usize,
+
+    /// Id of the frozen point, which is not a data point
+    pub frozen_point: Option<u32>,
+}
+
+/// Where the nodes of an index are kept. The aligned disk index file is the default, and an
+/// embedder can implement this over a key-value store.
+pub trait StorageProvider<T> {
+    /// Metadata of the stored index
+    fn metadata(&self) -> ANNResult<StorageMetadata>;
+
+    /// Read the vector and the neighbors of node id
+    fn get_node(&self, id: u32) -> ANNResult<StoredNode<T>>;
+
+    /// Write the vector and the neighbors of node id
+    fn put_node(&mut self, id: u32, node: &StoredNode<T>) -> ANNResult<()>;
+
+    /// Write the metadata of the index, before its nodes
+    fn put_metadata(&mut self, metadata: &StorageMetadata) -> ANNResult<()>;
+
+    /// Read the nodes ids ahead of the get_node and put_node calls on them. Stores that gain
+    /// nothing from it do nothing.
+    fn read_ahead(&self, ids: &[u32]) -> ANNResult<()> {
+        let _ = ids;
+        Ok(())
+    }
+}
+
+/// Copy the metadata and every node of from to to, e.g. to move a disk index into another
+/// store. Returns the number of nodes copied.
+pub fn copy_nodes<T>(
+    from: &dyn StorageProvider<T>,
+    to: &mut dyn StorageProvider<T>,
+) -> ANNResult<usize> {
+    let metadata = from.metadata()?;
+    to.put_metadata(&metadata)?;
+    let num_points = metadata.num_points;
+    for id in 0..num_points as u32 {
+        to.put_node(id, &from.get_node(id)?)?;
+    }
+
+    Ok(num_points)
+}
+
+/// Check that node fits in an index of the given metadata
+fn check_node<T>(metadata: &StorageMetadata, id: u32, node: &StoredNode<T>) -> ANNResult<()> {
+    if node.vector.len() != metadata.dim {
+        return Err(ANNError::log_index_error(format!(
+            "ERROR: Node {} has a vector of {} dimensions, expecting {}.",
+            id,
+            node.vector.len(),
+            metadata.dim
+        )));
+    }
+    if node.neighbors.len() > metadata.max_degree {
+        return Err(ANNError::log_index_error(format!(
+            "ERROR: Node {} has {} neighbors, more than the max degree {}.",
+            id,
+            node.neighbors.len(),
+            metadata.max_degree
+        )));
+    }
+
+    Ok(())
+}
+
+/// Nodes kept in a map, for tests and for indexes that never touch the disk
+#[derive(Debug, Clone)]
+pub struct InMemoryStorageProvider<T> {
+    metadata: StorageMetadata,
+    nodes: HashMap<u32, StoredNode<T>>,
+}
+
+impl<T> InMemoryStorageProvider<T> {
+    /// Create an empty store of vectors of dim dimensions and nodes of up to max_degree
+    /// neighbors, searched from medoid
+    pub fn new(dim: usize, max_degree: usize, medoid: u32) -> Self {
+        Self {
+            metadata: StorageMetadata {
+                num_points: 0,
+                dim,
+                medoid,
+                max_degree,
+                frozen_point: None,
+            },
+            nodes: HashMap::new(),
+        }
+    }
+}
+
+impl<T: Clone> StorageProvider<T> for InMemoryStorageProvider<T> {
+    fn metadata(&self) -> ANNResult<StorageMetadata> {
+        Ok(self.metadata)
+    }
+
+    fn get_node(&self, id: u32) -> ANNResult<StoredNode<T>> {
+        self.nodes.get(&id).cloned().ok_or_else(|| {
+            ANNError::log_index_error(format!("ERROR: Node {} is not in the store.", id))
+        })
+    }
+
+    fn put_node(&mut self, id: u32, node: &StoredNode<T>) -> ANNResult<()> {
+        check_node(&self.metadata, id, node)?;
+        self.nodes.insert(id, node.clone());
+        self.metadata.num_points = self.metadata.num_points.max(id as usize + 1);
+        Ok(())
+    }
+
+    fn put_metadata(&mut self, metadata: &StorageMetadata) -> ANNResult<()> {
+        self.metadata = *metadata;
+        Ok(())
+    }
+}
+
+/// Nodes of the disk index file written by DiskIndexStorage::create_disk_layout, read and
+/// overwritten in place. The number of points is fixed by the layout.
+#[derive(Debug)]
+pub struct AlignedFileStorageProvider<T> {
+    layout_meta: DiskLayoutMeta,
+    file: Mutex<File>,
+    filename: String,
+
+    /// Takes turns for the writes with the searches of the file when set
+    io_coordinator: Option<Arc<IoCoordinator>>,
+
+    _marker: PhantomData<T>,
+}
+
+impl<T> AlignedFileStorageProvider<T> {
+    /// Open the disk index of storage
+    pub fn open(storage: &DiskIndexStorage<T>) -> ANNResult<Self> {
+        let layout_meta = storage.load_disk_layout_meta()?;
+        let filename = storage.disk_index_file();
+        let file = OpenOptions::new().read(true).write(true).open(&filename)?;
+        Ok(Self {
+            layout_meta,
+            file: Mutex::new(file),
+            filename,
+            io_coordinator: None,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Write the nodes in turns given by coordinator, shared with the DiskIndex searching the
+    /// file, so the searches never read a node half written
+    pub fn with_io_coordinator(mut self, coordinator: Arc<IoCoordinator>) -> Self {
+        self.io_coordinator = Some(coordinator);
+        self
+    }
+
+    /// Byte offset of the node in the file, after checking its id
+    fn node_offset(&self, id: u32) -> ANNResult<u64> {
+        if id as usize >= self.layout_meta.num_pts {
+            return Err(ANNError::log_index_error(format!(
+                "ERROR: Node {} is out of the {} points of disk index {}.",
+                id, self.layout_meta.num_pts, self.filename
+            )));
+        }
+
+        Ok((self.layout_meta.node_sector(id) * SECTOR_LEN
+            + self.layout_meta.node_offset_in_sector(id)) as u64)
+    }
+
+    fn lock_file(&self) -> ANNResult<std::sync::MutexGuard<'_, File>> {
+        self.file.lock().map_err(|_| {
+            ANNError::log_lock_poison_error(format!(
+                "failed to acquire the lock for disk index {}.",
+                self.filename
+            ))
+        })
+    }
+
+    fn vector_len(&self) -> usize {
+        self.layout_meta.dim * mem::size_of::<T>()
+    }
+}
+
+impl<T: Copy> StorageProvider<T> for AlignedFileStorageProvider<T> {
+    fn metadata(&self) -> ANNResult<StorageMetadata> {
+        let neighbors_len = self
+            .layout_meta
+            .max_node_len
+            .saturating_sub(self.vector_len() + mem::size_of::<u32>());
+        Ok(StorageMetadata {
+            num_points: self.layout_meta.num_pts,
+            dim: self.layout_meta.dim,
+            medoid: self.layout_meta.medoid,
+            max_degree: neighbors_len / mem::size_of::<u32>(),
+            frozen_point: self.layout_meta.frozen_point,
+        })
+    }
+
+    fn get_node(&self, id: u32) -> ANNResult<StoredNode<T>> {
+        let offset = self.node_offset(id)?;
+        let mut node_buf = vec![0u8; self.layout_meta.max_node_len];
+        {
+            let mut file = self.lock_file()?;
+            file.seek(SeekFrom::Start(offset))?;
+            file.read_exact(&mut node_buf)?;
+        }
+
+        let vector_len = self.vector_len();
+        let vector = node_buf[..vector_len]
+            .chunks_exact(mem::size_of::<T>())
+            // SAFETY: each chunk holds the size_of::<T>() bytes of a T written by the layout
+            .map(|bytes| unsafe { std::ptr::read_unaligned(bytes.as_ptr() as *const T) })
+            .collect();
+        let num_neighbors = LittleEndian::read_u32(&node_buf[vector_len..]) as usize;
+        let neighbors_start = vector_len + mem::size_of::<u32>();
+        let neighbors_buf = node_buf
+            .get(neighbors_start..neighbors_start + num_neighbors * mem::size_of::<u32>())
+            .ok_or_else(|| {
+                ANNError::log_index_error(format!(
+                    "ERROR: Node {} of disk index {} has {} neighbors, more than fit in it.",
+                    id, self.filename, num_neighbors
+                ))
+            })?;
+
+        Ok(StoredNode {
+            vector,
+            neighbors: neighbors_buf
+                .chunks_exact(mem::size_of::<u32>())
+                .map(LittleEndian::read_u32)
+                .collect(),
+        })
+    }
+
+    fn put_node(&mut self, id: u32, node: &StoredNode<T>) -> ANNResult<()> {
+        check_node(&self.metadata()?, id, node)?;
+        let offset = self.node_offset(id)?;
+
+        let mut node_buf = vec![0u8; self.layout_meta.max_node_len];
+        let vector_len = self.vector_len();
+        // SAFETY: the vector holds dim values of T, vector_len bytes
+        let vector_bytes =
+            unsafe { std::slice::from_raw_parts(node.vector.as_ptr() as *const u8, vector_len) };
+        node_buf[..vector_len].copy_from_slice(vector_bytes);
+        LittleEndian::write_u32(&mut node_buf[vector_len..], node.neighbors.len() as u32);
+        let neighbors_start = vector_len + mem::size_of::<u32>();
+        LittleEndian::write_u32_into(
+            &node.neighbors,
+            &mut node_buf
+                [neighbors_start..neighbors_start + node.neighbors.len() * mem::size_of::<u32>()],
+        );
+
+        let _permit = self
+            .io_coordinator
+            .as_ref()
+            .map(|coordinator| coordinator.acquire(IoClass::Write))
+            .transpose()?;
+        let mut file = self.lock_file()?;
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(&node_buf)?;
+        Ok(())
+    }
+
+    /// The layout fixes the metadata, only metadata it already holds is accepted
+    fn put_metadata(&mut self, metadata: &StorageMetadata) -> ANNResult<()> {
+        let layout = self.metadata()?;
+        let same_degree = StorageMetadata {
+            max_degree: layout.max_degree,
+            ..*metadata
+        };
+        if metadata.max_degree > layout.max_degree || same_degree != layout {
+            return Err(ANNError::log_index_error(format!(
+                "ERROR: Disk index {} has the layout of {:?}, it can't hold {:?}.",
+                self.filename, layout, metadata
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Advise the OS to read the distinct sectors holding the nodes into the page cache,
+    /// adjacent sectors together, so the writes to them that follow don't wait on reading the
+    /// rest of their pages. This is posix_fadvise WILLNEED on Linux and nothing elsewhere; no
+    /// sector is read here.
+    #[cfg(feature = "platform")]
+    fn read_ahead(&self, ids: &[u32]) -> ANNResult<()> {
+        let sectors_per_node = self.layout_meta.sectors_per_node();
+        let mut sectors = Vec::with_capacity(ids.len() * sectors_per_node);
+        for &id in ids {
+            self.node_offset(id)?;
+            let first = self.layout_meta.node_sector(id);
+            sectors.extend(first..first + sectors_per_node);
+        }
+        sectors.sort_unstable();
+        sectors.dedup();
+
+        // Runs of adjacent sectors, as their first sector and their length
+        let mut runs: Vec<(usize, usize)> = Vec::new();
+        for sector in sectors {
+            match runs.last_mut() {
+                Some((first, len)) if *first + *len == sector => *len += 1,
+                _ => runs.push((sector, 1)),
+            }
+        }
+
+        let file = self.lock_file()?;
+        for (first, len) in runs {
+            platform::advise_will_need(
+                &file,
+                (first * SECTOR_LEN) as u64,
+                (len * SECTOR_LEN) as u64,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Without the platform crate there is no read ahead advice, the ids are only checked
+    #[cfg(not(feature = "platform"))]
+    fn read_ahead(&self, ids: &[u32]) -> ANNResult<()> {
+        for &id in ids {
+            self.node_offset(id)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod storage_provider_test {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn disk_index_nodes_copy_to_memory_and_back() {
+        let prefix = "storage_provider_test";
+        fs::copy(
+            "tests/data/truth_disk_index_siftsmall_learn_256pts_R4_L50_A1.2_disk.index",
+            format!("{}_disk.index", prefix),
+        )
+        .unwrap();
+        let storage = DiskIndexStorage::<f32>::new(
+            "tests/data/siftsmall_learn_256pts.fbin".to_string(),
+            prefix.to_string(),
+        )
+        .unwrap();
+        let mut adjacency_lists = Vec::new();
+        storage
+            .for_each_adjacency_list(|_, neighbors| {
+                adjacency_lists.push(neighbors.to_vec());
+                Ok(())
+            })
+            .unwrap();
+
+        let mut disk = AlignedFileStorageProvider::open(&storage).unwrap();
+        let metadata = disk.metadata().unwrap();
+        assert_eq!((metadata.num_points, metadata.dim), (256, 128));
+        assert_eq!(metadata.max_degree, 4);
+
+        let mut memory = InMemoryStorageProvider::new(metadata.dim, metadata.max_degree, 0);
+        assert_eq!(copy_nodes(&disk, &mut memory).unwrap(), 256);
+        assert_eq!(memory.metadata().unwrap(), metadata);
+        assert!(disk.put_metadata(&metadata).is_ok());
+        let moved_medoid = StorageMetadata {
+            medoid: metadata.medoid + 1,
+            ..metadata
+        };
+        assert!(disk.put_metadata(&moved_medoid).is_err());
+        for (id, neighbors) in adjacency_lists.iter().enumerate() {
+            assert_eq!(&memory.get_node(id as u32).unwrap().neighbors, neighbors);
+        }
+
+        let mut node = memory.get_node(7).unwrap();
+        node.vector[0] = -1.5;
+        node.neighbors = vec![3, 2, 1];
+        disk.put_node(7, &node).unwrap();
+        let written = disk.get_node(7).unwrap();
+        let neighbor = disk.get_node(8).unwrap();
+        let too_many_neighbors = StoredNode {
+            vector: node.vector.clone(),
+            neighbors: vec![0; 5],
+        };
+        disk.read_ahead(&[8, 200, 7, 8]).unwrap();
+        let read_ahead_out_of_range = disk.read_ahead(&[7, 256]);
+        let out_of_range = disk.put_node(256, &node);
+        let too_large = disk.put_node(7, &too_many_neighbors);
+        fs::remove_file(format!("{}_disk.index", prefix)).unwrap();
+
+        assert_eq!(written, node);
+        assert_eq!(neighbor, memory.get_node(8).unwrap());
+        assert!(read_ahead_out_of_range.is_err());
+        assert!(out_of_range.is_err());
+        assert!(too_large.is_err());
+        assert!(memory.get_node(256).is_err());
+    }
+}
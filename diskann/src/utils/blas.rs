@@ -0,0 +1,133 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! The BLAS routines of k-means and OPQ.
+//! With the blas feature they are OpenBLAS's through cblas. Targets without a native BLAS,
+//! e.g. wasm32-unknown-unknown, get plain loops under the same names, which only handle the
+//! row major layout the crate uses.
+
+#[cfg(feature = "blas")]
+extern crate openblas_src;
+
+#[cfg(feature = "blas")]
+pub(crate) use cblas::{sgemm, snrm2, Layout, Transpose};
+
+#[cfg(not(feature = "blas"))]
+pub(crate) use fallback::{sgemm, snrm2, Layout, Transpose};
+
+#[cfg(not(feature = "blas"))]
+mod fallback {
+    use rayon::prelude::*;
+
+    /// Storage order of the matrices
+    #[derive(Debug, Clone, Copy)]
+    pub(crate) enum Layout {
+        /// Rows are contiguous
+        RowMajor,
+    }
+
+    /// Whether a matrix is used as it is or transposed
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub(crate) enum Transpose {
+        /// As it is
+        None,
+        /// Transposed
+        Ordinary,
+    }
+
+    /// C = alpha * op(A) * op(B) + beta * C, for an m * k op(A), a k * n op(B) and an m * n C
+    /// with leading dimensions lda, ldb and ldc. Unsafe only to match cblas.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) unsafe fn sgemm(
+        layout: Layout,
+        transa: Transpose,
+        transb: Transpose,
+        m: i32,
+        n: i32,
+        k: i32,
+        alpha: f32,
+        a: &[f32],
+        lda: i32,
+        b: &[f32],
+        ldb: i32,
+        beta: f32,
+        c: &mut [f32],
+        ldc: i32,
+    ) {
+        let Layout::RowMajor = layout;
+        let (m, n, k) = (m as usize, n as usize, k as usize);
+        let (lda, ldb, ldc) = (lda as usize, ldb as usize, ldc as usize);
+        let a_at = |i: usize, p: usize| match transa {
+            Transpose::None => a[i * lda + p],
+            Transpose::Ordinary => a[p * lda + i],
+        };
+        let b_at = |p: usize, j: usize| match transb {
+            Transpose::None => b[p * ldb + j],
+            Transpose::Ordinary => b[j * ldb + p],
+        };
+
+        c.par_chunks_mut(ldc).take(m).enumerate().for_each(|(i, row)| {
+            for (j, value) in row[..n].iter_mut().enumerate() {
+                let dot: f32 = (0..k).map(|p| a_at(i, p) * b_at(p, j)).sum();
+                // C isn't read when beta is 0, as with BLAS, so it may start uninitialized
+                *value = if beta == 0.0 {
+                    alpha * dot
+                } else {
+                    alpha * dot + beta * *value
+                };
+            }
+        });
+    }
+
+    /// Euclidean norm of the n elements of x spaced incx apart. Unsafe only to match cblas.
+    pub(crate) unsafe fn snrm2(n: i32, x: &[f32], incx: i32) -> f32 {
+        x.iter()
+            .step_by(incx as usize)
+            .take(n as usize)
+            .map(|value| value * value)
+            .sum::<f32>()
+            .sqrt()
+    }
+}
+
+#[cfg(test)]
+mod blas_test {
+    use super::*;
+
+    #[test]
+    fn sgemm_multiplies_transposed_matrices() {
+        // A is 2 x 3, B is 3 x 2, both stored transposed
+        let a_t = [1.0, 4.0, 2.0, 5.0, 3.0, 6.0];
+        let b_t = [1.0, 3.0, 5.0, 2.0, 4.0, 6.0];
+        let mut c = [1.0; 4];
+        unsafe {
+            sgemm(
+                Layout::RowMajor,
+                Transpose::Ordinary,
+                Transpose::Ordinary,
+                2,
+                2,
+                3,
+                2.0,
+                &a_t,
+                2,
+                &b_t,
+                3,
+                1.0,
+                &mut c,
+                2,
+            );
+        }
+        // A * B = [[22, 28], [49, 64]]
+        assert_eq!(c, [45.0, 57.0, 99.0, 129.0]);
+    }
+
+    #[test]
+    fn snrm2_skips_by_the_increment() {
+        let x = [3.0, 100.0, 4.0, 100.0];
+        assert_eq!(unsafe { snrm2(2, &x, 2) }, 5.0);
+    }
+}
@@ -0,0 +1,167 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Reader of the vectors of a bin file a chunk at a time.
+//!
+//! A bin file is the number of points and the dimension as little endian i32, then the
+//! points one after the other. The header is checked against the size of the file on open,
+//! so a truncated or mislabeled file fails before anything is copied, and the progress of
+//! the read is logged as the chunks come in.
+
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::marker::PhantomData;
+use std::mem;
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use crate::common::{ANNError, ANNResult};
+use crate::instrumentation::IndexLogger;
+
+/// Size of the header of a bin file
+const BIN_HEADER_SIZE: u64 = 2 * mem::size_of::<i32>() as u64;
+
+/// Reader of the vectors of a bin file, chunk_points at a time
+pub struct ChunkedBinReader<T> {
+    reader: BufReader<File>,
+
+    /// Number of points the header gives
+    num_points: usize,
+
+    /// Dimension the header gives
+    dim: usize,
+
+    /// Most points read in a chunk
+    chunk_points: usize,
+
+    /// Number of points read so far
+    num_read: usize,
+
+    logger: IndexLogger,
+
+    element: PhantomData<T>,
+}
+
+impl<T> std::fmt::Debug for ChunkedBinReader<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChunkedBinReader")
+            .field("num_points", &self.num_points)
+            .field("dim", &self.dim)
+            .field("chunk_points", &self.chunk_points)
+            .field("num_read", &self.num_read)
+            .finish()
+    }
+}
+
+impl<T: Default + Copy> ChunkedBinReader<T> {
+    /// Open the bin file at filename, checking that its header matches its size for points
+    /// of type T
+    pub fn open(filename: &str, chunk_points: usize) -> ANNResult<Self> {
+        let file = File::open(filename)?;
+        let file_size = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+        if file_size < BIN_HEADER_SIZE {
+            return Err(ANNError::log_index_error(format!(
+                "ERROR: Data file {} has {} bytes, less than the header of a bin file.",
+                filename, file_size
+            )));
+        }
+
+        let num_points = reader.read_i32::<LittleEndian>()?;
+        let dim = reader.read_i32::<LittleEndian>()?;
+        let expected_size = (num_points.max(0) as u64)
+            .checked_mul(dim.max(0) as u64)
+            .and_then(|values| values.checked_mul(mem::size_of::<T>() as u64))
+            .and_then(|bytes| bytes.checked_add(BIN_HEADER_SIZE));
+        if num_points < 0 || dim < 0 || expected_size != Some(file_size) {
+            return Err(ANNError::log_index_error(format!(
+                "ERROR: Data file {} has a header of {} points of dimension {}, which doesn't \
+                 match its size of {} bytes for elements of {} bytes.",
+                filename,
+                num_points,
+                dim,
+                file_size,
+                mem::size_of::<T>()
+            )));
+        }
+
+        let chunk_points = chunk_points.max(1);
+        Ok(Self {
+            reader,
+            num_points: num_points as usize,
+            dim: dim as usize,
+            chunk_points,
+            num_read: 0,
+            logger: IndexLogger::for_stage("Reading data", num_points as usize, chunk_points),
+            element: PhantomData,
+        })
+    }
+
+    /// Number of points of the file
+    pub fn num_points(&self) -> usize {
+        self.num_points
+    }
+
+    /// Dimension of the points
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// Values of the next chunk of points, row after row, None once every point is read
+    pub fn next_chunk(&mut self) -> ANNResult<Option<Vec<T>>> {
+        let num_points = self.chunk_points.min(self.num_points - self.num_read);
+        if num_points == 0 {
+            return Ok(None);
+        }
+
+        let mut rows = vec![T::default(); num_points * self.dim];
+        // SAFETY: the bytes of the rows are overwritten with the values the file holds
+        let bytes = unsafe {
+            std::slice::from_raw_parts_mut(
+                rows.as_mut_ptr() as *mut u8,
+                mem::size_of_val(&rows[..]),
+            )
+        };
+        self.reader.read_exact(bytes)?;
+        self.num_read += num_points;
+        self.logger.items_processed(num_points)?;
+
+        Ok(Some(rows))
+    }
+}
+
+#[cfg(test)]
+mod chunked_bin_reader_test {
+    use super::*;
+    use crate::utils::save_bin_f32;
+
+    #[test]
+    fn chunks_cover_the_file_and_headers_are_validated() {
+        let file = "chunks_cover_the_file_and_headers_are_validated.bin";
+        let data: Vec<f32> = (0..35).map(|i| i as f32).collect();
+        save_bin_f32(file, &data, 7, 5, 0).unwrap();
+
+        let mut reader = ChunkedBinReader::<f32>::open(file, 3).unwrap();
+        assert_eq!((reader.num_points(), reader.dim()), (7, 5));
+        let mut chunk_sizes = Vec::new();
+        let mut values = Vec::new();
+        while let Some(chunk) = reader.next_chunk().unwrap() {
+            chunk_sizes.push(chunk.len() / 5);
+            values.extend(chunk);
+        }
+        assert_eq!(chunk_sizes, vec![3, 3, 1]);
+        assert_eq!(values, data);
+
+        // The same bytes don't make whole points of another type, nor a truncated file
+        assert!(ChunkedBinReader::<u64>::open(file, 3).is_err());
+        let bytes = std::fs::read(file).unwrap();
+        std::fs::write(file, &bytes[..bytes.len() - 4]).unwrap();
+        assert!(ChunkedBinReader::<f32>::open(file, 3).is_err());
+        std::fs::write(file, &bytes[..6]).unwrap();
+        assert!(ChunkedBinReader::<f32>::open(file, 3).is_err());
+        std::fs::remove_file(file).unwrap();
+    }
+}
@@ -12,7 +12,17 @@ use std::fs::{self, File, OpenOptions};
 use std::io::{Read, BufReader, Write, Seek, SeekFrom};
 use std::path::Path;
 
+#[cfg(feature = "platform")]
+pub use platform::MmapFile;
+#[cfg(feature = "platform")]
+use platform::{FileLock, LockMode};
+
+use crate::common::{ANNError, ANNResult};
 use crate::model::data_store::DatasetDto;
+use crate::utils::ChunkedBinReader;
+
+/// Points copy_aligned_data_from_file reads at a time
+const COPY_CHUNK_POINTS: usize = 65536;
 
 /// Read metadata of data file.
 pub fn load_metadata_from_file(file_name: &str) -> std::io::Result<(usize, usize)> {
@@ -57,26 +67,25 @@ pub fn copy_aligned_data_from_file<T: Default + Copy>(
     bin_file: &str,
     dataset_dto: DatasetDto<T>,
     pts_offset: usize,
-) -> std::io::Result<(usize, usize)> {
-    let mut reader = File::open(bin_file)?;
-
-    let npts = reader.read_i32::<LittleEndian>()? as usize;
-    let dim = reader.read_i32::<LittleEndian>()? as usize;
+) -> ANNResult<(usize, usize)> {
+    let mut reader = ChunkedBinReader::<T>::open(bin_file, COPY_CHUNK_POINTS)?;
+    let npts = reader.num_points();
+    let dim = reader.dim();
     let rounded_dim = dataset_dto.rounded_dim;
-    let offset = pts_offset * rounded_dim;
+    if dim > rounded_dim || (pts_offset + npts) * rounded_dim > dataset_dto.data.len() {
+        return Err(ANNError::log_index_error(format!(
+            "ERROR: {} points of dimension {} from {} don't fit at point {} of a dataset of \
+             {} values of dimension {}.",
+            npts, dim, bin_file, pts_offset, dataset_dto.data.len(), rounded_dim
+        )));
+    }
 
-    for i in 0..npts {
-        let data_slice = &mut dataset_dto.data[offset + i * rounded_dim..offset + i * rounded_dim + dim];
-        let mut buf = vec![0u8; dim * mem::size_of::<T>()];
-        reader.read_exact(&mut buf)?;
-        
-        let ptr = buf.as_ptr() as *const T;
-        let temp_slice = unsafe { std::slice::from_raw_parts(ptr, dim) };
-        data_slice.copy_from_slice(temp_slice);
-        
-        (i * rounded_dim + dim..i * rounded_dim + rounded_dim).for_each(|j| {
-            dataset_dto.data[j] = T::default();
-        });
+    let mut slots = dataset_dto.data[pts_offset * rounded_dim..].chunks_exact_mut(rounded_dim);
+    while let Some(chunk) = reader.next_chunk()? {
+        for (point, slot) in chunk.chunks_exact(dim.max(1)).zip(&mut slots) {
+            slot[..dim].copy_from_slice(point);
+            slot[dim..].fill(T::default());
+        }
     }
 
     Ok((npts, dim))
@@ -110,6 +119,89 @@ pub fn file_exists(filename: &str) -> bool {
     std::path::Path::new(filename).exists()
 }
 
+/// A whole file read into memory, in place of the mapping of platform::MmapFile where the
+/// platform crate isn't built. The bytes start 8-byte aligned, as a mapping starts at a page.
+#[cfg(not(feature = "platform"))]
+#[derive(Debug)]
+pub struct MmapFile {
+    words: Vec<u64>,
+    len: usize,
+}
+
+#[cfg(not(feature = "platform"))]
+impl MmapFile {
+    /// Read the whole file
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        let mut words = vec![0u64; bytes.len().div_ceil(mem::size_of::<u64>())];
+        for (word, chunk) in words.iter_mut().zip(bytes.chunks(mem::size_of::<u64>())) {
+            let mut word_bytes = [0u8; 8];
+            word_bytes[..chunk.len()].copy_from_slice(chunk);
+            *word = u64::from_ne_bytes(word_bytes);
+        }
+
+        Ok(Self {
+            words,
+            len: bytes.len(),
+        })
+    }
+
+    /// Contents of the file
+    pub fn as_bytes(&self) -> &[u8] {
+        // The words hold at least len bytes
+        unsafe { std::slice::from_raw_parts(self.words.as_ptr() as *const u8, self.len) }
+    }
+}
+
+/// Acquire the advisory lock guarding writes to an index output path.
+/// The lock lives in `<path_prefix>.lock`, which is removed when the last guard is dropped.
+/// Fails instead of blocking when another process is already writing or reading the same
+/// output.
+#[cfg(feature = "platform")]
+pub fn lock_index_output(path_prefix: &str) -> ANNResult<FileLock> {
+    let lock_file = format!("{}.lock", path_prefix);
+    FileLock::try_lock_transient(&lock_file, LockMode::Exclusive)?.ok_or_else(|| {
+        ANNError::log_index_error(format!(
+            "ERROR: Index output {} is locked by another process ({}).",
+            path_prefix, lock_file
+        ))
+    })
+}
+
+/// Acquire the advisory lock of an index opened for reading, shared with the other readers.
+/// Fails instead of blocking when the index is being written, and keeps writers out while the
+/// returned guard lives.
+#[cfg(feature = "platform")]
+pub fn lock_index_input(path_prefix: &str) -> ANNResult<FileLock> {
+    let lock_file = format!("{}.lock", path_prefix);
+    FileLock::try_lock_transient(&lock_file, LockMode::Shared)?.ok_or_else(|| {
+        ANNError::log_index_error(format!(
+            "ERROR: Index {} is being written by another process ({}).",
+            path_prefix, lock_file
+        ))
+    })
+}
+
+/// Guard of an index lock without the platform crate, which has no advisory file locks.
+/// Holding it keeps nothing out.
+#[cfg(not(feature = "platform"))]
+#[derive(Debug)]
+pub struct FileLock;
+
+/// Without the platform crate there are no advisory file locks, other processes writing the
+/// same output aren't kept out
+#[cfg(not(feature = "platform"))]
+pub fn lock_index_output(_path_prefix: &str) -> ANNResult<FileLock> {
+    Ok(FileLock)
+}
+
+/// Without the platform crate there are no advisory file locks, other processes writing the
+/// index aren't kept out
+#[cfg(not(feature = "platform"))]
+pub fn lock_index_input(_path_prefix: &str) -> ANNResult<FileLock> {
+    Ok(FileLock)
+}
+
 /// Save data to file
 /// # Arguments
 /// * `filename` - filename where the data is
@@ -217,6 +309,26 @@ mod file_util_test {
 
     pub const DIM_8: usize = 8;
 
+    #[cfg(feature = "platform")]
+    #[test]
+    fn lock_index_output_test() {
+        let path_prefix = "test_lock_index_output";
+        {
+            let _lock = lock_index_output(path_prefix).unwrap();
+            assert!(lock_index_output(path_prefix).is_err());
+            assert!(lock_index_input(path_prefix).is_err());
+        }
+        {
+            let _readers = [
+                lock_index_input(path_prefix).unwrap(),
+                lock_index_input(path_prefix).unwrap(),
+            ];
+            assert!(lock_index_output(path_prefix).is_err());
+        }
+        assert!(lock_index_output(path_prefix).is_ok());
+        assert!(!file_exists(&format!("{}.lock", path_prefix)));
+    }
+
     #[test]
     fn load_metadata_test() {
         let file_name = "test_load_metadata_test.bin";
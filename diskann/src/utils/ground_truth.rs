@@ -0,0 +1,283 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Exact nearest neighbors of queries found by comparing them with every point of a dataset.
+//!
+//! The neighbors are written as a truth set: the number of queries and K as little endian
+//! i32, the K ids of every query, nearest first, then their K distances, the format the
+//! search drivers measure recall against.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::mem;
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use log::info;
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+use vector::{FullPrecisionDistance, Metric};
+
+use crate::common::{ANNError, ANNResult, AlignedBoxWithSlice};
+use crate::utils::load_bin;
+
+/// K nearest neighbors of every query, nearest first
+#[derive(Debug, Clone, PartialEq)]
+pub struct GroundTruth {
+    /// Number of queries
+    pub num_queries: usize,
+
+    /// Number of neighbors of each query
+    pub k_value: usize,
+
+    /// Ids of the neighbors, K per query
+    pub ids: Vec<u32>,
+
+    /// Distances of the neighbors, K per query
+    pub distances: Vec<f32>,
+}
+
+impl GroundTruth {
+    /// Ids of the neighbors of a query, nearest first
+    pub fn neighbors(&self, query_id: usize) -> &[u32] {
+        &self.ids[query_id * self.k_value..(query_id + 1) * self.k_value]
+    }
+
+    /// Write the neighbors to filename as a truth set with distances
+    pub fn save(&self, filename: &str) -> ANNResult<()> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        writer.write_i32::<LittleEndian>(self.num_queries as i32)?;
+        writer.write_i32::<LittleEndian>(self.k_value as i32)?;
+        for id in self.ids.iter() {
+            writer.write_u32::<LittleEndian>(*id)?;
+        }
+        for distance in self.distances.iter() {
+            writer.write_f32::<LittleEndian>(*distance)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Find the exact K nearest neighbors of each query among the points, both given row by row
+/// with dim elements each, by the SIMD distance of metric on vectors padded to N elements.
+/// Queries are searched in parallel unless num_threads is 1. Ties go to the lower id.
+pub fn compute_ground_truth<T, const N: usize>(
+    points: &[T],
+    queries: &[T],
+    dim: usize,
+    k_value: usize,
+    metric: Metric,
+    num_threads: u32,
+) -> ANNResult<GroundTruth>
+where
+    T: Default + Copy + Sync + Send,
+    [T; N]: FullPrecisionDistance<T, N>,
+{
+    if dim == 0 || dim > N {
+        return Err(ANNError::log_index_config_error(
+            "dim".to_string(),
+            format!("Dimension {} should be in [1, {}]", dim, N),
+        ));
+    }
+    if !points.len().is_multiple_of(dim) || !queries.len().is_multiple_of(dim) {
+        return Err(ANNError::log_index_error(format!(
+            "ERROR: {} point and {} query elements aren't whole vectors of dimension {}.",
+            points.len(),
+            queries.len(),
+            dim
+        )));
+    }
+    let num_points = points.len() / dim;
+    let num_queries = queries.len() / dim;
+    if k_value == 0 || k_value > num_points {
+        return Err(ANNError::log_index_config_error(
+            "k_value".to_string(),
+            format!(
+                "K {} should be in [1, {}], the number of points",
+                k_value, num_points
+            ),
+        ));
+    }
+
+    let points = padded_rows::<T, N>(points, dim)?;
+    let queries = padded_rows::<T, N>(queries, dim)?;
+    let point_rows = rows::<T, N>(&points)?;
+    let query_rows = rows::<T, N>(&queries)?;
+
+    let search = |query_id: usize| -> Vec<(u32, f32)> {
+        nearest_points(query_rows[query_id], &point_rows, k_value, metric)
+    };
+    let neighbors: Vec<Vec<(u32, f32)>> = if num_threads == 1 {
+        (0..num_queries).map(search).collect()
+    } else {
+        (0..num_queries).into_par_iter().map(search).collect()
+    };
+
+    let mut ids = Vec::with_capacity(num_queries * k_value);
+    let mut distances = Vec::with_capacity(num_queries * k_value);
+    for (id, distance) in neighbors.into_iter().flatten() {
+        ids.push(id);
+        distances.push(distance);
+    }
+    Ok(GroundTruth {
+        num_queries,
+        k_value,
+        ids,
+        distances,
+    })
+}
+
+/// Compute the K nearest neighbors of the queries of query_file among the points of
+/// points_file, both in the bin format, and save them to truth_file
+pub fn generate_ground_truth_file<T, const N: usize>(
+    points_file: &str,
+    query_file: &str,
+    truth_file: &str,
+    k_value: usize,
+    metric: Metric,
+    num_threads: u32,
+) -> ANNResult<GroundTruth>
+where
+    T: Default + Copy + Sync + Send,
+    [T; N]: FullPrecisionDistance<T, N>,
+{
+    let (points, num_points, dim) = load_bin::<T>(points_file, 0)?;
+    let (queries, num_queries, query_dim) = load_bin::<T>(query_file, 0)?;
+    if query_dim != dim {
+        return Err(ANNError::log_index_error(format!(
+            "ERROR: Queries of {} have dimension {}, the points of {} have dimension {}.",
+            query_file, query_dim, points_file, dim
+        )));
+    }
+
+    info!(
+        "Computing the {} nearest neighbors of {} queries among {} points",
+        k_value, num_queries, num_points
+    );
+    let ground_truth =
+        compute_ground_truth::<T, N>(&points, &queries, dim, k_value, metric, num_threads)?;
+    ground_truth.save(truth_file)?;
+    Ok(ground_truth)
+}
+
+/// Copy rows of dim elements into an aligned buffer of rows of N elements, zero padded
+fn padded_rows<T: Default + Copy, const N: usize>(
+    data: &[T],
+    dim: usize,
+) -> ANNResult<AlignedBoxWithSlice<T>> {
+    let num_rows = data.len() / dim;
+    let mut padded = AlignedBoxWithSlice::new(num_rows.max(1) * N, mem::size_of::<T>() * 16)?;
+    for (row, padded_row) in data
+        .chunks_exact(dim)
+        .zip(padded.as_mut_slice().chunks_exact_mut(N))
+    {
+        padded_row[..dim].copy_from_slice(row);
+    }
+    Ok(padded)
+}
+
+/// Rows of N elements of a buffer filled by padded_rows
+fn rows<T, const N: usize>(padded: &AlignedBoxWithSlice<T>) -> ANNResult<Vec<&[T; N]>> {
+    padded
+        .as_slice()
+        .chunks_exact(N)
+        .map(|row| {
+            <&[T; N]>::try_from(row).map_err(|err| ANNError::log_index_error(err.to_string()))
+        })
+        .collect()
+}
+
+/// K closest (id, distance) pairs of points to query, closest first
+fn nearest_points<T, const N: usize>(
+    query: &[T; N],
+    points: &[&[T; N]],
+    k_value: usize,
+    metric: Metric,
+) -> Vec<(u32, f32)>
+where
+    [T; N]: FullPrecisionDistance<T, N>,
+{
+    let mut nearest: Vec<(u32, f32)> = Vec::with_capacity(k_value + 1);
+    for (id, point) in points.iter().enumerate() {
+        let distance = <[T; N]>::distance_compare(query, point, metric);
+        if nearest.len() == k_value && distance >= nearest[k_value - 1].1 {
+            continue;
+        }
+        let pos = nearest.partition_point(|&(_, nearer)| nearer <= distance);
+        nearest.insert(pos, (id as u32, distance));
+        nearest.truncate(k_value);
+    }
+    nearest
+}
+
+#[cfg(test)]
+mod ground_truth_test {
+    use super::*;
+    use crate::utils::save_bin_f32;
+
+    #[test]
+    fn ground_truth_matches_sorted_distances_and_is_saved() {
+        let dim = 5;
+        let points: Vec<f32> = (0..300 * dim)
+            .map(|i| ((i * 7919) % 1000) as f32 / 100.0)
+            .collect();
+        let queries: Vec<f32> = (0..12 * dim)
+            .map(|i| ((i * 104_729) % 1000) as f32 / 100.0)
+            .collect();
+
+        let ground_truth =
+            compute_ground_truth::<f32, 8>(&points, &queries, dim, 10, Metric::L2, 4).unwrap();
+        let serial =
+            compute_ground_truth::<f32, 8>(&points, &queries, dim, 10, Metric::L2, 1).unwrap();
+        assert_eq!(ground_truth, serial);
+
+        for (query_id, query) in queries.chunks_exact(dim).enumerate() {
+            let distance = |id: u32| -> f32 {
+                let point = &points[id as usize * dim..(id as usize + 1) * dim];
+                query
+                    .iter()
+                    .zip(point)
+                    .map(|(x, y)| (x - y) * (x - y))
+                    .sum()
+            };
+            let mut expected: Vec<u32> = (0..300).collect();
+            expected.sort_by(|a, b| distance(*a).total_cmp(&distance(*b)));
+            let found = ground_truth.neighbors(query_id);
+            for (rank, id) in found.iter().enumerate() {
+                let exact = distance(expected[rank]);
+                assert!((distance(*id) - exact).abs() <= 1e-3 * exact.max(1.0));
+            }
+        }
+
+        let points_file = "ground_truth_matches_sorted_distances_and_is_saved.points.bin";
+        let query_file = "ground_truth_matches_sorted_distances_and_is_saved.queries.bin";
+        let truth_file = "ground_truth_matches_sorted_distances_and_is_saved.truth.bin";
+        save_bin_f32(points_file, &points, 300, dim, 0).unwrap();
+        save_bin_f32(query_file, &queries, 12, dim, 0).unwrap();
+        let from_files = generate_ground_truth_file::<f32, 8>(
+            points_file,
+            query_file,
+            truth_file,
+            10,
+            Metric::L2,
+            2,
+        )
+        .unwrap();
+        let bytes = std::fs::read(truth_file).unwrap();
+        for file in [points_file, query_file, truth_file] {
+            std::fs::remove_file(file).unwrap();
+        }
+
+        assert_eq!(from_files, ground_truth);
+        assert_eq!(bytes.len(), 8 + 2 * 12 * 10 * 4);
+        assert_eq!(&bytes[..8], &[12, 0, 0, 0, 10, 0, 0, 0]);
+        assert_eq!(&bytes[8..12], &ground_truth.ids[0].to_le_bytes());
+
+        assert!(
+            compute_ground_truth::<f32, 8>(&points, &queries, dim, 301, Metric::L2, 1).is_err()
+        );
+        assert!(compute_ground_truth::<f32, 8>(&points, &queries, 9, 10, Metric::L2, 1).is_err());
+    }
+}
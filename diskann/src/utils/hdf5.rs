@@ -0,0 +1,1249 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Datasets of ann-benchmarks, read from the HDF5 files it distributes them in.
+//!
+//! An ann-benchmarks file holds the train vectors, the test queries, the ids and distances of
+//! their nearest train vectors as the neighbors and distances datasets, and the name of the
+//! distance as an attribute of the root group. The reader understands the part of HDF5 h5py
+//! writes by default: a version 0 or 1 superblock, version 1 object headers, groups stored as
+//! symbol tables, contiguous, compact or chunked datasets of little endian integers or floats,
+//! and string attributes of fixed or variable length. Filtered datasets, such as compressed
+//! ones, are refused.
+
+use std::borrow::Cow;
+
+use log::info;
+use vector::Metric;
+
+use crate::common::{ANNError, ANNResult};
+use crate::utils::{GroundTruth, MmapFile};
+
+/// First bytes of the superblock
+const HDF5_SIGNATURE: &[u8; 8] = b"\x89HDF\r\n\x1a\n";
+
+/// Value of an address that points nowhere
+const UNDEFINED_ADDRESS: u64 = u64::MAX;
+
+const MSG_DATASPACE: u16 = 0x0001;
+const MSG_DATATYPE: u16 = 0x0003;
+const MSG_LAYOUT: u16 = 0x0008;
+const MSG_FILTER_PIPELINE: u16 = 0x000B;
+const MSG_ATTRIBUTE: u16 = 0x000C;
+const MSG_CONTINUATION: u16 = 0x0010;
+const MSG_SYMBOL_TABLE: u16 = 0x0011;
+
+const CLASS_FIXED_POINT: u8 = 0;
+const CLASS_FLOATING_POINT: u8 = 1;
+const CLASS_STRING: u8 = 3;
+const CLASS_VARIABLE_LENGTH: u8 = 9;
+
+/// Element type of a dataset
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Element {
+    /// Little endian integer of the given size in bytes
+    Integer { size: usize, signed: bool },
+
+    /// Little endian IEEE float of the given size in bytes
+    Float { size: usize },
+}
+
+/// Shape, element type and bytes of a dataset
+#[derive(Debug)]
+struct Dataset<'a> {
+    shape: Vec<usize>,
+    element: Element,
+    data: Cow<'a, [u8]>,
+}
+
+/// Where the data of a dataset is stored
+#[derive(Debug)]
+enum Layout<'a> {
+    /// Compact or contiguous, the bytes of the data
+    Bytes(&'a [u8]),
+
+    /// Chunks of the given dimensions, the last one the element size, indexed by the B-tree at
+    /// btree_address
+    Chunked {
+        btree_address: u64,
+        chunk_dims: Vec<usize>,
+    },
+}
+
+/// A mapped HDF5 file
+#[derive(Debug)]
+pub struct Hdf5File {
+    file: MmapFile,
+
+    /// Name of the file, for error messages
+    filename: String,
+
+    /// Position of the superblock, which addresses are relative to
+    base_address: usize,
+
+    /// Bytes of an address
+    offset_size: usize,
+
+    /// Bytes of a length
+    length_size: usize,
+
+    /// Address of the object header of the root group
+    root_address: u64,
+}
+
+impl Hdf5File {
+    /// Map the HDF5 file at filename and read its superblock
+    pub fn open(filename: &str) -> ANNResult<Self> {
+        let file = MmapFile::open(filename)?;
+        let bytes = file.as_bytes();
+
+        // The superblock follows an optional user block of 512 bytes or a power of two above
+        let mut base_address = 0;
+        while bytes.get(base_address..base_address + HDF5_SIGNATURE.len())
+            != Some(&HDF5_SIGNATURE[..])
+        {
+            base_address = if base_address == 0 {
+                512
+            } else {
+                base_address * 2
+            };
+            if base_address >= bytes.len() {
+                return Err(ANNError::log_index_error(format!(
+                    "ERROR: {} isn't an HDF5 file.",
+                    filename
+                )));
+            }
+        }
+
+        let mut hdf5 = Self {
+            file,
+            filename: filename.to_string(),
+            base_address,
+            offset_size: 8,
+            length_size: 8,
+            root_address: 0,
+        };
+        let superblock = hdf5.bytes_at(0, 24)?;
+        let (version, offset_size, length_size) = (superblock[8], superblock[13], superblock[14]);
+        let addresses_start = match version {
+            0 => 24,
+            1 => 28,
+            version => {
+                return Err(hdf5.format_error(&format!(
+                    "superblock version {} isn't supported, only the versions 0 and 1 h5py \
+                     writes by default",
+                    version
+                )))
+            }
+        };
+        hdf5.offset_size = offset_size as usize;
+        hdf5.length_size = length_size as usize;
+        if !matches!(hdf5.offset_size, 2 | 4 | 8) || !matches!(hdf5.length_size, 2 | 4 | 8) {
+            return Err(hdf5.format_error("invalid address or length size"));
+        }
+
+        // Base, free space, end of file and driver addresses, then the root symbol table entry
+        // starting with the offset of its name
+        hdf5.root_address = hdf5.offset_at(addresses_start + 5 * hdf5.offset_size)?;
+        Ok(hdf5)
+    }
+
+    /// Values of the dataset at path, rows of columns, converted to f32. Returns them with the
+    /// number of rows and columns.
+    pub fn read_f32(&self, path: &str) -> ANNResult<(Vec<f32>, usize, usize)> {
+        let dataset = self.dataset(path)?;
+        let (num_rows, num_cols) = self.matrix_shape(path, &dataset)?;
+        let values = match dataset.element {
+            Element::Float { size: 4 } => dataset
+                .data
+                .chunks_exact(4)
+                .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                .collect(),
+            Element::Float { size: 8 } => dataset
+                .data
+                .chunks_exact(8)
+                .map(|bytes| f64::from_le_bytes(le_array(bytes)) as f32)
+                .collect(),
+            Element::Integer { size, signed } => dataset
+                .data
+                .chunks_exact(size)
+                .map(|bytes| integer(bytes, signed) as f32)
+                .collect(),
+            Element::Float { size } => {
+                return Err(self.format_error(&format!("{} byte floats aren't supported", size)))
+            }
+        };
+        Ok((values, num_rows, num_cols))
+    }
+
+    /// Values of the integer dataset at path, rows of columns, as u32 ids. Returns them with
+    /// the number of rows and columns. Fails if a value doesn't fit.
+    pub fn read_u32(&self, path: &str) -> ANNResult<(Vec<u32>, usize, usize)> {
+        let dataset = self.dataset(path)?;
+        let (num_rows, num_cols) = self.matrix_shape(path, &dataset)?;
+        let Element::Integer { size, signed } = dataset.element else {
+            return Err(self.format_error(&format!("dataset {} doesn't hold integers", path)));
+        };
+        let values = dataset
+            .data
+            .chunks_exact(size)
+            .map(|bytes| {
+                u32::try_from(integer(bytes, signed)).map_err(|_| {
+                    self.format_error(&format!(
+                        "dataset {} has the value {}, which isn't an id",
+                        path,
+                        integer(bytes, signed)
+                    ))
+                })
+            })
+            .collect::<ANNResult<_>>()?;
+        Ok((values, num_rows, num_cols))
+    }
+
+    /// String attribute of the root group, None if it has no attribute of that name
+    pub fn string_attribute(&self, name: &str) -> ANNResult<Option<String>> {
+        for (msg_type, data) in self.messages(self.root_address)? {
+            if msg_type != MSG_ATTRIBUTE {
+                continue;
+            }
+            let (attribute_name, datatype, value) = self.parse_attribute(data)?;
+            if attribute_name != name {
+                continue;
+            }
+
+            let class = datatype[0] & 0x0f;
+            let text = match class {
+                CLASS_STRING => value,
+                CLASS_VARIABLE_LENGTH if datatype[1] & 0x0f == 1 => {
+                    let len = self.field(value, 0, 4)? as usize;
+                    let collection = self.field(value, 4, self.offset_size)?;
+                    let index = self.field(value, 4 + self.offset_size, 4)?;
+                    let object = self.global_heap_object(collection, index)?;
+                    object.get(..len).ok_or_else(|| {
+                        self.format_error(&format!("attribute {} is truncated", name))
+                    })?
+                }
+                _ => return Err(self.format_error(&format!("attribute {} isn't a string", name))),
+            };
+            let text = String::from_utf8_lossy(text);
+            return Ok(Some(text.trim_end_matches(['\0', ' ']).to_string()));
+        }
+        Ok(None)
+    }
+
+    /// Find the dataset at a path of group names separated by /
+    fn dataset(&self, path: &str) -> ANNResult<Dataset<'_>> {
+        let mut address = self.root_address;
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            address = self
+                .group_links(address)?
+                .into_iter()
+                .find(|(link, _)| link == name)
+                .map(|(_, address)| address)
+                .ok_or_else(|| self.format_error(&format!("no dataset {}", path)))?;
+        }
+
+        let (mut shape, mut element, mut layout) = (None, None, None);
+        for (msg_type, msg) in self.messages(address)? {
+            match msg_type {
+                MSG_DATASPACE => shape = Some(self.parse_dataspace(msg)?),
+                MSG_DATATYPE => element = Some(self.parse_datatype(msg)?),
+                MSG_LAYOUT => layout = Some(self.parse_layout(msg)?),
+                MSG_FILTER_PIPELINE => {
+                    return Err(self.format_error(&format!(
+                        "dataset {} is filtered, such as compressed, repack it without filters",
+                        path
+                    )))
+                }
+                _ => {}
+            }
+        }
+        let (Some(shape), Some(element), Some(layout)) = (shape, element, layout) else {
+            return Err(self.format_error(&format!("{} isn't a dataset", path)));
+        };
+
+        let element_size = match element {
+            Element::Integer { size, .. } | Element::Float { size } => size,
+        };
+        let data = match layout {
+            Layout::Bytes(data) => Cow::Borrowed(data),
+            Layout::Chunked {
+                btree_address,
+                chunk_dims,
+            } => Cow::Owned(self.read_chunks(
+                path,
+                &shape,
+                element_size,
+                btree_address,
+                &chunk_dims,
+            )?),
+        };
+        if data.len() != shape.iter().product::<usize>() * element_size {
+            return Err(self.format_error(&format!(
+                "dataset {} has {} bytes for the shape {:?}",
+                path,
+                data.len(),
+                shape
+            )));
+        }
+        Ok(Dataset {
+            shape,
+            element,
+            data,
+        })
+    }
+
+    /// Data of a chunked dataset of one or two dimensions, the chunks it has no chunk for
+    /// holding zeros
+    fn read_chunks(
+        &self,
+        path: &str,
+        shape: &[usize],
+        element_size: usize,
+        btree_address: u64,
+        chunk_dims: &[usize],
+    ) -> ANNResult<Vec<u8>> {
+        // Vectors are a single column
+        let (num_rows, num_cols, chunk_rows, chunk_cols) = match (shape, chunk_dims) {
+            (&[num_rows], &[chunk_rows, size]) if size == element_size => {
+                (num_rows, 1, chunk_rows, 1)
+            }
+            (&[num_rows, num_cols], &[chunk_rows, chunk_cols, size]) if size == element_size => {
+                (num_rows, num_cols, chunk_rows, chunk_cols)
+            }
+            _ => {
+                return Err(self.format_error(&format!(
+                    "dataset {} of shape {:?} has chunks of {:?}",
+                    path, shape, chunk_dims
+                )))
+            }
+        };
+        if chunk_rows == 0 || chunk_cols == 0 {
+            return Err(self.format_error(&format!("dataset {} has empty chunks", path)));
+        }
+
+        let mut data = vec![0u8; num_rows * num_cols * element_size];
+        if btree_address == self.undefined_address() {
+            return Ok(data);
+        }
+        let mut chunks = Vec::new();
+        self.collect_chunks(btree_address, shape.len(), &mut chunks)?;
+
+        let row_size = chunk_cols * element_size;
+        for (offsets, chunk_address, chunk_size) in chunks {
+            let (row, col) = match offsets[..] {
+                [row, _] => (row as usize, 0),
+                [row, col, _] => (row as usize, col as usize),
+                _ => unreachable!("chunk keys have one offset per dimension"),
+            };
+            if row >= num_rows
+                || col >= num_cols
+                || !row.is_multiple_of(chunk_rows)
+                || !col.is_multiple_of(chunk_cols)
+            {
+                return Err(
+                    self.format_error(&format!("dataset {} has a chunk at {:?}", path, offsets))
+                );
+            }
+            if chunk_size < chunk_rows * row_size {
+                return Err(self.format_error(&format!(
+                    "dataset {} has a chunk of {} bytes",
+                    path, chunk_size
+                )));
+            }
+
+            // Chunks at the edges are padded to full size
+            let chunk = self.bytes_at(chunk_address, chunk_size)?;
+            let copy_size = chunk_cols.min(num_cols - col) * element_size;
+            for chunk_row in 0..chunk_rows.min(num_rows - row) {
+                let start = ((row + chunk_row) * num_cols + col) * element_size;
+                data[start..start + copy_size].copy_from_slice(
+                    &chunk[chunk_row * row_size..chunk_row * row_size + copy_size],
+                );
+            }
+        }
+        Ok(data)
+    }
+
+    /// Add the offsets, address and size of the chunks of the chunk B-tree node at address and
+    /// of the nodes below it, for a dataset of rank dimensions
+    fn collect_chunks(
+        &self,
+        address: u64,
+        rank: usize,
+        chunks: &mut Vec<(Vec<u64>, u64, usize)>,
+    ) -> ANNResult<()> {
+        let node = self.bytes_at(address, 8)?;
+        if &node[..4] != b"TREE" || node[4] != 1 {
+            return Err(self.format_error("missing chunk B-tree node"));
+        }
+        let level = node[5];
+        let num_entries = self.field(node, 6, 2)? as usize;
+
+        // Siblings, then keys and children alternating, starting and ending with a key. A key
+        // is the chunk size, the filter mask and an offset per dimension and for the element.
+        let first_key = 8 + 2 * self.offset_size;
+        let key_size = 8 + 8 * (rank + 1);
+        let stride = key_size + self.offset_size;
+        let node = self.bytes_at(address, first_key + num_entries * stride + key_size)?;
+        for entry in 0..num_entries {
+            let key = first_key + entry * stride;
+            let child = self.field(node, key + key_size, self.offset_size)?;
+            if level > 0 {
+                self.collect_chunks(child, rank, chunks)?;
+                continue;
+            }
+
+            let chunk_size = self.field(node, key, 4)? as usize;
+            let offsets = (0..rank + 1)
+                .map(|dim| self.field(node, key + 8 + 8 * dim, 8))
+                .collect::<ANNResult<_>>()?;
+            chunks.push((offsets, child, chunk_size));
+        }
+        Ok(())
+    }
+
+    /// Rows and columns of a dataset of one or two dimensions
+    fn matrix_shape(&self, path: &str, dataset: &Dataset) -> ANNResult<(usize, usize)> {
+        match dataset.shape[..] {
+            [num_rows] => Ok((num_rows, 1)),
+            [num_rows, num_cols] => Ok((num_rows, num_cols)),
+            _ => Err(self.format_error(&format!(
+                "dataset {} of shape {:?} isn't a matrix",
+                path, dataset.shape
+            ))),
+        }
+    }
+
+    /// Type and data of the messages of the version 1 object header at address, following
+    /// its continuation blocks
+    fn messages(&self, address: u64) -> ANNResult<Vec<(u16, &[u8])>> {
+        let header = self.bytes_at(address, 16)?;
+        if header[0] != 1 {
+            return Err(self.format_error(&format!(
+                "object header version {} isn't supported",
+                header[0]
+            )));
+        }
+        let header_size = self.field(header, 8, 4)?;
+
+        let mut messages = Vec::new();
+        let mut blocks = vec![(address + 16, header_size)];
+        while let Some((start, size)) = blocks.pop() {
+            let block = self.bytes_at(start, size as usize)?;
+            let mut pos = 0;
+            while pos + 8 <= block.len() {
+                let msg_type = self.field(block, pos, 2)? as u16;
+                let msg_size = self.field(block, pos + 2, 2)? as usize;
+                let flags = block[pos + 4];
+                let data = block
+                    .get(pos + 8..pos + 8 + msg_size)
+                    .ok_or_else(|| self.format_error("truncated object header message"))?;
+                if flags & 0x02 != 0 {
+                    return Err(self.format_error("shared object header messages aren't supported"));
+                }
+                if msg_type == MSG_CONTINUATION {
+                    blocks.push((
+                        self.field(data, 0, self.offset_size)?,
+                        self.field(data, self.offset_size, self.length_size)?,
+                    ));
+                } else {
+                    messages.push((msg_type, data));
+                }
+                pos += 8 + msg_size;
+            }
+        }
+        Ok(messages)
+    }
+
+    /// Names and object header addresses of the members of the group at address
+    fn group_links(&self, address: u64) -> ANNResult<Vec<(String, u64)>> {
+        let symbol_table = self
+            .messages(address)?
+            .into_iter()
+            .find(|(msg_type, _)| *msg_type == MSG_SYMBOL_TABLE)
+            .map(|(_, data)| data)
+            .ok_or_else(|| self.format_error("group isn't stored as a symbol table"))?;
+        let btree_address = self.field(symbol_table, 0, self.offset_size)?;
+        let heap_address = self.field(symbol_table, self.offset_size, self.offset_size)?;
+
+        let heap = self.bytes_at(heap_address, 8 + 2 * self.length_size + self.offset_size)?;
+        if &heap[..4] != b"HEAP" {
+            return Err(self.format_error("missing local heap"));
+        }
+        let heap_data_address = self.field(heap, 8 + 2 * self.length_size, self.offset_size)?;
+
+        let mut links = Vec::new();
+        self.collect_links(btree_address, heap_data_address, &mut links)?;
+        Ok(links)
+    }
+
+    /// Add the links of the group B-tree node at address and of the nodes below it
+    fn collect_links(
+        &self,
+        address: u64,
+        heap_data_address: u64,
+        links: &mut Vec<(String, u64)>,
+    ) -> ANNResult<()> {
+        let node = self.bytes_at(address, 8)?;
+        if &node[..4] != b"TREE" || node[4] != 0 {
+            return Err(self.format_error("missing group B-tree node"));
+        }
+        let level = node[5];
+        let num_entries = self.field(node, 6, 2)? as usize;
+
+        // Siblings, then keys and children alternating, starting and ending with a key
+        let first_child = 8 + 2 * self.offset_size + self.length_size;
+        let stride = self.length_size + self.offset_size;
+        let node = self.bytes_at(address, first_child + num_entries * stride)?;
+        for entry in 0..num_entries {
+            let child = self.field(node, first_child + entry * stride, self.offset_size)?;
+            if level > 0 {
+                self.collect_links(child, heap_data_address, links)?;
+                continue;
+            }
+
+            let symbols = self.bytes_at(child, 8)?;
+            if &symbols[..4] != b"SNOD" {
+                return Err(self.format_error("missing symbol table node"));
+            }
+            let num_symbols = self.field(symbols, 6, 2)? as usize;
+            let entry_size = 2 * self.offset_size + 24;
+            let symbols = self.bytes_at(child, 8 + num_symbols * entry_size)?;
+            for symbol in 0..num_symbols {
+                let start = 8 + symbol * entry_size;
+                let name_offset = self.field(symbols, start, self.offset_size)?;
+                let header_address =
+                    self.field(symbols, start + self.offset_size, self.offset_size)?;
+                links.push((
+                    self.heap_name(heap_data_address + name_offset)?,
+                    header_address,
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Null terminated string at address
+    fn heap_name(&self, address: u64) -> ANNResult<String> {
+        let start = self.position(address)?;
+        let bytes = &self.file.as_bytes()[start..];
+        let len = bytes
+            .iter()
+            .position(|&byte| byte == 0)
+            .ok_or_else(|| self.format_error("unterminated link name"))?;
+        Ok(String::from_utf8_lossy(&bytes[..len]).to_string())
+    }
+
+    /// Data of the object of a global heap collection
+    fn global_heap_object(&self, collection: u64, index: u64) -> ANNResult<&[u8]> {
+        let header = self.bytes_at(collection, 8 + self.length_size)?;
+        if &header[..4] != b"GCOL" {
+            return Err(self.format_error("missing global heap collection"));
+        }
+        let collection_size = self.field(header, 8, self.length_size)? as usize;
+        let heap = self.bytes_at(collection, collection_size)?;
+
+        let mut pos = 8 + self.length_size;
+        while pos + 8 + self.length_size <= heap.len() {
+            let object_index = self.field(heap, pos, 2)?;
+            let object_size = self.field(heap, pos + 8, self.length_size)? as usize;
+            if object_index == 0 {
+                break;
+            }
+            let data_start = pos + 8 + self.length_size;
+            if object_index == index {
+                return heap
+                    .get(data_start..data_start + object_size)
+                    .ok_or_else(|| self.format_error("truncated global heap object"));
+            }
+            pos = data_start + object_size.next_multiple_of(8);
+        }
+        Err(self.format_error(&format!("no global heap object {}", index)))
+    }
+
+    /// Dimensions of a dataspace message
+    fn parse_dataspace(&self, msg: &[u8]) -> ANNResult<Vec<usize>> {
+        let dims_start = match msg.first() {
+            Some(1) => 8,
+            Some(2) => 4,
+            _ => return Err(self.format_error("unsupported dataspace version")),
+        };
+        let rank = msg[1] as usize;
+        (0..rank)
+            .map(|dim| {
+                Ok(
+                    self.field(msg, dims_start + dim * self.length_size, self.length_size)?
+                        as usize,
+                )
+            })
+            .collect()
+    }
+
+    /// Element type of a datatype message
+    fn parse_datatype(&self, msg: &[u8]) -> ANNResult<Element> {
+        let class = msg.first().map(|byte| byte & 0x0f);
+        let flags = *msg.get(1).unwrap_or(&0);
+        let size = self.field(msg, 4, 4)? as usize;
+        if flags & 0x01 != 0 {
+            return Err(self.format_error("big endian values aren't supported"));
+        }
+        match class {
+            Some(CLASS_FIXED_POINT) if matches!(size, 1 | 2 | 4 | 8) => Ok(Element::Integer {
+                size,
+                signed: flags & 0x08 != 0,
+            }),
+            Some(CLASS_FLOATING_POINT) if flags & 0x40 == 0 => Ok(Element::Float { size }),
+            _ => Err(self.format_error("datasets should hold integers or floats")),
+        }
+    }
+
+    /// Where a layout message stores the data
+    fn parse_layout<'a>(&'a self, msg: &'a [u8]) -> ANNResult<Layout<'a>> {
+        let version = msg.first().copied();
+        if !matches!(version, Some(3) | Some(4)) {
+            return Err(self.format_error("unsupported data layout version"));
+        }
+        match msg.get(1) {
+            // Compact, the data is in the message
+            Some(0) => {
+                let size = self.field(msg, 2, 2)? as usize;
+                msg.get(4..4 + size)
+                    .map(Layout::Bytes)
+                    .ok_or_else(|| self.format_error("truncated compact dataset"))
+            }
+            // Contiguous
+            Some(1) => {
+                let address = self.field(msg, 2, self.offset_size)?;
+                let size = self.field(msg, 2 + self.offset_size, self.length_size)? as usize;
+                if address == self.undefined_address() {
+                    return Ok(Layout::Bytes(&[]));
+                }
+                Ok(Layout::Bytes(self.bytes_at(address, size)?))
+            }
+            // Chunked, indexed by a version 1 B-tree in version 3 messages
+            Some(2) if version == Some(3) => {
+                let num_dims = self.field(msg, 2, 1)? as usize;
+                let btree_address = self.field(msg, 3, self.offset_size)?;
+                let chunk_dims = (0..num_dims)
+                    .map(|dim| Ok(self.field(msg, 3 + self.offset_size + 4 * dim, 4)? as usize))
+                    .collect::<ANNResult<_>>()?;
+                Ok(Layout::Chunked {
+                    btree_address,
+                    chunk_dims,
+                })
+            }
+            _ => Err(self.format_error(
+                "only the chunk index of the version 3 layout is supported, repack the file \
+                 with contiguous layout",
+            )),
+        }
+    }
+
+    /// Name, datatype message and value of an attribute message
+    fn parse_attribute<'a>(&self, msg: &'a [u8]) -> ANNResult<(String, &'a [u8], &'a [u8])> {
+        let version = *msg.first().unwrap_or(&0);
+        let name_size = self.field(msg, 2, 2)? as usize;
+        let datatype_size = self.field(msg, 4, 2)? as usize;
+        let dataspace_size = self.field(msg, 6, 2)? as usize;
+        let (start, pad): (usize, fn(usize) -> usize) = match version {
+            1 => (8, |size| size.next_multiple_of(8)),
+            2 => (8, |size| size),
+            3 => (9, |size| size),
+            _ => return Err(self.format_error("unsupported attribute version")),
+        };
+
+        let datatype_start = start + pad(name_size);
+        let value_start = datatype_start + pad(datatype_size) + pad(dataspace_size);
+        let truncated = || self.format_error("truncated attribute");
+        let name = msg.get(start..start + name_size).ok_or_else(truncated)?;
+        let datatype = msg
+            .get(datatype_start..datatype_start + datatype_size)
+            .ok_or_else(truncated)?;
+        let value = msg.get(value_start..).ok_or_else(truncated)?;
+        let name = String::from_utf8_lossy(name)
+            .trim_end_matches('\0')
+            .to_string();
+        Ok((name, datatype, value))
+    }
+
+    /// Little endian unsigned value of size bytes at pos of bytes
+    fn field(&self, bytes: &[u8], pos: usize, size: usize) -> ANNResult<u64> {
+        let value = bytes
+            .get(pos..pos + size)
+            .ok_or_else(|| self.format_error("truncated structure"))?;
+        Ok(value
+            .iter()
+            .rev()
+            .fold(0u64, |value, &byte| (value << 8) | byte as u64))
+    }
+
+    /// Address read at a position of the superblock
+    fn offset_at(&self, pos: usize) -> ANNResult<u64> {
+        let bytes = self.bytes_at(0, pos + self.offset_size)?;
+        self.field(bytes, pos, self.offset_size)
+    }
+
+    /// len bytes at address
+    fn bytes_at(&self, address: u64, len: usize) -> ANNResult<&[u8]> {
+        let start = self.position(address)?;
+        self.file
+            .as_bytes()
+            .get(start..start + len)
+            .ok_or_else(|| self.format_error("structure past the end of the file"))
+    }
+
+    /// Position in the file of an address
+    fn position(&self, address: u64) -> ANNResult<usize> {
+        usize::try_from(address)
+            .ok()
+            .and_then(|address| address.checked_add(self.base_address))
+            .filter(|&pos| pos < self.file.as_bytes().len())
+            .ok_or_else(|| {
+                self.format_error(&format!("address {} past the end of the file", address))
+            })
+    }
+
+    /// The all ones address of offset_size bytes
+    fn undefined_address(&self) -> u64 {
+        UNDEFINED_ADDRESS >> (64 - 8 * self.offset_size)
+    }
+
+    fn format_error(&self, msg: &str) -> ANNError {
+        ANNError::log_index_error(format!(
+            "ERROR: Can't read HDF5 file {}: {}.",
+            self.filename, msg
+        ))
+    }
+}
+
+/// An ann-benchmarks dataset
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnnBenchmarksDataset {
+    /// Name of the distance, such as euclidean or angular
+    pub distance: String,
+
+    /// Dimension of the vectors
+    pub dim: usize,
+
+    /// Vectors to index, row after row
+    pub train: Vec<f32>,
+
+    /// Number of train vectors
+    pub num_train: usize,
+
+    /// Query vectors, row after row
+    pub test: Vec<f32>,
+
+    /// Number of queries
+    pub num_test: usize,
+
+    /// Ids of the nearest train vectors of each query, nearest first, and their distances
+    pub ground_truth: GroundTruth,
+}
+
+impl AnnBenchmarksDataset {
+    /// Read the train, test, neighbors and distances datasets and the distance attribute of an
+    /// ann-benchmarks HDF5 file
+    pub fn load(filename: &str) -> ANNResult<Self> {
+        let file = Hdf5File::open(filename)?;
+        let distance = file
+            .string_attribute("distance")?
+            .ok_or_else(|| file.format_error("no distance attribute"))?;
+        let (train, num_train, dim) = file.read_f32("train")?;
+        let (test, num_test, test_dim) = file.read_f32("test")?;
+        let (ids, num_queries, k_value) = file.read_u32("neighbors")?;
+        let (distances, distances_rows, distances_cols) = file.read_f32("distances")?;
+
+        if test_dim != dim {
+            return Err(file.format_error(&format!(
+                "test vectors have dimension {}, train vectors {}",
+                test_dim, dim
+            )));
+        }
+        if num_queries != num_test || (distances_rows, distances_cols) != (num_queries, k_value) {
+            return Err(file.format_error(&format!(
+                "{} queries with {} x {} neighbors and {} x {} distances",
+                num_test, num_queries, k_value, distances_rows, distances_cols
+            )));
+        }
+        if let Some(id) = ids.iter().find(|&&id| id as usize >= num_train) {
+            return Err(
+                file.format_error(&format!("neighbor {} of {} train vectors", id, num_train))
+            );
+        }
+
+        info!(
+            "Loaded ann-benchmarks dataset {}: {} train and {} test vectors of dimension {}, \
+             {} neighbors each, {} distance",
+            filename, num_train, num_test, dim, k_value, distance
+        );
+        Ok(Self {
+            distance,
+            dim,
+            train,
+            num_train,
+            test,
+            num_test,
+            ground_truth: GroundTruth {
+                num_queries,
+                k_value,
+                ids,
+                distances,
+            },
+        })
+    }
+
+    /// Metric of the distance of the dataset
+    pub fn metric(&self) -> ANNResult<Metric> {
+        match self.distance.as_str() {
+            "euclidean" => Ok(Metric::L2),
+            "angular" => Ok(Metric::Cosine),
+            "hamming" => Ok(Metric::Hamming),
+            "jaccard" => Ok(Metric::Tanimoto),
+            _ => Err(ANNError::log_index_config_error(
+                "distance".to_string(),
+                format!("Unsupported ann-benchmarks distance {}", self.distance),
+            )),
+        }
+    }
+
+    /// Train vectors as the rows the in-memory index is built from
+    pub fn train_vectors(&self) -> Vec<Vec<f32>> {
+        self.train
+            .chunks_exact(self.dim.max(1))
+            .map(<[f32]>::to_vec)
+            .collect()
+    }
+
+    /// Test query i
+    pub fn query(&self, i: usize) -> &[f32] {
+        &self.test[i * self.dim..(i + 1) * self.dim]
+    }
+}
+
+/// Array of 8 bytes of a chunk of 8
+fn le_array(bytes: &[u8]) -> [u8; 8] {
+    let mut array = [0u8; 8];
+    array.copy_from_slice(bytes);
+    array
+}
+
+/// Little endian integer of up to 8 bytes, sign extended if signed
+fn integer(bytes: &[u8], signed: bool) -> i64 {
+    let mut array = [0u8; 8];
+    array[..bytes.len()].copy_from_slice(bytes);
+    if signed && bytes.last().is_some_and(|&byte| byte & 0x80 != 0) {
+        array[bytes.len()..].fill(0xff);
+    }
+    i64::from_le_bytes(array)
+}
+
+#[cfg(test)]
+mod hdf5_test {
+    use super::*;
+
+    /// Bytes of an HDF5 file in the layout h5py writes, built from the leaves up
+    struct TestFile {
+        bytes: Vec<u8>,
+    }
+
+    impl TestFile {
+        /// Append a block at the next multiple of 8 and return its address
+        fn append(&mut self, block: &[u8]) -> u64 {
+            self.bytes.resize(self.bytes.len().next_multiple_of(8), 0);
+            let address = self.bytes.len() as u64;
+            self.bytes.extend_from_slice(block);
+            address
+        }
+
+        /// Append a contiguous dataset and return the address of its object header
+        fn dataset(&mut self, shape: &[u64], datatype: &[u8], data: &[u8]) -> u64 {
+            let data_address = self.append(data);
+            let mut dataspace = vec![1, shape.len() as u8, 0, 0, 0, 0, 0, 0];
+            shape
+                .iter()
+                .for_each(|dim| dataspace.extend(dim.to_le_bytes()));
+            let mut layout = vec![3, 1];
+            layout.extend(data_address.to_le_bytes());
+            layout.extend((data.len() as u64).to_le_bytes());
+            let header = object_header(&[
+                message(MSG_DATASPACE, &dataspace),
+                message(MSG_DATATYPE, datatype),
+                message(MSG_LAYOUT, &layout),
+            ]);
+            self.append(&header)
+        }
+
+        /// Append a matrix dataset stored as chunks of the given rows and columns, indexed by a
+        /// B-tree of up to two leaves, and return the address of its object header
+        fn chunked_dataset(
+            &mut self,
+            shape: [usize; 2],
+            chunk_dims: [usize; 2],
+            datatype: &[u8],
+            element_size: usize,
+            data: &[u8],
+        ) -> u64 {
+            let [num_rows, num_cols] = shape;
+            let [chunk_rows, chunk_cols] = chunk_dims;
+            let chunk_size = chunk_rows * chunk_cols * element_size;
+            let key = |size: usize, row: usize, col: usize| {
+                let mut key = (size as u32).to_le_bytes().to_vec();
+                key.extend(0u32.to_le_bytes());
+                [row, col, 0]
+                    .iter()
+                    .for_each(|&offset| key.extend((offset as u64).to_le_bytes()));
+                key
+            };
+
+            // Chunks at the edges are padded with values that aren't read
+            let mut chunks = Vec::new();
+            for row in (0..num_rows).step_by(chunk_rows) {
+                for col in (0..num_cols).step_by(chunk_cols) {
+                    let mut chunk = vec![0xee; chunk_size];
+                    for chunk_row in 0..chunk_rows.min(num_rows - row) {
+                        let copy_size = chunk_cols.min(num_cols - col) * element_size;
+                        let start = ((row + chunk_row) * num_cols + col) * element_size;
+                        let dest = chunk_row * chunk_cols * element_size;
+                        chunk[dest..dest + copy_size]
+                            .copy_from_slice(&data[start..start + copy_size]);
+                    }
+                    chunks.push((row, col, self.append(&chunk)));
+                }
+            }
+
+            let node = |level: u8, entries: &[(usize, usize, u64)], size: usize| {
+                let mut node = b"TREE\x01".to_vec();
+                node.push(level);
+                node.extend((entries.len() as u16).to_le_bytes());
+                node.extend([0xff; 16]);
+                for &(row, col, child) in entries {
+                    node.extend(key(size, row, col));
+                    node.extend(child.to_le_bytes());
+                }
+                node.extend(key(0, num_rows, 0));
+                node
+            };
+            let leaves: Vec<_> = chunks
+                .chunks(chunks.len().div_ceil(2))
+                .map(|leaf| {
+                    (
+                        leaf[0].0,
+                        leaf[0].1,
+                        self.append(&node(0, leaf, chunk_size)),
+                    )
+                })
+                .collect();
+            let btree_address = self.append(&node(1, &leaves, chunk_size));
+
+            let mut dataspace = vec![1, 2, 0, 0, 0, 0, 0, 0];
+            [num_rows, num_cols]
+                .iter()
+                .for_each(|&dim| dataspace.extend((dim as u64).to_le_bytes()));
+            let mut layout = vec![3, 2, 3];
+            layout.extend(btree_address.to_le_bytes());
+            [chunk_rows, chunk_cols, element_size]
+                .iter()
+                .for_each(|&dim| layout.extend((dim as u32).to_le_bytes()));
+            let header = object_header(&[
+                message(MSG_DATASPACE, &dataspace),
+                message(MSG_DATATYPE, datatype),
+                message(MSG_LAYOUT, &layout),
+            ]);
+            self.append(&header)
+        }
+
+        /// Append a dataset, chunked if chunk_dims is given
+        fn matrix(
+            &mut self,
+            shape: [usize; 2],
+            chunk_dims: Option<[usize; 2]>,
+            datatype: &[u8],
+            element_size: usize,
+            data: &[u8],
+        ) -> u64 {
+            match chunk_dims {
+                Some(chunk_dims) => {
+                    self.chunked_dataset(shape, chunk_dims, datatype, element_size, data)
+                }
+                None => self.dataset(&shape.map(|dim| dim as u64), datatype, data),
+            }
+        }
+    }
+
+    fn message(msg_type: u16, data: &[u8]) -> Vec<u8> {
+        let size = data.len().next_multiple_of(8);
+        let mut msg = msg_type.to_le_bytes().to_vec();
+        msg.extend((size as u16).to_le_bytes());
+        msg.extend([0; 4]);
+        msg.extend(data);
+        msg.resize(8 + size, 0);
+        msg
+    }
+
+    fn object_header(messages: &[Vec<u8>]) -> Vec<u8> {
+        let size: usize = messages.iter().map(Vec::len).sum();
+        let mut header = vec![1, 0];
+        header.extend((messages.len() as u16).to_le_bytes());
+        header.extend(1u32.to_le_bytes());
+        header.extend((size as u32).to_le_bytes());
+        header.extend([0; 4]);
+        messages.iter().for_each(|msg| header.extend(msg));
+        header
+    }
+
+    fn datatype(class: u8, flags: [u8; 3], size: u32, properties: &[u8]) -> Vec<u8> {
+        let mut datatype = vec![0x10 | class];
+        datatype.extend(flags);
+        datatype.extend(size.to_le_bytes());
+        datatype.extend(properties);
+        datatype
+    }
+
+    fn f32_datatype() -> Vec<u8> {
+        let properties = [0, 0, 32, 0, 23, 8, 0, 23, 127, 0, 0, 0];
+        datatype(CLASS_FLOATING_POINT, [0x20, 31, 0], 4, &properties)
+    }
+
+    fn f32_bytes(values: &[f32]) -> Vec<u8> {
+        values
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect()
+    }
+
+    /// Write an ann-benchmarks file with the given datasets and angular distance, the datasets
+    /// stored as chunks of chunk_dims if given
+    fn write_ann_benchmarks(
+        filename: &str,
+        dim: usize,
+        train: &[f32],
+        test: &[f32],
+        neighbors: &[i64],
+        distances: &[f64],
+        chunk_dims: Option<[usize; 2]>,
+    ) {
+        let mut file = TestFile { bytes: vec![0; 96] };
+        let k_value = neighbors.len() / (test.len() / dim);
+        let rows = |values: usize, cols: usize| [values / cols, cols];
+        let train_address = file.matrix(
+            rows(train.len(), dim),
+            chunk_dims,
+            &f32_datatype(),
+            4,
+            &f32_bytes(train),
+        );
+        let test_address = file.matrix(
+            rows(test.len(), dim),
+            chunk_dims,
+            &f32_datatype(),
+            4,
+            &f32_bytes(test),
+        );
+        let i64_datatype = datatype(CLASS_FIXED_POINT, [0x08, 0, 0], 8, &[0, 0, 64, 0]);
+        let neighbor_bytes: Vec<u8> = neighbors.iter().flat_map(|id| id.to_le_bytes()).collect();
+        let neighbors_address = file.matrix(
+            rows(neighbors.len(), k_value),
+            chunk_dims,
+            &i64_datatype,
+            8,
+            &neighbor_bytes,
+        );
+        let f64_properties = [0, 0, 64, 0, 52, 11, 0, 52, 255, 3, 0, 0];
+        let f64_datatype = datatype(CLASS_FLOATING_POINT, [0x20, 63, 0], 8, &f64_properties);
+        let distance_bytes: Vec<u8> = distances.iter().flat_map(|d| d.to_le_bytes()).collect();
+        let distances_address = file.matrix(
+            rows(distances.len(), k_value),
+            chunk_dims,
+            &f64_datatype,
+            8,
+            &distance_bytes,
+        );
+
+        // Names in the local heap, which starts with the empty name
+        let mut heap_data = vec![0u8; 8];
+        let mut symbols = Vec::new();
+        for (name, address) in [
+            ("distances", distances_address),
+            ("neighbors", neighbors_address),
+            ("test", test_address),
+            ("train", train_address),
+        ] {
+            symbols.extend((heap_data.len() as u64).to_le_bytes());
+            symbols.extend(address.to_le_bytes());
+            symbols.extend([0; 24]);
+            heap_data.extend(name.as_bytes());
+            heap_data.resize((heap_data.len() + 1).next_multiple_of(8), 0);
+        }
+        let heap_data_address = file.append(&heap_data);
+        let mut snod = b"SNOD\x01\x00".to_vec();
+        snod.extend(4u16.to_le_bytes());
+        snod.extend(symbols);
+        let snod_address = file.append(&snod);
+
+        let mut tree = b"TREE\x00\x00".to_vec();
+        tree.extend(1u16.to_le_bytes());
+        tree.extend([0xff; 16]);
+        tree.extend(0u64.to_le_bytes());
+        tree.extend(snod_address.to_le_bytes());
+        tree.extend(40u64.to_le_bytes());
+        let tree_address = file.append(&tree);
+        let mut heap = b"HEAP\x00\x00\x00\x00".to_vec();
+        heap.extend((heap_data.len() as u64).to_le_bytes());
+        heap.extend([0xff; 8]);
+        heap.extend(heap_data_address.to_le_bytes());
+        let heap_address = file.append(&heap);
+
+        // The distance, a variable length string in the global heap
+        let mut collection = b"GCOL\x01\x00\x00\x00".to_vec();
+        collection.extend(48u64.to_le_bytes());
+        collection.extend([1, 0, 0, 0, 0, 0, 0, 0]);
+        collection.extend(7u64.to_le_bytes());
+        collection.extend(b"angular\0");
+        let collection_address = file.append(&collection);
+        let mut attribute = vec![1, 0];
+        attribute.extend(9u16.to_le_bytes());
+        attribute.extend(16u16.to_le_bytes());
+        attribute.extend(8u16.to_le_bytes());
+        attribute.extend(b"distance\0\0\0\0\0\0\0\0");
+        attribute.extend(datatype(CLASS_VARIABLE_LENGTH, [0x01, 0, 0], 16, &[]));
+        attribute.extend(datatype(CLASS_STRING, [0, 0, 0], 1, &[]));
+        attribute.extend([1, 0, 0, 0, 0, 0, 0, 0]);
+        attribute.extend(7u32.to_le_bytes());
+        attribute.extend(collection_address.to_le_bytes());
+        attribute.extend(1u32.to_le_bytes());
+
+        // The attribute is in a continuation block of the root group header
+        let continuation = message(MSG_ATTRIBUTE, &attribute);
+        let continuation_address = file.append(&continuation);
+        let mut symbol_table = tree_address.to_le_bytes().to_vec();
+        symbol_table.extend(heap_address.to_le_bytes());
+        let mut continuation_message = continuation_address.to_le_bytes().to_vec();
+        continuation_message.extend((continuation.len() as u64).to_le_bytes());
+        let root_address = file.append(&object_header(&[
+            message(MSG_SYMBOL_TABLE, &symbol_table),
+            message(MSG_CONTINUATION, &continuation_message),
+        ]));
+
+        let end_address = file.bytes.len() as u64;
+        file.bytes[..8].copy_from_slice(HDF5_SIGNATURE);
+        file.bytes[13..15].copy_from_slice(&[8, 8]);
+        file.bytes[16..20].copy_from_slice(&[4, 0, 16, 0]);
+        file.bytes[32..40].fill(0xff);
+        file.bytes[40..48].copy_from_slice(&end_address.to_le_bytes());
+        file.bytes[48..56].fill(0xff);
+        file.bytes[64..72].copy_from_slice(&root_address.to_le_bytes());
+        std::fs::write(filename, &file.bytes).unwrap();
+    }
+
+    #[test]
+    fn ann_benchmarks_dataset_round_trip() {
+        let file = "ann_benchmarks_dataset_round_trip.hdf5";
+        let (dim, num_train, num_test, k_value) = (3, 10, 2, 4);
+        let train: Vec<f32> = (0..num_train * dim).map(|i| i as f32 * 0.25).collect();
+        let test: Vec<f32> = (0..num_test * dim).map(|i| -(i as f32)).collect();
+        let neighbors: Vec<i64> = vec![0, 1, 2, 3, 9, 8, 7, 6];
+        let distances: Vec<f64> = (0..num_test * k_value).map(|i| i as f64 / 8.0).collect();
+        write_ann_benchmarks(file, dim, &train, &test, &neighbors, &distances, None);
+
+        let dataset = AnnBenchmarksDataset::load(file).unwrap();
+        assert_eq!(dataset.distance, "angular");
+        assert_eq!(dataset.metric().unwrap(), Metric::Cosine);
+        assert_eq!(
+            (dataset.dim, dataset.num_train, dataset.num_test),
+            (dim, num_train, num_test)
+        );
+        assert_eq!(dataset.train, train);
+        assert_eq!(dataset.query(1), &test[dim..]);
+        assert_eq!(dataset.train_vectors()[2], train[2 * dim..3 * dim].to_vec());
+        assert_eq!(dataset.ground_truth.k_value, k_value);
+        assert_eq!(dataset.ground_truth.ids, vec![0, 1, 2, 3, 9, 8, 7, 6]);
+        assert_eq!(dataset.ground_truth.distances[5], 0.625);
+
+        let hdf5 = Hdf5File::open(file).unwrap();
+        assert!(hdf5.read_u32("train").is_err());
+        assert!(hdf5.read_f32("missing").is_err());
+        assert_eq!(hdf5.string_attribute("dimension").unwrap(), None);
+        drop(hdf5);
+
+        // Neighbors past the train vectors and files that aren't HDF5 are rejected
+        let neighbors: Vec<i64> = vec![0, 1, 2, 3, 10, 8, 7, 6];
+        write_ann_benchmarks(file, dim, &train, &test, &neighbors, &distances, None);
+        assert!(AnnBenchmarksDataset::load(file).is_err());
+        std::fs::write(file, b"not an HDF5 file").unwrap();
+        assert!(Hdf5File::open(file).is_err());
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn chunked_datasets_read_like_contiguous_ones() {
+        let (contiguous, chunked) = (
+            "chunked_datasets_read_like_contiguous_ones_contiguous.hdf5",
+            "chunked_datasets_read_like_contiguous_ones_chunked.hdf5",
+        );
+        let (dim, num_train, num_test, k_value) = (5, 11, 3, 4);
+        let train: Vec<f32> = (0..num_train * dim).map(|i| i as f32 * 0.5).collect();
+        let test: Vec<f32> = (0..num_test * dim).map(|i| -(i as f32)).collect();
+        let neighbors: Vec<i64> = (0..(num_test * k_value) as i64).map(|i| i % 11).collect();
+        let distances: Vec<f64> = (0..num_test * k_value).map(|i| i as f64 / 4.0).collect();
+        write_ann_benchmarks(contiguous, dim, &train, &test, &neighbors, &distances, None);
+
+        // Chunks of 2 x 3 leave partial chunks at the last rows and columns
+        write_ann_benchmarks(
+            chunked,
+            dim,
+            &train,
+            &test,
+            &neighbors,
+            &distances,
+            Some([2, 3]),
+        );
+        let dataset = AnnBenchmarksDataset::load(chunked).unwrap();
+        assert_eq!(dataset, AnnBenchmarksDataset::load(contiguous).unwrap());
+        assert_eq!(dataset.train, train);
+        assert_eq!(dataset.ground_truth.ids[5], 5);
+
+        // Chunks as large as the datasets are a single chunk per dataset
+        write_ann_benchmarks(
+            chunked,
+            dim,
+            &train,
+            &test,
+            &neighbors,
+            &distances,
+            Some([16, 8]),
+        );
+        assert_eq!(AnnBenchmarksDataset::load(chunked).unwrap(), dataset);
+        std::fs::remove_file(contiguous).unwrap();
+        std::fs::remove_file(chunked).unwrap();
+    }
+
+    #[test]
+    fn h5py_fixtures_are_read() {
+        let (dim, num_train, num_test, k_value) = (5, 11, 3, 4);
+        let train: Vec<f32> = (0..num_train * dim).map(|i| i as f32 * 0.5).collect();
+        let test: Vec<f32> = (0..num_test * dim).map(|i| -(i as f32)).collect();
+        let ids: Vec<u32> = (0..(num_test * k_value) as u32).map(|i| i % 11).collect();
+        let distances: Vec<f32> = (0..num_test * k_value).map(|i| i as f32 / 4.0).collect();
+
+        for file in [
+            "tests/data/ann_benchmarks_contiguous.hdf5",
+            "tests/data/ann_benchmarks_chunked.hdf5",
+        ] {
+            let dataset = AnnBenchmarksDataset::load(file).unwrap();
+            assert_eq!(dataset.distance, "angular");
+            assert_eq!(
+                (dataset.dim, dataset.num_train, dataset.num_test),
+                (dim, num_train, num_test)
+            );
+            assert_eq!(dataset.train, train);
+            assert_eq!(dataset.test, test);
+            assert_eq!(dataset.ground_truth.ids, ids);
+            assert_eq!(dataset.ground_truth.distances, distances);
+        }
+    }
+}
@@ -0,0 +1,279 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Scale/zero-point int8 quantization for dataset ingestion.
+//! A value x is stored as q = clamp(round(x / scale) + zero_point, -128, 127).
+//! L2, L1 and Chebyshev rankings don't depend on the zero point, but the dot products behind
+//! cosine do, so cosine data is always quantized symmetrically (zero_point = 0).
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+
+use vector::Metric;
+
+use crate::common::{ANNError, ANNResult};
+
+/// Values read or written per batch when converting files
+const CONVERSION_BATCH: usize = 1 << 16;
+
+/// Scale and zero point mapping real values to int8
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Int8Quantizer {
+    /// Real value of one quantization step
+    pub scale: f32,
+
+    /// Quantized value of 0.0
+    pub zero_point: i8,
+}
+
+impl Int8Quantizer {
+    /// Create a quantizer from known parameters, e.g. the ones exported along with a model
+    pub fn new(scale: f32, zero_point: i8) -> ANNResult<Self> {
+        if !(scale.is_finite() && scale > 0.0) {
+            return Err(ANNError::log_index_config_error(
+                "scale".to_string(),
+                format!("Quantization scale must be finite and positive, got {}", scale),
+            ));
+        }
+
+        Ok(Self { scale, zero_point })
+    }
+
+    /// Quantizer with zero_point = 0 covering [-max_abs, max_abs]
+    pub fn fit_symmetric(min: f32, max: f32) -> ANNResult<Self> {
+        let max_abs = min.abs().max(max.abs());
+        Self::new(if max_abs > 0.0 { max_abs / 127.0 } else { 1.0 }, 0)
+    }
+
+    /// Quantizer using the whole int8 range for [min, max]
+    pub fn fit_affine(min: f32, max: f32) -> ANNResult<Self> {
+        // 0.0 is kept in range so it stays exactly representable
+        let (min, max) = (min.min(0.0), max.max(0.0));
+        if max <= min {
+            return Self::new(1.0, 0);
+        }
+
+        let scale = (max - min) / 255.0;
+        let zero_point = (-128.0 - min / scale).round().clamp(-128.0, 127.0) as i8;
+        Self::new(scale, zero_point)
+    }
+
+    /// Pick the quantizer suited to the metric for values in [min, max]
+    pub fn fit(min: f32, max: f32, metric: Metric) -> ANNResult<Self> {
+        match metric {
+            Metric::L2 | Metric::L1 | Metric::Chebyshev => Self::fit_affine(min, max),
+            Metric::Cosine => Self::fit_symmetric(min, max),
+            Metric::Hamming | Metric::Tanimoto => Err(ANNError::log_index_config_error(
+                "metric".to_string(),
+                format!(
+                    "{:?} data is already packed binary, it can't be quantized to int8",
+                    metric
+                ),
+            )),
+        }
+    }
+
+    /// Quantize one value, saturating at the int8 range
+    #[inline]
+    pub fn quantize(&self, value: f32) -> i8 {
+        (value / self.scale + self.zero_point as f32)
+            .round()
+            .clamp(i8::MIN as f32, i8::MAX as f32) as i8
+    }
+
+    /// Real value of a quantized value
+    #[inline]
+    pub fn dequantize(&self, value: i8) -> f32 {
+        (value as i32 - self.zero_point as i32) as f32 * self.scale
+    }
+
+    /// Save the parameters as scale (f32) followed by zero point (i8)
+    pub fn save(&self, filename: &str) -> ANNResult<()> {
+        let mut writer = File::create(filename)?;
+        writer.write_f32::<LittleEndian>(self.scale)?;
+        writer.write_i8(self.zero_point)?;
+        Ok(())
+    }
+
+    /// Load parameters written by save
+    pub fn load(filename: &str) -> ANNResult<Self> {
+        let mut reader = File::open(filename)?;
+        let scale = reader.read_f32::<LittleEndian>()?;
+        let zero_point = reader.read_i8()?;
+        Self::new(scale, zero_point)
+    }
+}
+
+/// Quantize an f32 bin file to an int8 bin file with parameters fitted to the data range.
+/// Returns the quantizer that was used.
+pub fn quantize_f32_bin_to_i8(input: &str, output: &str, metric: Metric) -> ANNResult<Int8Quantizer> {
+    let (mut min, mut max) = (f32::MAX, f32::MIN);
+    for_each_batch::<f32>(input, |values| {
+        for value in values {
+            min = min.min(*value);
+            max = max.max(*value);
+        }
+        Ok(())
+    })?;
+
+    let quantizer = Int8Quantizer::fit(min, max, metric)?;
+    convert_bin(input, output, |value: f32| quantizer.quantize(value))?;
+    Ok(quantizer)
+}
+
+/// Re-quantize an int8 bin file produced with a non-zero zero point to a symmetric one,
+/// so dot products and cosine distances are computed on the centered values.
+/// Returns the quantizer of the output file.
+pub fn requantize_i8_bin_symmetric(
+    input: &str,
+    output: &str,
+    input_quantizer: &Int8Quantizer,
+) -> ANNResult<Int8Quantizer> {
+    let (mut min, mut max) = (f32::MAX, f32::MIN);
+    for_each_batch::<i8>(input, |values| {
+        for value in values {
+            let real = input_quantizer.dequantize(*value);
+            min = min.min(real);
+            max = max.max(real);
+        }
+        Ok(())
+    })?;
+
+    let quantizer = Int8Quantizer::fit_symmetric(min, max)?;
+    convert_bin(input, output, |value: i8| {
+        quantizer.quantize(input_quantizer.dequantize(value))
+    })?;
+    Ok(quantizer)
+}
+
+/// Element types of the bin files handled here
+trait BinElement: Copy + Default {
+    fn read_from(reader: &mut impl Read) -> std::io::Result<Self>;
+}
+
+impl BinElement for f32 {
+    fn read_from(reader: &mut impl Read) -> std::io::Result<Self> {
+        reader.read_f32::<LittleEndian>()
+    }
+}
+
+impl BinElement for i8 {
+    fn read_from(reader: &mut impl Read) -> std::io::Result<Self> {
+        reader.read_i8()
+    }
+}
+
+/// Read the (npts, dim) header of a bin file
+fn read_header(reader: &mut impl Read) -> ANNResult<(usize, usize)> {
+    let npts = reader.read_i32::<LittleEndian>()? as usize;
+    let dim = reader.read_i32::<LittleEndian>()? as usize;
+    Ok((npts, dim))
+}
+
+/// Stream the values of a bin file in batches
+fn for_each_batch<T: BinElement>(
+    filename: &str,
+    mut f: impl FnMut(&[T]) -> ANNResult<()>,
+) -> ANNResult<()> {
+    let mut reader = BufReader::new(File::open(filename)?);
+    let (npts, dim) = read_header(&mut reader)?;
+
+    let mut remaining = npts * dim;
+    let mut batch = Vec::with_capacity(CONVERSION_BATCH.min(remaining));
+    while remaining > 0 {
+        batch.clear();
+        for _ in 0..CONVERSION_BATCH.min(remaining) {
+            batch.push(T::read_from(&mut reader)?);
+        }
+        remaining -= batch.len();
+        f(&batch)?;
+    }
+
+    Ok(())
+}
+
+/// Write a copy of a bin file with every value converted to i8
+fn convert_bin<T: BinElement>(input: &str, output: &str, convert: impl Fn(T) -> i8) -> ANNResult<()> {
+    let (npts, dim) = read_header(&mut File::open(input)?)?;
+
+    let mut writer = BufWriter::new(File::create(output)?);
+    writer.write_i32::<LittleEndian>(npts as i32)?;
+    writer.write_i32::<LittleEndian>(dim as i32)?;
+
+    let mut out = Vec::with_capacity(CONVERSION_BATCH);
+    for_each_batch::<T>(input, |values| {
+        out.clear();
+        out.extend(values.iter().map(|value| convert(*value) as u8));
+        writer.write_all(&out)?;
+        Ok(())
+    })?;
+
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod int8_quantizer_test {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::{load_bin, save_bin_f32};
+
+    #[test]
+    fn fit_round_trips_within_half_a_step() {
+        let values = [-3.0f32, -0.7, 0.0, 0.25, 1.5, 6.0];
+
+        let affine = Int8Quantizer::fit(-3.0, 6.0, Metric::L2).unwrap();
+        let symmetric = Int8Quantizer::fit(-3.0, 6.0, Metric::Cosine).unwrap();
+        assert_eq!(symmetric.zero_point, 0);
+        assert!(affine.scale < symmetric.scale);
+
+        for quantizer in [affine, symmetric] {
+            assert_eq!(quantizer.dequantize(quantizer.quantize(0.0)), 0.0);
+            for value in values {
+                let error = (quantizer.dequantize(quantizer.quantize(value)) - value).abs();
+                assert!(error <= quantizer.scale / 2.0 + 1e-6);
+            }
+        }
+
+        assert!(Int8Quantizer::fit(0.0, 1.0, Metric::Hamming).is_err());
+        assert!(Int8Quantizer::new(0.0, 0).is_err());
+    }
+
+    #[test]
+    fn quantize_and_requantize_bin_files() {
+        let f32_file = "int8_quantizer_test_f32.bin";
+        let i8_file = "int8_quantizer_test_i8.bin";
+        let centered_file = "int8_quantizer_test_centered.bin";
+        let params_file = "int8_quantizer_test.params";
+
+        let data: Vec<f32> = (0..24).map(|i| i as f32 * 0.5 - 2.0).collect();
+        save_bin_f32(f32_file, &data, 3, 8, 0).unwrap();
+
+        let quantizer = quantize_f32_bin_to_i8(f32_file, i8_file, Metric::L2).unwrap();
+        assert_ne!(quantizer.zero_point, 0);
+        let (quantized, npts, dim) = load_bin::<i8>(i8_file, 0).unwrap();
+        assert_eq!((npts, dim), (3, 8));
+        for (q, value) in quantized.iter().zip(&data) {
+            assert!((quantizer.dequantize(*q) - value).abs() <= quantizer.scale / 2.0 + 1e-6);
+        }
+
+        quantizer.save(params_file).unwrap();
+        assert_eq!(Int8Quantizer::load(params_file).unwrap(), quantizer);
+
+        let centered = requantize_i8_bin_symmetric(i8_file, centered_file, &quantizer).unwrap();
+        assert_eq!(centered.zero_point, 0);
+        let (requantized, _, _) = load_bin::<i8>(centered_file, 0).unwrap();
+        for (q, value) in requantized.iter().zip(&data) {
+            assert!((centered.dequantize(*q) - value).abs() <= centered.scale + quantizer.scale);
+        }
+
+        for file in [f32_file, i8_file, centered_file, params_file] {
+            fs::remove_file(file).unwrap();
+        }
+    }
+}
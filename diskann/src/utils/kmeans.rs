@@ -6,12 +6,52 @@
 
 //! Aligned allocator
 
-use rand::{distributions::Uniform, prelude::Distribution, thread_rng};
+use rand::{distributions::Uniform, prelude::Distribution, Rng};
 use rayon::prelude::*;
 use std::cmp::min;
 
 use crate::common::ANNResult;
 use crate::utils::math_util::{calc_distance, compute_closest_centers, compute_vecs_l2sq};
+use crate::utils::rng::seeded_rng;
+
+/// Relative drop of the residual under which Lloyd's iterations stop by default
+pub const DEFAULT_KMEANS_TOLERANCE: f32 = 0.00001;
+
+/// When Lloyd's iterations stop
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KMeansParams {
+    /// Largest number of iterations
+    pub max_reps: usize,
+
+    /// Iterations stop once an iteration lowers the residual by less than this fraction of it
+    pub tolerance: f32,
+
+    /// Seed of the k-means++ seeding, which draws from the OS when None
+    pub seed: Option<u64>,
+}
+
+impl KMeansParams {
+    /// Run up to max_reps iterations with the default tolerance
+    pub const fn new(max_reps: usize) -> Self {
+        Self {
+            max_reps,
+            tolerance: DEFAULT_KMEANS_TOLERANCE,
+            seed: None,
+        }
+    }
+
+    /// Stop once an iteration lowers the residual by less than tolerance of it
+    pub const fn with_tolerance(mut self, tolerance: f32) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Seed the k-means++ seeding, so the same data and seed give the same centers
+    pub const fn with_seed(mut self, seed: Option<u64>) -> Self {
+        self.seed = seed;
+        self
+    }
+}
 
 /// Run Lloyds one iteration
 /// Given data in row-major num_points * dim, and centers in row-major
@@ -96,7 +136,7 @@ fn lloyds_iter(
     Ok(residual)
 }
 
-/// Run Lloyds until max_reps or stopping criterion
+/// Run Lloyds until max_reps or the residual drops by less than the tolerance
 /// If you pass NULL for closest_docs and closest_center, it will NOT return
 /// the results, else it will assume appropriate allocation as closest_docs =
 /// new vec<usize> [num_centers], and closest_center = new size_t[num_points]
@@ -107,7 +147,7 @@ fn run_lloyds(
     dim: usize,
     centers: &mut [f32],
     num_centers: usize,
-    max_reps: usize,
+    params: KMeansParams,
 ) -> ANNResult<(Vec<Vec<usize>>, Vec<u32>, f32)> {
     let mut residual = f32::MAX;
 
@@ -119,7 +159,7 @@ fn run_lloyds(
 
     let mut old_residual;
 
-    for i in 0..max_reps {
+    for i in 0..params.max_reps {
         old_residual = residual;
 
         residual = lloyds_iter(
@@ -133,7 +173,9 @@ fn run_lloyds(
             &mut closest_center,
         )?;
 
-        if (i != 0 && (old_residual - residual) / residual < 0.00001) || (residual < f32::EPSILON) {
+        if (i != 0 && (old_residual - residual) / residual < params.tolerance)
+            || (residual < f32::EPSILON)
+        {
             println!(
                 "Residuals unchanged: {} becomes {}. Early termination.",
                 old_residual, residual
@@ -153,15 +195,15 @@ fn selecting_pivots(
     dim: usize,
     pivot_data: &mut [f32],
     num_centers: usize,
+    rng: &mut impl Rng,
 ) {
     let mut picked = Vec::new();
-    let mut rng = thread_rng();
     let distribution = Uniform::from(0..num_points);
 
     for j in 0..num_centers {
-        let mut tmp_pivot = distribution.sample(&mut rng);
+        let mut tmp_pivot = distribution.sample(rng);
         while picked.contains(&tmp_pivot) {
-            tmp_pivot = distribution.sample(&mut rng);
+            tmp_pivot = distribution.sample(rng);
         }
         picked.push(tmp_pivot);
         let data_offset = tmp_pivot * dim;
@@ -182,19 +224,19 @@ fn k_meanspp_selecting_pivots(
     dim: usize,
     pivot_data: &mut [f32],
     num_centers: usize,
+    rng: &mut impl Rng,
 ) {
     if num_points > (1 << 23) {
         println!("ERROR: n_pts {} currently not supported for k-means++, maximum is 8388608. Falling back to random pivot selection.", num_points);
-        selecting_pivots(data, num_points, dim, pivot_data, num_centers);
+        selecting_pivots(data, num_points, dim, pivot_data, num_centers, rng);
         return;
     }
 
     let mut picked: Vec<usize> = Vec::new();
-    let mut rng = thread_rng();
     let real_distribution = Uniform::from(0.0..1.0);
     let int_distribution = Uniform::from(0..num_points);
 
-    let init_id = int_distribution.sample(&mut rng);
+    let init_id = int_distribution.sample(rng);
     let mut num_picked = 1;
 
     picked.push(init_id);
@@ -216,12 +258,9 @@ fn k_meanspp_selecting_pivots(
     let mut sum_flag = false;
 
     while num_picked < num_centers {
-        dart_val = real_distribution.sample(&mut rng);
+        dart_val = real_distribution.sample(rng);
 
-        let mut sum: f64 = 0.0;
-        for item in dist.iter().take(num_points) {
-            sum += *item as f64;
-        }
+        let sum: f64 = dist.par_iter().map(|item| *item as f64).sum();
         if sum == 0.0 {
             sum_flag = true;
         }
@@ -269,9 +308,29 @@ pub fn k_means_clustering(
     num_centers: usize,
     max_reps: usize,
 ) -> ANNResult<(Vec<Vec<usize>>, Vec<u32>, f32)> {
-    k_meanspp_selecting_pivots(data, num_points, dim, centers, num_centers);
+    k_means_clustering_with_params(
+        data,
+        num_points,
+        dim,
+        centers,
+        num_centers,
+        KMeansParams::new(max_reps),
+    )
+}
+
+/// k-means with k-means++ seeding, stopping as params say
+pub fn k_means_clustering_with_params(
+    data: &[f32],
+    num_points: usize,
+    dim: usize,
+    centers: &mut [f32],
+    num_centers: usize,
+    params: KMeansParams,
+) -> ANNResult<(Vec<Vec<usize>>, Vec<u32>, f32)> {
+    let mut rng = seeded_rng(params.seed);
+    k_meanspp_selecting_pivots(data, num_points, dim, centers, num_centers, &mut rng);
     let (closest_docs, closest_center, residual) =
-        run_lloyds(data, num_points, dim, centers, num_centers, max_reps)?;
+        run_lloyds(data, num_points, dim, centers, num_centers, params)?;
     Ok((closest_docs, closest_center, residual))
 }
 
@@ -338,8 +397,15 @@ mod kmeans_test {
         let data: Vec<f32> = (1..=num_points * dim).map(|x| x as f32).collect();
         let mut centers = [1.0, 2.0, 7.0, 8.0, 19.0, 20.0];
 
-        let (mut closest_docs, mut closest_center, residual) =
-            run_lloyds(&data, num_points, dim, &mut centers, num_centers, max_reps).unwrap();
+        let (mut closest_docs, mut closest_center, residual) = run_lloyds(
+            &data,
+            num_points,
+            dim,
+            &mut centers,
+            num_centers,
+            KMeansParams::new(max_reps),
+        )
+        .unwrap();
 
         let expected_centers: [f32; 6] = [3.0, 4.0, 10.0, 11.0, 17.0, 18.0];
         let expected_closest_docs: Vec<Vec<usize>> =
@@ -360,6 +426,32 @@ mod kmeans_test {
         assert_relative_eq!(residual, expected_residual, epsilon = 1.0e-6_f32);
     }
 
+    #[test]
+    fn run_lloyds_stops_within_tolerance() {
+        let dim = 2;
+        let num_points = 10;
+        let num_centers = 3;
+        let data: Vec<f32> = (1..=num_points * dim).map(|x| x as f32).collect();
+
+        let run = |params: KMeansParams| {
+            let mut centers = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+            let (_, _, residual) =
+                run_lloyds(&data, num_points, dim, &mut centers, num_centers, params).unwrap();
+            (centers, residual)
+        };
+
+        // Any second iteration improves the residual by less than this tolerance
+        let (loose_centers, loose_residual) = run(KMeansParams::new(8).with_tolerance(f32::MAX));
+        let (two_rep_centers, two_rep_residual) = run(KMeansParams::new(2));
+        assert_eq!(loose_centers, two_rep_centers);
+        assert_relative_eq!(loose_residual, 156.0, epsilon = 1.0e-3_f32);
+        assert_eq!(loose_residual, two_rep_residual);
+
+        // The default tolerance lets the iterations run until they converge
+        let (_, residual) = run(KMeansParams::new(8));
+        assert_relative_eq!(residual, 72.0, epsilon = 1.0e-3_f32);
+    }
+
     #[test]
     fn selecting_pivots_test() {
         let dim = 2;
@@ -372,7 +464,14 @@ mod kmeans_test {
 
         let mut pivot_data = vec![0.0; num_centers * dim];
 
-        selecting_pivots(&data, num_points, dim, &mut pivot_data, num_centers);
+        selecting_pivots(
+            &data,
+            num_points,
+            dim,
+            &mut pivot_data,
+            num_centers,
+            &mut rng,
+        );
 
         // Verify that each pivot point corresponds to a point in the data
         for i in 0..num_centers {
@@ -406,7 +505,14 @@ mod kmeans_test {
 
         let mut pivot_data = vec![0.0; num_centers * dim];
 
-        k_meanspp_selecting_pivots(&data, num_points, dim, &mut pivot_data, num_centers);
+        k_meanspp_selecting_pivots(
+            &data,
+            num_points,
+            dim,
+            &mut pivot_data,
+            num_centers,
+            &mut rng,
+        );
 
         // Verify that each pivot point corresponds to a point in the data
         for i in 0..num_centers {
@@ -427,4 +533,30 @@ mod kmeans_test {
             assert!(found, "Pivot not found in data");
         }
     }
+
+    #[test]
+    fn seeded_k_means_repeats() {
+        let dim = 4;
+        let num_points = 200;
+        let num_centers = 8;
+        let mut rng = rand::thread_rng();
+        let data: Vec<f32> = (0..num_points * dim).map(|_| rng.gen()).collect();
+
+        let run = |seed| {
+            let mut centers = vec![0.0; num_centers * dim];
+            let params = KMeansParams::new(4).with_seed(Some(seed));
+            let (_, closest_center, _) = k_means_clustering_with_params(
+                &data,
+                num_points,
+                dim,
+                &mut centers,
+                num_centers,
+                params,
+            )
+            .unwrap();
+            (centers, closest_center)
+        };
+
+        assert_eq!(run(11), run(11));
+    }
 }
@@ -6,10 +6,6 @@
 
 //! Aligned allocator
 
-extern crate cblas;
-extern crate openblas_src;
-
-use cblas::{sgemm, snrm2, Layout, Transpose};
 use rayon::prelude::*;
 use std::{
     cmp::{min, Ordering},
@@ -18,6 +14,7 @@ use std::{
 };
 
 use crate::common::{ANNError, ANNResult};
+use crate::utils::blas::{sgemm, snrm2, Layout, Transpose};
 
 struct PivotContainer {
     piv_id: usize,
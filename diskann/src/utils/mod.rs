@@ -27,8 +27,37 @@ pub use cached_writer::*;
 pub mod partition;
 pub use partition::*;
 
+pub(crate) mod blas;
+
 pub mod math_util;
 pub use math_util::*;
 
 pub mod kmeans;
 pub use kmeans::*;
+
+pub mod output_format;
+pub use output_format::*;
+
+pub mod int8_quantizer;
+pub use int8_quantizer::*;
+
+pub mod preprocessing;
+pub use preprocessing::*;
+
+pub mod ground_truth;
+pub use ground_truth::*;
+
+pub mod npy;
+pub use npy::*;
+
+pub mod hdf5;
+pub use hdf5::*;
+
+pub mod chunked_bin_reader;
+pub use chunked_bin_reader::*;
+
+pub mod text_vectors;
+pub use text_vectors::*;
+
+pub mod rng;
+pub use rng::*;
@@ -0,0 +1,740 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Vectors stored as two dimensional NumPy .npy arrays, alone or as arrays of an .npz archive.
+//!
+//! A .npy file holds the magic bytes, a version, the length of the header and the header, a
+//! Python dict literal giving the element type, the memory order and the shape, then the
+//! elements. The file is mapped and its rows handed to the index as they are, so vectors
+//! dumped by an embedding pipeline need no conversion to the bin format first.
+//!
+//! An .npz file is a zip archive of .npy files, one per array, named after the array. The
+//! reader understands the archives np.savez writes: members stored without compression, with
+//! or without zip64 sizes. The rows of a member are read in place, or copied when the zip
+//! headers before them leave them misaligned. Members of np.savez_compressed are refused.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::mem;
+use std::ops::Range;
+
+use vector::Half;
+
+use crate::common::{ANNError, ANNResult};
+use crate::utils::MmapFile;
+
+/// First bytes of a .npy file
+const NPY_MAGIC: &[u8; 6] = b"\x93NUMPY";
+
+/// The header and the magic bytes before it are padded to a multiple of this size
+const NPY_HEADER_ALIGNMENT: usize = 64;
+
+/// Signatures of the zip records an .npz file is made of
+const ZIP_LOCAL_HEADER: u32 = 0x0403_4b50;
+const ZIP_CENTRAL_HEADER: u32 = 0x0201_4b50;
+const ZIP_END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY: u32 = 0x0606_4b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY_LOCATOR: u32 = 0x0706_4b50;
+
+/// Id of the extra field holding the 64 bit sizes and offset of a zip64 member
+const ZIP64_EXTRA_FIELD: u16 = 0x0001;
+
+/// Compression method of a member stored as it is
+const ZIP_STORED: u16 = 0;
+
+/// Size of the end of central directory record without its trailing comment
+const ZIP_END_OF_CENTRAL_DIRECTORY_LEN: usize = 22;
+
+/// Element types of .npy arrays, by their NumPy type string
+pub trait NpyElement: Copy {
+    /// Type string of the header, byte order, kind and size of an element
+    const DESCR: &'static str;
+}
+
+impl NpyElement for f32 {
+    const DESCR: &'static str = "<f4";
+}
+
+impl NpyElement for Half {
+    const DESCR: &'static str = "<f2";
+}
+
+impl NpyElement for i8 {
+    const DESCR: &'static str = "|i1";
+}
+
+impl NpyElement for u8 {
+    const DESCR: &'static str = "|u1";
+}
+
+/// A mapped .npy file, or array of an .npz file, of num_points rows of dim elements
+#[derive(Debug)]
+pub struct NpyFile {
+    file: MmapFile,
+
+    /// The elements copied to aligned memory, when they aren't aligned in the file
+    copied: Option<Vec<u64>>,
+
+    /// Type string of the elements
+    descr: String,
+
+    /// Offset of the first element in the file
+    data_offset: usize,
+
+    /// Number of bytes of the elements
+    data_len: usize,
+
+    /// Number of rows
+    num_points: usize,
+
+    /// Number of elements of a row
+    dim: usize,
+}
+
+impl NpyFile {
+    /// Map the .npy file at filename and check that it holds a two dimensional array in row
+    /// major order that fills the file. An .npz filename opens the only array of the archive.
+    pub fn open(filename: &str) -> ANNResult<Self> {
+        if filename.ends_with(".npz") {
+            return Self::open_npz(filename, None);
+        }
+
+        let file = MmapFile::open(filename)?;
+        let len = file.as_bytes().len();
+        Self::from_member(file, 0..len, filename)
+    }
+
+    /// Map the .npz file at filename and open its array of the given name, which np.savez
+    /// takes from the keyword the array was passed as, or its only array when there is no
+    /// name. The member name of an array may be given with or without its .npy extension.
+    pub fn open_npz(filename: &str, array: Option<&str>) -> ANNResult<Self> {
+        let file = MmapFile::open(filename)?;
+        let members = zip_members(file.as_bytes()).map_err(|msg| {
+            ANNError::log_index_error(format!(
+                "ERROR: {} isn't a valid .npz file: {}.",
+                filename, msg
+            ))
+        })?;
+        let names: Vec<&str> = members.iter().map(|member| member.array_name()).collect();
+
+        let member = match array {
+            Some(array) => {
+                let array = array.strip_suffix(".npy").unwrap_or(array);
+                members.iter().find(|member| member.array_name() == array).ok_or_else(|| {
+                    ANNError::log_index_error(format!(
+                        "ERROR: {} has no array {}, its arrays are {:?}.",
+                        filename, array, names
+                    ))
+                })?
+            }
+            None => match &members[..] {
+                [member] => member,
+                _ => {
+                    return Err(ANNError::log_index_error(format!(
+                        "ERROR: {} holds the arrays {:?}, name the one to read.",
+                        filename, names
+                    )))
+                }
+            },
+        };
+
+        if member.method != ZIP_STORED {
+            return Err(ANNError::log_index_error(format!(
+                "ERROR: array {} of {} is compressed with zip method {}, save it with np.savez \
+                 instead of np.savez_compressed.",
+                member.array_name(),
+                filename,
+                member.method
+            )));
+        }
+        let data = member.data.clone();
+        Self::from_member(file, data, &format!("{}[{}]", filename, member.array_name()))
+    }
+
+    /// Check that the bytes of a mapped file in range are a .npy file of a two dimensional
+    /// array in row major order that fills them
+    fn from_member(file: MmapFile, range: Range<usize>, filename: &str) -> ANNResult<Self> {
+        let bytes = &file.as_bytes()[range.clone()];
+        let invalid = |msg: &str| {
+            ANNError::log_index_error(format!(
+                "ERROR: {} isn't a valid .npy file: {}.",
+                filename, msg
+            ))
+        };
+
+        if bytes.len() < NPY_MAGIC.len() + 4 || &bytes[..NPY_MAGIC.len()] != NPY_MAGIC {
+            return Err(invalid("missing magic bytes"));
+        }
+        let (header_start, header_len) = match bytes[NPY_MAGIC.len()] {
+            1 => (10, u16::from_le_bytes([bytes[8], bytes[9]]) as usize),
+            2 | 3 if bytes.len() >= 12 => (
+                12,
+                u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]) as usize,
+            ),
+            version => return Err(invalid(&format!("unknown version {}", version))),
+        };
+        let data_offset = header_start + header_len;
+        let header = bytes
+            .get(header_start..data_offset)
+            .and_then(|header| std::str::from_utf8(header).ok())
+            .ok_or_else(|| invalid("truncated header"))?;
+
+        let descr = header_value(header, "descr")
+            .and_then(|value| value.strip_prefix('\'')?.split('\'').next())
+            .ok_or_else(|| invalid("no element type"))?
+            .to_string();
+        if header_value(header, "fortran_order").map(|value| value.starts_with("False"))
+            != Some(true)
+        {
+            return Err(invalid("only row major arrays are supported"));
+        }
+        let shape: Vec<usize> = header_value(header, "shape")
+            .and_then(|value| value.strip_prefix('('))
+            .and_then(|value| value.split(')').next())
+            .ok_or_else(|| invalid("no shape"))?
+            .split(',')
+            .map(str::trim)
+            .filter(|dim| !dim.is_empty())
+            .map(|dim| {
+                dim.parse::<usize>()
+                    .map_err(|_| invalid("shape isn't a tuple of sizes"))
+            })
+            .collect::<ANNResult<_>>()?;
+        let (num_points, dim) = match shape[..] {
+            [num_points, dim] => (num_points, dim),
+            _ => {
+                return Err(invalid(&format!(
+                    "shape {:?} isn't the rows and columns of a matrix",
+                    shape
+                )))
+            }
+        };
+
+        let element_size = descr
+            .get(2..)
+            .and_then(|size| size.parse::<usize>().ok())
+            .ok_or_else(|| invalid(&format!("unknown element type {}", descr)))?;
+        let data_len = num_points
+            .checked_mul(dim)
+            .and_then(|len| len.checked_mul(element_size))
+            .ok_or_else(|| {
+                invalid(&format!(
+                    "a {} x {} array of {} doesn't fit in memory",
+                    num_points, dim, descr
+                ))
+            })?;
+        if bytes.len().checked_sub(data_offset) != Some(data_len) {
+            return Err(invalid(&format!(
+                "{} bytes of data for a {} x {} array of {}",
+                bytes.len().saturating_sub(data_offset),
+                num_points,
+                dim,
+                descr
+            )));
+        }
+
+        // Rows the zip headers before an .npz member leave misaligned are copied
+        let data_offset = range.start + data_offset;
+        let data = &file.as_bytes()[data_offset..data_offset + data_len];
+        let copied = (!(data.as_ptr() as usize).is_multiple_of(element_size.max(1))).then(|| {
+            let mut words = vec![0u64; data_len.div_ceil(mem::size_of::<u64>())];
+            // SAFETY: the words hold at least data_len bytes
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    data.as_ptr(),
+                    words.as_mut_ptr() as *mut u8,
+                    data_len,
+                )
+            };
+            words
+        });
+
+        Ok(Self {
+            file,
+            copied,
+            descr,
+            data_offset,
+            data_len,
+            num_points,
+            dim,
+        })
+    }
+
+    /// Number of rows
+    pub fn num_points(&self) -> usize {
+        self.num_points
+    }
+
+    /// Number of elements of a row
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// NumPy type string of the elements, such as <f4 for f32
+    pub fn descr(&self) -> &str {
+        &self.descr
+    }
+
+    /// The elements, row after row, read in place from the mapping. Fails if they aren't of
+    /// type T.
+    pub fn rows<T: NpyElement>(&self) -> ANNResult<&[T]> {
+        if self.descr != T::DESCR {
+            return Err(ANNError::log_index_error(format!(
+                "ERROR: .npy array of {} read as {}.",
+                self.descr,
+                T::DESCR
+            )));
+        }
+
+        let data = match &self.copied {
+            // SAFETY: the words hold at least data_len bytes
+            Some(words) => unsafe {
+                std::slice::from_raw_parts(words.as_ptr() as *const u8, self.data_len)
+            },
+            None => &self.file.as_bytes()[self.data_offset..self.data_offset + self.data_len],
+        };
+        if !data.as_ptr().cast::<T>().is_aligned() {
+            return Err(ANNError::log_index_error(format!(
+                "ERROR: .npy data at offset {} isn't aligned for {}.",
+                self.data_offset,
+                T::DESCR
+            )));
+        }
+        // SAFETY: the data is aligned for T, spans num_points * dim elements as checked on
+        // open, and NpyElement types are valid for any bit pattern
+        Ok(unsafe {
+            std::slice::from_raw_parts(data.as_ptr() as *const T, self.num_points * self.dim)
+        })
+    }
+}
+
+/// Save num_points rows of dim elements to filename as a version 1.0 .npy array
+pub fn save_npy<T: NpyElement>(
+    filename: &str,
+    data: &[T],
+    num_points: usize,
+    dim: usize,
+) -> ANNResult<()> {
+    if data.len() != num_points * dim {
+        return Err(ANNError::log_index_error(format!(
+            "ERROR: {} elements aren't {} rows of {}.",
+            data.len(),
+            num_points,
+            dim
+        )));
+    }
+
+    let mut header = format!(
+        "{{'descr': '{}', 'fortran_order': False, 'shape': ({}, {}), }}",
+        T::DESCR,
+        num_points,
+        dim
+    );
+    let unpadded = NPY_MAGIC.len() + 4 + header.len() + 1;
+    header.push_str(&" ".repeat(unpadded.next_multiple_of(NPY_HEADER_ALIGNMENT) - unpadded));
+    header.push('\n');
+
+    let mut writer = BufWriter::new(File::create(filename)?);
+    writer.write_all(NPY_MAGIC)?;
+    writer.write_all(&[1, 0])?;
+    writer.write_all(&(header.len() as u16).to_le_bytes())?;
+    writer.write_all(header.as_bytes())?;
+    // SAFETY: the elements are plain Copy values, written as the bytes they are made of
+    let bytes =
+        unsafe { std::slice::from_raw_parts(data.as_ptr() as *const u8, mem::size_of_val(data)) };
+    writer.write_all(bytes)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// A member of a zip archive
+#[derive(Debug)]
+struct ZipMember {
+    /// Name of the member in the archive
+    name: String,
+
+    /// Compression method, 0 when stored as it is
+    method: u16,
+
+    /// Range of the file holding the data of the member
+    data: Range<usize>,
+}
+
+impl ZipMember {
+    /// Name of the array np.savez stored as the member, its name without the .npy extension
+    fn array_name(&self) -> &str {
+        self.name.strip_suffix(".npy").unwrap_or(&self.name)
+    }
+}
+
+/// The members listed by the central directory of a zip archive
+fn zip_members(bytes: &[u8]) -> Result<Vec<ZipMember>, String> {
+    let u16_at = |offset: usize| -> Result<u16, String> {
+        bytes
+            .get(offset..offset + 2)
+            .map(|le| u16::from_le_bytes([le[0], le[1]]))
+            .ok_or_else(|| format!("record at {} past the end of the file", offset))
+    };
+    let u32_at = |offset: usize| -> Result<u32, String> {
+        bytes
+            .get(offset..offset + 4)
+            .map(|le| u32::from_le_bytes([le[0], le[1], le[2], le[3]]))
+            .ok_or_else(|| format!("record at {} past the end of the file", offset))
+    };
+    let u64_at = |offset: usize| -> Result<u64, String> {
+        Ok(u32_at(offset)? as u64 | (u32_at(offset + 4)? as u64) << 32)
+    };
+
+    // The end of central directory record is last, followed by a comment of up to 64KiB
+    let end = (ZIP_END_OF_CENTRAL_DIRECTORY_LEN..=bytes.len())
+        .rev()
+        .take(u16::MAX as usize + 1)
+        .map(|end| end - ZIP_END_OF_CENTRAL_DIRECTORY_LEN)
+        .find(|&end| u32_at(end) == Ok(ZIP_END_OF_CENTRAL_DIRECTORY))
+        .ok_or("no end of central directory record")?;
+    let mut num_members = u16_at(end + 10)? as u64;
+    let mut directory_offset = u32_at(end + 16)? as u64;
+    if num_members == u16::MAX as u64 || directory_offset == u32::MAX as u64 {
+        let locator = end
+            .checked_sub(20)
+            .filter(|&locator| u32_at(locator) == Ok(ZIP64_END_OF_CENTRAL_DIRECTORY_LOCATOR))
+            .ok_or("no zip64 end of central directory locator")?;
+        let zip64_end = u64_at(locator + 8)? as usize;
+        if u32_at(zip64_end)? != ZIP64_END_OF_CENTRAL_DIRECTORY {
+            return Err("no zip64 end of central directory record".to_string());
+        }
+        num_members = u64_at(zip64_end + 32)?;
+        directory_offset = u64_at(zip64_end + 48)?;
+    }
+
+    let mut members = Vec::new();
+    let mut offset = directory_offset as usize;
+    for _ in 0..num_members {
+        if u32_at(offset)? != ZIP_CENTRAL_HEADER {
+            return Err(format!("no central directory header at {}", offset));
+        }
+        let method = u16_at(offset + 10)?;
+        let mut compressed_size = u32_at(offset + 20)? as u64;
+        let mut size = u32_at(offset + 24)? as u64;
+        let name_len = u16_at(offset + 28)? as usize;
+        let extra_len = u16_at(offset + 30)? as usize;
+        let comment_len = u16_at(offset + 32)? as usize;
+        let mut local_offset = u32_at(offset + 42)? as u64;
+        let name = bytes
+            .get(offset + 46..offset + 46 + name_len)
+            .and_then(|name| std::str::from_utf8(name).ok())
+            .ok_or_else(|| format!("invalid member name at {}", offset + 46))?
+            .to_string();
+
+        // The zip64 extra field holds, in order, the sizes and offset too large for their
+        // 32 bit fields
+        let mut extra = offset + 46 + name_len;
+        let extra_end = extra + extra_len;
+        while extra + 4 <= extra_end {
+            let (id, len) = (u16_at(extra)?, u16_at(extra + 2)? as usize);
+            if id == ZIP64_EXTRA_FIELD {
+                let mut field = extra + 4;
+                for value in [&mut size, &mut compressed_size, &mut local_offset] {
+                    if *value == u32::MAX as u64 {
+                        *value = u64_at(field)?;
+                        field += 8;
+                    }
+                }
+            }
+            extra += 4 + len;
+        }
+
+        let local = local_offset as usize;
+        if u32_at(local)? != ZIP_LOCAL_HEADER {
+            return Err(format!("no local header of {} at {}", name, local));
+        }
+        let data_start = local + 30 + u16_at(local + 26)? as usize + u16_at(local + 28)? as usize;
+        let data_end = data_start
+            .checked_add(compressed_size as usize)
+            .filter(|&data_end| data_end <= bytes.len())
+            .ok_or_else(|| format!("member {} past the end of the file", name))?;
+        if method == ZIP_STORED && size != compressed_size {
+            return Err(format!(
+                "stored member {} of {} bytes takes {}",
+                name, size, compressed_size
+            ));
+        }
+
+        members.push(ZipMember {
+            name,
+            method,
+            data: data_start..data_end,
+        });
+        offset = extra_end + comment_len;
+    }
+
+    Ok(members)
+}
+
+/// Text following 'key': in a header dict
+fn header_value<'a>(header: &'a str, key: &str) -> Option<&'a str> {
+    let quoted = format!("'{}':", key);
+    let start = header.find(&quoted)? + quoted.len();
+    Some(header[start..].trim_start())
+}
+
+#[cfg(test)]
+mod npy_test {
+    use super::*;
+
+    #[test]
+    fn npy_rows_round_trip_and_are_validated() {
+        let file = "npy_rows_round_trip_and_are_validated.npy";
+        let data: Vec<f32> = (0..30).map(|i| i as f32 * 0.5).collect();
+        save_npy(file, &data, 10, 3).unwrap();
+
+        let npy = NpyFile::open(file).unwrap();
+        assert_eq!((npy.num_points(), npy.dim(), npy.descr()), (10, 3, "<f4"));
+        assert_eq!(npy.rows::<f32>().unwrap(), &data[..]);
+        assert!(npy.rows::<u8>().is_err());
+        drop(npy);
+
+        // A file cut short or written in column major order is rejected
+        let mut bytes = std::fs::read(file).unwrap();
+        std::fs::write(file, &bytes[..bytes.len() - 4]).unwrap();
+        assert!(NpyFile::open(file).is_err());
+        let header_end = bytes.iter().position(|&byte| byte == b'\n').unwrap();
+        let header = std::str::from_utf8(&bytes[10..header_end]).unwrap();
+        let fortran = header.replace("False", "True ");
+        bytes[10..header_end].copy_from_slice(fortran.as_bytes());
+        std::fs::write(file, &bytes).unwrap();
+        assert!(NpyFile::open(file).is_err());
+
+        let codes: Vec<u8> = (0..12).collect();
+        save_npy(file, &codes, 4, 3).unwrap();
+        let npy = NpyFile::open(file).unwrap();
+        assert_eq!(npy.rows::<u8>().unwrap(), &codes[..]);
+        assert!(save_npy(file, &codes, 5, 3).is_err());
+        std::fs::remove_file(file).unwrap();
+    }
+
+    /// Write a version 1.0 .npy file of the header and data given as is
+    fn write_raw_npy(filename: &str, header: &str, data: &[u8]) {
+        let mut bytes = NPY_MAGIC.to_vec();
+        bytes.extend_from_slice(&[1, 0]);
+        bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(header.as_bytes());
+        bytes.extend_from_slice(data);
+        std::fs::write(filename, bytes).unwrap();
+    }
+
+    /// Write the members to filename as a zip archive of the layout np.savez writes, with the
+    /// zip64 sizes numpy forces when asked
+    fn write_npz(filename: &str, members: &[(&str, Vec<u8>)], method: u16, zip64: bool) {
+        let mut bytes = Vec::new();
+        let mut directory = Vec::new();
+        for (name, data) in members {
+            let local_offset = bytes.len() as u64;
+            let (size, offset) = if zip64 {
+                (u32::MAX, u32::MAX)
+            } else {
+                (data.len() as u32, local_offset as u32)
+            };
+
+            bytes.extend_from_slice(&ZIP_LOCAL_HEADER.to_le_bytes());
+            bytes.extend_from_slice(&[45, 0, 0, 0]);
+            bytes.extend_from_slice(&method.to_le_bytes());
+            bytes.extend_from_slice(&[0; 8]);
+            bytes.extend_from_slice(&size.to_le_bytes());
+            bytes.extend_from_slice(&size.to_le_bytes());
+            bytes.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            bytes.extend_from_slice(&(if zip64 { 20u16 } else { 0 }).to_le_bytes());
+            bytes.extend_from_slice(name.as_bytes());
+            if zip64 {
+                bytes.extend_from_slice(&ZIP64_EXTRA_FIELD.to_le_bytes());
+                bytes.extend_from_slice(&16u16.to_le_bytes());
+                bytes.extend_from_slice(&(data.len() as u64).to_le_bytes());
+                bytes.extend_from_slice(&(data.len() as u64).to_le_bytes());
+            }
+            bytes.extend_from_slice(data);
+
+            directory.extend_from_slice(&ZIP_CENTRAL_HEADER.to_le_bytes());
+            directory.extend_from_slice(&[45, 0, 45, 0, 0, 0]);
+            directory.extend_from_slice(&method.to_le_bytes());
+            directory.extend_from_slice(&[0; 8]);
+            directory.extend_from_slice(&size.to_le_bytes());
+            directory.extend_from_slice(&size.to_le_bytes());
+            directory.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            directory.extend_from_slice(&(if zip64 { 28u16 } else { 0 }).to_le_bytes());
+            directory.extend_from_slice(&[0; 10]);
+            directory.extend_from_slice(&offset.to_le_bytes());
+            directory.extend_from_slice(name.as_bytes());
+            if zip64 {
+                directory.extend_from_slice(&ZIP64_EXTRA_FIELD.to_le_bytes());
+                directory.extend_from_slice(&24u16.to_le_bytes());
+                directory.extend_from_slice(&(data.len() as u64).to_le_bytes());
+                directory.extend_from_slice(&(data.len() as u64).to_le_bytes());
+                directory.extend_from_slice(&local_offset.to_le_bytes());
+            }
+        }
+
+        let directory_offset = bytes.len() as u64;
+        bytes.extend_from_slice(&directory);
+        let (num_members, offset) = if zip64 {
+            let zip64_end = bytes.len() as u64;
+            bytes.extend_from_slice(&ZIP64_END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+            bytes.extend_from_slice(&44u64.to_le_bytes());
+            bytes.extend_from_slice(&[45, 0, 45, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+            bytes.extend_from_slice(&(members.len() as u64).to_le_bytes());
+            bytes.extend_from_slice(&(members.len() as u64).to_le_bytes());
+            bytes.extend_from_slice(&(directory.len() as u64).to_le_bytes());
+            bytes.extend_from_slice(&directory_offset.to_le_bytes());
+            bytes.extend_from_slice(&ZIP64_END_OF_CENTRAL_DIRECTORY_LOCATOR.to_le_bytes());
+            bytes.extend_from_slice(&0u32.to_le_bytes());
+            bytes.extend_from_slice(&zip64_end.to_le_bytes());
+            bytes.extend_from_slice(&1u32.to_le_bytes());
+            (u16::MAX, u32::MAX)
+        } else {
+            (members.len() as u16, directory_offset as u32)
+        };
+        bytes.extend_from_slice(&ZIP_END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+        bytes.extend_from_slice(&[0; 4]);
+        bytes.extend_from_slice(&num_members.to_le_bytes());
+        bytes.extend_from_slice(&num_members.to_le_bytes());
+        bytes.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&offset.to_le_bytes());
+        bytes.extend_from_slice(&0u16.to_le_bytes());
+        std::fs::write(filename, bytes).unwrap();
+    }
+
+    /// The bytes of a .npy file of the elements
+    fn npy_bytes<T: NpyElement>(data: &[T], num_points: usize, dim: usize) -> Vec<u8> {
+        let file = format!("npy_bytes_{}.npy", std::process::id());
+        save_npy(&file, data, num_points, dim).unwrap();
+        let bytes = std::fs::read(&file).unwrap();
+        std::fs::remove_file(&file).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn npz_arrays_are_read_by_name_or_alone() {
+        let file = "npz_arrays_are_read_by_name_or_alone.npz";
+        let base: Vec<f32> = (0..30).map(|i| i as f32 * 0.5).collect();
+        let codes: Vec<u8> = (0..12).collect();
+        let members = [
+            ("base.npy", npy_bytes(&base, 10, 3)),
+            ("codes.npy", npy_bytes(&codes, 4, 3)),
+        ];
+
+        for zip64 in [false, true] {
+            write_npz(file, &members, ZIP_STORED, zip64);
+            let npy = NpyFile::open_npz(file, Some("base")).unwrap();
+            assert_eq!((npy.num_points(), npy.dim()), (10, 3));
+            // The 38 bytes of the local header leave the rows misaligned for f32
+            assert!(npy.copied.is_some());
+            assert_eq!(npy.rows::<f32>().unwrap(), &base[..]);
+            let npy = NpyFile::open_npz(file, Some("codes.npy")).unwrap();
+            assert_eq!(npy.rows::<u8>().unwrap(), &codes[..]);
+
+            assert!(NpyFile::open_npz(file, Some("ids"))
+                .unwrap_err()
+                .to_string()
+                .contains("has no array ids"));
+            assert!(NpyFile::open(file)
+                .unwrap_err()
+                .to_string()
+                .contains("name the one to read"));
+
+            write_npz(file, &members[..1], ZIP_STORED, zip64);
+            assert_eq!(NpyFile::open(file).unwrap().rows::<f32>().unwrap(), &base[..]);
+        }
+
+        // A member cut short fails the size checks of the .npy file it holds
+        write_npz(file, &[("base.npy", members[0].1[..100].to_vec())], ZIP_STORED, false);
+        assert!(NpyFile::open(file).is_err());
+        std::fs::write(file, b"PK not an archive").unwrap();
+        assert!(NpyFile::open(file)
+            .unwrap_err()
+            .to_string()
+            .contains("no end of central directory"));
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn compressed_npz_arrays_are_rejected() {
+        let file = "compressed_npz_arrays_are_rejected.npz";
+        let data: Vec<f32> = (0..6).map(|i| i as f32).collect();
+        // Deflate, as np.savez_compressed writes
+        write_npz(file, &[("base.npy", npy_bytes(&data, 2, 3))], 8, false);
+
+        let err = NpyFile::open(file).unwrap_err();
+        assert!(err.to_string().contains("np.savez_compressed"));
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn truncated_header_is_rejected() {
+        let file = "truncated_header_is_rejected.npy";
+        let mut bytes = NPY_MAGIC.to_vec();
+        bytes.extend_from_slice(&[1, 0]);
+        bytes.extend_from_slice(&100u16.to_le_bytes());
+        bytes.extend_from_slice(b"{'descr': '<f4', ");
+        std::fs::write(file, bytes).unwrap();
+
+        let err = NpyFile::open(file).unwrap_err();
+        assert!(err.to_string().contains("truncated header"));
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn fortran_order_is_rejected() {
+        let file = "fortran_order_is_rejected.npy";
+        write_raw_npy(
+            file,
+            "{'descr': '<f4', 'fortran_order': True, 'shape': (2, 3), }\n",
+            &[0u8; 24],
+        );
+
+        let err = NpyFile::open(file).unwrap_err();
+        assert!(err.to_string().contains("row major"));
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn wrong_dtype_is_rejected() {
+        let file = "wrong_dtype_is_rejected.npy";
+        write_raw_npy(
+            file,
+            "{'descr': '|O', 'fortran_order': False, 'shape': (2, 3), }\n",
+            &[0u8; 48],
+        );
+        assert!(NpyFile::open(file)
+            .unwrap_err()
+            .to_string()
+            .contains("unknown element type"));
+
+        // Doubles open, but aren't read as f32
+        write_raw_npy(
+            file,
+            "{'descr': '<f8', 'fortran_order': False, 'shape': (2, 3), }\n",
+            &[0u8; 48],
+        );
+        let npy = NpyFile::open(file).unwrap();
+        assert!(npy.rows::<f32>().is_err());
+        drop(npy);
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn overflowing_shape_is_rejected() {
+        let file = "overflowing_shape_is_rejected.npy";
+        // usize::MAX / 2 + 1 rows of 2 elements wrap to 0 bytes of data
+        let header = format!(
+            "{{'descr': '|u1', 'fortran_order': False, 'shape': ({}, 2), }}\n",
+            usize::MAX / 2 + 1
+        );
+        write_raw_npy(file, &header, &[]);
+
+        let err = NpyFile::open(file).unwrap_err();
+        assert!(err.to_string().contains("doesn't fit in memory"));
+        std::fs::remove_file(file).unwrap();
+    }
+}
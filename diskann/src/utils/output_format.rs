@@ -0,0 +1,243 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Machine-readable JSON/CSV reports for the command line drivers
+
+use std::str::FromStr;
+
+use crate::common::{ANNError, ANNResult};
+
+/// Format of the results printed by a command line driver
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Human readable log lines
+    #[default]
+    Text,
+
+    /// JSON array with one object per row
+    Json,
+
+    /// CSV with a header line
+    Csv,
+}
+
+impl FromStr for OutputFormat {
+    type Err = ANNError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            _ => Err(ANNError::log_index_config_error(
+                "format".to_string(),
+                format!("Unknown output format {}, use text, json or csv", s),
+            )),
+        }
+    }
+}
+
+/// One cell of a report
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReportValue {
+    /// Integer value
+    Int(i64),
+
+    /// Floating point value, written as null in JSON if not finite
+    Float(f64),
+
+    /// String value
+    Str(String),
+}
+
+macro_rules! report_value_from {
+    ($variant:ident, $target:ty, $($t:ty),*) => {
+        $(
+            impl From<$t> for ReportValue {
+                fn from(value: $t) -> Self {
+                    ReportValue::$variant(value as $target)
+                }
+            }
+        )*
+    };
+}
+
+report_value_from!(Int, i64, u32, i32, u64, i64, usize);
+report_value_from!(Float, f64, f64);
+
+impl From<f32> for ReportValue {
+    fn from(value: f32) -> Self {
+        // Go through the shortest decimal form so 0.1f32 is reported as 0.1
+        ReportValue::Float(value.to_string().parse().unwrap_or(value as f64))
+    }
+}
+
+impl From<&str> for ReportValue {
+    fn from(value: &str) -> Self {
+        ReportValue::Str(value.to_string())
+    }
+}
+
+impl From<String> for ReportValue {
+    fn from(value: String) -> Self {
+        ReportValue::Str(value)
+    }
+}
+
+/// Table of results with named columns
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    columns: Vec<String>,
+    rows: Vec<Vec<ReportValue>>,
+}
+
+impl Report {
+    /// Create an empty report with the given columns
+    pub fn new(columns: &[&str]) -> Self {
+        Self {
+            columns: columns.iter().map(|c| c.to_string()).collect(),
+            rows: Vec::new(),
+        }
+    }
+
+    /// Append a row, with one value per column
+    pub fn add_row(&mut self, row: Vec<ReportValue>) -> ANNResult<()> {
+        if row.len() != self.columns.len() {
+            return Err(ANNError::log_index_error(format!(
+                "Report row has {} values but there are {} columns",
+                row.len(),
+                self.columns.len()
+            )));
+        }
+
+        self.rows.push(row);
+        Ok(())
+    }
+
+    /// Render the report, None for the text format where the drivers log as usual
+    pub fn render(&self, format: OutputFormat) -> Option<String> {
+        match format {
+            OutputFormat::Text => None,
+            OutputFormat::Json => Some(self.to_json()),
+            OutputFormat::Csv => Some(self.to_csv()),
+        }
+    }
+
+    /// Print the report to stdout if the format is not text
+    pub fn print(&self, format: OutputFormat) {
+        if let Some(output) = self.render(format) {
+            print!("{}", output);
+        }
+    }
+
+    fn to_json(&self) -> String {
+        let mut out = String::from("[");
+        for (i, row) in self.rows.iter().enumerate() {
+            out.push_str(if i == 0 { "\n  {" } else { ",\n  {" });
+            for (j, (column, value)) in self.columns.iter().zip(row).enumerate() {
+                if j > 0 {
+                    out.push_str(", ");
+                }
+                push_json_string(&mut out, column);
+                out.push_str(": ");
+                match value {
+                    ReportValue::Int(v) => out.push_str(&v.to_string()),
+                    ReportValue::Float(v) if v.is_finite() => out.push_str(&v.to_string()),
+                    ReportValue::Float(_) => out.push_str("null"),
+                    ReportValue::Str(v) => push_json_string(&mut out, v),
+                }
+            }
+            out.push('}');
+        }
+        out.push_str(if self.rows.is_empty() { "]\n" } else { "\n]\n" });
+        out
+    }
+
+    fn to_csv(&self) -> String {
+        let mut out = String::new();
+        let header: Vec<String> = self.columns.iter().map(|c| csv_field(c)).collect();
+        out.push_str(&header.join(","));
+        out.push('\n');
+
+        for row in self.rows.iter() {
+            let fields: Vec<String> = row
+                .iter()
+                .map(|value| match value {
+                    ReportValue::Int(v) => v.to_string(),
+                    ReportValue::Float(v) => v.to_string(),
+                    ReportValue::Str(v) => csv_field(v),
+                })
+                .collect();
+            out.push_str(&fields.join(","));
+            out.push('\n');
+        }
+        out
+    }
+}
+
+fn push_json_string(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Quote a CSV field if it contains a separator, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod output_format_test {
+    use super::*;
+
+    fn sample_report() -> Report {
+        let mut report = Report::new(&["L", "recall", "path"]);
+        report
+            .add_row(vec![50u32.into(), 98.5f64.into(), "a,b".into()])
+            .unwrap();
+        report
+            .add_row(vec![100u32.into(), f64::NAN.into(), "say \"hi\"".into()])
+            .unwrap();
+        report
+    }
+
+    #[test]
+    fn report_renders_json_and_csv() {
+        let report = sample_report();
+
+        assert_eq!(report.render(OutputFormat::Text), None);
+        assert_eq!(
+            report.render(OutputFormat::Json).unwrap(),
+            "[\n  {\"L\": 50, \"recall\": 98.5, \"path\": \"a,b\"},\n  {\"L\": 100, \"recall\": null, \"path\": \"say \\\"hi\\\"\"}\n]\n"
+        );
+        assert_eq!(
+            report.render(OutputFormat::Csv).unwrap(),
+            "L,recall,path\n50,98.5,\"a,b\"\n100,NaN,\"say \"\"hi\"\"\"\n"
+        );
+    }
+
+    #[test]
+    fn report_rejects_mismatched_row_and_unknown_format() {
+        let mut report = Report::new(&["a", "b"]);
+        assert!(report.add_row(vec![1u32.into()]).is_err());
+        assert_eq!("JSON".parse::<OutputFormat>().unwrap(), OutputFormat::Json);
+        assert!("xml".parse::<OutputFormat>().is_err());
+    }
+}
@@ -4,12 +4,17 @@
  */
 use std::mem;
 use std::{fs::File, path::Path};
-use std::io::{Write, Seek, SeekFrom};
+use std::io::{BufWriter, Write, Seek, SeekFrom};
 use rand::distributions::{Distribution, Uniform};
+use log::info;
+use rand::Rng;
 
-use crate::common::ANNResult;
+use crate::common::{ANNError, ANNResult};
 
-use super::CachedReader;
+use super::{k_means_clustering_with_params, seeded_rng, step_seed, CachedReader, KMeansParams, RandomStep};
+
+/// Lloyd iterations of the k-means splitting data into shards
+const NUM_SHARD_KMEANS_REPS: usize = 15;
 
 /// streams data from the file, and samples each vector with probability p_val
 /// and returns a matrix of size slice_size* ndims as floating point type.
@@ -20,7 +25,8 @@ use super::CachedReader;
 /// * `sampled_vectors` - sampled vector chose by p_val possibility
 /// * `slice_size` - how many sampled data return
 /// * `dim` - each sample data dimension
-pub fn gen_random_slice<T: Default + Copy + Into<f32>>(data_file: &str, mut p_val: f64) -> ANNResult<(Vec<f32>, usize, usize)> {
+/// * `seed` - seed of the sampling, drawn from the OS when None
+pub fn gen_random_slice<T: Default + Copy + Into<f32>>(data_file: &str, mut p_val: f64, seed: Option<u64>) -> ANNResult<(Vec<f32>, usize, usize)> {
     let read_blk_size = 64 * 1024 * 1024;
     let mut reader = CachedReader::new(data_file, read_blk_size)?;
 
@@ -30,7 +36,7 @@ pub fn gen_random_slice<T: Default + Copy + Into<f32>>(data_file: &str, mut p_va
     let mut slice_size = 0;
     p_val = if p_val < 1f64 { p_val } else { 1f64 };
 
-    let mut generator = rand::thread_rng();
+    let mut generator = seeded_rng(seed);
     let distribution = Uniform::from(0.0..1.0);
 
     for _ in 0..npts {
@@ -48,8 +54,53 @@ pub fn gen_random_slice<T: Default + Copy + Into<f32>>(data_file: &str, mut p_va
     Ok((sampled_vectors, slice_size, dim))
 }
 
-/// Generate random sample data and write into output_file
-pub fn gen_sample_data<T>(data_file: &str, output_file: &str, sampling_rate: f64) -> ANNResult<()> {
+/// Streams data from the file and keeps a uniform random sample of sample_size vectors,
+/// or every vector if the file has fewer, by reservoir sampling. Unlike gen_random_slice
+/// the number of samples is exact, and memory holds only the sample, however large the file.
+/// Returns the sample as floating point rows, the number of sampled vectors and the dimension.
+/// The same seed draws the same sample, None draws it from the OS.
+pub fn reservoir_sample<T: Default + Copy + Into<f32>>(data_file: &str, sample_size: usize, seed: Option<u64>) -> ANNResult<(Vec<f32>, usize, usize)> {
+    if sample_size == 0 {
+        return Err(ANNError::log_index_config_error(
+            "sample_size".to_string(),
+            "Sample size should be positive".to_string(),
+        ));
+    }
+
+    let read_blk_size = 64 * 1024 * 1024;
+    let mut reader = CachedReader::new(data_file, read_blk_size)?;
+
+    let npts = reader.read_u32()? as usize;
+    let dim = reader.read_u32()? as usize;
+    let num_sampled = sample_size.min(npts);
+    let mut sampled_vectors: Vec<f32> = Vec::with_capacity(num_sampled * dim);
+
+    let mut generator = seeded_rng(seed);
+    let mut cur_vector_bytes = vec![0u8; dim * mem::size_of::<T>()];
+    for i in 0..npts {
+        reader.read(&mut cur_vector_bytes)?;
+        let ptr = cur_vector_bytes.as_ptr() as *const T;
+        let cur_vector_t = unsafe { std::slice::from_raw_parts(ptr, dim) };
+        if i < num_sampled {
+            sampled_vectors.extend(cur_vector_t.iter().map(|&t| t.into()));
+            continue;
+        }
+
+        // The i-th vector replaces a random sample with probability sample_size / (i + 1)
+        let slot = generator.gen_range(0..=i);
+        if slot < num_sampled {
+            for (sample, &t) in sampled_vectors[slot * dim..(slot + 1) * dim].iter_mut().zip(cur_vector_t) {
+                *sample = t.into();
+            }
+        }
+    }
+
+    Ok((sampled_vectors, num_sampled, dim))
+}
+
+/// Generate random sample data and write into output_file, sampled with seed or from the OS
+/// when it is None
+pub fn gen_sample_data<T>(data_file: &str, output_file: &str, sampling_rate: f64, seed: Option<u64>) -> ANNResult<()> {
     let read_blk_size = 64 * 1024 * 1024;
     let mut reader = CachedReader::new(data_file, read_blk_size)?;
 
@@ -60,7 +111,7 @@ pub fn gen_sample_data<T>(data_file: &str, output_file: &str, sampling_rate: f64
 
     let mut num_sampled_pts = 0u32;
     let one_const = 1u32;
-    let mut generator = rand::thread_rng();
+    let mut generator = seeded_rng(seed);
     let distribution = Uniform::from(0.0..1.0);
 
     let npts_u32 = reader.read_u32()?;
@@ -86,11 +137,138 @@ pub fn gen_sample_data<T>(data_file: &str, output_file: &str, sampling_rate: f64
     sample_data_writer.write_all(&num_sampled_pts.to_le_bytes())?;
     sample_id_writer.seek(SeekFrom::Start(0))?;
     sample_id_writer.write_all(&num_sampled_pts.to_le_bytes())?;
-    println!("Wrote {} points to sample file: {}", num_sampled_pts, sample_data_path);
+    info!("Wrote {} points to sample file: {}", num_sampled_pts, sample_data_path);
 
     Ok(())
 }
 
+/// Data file of shard shard_index written by partition_into_shards
+pub fn shard_data_file(output_prefix: &str, shard_index: usize) -> String {
+    format!("{}_subshard-{}.bin", output_prefix, shard_index)
+}
+
+/// Ids file of shard shard_index written by partition_into_shards
+pub fn shard_ids_file(output_prefix: &str, shard_index: usize) -> String {
+    format!("{}_subshard-{}_ids_uint32.bin", output_prefix, shard_index)
+}
+
+/// Split the data into num_shards overlapping shards. K-means centers are trained on a sample
+/// of the data taken with probability sampling_rate. Every point goes to the shards of its
+/// num_shards_per_point closest centers among those holding fewer than max_shard_size points,
+/// or to the shard of its closest center if all of them are full. Shards never grow over
+/// max_shard_size when num_shards * max_shard_size covers num_shards_per_point copies of
+/// every point. Each shard is written to shard_data_file with the ids
+/// of its points, in increasing order, in shard_ids_file.
+/// Returns the number of points of each shard.
+/// # Arguments
+/// * `data_file` - filename where the data is
+/// * `output_prefix` - prefix of the shard files
+/// * `sampling_rate` - probability of a point to be sampled for training the centers
+/// * `num_shards` - number of shards
+/// * `num_shards_per_point` - most shards a point is written to
+/// * `max_shard_size` - size over which a shard takes no more points
+/// * `seed` - seed of the build the sample and the centers are drawn with, None draws from the OS
+pub fn partition_into_shards<T: Default + Copy + Into<f32>>(
+    data_file: &str,
+    output_prefix: &str,
+    sampling_rate: f64,
+    num_shards: usize,
+    num_shards_per_point: usize,
+    max_shard_size: usize,
+    seed: Option<u64>,
+) -> ANNResult<Vec<usize>> {
+    if num_shards_per_point == 0 || num_shards_per_point > num_shards {
+        return Err(ANNError::log_index_config_error(
+            "num_shards_per_point".to_string(),
+            format!("Each point must go to between 1 and {} shards, got {}", num_shards, num_shards_per_point),
+        ));
+    }
+
+    let (sample, num_sampled, dim) = gen_random_slice::<T>(data_file, sampling_rate, step_seed(seed, RandomStep::ShardSample))?;
+    if num_sampled < num_shards {
+        return Err(ANNError::log_index_error(format!(
+            "ERROR: Sampled {} points from {}, too few to split it into {} shards.",
+            num_sampled, data_file, num_shards
+        )));
+    }
+
+    let mut centers = vec![0.0f32; num_shards * dim];
+    let params = KMeansParams::new(NUM_SHARD_KMEANS_REPS).with_seed(step_seed(seed, RandomStep::ShardCenters));
+    k_means_clustering_with_params(&sample, num_sampled, dim, &mut centers, num_shards, params)?;
+
+    let mut data_writers = Vec::with_capacity(num_shards);
+    let mut id_writers = Vec::with_capacity(num_shards);
+    for shard_index in 0..num_shards {
+        let mut data_writer = BufWriter::new(File::create(shard_data_file(output_prefix, shard_index))?);
+        data_writer.write_all(&0u32.to_le_bytes())?;
+        data_writer.write_all(&(dim as u32).to_le_bytes())?;
+        data_writers.push(data_writer);
+
+        let mut id_writer = BufWriter::new(File::create(shard_ids_file(output_prefix, shard_index))?);
+        id_writer.write_all(&0u32.to_le_bytes())?;
+        id_writer.write_all(&1u32.to_le_bytes())?;
+        id_writers.push(id_writer);
+    }
+
+    let read_blk_size = 64 * 1024 * 1024;
+    let mut reader = CachedReader::new(data_file, read_blk_size)?;
+    let npts = reader.read_u32()?;
+    reader.read_u32()?;
+
+    let mut shard_sizes = vec![0usize; num_shards];
+    let mut cur_row_bytes = vec![0u8; dim * mem::size_of::<T>()];
+    let mut center_distances: Vec<(f32, usize)> = Vec::with_capacity(num_shards);
+    for id in 0..npts {
+        reader.read(&mut cur_row_bytes)?;
+        let ptr = cur_row_bytes.as_ptr() as *const T;
+        let cur_vector_t = unsafe { std::slice::from_raw_parts(ptr, dim) };
+
+        center_distances.clear();
+        center_distances.extend(centers.chunks_exact(dim).enumerate().map(|(shard_index, center)| {
+            let distance = cur_vector_t
+                .iter()
+                .zip(center)
+                .map(|(&x, c)| (x.into() - c) * (x.into() - c))
+                .sum::<f32>();
+            (distance, shard_index)
+        }));
+        center_distances.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut num_copies = 0;
+        for &(_, shard_index) in center_distances.iter() {
+            if num_copies == num_shards_per_point {
+                break;
+            }
+            if shard_sizes[shard_index] >= max_shard_size {
+                continue;
+            }
+            data_writers[shard_index].write_all(&cur_row_bytes)?;
+            id_writers[shard_index].write_all(&id.to_le_bytes())?;
+            shard_sizes[shard_index] += 1;
+            num_copies += 1;
+        }
+
+        if num_copies == 0 {
+            let shard_index = center_distances[0].1;
+            data_writers[shard_index].write_all(&cur_row_bytes)?;
+            id_writers[shard_index].write_all(&id.to_le_bytes())?;
+            shard_sizes[shard_index] += 1;
+        }
+    }
+
+    for ((data_writer, id_writer), &shard_size) in data_writers.iter_mut().zip(&mut id_writers).zip(&shard_sizes) {
+        data_writer.seek(SeekFrom::Start(0))?;
+        data_writer.write_all(&(shard_size as u32).to_le_bytes())?;
+        data_writer.flush()?;
+        id_writer.seek(SeekFrom::Start(0))?;
+        id_writer.write_all(&(shard_size as u32).to_le_bytes())?;
+        id_writer.flush()?;
+    }
+    info!("Split {} points into {} shards of sizes {:?}", npts, num_shards, shard_sizes);
+
+    Ok(shard_sizes)
+}
+
 #[cfg(test)]
 mod partition_test {
     use std::{fs, io::Read};
@@ -112,7 +290,7 @@ mod partition_test {
         std::fs::write(file_name, data).expect("Failed to write sample file");
 
         let sample_file_prefix = file_name.to_string() + "_sample";
-        gen_sample_data::<f32>(file_name, sample_file_prefix.as_str(), 1f64).unwrap();
+        gen_sample_data::<f32>(file_name, sample_file_prefix.as_str(), 1f64, None).unwrap();
 
         let sample_data_path = format!("{}_data.bin", sample_file_prefix);
         let sample_ids_path = format!("{}_ids.bin", sample_file_prefix);
@@ -147,5 +325,72 @@ mod partition_test {
         fs::remove_file(sample_data_path.as_str()).expect("Failed to delete file");
         fs::remove_file(sample_ids_path.as_str()).expect("Failed to delete file");
     }
+
+    #[test]
+    fn reservoir_sample_test() {
+        let data_file = "tests/data/siftsmall_learn_256pts.fbin";
+        let (data, num_points, dim) = crate::utils::load_bin::<f32>(data_file, 0).unwrap();
+        let rows: Vec<&[f32]> = data.chunks_exact(dim).collect();
+
+        let (sample, num_sampled, sample_dim) = reservoir_sample::<f32>(data_file, 50, None).unwrap();
+        assert_eq!((num_sampled, sample_dim, sample.len()), (50, dim, 50 * dim));
+        let mut sampled_ids: Vec<usize> = sample
+            .chunks_exact(dim)
+            .map(|row| rows.iter().position(|point| *point == row).unwrap())
+            .collect();
+        sampled_ids.sort_unstable();
+        sampled_ids.dedup();
+        assert_eq!(sampled_ids.len(), 50);
+
+        // The same seed draws the same sample
+        let seeded = reservoir_sample::<f32>(data_file, 50, Some(3)).unwrap();
+        assert_eq!(seeded, reservoir_sample::<f32>(data_file, 50, Some(3)).unwrap());
+
+        // A sample larger than the data holds every vector in file order
+        let (sample, num_sampled, _) = reservoir_sample::<f32>(data_file, 1000, None).unwrap();
+        assert_eq!(num_sampled, num_points);
+        assert_eq!(sample, data);
+        assert!(reservoir_sample::<f32>(data_file, 0, None).is_err());
+    }
+
+    #[test]
+    fn partition_into_shards_test() {
+        let data_file = "tests/data/siftsmall_learn_256pts.fbin";
+        let output_prefix = "partition_into_shards_test";
+        let (data, num_points, dim) = crate::utils::load_bin::<f32>(data_file, 0).unwrap();
+
+        assert!(partition_into_shards::<f32>(data_file, output_prefix, 1f64, 4, 5, num_points, None).is_err());
+
+        let shard_sizes = partition_into_shards::<f32>(data_file, output_prefix, 1f64, 4, 2, num_points, None).unwrap();
+        assert_eq!(shard_sizes.iter().sum::<usize>(), 2 * num_points);
+
+        let mut times_seen = vec![0; num_points];
+        for (shard_index, &shard_size) in shard_sizes.iter().enumerate() {
+            let (shard_data, shard_num_points, shard_dim) =
+                crate::utils::load_bin::<f32>(&shard_data_file(output_prefix, shard_index), 0).unwrap();
+            let (ids, num_ids, _) = crate::utils::load_bin::<u32>(&shard_ids_file(output_prefix, shard_index), 0).unwrap();
+            assert_eq!((shard_num_points, shard_dim, num_ids), (shard_size, dim, shard_size));
+            assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+
+            for (row, &id) in shard_data.chunks_exact(dim).zip(&ids) {
+                assert_eq!(row, &data[id as usize * dim..(id as usize + 1) * dim]);
+                times_seen[id as usize] += 1;
+            }
+
+            fs::remove_file(shard_data_file(output_prefix, shard_index)).unwrap();
+            fs::remove_file(shard_ids_file(output_prefix, shard_index)).unwrap();
+        }
+        assert!(times_seen.iter().all(|&count| count == 2));
+
+        // Points overflowing the shards of their closest centers go to the next closest ones,
+        // the last ones may find a single shard with room left
+        let shard_sizes = partition_into_shards::<f32>(data_file, output_prefix, 1f64, 4, 2, 130, None).unwrap();
+        assert!(shard_sizes.iter().all(|&shard_size| shard_size <= 130));
+        assert!(shard_sizes.iter().sum::<usize>() >= num_points);
+        for shard_index in 0..4 {
+            fs::remove_file(shard_data_file(output_prefix, shard_index)).unwrap();
+            fs::remove_file(shard_ids_file(output_prefix, shard_index)).unwrap();
+        }
+    }
 }
 
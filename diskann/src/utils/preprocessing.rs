@@ -0,0 +1,262 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Preprocessing of f32 vectors: mean-centering, per-dimension scaling and L2 normalization.
+//! The parameters are fitted on the data file before building, applied to every point
+//! as it is ingested, and saved so the same transformation can be applied to the queries.
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+
+use vector::{multiply_in_place, normalize_in_place, subtract_in_place};
+
+use crate::common::{ANNError, ANNResult};
+
+/// Transformations applied to a vector, in this order: subtract the mean, multiply by the
+/// scales, normalize to unit L2 norm
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Preprocessing {
+    /// Subtracted from every vector, one value per dimension
+    pub mean: Option<Vec<f32>>,
+
+    /// Multiplies every vector, one value per dimension
+    pub scales: Option<Vec<f32>>,
+
+    /// Scale every vector to unit L2 norm
+    pub normalize: bool,
+}
+
+impl Preprocessing {
+    /// Fit the preprocessing to the points of an f32 bin file.
+    /// * `center` - subtract the mean of each dimension
+    /// * `standardize` - divide each dimension by its standard deviation
+    /// * `normalize` - scale every vector to unit L2 norm
+    pub fn fit_f32_bin(
+        filename: &str,
+        center: bool,
+        standardize: bool,
+        normalize: bool,
+    ) -> ANNResult<Self> {
+        if !(center || standardize) {
+            return Ok(Self {
+                normalize,
+                ..Default::default()
+            });
+        }
+
+        let mut reader = BufReader::new(File::open(filename)?);
+        let (npts, dim) = read_header(&mut reader)?;
+        if npts == 0 {
+            return Err(ANNError::log_index_error(format!(
+                "Can't fit the preprocessing to {}, it has no points",
+                filename
+            )));
+        }
+
+        // Accumulate in f64, the sums of squares of large datasets lose precision in f32
+        let mut sum = vec![0f64; dim];
+        let mut sum_sq = vec![0f64; dim];
+        let mut row = vec![0f32; dim];
+        for _ in 0..npts {
+            reader.read_f32_into::<LittleEndian>(&mut row)?;
+            for ((s, s_sq), x) in sum.iter_mut().zip(sum_sq.iter_mut()).zip(&row) {
+                *s += *x as f64;
+                *s_sq += (*x as f64) * (*x as f64);
+            }
+        }
+
+        let mean: Vec<f64> = sum.iter().map(|s| s / npts as f64).collect();
+        let scales = standardize.then(|| {
+            mean.iter()
+                .zip(&sum_sq)
+                .map(|(m, s_sq)| {
+                    let std_dev = (s_sq / npts as f64 - m * m).max(0.0).sqrt();
+                    // A constant dimension is left as is
+                    if std_dev > 0.0 {
+                        (1.0 / std_dev) as f32
+                    } else {
+                        1.0
+                    }
+                })
+                .collect()
+        });
+
+        Ok(Self {
+            mean: center.then(|| mean.iter().map(|m| *m as f32).collect()),
+            scales,
+            normalize,
+        })
+    }
+
+    /// Whether the preprocessing leaves vectors unchanged
+    pub fn is_identity(&self) -> bool {
+        self.mean.is_none() && self.scales.is_none() && !self.normalize
+    }
+
+    /// Transform a vector in place. A vector padded beyond the dimension of the mean and scales
+    /// only has its leading dimensions centered and scaled, the padding stays zero.
+    pub fn apply(&self, v: &mut [f32]) -> ANNResult<()> {
+        if let Some(mean) = &self.mean {
+            subtract_in_place(Self::leading_dims(v, mean.len())?, mean);
+        }
+
+        if let Some(scales) = &self.scales {
+            multiply_in_place(Self::leading_dims(v, scales.len())?, scales);
+        }
+
+        if self.normalize {
+            normalize_in_place(v);
+        }
+
+        Ok(())
+    }
+
+    /// Write a copy of an f32 bin file with every point transformed
+    pub fn preprocess_f32_bin(&self, input: &str, output: &str) -> ANNResult<()> {
+        let mut reader = BufReader::new(File::open(input)?);
+        let (npts, dim) = read_header(&mut reader)?;
+
+        let mut writer = BufWriter::new(File::create(output)?);
+        writer.write_i32::<LittleEndian>(npts as i32)?;
+        writer.write_i32::<LittleEndian>(dim as i32)?;
+
+        let mut row = vec![0f32; dim];
+        for _ in 0..npts {
+            reader.read_f32_into::<LittleEndian>(&mut row)?;
+            self.apply(&mut row)?;
+            for x in &row {
+                writer.write_f32::<LittleEndian>(*x)?;
+            }
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Save the parameters as the normalize flag (u8), then the mean and the scales, each as
+    /// a length (u32, 0 if absent) followed by its f32 values
+    pub fn save(&self, filename: &str) -> ANNResult<()> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        writer.write_u8(self.normalize as u8)?;
+        for values in [&self.mean, &self.scales] {
+            let values = values.as_deref().unwrap_or_default();
+            writer.write_u32::<LittleEndian>(values.len() as u32)?;
+            for x in values {
+                writer.write_f32::<LittleEndian>(*x)?;
+            }
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Load parameters written by save
+    pub fn load(filename: &str) -> ANNResult<Self> {
+        let mut reader = BufReader::new(File::open(filename)?);
+        let normalize = reader.read_u8()? != 0;
+        let mut read_values = || -> ANNResult<Option<Vec<f32>>> {
+            let len = reader.read_u32::<LittleEndian>()? as usize;
+            if len == 0 {
+                return Ok(None);
+            }
+
+            let mut values = vec![0f32; len];
+            reader.read_f32_into::<LittleEndian>(&mut values)?;
+            Ok(Some(values))
+        };
+        let mean = read_values()?;
+        let scales = read_values()?;
+
+        Ok(Self {
+            mean,
+            scales,
+            normalize,
+        })
+    }
+
+    fn leading_dims(v: &mut [f32], dim: usize) -> ANNResult<&mut [f32]> {
+        if v.len() < dim {
+            return Err(ANNError::log_index_error(format!(
+                "Vector has dimension {}, the preprocessing was fitted with dimension {}",
+                v.len(),
+                dim
+            )));
+        }
+
+        Ok(&mut v[..dim])
+    }
+}
+
+/// Read the (npts, dim) header of a bin file
+fn read_header(reader: &mut impl Read) -> ANNResult<(usize, usize)> {
+    let npts = reader.read_i32::<LittleEndian>()? as usize;
+    let dim = reader.read_i32::<LittleEndian>()? as usize;
+    Ok((npts, dim))
+}
+
+#[cfg(test)]
+mod preprocessing_test {
+    use std::fs;
+
+    use approx::assert_abs_diff_eq;
+
+    use super::*;
+    use crate::utils::{load_bin, save_bin_f32};
+
+    #[test]
+    fn fit_and_apply_to_bin_files() {
+        let data_file = "preprocessing_test_data.bin";
+        let output_file = "preprocessing_test_output.bin";
+        let params_file = "preprocessing_test.params";
+
+        // Dimension 1 is constant, it keeps a scale of 1
+        let (npts, dim) = (6, 3);
+        let data: Vec<f32> = (0..npts)
+            .flat_map(|i| [i as f32 * 2.0 + 1.0, 5.0, (i % 3) as f32 - 4.0])
+            .collect();
+        save_bin_f32(data_file, &data, npts, dim, 0).unwrap();
+
+        let standardized = Preprocessing::fit_f32_bin(data_file, true, true, false).unwrap();
+        assert_eq!(standardized.scales.as_ref().unwrap()[1], 1.0);
+        standardized
+            .preprocess_f32_bin(data_file, output_file)
+            .unwrap();
+        let (output, _, _) = load_bin::<f32>(output_file, 0).unwrap();
+        for d in 0..dim {
+            let column: Vec<f32> = output.iter().skip(d).step_by(dim).copied().collect();
+            let mean = column.iter().sum::<f32>() / npts as f32;
+            let variance = column.iter().map(|x| (x - mean).powi(2)).sum::<f32>() / npts as f32;
+            assert_abs_diff_eq!(mean, 0.0, epsilon = 1e-5);
+            if d != 1 {
+                assert_abs_diff_eq!(variance, 1.0, epsilon = 1e-4);
+            }
+        }
+
+        standardized.save(params_file).unwrap();
+        assert_eq!(Preprocessing::load(params_file).unwrap(), standardized);
+
+        // Padded queries only have their leading dimensions transformed
+        let normalized = Preprocessing::fit_f32_bin(data_file, true, false, true).unwrap();
+        let mut query = [3.0f32, 5.0, -3.0, 0.0];
+        normalized.apply(&mut query).unwrap();
+        assert_abs_diff_eq!(
+            query.iter().map(|x| x * x).sum::<f32>(),
+            1.0,
+            epsilon = 1e-5
+        );
+        assert_eq!(query[3], 0.0);
+        assert!(normalized.apply(&mut [1.0, 2.0]).is_err());
+
+        assert!(Preprocessing::fit_f32_bin(data_file, false, false, false)
+            .unwrap()
+            .is_identity());
+
+        for file in [data_file, output_file, params_file] {
+            fs::remove_file(file).unwrap();
+        }
+    }
+}
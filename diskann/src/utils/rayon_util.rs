@@ -3,11 +3,15 @@
  * Licensed under the MIT license.
  */
 use std::ops::Range;
+
 use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+use rayon::{ThreadPool, ThreadPoolBuilder};
 
-use crate::common::ANNResult;
+use crate::common::{ANNError, ANNResult};
 
-/// based on thread_num, execute the task in parallel using Rayon or serial
+/// based on thread_num, execute the task in parallel using Rayon or serial.
+/// Tasks run serially when num_threads is 1 and on the current pool otherwise, see
+/// install_on_pool for running them on a given pool.
 #[inline]
 pub fn execute_with_rayon<F>(range: Range<usize>, num_threads: u32, f: F) -> ANNResult<()>
 where F: Fn(usize) -> ANNResult<()> + Sync + Send + Copy
@@ -22,12 +26,64 @@ where F: Fn(usize) -> ANNResult<()> + Sync + Send + Copy
     }
 }
 
-/// set the thread count of Rayon, otherwise it will use threads as many as logical cores.
-#[inline]
-pub fn set_rayon_num_threads(num_threads: u32) {
-    std::env::set_var(
-        "RAYON_NUM_THREADS",
-        num_threads.to_string(),
-    );
+/// Start a pool of num_threads threads for the parallel phases of a build. The caller owns
+/// the pool, its threads stop when the last handle to it is dropped.
+pub fn build_thread_pool(num_threads: u32) -> ANNResult<ThreadPool> {
+    ThreadPoolBuilder::new()
+        .num_threads(num_threads as usize)
+        .thread_name(|index| format!("diskann-build-{}", index))
+        .build()
+        .map_err(|err| {
+            ANNError::log_index_error(format!(
+                "Failed to start a pool of {} threads: {}",
+                num_threads, err
+            ))
+        })
+}
+
+/// Run op on pool when there is one, so the parallel iterators it starts run there, and on
+/// the calling thread otherwise
+pub fn install_on_pool<R, OP>(pool: Option<&ThreadPool>, op: OP) -> R
+where
+    R: Send,
+    OP: FnOnce() -> R + Send,
+{
+    match pool {
+        Some(pool) => pool.install(op),
+        None => op(),
+    }
 }
 
+#[cfg(test)]
+mod rayon_util_test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[test]
+    fn tasks_run_on_a_pool_of_the_thread_count() {
+        let pool = build_thread_pool(3).unwrap();
+        let max_threads = AtomicUsize::new(0);
+        install_on_pool(Some(&pool), || {
+            execute_with_rayon(0..64, 0, |_| {
+                max_threads.fetch_max(rayon::current_num_threads(), Ordering::Relaxed);
+                Ok(())
+            })
+        })
+        .unwrap();
+        assert_eq!(max_threads.load(Ordering::Relaxed), 3);
+
+        let serial_thread = std::thread::current().id();
+        execute_with_rayon(0..8, 1, |_| {
+            assert_eq!(std::thread::current().id(), serial_thread);
+            Ok(())
+        })
+        .unwrap();
+        assert!(execute_with_rayon(0..8, 2, |i| if i == 5 {
+            Err(ANNError::log_index_error("task failed".to_string()))
+        } else {
+            Ok(())
+        })
+        .is_err());
+    }
+}
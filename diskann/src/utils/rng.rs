@@ -0,0 +1,95 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Random number generators of the build steps.
+//!
+//! A build with a seed gives each step that draws random numbers its own generator, seeded from
+//! the build seed and the step, so the draws of a step don't depend on how many numbers the
+//! steps before it drew. A build without a seed seeds every generator from the OS.
+
+use rand::{rngs::SmallRng, SeedableRng};
+
+/// Steps of a build drawing random numbers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RandomStep {
+    /// Sample of the vectors the PQ pivots are trained on
+    PQTrainingSample,
+
+    /// k-means++ seeding of the PQ pivots
+    PQPivots,
+
+    /// Sample of the vectors the shard centers are trained on
+    ShardSample,
+
+    /// k-means++ seeding of the shard centers
+    ShardCenters,
+
+    /// Sample of the vectors kept as warm-up queries of a disk index
+    WarmupSample,
+
+    /// Points drawn to pick the entry points of the graph
+    EntryPoints,
+
+    /// Training of the compressed vectors the build prune compares
+    PruneQuantization,
+}
+
+impl RandomStep {
+    fn id(self) -> u64 {
+        match self {
+            RandomStep::PQTrainingSample => 1,
+            RandomStep::PQPivots => 2,
+            RandomStep::ShardSample => 3,
+            RandomStep::ShardCenters => 4,
+            RandomStep::WarmupSample => 5,
+            RandomStep::EntryPoints => 6,
+            RandomStep::PruneQuantization => 7,
+        }
+    }
+}
+
+/// Seed of a step of a build seeded with seed, None when the build is seeded from the OS
+pub fn step_seed(seed: Option<u64>, step: RandomStep) -> Option<u64> {
+    // splitmix64 of the pair, so close build seeds and steps give unrelated streams
+    seed.map(|seed| {
+        let mut z = seed ^ step.id().wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    })
+}
+
+/// Generator seeded with seed, or from the OS when there is none
+pub fn seeded_rng(seed: Option<u64>) -> SmallRng {
+    match seed {
+        Some(seed) => SmallRng::seed_from_u64(seed),
+        None => SmallRng::from_entropy(),
+    }
+}
+
+#[cfg(test)]
+mod rng_test {
+    use rand::Rng;
+
+    use super::*;
+
+    #[test]
+    fn seeded_generators_repeat_and_steps_differ() {
+        let draw = |seed| -> Vec<u32> {
+            let mut rng = seeded_rng(seed);
+            (0..8).map(|_| rng.gen()).collect()
+        };
+
+        let seed = step_seed(Some(7), RandomStep::PQPivots);
+        assert_eq!(draw(seed), draw(seed));
+        assert_ne!(
+            draw(seed),
+            draw(step_seed(Some(7), RandomStep::ShardCenters))
+        );
+        assert_ne!(draw(seed), draw(step_seed(Some(8), RandomStep::PQPivots)));
+        assert_eq!(step_seed(None, RandomStep::PQPivots), None);
+    }
+}
@@ -0,0 +1,357 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Vectors exported as CSV or JSON lines, an id and an embedding per row.
+//!
+//! A CSV file starts with a header naming its columns and separates them with commas, tabs
+//! or semicolons, whichever the header uses. The embedding cell holds the values separated
+//! by commas, semicolons or spaces, optionally in brackets, and is quoted when it contains
+//! the column separator. A JSON lines file holds an object per line whose embedding is an
+//! array of numbers. Blank lines are skipped, and a row that can't be read fails the load
+//! with its line number.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use log::info;
+use serde_json::Value;
+
+use crate::common::{ANNError, ANNResult};
+
+/// Names of the columns, or the keys of the objects, holding the id and the embedding
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextVectorColumns {
+    /// Column of the id
+    pub id: String,
+
+    /// Column of the embedding
+    pub embedding: String,
+}
+
+impl Default for TextVectorColumns {
+    fn default() -> Self {
+        Self {
+            id: "id".to_string(),
+            embedding: "embedding".to_string(),
+        }
+    }
+}
+
+/// Vectors read from a CSV or JSON lines file, in the order of the rows
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TextVectors {
+    /// Id of each row, as written in the file
+    pub ids: Vec<String>,
+
+    /// Values of the embeddings, row after row
+    pub vectors: Vec<f32>,
+
+    /// Dimension of the embeddings
+    pub dim: usize,
+}
+
+impl TextVectors {
+    /// Number of vectors
+    pub fn num_points(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Add the embedding of a row, which must have the dimension of the first one
+    fn push(&mut self, id: String, embedding: Vec<f32>, row: &RowContext) -> ANNResult<()> {
+        if embedding.is_empty() {
+            return Err(row.error("the embedding is empty"));
+        }
+        if self.ids.is_empty() {
+            self.dim = embedding.len();
+        } else if embedding.len() != self.dim {
+            return Err(row.error(&format!(
+                "the embedding has {} values, the rows before it {}",
+                embedding.len(),
+                self.dim
+            )));
+        }
+
+        self.ids.push(id);
+        self.vectors.extend(embedding);
+        Ok(())
+    }
+}
+
+/// Load the vectors of a .csv, .tsv, .jsonl or .ndjson file, by its extension
+pub fn load_text_vectors(filename: &str, columns: &TextVectorColumns) -> ANNResult<TextVectors> {
+    let extension = filename.rsplit('.').next().unwrap_or("");
+    match extension.to_ascii_lowercase().as_str() {
+        "csv" | "tsv" => load_csv_vectors(filename, columns),
+        "jsonl" | "ndjson" => load_jsonl_vectors(filename, columns),
+        _ => Err(ANNError::log_index_error(format!(
+            "ERROR: {} isn't a .csv, .tsv, .jsonl or .ndjson file.",
+            filename
+        ))),
+    }
+}
+
+/// Load the vectors of a CSV file with a header row
+pub fn load_csv_vectors(filename: &str, columns: &TextVectorColumns) -> ANNResult<TextVectors> {
+    let mut lines = BufReader::new(File::open(filename)?).lines().enumerate();
+    let (separator, id_column, embedding_column) = loop {
+        let Some((index, line)) = lines.next() else {
+            return Err(ANNError::log_index_error(format!(
+                "ERROR: CSV file {} has no header.",
+                filename
+            )));
+        };
+        let line = line?;
+        let header = line.trim_start_matches('\u{feff}').trim();
+        if header.is_empty() {
+            continue;
+        }
+
+        let separator = [',', '\t', ';']
+            .into_iter()
+            .find(|&separator| header.contains(separator))
+            .unwrap_or(',');
+        let row = RowContext::new(filename, index);
+        let names = split_csv_row(header, separator, &row)?;
+        let column = |name: &str| {
+            names
+                .iter()
+                .position(|column| column == name)
+                .ok_or_else(|| {
+                    row.error(&format!(
+                        "the header has no column {}, only {}",
+                        name,
+                        names.join(", ")
+                    ))
+                })
+        };
+        break (separator, column(&columns.id)?, column(&columns.embedding)?);
+    };
+
+    let mut vectors = TextVectors::default();
+    for (index, line) in lines {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let row = RowContext::new(filename, index);
+        let fields = split_csv_row(line.trim_end_matches('\r'), separator, &row)?;
+        let field = |column: usize, name: &str| {
+            fields
+                .get(column)
+                .ok_or_else(|| row.error(&format!("the row has no {} column", name)))
+        };
+        let id = field(id_column, &columns.id)?.clone();
+        let embedding = parse_embedding(field(embedding_column, &columns.embedding)?, &row)?;
+        vectors.push(id, embedding, &row)?;
+    }
+
+    info!(
+        "Loaded {} vectors of dimension {} from CSV file {}",
+        vectors.num_points(),
+        vectors.dim,
+        filename
+    );
+    Ok(vectors)
+}
+
+/// Load the vectors of a JSON lines file
+pub fn load_jsonl_vectors(filename: &str, columns: &TextVectorColumns) -> ANNResult<TextVectors> {
+    let mut vectors = TextVectors::default();
+    for (index, line) in BufReader::new(File::open(filename)?).lines().enumerate() {
+        let line = line?;
+        let line = line.trim_start_matches('\u{feff}').trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let row = RowContext::new(filename, index);
+        let object: Value = serde_json::from_str(line)
+            .map_err(|err| row.error(&format!("invalid JSON, {}", err)))?;
+        let id = match object.get(&columns.id) {
+            Some(Value::String(id)) => id.clone(),
+            Some(Value::Number(id)) => id.to_string(),
+            Some(_) => return Err(row.error(&format!("{} isn't a string or a number", columns.id))),
+            None => return Err(row.error(&format!("the object has no {}", columns.id))),
+        };
+        let embedding = match object.get(&columns.embedding) {
+            Some(Value::Array(values)) => values
+                .iter()
+                .map(|value| {
+                    value
+                        .as_f64()
+                        .map(|value| value as f32)
+                        .filter(|value| value.is_finite())
+                        .ok_or_else(|| {
+                            row.error(&format!(
+                                "{} holds {}, which isn't a finite number",
+                                columns.embedding, value
+                            ))
+                        })
+                })
+                .collect::<ANNResult<Vec<f32>>>()?,
+            _ => return Err(row.error(&format!("the object has no array {}", columns.embedding))),
+        };
+        vectors.push(id, embedding, &row)?;
+    }
+
+    info!(
+        "Loaded {} vectors of dimension {} from JSON lines file {}",
+        vectors.num_points(),
+        vectors.dim,
+        filename
+    );
+    Ok(vectors)
+}
+
+/// File and line of a row, for error messages
+struct RowContext<'a> {
+    filename: &'a str,
+    line: usize,
+}
+
+impl<'a> RowContext<'a> {
+    fn new(filename: &'a str, index: usize) -> Self {
+        Self {
+            filename,
+            line: index + 1,
+        }
+    }
+
+    fn error(&self, msg: &str) -> ANNError {
+        ANNError::log_index_error(format!(
+            "ERROR: Line {} of {}: {}.",
+            self.line, self.filename, msg
+        ))
+    }
+}
+
+/// Fields of a CSV row, unquoting the fields in double quotes
+fn split_csv_row(line: &str, separator: char, row: &RowContext) -> ANNResult<Vec<String>> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' if in_quotes => in_quotes = false,
+            '"' if field.trim().is_empty() => {
+                field.clear();
+                in_quotes = true;
+            }
+            c if c == separator && !in_quotes => {
+                fields.push(field.trim().to_string());
+                field.clear();
+            }
+            c => field.push(c),
+        }
+    }
+    if in_quotes {
+        return Err(row.error("a quoted field isn't closed"));
+    }
+    fields.push(field.trim().to_string());
+    Ok(fields)
+}
+
+/// Values of an embedding cell, such as [0.5, 1.0] or 0.5 1.0
+fn parse_embedding(cell: &str, row: &RowContext) -> ANNResult<Vec<f32>> {
+    let values = cell.trim();
+    let values = values
+        .strip_prefix('[')
+        .and_then(|values| values.strip_suffix(']'))
+        .unwrap_or(values);
+    values
+        .split(|c: char| c == ',' || c == ';' || c.is_whitespace())
+        .filter(|value| !value.is_empty())
+        .map(|value| {
+            value
+                .parse::<f32>()
+                .ok()
+                .filter(|value| value.is_finite())
+                .ok_or_else(|| {
+                    row.error(&format!(
+                        "the embedding holds {}, which isn't a finite number",
+                        value
+                    ))
+                })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod text_vectors_test {
+    use super::*;
+
+    fn load(filename: &str, contents: &str) -> ANNResult<TextVectors> {
+        std::fs::write(filename, contents).unwrap();
+        let vectors = load_text_vectors(filename, &TextVectorColumns::default());
+        std::fs::remove_file(filename).unwrap();
+        vectors
+    }
+
+    #[test]
+    fn csv_rows_are_read_tolerantly() {
+        let csv = "\u{feff}title,id,embedding\r\n\
+                   \"Hello, world\",a1,\"[0.5, 1, -2]\"\r\n\
+                   \r\n\
+                   plain,7,0.25 0.5 0.75\n\
+                   \"say \"\"hi\"\"\",b;2,[1;2;3]\n";
+        let vectors = load("csv_rows_are_read_tolerantly.csv", csv).unwrap();
+        assert_eq!(vectors.ids, vec!["a1", "7", "b;2"]);
+        assert_eq!(vectors.dim, 3);
+        assert_eq!(
+            vectors.vectors,
+            vec![0.5, 1.0, -2.0, 0.25, 0.5, 0.75, 1.0, 2.0, 3.0]
+        );
+
+        let tsv = "id\tembedding\nx\t1,2\ny\t3,4\n";
+        let vectors = load("csv_rows_are_read_tolerantly.tsv", tsv).unwrap();
+        assert_eq!(vectors.vectors, vec![1.0, 2.0, 3.0, 4.0]);
+
+        // Malformed rows name their line
+        let error = load(
+            "csv_rows_are_read_tolerantly.csv",
+            "id,embedding\na,1 2\nb,1 x\n",
+        );
+        assert!(error.unwrap_err().to_string().contains("Line 3"));
+        assert!(load(
+            "csv_rows_are_read_tolerantly.csv",
+            "id,embedding\na,1 2\nb,1\n"
+        )
+        .is_err());
+        assert!(load("csv_rows_are_read_tolerantly.csv", "id,vector\na,1 2\n").is_err());
+        assert!(load(
+            "csv_rows_are_read_tolerantly.csv",
+            "id,embedding\na,\"1 2\n"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn jsonl_objects_are_read_with_their_ids() {
+        let jsonl = "{\"id\": 3, \"embedding\": [1.5, 2], \"text\": \"x\"}\n\
+                     \n\
+                     {\"id\": \"doc-4\", \"embedding\": [0, -1e-3]}\n";
+        let vectors = load("jsonl_objects_are_read_with_their_ids.jsonl", jsonl).unwrap();
+        assert_eq!(vectors.ids, vec!["3", "doc-4"]);
+        assert_eq!(vectors.vectors, vec![1.5, 2.0, 0.0, -1e-3]);
+
+        for malformed in [
+            "{\"id\": 1, \"embedding\": [1, 2]}\n{\"id\": 2, \"embedding\": [1, \"2\"]}\n",
+            "{\"id\": 1, \"embedding\": [1, 2]}\n{\"id\": 2, \"embedding\": [1, 2]\n",
+            "{\"id\": 1, \"embedding\": [1, 2]}\n{\"embedding\": [1, 2]}\n",
+            "{\"id\": 1, \"embedding\": [1, 2]}\n{\"id\": 2, \"embedding\": [1]}\n",
+        ] {
+            let error = load("jsonl_objects_are_read_with_their_ids.jsonl", malformed);
+            assert!(error.unwrap_err().to_string().contains("Line 2"));
+        }
+        assert!(load("jsonl_objects_are_read_with_their_ids.txt", "").is_err());
+    }
+}
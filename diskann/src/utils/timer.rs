@@ -2,7 +2,8 @@
  * Copyright (c) Microsoft Corporation. All rights reserved.
  * Licensed under the MIT license.
  */
-use platform::*;
+#[cfg(feature = "platform")]
+use platform::{get_process_cycle_time, get_process_handle};
 use std::time::{Duration, Instant};
 
 #[derive(Clone)]
@@ -20,7 +21,7 @@ impl Default for Timer {
 
 impl Timer {
     pub fn new() -> Timer {
-        let pid = get_process_handle();
+        let pid = get_process_handle().ok();
         let cycles = get_process_cycle_time(pid);
         Timer {
             check_point: Instant::now(),
@@ -63,6 +64,17 @@ impl Timer {
     }
 }
 
+/// Cycles are counted by the platform crate, without it only the time is measured
+#[cfg(not(feature = "platform"))]
+fn get_process_handle() -> std::io::Result<usize> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(not(feature = "platform"))]
+fn get_process_cycle_time(_process_handle: Option<usize>) -> Option<u64> {
+    None
+}
+
 #[cfg(test)]
 mod timer_tests {
     use super::*;
@@ -72,7 +84,7 @@ mod timer_tests {
     fn test_new() {
         let timer = Timer::new();
         assert!(timer.check_point.elapsed().as_secs() < 1);
-        if cfg!(windows) {
+        if cfg!(all(feature = "platform", any(windows, target_os = "linux"))) {
             assert!(timer.pid.is_some());
             assert!(timer.cycles.is_some());
         }
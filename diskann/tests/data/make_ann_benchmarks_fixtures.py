@@ -0,0 +1,41 @@
+# Copyright (c) Microsoft Corporation. All rights reserved.
+# Licensed under the MIT license.
+
+"""Write the ann-benchmarks fixtures the HDF5 reader is tested against.
+
+Run from diskann/tests/data with h5py installed:
+
+    python make_ann_benchmarks_fixtures.py
+
+ann_benchmarks_contiguous.hdf5 has the contiguous datasets h5py writes by default and
+ann_benchmarks_chunked.hdf5 the same datasets in chunks of 2 x 3, which leave partial chunks
+at the last rows and columns. The values are those hdf5_test::h5py_fixtures_are_read expects.
+"""
+
+import h5py
+import numpy as np
+
+DIM, NUM_TRAIN, NUM_TEST, K = 5, 11, 3, 4
+
+
+def write(filename, chunks):
+    train = (np.arange(NUM_TRAIN * DIM, dtype=np.float32) * 0.5).reshape(NUM_TRAIN, DIM)
+    test = -np.arange(NUM_TEST * DIM, dtype=np.float32).reshape(NUM_TEST, DIM)
+    neighbors = (np.arange(NUM_TEST * K, dtype=np.int64) % NUM_TRAIN).reshape(NUM_TEST, K)
+    distances = (np.arange(NUM_TEST * K, dtype=np.float64) / 4).reshape(NUM_TEST, K)
+
+    with h5py.File(filename, "w") as f:
+        f.attrs["distance"] = "angular"
+        f.attrs["dimension"] = DIM
+        for name, data in [
+            ("train", train),
+            ("test", test),
+            ("neighbors", neighbors),
+            ("distances", distances),
+        ]:
+            f.create_dataset(name, data=data, chunks=chunks)
+
+
+if __name__ == "__main__":
+    write("ann_benchmarks_contiguous.hdf5", None)
+    write("ann_benchmarks_chunked.hdf5", (2, 3))
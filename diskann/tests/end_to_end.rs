@@ -0,0 +1,354 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![cfg(feature = "integration")]
+
+//! End-to-end tests that build indices from generated data and search them through the public
+//! API, so that regressions across modules surface here. Run with
+//! `cargo test -p diskann --features integration --test end_to_end`.
+
+use std::collections::HashSet;
+use std::fs;
+
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+
+use diskann::index::create_inmem_index;
+use diskann::model::{IndexConfiguration, IndexWriteParametersBuilder};
+use diskann::utils::{file_exists, round_up, save_data_in_base_dimensions};
+use vector::Metric;
+
+const DIM: usize = 128;
+const NUM_POINTS: usize = 1000;
+const NUM_CLUSTERS: usize = 20;
+const NUM_QUERIES: usize = 20;
+const K: usize = 10;
+const L: u32 = 100;
+const R: u32 = 32;
+const SEED: u64 = 42;
+
+/// Points scattered around a few random centers, so the data has some structure to index
+fn generate_points(num_points: usize, rng: &mut SmallRng) -> Vec<Vec<f32>> {
+    let centers: Vec<Vec<f32>> = (0..NUM_CLUSTERS)
+        .map(|_| (0..DIM).map(|_| rng.gen_range(-10.0..10.0)).collect())
+        .collect();
+    (0..num_points)
+        .map(|i| {
+            centers[i % NUM_CLUSTERS]
+                .iter()
+                .map(|c| c + rng.gen_range(-1.0..1.0))
+                .collect()
+        })
+        .collect()
+}
+
+/// Queries are points of the data set with a little noise added
+fn generate_queries(points: &[Vec<f32>], rng: &mut SmallRng) -> Vec<Vec<f32>> {
+    (0..NUM_QUERIES)
+        .map(|_| {
+            let point = &points[rng.gen_range(0..points.len())];
+            point.iter().map(|x| x + rng.gen_range(-0.1..0.1)).collect()
+        })
+        .collect()
+}
+
+fn squared_l2(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum()
+}
+
+/// Exact k nearest neighbors of the query among the points that are not excluded
+fn brute_force_knn(points: &[Vec<f32>], query: &[f32], excluded: &HashSet<u32>) -> Vec<u32> {
+    let mut distances: Vec<(f32, u32)> = points
+        .iter()
+        .enumerate()
+        .filter(|(id, _)| !excluded.contains(&(*id as u32)))
+        .map(|(id, point)| (squared_l2(point, query), id as u32))
+        .collect();
+    distances.sort_by(|a, b| a.0.total_cmp(&b.0));
+    distances.iter().take(K).map(|(_, id)| *id).collect()
+}
+
+fn recall(results: &[u32], truth: &[u32]) -> f32 {
+    let truth: HashSet<&u32> = truth.iter().collect();
+    results.iter().filter(|id| truth.contains(id)).count() as f32 / truth.len() as f32
+}
+
+fn write_points(path: &str, points: &[Vec<f32>]) {
+    let mut data: Vec<f32> = points.iter().flatten().copied().collect();
+    save_data_in_base_dimensions(path, &mut data, points.len(), DIM, DIM, 0).unwrap();
+}
+
+fn remove_files(paths: &[String]) {
+    for path in paths {
+        if file_exists(path) {
+            fs::remove_file(path).unwrap();
+        }
+    }
+}
+
+fn inmem_config(max_points: usize) -> IndexConfiguration {
+    IndexConfiguration::new(
+        Metric::L2,
+        DIM,
+        round_up(DIM as u64, 16_u64) as usize,
+        max_points,
+        false,
+        0,
+        false,
+        0,
+        1.0,
+        IndexWriteParametersBuilder::new(L, R)
+            .with_alpha(1.2)
+            .with_num_threads(4)
+            .build(),
+    )
+}
+
+#[test]
+fn inmem_index_build_insert_delete_and_reload() {
+    let mut rng = SmallRng::seed_from_u64(SEED);
+    let points = generate_points(NUM_POINTS, &mut rng);
+    let queries = generate_queries(&points, &mut rng);
+
+    let mut index = create_inmem_index::<f32>(inmem_config(NUM_POINTS)).unwrap();
+    index.build_from_vectors(&points[..NUM_POINTS / 2]).unwrap();
+    index.insert_vectors(&points[NUM_POINTS / 2..]).unwrap();
+
+    let mut indices = vec![0u32; K];
+    let no_deletes = HashSet::new();
+    let mut total_recall = 0.0;
+    for query in &queries {
+        index.search(query, K, L, &mut indices).unwrap();
+        total_recall += recall(&indices, &brute_force_knn(&points, query, &no_deletes));
+    }
+    assert!(total_recall / NUM_QUERIES as f32 >= 0.9);
+
+    // Delete the exact nearest neighbor of every query, none of them may come back
+    let deleted: HashSet<u32> = queries
+        .iter()
+        .map(|query| brute_force_knn(&points, query, &no_deletes)[0])
+        .collect();
+    let num_deleted = deleted.len();
+    index
+        .soft_delete(deleted.iter().copied().collect(), num_deleted)
+        .unwrap();
+
+    let mut total_recall = 0.0;
+    for query in &queries {
+        index.search(query, K, L, &mut indices).unwrap();
+        assert!(indices.iter().all(|id| !deleted.contains(id)));
+        total_recall += recall(&indices, &brute_force_knn(&points, query, &deleted));
+    }
+    assert!(total_recall / NUM_QUERIES as f32 >= 0.9);
+
+    // Unlinking the deleted points from the graph must not cost the others their recall
+    assert_eq!(index.consolidate_deletes().unwrap(), num_deleted);
+    index.compact_graph().unwrap();
+
+    let mut results_before_save = Vec::new();
+    let mut total_recall = 0.0;
+    for query in &queries {
+        index.search(query, K, L, &mut indices).unwrap();
+        assert!(indices.iter().all(|id| !deleted.contains(id)));
+        total_recall += recall(&indices, &brute_force_knn(&points, query, &deleted));
+        results_before_save.push(indices.clone());
+    }
+    assert!(total_recall / NUM_QUERIES as f32 >= 0.9);
+
+    let index_path = "end_to_end_inmem_index";
+    index.save(index_path).unwrap();
+
+    let mut reloaded = create_inmem_index::<f32>(inmem_config(NUM_POINTS)).unwrap();
+    reloaded.load(index_path, NUM_POINTS).unwrap();
+    for (query, expected) in queries.iter().zip(&results_before_save) {
+        reloaded.search(query, K, L, &mut indices).unwrap();
+        assert_eq!(&indices, expected);
+    }
+
+    remove_files(&[
+        index_path.to_string(),
+        format!("{}.data", index_path),
+        format!("{}.delete", index_path),
+        format!("{}.lock", index_path),
+    ]);
+}
+
+/// Build a disk index over points within index_build_ram_limit_gb, returns the paths of the
+/// data file and the prefix of the index
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+fn build_disk_index_over(
+    points: &[Vec<f32>],
+    name: &str,
+    index_build_ram_limit_gb: f64,
+) -> (String, String) {
+    use diskann::index::ann_disk_index::build_disk_index;
+    use diskann::model::DiskIndexBuildParameters;
+
+    let data_path = format!("end_to_end_{}_data.fbin", name);
+    let index_prefix = format!("end_to_end_{}_index", name);
+    write_points(&data_path, points);
+
+    build_disk_index::<f32>(
+        &data_path,
+        &index_prefix,
+        Metric::L2,
+        IndexWriteParametersBuilder::new(L, R)
+            .with_alpha(1.2)
+            .with_num_threads(4)
+            .build(),
+        DiskIndexBuildParameters::new(0.01, index_build_ram_limit_gb).unwrap(),
+        32,
+    )
+    .unwrap();
+
+    (data_path, index_prefix)
+}
+
+/// Remove the data file and the files of the disk index built by build_disk_index_over
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+fn remove_disk_index_files(data_path: String, index_prefix: &str) {
+    remove_files(&[
+        data_path,
+        format!("{}_disk.index", index_prefix),
+        format!("{}.bin_pq_pivots.bin", index_prefix),
+        format!("{}.bin_pq_compressed.bin", index_prefix),
+        format!("{}_sample_data.bin", index_prefix),
+        format!("{}_sample_ids.bin", index_prefix),
+        format!("{}_mem.index.lock", index_prefix),
+        format!("{}.lock", index_prefix),
+    ]);
+}
+
+/// Build a disk index over generated data within index_build_ram_limit_gb, then search it
+#[cfg(target_os = "linux")]
+async fn build_and_search_disk_index(name: &str, index_build_ram_limit_gb: f64) {
+    use diskann::index::DiskIndex;
+    use diskann::model::vertex::DIM_128;
+    use diskann::storage::DiskIndexStorage;
+
+    let mut rng = SmallRng::seed_from_u64(SEED);
+    let points = generate_points(NUM_POINTS, &mut rng);
+    let queries = generate_queries(&points, &mut rng);
+
+    let (data_path, index_prefix) = build_disk_index_over(&points, name, index_build_ram_limit_gb);
+    let index_prefix = index_prefix.as_str();
+
+    let config = IndexConfiguration::new(
+        Metric::L2,
+        DIM,
+        DIM_128,
+        NUM_POINTS,
+        false,
+        0,
+        false,
+        0,
+        1.0,
+        IndexWriteParametersBuilder::new(L, R).build(),
+    );
+    let storage =
+        DiskIndexStorage::<f32>::new(data_path.clone(), index_prefix.to_string()).unwrap();
+    let mut index = DiskIndex::<f32, DIM_128>::new(None, config, storage);
+    index.load(64).await.unwrap();
+
+    let no_deletes = HashSet::new();
+    let mut total_recall = 0.0;
+    for query in &queries {
+        let (ids, distances) = index.search(query, K, L as usize, 4).await.unwrap();
+        assert_eq!(ids.len(), K);
+        assert!(distances.windows(2).all(|pair| pair[0] <= pair[1]));
+        total_recall += recall(&ids, &brute_force_knn(&points, query, &no_deletes));
+    }
+    assert!(total_recall / NUM_QUERIES as f32 >= 0.8);
+
+    remove_disk_index_files(data_path, index_prefix);
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn disk_index_build_and_search() {
+    build_and_search_disk_index("disk", 1.0).await;
+}
+
+/// The graph of 1000 points takes about 0.7MB, so a 0.3MB budget builds it in shards
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn sharded_disk_index_build_and_search() {
+    build_and_search_disk_index("sharded_disk", 0.0003).await;
+}
+
+/// Open the graph of a disk index with the aligned reader of the platform
+#[cfg(target_os = "linux")]
+async fn open_disk_graph(disk_index_file: &str) -> diskann::storage::DiskGraphStorage {
+    use diskann::model::LinuxAlignedFileReader;
+
+    let reader = LinuxAlignedFileReader::new(disk_index_file).await.unwrap();
+    diskann::storage::DiskGraphStorage::new(std::sync::Arc::new(reader))
+        .await
+        .unwrap()
+}
+
+/// Open the graph of a disk index with the aligned reader of the platform
+#[cfg(target_os = "windows")]
+async fn open_disk_graph(disk_index_file: &str) -> diskann::storage::DiskGraphStorage {
+    use diskann::model::WindowsAlignedFileReader;
+
+    let reader = WindowsAlignedFileReader::new(disk_index_file).unwrap();
+    diskann::storage::DiskGraphStorage::new(std::sync::Arc::new(reader)).unwrap()
+}
+
+/// The nodes written by the build read back through the aligned reader of each platform,
+/// the only part of the disk index that differs between the Linux and Windows backends
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+#[tokio::test]
+async fn disk_index_round_trips_through_the_platform_reader() {
+    use diskann::model::SECTOR_LEN;
+    use diskann::storage::DiskIndexStorage;
+
+    let mut rng = SmallRng::seed_from_u64(SEED);
+    let points = generate_points(NUM_POINTS, &mut rng);
+    let (data_path, index_prefix) = build_disk_index_over(&points, "round_trip", 1.0);
+
+    let storage =
+        DiskIndexStorage::<f32>::new(data_path.clone(), index_prefix.clone()).unwrap();
+    let layout_meta = storage.load_disk_layout_meta().unwrap();
+    assert_eq!(layout_meta.num_pts, NUM_POINTS);
+    assert_eq!(layout_meta.dim, DIM);
+
+    let mut adjacency_lists = Vec::new();
+    storage
+        .for_each_adjacency_list(|_, neighbors| {
+            adjacency_lists.push(neighbors.to_vec());
+            Ok(())
+        })
+        .unwrap();
+
+    let disk_graph = open_disk_graph(&storage.disk_index_file()).await;
+    let vector_len = DIM * std::mem::size_of::<f32>();
+    for (id, point) in points.iter().enumerate() {
+        let id = id as u32;
+        let offset = layout_meta.node_sector(id) * SECTOR_LEN
+            + layout_meta.node_offset_in_sector(id);
+        let node = disk_graph
+            .read_range(offset as u64, layout_meta.max_node_len)
+            .await
+            .unwrap();
+
+        let vector: Vec<f32> = node[..vector_len]
+            .chunks_exact(4)
+            .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect();
+        assert_eq!(&vector, point);
+
+        let num_neighbors =
+            u32::from_le_bytes(node[vector_len..vector_len + 4].try_into().unwrap()) as usize;
+        let neighbors: Vec<u32> = node[vector_len + 4..vector_len + 4 + num_neighbors * 4]
+            .chunks_exact(4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect();
+        assert!(!neighbors.is_empty());
+        assert_eq!(neighbors, adjacency_lists[id as usize]);
+    }
+
+    remove_disk_index_files(data_path, &index_prefix);
+}
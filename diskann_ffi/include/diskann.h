@@ -0,0 +1,82 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+
+/*
+ * C interface of the diskann in-memory index of f32 vectors.
+ *
+ * Every function returns DISKANN_OK on success. On failure, diskann_last_error returns the
+ * message of the failure on the calling thread, valid until the next failure on the thread.
+ */
+
+#ifndef DISKANN_H
+#define DISKANN_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Errors of the index have the stable code of their kind, see diskann_last_error_code */
+typedef enum DiskannStatus {
+    DISKANN_OK = 0,
+    DISKANN_INDEX_ERROR = 1,
+    DISKANN_INDEX_CONFIG_ERROR = 2,
+    DISKANN_TRY_FROM_INT_ERROR = 3,
+    DISKANN_IO_ERROR = 4,
+    DISKANN_MEMORY_ALLOC_LAYOUT_ERROR = 5,
+    DISKANN_LOCK_POISON_ERROR = 6,
+    DISKANN_DISK_IO_ALIGNMENT_ERROR = 7,
+    DISKANN_LOG_ERROR = 8,
+    DISKANN_PQ_ERROR = 9,
+    DISKANN_TRY_FROM_SLICE_ERROR = 10,
+    DISKANN_JOIN_ERROR = 11,
+    DISKANN_INVALID_ARGUMENT = 256,
+    DISKANN_PANIC = 257,
+} DiskannStatus;
+
+/* Opaque handle of an index */
+typedef struct DiskannIndex DiskannIndex;
+
+const char *diskann_last_error(void);
+/* Stable code of the kind of the last error, 0 for invalid arguments and panics */
+uint32_t diskann_last_error_code(void);
+bool diskann_last_error_retryable(void);
+
+/* metric is "l2", "l1", "cosine", "chebyshev", "hamming" or "tanimoto" */
+DiskannStatus diskann_index_create(const char *metric, size_t dim, size_t max_points,
+                                   uint32_t max_degree, uint32_t list_size, float alpha,
+                                   uint32_t num_threads, DiskannIndex **index);
+void diskann_index_free(DiskannIndex *index);
+
+/* vectors holds num_points * dim values, their ids are their positions */
+DiskannStatus diskann_index_build(DiskannIndex *index, const float *vectors, size_t num_points);
+DiskannStatus diskann_index_build_from_file(DiskannIndex *index, const char *path);
+
+DiskannStatus diskann_index_load(DiskannIndex *index, const char *path, size_t num_points);
+DiskannStatus diskann_index_save(DiskannIndex *index, const char *path);
+
+DiskannStatus diskann_index_insert(DiskannIndex *index, const float *vector, uint32_t *id);
+DiskannStatus diskann_index_delete(DiskannIndex *index, uint32_t id);
+
+/* ids and distances have room for k values, distances may be NULL */
+DiskannStatus diskann_index_search(DiskannIndex *index, const float *query, size_t k,
+                                   uint32_t list_size, uint32_t *ids, float *distances,
+                                   size_t *num_results);
+
+/* queries holds num_queries * dim values, searched in parallel. ids and distances have room
+ * for num_queries * k values, query i's from i * k, distances may be NULL. num_results has
+ * room for num_queries values. */
+DiskannStatus diskann_index_search_batch(DiskannIndex *index, const float *queries,
+                                         size_t num_queries, size_t k, uint32_t list_size,
+                                         uint32_t *ids, float *distances, size_t *num_results);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* DISKANN_H */
@@ -0,0 +1,588 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! C interface of the in-memory index, for services in other languages to link against.
+//!
+//! An index is an opaque handle created by `diskann_index_create` and released by
+//! `diskann_index_free`. Every function returns a `DiskannStatus`, `DISKANN_OK` on success,
+//! and on failure leaves a message that `diskann_last_error` returns on the same thread. A
+//! panic is caught at the boundary and reported as `DISKANN_PANIC` rather than unwinding into
+//! the caller. The declarations are in `include/diskann.h`. Vectors are f32.
+
+use std::cell::{Cell, RefCell};
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+
+use diskann::common::{ANNError, ErrorKind};
+use diskann::index::{create_inmem_index, ANNInmemIndex};
+use diskann::model::{IndexConfigurationBuilder, IndexWriteParametersBuilder, SearchResultFields};
+use diskann::utils::load_metadata_from_file;
+use rayon::prelude::*;
+use vector::Metric;
+
+/// Outcome of a call. The status of an ANNError is the code of its ErrorKind, under its
+/// context; failures at the boundary itself have codes above those of every kind.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiskannStatus {
+    /// The call succeeded
+    Ok = 0,
+
+    /// Index construction or search failed
+    IndexError = ErrorKind::Index as isize,
+
+    /// The configuration of the index is invalid
+    IndexConfigError = ErrorKind::IndexConfig as isize,
+
+    /// An integer didn't fit in its type
+    TryFromIntError = ErrorKind::TryFromInt as isize,
+
+    /// Reading or writing a file failed
+    IOError = ErrorKind::IO as isize,
+
+    /// Allocating memory failed
+    MemoryAllocLayoutError = ErrorKind::MemoryAllocLayout as isize,
+
+    /// A lock was poisoned by a thread that panicked holding it
+    LockPoisonError = ErrorKind::LockPoison as isize,
+
+    /// A disk index file isn't aligned
+    DiskIOAlignmentError = ErrorKind::DiskIOAlignment as isize,
+
+    /// Writing the log failed
+    LogError = ErrorKind::Log as isize,
+
+    /// PQ construction failed
+    PQError = ErrorKind::PQ as isize,
+
+    /// An array had the wrong length
+    TryFromSliceError = ErrorKind::TryFromSlice as isize,
+
+    /// A task or thread panicked or was cancelled
+    JoinError = ErrorKind::Join as isize,
+
+    /// A pointer was null, a string wasn't UTF-8 or an argument was out of range
+    InvalidArgument = 256,
+
+    /// The call panicked
+    Panic = 257,
+}
+
+impl From<ErrorKind> for DiskannStatus {
+    fn from(kind: ErrorKind) -> Self {
+        match kind {
+            ErrorKind::Index => DiskannStatus::IndexError,
+            ErrorKind::IndexConfig => DiskannStatus::IndexConfigError,
+            ErrorKind::TryFromInt => DiskannStatus::TryFromIntError,
+            ErrorKind::IO => DiskannStatus::IOError,
+            ErrorKind::MemoryAllocLayout => DiskannStatus::MemoryAllocLayoutError,
+            ErrorKind::LockPoison => DiskannStatus::LockPoisonError,
+            ErrorKind::DiskIOAlignment => DiskannStatus::DiskIOAlignmentError,
+            ErrorKind::Log => DiskannStatus::LogError,
+            ErrorKind::PQ => DiskannStatus::PQError,
+            ErrorKind::TryFromSlice => DiskannStatus::TryFromSliceError,
+            ErrorKind::Join => DiskannStatus::JoinError,
+        }
+    }
+}
+
+impl From<&ANNError> for DiskannStatus {
+    fn from(err: &ANNError) -> Self {
+        DiskannStatus::from(err.kind())
+    }
+}
+
+/// In-memory index of f32 vectors behind a handle
+pub struct DiskannIndex {
+    index: Box<dyn ANNInmemIndex<f32>>,
+    dim: usize,
+}
+
+impl std::fmt::Debug for DiskannIndex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DiskannIndex")
+            .field("dim", &self.dim)
+            .finish()
+    }
+}
+
+thread_local! {
+    /// Message of the last failure on the thread
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+
+    /// ANNError code and retryability of the last failure on the thread
+    static LAST_ERROR_CODE: Cell<(u32, bool)> = const { Cell::new((0, false)) };
+}
+
+/// Failure of a call, with its message
+struct FfiError {
+    status: DiskannStatus,
+    message: String,
+
+    /// ErrorKind code of the ANNError, 0 if the failure isn't one
+    code: u32,
+    retryable: bool,
+}
+
+impl From<ANNError> for FfiError {
+    fn from(err: ANNError) -> Self {
+        Self {
+            status: DiskannStatus::from(&err),
+            message: err.to_string(),
+            code: err.code(),
+            retryable: err.is_retryable(),
+        }
+    }
+}
+
+fn invalid_argument(message: &str) -> FfiError {
+    FfiError {
+        status: DiskannStatus::InvalidArgument,
+        message: message.to_string(),
+        code: 0,
+        retryable: false,
+    }
+}
+
+/// Run a call, turning its error or panic into a status and the last error message
+fn call(f: impl FnOnce() -> Result<(), FfiError>) -> DiskannStatus {
+    let err = match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => return DiskannStatus::Ok,
+        Ok(Err(err)) => err,
+        Err(payload) => FfiError {
+            status: DiskannStatus::Panic,
+            message: payload
+                .downcast_ref::<String>()
+                .map(String::as_str)
+                .or_else(|| payload.downcast_ref::<&str>().copied())
+                .unwrap_or("panic")
+                .to_string(),
+            code: 0,
+            retryable: false,
+        },
+    };
+
+    let message = CString::new(err.message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = message);
+    LAST_ERROR_CODE.with(|last_code| last_code.set((err.code, err.retryable)));
+    err.status
+}
+
+/// The index behind a handle
+///
+/// # Safety
+///
+/// index must be null or a handle from diskann_index_create not yet freed.
+unsafe fn index_ref<'a>(index: *mut DiskannIndex) -> Result<&'a mut DiskannIndex, FfiError> {
+    index
+        .as_mut()
+        .ok_or_else(|| invalid_argument("index is null"))
+}
+
+/// A string argument
+///
+/// # Safety
+///
+/// value must be null or a nul terminated string.
+unsafe fn str_arg<'a>(value: *const c_char, name: &str) -> Result<&'a str, FfiError> {
+    if value.is_null() {
+        return Err(invalid_argument(&format!("{} is null", name)));
+    }
+    CStr::from_ptr(value)
+        .to_str()
+        .map_err(|_| invalid_argument(&format!("{} isn't UTF-8", name)))
+}
+
+/// A slice argument of len values
+///
+/// # Safety
+///
+/// values must be null or point to len values.
+unsafe fn slice_arg<'a, T>(values: *const T, len: usize, name: &str) -> Result<&'a [T], FfiError> {
+    if values.is_null() {
+        return Err(invalid_argument(&format!("{} is null", name)));
+    }
+    Ok(std::slice::from_raw_parts(values, len))
+}
+
+/// Message of the last failed call on the calling thread, empty if none failed. The string
+/// stays valid until the next failure on the thread.
+#[no_mangle]
+pub extern "C" fn diskann_last_error() -> *const c_char {
+    LAST_ERROR.with(|last_error| last_error.borrow().as_ptr())
+}
+
+/// Stable ErrorKind code of the ANNError of the last failed call on the calling thread, 0 if
+/// none failed or the failure was an invalid argument or a panic
+#[no_mangle]
+pub extern "C" fn diskann_last_error_code() -> u32 {
+    LAST_ERROR_CODE.with(|last_code| last_code.get().0)
+}
+
+/// Whether the last failed call on the calling thread may succeed if made again
+#[no_mangle]
+pub extern "C" fn diskann_last_error_retryable() -> bool {
+    LAST_ERROR_CODE.with(|last_code| last_code.get().1)
+}
+
+/// Create an empty in-memory index of up to max_points vectors of dim values and store its
+/// handle in index. metric is the name of the distance, such as "l2" or "cosine".
+///
+/// # Safety
+///
+/// metric must be a nul terminated string and index a valid pointer to a handle.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn diskann_index_create(
+    metric: *const c_char,
+    dim: usize,
+    max_points: usize,
+    max_degree: u32,
+    list_size: u32,
+    alpha: f32,
+    num_threads: u32,
+    index: *mut *mut DiskannIndex,
+) -> DiskannStatus {
+    call(|| {
+        if index.is_null() {
+            return Err(invalid_argument("index is null"));
+        }
+        let metric: Metric = str_arg(metric, "metric")?
+            .parse()
+            .map_err(|_| invalid_argument("metric isn't the name of a distance"))?;
+
+        let index_write_parameters = IndexWriteParametersBuilder::new(list_size, max_degree)
+            .with_alpha(alpha)
+            .with_num_threads(num_threads)
+            .build();
+        let config = IndexConfigurationBuilder::new(metric, dim, max_points)
+            .with_index_write_parameters(index_write_parameters)
+            .build();
+        let handle = DiskannIndex {
+            index: create_inmem_index::<f32>(config)?,
+            dim,
+        };
+        *index = Box::into_raw(Box::new(handle));
+        Ok(())
+    })
+}
+
+/// Release an index. A null handle is ignored.
+///
+/// # Safety
+///
+/// index must be null or a handle from diskann_index_create not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn diskann_index_free(index: *mut DiskannIndex) {
+    if !index.is_null() {
+        drop(Box::from_raw(index));
+    }
+}
+
+/// Build the index from num_points vectors stored one after the other, their ids are their
+/// positions
+///
+/// # Safety
+///
+/// index must be a live handle and vectors point to num_points * dim values.
+#[no_mangle]
+pub unsafe extern "C" fn diskann_index_build(
+    index: *mut DiskannIndex,
+    vectors: *const f32,
+    num_points: usize,
+) -> DiskannStatus {
+    call(|| {
+        let index = index_ref(index)?;
+        let rows = slice_arg(vectors, num_points * index.dim, "vectors")?;
+        Ok(index.index.build_from_rows(rows)?)
+    })
+}
+
+/// Build the index from all the vectors of a bin file
+///
+/// # Safety
+///
+/// index must be a live handle and path a nul terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn diskann_index_build_from_file(
+    index: *mut DiskannIndex,
+    path: *const c_char,
+) -> DiskannStatus {
+    call(|| {
+        let index = index_ref(index)?;
+        let path = str_arg(path, "path")?;
+        let (num_points, _) = load_metadata_from_file(path).map_err(ANNError::from)?;
+        Ok(index.index.build(path, num_points)?)
+    })
+}
+
+/// Load an index of num_points points saved at path
+///
+/// # Safety
+///
+/// index must be a live handle and path a nul terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn diskann_index_load(
+    index: *mut DiskannIndex,
+    path: *const c_char,
+    num_points: usize,
+) -> DiskannStatus {
+    call(|| {
+        let index = index_ref(index)?;
+        let path = str_arg(path, "path")?;
+        Ok(index.index.load(path, num_points)?)
+    })
+}
+
+/// Save the index at path
+///
+/// # Safety
+///
+/// index must be a live handle and path a nul terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn diskann_index_save(
+    index: *mut DiskannIndex,
+    path: *const c_char,
+) -> DiskannStatus {
+    call(|| {
+        let index = index_ref(index)?;
+        let path = str_arg(path, "path")?;
+        Ok(index.index.save(path)?)
+    })
+}
+
+/// Insert a vector of dim values and store its id in id. Other threads may search or insert
+/// at the same time.
+///
+/// # Safety
+///
+/// index must be a live handle, vector point to dim values and id be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn diskann_index_insert(
+    index: *mut DiskannIndex,
+    vector: *const f32,
+    id: *mut u32,
+) -> DiskannStatus {
+    call(|| {
+        let index = index_ref(index)?;
+        let vector = slice_arg(vector, index.dim, "vector")?;
+        if id.is_null() {
+            return Err(invalid_argument("id is null"));
+        }
+        *id = index.index.insert_point(vector)?;
+        Ok(())
+    })
+}
+
+/// Delete the point of id id, searches started after the call don't return it
+///
+/// # Safety
+///
+/// index must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn diskann_index_delete(index: *mut DiskannIndex, id: u32) -> DiskannStatus {
+    call(|| {
+        let index = index_ref(index)?;
+        Ok(index.index.delete_point(id)?)
+    })
+}
+
+/// Search the k nearest neighbors of a query of dim values with a search list of list_size.
+/// Writes their ids, and their distances unless distances is null, nearest first, and the
+/// number of neighbors found in num_results.
+///
+/// # Safety
+///
+/// index must be a live handle, query point to dim values, ids and distances unless null
+/// to room for k values, and num_results be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn diskann_index_search(
+    index: *mut DiskannIndex,
+    query: *const f32,
+    k: usize,
+    list_size: u32,
+    ids: *mut u32,
+    distances: *mut f32,
+    num_results: *mut usize,
+) -> DiskannStatus {
+    call(|| {
+        let index = index_ref(index)?;
+        let query = slice_arg(query, index.dim, "query")?;
+        if ids.is_null() || num_results.is_null() {
+            return Err(invalid_argument("ids and num_results must not be null"));
+        }
+
+        let (results, _) =
+            index
+                .index
+                .search_with_details(query, k, list_size, SearchResultFields::NONE)?;
+        for (i, result) in results.iter().take(k).enumerate() {
+            *ids.add(i) = result.id;
+            if !distances.is_null() {
+                *distances.add(i) = result.distance;
+            }
+        }
+        *num_results = results.len().min(k);
+        Ok(())
+    })
+}
+
+/// Search the k nearest neighbors of num_queries queries of dim values stored one after the
+/// other, in parallel, with a search list of list_size. Writes the ids of query i, and their
+/// distances unless distances is null, nearest first from i * k, and the number of neighbors
+/// found in num_results[i].
+///
+/// # Safety
+///
+/// index must be a live handle, queries point to num_queries * dim values, ids and distances
+/// unless null to room for num_queries * k values, and num_results to room for num_queries
+/// values.
+#[no_mangle]
+pub unsafe extern "C" fn diskann_index_search_batch(
+    index: *mut DiskannIndex,
+    queries: *const f32,
+    num_queries: usize,
+    k: usize,
+    list_size: u32,
+    ids: *mut u32,
+    distances: *mut f32,
+    num_results: *mut usize,
+) -> DiskannStatus {
+    call(|| {
+        let index = index_ref(index)?;
+        let len = num_queries
+            .checked_mul(index.dim)
+            .ok_or_else(|| invalid_argument("num_queries * dim overflows"))?;
+        let queries = slice_arg(queries, len, "queries")?;
+        if ids.is_null() || num_results.is_null() {
+            return Err(invalid_argument("ids and num_results must not be null"));
+        }
+
+        let batch_results = queries
+            .par_chunks(index.dim)
+            .map(|query| {
+                index
+                    .index
+                    .search_with_details(query, k, list_size, SearchResultFields::NONE)
+                    .map(|(results, _)| results)
+            })
+            .collect::<Result<Vec<_>, ANNError>>()?;
+        for (query, results) in batch_results.iter().enumerate() {
+            for (i, result) in results.iter().take(k).enumerate() {
+                *ids.add(query * k + i) = result.id;
+                if !distances.is_null() {
+                    *distances.add(query * k + i) = result.distance;
+                }
+            }
+            *num_results.add(query) = results.len().min(k);
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod ffi_test {
+    use std::ptr;
+
+    use super::*;
+
+    fn last_error() -> String {
+        unsafe { CStr::from_ptr(diskann_last_error()) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn invalid_arguments_report_status_and_message() {
+        let mut index = ptr::null_mut();
+        let status = unsafe {
+            diskann_index_create(c"manhattan".as_ptr(), 8, 100, 16, 50, 1.2, 1, &mut index)
+        };
+        assert_eq!(status, DiskannStatus::InvalidArgument);
+        assert!(last_error().contains("metric"));
+        assert!(index.is_null());
+
+        let status = unsafe { diskann_index_save(ptr::null_mut(), c"index".as_ptr()) };
+        assert_eq!(status, DiskannStatus::InvalidArgument);
+        assert_eq!(last_error(), "index is null");
+        assert_eq!(diskann_last_error_code(), 0);
+
+        // Errors of the index keep the status of their ANNError variant
+        let status =
+            unsafe { diskann_index_create(c"l2".as_ptr(), 2000, 100, 16, 50, 1.2, 1, &mut index) };
+        assert_eq!(status, DiskannStatus::IndexError);
+        assert!(last_error().contains("Invalid dimension"));
+        assert_eq!(diskann_last_error_code(), ErrorKind::Index.code());
+        assert!(!diskann_last_error_retryable());
+        unsafe { diskann_index_free(index) };
+    }
+
+    #[test]
+    fn statuses_are_the_codes_of_error_kinds() {
+        for kind in ErrorKind::ALL {
+            assert_eq!(DiskannStatus::from(kind) as u32, kind.code());
+        }
+        assert!(DiskannStatus::InvalidArgument as u32 > ErrorKind::Join.code());
+    }
+
+    #[test]
+    fn build_search_and_insert_through_handles() {
+        // Points on a 3-d grid padded to 8 dimensions, so each one is its own nearest neighbor
+        let vectors: Vec<f32> = (0..125)
+            .flat_map(|i| {
+                let mut vector = vec![(i % 5) as f32, ((i / 5) % 5) as f32, (i / 25) as f32];
+                vector.resize(8, 0.5);
+                vector
+            })
+            .collect();
+
+        let mut index = ptr::null_mut();
+        unsafe {
+            let status = diskann_index_create(c"l2".as_ptr(), 8, 200, 16, 50, 1.2, 1, &mut index);
+            assert_eq!(status, DiskannStatus::Ok);
+            assert_eq!(
+                diskann_index_build(index, vectors.as_ptr(), 100),
+                DiskannStatus::Ok
+            );
+
+            let mut id = 0;
+            let status = diskann_index_insert(index, vectors[800..].as_ptr(), &mut id);
+            assert_eq!((status, id), (DiskannStatus::Ok, 100));
+
+            let (mut ids, mut distances, mut num_results) = ([0u32; 3], [0f32; 3], 0);
+            let status = diskann_index_search(
+                index,
+                vectors[800..].as_ptr(),
+                3,
+                50,
+                ids.as_mut_ptr(),
+                distances.as_mut_ptr(),
+                &mut num_results,
+            );
+            assert_eq!((status, num_results), (DiskannStatus::Ok, 3));
+            assert_eq!((ids[0], distances[0]), (100, 0.0));
+            assert!(distances[1] >= distances[0]);
+
+            // Each query of a batch finds its own point first
+            let (mut ids, mut num_results) = ([0u32; 4 * 3], [0usize; 4]);
+            let status = diskann_index_search_batch(
+                index,
+                vectors[8 * 10..8 * 14].as_ptr(),
+                4,
+                3,
+                50,
+                ids.as_mut_ptr(),
+                ptr::null_mut(),
+                num_results.as_mut_ptr(),
+            );
+            assert_eq!((status, num_results), (DiskannStatus::Ok, [3; 4]));
+            let first_ids: Vec<u32> = ids.chunks(3).map(|ids| ids[0]).collect();
+            assert_eq!(first_ids, vec![10, 11, 12, 13]);
+
+            diskann_index_free(index);
+        }
+    }
+}
@@ -0,0 +1,35 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Single dependency for the DiskANN crates of this workspace, at one version.
+//!
+//! The crates are re-exported whole, so `diskannrs::diskann::index::create_inmem_index` is
+//! `diskann::index::create_inmem_index`. The most used types are also re-exported at the root.
+//!
+//! Features:
+//! - `simd-native`: pick the distance kernels from the target features the build was compiled
+//!   with, e.g. `-C target-cpu=native`, instead of detecting the CPU at run time.
+//! - `io-uring`: read the sectors of a disk index search in one io_uring batch per round trip
+//!   on Linux, instead of one blocking read each.
+//! - `server`: the request handlers of the search service, as `diskannrs::server`.
+//! - `ffi`: the C interface of the in-memory index, as `diskannrs::ffi`.
+
+pub use diskann;
+pub use logger;
+pub use platform;
+pub use vector;
+
+#[cfg(feature = "ffi")]
+pub use diskann_ffi as ffi;
+#[cfg(feature = "server")]
+pub use search_service as server;
+
+pub use diskann::common::{ANNError, ANNResult};
+pub use diskann::index::{create_inmem_index, ANNInmemIndex, DiskIndex};
+pub use diskann::model::{IndexConfiguration, IndexWriteParametersBuilder, SearchParams};
+#[cfg(target_arch = "x86_64")]
+pub use vector::simd_level;
+pub use vector::{kernel_report, kernel_selections, Metric, SimdLevel};
@@ -1,10 +1,20 @@
+#[cfg(feature = "protobuf")]
 use std::env;
+#[cfg(all(feature = "protobuf", target_os = "linux"))]
 use std::path::PathBuf;
 
-#[cfg(target_os = "windows")]
+#[cfg(all(feature = "protobuf", target_os = "windows"))]
 extern crate vcpkg;
 
 fn main() {
+    // Without the protobuf feature the log messages are plain Rust types, there is nothing to
+    // generate and protoc isn't needed
+    #[cfg(feature = "protobuf")]
+    compile_protos();
+}
+
+#[cfg(feature = "protobuf")]
+fn compile_protos() {
     #[cfg(target_os = "windows")]
     {
         let protopkg = vcpkg::find_package("protobuf").unwrap();
@@ -43,4 +53,4 @@ fn main() {
     }
 
     prost_build::compile_protos(&["src/indexlog.proto"], &["src/"]).unwrap();
-}
\ No newline at end of file
+}
@@ -7,11 +7,7 @@
     warn(clippy::panic, clippy::unwrap_used, clippy::expect_used)
 )]
 
-pub mod logger {
-    pub mod indexlog {
-        include!(concat!(env!("OUT_DIR"), "/diskann_logger.rs"));
-    }
-}
+pub mod logger;
 
 pub mod error_logger;
 pub mod log_error;
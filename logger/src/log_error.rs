@@ -21,6 +21,7 @@ pub enum LogError {
     LockPoisonError { err: String },
 
     /// Failed to create EtwPublisher
+    #[cfg(all(target_os = "windows", feature = "etw"))]
     #[error("EtwProviderError: {err:?}")]
     ETWProviderError { err: win_etw_provider::Error },
 }
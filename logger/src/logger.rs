@@ -0,0 +1,11 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#[cfg(feature = "protobuf")]
+pub mod indexlog {
+    include!(concat!(env!("OUT_DIR"), "/diskann_logger.rs"));
+}
+
+#[cfg(not(feature = "protobuf"))]
+pub mod indexlog;
@@ -0,0 +1,128 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+//! The messages of indexlog.proto as plain Rust types, used unless the protobuf feature is on.
+//! They have the fields and enum values prost generates from the proto, so the rest of the
+//! crate and its users build the same way with either.
+
+/// Level of a log message
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[repr(i32)]
+pub enum LogLevel {
+    #[default]
+    Unspecified = 0,
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+    Trace = 5,
+}
+
+impl LogLevel {
+    /// The level with the given value, None if the value isn't a level
+    pub fn from_i32(value: i32) -> Option<Self> {
+        match value {
+            0 => Some(LogLevel::Unspecified),
+            1 => Some(LogLevel::Error),
+            2 => Some(LogLevel::Warn),
+            3 => Some(LogLevel::Info),
+            4 => Some(LogLevel::Debug),
+            5 => Some(LogLevel::Trace),
+            _ => None,
+        }
+    }
+}
+
+/// Step of a disk index build
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[repr(i32)]
+pub enum DiskIndexConstructionCheckpoint {
+    #[default]
+    None = 0,
+    PqConstruction = 1,
+    InmemIndexBuild = 2,
+    DiskLayout = 3,
+}
+
+impl DiskIndexConstructionCheckpoint {
+    /// The checkpoint with the given value, None if the value isn't a checkpoint
+    pub fn from_i32(value: i32) -> Option<Self> {
+        match value {
+            0 => Some(DiskIndexConstructionCheckpoint::None),
+            1 => Some(DiskIndexConstructionCheckpoint::PqConstruction),
+            2 => Some(DiskIndexConstructionCheckpoint::InmemIndexBuild),
+            3 => Some(DiskIndexConstructionCheckpoint::DiskLayout),
+            _ => None,
+        }
+    }
+}
+
+/// Progress of an in-memory index build
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IndexConstructionLog {
+    pub percentage_complete: f32,
+    pub time_spent_in_seconds: f32,
+    pub g_cycles_spent: f32,
+    pub log_level: i32,
+}
+
+/// Completion of a step of a disk index build
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DiskIndexConstructionLog {
+    pub checkpoint: i32,
+    pub time_spent_in_seconds: f32,
+    pub g_cycles_spent: f32,
+    pub log_level: i32,
+}
+
+/// Line logged through the log crate
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TraceLog {
+    pub log_line: String,
+    pub log_level: i32,
+}
+
+/// Error message
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ErrorLog {
+    pub error_message: String,
+    pub log_level: i32,
+}
+
+/// A log message, with one of its fields set
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Log {
+    pub index_construction_log: Option<IndexConstructionLog>,
+    pub disk_index_construction_log: Option<DiskIndexConstructionLog>,
+    pub error_log: Option<ErrorLog>,
+    pub trace_log: Option<TraceLog>,
+}
+
+#[cfg(test)]
+mod indexlog_test {
+    use super::*;
+
+    #[test]
+    fn enums_round_trip_through_i32() {
+        for level in [
+            LogLevel::Unspecified,
+            LogLevel::Error,
+            LogLevel::Warn,
+            LogLevel::Info,
+            LogLevel::Debug,
+            LogLevel::Trace,
+        ] {
+            assert_eq!(LogLevel::from_i32(level as i32), Some(level));
+        }
+        assert_eq!(LogLevel::from_i32(6), None);
+
+        assert_eq!(
+            DiskIndexConstructionCheckpoint::from_i32(
+                DiskIndexConstructionCheckpoint::DiskLayout as i32
+            ),
+            Some(DiskIndexConstructionCheckpoint::DiskLayout)
+        );
+        assert_eq!(DiskIndexConstructionCheckpoint::from_i32(-1), None);
+    }
+}
@@ -11,24 +11,40 @@ use std::sync::mpsc::{self, Sender};
 use std::sync::Mutex;
 use std::thread;
 
+use once_cell::sync::Lazy;
+#[cfg(all(target_os = "windows", feature = "etw"))]
 use win_etw_macros::trace_logging_provider;
 
 trait MessagePublisher {
     fn publish(&self, log_level: LogLevel, message: &str);
 }
 
+/// Publisher writing the logs to stdout, one line per message
+#[cfg(not(all(target_os = "windows", feature = "etw")))]
+struct StdoutPublisher;
+
+#[cfg(not(all(target_os = "windows", feature = "etw")))]
+impl MessagePublisher for StdoutPublisher {
+    fn publish(&self, log_level: LogLevel, message: &str) {
+        println!("[{:?}] {}", log_level, message);
+    }
+}
+
 // ETW provider - the GUID specified here is that of the default provider for Geneva Metric Extensions
 // We are just using it as a placeholder until we have a version of OpenTelemetry exporter for Rust
+#[cfg(all(target_os = "windows", feature = "etw"))]
 #[trace_logging_provider(guid = "edc24920-e004-40f6-a8e1-0e6e48f39d84")]
 trait EtwTraceProvider {
     fn write(msg: &str);
 }
 
+#[cfg(all(target_os = "windows", feature = "etw"))]
 struct EtwPublisher {
     provider: EtwTraceProvider,
     publish_to_stdout: bool,
 }
 
+#[cfg(all(target_os = "windows", feature = "etw"))]
 impl EtwPublisher {
     pub fn new() -> Result<Self, win_etw_provider::Error> {
         let provider = EtwTraceProvider::new();
@@ -39,6 +55,7 @@ impl EtwPublisher {
     }
 }
 
+#[cfg(all(target_os = "windows", feature = "etw"))]
 fn log_level_to_etw(level: LogLevel) -> win_etw_provider::Level {
     match level {
         LogLevel::Error => win_etw_provider::Level::ERROR,
@@ -62,6 +79,7 @@ fn i32_to_log_level(value: i32) -> LogLevel {
     }
 }
 
+#[cfg(all(target_os = "windows", feature = "etw"))]
 impl MessagePublisher for EtwPublisher {
     fn publish(&self, log_level: LogLevel, message: &str) {
         let options = win_etw_provider::EventOptions {
@@ -135,26 +153,20 @@ impl MessageProcessor {
     }
 }
 
-lazy_static::lazy_static! {
-    /// Singleton logger.
-    static ref PROCESSOR: MessageProcessor = {
+/// Singleton logger.
+static PROCESSOR: Lazy<MessageProcessor> = Lazy::new(MessageProcessor::start_processing);
 
-        MessageProcessor::start_processing()
-    };
-}
-
-lazy_static::lazy_static! {
-    /// Singleton publisher.
-    static ref PUBLISHER: Result<EtwPublisher, win_etw_provider::Error> = {
-        EtwPublisher::new()
-    };
-}
+/// Singleton publisher.
+#[cfg(all(target_os = "windows", feature = "etw"))]
+static PUBLISHER: Lazy<Result<EtwPublisher, win_etw_provider::Error>> =
+    Lazy::new(EtwPublisher::new);
 
 /// Send a message to the logging system.
 pub fn send_log(message: Log) -> Result<(), LogError> {
     PROCESSOR.log(message)
 }
 
+#[cfg(all(target_os = "windows", feature = "etw"))]
 fn publish(log_level: LogLevel, message: &str) -> Result<(), LogError> {
     match *PUBLISHER {
         Ok(ref etw_publisher) => {
@@ -165,3 +177,8 @@ fn publish(log_level: LogLevel, message: &str) -> Result<(), LogError> {
     }
 }
 
+#[cfg(not(all(target_os = "windows", feature = "etw")))]
+fn publish(log_level: LogLevel, message: &str) -> Result<(), LogError> {
+    StdoutPublisher.publish(log_level, message);
+    Ok(())
+}
@@ -0,0 +1,302 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+//! Cross-process advisory file locks.
+//! The lock is held on a dedicated lock file and released when the `FileLock` is dropped
+//! (or the process exits). Advisory means only cooperating processes are blocked.
+//! A transient lock also removes its lock file when the last holder releases it.
+
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[cfg(unix)]
+use std::os::unix::{fs::MetadataExt, io::AsRawFd};
+
+#[cfg(target_os = "windows")]
+use std::os::windows::io::AsRawHandle;
+
+#[cfg(target_os = "windows")]
+use winapi::{
+    shared::winerror::ERROR_LOCK_VIOLATION,
+    um::{
+        fileapi::{LockFileEx, UnlockFileEx},
+        minwinbase::{LOCKFILE_EXCLUSIVE_LOCK, LOCKFILE_FAIL_IMMEDIATELY, OVERLAPPED},
+    },
+};
+
+/// Lock mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockMode {
+    /// Any number of readers may hold the lock at once
+    Shared,
+
+    /// A single writer holds the lock
+    Exclusive,
+}
+
+/// An advisory lock held on a file until dropped
+#[derive(Debug)]
+pub struct FileLock {
+    file: File,
+    mode: LockMode,
+
+    /// Lock file removed by the last holder, for a transient lock
+    transient_path: Option<PathBuf>,
+}
+
+impl FileLock {
+    /// Block until the lock on `path` is acquired. The lock file is created if it doesn't exist.
+    pub fn lock<P: AsRef<Path>>(path: P, mode: LockMode) -> io::Result<Self> {
+        let file = open_lock_file(path.as_ref())?;
+        lock_file(&file, mode, true)?;
+        Ok(Self {
+            file,
+            mode,
+            transient_path: None,
+        })
+    }
+
+    /// Try to acquire the lock on `path` without blocking.
+    /// Returns None if another holder conflicts with the requested mode.
+    pub fn try_lock<P: AsRef<Path>>(path: P, mode: LockMode) -> io::Result<Option<Self>> {
+        let file = open_lock_file(path.as_ref())?;
+        match lock_file(&file, mode, false) {
+            Ok(()) => Ok(Some(Self {
+                file,
+                mode,
+                transient_path: None,
+            })),
+            Err(err) if is_contended(&err) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Try to acquire the lock on `path` without blocking, like try_lock, and remove the lock
+    /// file when the last holder releases it.
+    pub fn try_lock_transient<P: AsRef<Path>>(path: P, mode: LockMode) -> io::Result<Option<Self>> {
+        let path = path.as_ref();
+        loop {
+            let file = open_lock_file(path)?;
+            match lock_file(&file, mode, false) {
+                Ok(()) => (),
+                Err(err) if is_contended(&err) => return Ok(None),
+                Err(err) => return Err(err),
+            }
+
+            // The file was removed by its last holder between the open and the lock, so it no
+            // longer guards the path: lock the file now at the path instead
+            if is_file_at(&file, path)? {
+                return Ok(Some(Self {
+                    file,
+                    mode,
+                    transient_path: Some(path.to_path_buf()),
+                }));
+            }
+            unlock_file(&file)?;
+        }
+    }
+
+    /// Mode the lock was acquired with
+    pub fn mode(&self) -> LockMode {
+        self.mode
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        if let Err(err) = self.release() {
+            log::warn!("Error when releasing FileLock: {:?}", err);
+        }
+    }
+}
+
+impl FileLock {
+    fn release(&self) -> io::Result<()> {
+        let path = match &self.transient_path {
+            Some(path) => path,
+            None => return unlock_file(&self.file),
+        };
+
+        // A shared holder is the last one when it can take the lock exclusively
+        if self.mode == LockMode::Shared {
+            unlock_file(&self.file)?;
+            match lock_file(&self.file, LockMode::Exclusive, false) {
+                Ok(()) => (),
+                Err(err) if is_contended(&err) => return Ok(()),
+                Err(err) => return Err(err),
+            }
+        }
+
+        // Removed while locked, so a holder of the path's file never sees it go
+        if is_file_at(&self.file, path)? {
+            fs::remove_file(path)?;
+        }
+        unlock_file(&self.file)
+    }
+}
+
+fn open_lock_file(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+}
+
+#[cfg(unix)]
+fn lock_file(file: &File, mode: LockMode, blocking: bool) -> io::Result<()> {
+    let mut operation = match mode {
+        LockMode::Shared => libc::LOCK_SH,
+        LockMode::Exclusive => libc::LOCK_EX,
+    };
+    if !blocking {
+        operation |= libc::LOCK_NB;
+    }
+
+    loop {
+        if unsafe { libc::flock(file.as_raw_fd(), operation) } == 0 {
+            return Ok(());
+        }
+
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn unlock_file(file: &File) -> io::Result<()> {
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_UN) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(unix)]
+fn is_contended(err: &io::Error) -> bool {
+    err.raw_os_error() == Some(libc::EWOULDBLOCK)
+}
+
+#[cfg(unix)]
+fn is_file_at(file: &File, path: &Path) -> io::Result<bool> {
+    let locked = file.metadata()?;
+    match fs::metadata(path) {
+        Ok(current) => Ok(locked.dev() == current.dev() && locked.ino() == current.ino()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn lock_file(file: &File, mode: LockMode, blocking: bool) -> io::Result<()> {
+    let mut flags = match mode {
+        LockMode::Shared => 0,
+        LockMode::Exclusive => LOCKFILE_EXCLUSIVE_LOCK,
+    };
+    if !blocking {
+        flags |= LOCKFILE_FAIL_IMMEDIATELY;
+    }
+
+    // Lock the whole file range
+    let result = unsafe {
+        let mut overlapped: OVERLAPPED = std::mem::zeroed();
+        LockFileEx(file.as_raw_handle() as _, flags, 0, u32::MAX, u32::MAX, &mut overlapped)
+    };
+
+    if result != 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn unlock_file(file: &File) -> io::Result<()> {
+    let result = unsafe {
+        let mut overlapped: OVERLAPPED = std::mem::zeroed();
+        UnlockFileEx(file.as_raw_handle() as _, 0, u32::MAX, u32::MAX, &mut overlapped)
+    };
+
+    if result != 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn is_contended(err: &io::Error) -> bool {
+    err.raw_os_error() == Some(ERROR_LOCK_VIOLATION as i32)
+}
+
+/// A removed file can't be opened again until its last handle is closed, so the file opened
+/// at the path is always the one there
+#[cfg(target_os = "windows")]
+fn is_file_at(_file: &File, _path: &Path) -> io::Result<bool> {
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exclusive_lock_excludes_other_holders() {
+        let lock_path = "file_lock_exclusive_test.lock";
+        {
+            let lock = FileLock::try_lock(lock_path, LockMode::Exclusive).unwrap();
+            assert!(lock.is_some());
+            assert_eq!(lock.as_ref().map(FileLock::mode), Some(LockMode::Exclusive));
+
+            assert!(FileLock::try_lock(lock_path, LockMode::Exclusive).unwrap().is_none());
+            assert!(FileLock::try_lock(lock_path, LockMode::Shared).unwrap().is_none());
+        }
+
+        // Released on drop
+        assert!(FileLock::try_lock(lock_path, LockMode::Exclusive).unwrap().is_some());
+        std::fs::remove_file(lock_path).unwrap();
+    }
+
+    #[test]
+    fn shared_locks_coexist() {
+        let lock_path = "file_lock_shared_test.lock";
+        {
+            let _first = FileLock::lock(lock_path, LockMode::Shared).unwrap();
+            let second = FileLock::try_lock(lock_path, LockMode::Shared).unwrap();
+            assert!(second.is_some());
+
+            assert!(FileLock::try_lock(lock_path, LockMode::Exclusive).unwrap().is_none());
+        }
+        std::fs::remove_file(lock_path).unwrap();
+    }
+
+    #[test]
+    fn transient_lock_file_is_removed_by_the_last_holder() {
+        let lock_path = "file_lock_transient_test.lock";
+        {
+            let first = FileLock::try_lock_transient(lock_path, LockMode::Shared).unwrap();
+            let second = FileLock::try_lock_transient(lock_path, LockMode::Shared).unwrap();
+            assert!(first.is_some() && second.is_some());
+            assert!(FileLock::try_lock_transient(lock_path, LockMode::Exclusive)
+                .unwrap()
+                .is_none());
+
+            drop(first);
+            assert!(Path::new(lock_path).exists());
+        }
+        assert!(!Path::new(lock_path).exists());
+
+        {
+            let _lock = FileLock::try_lock_transient(lock_path, LockMode::Exclusive).unwrap();
+            assert!(FileLock::try_lock_transient(lock_path, LockMode::Shared)
+                .unwrap()
+                .is_none());
+        }
+        assert!(!Path::new(lock_path).exists());
+    }
+}
@@ -0,0 +1,356 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+//! Batched positional reads through io_uring on Linux.
+//! A batch of reads is submitted with one system call and waited on with as few as the
+//! completions need, instead of one pread per read. The ring is set up with the raw system
+//! calls and is not shared between threads.
+
+use std::fs::File;
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+const IORING_OFF_SQ_RING: libc::off_t = 0;
+const IORING_OFF_CQ_RING: libc::off_t = 0x8000000;
+const IORING_OFF_SQES: libc::off_t = 0x10000000;
+const IORING_ENTER_GETEVENTS: libc::c_uint = 1;
+const IORING_OP_READ: u8 = 22;
+
+#[repr(C)]
+#[derive(Default)]
+struct SqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct CqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+/// struct io_uring_params
+#[repr(C)]
+#[derive(Default)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqringOffsets,
+    cq_off: CqringOffsets,
+}
+
+/// struct io_uring_sqe, with the fields of a read
+#[repr(C)]
+struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    rw_flags: u32,
+    user_data: u64,
+    pad: [u64; 3],
+}
+
+/// struct io_uring_cqe
+#[repr(C)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+/// A memory mapped region of the ring, unmapped when dropped
+struct Mapping {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl Mapping {
+    fn new(fd: &OwnedFd, len: usize, offset: libc::off_t) -> io::Result<Self> {
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_POPULATE,
+                fd.as_raw_fd(),
+                offset,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            ptr: ptr as *mut u8,
+            len,
+        })
+    }
+
+    /// Pointer to the value of type T at offset bytes into the mapping
+    fn at<T>(&self, offset: u32) -> *mut T {
+        unsafe { self.ptr.add(offset as usize) as *mut T }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr as *mut libc::c_void, self.len) };
+    }
+}
+
+/// An io_uring instance reading files in batches of up to its number of entries
+pub struct IoUring {
+    sq_ring: Mapping,
+    cq_ring: Mapping,
+    sqes: Mapping,
+    params: Params,
+
+    // Declared last so the mappings are gone before the ring is closed
+    fd: OwnedFd,
+}
+
+// The ring is only reached through &mut self
+unsafe impl Send for IoUring {}
+
+impl std::fmt::Debug for IoUring {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IoUring")
+            .field("fd", &self.fd)
+            .field("entries", &self.params.sq_entries)
+            .finish()
+    }
+}
+
+impl IoUring {
+    /// Set up a ring of at least entries submission entries. Fails where io_uring is missing
+    /// or disabled, e.g. by kernel.io_uring_disabled or a seccomp filter.
+    pub fn new(entries: u32) -> io::Result<Self> {
+        let mut params = Params::default();
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_setup,
+                entries as libc::c_uint,
+                &mut params as *mut Params,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd as i32) };
+
+        let sq_ring_len = params.sq_off.array as usize + params.sq_entries as usize * 4;
+        let cq_ring_len =
+            params.cq_off.cqes as usize + params.cq_entries as usize * std::mem::size_of::<Cqe>();
+        let sqes_len = params.sq_entries as usize * std::mem::size_of::<Sqe>();
+        Ok(Self {
+            sq_ring: Mapping::new(&fd, sq_ring_len, IORING_OFF_SQ_RING)?,
+            cq_ring: Mapping::new(&fd, cq_ring_len, IORING_OFF_CQ_RING)?,
+            sqes: Mapping::new(&fd, sqes_len, IORING_OFF_SQES)?,
+            params,
+            fd,
+        })
+    }
+
+    /// Fill each buffer with the bytes of file at its offset, failing like read_exact_at when
+    /// the file ends before a buffer is full
+    pub fn read_exact_at(&mut self, file: &File, reads: &mut [(u64, &mut [u8])]) -> io::Result<()> {
+        for batch in reads.chunks_mut(self.params.sq_entries as usize) {
+            self.read_batch(file, batch)?;
+        }
+        Ok(())
+    }
+
+    fn read_batch(&mut self, file: &File, reads: &mut [(u64, &mut [u8])]) -> io::Result<()> {
+        let sq_mask = unsafe { *self.sq_ring.at::<u32>(self.params.sq_off.ring_mask) };
+        let sq_array = self.sq_ring.at::<u32>(self.params.sq_off.array);
+        let sq_tail = unsafe { &*self.sq_ring.at::<AtomicU32>(self.params.sq_off.tail) };
+
+        // Only this thread moves the tail, the kernel reads the entries up to it
+        let first = sq_tail.load(Ordering::Relaxed);
+        let mut tail = first;
+        for (i, (offset, buf)) in reads.iter_mut().enumerate() {
+            let index = tail & sq_mask;
+            unsafe {
+                self.sqes.at::<Sqe>(0).add(index as usize).write(Sqe {
+                    opcode: IORING_OP_READ,
+                    flags: 0,
+                    ioprio: 0,
+                    fd: file.as_raw_fd(),
+                    off: *offset,
+                    addr: buf.as_mut_ptr() as u64,
+                    len: buf.len() as u32,
+                    rw_flags: 0,
+                    user_data: i as u64,
+                    pad: [0; 3],
+                });
+                *sq_array.add(index as usize) = index;
+            }
+            tail = tail.wrapping_add(1);
+        }
+        sq_tail.store(tail, Ordering::Release);
+
+        // Interrupted calls are made again, any other error or a call that consumes no entry
+        // stops the submission
+        let mut submitted = 0;
+        let mut submit_error = None;
+        while submitted < reads.len() {
+            let err = match self.enter((reads.len() - submitted) as u32, 0, 0) {
+                Ok(0) => io::Error::other("io_uring consumed none of the submitted reads"),
+                Ok(count) => {
+                    submitted += count;
+                    continue;
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => err,
+            };
+            // Take back the entries the kernel didn't consume
+            sq_tail.store(first.wrapping_add(submitted as u32), Ordering::Release);
+            submit_error = Some(err);
+            break;
+        }
+
+        // The kernel writes into the buffers until their completions are reaped, so every
+        // submitted read is waited on before returning. Each wait blocks until a completion
+        // arrives; only a ring that can no longer be entered fails it.
+        let mut results = vec![0i32; reads.len()];
+        let mut completed = 0;
+        while completed < submitted {
+            completed += self.reap(&mut results);
+            if completed < submitted {
+                self.enter(0, 1, IORING_ENTER_GETEVENTS)
+                    .or_else(|err| match err.kind() {
+                        io::ErrorKind::Interrupted => Ok(0),
+                        _ => Err(err),
+                    })?;
+            }
+        }
+        if let Some(err) = submit_error {
+            return Err(err);
+        }
+
+        for ((offset, buf), res) in reads.iter_mut().zip(results) {
+            if res < 0 {
+                return Err(io::Error::from_raw_os_error(-res));
+            }
+            // A short read is finished with pread, which fails at the end of the file
+            let read = res as usize;
+            if read < buf.len() {
+                file.read_exact_at(&mut buf[read..], *offset + read as u64)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Store the results of the completions waiting in the ring, returns how many there were
+    fn reap(&mut self, results: &mut [i32]) -> usize {
+        let cq_off = &self.params.cq_off;
+        let cq_mask = unsafe { *self.cq_ring.at::<u32>(cq_off.ring_mask) };
+        let cqes = self.cq_ring.at::<Cqe>(cq_off.cqes);
+        let cq_head = unsafe { &*self.cq_ring.at::<AtomicU32>(cq_off.head) };
+        let cq_tail = unsafe { &*self.cq_ring.at::<AtomicU32>(cq_off.tail) };
+
+        let mut head = cq_head.load(Ordering::Relaxed);
+        let tail = cq_tail.load(Ordering::Acquire);
+        let mut count = 0;
+        while head != tail {
+            let cqe = unsafe { &*cqes.add((head & cq_mask) as usize) };
+            results[cqe.user_data as usize] = cqe.res;
+            head = head.wrapping_add(1);
+            count += 1;
+        }
+        cq_head.store(head, Ordering::Release);
+        count
+    }
+
+    fn enter(&self, to_submit: u32, min_complete: u32, flags: libc::c_uint) -> io::Result<usize> {
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_enter,
+                self.fd.as_raw_fd(),
+                to_submit as libc::c_uint,
+                min_complete as libc::c_uint,
+                flags,
+                ptr::null::<libc::sigset_t>(),
+                0usize,
+            )
+        };
+        if ret < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(ret as usize)
+        }
+    }
+}
+
+#[cfg(test)]
+mod io_uring_test {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn batches_are_read_at_their_offsets() {
+        let path = "io_uring_batches_are_read_at_their_offsets.bin";
+        let contents: Vec<u8> = (0..8192).map(|i| (i % 251) as u8).collect();
+        fs::write(path, &contents).unwrap();
+        let file = File::open(path).unwrap();
+
+        // Where io_uring is disabled there is nothing to test
+        let mut ring = match IoUring::new(2) {
+            Ok(ring) => ring,
+            Err(_) => {
+                fs::remove_file(path).unwrap();
+                return;
+            }
+        };
+
+        // More reads than entries take several batches
+        let mut bufs = vec![vec![0u8; 512]; 5];
+        let offsets = [0u64, 4096, 512, 7680, 1000];
+        let mut reads: Vec<(u64, &mut [u8])> = offsets
+            .iter()
+            .zip(bufs.iter_mut())
+            .map(|(&offset, buf)| (offset, buf.as_mut_slice()))
+            .collect();
+        ring.read_exact_at(&file, &mut reads).unwrap();
+        for (&offset, buf) in offsets.iter().zip(&bufs) {
+            assert_eq!(
+                buf.as_slice(),
+                &contents[offset as usize..offset as usize + 512]
+            );
+        }
+
+        let mut past_end = vec![0u8; 512];
+        let result = ring.read_exact_at(&file, &mut [(7900, past_end.as_mut_slice())]);
+        fs::remove_file(path).unwrap();
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -8,13 +8,39 @@
 )]
 
 pub mod perf;
-pub use perf::{get_process_cycle_time, get_process_handle};
+pub use perf::{
+    count_hardware_events, get_process_cycle_time, get_process_handle, HardwareCounters,
+    HardwareEvent,
+};
 
+#[cfg(feature = "tokio-file")]
 pub mod file_io;
+#[cfg(feature = "tokio-file")]
 pub use file_io::{get_queued_completion_status, read_file_to_slice};
 
+#[cfg(feature = "tokio-file")]
 pub mod file_handle;
+#[cfg(feature = "tokio-file")]
 pub use file_handle::FileHandle;
 
+#[cfg(feature = "tokio-file")]
 pub mod io_completion_port;
+#[cfg(feature = "tokio-file")]
 pub use io_completion_port::IOCompletionPort;
+
+#[cfg(target_os = "linux")]
+pub mod io_uring;
+#[cfg(target_os = "linux")]
+pub use io_uring::IoUring;
+
+pub mod file_lock;
+pub use file_lock::{FileLock, LockMode};
+
+pub mod page_cache;
+pub use page_cache::{advise_will_need, evict_from_page_cache};
+
+pub mod mmap;
+pub use mmap::MmapFile;
+
+pub mod memory;
+pub use memory::{current_memory_usage, MemoryTracker, MemoryUsage};
@@ -0,0 +1,154 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+//! Resident memory of the process, sampled by long running builds to report their footprint.
+//! The OS keeps the peak itself, so the peak includes the spikes between samples.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[cfg(target_os = "windows")]
+#[repr(C)]
+#[derive(Default)]
+struct ProcessMemoryCounters {
+    cb: u32,
+    page_fault_count: u32,
+    peak_working_set_size: usize,
+    working_set_size: usize,
+    quota_peak_paged_pool_usage: usize,
+    quota_paged_pool_usage: usize,
+    quota_peak_non_paged_pool_usage: usize,
+    quota_non_paged_pool_usage: usize,
+    pagefile_usage: usize,
+    peak_pagefile_usage: usize,
+}
+
+#[cfg(target_os = "windows")]
+#[link(name = "kernel32")]
+extern "system" {
+    fn GetCurrentProcess() -> isize;
+    fn K32GetProcessMemoryInfo(
+        process: isize,
+        counters: *mut ProcessMemoryCounters,
+        cb: u32,
+    ) -> i32;
+}
+
+/// Resident memory of the process at a point in time
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// Memory resident now in bytes (VmRSS, the working set on Windows)
+    pub resident_bytes: u64,
+
+    /// Most memory resident since the process started in bytes (VmHWM, the peak working set
+    /// on Windows)
+    pub peak_resident_bytes: u64,
+}
+
+/// Resident memory of the process, None where it can't be read
+#[cfg(target_os = "linux")]
+pub fn current_memory_usage() -> Option<MemoryUsage> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    // The values are in kB, e.g. "VmHWM:     1234 kB"
+    let field = |name: &str| -> Option<u64> {
+        let line = status.lines().find(|line| line.starts_with(name))?;
+        let kb: u64 = line[name.len()..].split_whitespace().next()?.parse().ok()?;
+        Some(kb * 1024)
+    };
+    Some(MemoryUsage {
+        resident_bytes: field("VmRSS:")?,
+        peak_resident_bytes: field("VmHWM:")?,
+    })
+}
+
+/// Resident memory of the process, None where it can't be read
+#[cfg(target_os = "windows")]
+pub fn current_memory_usage() -> Option<MemoryUsage> {
+    let mut counters = ProcessMemoryCounters {
+        cb: std::mem::size_of::<ProcessMemoryCounters>() as u32,
+        ..Default::default()
+    };
+    let ok = unsafe { K32GetProcessMemoryInfo(GetCurrentProcess(), &mut counters, counters.cb) };
+    if ok == 0 {
+        return None;
+    }
+    Some(MemoryUsage {
+        resident_bytes: counters.working_set_size as u64,
+        peak_resident_bytes: counters.peak_working_set_size as u64,
+    })
+}
+
+/// Resident memory of the process, None where it can't be read
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+pub fn current_memory_usage() -> Option<MemoryUsage> {
+    None
+}
+
+/// Samples the resident memory of the process, e.g. at the checkpoints of a build, and keeps
+/// the peak since the tracker was created
+#[derive(Debug)]
+pub struct MemoryTracker {
+    start: Option<MemoryUsage>,
+    peak_resident_bytes: AtomicU64,
+}
+
+impl MemoryTracker {
+    /// Start tracking from the memory resident now
+    pub fn new() -> Self {
+        let start = current_memory_usage();
+        Self {
+            start,
+            peak_resident_bytes: AtomicU64::new(start.map_or(0, |usage| usage.resident_bytes)),
+        }
+    }
+
+    /// Read the resident memory now and update the peak, None where it can't be read
+    pub fn sample(&self) -> Option<MemoryUsage> {
+        let usage = current_memory_usage()?;
+        // The process peak only counts for the tracker once it rises past the peak the
+        // process had when tracking started
+        let peak = match self.start {
+            Some(start) if usage.peak_resident_bytes > start.peak_resident_bytes => {
+                usage.peak_resident_bytes
+            }
+            _ => usage.resident_bytes,
+        };
+        self.peak_resident_bytes.fetch_max(peak, Ordering::Relaxed);
+        Some(usage)
+    }
+
+    /// Most memory resident since tracking started as far as the samples and the OS tell, None
+    /// where it can't be read
+    pub fn peak_resident_bytes(&self) -> Option<u64> {
+        self.start?;
+        Some(self.peak_resident_bytes.load(Ordering::Relaxed))
+    }
+}
+
+impl Default for MemoryTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod memory_test {
+    use super::*;
+
+    #[test]
+    fn tracker_keeps_the_peak_of_freed_memory() {
+        let tracker = MemoryTracker::new();
+        let usage = current_memory_usage().unwrap();
+        assert!(usage.resident_bytes > 0);
+        assert!(usage.peak_resident_bytes >= usage.resident_bytes);
+
+        // Filling the block makes its pages resident
+        let block = vec![1u8; 64 * 1024 * 1024];
+        let sample = tracker.sample().unwrap();
+        assert!(sample.resident_bytes >= block.len() as u64);
+        drop(block);
+
+        tracker.sample().unwrap();
+        assert!(tracker.peak_resident_bytes().unwrap() >= 64 * 1024 * 1024);
+    }
+}
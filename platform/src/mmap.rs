@@ -0,0 +1,135 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+//! Read-only memory-mapped files. The pages are loaded by the OS on first access, so mapping
+//! a large file returns immediately. On platforms without support the file is read instead.
+
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+#[cfg(target_os = "linux")]
+use std::os::unix::io::AsRawFd;
+
+#[cfg(target_os = "linux")]
+extern "C" {
+    fn mmap(addr: *mut u8, len: usize, prot: i32, flags: i32, fd: i32, offset: i64) -> *mut u8;
+    fn munmap(addr: *mut u8, len: usize) -> i32;
+}
+
+#[cfg(target_os = "linux")]
+const PROT_READ: i32 = 1;
+#[cfg(target_os = "linux")]
+const MAP_PRIVATE: i32 = 2;
+
+/// A file mapped read-only into memory, unmapped when dropped
+#[derive(Debug)]
+pub struct MmapFile {
+    #[cfg(target_os = "linux")]
+    ptr: *mut u8,
+
+    #[cfg(target_os = "linux")]
+    len: usize,
+
+    #[cfg(not(target_os = "linux"))]
+    data: Vec<u8>,
+}
+
+// The mapping is read-only and owned by this value
+unsafe impl Send for MmapFile {}
+unsafe impl Sync for MmapFile {}
+
+impl MmapFile {
+    /// Map the whole file. The mapping starts at a page boundary.
+    #[cfg(target_os = "linux")]
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let len = usize::try_from(file.metadata()?.len())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        if len == 0 {
+            return Ok(Self {
+                ptr: std::ptr::null_mut(),
+                len,
+            });
+        }
+
+        // The mapping stays valid after the file is closed
+        let ptr = unsafe {
+            mmap(
+                std::ptr::null_mut(),
+                len,
+                PROT_READ,
+                MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr as isize == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self { ptr, len })
+    }
+
+    /// Read the whole file into memory
+    #[cfg(not(target_os = "linux"))]
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        use std::io::Read;
+
+        let mut data = Vec::new();
+        File::open(path)?.read_to_end(&mut data)?;
+        Ok(Self { data })
+    }
+
+    /// Contents of the file
+    #[cfg(target_os = "linux")]
+    pub fn as_bytes(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+
+    /// Contents of the file
+    #[cfg(not(target_os = "linux"))]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for MmapFile {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            unsafe {
+                munmap(self.ptr, self.len);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod mmap_test {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn maps_file_contents() {
+        let path = "mmap_test.bin";
+        let contents: Vec<u8> = (0..10000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(path, &contents).unwrap();
+
+        let mapped = MmapFile::open(path).unwrap();
+        assert_eq!(mapped.as_bytes(), contents.as_slice());
+        assert_eq!(mapped.as_bytes().as_ptr() as usize % 4096, 0);
+        drop(mapped);
+
+        fs::write(path, []).unwrap();
+        assert!(MmapFile::open(path).unwrap().as_bytes().is_empty());
+        assert!(MmapFile::open("mmap_test_missing.bin").is_err());
+
+        fs::remove_file(path).unwrap();
+    }
+}
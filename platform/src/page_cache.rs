@@ -0,0 +1,95 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+//! Evicting files from the OS page cache, so the next read of an index file comes from the
+//! device as it would after a restart. Only clean pages can be dropped, so the file should not
+//! have unflushed writes. Ranges of a file can also be read into the cache ahead of their use.
+
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+#[cfg(target_os = "linux")]
+use std::os::unix::io::AsRawFd;
+
+#[cfg(target_os = "linux")]
+extern "C" {
+    fn posix_fadvise(fd: i32, offset: i64, len: i64, advice: i32) -> i32;
+}
+
+#[cfg(target_os = "linux")]
+const POSIX_FADV_WILLNEED: i32 = 3;
+
+#[cfg(target_os = "linux")]
+const POSIX_FADV_DONTNEED: i32 = 4;
+
+/// Ask the OS to drop the cached pages of a file (posix_fadvise POSIX_FADV_DONTNEED)
+#[cfg(target_os = "linux")]
+pub fn evict_from_page_cache<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let file = File::open(path)?;
+    // A length of 0 covers the whole file. The error is returned, not set in errno.
+    let ret = unsafe { posix_fadvise(file.as_raw_fd(), 0, 0, POSIX_FADV_DONTNEED) };
+    if ret != 0 {
+        return Err(io::Error::from_raw_os_error(ret));
+    }
+    Ok(())
+}
+
+/// Ask the OS to start reading len bytes of file at offset into the page cache
+/// (posix_fadvise POSIX_FADV_WILLNEED). Returns without waiting for the reads.
+#[cfg(target_os = "linux")]
+pub fn advise_will_need(file: &File, offset: u64, len: u64) -> io::Result<()> {
+    let ret = unsafe {
+        posix_fadvise(
+            file.as_raw_fd(),
+            offset as i64,
+            len as i64,
+            POSIX_FADV_WILLNEED,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::from_raw_os_error(ret));
+    }
+    Ok(())
+}
+
+/// Advice is only given on Linux, elsewhere the pages are read when they are first used
+#[cfg(not(target_os = "linux"))]
+pub fn advise_will_need(_file: &File, _offset: u64, _len: u64) -> io::Result<()> {
+    Ok(())
+}
+
+/// Dropping the cached pages of a single file is not supported on this platform
+#[cfg(not(target_os = "linux"))]
+pub fn evict_from_page_cache<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    File::open(path)?;
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Evicting a file from the page cache is only supported on Linux",
+    ))
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod page_cache_test {
+    use std::fs;
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn evicts_existing_file() {
+        let path = "page_cache_test.bin";
+        let mut file = File::create(path).unwrap();
+        file.write_all(&[7u8; 8192]).unwrap();
+        file.sync_all().unwrap();
+
+        evict_from_page_cache(path).unwrap();
+        advise_will_need(&File::open(path).unwrap(), 4096, 4096).unwrap();
+        // The contents are read back from the device
+        assert_eq!(fs::read(path).unwrap(), vec![7u8; 8192]);
+        assert!(evict_from_page_cache("page_cache_test_missing.bin").is_err());
+
+        fs::remove_file(path).unwrap();
+    }
+}
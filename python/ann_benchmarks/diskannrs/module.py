@@ -0,0 +1,62 @@
+# Copyright (c) Microsoft Corporation. All rights reserved.
+# Licensed under the MIT license.
+
+"""ann-benchmarks algorithm of the in-memory index, through the diskannrs Python bindings.
+
+Copy this directory to ann_benchmarks/algorithms/diskannrs of an ann-benchmarks checkout, then
+build its image with `python install.py --algorithm diskannrs`.
+"""
+
+import numpy as np
+from diskannrs import Index
+
+from ..base.module import BaseANN
+
+# ann-benchmarks metrics and the distances of the index
+METRICS = {"euclidean": "l2", "angular": "cosine"}
+
+
+class DiskannRs(BaseANN):
+    def __init__(self, metric, index_params):
+        if metric not in METRICS:
+            raise NotImplementedError("diskannrs doesn't support the {} metric".format(metric))
+        self.metric = METRICS[metric]
+        self.max_degree = index_params.get("R", 64)
+        self.l_build = index_params.get("L", 100)
+        self.alpha = index_params.get("alpha", 1.2)
+        self.num_threads = index_params.get("num_threads", 1)
+        self.l_search = self.l_build
+        self.index = None
+        self.batch_results = []
+
+    def fit(self, X):
+        X = np.ascontiguousarray(X, dtype=np.float32)
+        self.index = Index(
+            self.metric,
+            X.shape[1],
+            X.shape[0],
+            max_degree=self.max_degree,
+            list_size=self.l_build,
+            alpha=self.alpha,
+            num_threads=self.num_threads,
+        )
+        self.index.build(X)
+
+    def set_query_arguments(self, l_search):
+        self.l_search = l_search
+
+    def query(self, v, n):
+        ids, _ = self.index.search(np.ascontiguousarray(v, dtype=np.float32), n, self.l_search)
+        return ids
+
+    def batch_query(self, X, n):
+        X = np.ascontiguousarray(X, dtype=np.float32)
+        self.batch_results, _ = self.index.search_batch(X, n, self.l_search)
+
+    def get_batch_results(self):
+        return self.batch_results
+
+    def __str__(self):
+        return "DiskANN(R={}, L={}, alpha={}, L_search={})".format(
+            self.max_degree, self.l_build, self.alpha, self.l_search
+        )
@@ -0,0 +1,285 @@
+# Copyright (c) Microsoft Corporation. All rights reserved.
+# Licensed under the MIT license.
+
+"""Python bindings of the in-memory index, over the C interface of diskann_ffi.
+
+The shared library is the file DISKANN_FFI_LIB names, else the one next to this package, else
+the one `cargo build --release -p diskann_ffi` writes in the target directory of the
+repository. Vectors are float32, given as a buffer such as a numpy array of float32 or as
+sequences of floats, one vector or rows of vectors.
+"""
+
+import ctypes
+import os
+import sys
+from array import array
+
+__all__ = ["DiskannError", "Index"]
+
+_LIBRARY_NAMES = {
+    "darwin": "libdiskann_ffi.dylib",
+    "win32": "diskann_ffi.dll",
+}
+
+# Status codes of diskann.h
+_OK = 0
+
+
+class DiskannError(Exception):
+    """Failed call of the C interface"""
+
+    def __init__(self, status, message, code, retryable):
+        super().__init__(message)
+
+        # DiskannStatus of the call
+        self.status = status
+
+        # Stable code of the kind of the ANNError, 0 for invalid arguments and panics
+        self.code = code
+
+        # Whether the call may succeed if made again
+        self.retryable = retryable
+
+
+def _library_path():
+    path = os.environ.get("DISKANN_FFI_LIB")
+    if path:
+        return path
+
+    name = _LIBRARY_NAMES.get(sys.platform, "libdiskann_ffi.so")
+    package_dir = os.path.dirname(os.path.abspath(__file__))
+    repo_target = os.path.join(package_dir, os.pardir, os.pardir, "target")
+    for path in [
+        os.path.join(package_dir, name),
+        os.path.join(repo_target, "release", name),
+        os.path.join(repo_target, "debug", name),
+    ]:
+        if os.path.exists(path):
+            return path
+    raise OSError(
+        "{} not found, build it with `cargo build --release -p diskann_ffi` "
+        "or set DISKANN_FFI_LIB".format(name)
+    )
+
+
+def _load_library():
+    lib = ctypes.CDLL(_library_path())
+    handle = ctypes.c_void_p
+    floats = ctypes.POINTER(ctypes.c_float)
+    ids = ctypes.POINTER(ctypes.c_uint32)
+    sizes = ctypes.POINTER(ctypes.c_size_t)
+    signatures = {
+        "diskann_last_error": (ctypes.c_char_p, []),
+        "diskann_last_error_code": (ctypes.c_uint32, []),
+        "diskann_last_error_retryable": (ctypes.c_bool, []),
+        "diskann_index_create": (
+            ctypes.c_int,
+            [
+                ctypes.c_char_p,
+                ctypes.c_size_t,
+                ctypes.c_size_t,
+                ctypes.c_uint32,
+                ctypes.c_uint32,
+                ctypes.c_float,
+                ctypes.c_uint32,
+                ctypes.POINTER(handle),
+            ],
+        ),
+        "diskann_index_free": (None, [handle]),
+        "diskann_index_build": (ctypes.c_int, [handle, floats, ctypes.c_size_t]),
+        "diskann_index_build_from_file": (ctypes.c_int, [handle, ctypes.c_char_p]),
+        "diskann_index_load": (ctypes.c_int, [handle, ctypes.c_char_p, ctypes.c_size_t]),
+        "diskann_index_save": (ctypes.c_int, [handle, ctypes.c_char_p]),
+        "diskann_index_insert": (ctypes.c_int, [handle, floats, ids]),
+        "diskann_index_delete": (ctypes.c_int, [handle, ctypes.c_uint32]),
+        "diskann_index_search": (
+            ctypes.c_int,
+            [handle, floats, ctypes.c_size_t, ctypes.c_uint32, ids, floats, sizes],
+        ),
+        "diskann_index_search_batch": (
+            ctypes.c_int,
+            [
+                handle,
+                floats,
+                ctypes.c_size_t,
+                ctypes.c_size_t,
+                ctypes.c_uint32,
+                ids,
+                floats,
+                sizes,
+            ],
+        ),
+    }
+    for name, (restype, argtypes) in signatures.items():
+        function = getattr(lib, name)
+        function.restype = restype
+        function.argtypes = argtypes
+    return lib
+
+
+_lib = None
+
+
+def _library():
+    global _lib
+    if _lib is None:
+        _lib = _load_library()
+    return _lib
+
+
+def _check(status):
+    if status != _OK:
+        lib = _library()
+        raise DiskannError(
+            status,
+            lib.diskann_last_error().decode("utf-8", "replace"),
+            lib.diskann_last_error_code(),
+            lib.diskann_last_error_retryable(),
+        )
+
+
+def _floats(values):
+    """float32 values of a buffer or of a vector or rows of vectors, with their count"""
+    try:
+        view = memoryview(values)
+    except TypeError:
+        view = None
+    if view is not None:
+        if view.format != "f":
+            raise TypeError("vectors must be float32, not {}".format(view.format))
+        buffer = (ctypes.c_float * (view.nbytes // 4)).from_buffer_copy(view.cast("B"))
+    else:
+        rows = [values] if len(values) == 0 or not hasattr(values[0], "__len__") else values
+        flat = array("f", (value for row in rows for value in row))
+        buffer = (ctypes.c_float * len(flat)).from_buffer(flat)
+    return buffer, len(buffer)
+
+
+class Index:
+    """In-memory index of float32 vectors of a dimension"""
+
+    def __init__(
+        self,
+        metric,
+        dim,
+        max_points,
+        max_degree=64,
+        list_size=100,
+        alpha=1.2,
+        num_threads=1,
+    ):
+        """Empty index of up to max_points vectors. metric is the name of the distance, such
+        as "l2" or "cosine", max_degree, list_size, alpha and num_threads those of the
+        builds and inserts."""
+        self.dim = dim
+        self._handle = ctypes.c_void_p()
+        _check(
+            _library().diskann_index_create(
+                metric.encode("utf-8"),
+                dim,
+                max_points,
+                max_degree,
+                list_size,
+                alpha,
+                num_threads,
+                ctypes.byref(self._handle),
+            )
+        )
+
+    def close(self):
+        """Free the index, which can't be used after"""
+        if self._handle:
+            _library().diskann_index_free(self._handle)
+            self._handle = ctypes.c_void_p()
+
+    def __del__(self):
+        if getattr(self, "_handle", None):
+            self.close()
+
+    def __enter__(self):
+        return self
+
+    def __exit__(self, *exc):
+        self.close()
+
+    def _num_vectors(self, count):
+        if count % self.dim != 0:
+            raise ValueError(
+                "{} values can't be split in vectors of dimension {}".format(count, self.dim)
+            )
+        return count // self.dim
+
+    def build(self, vectors):
+        """Build the index from vectors, their ids are their positions"""
+        buffer, count = _floats(vectors)
+        _check(_library().diskann_index_build(self._handle, buffer, self._num_vectors(count)))
+
+    def build_from_file(self, path):
+        """Build the index from the vectors of a bin file"""
+        _check(_library().diskann_index_build_from_file(self._handle, path.encode("utf-8")))
+
+    def load(self, path, num_points):
+        """Load num_points vectors and their graph saved under the path prefix"""
+        _check(_library().diskann_index_load(self._handle, path.encode("utf-8"), num_points))
+
+    def save(self, path):
+        """Save the index under the path prefix"""
+        _check(_library().diskann_index_save(self._handle, path.encode("utf-8")))
+
+    def insert(self, vector):
+        """Insert a vector and return its id"""
+        buffer, count = _floats(vector)
+        self._num_vectors(count)
+        point_id = ctypes.c_uint32()
+        _check(_library().diskann_index_insert(self._handle, buffer, ctypes.byref(point_id)))
+        return point_id.value
+
+    def delete(self, point_id):
+        """Delete the point of an id, later searches don't return it"""
+        _check(_library().diskann_index_delete(self._handle, point_id))
+
+    def search(self, query, k, list_size):
+        """Ids and distances of the k nearest neighbors of a query, nearest first"""
+        buffer, count = _floats(query)
+        if self._num_vectors(count) != 1:
+            raise ValueError("search takes one query, search_batch several")
+        ids = (ctypes.c_uint32 * k)()
+        distances = (ctypes.c_float * k)()
+        num_results = ctypes.c_size_t()
+        _check(
+            _library().diskann_index_search(
+                self._handle,
+                buffer,
+                k,
+                max(list_size, k),
+                ids,
+                distances,
+                ctypes.byref(num_results),
+            )
+        )
+        return list(ids[: num_results.value]), list(distances[: num_results.value])
+
+    def search_batch(self, queries, k, list_size):
+        """Ids and distances of the k nearest neighbors of each query, searched in parallel,
+        as a list of ids and a list of distances per query"""
+        buffer, count = _floats(queries)
+        num_queries = self._num_vectors(count)
+        ids = (ctypes.c_uint32 * (num_queries * k))()
+        distances = (ctypes.c_float * (num_queries * k))()
+        num_results = (ctypes.c_size_t * num_queries)()
+        _check(
+            _library().diskann_index_search_batch(
+                self._handle,
+                buffer,
+                num_queries,
+                k,
+                max(list_size, k),
+                ids,
+                distances,
+                num_results,
+            )
+        )
+        return (
+            [list(ids[i * k : i * k + num_results[i]]) for i in range(num_queries)],
+            [list(distances[i * k : i * k + num_results[i]]) for i in range(num_queries)],
+        )
@@ -0,0 +1,59 @@
+# Copyright (c) Microsoft Corporation. All rights reserved.
+# Licensed under the MIT license.
+
+"""Tests of the bindings, run from the python directory after building diskann_ffi:
+
+    python -m unittest discover tests
+"""
+
+import unittest
+from array import array
+
+from diskannrs import DiskannError, Index
+
+
+def grid(num_points):
+    """Points on a 3-d grid padded to 8 dimensions, each its own nearest neighbor"""
+    return [
+        [float(i % 5), float((i // 5) % 5), float(i // 25)] + [0.5] * 5
+        for i in range(num_points)
+    ]
+
+
+class IndexTest(unittest.TestCase):
+    def test_build_search_insert_and_delete(self):
+        vectors = grid(125)
+        with Index("l2", 8, 200, max_degree=16, list_size=50) as index:
+            index.build(vectors[:100])
+
+            ids, distances = index.search(vectors[42], 3, 50)
+            self.assertEqual(ids[0], 42)
+            self.assertEqual(distances[0], 0.0)
+            self.assertLessEqual(distances[0], distances[1])
+
+            point_id = index.insert(vectors[100])
+            self.assertEqual(point_id, 100)
+            index.delete(point_id)
+            self.assertNotEqual(index.search(vectors[100], 1, 50)[0], [point_id])
+
+            # A batch is rows or a float32 buffer of rows
+            flat = array("f", (value for vector in vectors[10:14] for value in vector))
+            for queries in [vectors[10:14], flat]:
+                ids, distances = index.search_batch(queries, 3, 50)
+                self.assertEqual([query_ids[0] for query_ids in ids], [10, 11, 12, 13])
+                self.assertEqual([len(query_ids) for query_ids in ids], [3] * 4)
+
+    def test_failures_raise_with_their_message(self):
+        with self.assertRaises(DiskannError) as failure:
+            Index("manhattan", 8, 100)
+        self.assertIn("metric", str(failure.exception))
+
+        with Index("l2", 8, 100) as index:
+            with self.assertRaises(ValueError):
+                index.search([1.0, 2.0], 1, 10)
+            with self.assertRaises(TypeError):
+                index.build(array("d", [0.0] * 8))
+
+
+if __name__ == "__main__":
+    unittest.main()
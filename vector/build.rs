@@ -1,5 +1,9 @@
 fn main() {
     println!("cargo:rerun-if-changed=distance.c");
+    // The C kernels are AVX2 code, there is nothing to build for other targets such as wasm32
+    if std::env::var("CARGO_CFG_TARGET_ARCH").is_ok_and(|arch| arch != "x86_64") {
+        return;
+    }
     if cfg!(target_os = "macos") {
         println!("Building for MacOS");
         std::env::set_var("CFLAGS", "-mavx2 -mfma -Wno-error -MP -O2 -D NDEBUG -D MKL_ILP64 -D USE_AVX2 -D USE_ACCELERATED_PQ -D NOMINMAX -D _TARGET_ARM_APPLE_DARWIN");
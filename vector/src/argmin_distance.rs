@@ -0,0 +1,224 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Fused distance + argmin kernels for small candidate sets.
+//! Candidates are processed four at a time so each query load is shared, and the running
+//! best is kept in registers instead of writing every distance to memory.
+//! Per candidate, the accumulation order is the same as the single-pair kernels, so the
+//! returned distance is bit-identical to calling them one by one.
+
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+#[cfg(target_arch = "x86_64")]
+use crate::avx512_distance::distance_l2_f32_avx512;
+use crate::l2_float_distance::distance_l2_vector_f32;
+#[cfg(target_arch = "x86_64")]
+use crate::simd::horizontal_sum;
+
+/// Candidates sharing one pass over the query
+const GROUP: usize = 4;
+
+/// Lanes of f32 in a 512-bit register
+#[cfg(target_arch = "x86_64")]
+const F32_LANES_512: usize = 16;
+
+/// Find the candidate closest to `a` by squared L2 with AVX2.
+/// Returns its position and distance, the first one wins ties. None if there are no candidates.
+#[inline(never)]
+pub fn distance_l2_argmin_vector_f32<const N: usize>(
+    a: &[f32; N],
+    candidates: &[&[f32; N]],
+) -> Option<(usize, f32)> {
+    debug_assert_eq!(N % 8, 0);
+
+    let mut best: Option<(usize, f32)> = None;
+    let mut groups = candidates.chunks_exact(GROUP);
+    for (group_idx, group) in groups.by_ref().enumerate() {
+        let distances = unsafe { l2_group_avx2::<N>(a, [group[0], group[1], group[2], group[3]]) };
+        keep_best(&mut best, group_idx * GROUP, distances);
+    }
+
+    let tail_start = candidates.len() - groups.remainder().len();
+    for (offset, b) in groups.remainder().iter().enumerate() {
+        keep_best(&mut best, tail_start + offset, [distance_l2_vector_f32::<N>(a, b)]);
+    }
+
+    best
+}
+
+/// Find the candidate closest to `a` by squared L2 with AVX-512F.
+/// Returns its position and distance, the first one wins ties. None if there are no candidates.
+/// # Safety
+/// The CPU must support avx512f.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx512f")]
+pub unsafe fn distance_l2_argmin_f32_avx512<const N: usize>(
+    a: &[f32; N],
+    candidates: &[&[f32; N]],
+) -> Option<(usize, f32)> {
+    let mut best: Option<(usize, f32)> = None;
+    let mut groups = candidates.chunks_exact(GROUP);
+    for (group_idx, group) in groups.by_ref().enumerate() {
+        let distances = l2_group_avx512(a, [group[0], group[1], group[2], group[3]].map(|b| &b[..]));
+        keep_best(&mut best, group_idx * GROUP, distances);
+    }
+
+    let tail_start = candidates.len() - groups.remainder().len();
+    for (offset, b) in groups.remainder().iter().enumerate() {
+        keep_best(&mut best, tail_start + offset, [distance_l2_f32_avx512(a, *b)]);
+    }
+
+    best
+}
+
+#[inline(always)]
+fn keep_best<const M: usize>(best: &mut Option<(usize, f32)>, first: usize, distances: [f32; M]) {
+    for (offset, distance) in distances.into_iter().enumerate() {
+        if best.is_none_or(|(_, best_distance)| distance < best_distance) {
+            *best = Some((first + offset, distance));
+        }
+    }
+}
+
+/// Same operation order as `distance_l2_vector_f32`, for four candidates at once
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+unsafe fn l2_group_avx2<const N: usize>(a: &[f32; N], group: [&[f32; N]; GROUP]) -> [f32; GROUP] {
+    let mut sums = [_mm256_setzero_ps(); GROUP];
+
+    for i in (0..N).step_by(8) {
+        let a_vec = _mm256_load_ps(&a[i]);
+        for (sum, b) in sums.iter_mut().zip(group) {
+            let diff = _mm256_sub_ps(a_vec, _mm256_load_ps(&b[i]));
+            *sum = _mm256_fmadd_ps(diff, diff, *sum);
+        }
+    }
+
+    sums.map(|sum| horizontal_sum(sum))
+}
+
+/// Squared L2 distances of four candidates one by one, on targets without AVX2
+#[cfg(not(target_arch = "x86_64"))]
+#[inline(always)]
+unsafe fn l2_group_avx2<const N: usize>(a: &[f32; N], group: [&[f32; N]; GROUP]) -> [f32; GROUP] {
+    group.map(|b| distance_l2_vector_f32::<N>(a, b))
+}
+
+/// Same operation order as `distance_l2_f32_avx512`, for four candidates at once
+#[cfg(target_arch = "x86_64")]
+#[inline]
+#[target_feature(enable = "avx512f")]
+unsafe fn l2_group_avx512(a: &[f32], group: [&[f32]; GROUP]) -> [f32; GROUP] {
+    let len = a.len();
+    let a_ptr = a.as_ptr();
+    let mut sum0 = [_mm512_setzero_ps(); GROUP];
+    let mut sum1 = [_mm512_setzero_ps(); GROUP];
+
+    let mut i = 0;
+    while i + 2 * F32_LANES_512 <= len {
+        let a0 = _mm512_loadu_ps(a_ptr.add(i));
+        let a1 = _mm512_loadu_ps(a_ptr.add(i + F32_LANES_512));
+        for k in 0..GROUP {
+            let b_ptr = group[k].as_ptr();
+            let diff0 = _mm512_sub_ps(a0, _mm512_loadu_ps(b_ptr.add(i)));
+            let diff1 = _mm512_sub_ps(a1, _mm512_loadu_ps(b_ptr.add(i + F32_LANES_512)));
+            sum0[k] = _mm512_fmadd_ps(diff0, diff0, sum0[k]);
+            sum1[k] = _mm512_fmadd_ps(diff1, diff1, sum1[k]);
+        }
+        i += 2 * F32_LANES_512;
+    }
+
+    while i < len {
+        let remaining = len - i;
+        let mask: __mmask16 = if remaining >= F32_LANES_512 {
+            u16::MAX
+        } else {
+            (1u16 << remaining) - 1
+        };
+        let a_vec = _mm512_maskz_loadu_ps(mask, a_ptr.add(i));
+        for k in 0..GROUP {
+            let diff = _mm512_sub_ps(a_vec, _mm512_maskz_loadu_ps(mask, group[k].as_ptr().add(i)));
+            sum0[k] = _mm512_fmadd_ps(diff, diff, sum0[k]);
+        }
+        i += F32_LANES_512;
+    }
+
+    std::array::from_fn(|k| _mm512_reduce_add_ps(_mm512_add_ps(sum0[k], sum1[k])))
+}
+
+#[cfg(test)]
+mod argmin_distance_test {
+    use rand::Rng;
+
+    use super::*;
+
+    #[repr(C, align(32))]
+    struct F32Slice104([f32; 104]);
+
+    fn random_vectors(count: usize) -> Vec<F32Slice104> {
+        let mut rng = rand::thread_rng();
+        (0..count)
+            .map(|_| F32Slice104(std::array::from_fn(|_| rng.gen_range(-1.0..1.0))))
+            .collect()
+    }
+
+    fn expected_argmin(a: &[f32; 104], candidates: &[&[f32; 104]]) -> Option<(usize, f32)> {
+        let mut best: Option<(usize, f32)> = None;
+        for (i, b) in candidates.iter().enumerate() {
+            let distance = distance_l2_vector_f32::<104>(a, b);
+            if best.is_none_or(|(_, d)| distance < d) {
+                best = Some((i, distance));
+            }
+        }
+        best
+    }
+
+    #[test]
+    fn argmin_matches_pairwise_kernel() {
+        let query = random_vectors(1);
+        let vectors = random_vectors(11);
+        for count in 0..=vectors.len() {
+            let candidates: Vec<&[f32; 104]> = vectors[..count].iter().map(|v| &v.0).collect();
+            assert_eq!(
+                distance_l2_argmin_vector_f32::<104>(&query[0].0, &candidates),
+                expected_argmin(&query[0].0, &candidates)
+            );
+        }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn argmin_avx512_matches_pairwise_kernel() {
+        if !is_x86_feature_detected!("avx512f") {
+            return;
+        }
+
+        let query = random_vectors(1);
+        let vectors = random_vectors(9);
+        let candidates: Vec<&[f32; 104]> = vectors.iter().map(|v| &v.0).collect();
+        let (idx, distance) =
+            unsafe { distance_l2_argmin_f32_avx512::<104>(&query[0].0, &candidates) }.unwrap();
+        for (i, b) in candidates.iter().enumerate() {
+            let pairwise = unsafe { distance_l2_f32_avx512(&query[0].0, *b) };
+            assert!(pairwise >= distance);
+            if i == idx {
+                assert_eq!(pairwise, distance);
+            }
+        }
+    }
+
+    #[test]
+    fn argmin_keeps_first_of_ties() {
+        let query = random_vectors(1);
+        let same = random_vectors(1);
+        let candidates = vec![&same[0].0; 6];
+        assert_eq!(
+            distance_l2_argmin_vector_f32::<104>(&query[0].0, &candidates).map(|(idx, _)| idx),
+            Some(0)
+        );
+    }
+}
@@ -0,0 +1,244 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! AVX-512 distance kernels. Tails shorter than a full register are handled with
+//! masked loads, so any dimension is supported without padding.
+//! Callers must check CPU support before calling, see `simd_dispatch`.
+
+use std::arch::x86_64::*;
+
+/// Lanes of f32 in a 512-bit register
+const F32_LANES: usize = 16;
+
+/// Lanes of i8 consumed per iteration (widened to 32 x i16)
+const I8_LANES: usize = 32;
+
+/// Calculate the squared L2 distance between two f32 vectors with AVX-512F
+/// # Safety
+/// The CPU must support avx512f, and a and b must have equal lengths.
+#[target_feature(enable = "avx512f")]
+pub unsafe fn distance_l2_f32_avx512(a: &[f32], b: &[f32]) -> f32 {
+    debug_assert_eq!(a.len(), b.len());
+
+    let len = a.len();
+    let a_ptr = a.as_ptr();
+    let b_ptr = b.as_ptr();
+
+    // Two independent accumulators to hide the fmadd latency
+    let mut sum0 = _mm512_setzero_ps();
+    let mut sum1 = _mm512_setzero_ps();
+
+    let mut i = 0;
+    while i + 2 * F32_LANES <= len {
+        let diff0 = _mm512_sub_ps(_mm512_loadu_ps(a_ptr.add(i)), _mm512_loadu_ps(b_ptr.add(i)));
+        let diff1 = _mm512_sub_ps(
+            _mm512_loadu_ps(a_ptr.add(i + F32_LANES)),
+            _mm512_loadu_ps(b_ptr.add(i + F32_LANES)),
+        );
+        sum0 = _mm512_fmadd_ps(diff0, diff0, sum0);
+        sum1 = _mm512_fmadd_ps(diff1, diff1, sum1);
+        i += 2 * F32_LANES;
+    }
+
+    while i < len {
+        let mask = tail_mask_16(len - i);
+        let diff = _mm512_sub_ps(
+            _mm512_maskz_loadu_ps(mask, a_ptr.add(i)),
+            _mm512_maskz_loadu_ps(mask, b_ptr.add(i)),
+        );
+        sum0 = _mm512_fmadd_ps(diff, diff, sum0);
+        i += F32_LANES;
+    }
+
+    _mm512_reduce_add_ps(_mm512_add_ps(sum0, sum1))
+}
+
+/// Calculate the squared L2 distance between two i8 vectors with AVX-512BW.
+/// Elements are widened to i16 so the differences can't overflow.
+/// # Safety
+/// The CPU must support avx512f, avx512bw and avx512vl, and a and b must have equal lengths.
+#[target_feature(enable = "avx512f,avx512bw,avx512vl")]
+pub unsafe fn distance_l2_i8_avx512(a: &[i8], b: &[i8]) -> f32 {
+    debug_assert_eq!(a.len(), b.len());
+
+    let len = a.len();
+    let mut sum = _mm512_setzero_si512();
+
+    let mut i = 0;
+    while i < len {
+        let diff = load_diff_i8(a, b, i, tail_mask_32(len - i));
+        sum = _mm512_add_epi32(sum, _mm512_madd_epi16(diff, diff));
+        i += I8_LANES;
+    }
+
+    _mm512_reduce_add_epi32(sum) as f32
+}
+
+/// Calculate the squared L2 distance between two i8 vectors with AVX-512 VNNI,
+/// fusing the multiply and the accumulation into a single vpdpwssd.
+/// # Safety
+/// The CPU must support avx512f, avx512bw, avx512vl and avx512vnni, and a and b must have equal
+/// lengths.
+#[target_feature(enable = "avx512f,avx512bw,avx512vl,avx512vnni")]
+pub unsafe fn distance_l2_i8_avx512_vnni(a: &[i8], b: &[i8]) -> f32 {
+    debug_assert_eq!(a.len(), b.len());
+
+    let len = a.len();
+    let mut sum = _mm512_setzero_si512();
+
+    let mut i = 0;
+    while i < len {
+        let diff = load_diff_i8(a, b, i, tail_mask_32(len - i));
+        sum = _mm512_dpwssd_epi32(sum, diff, diff);
+        i += I8_LANES;
+    }
+
+    _mm512_reduce_add_epi32(sum) as f32
+}
+
+/// Calculate the dot product of two i8 vectors with AVX-512BW
+/// # Safety
+/// The CPU must support avx512f, avx512bw and avx512vl, and a and b must have equal lengths.
+#[target_feature(enable = "avx512f,avx512bw,avx512vl")]
+pub unsafe fn dot_product_i8_avx512(a: &[i8], b: &[i8]) -> i32 {
+    debug_assert_eq!(a.len(), b.len());
+
+    let len = a.len();
+    let mut sum = _mm512_setzero_si512();
+
+    let mut i = 0;
+    while i < len {
+        let (a_vec, b_vec) = load_i8_pair(a, b, i, tail_mask_32(len - i));
+        sum = _mm512_add_epi32(sum, _mm512_madd_epi16(a_vec, b_vec));
+        i += I8_LANES;
+    }
+
+    _mm512_reduce_add_epi32(sum)
+}
+
+/// Calculate the dot product of two i8 vectors with AVX-512 VNNI
+/// # Safety
+/// The CPU must support avx512f, avx512bw, avx512vl and avx512vnni, and a and b must have equal
+/// lengths.
+#[target_feature(enable = "avx512f,avx512bw,avx512vl,avx512vnni")]
+pub unsafe fn dot_product_i8_avx512_vnni(a: &[i8], b: &[i8]) -> i32 {
+    debug_assert_eq!(a.len(), b.len());
+
+    let len = a.len();
+    let mut sum = _mm512_setzero_si512();
+
+    let mut i = 0;
+    while i < len {
+        let (a_vec, b_vec) = load_i8_pair(a, b, i, tail_mask_32(len - i));
+        sum = _mm512_dpwssd_epi32(sum, a_vec, b_vec);
+        i += I8_LANES;
+    }
+
+    _mm512_reduce_add_epi32(sum)
+}
+
+/// Load up to 32 i8 at offset from both vectors, sign extended to i16 lanes
+#[inline]
+#[target_feature(enable = "avx512f,avx512bw,avx512vl")]
+unsafe fn load_i8_pair(a: &[i8], b: &[i8], offset: usize, mask: __mmask32) -> (__m512i, __m512i) {
+    (
+        _mm512_cvtepi8_epi16(_mm256_maskz_loadu_epi8(mask, a.as_ptr().add(offset))),
+        _mm512_cvtepi8_epi16(_mm256_maskz_loadu_epi8(mask, b.as_ptr().add(offset))),
+    )
+}
+
+/// Load up to 32 i8 at offset from both vectors and return their difference as i16 lanes
+#[inline]
+#[target_feature(enable = "avx512f,avx512bw,avx512vl")]
+unsafe fn load_diff_i8(a: &[i8], b: &[i8], offset: usize, mask: __mmask32) -> __m512i {
+    let (a_vec, b_vec) = load_i8_pair(a, b, offset, mask);
+    _mm512_sub_epi16(a_vec, b_vec)
+}
+
+/// Mask selecting the first min(remaining, 16) lanes
+#[inline(always)]
+fn tail_mask_16(remaining: usize) -> __mmask16 {
+    if remaining >= 16 {
+        u16::MAX
+    } else {
+        (1u16 << remaining) - 1
+    }
+}
+
+/// Mask selecting the first min(remaining, 32) lanes
+#[inline(always)]
+fn tail_mask_32(remaining: usize) -> __mmask32 {
+    if remaining >= 32 {
+        u32::MAX
+    } else {
+        (1u32 << remaining) - 1
+    }
+}
+
+#[cfg(test)]
+mod avx512_distance_test {
+    use rand::Rng;
+
+    use super::*;
+    use crate::test_util::no_vector_compare_f32;
+
+    fn no_vector_compare_i8(a: &[i8], b: &[i8]) -> f32 {
+        a.iter()
+            .zip(b)
+            .map(|(x, y)| (*x as i32 - *y as i32).pow(2))
+            .sum::<i32>() as f32
+    }
+
+    #[test]
+    fn avx512_f32_matches_novector_with_tails() {
+        if !is_x86_feature_detected!("avx512f") {
+            return;
+        }
+
+        let mut rng = rand::thread_rng();
+        for len in [1, 7, 15, 16, 17, 33, 104, 128, 769, 1536] {
+            let a: Vec<f32> = (0..len).map(|_| rng.gen_range(-1.0..1.0)).collect();
+            let b: Vec<f32> = (0..len).map(|_| rng.gen_range(-1.0..1.0)).collect();
+
+            let distance = unsafe { distance_l2_f32_avx512(&a, &b) };
+            approx::assert_relative_eq!(distance, no_vector_compare_f32(&a, &b), max_relative = 1e-5);
+        }
+    }
+
+    #[test]
+    fn avx512_i8_matches_novector_with_tails() {
+        if !(is_x86_feature_detected!("avx512bw") && is_x86_feature_detected!("avx512vl")) {
+            return;
+        }
+
+        let mut rng = rand::thread_rng();
+        for len in [1, 31, 32, 33, 100, 768, 1025] {
+            let a: Vec<i8> = (0..len).map(|_| rng.gen()).collect();
+            let b: Vec<i8> = (0..len).map(|_| rng.gen()).collect();
+            let expected = no_vector_compare_i8(&a, &b);
+
+            let dot: i32 = a.iter().zip(&b).map(|(x, y)| *x as i32 * *y as i32).sum();
+
+            assert_eq!(unsafe { distance_l2_i8_avx512(&a, &b) }, expected);
+            assert_eq!(unsafe { dot_product_i8_avx512(&a, &b) }, dot);
+            if is_x86_feature_detected!("avx512vnni") {
+                assert_eq!(unsafe { distance_l2_i8_avx512_vnni(&a, &b) }, expected);
+                assert_eq!(unsafe { dot_product_i8_avx512_vnni(&a, &b) }, dot);
+            }
+        }
+    }
+
+    #[test]
+    fn avx512_i8_extreme_values() {
+        if !(is_x86_feature_detected!("avx512bw") && is_x86_feature_detected!("avx512vl")) {
+            return;
+        }
+
+        let a = [i8::MIN; 64];
+        let b = [i8::MAX; 64];
+        assert_eq!(unsafe { distance_l2_i8_avx512(&a, &b) }, 64.0 * 255.0 * 255.0);
+    }
+}
@@ -0,0 +1,201 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Distance calculation for bfloat16 vectors.
+//! bf16 is the upper half of an f32, so 8 values are widened to f32 lanes in-register by
+//! zero extending to 32 bits and shifting left by 16, then the f32 arithmetic is reused.
+
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+use crate::BFloat16;
+#[cfg(target_arch = "x86_64")]
+use crate::{
+    chebyshev_distance::horizontal_max, cosine_distance::cosine_distance, simd::horizontal_sum,
+};
+#[cfg(not(target_arch = "x86_64"))]
+use crate::{subspace_distance::distance_subspace_novector, Metric};
+
+/// Calculate the L2 distance by vector arithmetic
+#[cfg(target_arch = "x86_64")]
+#[inline(never)]
+pub fn distance_l2_vector_bf16<const N: usize>(a: &[BFloat16; N], b: &[BFloat16; N]) -> f32 {
+    debug_assert_eq!(N % 8, 0);
+
+    // make sure the addresses are 16 bytes aligned, see distance_l2_vector_f16
+    debug_assert_eq!(a.as_ptr().align_offset(16), 0);
+    debug_assert_eq!(b.as_ptr().align_offset(16), 0);
+
+    unsafe {
+        let mut sum = _mm256_setzero_ps();
+
+        // Iterate over the elements in steps of 8
+        for i in (0..N).step_by(8) {
+            let diff = _mm256_sub_ps(load_bf16x8(a, i), load_bf16x8(b, i));
+            sum = _mm256_fmadd_ps(diff, diff, sum);
+        }
+
+        horizontal_sum(sum)
+    }
+}
+
+/// Calculate the L2 distance element by element, on targets without AVX2
+#[cfg(not(target_arch = "x86_64"))]
+#[inline(never)]
+pub fn distance_l2_vector_bf16<const N: usize>(a: &[BFloat16; N], b: &[BFloat16; N]) -> f32 {
+    distance_subspace_novector(a, b, Metric::L2)
+}
+
+/// Calculate the L1 distance by vector arithmetic
+#[cfg(target_arch = "x86_64")]
+#[inline(never)]
+pub fn distance_l1_vector_bf16<const N: usize>(a: &[BFloat16; N], b: &[BFloat16; N]) -> f32 {
+    debug_assert_eq!(N % 8, 0);
+    debug_assert_eq!(a.as_ptr().align_offset(16), 0);
+    debug_assert_eq!(b.as_ptr().align_offset(16), 0);
+
+    unsafe {
+        let mut sum = _mm256_setzero_ps();
+        let sign_mask = _mm256_set1_ps(-0.0);
+
+        // Iterate over the elements in steps of 8
+        for i in (0..N).step_by(8) {
+            let diff = _mm256_sub_ps(load_bf16x8(a, i), load_bf16x8(b, i));
+            sum = _mm256_add_ps(sum, _mm256_andnot_ps(sign_mask, diff));
+        }
+
+        horizontal_sum(sum)
+    }
+}
+
+/// Calculate the L1 distance element by element, on targets without AVX2
+#[cfg(not(target_arch = "x86_64"))]
+#[inline(never)]
+pub fn distance_l1_vector_bf16<const N: usize>(a: &[BFloat16; N], b: &[BFloat16; N]) -> f32 {
+    distance_subspace_novector(a, b, Metric::L1)
+}
+
+/// Calculate the Chebyshev distance by vector arithmetic
+#[cfg(target_arch = "x86_64")]
+#[inline(never)]
+pub fn distance_chebyshev_vector_bf16<const N: usize>(a: &[BFloat16; N], b: &[BFloat16; N]) -> f32 {
+    debug_assert_eq!(N % 8, 0);
+    debug_assert_eq!(a.as_ptr().align_offset(16), 0);
+    debug_assert_eq!(b.as_ptr().align_offset(16), 0);
+
+    unsafe {
+        let mut max = _mm256_setzero_ps();
+        let sign_mask = _mm256_set1_ps(-0.0);
+
+        // Iterate over the elements in steps of 8
+        for i in (0..N).step_by(8) {
+            let diff = _mm256_sub_ps(load_bf16x8(a, i), load_bf16x8(b, i));
+            max = _mm256_max_ps(max, _mm256_andnot_ps(sign_mask, diff));
+        }
+
+        horizontal_max(max)
+    }
+}
+
+/// Calculate the Chebyshev distance element by element, on targets without AVX2
+#[cfg(not(target_arch = "x86_64"))]
+#[inline(never)]
+pub fn distance_chebyshev_vector_bf16<const N: usize>(a: &[BFloat16; N], b: &[BFloat16; N]) -> f32 {
+    distance_subspace_novector(a, b, Metric::Chebyshev)
+}
+
+/// Calculate the cosine distance by vector arithmetic
+#[cfg(target_arch = "x86_64")]
+#[inline(never)]
+pub fn distance_cosine_vector_bf16<const N: usize>(a: &[BFloat16; N], b: &[BFloat16; N]) -> f32 {
+    debug_assert_eq!(N % 8, 0);
+    debug_assert_eq!(a.as_ptr().align_offset(16), 0);
+    debug_assert_eq!(b.as_ptr().align_offset(16), 0);
+
+    unsafe {
+        let mut dot = _mm256_setzero_ps();
+        let mut norm_a = _mm256_setzero_ps();
+        let mut norm_b = _mm256_setzero_ps();
+
+        // Iterate over the elements in steps of 8
+        for i in (0..N).step_by(8) {
+            let a_vec = load_bf16x8(a, i);
+            let b_vec = load_bf16x8(b, i);
+            dot = _mm256_fmadd_ps(a_vec, b_vec, dot);
+            norm_a = _mm256_fmadd_ps(a_vec, a_vec, norm_a);
+            norm_b = _mm256_fmadd_ps(b_vec, b_vec, norm_b);
+        }
+
+        cosine_distance(horizontal_sum(dot), horizontal_sum(norm_a), horizontal_sum(norm_b))
+    }
+}
+
+/// Calculate the cosine distance element by element, on targets without AVX2
+#[cfg(not(target_arch = "x86_64"))]
+#[inline(never)]
+pub fn distance_cosine_vector_bf16<const N: usize>(a: &[BFloat16; N], b: &[BFloat16; N]) -> f32 {
+    distance_subspace_novector(a, b, Metric::Cosine)
+}
+
+/// Widen the 8 bf16 values starting at `i` to f32 lanes
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+unsafe fn load_bf16x8<const N: usize>(v: &[BFloat16; N], i: usize) -> __m256 {
+    let bits = _mm_load_si128(v.as_ptr().add(i) as *const __m128i);
+    _mm256_castsi256_ps(_mm256_slli_epi32::<16>(_mm256_cvtepu16_epi32(bits)))
+}
+
+#[cfg(test)]
+mod bf16_distance_test {
+    use approx::assert_abs_diff_eq;
+
+    use super::*;
+
+    #[repr(C, align(32))]
+    struct BF16Slice24([BFloat16; 24]);
+
+    fn to_bf16(values: [f32; 24]) -> BF16Slice24 {
+        BF16Slice24(values.map(BFloat16::from_f32))
+    }
+
+    fn widened(v: &BF16Slice24) -> Vec<f32> {
+        v.0.iter().map(|x| x.to_f32()).collect()
+    }
+
+    #[test]
+    fn bf16_kernels_match_novector() {
+        let a = to_bf16(std::array::from_fn(|i| (i as f32 * 0.7).sin() * 3.0));
+        let b = to_bf16(std::array::from_fn(|i| i as f32 * 0.25 - 2.0));
+        let (a_f32, b_f32) = (widened(&a), widened(&b));
+
+        let l2: f32 = a_f32.iter().zip(&b_f32).map(|(x, y)| (x - y).powi(2)).sum();
+        let l1: f32 = a_f32.iter().zip(&b_f32).map(|(x, y)| (x - y).abs()).sum();
+        let chebyshev = a_f32.iter().zip(&b_f32).map(|(x, y)| (x - y).abs()).fold(0.0, f32::max);
+        let dot: f32 = a_f32.iter().zip(&b_f32).map(|(x, y)| x * y).sum();
+        let norm_a: f32 = a_f32.iter().map(|x| x * x).sum();
+        let norm_b: f32 = b_f32.iter().map(|x| x * x).sum();
+
+        assert_abs_diff_eq!(distance_l2_vector_bf16::<24>(&a.0, &b.0), l2, epsilon = 1e-4);
+        assert_abs_diff_eq!(distance_l1_vector_bf16::<24>(&a.0, &b.0), l1, epsilon = 1e-4);
+        assert_eq!(distance_chebyshev_vector_bf16::<24>(&a.0, &b.0), chebyshev);
+        assert_abs_diff_eq!(
+            distance_cosine_vector_bf16::<24>(&a.0, &b.0),
+            1.0 - dot / (norm_a.sqrt() * norm_b.sqrt()),
+            epsilon = 1e-5
+        );
+    }
+
+    #[test]
+    fn bf16_keeps_f32_range() {
+        // 1e30 overflows f16 but is representable in bf16
+        let a = to_bf16([1e30; 24]);
+        let b = to_bf16([0.0; 24]);
+        let expected = 24.0 * a.0[0].to_f32();
+
+        assert_abs_diff_eq!(distance_l1_vector_bf16::<24>(&a.0, &b.0) / expected, 1.0, epsilon = 1e-6);
+        assert_eq!(distance_l2_vector_bf16::<24>(&a.0, &a.0), 0.0);
+    }
+}
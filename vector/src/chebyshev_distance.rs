@@ -0,0 +1,181 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Distance calculation for Chebyshev (L∞) Metric
+
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+use crate::Half;
+#[cfg(not(target_arch = "x86_64"))]
+use crate::{subspace_distance::distance_subspace_novector, Metric};
+
+/// Calculate the Chebyshev distance by vector arithmetic
+#[cfg(target_arch = "x86_64")]
+#[inline(never)]
+pub fn distance_chebyshev_vector_f32<const N: usize>(a: &[f32; N], b: &[f32; N]) -> f32 {
+    debug_assert_eq!(N % 8, 0);
+
+    // make sure the addresses are bytes aligned
+    debug_assert_eq!(a.as_ptr().align_offset(32), 0);
+    debug_assert_eq!(b.as_ptr().align_offset(32), 0);
+
+    unsafe {
+        let mut max = _mm256_setzero_ps();
+
+        // Iterate over the elements in steps of 8
+        for i in (0..N).step_by(8) {
+            let a_vec = _mm256_load_ps(&a[i]);
+            let b_vec = _mm256_load_ps(&b[i]);
+            max = _mm256_max_ps(max, abs_diff(a_vec, b_vec));
+        }
+
+        horizontal_max(max)
+    }
+}
+
+/// Calculate the Chebyshev distance element by element, on targets without AVX2
+#[cfg(not(target_arch = "x86_64"))]
+#[inline(never)]
+pub fn distance_chebyshev_vector_f32<const N: usize>(a: &[f32; N], b: &[f32; N]) -> f32 {
+    distance_subspace_novector(a, b, Metric::Chebyshev)
+}
+
+/// Calculate the Chebyshev distance by vector arithmetic
+#[cfg(target_arch = "x86_64")]
+#[inline(never)]
+pub fn distance_chebyshev_vector_f16<const N: usize>(a: &[Half; N], b: &[Half; N]) -> f32 {
+    debug_assert_eq!(N % 8, 0);
+
+    // make sure the addresses are 16 bytes aligned, see distance_l2_vector_f16
+    debug_assert_eq!(a.as_ptr().align_offset(16), 0);
+    debug_assert_eq!(b.as_ptr().align_offset(16), 0);
+
+    unsafe {
+        let mut max = _mm256_setzero_ps();
+        let a_ptr = a.as_ptr() as *const __m128i;
+        let b_ptr = b.as_ptr() as *const __m128i;
+
+        // Iterate over the elements in steps of 8
+        for i in (0..N).step_by(8) {
+            let a_vec = _mm256_cvtph_ps(_mm_load_si128(a_ptr.add(i / 8)));
+            let b_vec = _mm256_cvtph_ps(_mm_load_si128(b_ptr.add(i / 8)));
+            max = _mm256_max_ps(max, abs_diff(a_vec, b_vec));
+        }
+
+        horizontal_max(max)
+    }
+}
+
+/// Calculate the Chebyshev distance element by element, on targets without AVX2
+#[cfg(not(target_arch = "x86_64"))]
+#[inline(never)]
+pub fn distance_chebyshev_vector_f16<const N: usize>(a: &[Half; N], b: &[Half; N]) -> f32 {
+    distance_subspace_novector(a, b, Metric::Chebyshev)
+}
+
+/// Calculate the Chebyshev distance between two i8 vectors
+#[inline(never)]
+pub fn distance_chebyshev_vector_i8<const N: usize>(a: &[i8; N], b: &[i8; N]) -> f32 {
+    let mut max = 0i32;
+    for i in 0..N {
+        max = max.max((a[i] as i32 - b[i] as i32).abs());
+    }
+    max as f32
+}
+
+/// Calculate the Chebyshev distance between two u8 vectors
+#[inline(never)]
+pub fn distance_chebyshev_vector_u8<const N: usize>(a: &[u8; N], b: &[u8; N]) -> f32 {
+    let mut max = 0u8;
+    for i in 0..N {
+        max = max.max(a[i].abs_diff(b[i]));
+    }
+    max as f32
+}
+
+/// |a - b| by clearing the sign bit of the difference
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+unsafe fn abs_diff(a: __m256, b: __m256) -> __m256 {
+    _mm256_andnot_ps(_mm256_set1_ps(-0.0), _mm256_sub_ps(a, b))
+}
+
+/// Largest of the 8 lanes
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+pub(crate) unsafe fn horizontal_max(max: __m256) -> f32 {
+    let x128: __m128 = _mm_max_ps(_mm256_extractf128_ps(max, 1), _mm256_castps256_ps128(max));
+    let x64: __m128 = _mm_max_ps(x128, _mm_movehl_ps(x128, x128));
+    let x32: __m128 = _mm_max_ss(x64, _mm_shuffle_ps(x64, x64, 0x55));
+    _mm_cvtss_f32(x32)
+}
+
+#[cfg(test)]
+mod chebyshev_distance_test {
+    use super::*;
+
+    #[repr(C, align(32))]
+    struct F32Slice16([f32; 16]);
+
+    #[repr(C, align(32))]
+    struct F16Slice16([Half; 16]);
+
+    fn no_vector_chebyshev(a: &[f32], b: &[f32]) -> f32 {
+        a.iter()
+            .zip(b)
+            .map(|(x, y)| (x - y).abs())
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn chebyshev_f32_matches_novector() {
+        let a = F32Slice16(std::array::from_fn(|i| i as f32 - 7.5));
+        let b = F32Slice16(std::array::from_fn(|i| (i as f32 * 0.3).sin()));
+
+        // The largest difference is in every lane position in turn
+        for lane in 0..16 {
+            let mut c = F32Slice16(b.0);
+            c.0[lane] = 100.0;
+            assert_eq!(
+                distance_chebyshev_vector_f32::<16>(&a.0, &c.0),
+                no_vector_chebyshev(&a.0, &c.0)
+            );
+        }
+        assert_eq!(
+            distance_chebyshev_vector_f32::<16>(&a.0, &b.0),
+            no_vector_chebyshev(&a.0, &b.0)
+        );
+        assert_eq!(distance_chebyshev_vector_f32::<16>(&a.0, &a.0), 0.0);
+    }
+
+    #[test]
+    fn chebyshev_f16_and_integers_match_novector() {
+        let a: [f32; 16] = std::array::from_fn(|i| i as f32 - 3.0);
+        let b: [f32; 16] = std::array::from_fn(|i| 10.0 - 2.0 * i as f32);
+        let expected = no_vector_chebyshev(&a, &b);
+
+        let a_f16 = F16Slice16(a.map(Half::from_f32));
+        let b_f16 = F16Slice16(b.map(Half::from_f32));
+        assert_eq!(
+            distance_chebyshev_vector_f16::<16>(&a_f16.0, &b_f16.0),
+            expected
+        );
+
+        assert_eq!(
+            distance_chebyshev_vector_i8::<16>(&a.map(|x| x as i8), &b.map(|x| x as i8)),
+            expected
+        );
+        assert_eq!(
+            distance_chebyshev_vector_i8::<16>(&[i8::MIN; 16], &[i8::MAX; 16]),
+            255.0
+        );
+        assert_eq!(
+            distance_chebyshev_vector_u8::<16>(&[0; 16], &[u8::MAX; 16]),
+            255.0
+        );
+    }
+}
@@ -0,0 +1,202 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Distance calculation for Cosine Metric.
+//! The distance is 1 - cos(a, b), so it lies in [0, 2] and smaller is closer like L2.
+//! Vectors don't need to be normalized beforehand.
+
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+#[cfg(target_arch = "x86_64")]
+use crate::simd::horizontal_sum;
+use crate::Half;
+#[cfg(not(target_arch = "x86_64"))]
+use crate::{subspace_distance::distance_subspace_novector, Metric};
+
+/// Calculate the cosine distance by vector arithmetic
+#[cfg(target_arch = "x86_64")]
+#[inline(never)]
+pub fn distance_cosine_vector_f32<const N: usize>(a: &[f32; N], b: &[f32; N]) -> f32 {
+    debug_assert_eq!(N % 8, 0);
+
+    // make sure the addresses are bytes aligned
+    debug_assert_eq!(a.as_ptr().align_offset(32), 0);
+    debug_assert_eq!(b.as_ptr().align_offset(32), 0);
+
+    unsafe {
+        let mut dot = _mm256_setzero_ps();
+        let mut norm_a = _mm256_setzero_ps();
+        let mut norm_b = _mm256_setzero_ps();
+
+        // Iterate over the elements in steps of 8
+        for i in (0..N).step_by(8) {
+            let a_vec = _mm256_load_ps(&a[i]);
+            let b_vec = _mm256_load_ps(&b[i]);
+            dot = _mm256_fmadd_ps(a_vec, b_vec, dot);
+            norm_a = _mm256_fmadd_ps(a_vec, a_vec, norm_a);
+            norm_b = _mm256_fmadd_ps(b_vec, b_vec, norm_b);
+        }
+
+        cosine_distance(horizontal_sum(dot), horizontal_sum(norm_a), horizontal_sum(norm_b))
+    }
+}
+
+/// Calculate the cosine distance element by element, on targets without AVX2
+#[cfg(not(target_arch = "x86_64"))]
+#[inline(never)]
+pub fn distance_cosine_vector_f32<const N: usize>(a: &[f32; N], b: &[f32; N]) -> f32 {
+    distance_subspace_novector(a, b, Metric::Cosine)
+}
+
+/// Calculate the cosine distance by vector arithmetic
+#[cfg(target_arch = "x86_64")]
+#[inline(never)]
+pub fn distance_cosine_vector_f16<const N: usize>(a: &[Half; N], b: &[Half; N]) -> f32 {
+    debug_assert_eq!(N % 8, 0);
+
+    // make sure the addresses are 16 bytes aligned, see distance_l2_vector_f16
+    debug_assert_eq!(a.as_ptr().align_offset(16), 0);
+    debug_assert_eq!(b.as_ptr().align_offset(16), 0);
+
+    unsafe {
+        let mut dot = _mm256_setzero_ps();
+        let mut norm_a = _mm256_setzero_ps();
+        let mut norm_b = _mm256_setzero_ps();
+        let a_ptr = a.as_ptr() as *const __m128i;
+        let b_ptr = b.as_ptr() as *const __m128i;
+
+        // Iterate over the elements in steps of 8
+        for i in (0..N).step_by(8) {
+            let a_vec = _mm256_cvtph_ps(_mm_load_si128(a_ptr.add(i / 8)));
+            let b_vec = _mm256_cvtph_ps(_mm_load_si128(b_ptr.add(i / 8)));
+            dot = _mm256_fmadd_ps(a_vec, b_vec, dot);
+            norm_a = _mm256_fmadd_ps(a_vec, a_vec, norm_a);
+            norm_b = _mm256_fmadd_ps(b_vec, b_vec, norm_b);
+        }
+
+        cosine_distance(horizontal_sum(dot), horizontal_sum(norm_a), horizontal_sum(norm_b))
+    }
+}
+
+/// Calculate the cosine distance element by element, on targets without AVX2
+#[cfg(not(target_arch = "x86_64"))]
+#[inline(never)]
+pub fn distance_cosine_vector_f16<const N: usize>(a: &[Half; N], b: &[Half; N]) -> f32 {
+    distance_subspace_novector(a, b, Metric::Cosine)
+}
+
+/// Calculate the cosine distance between two i8 vectors, accumulating in i32
+#[inline(never)]
+pub fn distance_cosine_vector_i8<const N: usize>(a: &[i8; N], b: &[i8; N]) -> f32 {
+    let mut dot = 0i32;
+    let mut norm_a = 0i32;
+    let mut norm_b = 0i32;
+    for i in 0..N {
+        let (x, y) = (a[i] as i32, b[i] as i32);
+        dot += x * y;
+        norm_a += x * x;
+        norm_b += y * y;
+    }
+
+    cosine_distance(dot as f32, norm_a as f32, norm_b as f32)
+}
+
+/// Calculate the cosine distance between two u8 vectors, accumulating in u32
+#[inline(never)]
+pub fn distance_cosine_vector_u8<const N: usize>(a: &[u8; N], b: &[u8; N]) -> f32 {
+    let mut dot = 0u32;
+    let mut norm_a = 0u32;
+    let mut norm_b = 0u32;
+    for i in 0..N {
+        let (x, y) = (a[i] as u32, b[i] as u32);
+        dot += x * y;
+        norm_a += x * x;
+        norm_b += y * y;
+    }
+
+    cosine_distance(dot as f32, norm_a as f32, norm_b as f32)
+}
+
+/// Calculate the dot product of two i8 vectors, widening to i32 so the loop auto-vectorizes
+#[cfg(target_arch = "x86_64")]
+#[inline(never)]
+pub fn dot_product_vector_i8<const N: usize>(a: &[i8; N], b: &[i8; N]) -> i32 {
+    let mut dot = 0i32;
+    for i in 0..N {
+        dot += a[i] as i32 * b[i] as i32;
+    }
+    dot
+}
+
+/// 1 - dot / (|a| * |b|). A zero vector has no direction, so it is treated as orthogonal to everything.
+#[inline(always)]
+pub(crate) fn cosine_distance(dot: f32, norm_a_sq: f32, norm_b_sq: f32) -> f32 {
+    if norm_a_sq == 0.0 || norm_b_sq == 0.0 {
+        return 1.0;
+    }
+
+    1.0 - dot / (norm_a_sq.sqrt() * norm_b_sq.sqrt())
+}
+
+#[cfg(test)]
+mod cosine_distance_test {
+    use approx::assert_abs_diff_eq;
+
+    use super::*;
+
+    #[repr(C, align(32))]
+    struct F32Slice16([f32; 16]);
+
+    #[repr(C, align(32))]
+    struct F16Slice16([Half; 16]);
+
+    fn no_vector_cosine(a: &[f32], b: &[f32]) -> f32 {
+        let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+        let norm_a: f32 = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+        let norm_b: f32 = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+        1.0 - dot / (norm_a * norm_b)
+    }
+
+    #[test]
+    fn cosine_f32_matches_novector() {
+        let a = F32Slice16(std::array::from_fn(|i| i as f32 - 7.5));
+        let b = F32Slice16(std::array::from_fn(|i| (i as f32 * 0.3).sin()));
+
+        assert_abs_diff_eq!(
+            distance_cosine_vector_f32::<16>(&a.0, &b.0),
+            no_vector_cosine(&a.0, &b.0),
+            epsilon = 1e-6
+        );
+    }
+
+    #[test]
+    fn cosine_is_scale_invariant() {
+        let a = F32Slice16(std::array::from_fn(|i| i as f32 + 1.0));
+        let scaled = F32Slice16(std::array::from_fn(|i| (i as f32 + 1.0) * 42.0));
+        let opposite = F32Slice16(std::array::from_fn(|i| -(i as f32 + 1.0)));
+        let zero = F32Slice16([0.0; 16]);
+
+        assert_abs_diff_eq!(distance_cosine_vector_f32::<16>(&a.0, &scaled.0), 0.0, epsilon = 1e-6);
+        assert_abs_diff_eq!(distance_cosine_vector_f32::<16>(&a.0, &opposite.0), 2.0, epsilon = 1e-6);
+        assert_eq!(distance_cosine_vector_f32::<16>(&a.0, &zero.0), 1.0);
+    }
+
+    #[test]
+    fn cosine_f16_and_i8_match_novector() {
+        let a: [f32; 16] = std::array::from_fn(|i| i as f32 - 3.0);
+        let b: [f32; 16] = std::array::from_fn(|i| 10.0 - i as f32);
+        let expected = no_vector_cosine(&a, &b);
+
+        let a_f16 = F16Slice16(a.map(Half::from_f32));
+        let b_f16 = F16Slice16(b.map(Half::from_f32));
+        assert_abs_diff_eq!(distance_cosine_vector_f16::<16>(&a_f16.0, &b_f16.0), expected, epsilon = 1e-3);
+
+        let a_i8 = a.map(|x| x as i8);
+        let b_i8 = b.map(|x| x as i8);
+        assert_abs_diff_eq!(distance_cosine_vector_i8::<16>(&a_i8, &b_i8), expected, epsilon = 1e-6);
+    }
+}
@@ -2,52 +2,253 @@
  * Copyright (c) Microsoft Corporation. All rights reserved.
  * Licensed under the MIT license.
  */
-use crate::l2_float_distance::{distance_l2_vector_f16, distance_l2_vector_f32};
-use crate::{Half, Metric};
+use crate::bf16_distance::{
+    distance_chebyshev_vector_bf16, distance_cosine_vector_bf16, distance_l1_vector_bf16,
+    distance_l2_vector_bf16,
+};
+use std::ops::Range;
+
+use crate::chebyshev_distance::{
+    distance_chebyshev_vector_f16, distance_chebyshev_vector_f32, distance_chebyshev_vector_i8,
+    distance_chebyshev_vector_u8,
+};
+use crate::cosine_distance::{
+    distance_cosine_vector_f16, distance_cosine_vector_f32, distance_cosine_vector_u8,
+};
+use crate::hamming_distance::{distance_hamming_i8, distance_hamming_u8};
+use crate::l1_distance::{
+    distance_l1_vector_f16, distance_l1_vector_f32, distance_l1_vector_i8, distance_l1_vector_u8,
+};
+use crate::l2_float_distance::{distance_l2_vector_f16, distance_l2_vector_u8};
+use crate::simd_dispatch::{
+    distance_cosine_i8, distance_l2_argmin_f32, distance_l2_f32, distance_l2_i8,
+};
+use crate::subspace_distance::{
+    distance_cosine_slice_f32, distance_l2_slice_f32, distance_subspace_novector,
+};
+use crate::tanimoto_distance::{distance_tanimoto_i8, distance_tanimoto_u8};
+use crate::weighted_distance::{
+    distance_cosine_weighted_f32, distance_l2_weighted_f32, distance_weighted_novector,
+};
+use crate::{BFloat16, Half, Metric};
 
 /// Distance contract for full-precision vertex
 pub trait FullPrecisionDistance<T, const N: usize> {
     /// Get the distance between vertex a and vertex b
     fn distance_compare(a: &[T; N], b: &[T; N], vec_type: Metric) -> f32;
+
+    /// Get the position and distance of the candidate closest to vertex a, without
+    /// materializing the distances. The first candidate wins ties. None if there are no candidates.
+    #[inline(always)]
+    fn distance_argmin(a: &[T; N], candidates: &[&[T; N]], metric: Metric) -> Option<(usize, f32)>
+    where
+        Self: Sized,
+    {
+        argmin_by(candidates, |b| Self::distance_compare(a, b, metric))
+    }
+
+    /// Get the distance between vertex a and vertex b with a weight per dimension
+    #[inline(always)]
+    fn distance_compare_weighted(a: &[T; N], b: &[T; N], weights: &[f32; N], metric: Metric) -> f32
+    where
+        T: Copy + Into<f32>,
+    {
+        distance_weighted_novector(a, b, weights, metric)
+    }
+
+    /// Get the distance between vertex a and vertex b over the dimensions in dims only
+    #[inline(always)]
+    fn distance_compare_subspace(a: &[T; N], b: &[T; N], dims: Range<usize>, metric: Metric) -> f32
+    where
+        T: Copy + Into<f32>,
+    {
+        distance_subspace_novector(&a[dims.clone()], &b[dims], metric)
+    }
+}
+
+/// Distance an index is generic over, so user-defined metrics (e.g. weighted L2 or Poincaré)
+/// can be plugged in without changes to this crate. `metric` is the metric of the index
+/// configuration, custom distances are free to ignore it.
+pub trait Distance<T, const N: usize>: Send + Sync {
+    /// Get the distance between vertex a and vertex b, smaller is closer
+    fn distance(&self, a: &[T; N], b: &[T; N], metric: Metric) -> f32;
+
+    /// Get the position and distance of the candidate closest to vertex a.
+    /// The first candidate wins ties. None if there are no candidates.
+    #[inline(always)]
+    fn argmin(&self, a: &[T; N], candidates: &[&[T; N]], metric: Metric) -> Option<(usize, f32)> {
+        argmin_by(candidates, |b| self.distance(a, b, metric))
+    }
+
+    /// Get the distance with a weight per dimension given at query time.
+    /// The default ignores the weights, distances that support weighting override it.
+    #[inline(always)]
+    fn distance_weighted(&self, a: &[T; N], b: &[T; N], _weights: &[f32; N], metric: Metric) -> f32 {
+        self.distance(a, b, metric)
+    }
+
+    /// Get the distance over a contiguous range of dimensions given at query time.
+    /// The default compares all dimensions, distances that support subspaces override it.
+    #[inline(always)]
+    fn distance_subspace(&self, a: &[T; N], b: &[T; N], _dims: Range<usize>, metric: Metric) -> f32 {
+        self.distance(a, b, metric)
+    }
+}
+
+/// The built-in metrics, dispatched to the SIMD kernels of FullPrecisionDistance
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BuiltinDistance;
+
+impl<T, const N: usize> Distance<T, N> for BuiltinDistance
+where
+    T: Copy + Into<f32>,
+    [T; N]: FullPrecisionDistance<T, N>,
+{
+    #[inline(always)]
+    fn distance(&self, a: &[T; N], b: &[T; N], metric: Metric) -> f32 {
+        <[T; N]>::distance_compare(a, b, metric)
+    }
+
+    #[inline(always)]
+    fn argmin(&self, a: &[T; N], candidates: &[&[T; N]], metric: Metric) -> Option<(usize, f32)> {
+        <[T; N]>::distance_argmin(a, candidates, metric)
+    }
+
+    #[inline(always)]
+    fn distance_weighted(&self, a: &[T; N], b: &[T; N], weights: &[f32; N], metric: Metric) -> f32 {
+        <[T; N]>::distance_compare_weighted(a, b, weights, metric)
+    }
+
+    #[inline(always)]
+    fn distance_subspace(&self, a: &[T; N], b: &[T; N], dims: Range<usize>, metric: Metric) -> f32 {
+        <[T; N]>::distance_compare_subspace(a, b, dims, metric)
+    }
 }
 
-// reason = "Not supported Metric type Metric::Cosine"
+/// Scan the candidates keeping only the running best
+#[inline(always)]
+fn argmin_by<T, const N: usize>(
+    candidates: &[&[T; N]],
+    distance: impl Fn(&[T; N]) -> f32,
+) -> Option<(usize, f32)> {
+    let mut best: Option<(usize, f32)> = None;
+    for (i, b) in candidates.iter().enumerate() {
+        let distance = distance(b);
+        if best.is_none_or(|(_, best_distance)| distance < best_distance) {
+            best = Some((i, distance));
+        }
+    }
+    best
+}
+
+// reason = "Hamming and Tanimoto distances are only defined over packed binary vectors (u8/i8)"
 #[allow(clippy::panic)]
 impl<const N: usize> FullPrecisionDistance<f32, N> for [f32; N] {
     /// Calculate distance between two f32 Vertex
     #[inline(always)]
     fn distance_compare(a: &[f32; N], b: &[f32; N], metric: Metric) -> f32 {
         match metric {
-            Metric::L2 => distance_l2_vector_f32::<N>(a, b),
-            _ => panic!("Not supported Metric type {:?}", metric),
+            Metric::L2 => distance_l2_f32::<N>(a, b),
+            Metric::L1 => distance_l1_vector_f32::<N>(a, b),
+            Metric::Chebyshev => distance_chebyshev_vector_f32::<N>(a, b),
+            Metric::Cosine => distance_cosine_vector_f32::<N>(a, b),
+            Metric::Hamming | Metric::Tanimoto => {
+                panic!("{:?} distance is not supported for VectorType f32", metric)
+            }
+        }
+    }
+
+    /// Find the closest f32 candidate, four candidates per pass for L2
+    #[inline(always)]
+    fn distance_argmin(a: &[f32; N], candidates: &[&[f32; N]], metric: Metric) -> Option<(usize, f32)> {
+        match metric {
+            Metric::L2 => distance_l2_argmin_f32::<N>(a, candidates),
+            _ => argmin_by(candidates, |b| Self::distance_compare(a, b, metric)),
+        }
+    }
+
+    /// Weighted f32 L2 and cosine have vector kernels
+    #[inline(always)]
+    fn distance_compare_weighted(a: &[f32; N], b: &[f32; N], weights: &[f32; N], metric: Metric) -> f32 {
+        match metric {
+            Metric::L2 => distance_l2_weighted_f32::<N>(a, b, weights),
+            Metric::Cosine => distance_cosine_weighted_f32::<N>(a, b, weights),
+            _ => distance_weighted_novector(a, b, weights, metric),
+        }
+    }
+
+    /// f32 L2 and cosine over a range of dimensions have vector kernels
+    #[inline(always)]
+    fn distance_compare_subspace(a: &[f32; N], b: &[f32; N], dims: Range<usize>, metric: Metric) -> f32 {
+        match metric {
+            Metric::L2 => distance_l2_slice_f32(&a[dims.clone()], &b[dims]),
+            Metric::Cosine => distance_cosine_slice_f32(&a[dims.clone()], &b[dims]),
+            _ => distance_subspace_novector(&a[dims.clone()], &b[dims], metric),
         }
     }
 }
 
-// reason = "Not supported Metric type Metric::Cosine"
+// reason = "Hamming and Tanimoto distances are only defined over packed binary vectors (u8/i8)"
 #[allow(clippy::panic)]
 impl<const N: usize> FullPrecisionDistance<Half, N> for [Half; N] {
     fn distance_compare(a: &[Half; N], b: &[Half; N], metric: Metric) -> f32 {
         match metric {
             Metric::L2 => distance_l2_vector_f16::<N>(a, b),
-            _ => panic!("Not supported Metric type {:?}", metric),
+            Metric::L1 => distance_l1_vector_f16::<N>(a, b),
+            Metric::Chebyshev => distance_chebyshev_vector_f16::<N>(a, b),
+            Metric::Cosine => distance_cosine_vector_f16::<N>(a, b),
+            Metric::Hamming | Metric::Tanimoto => {
+                panic!("{:?} distance is not supported for VectorType f16", metric)
+            }
         }
     }
 }
 
-// reason = "Not yet supported Vector i8"
+// reason = "Hamming and Tanimoto distances are only defined over packed binary vectors (u8/i8)"
 #[allow(clippy::panic)]
+impl<const N: usize> FullPrecisionDistance<BFloat16, N> for [BFloat16; N] {
+    /// Calculate distance between two bf16 Vertex, widened to f32 lanes
+    #[inline(always)]
+    fn distance_compare(a: &[BFloat16; N], b: &[BFloat16; N], metric: Metric) -> f32 {
+        match metric {
+            Metric::L2 => distance_l2_vector_bf16::<N>(a, b),
+            Metric::L1 => distance_l1_vector_bf16::<N>(a, b),
+            Metric::Chebyshev => distance_chebyshev_vector_bf16::<N>(a, b),
+            Metric::Cosine => distance_cosine_vector_bf16::<N>(a, b),
+            Metric::Hamming | Metric::Tanimoto => {
+                panic!("{:?} distance is not supported for VectorType bf16", metric)
+            }
+        }
+    }
+}
+
 impl<const N: usize> FullPrecisionDistance<i8, N> for [i8; N] {
-    fn distance_compare(_a: &[i8; N], _b: &[i8; N], _metric: Metric) -> f32 {
-        panic!("Not supported VectorType i8")
+    /// Calculate distance between two i8 Vertex
+    #[inline(always)]
+    fn distance_compare(a: &[i8; N], b: &[i8; N], metric: Metric) -> f32 {
+        match metric {
+            Metric::L2 => distance_l2_i8::<N>(a, b),
+            Metric::L1 => distance_l1_vector_i8::<N>(a, b),
+            Metric::Chebyshev => distance_chebyshev_vector_i8::<N>(a, b),
+            Metric::Cosine => distance_cosine_i8::<N>(a, b),
+            Metric::Hamming => distance_hamming_i8::<N>(a, b),
+            Metric::Tanimoto => distance_tanimoto_i8::<N>(a, b),
+        }
     }
 }
 
-// reason = "Not yet supported Vector u8"
-#[allow(clippy::panic)]
 impl<const N: usize> FullPrecisionDistance<u8, N> for [u8; N] {
-    fn distance_compare(_a: &[u8; N], _b: &[u8; N], _metric: Metric) -> f32 {
-        panic!("Not supported VectorType u8")
+    /// Calculate distance between two u8 Vertex. With Hamming and Tanimoto the bytes are packed binary codes.
+    #[inline(always)]
+    fn distance_compare(a: &[u8; N], b: &[u8; N], metric: Metric) -> f32 {
+        match metric {
+            Metric::L2 => distance_l2_vector_u8::<N>(a, b),
+            Metric::L1 => distance_l1_vector_u8::<N>(a, b),
+            Metric::Chebyshev => distance_chebyshev_vector_u8::<N>(a, b),
+            Metric::Cosine => distance_cosine_vector_u8::<N>(a, b),
+            Metric::Hamming => distance_hamming_u8::<N>(a, b),
+            Metric::Tanimoto => distance_tanimoto_u8::<N>(a, b),
+        }
     }
 }
 
@@ -339,6 +540,21 @@ mod distance_test {
         assert_eq!(distance, no_vector_compare_f16(&a_slice.0, &b_slice.0));
     }
 
+    #[test]
+    fn test_dist_cosine_float_turing() {
+        let (a_slice, b_slice) = get_turing_test_data();
+        let distance = <[f32; 112] as FullPrecisionDistance<f32, 112>>::distance_compare(
+            &a_slice.0,
+            &b_slice.0,
+            Metric::Cosine,
+        );
+
+        let dot: f32 = a_slice.0.iter().zip(b_slice.0.iter()).map(|(a, b)| a * b).sum();
+        let norm_a: f32 = a_slice.0.iter().map(|a| a * a).sum::<f32>().sqrt();
+        let norm_b: f32 = b_slice.0.iter().map(|b| b * b).sum::<f32>().sqrt();
+        assert_abs_diff_eq!(distance, 1.0 - dot / (norm_a * norm_b), epsilon = 1e-6);
+    }
+
     #[test]
     fn distance_test() {
         #[repr(C, align(32))]
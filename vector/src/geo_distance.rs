@@ -0,0 +1,96 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Great-circle distance between points on a sphere, a Distance for indices of geographic points.
+//! Each vector holds the latitude then the longitude of a point in degrees, the other dimensions
+//! are ignored.
+
+use crate::{Distance, Metric};
+
+/// Mean radius of the Earth in kilometers
+pub const EARTH_RADIUS_KM: f32 = 6371.009;
+
+/// Haversine distance between lat/lon points, in the unit of the sphere radius.
+/// The metric of the index configuration is ignored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HaversineDistance {
+    radius: f32,
+}
+
+impl HaversineDistance {
+    /// Distances over a sphere of the given radius
+    pub fn new(radius: f32) -> Self {
+        Self { radius }
+    }
+
+    /// Radius of the sphere
+    pub fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    /// Distance between two points given as latitude and longitude in degrees
+    #[inline]
+    pub fn between(&self, lat_a: f32, lon_a: f32, lat_b: f32, lon_b: f32) -> f32 {
+        // f64 keeps the distance of nearby points from drowning in rounding
+        let (lat_a, lat_b) = ((lat_a as f64).to_radians(), (lat_b as f64).to_radians());
+        let d_lat = lat_b - lat_a;
+        let d_lon = (lon_b as f64 - lon_a as f64).to_radians();
+
+        let h =
+            (d_lat / 2.0).sin().powi(2) + lat_a.cos() * lat_b.cos() * (d_lon / 2.0).sin().powi(2);
+        (2.0 * h.sqrt().min(1.0).asin() * self.radius as f64) as f32
+    }
+}
+
+impl Default for HaversineDistance {
+    /// Distances in kilometers over the Earth
+    fn default() -> Self {
+        Self::new(EARTH_RADIUS_KM)
+    }
+}
+
+impl<const N: usize> Distance<f32, N> for HaversineDistance {
+    #[inline]
+    fn distance(&self, a: &[f32; N], b: &[f32; N], _metric: Metric) -> f32 {
+        self.between(a[0], a[1], b[0], b[1])
+    }
+}
+
+#[cfg(test)]
+mod geo_distance_test {
+    use approx::assert_relative_eq;
+
+    use super::*;
+
+    #[test]
+    fn haversine_matches_known_distances() {
+        let earth = HaversineDistance::default();
+        let london = [51.5074f32, -0.1278, 0.0, 0.0];
+        let paris = [48.8566f32, 2.3522, 0.0, 0.0];
+        let sydney = [-33.8688f32, 151.2093, 0.0, 0.0];
+
+        assert_relative_eq!(
+            earth.distance(&london, &paris, Metric::L2),
+            343.5,
+            max_relative = 1e-3
+        );
+        assert_relative_eq!(
+            earth.distance(&london, &sydney, Metric::L2),
+            16993.9,
+            max_relative = 1e-3
+        );
+        assert_eq!(earth.distance(&paris, &paris, Metric::L2), 0.0);
+
+        // Across the antimeridian and between the poles
+        assert_relative_eq!(
+            earth.between(0.0, 179.5, 0.0, -179.5),
+            111.195,
+            max_relative = 1e-3
+        );
+        let unit = HaversineDistance::new(1.0);
+        assert_relative_eq!(unit.between(90.0, 0.0, -90.0, 0.0), std::f32::consts::PI);
+    }
+}
@@ -0,0 +1,91 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Distance calculation for Hamming Metric over packed binary vectors.
+//! Each byte holds 8 dimensions, so a vector of N bytes is an 8*N bit code.
+
+/// Bytes consumed per popcount
+const WORD_BYTES: usize = std::mem::size_of::<u64>();
+
+/// Calculate the number of differing bits between two packed binary vectors
+#[inline(never)]
+pub fn distance_hamming_u8<const N: usize>(a: &[u8; N], b: &[u8; N]) -> f32 {
+    hamming_bytes(a, b) as f32
+}
+
+/// Calculate the number of differing bits between two packed binary vectors stored as i8
+#[inline(never)]
+pub fn distance_hamming_i8<const N: usize>(a: &[i8; N], b: &[i8; N]) -> f32 {
+    let a_bytes: &[u8] = bytemuck::cast_slice(a);
+    let b_bytes: &[u8] = bytemuck::cast_slice(b);
+    hamming_bytes(a_bytes, b_bytes) as f32
+}
+
+/// XOR and popcount a word at a time, then finish the tail byte by byte
+#[inline(always)]
+fn hamming_bytes(a: &[u8], b: &[u8]) -> u32 {
+    debug_assert_eq!(a.len(), b.len());
+
+    let a_words = a.chunks_exact(WORD_BYTES);
+    let b_words = b.chunks_exact(WORD_BYTES);
+    let tail: u32 = a_words
+        .remainder()
+        .iter()
+        .zip(b_words.remainder())
+        .map(|(x, y)| (x ^ y).count_ones())
+        .sum();
+
+    a_words
+        .zip(b_words)
+        .map(|(x, y)| (word(x) ^ word(y)).count_ones())
+        .sum::<u32>()
+        + tail
+}
+
+#[inline(always)]
+fn word(bytes: &[u8]) -> u64 {
+    let mut buf = [0u8; WORD_BYTES];
+    buf.copy_from_slice(bytes);
+    u64::from_ne_bytes(buf)
+}
+
+#[cfg(test)]
+mod hamming_distance_test {
+    use rand::Rng;
+
+    use super::*;
+
+    fn no_vector_hamming(a: &[u8], b: &[u8]) -> f32 {
+        let mut bits = 0;
+        for (x, y) in a.iter().zip(b) {
+            for bit in 0..8 {
+                if (x >> bit) & 1 != (y >> bit) & 1 {
+                    bits += 1;
+                }
+            }
+        }
+        bits as f32
+    }
+
+    #[test]
+    fn hamming_matches_novector() {
+        let mut rng = rand::thread_rng();
+        let a: [u8; 36] = std::array::from_fn(|_| rng.gen());
+        let b: [u8; 36] = std::array::from_fn(|_| rng.gen());
+
+        assert_eq!(distance_hamming_u8::<36>(&a, &b), no_vector_hamming(&a, &b));
+        assert_eq!(distance_hamming_u8::<36>(&a, &a), 0.0);
+    }
+
+    #[test]
+    fn hamming_i8_counts_sign_bits() {
+        let a = [0i8; 16];
+        let b = [-1i8; 16];
+
+        assert_eq!(distance_hamming_i8::<16>(&a, &b), 128.0);
+        assert_eq!(distance_hamming_u8::<16>(&[0; 16], &[u8::MAX; 16]), 128.0);
+    }
+}
@@ -6,21 +6,26 @@
 
 //! Distance calculation for L2 Metric
 
-#[cfg(not(target_feature = "avx2"))]
+#[cfg(all(target_arch = "x86_64", not(target_feature = "avx2")))]
 compile_error!("Library must be compiled with -C target-feature=+avx2");
 
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::*;
 
 use crate::Half;
+#[cfg(not(target_arch = "x86_64"))]
+use crate::{subspace_distance::distance_subspace_novector, Metric};
 
 /// Calculate the distance by vector arithmetic
+#[cfg(target_arch = "x86_64")]
 #[inline(never)]
 pub fn distance_l2_vector_f16<const N: usize>(a: &[Half; N], b: &[Half; N]) -> f32 {
     debug_assert_eq!(N % 8, 0);
 
-    // make sure the addresses are bytes aligned
-    debug_assert_eq!(a.as_ptr().align_offset(32), 0);
-    debug_assert_eq!(b.as_ptr().align_offset(32), 0);
+    // 8 halves are loaded at a time, so 16 bytes alignment is enough. Rows of an odd
+    // multiple of 8 dimensions (e.g. 104) are only 16 bytes aligned in the dataset.
+    debug_assert_eq!(a.as_ptr().align_offset(16), 0);
+    debug_assert_eq!(b.as_ptr().align_offset(16), 0);
 
     unsafe {
         let mut sum = _mm256_setzero_ps();
@@ -46,7 +51,15 @@ pub fn distance_l2_vector_f16<const N: usize>(a: &[Half; N], b: &[Half; N]) -> f
     }
 }
 
+/// Calculate the distance element by element, on targets without AVX2
+#[cfg(not(target_arch = "x86_64"))]
+#[inline(never)]
+pub fn distance_l2_vector_f16<const N: usize>(a: &[Half; N], b: &[Half; N]) -> f32 {
+    distance_subspace_novector(a, b, Metric::L2)
+}
+
 /// Calculate the distance by vector arithmetic
+#[cfg(target_arch = "x86_64")]
 #[inline(never)]
 pub fn distance_l2_vector_f32<const N: usize>(a: &[f32; N], b: &[f32; N]) -> f32 {
     debug_assert_eq!(N % 8, 0);
@@ -76,3 +89,31 @@ pub fn distance_l2_vector_f32<const N: usize>(a: &[f32; N], b: &[f32; N]) -> f32
     }
 }
 
+/// Calculate the distance element by element, on targets without AVX2
+#[cfg(not(target_arch = "x86_64"))]
+#[inline(never)]
+pub fn distance_l2_vector_f32<const N: usize>(a: &[f32; N], b: &[f32; N]) -> f32 {
+    distance_subspace_novector(a, b, Metric::L2)
+}
+
+/// Calculate the distance between two i8 vectors, widening to i32 so the loop auto-vectorizes
+#[inline(never)]
+pub fn distance_l2_vector_i8<const N: usize>(a: &[i8; N], b: &[i8; N]) -> f32 {
+    let mut sum = 0i32;
+    for i in 0..N {
+        let diff = a[i] as i32 - b[i] as i32;
+        sum += diff * diff;
+    }
+    sum as f32
+}
+
+/// Calculate the distance between two u8 vectors, accumulating in i32
+#[inline(never)]
+pub fn distance_l2_vector_u8<const N: usize>(a: &[u8; N], b: &[u8; N]) -> f32 {
+    let mut sum = 0i32;
+    for i in 0..N {
+        let diff = a[i] as i32 - b[i] as i32;
+        sum += diff * diff;
+    }
+    sum as f32
+}
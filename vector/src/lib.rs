@@ -10,15 +10,56 @@
 // #![feature(stdsimd)]
 // mod f32x16;
 // Uncomment above 2 to experiment with f32x16
+mod argmin_distance;
+#[cfg(target_arch = "x86_64")]
+mod avx512_distance;
+mod bf16_distance;
+mod bfloat16;
+mod chebyshev_distance;
+mod cosine_distance;
 mod distance;
+mod geo_distance;
 mod half;
+mod hamming_distance;
+mod l1_distance;
 mod l2_float_distance;
 mod metric;
+#[cfg(target_arch = "aarch64")]
+mod neon_distance;
+mod pq_scan;
+mod preprocess;
+mod simd_dispatch;
+#[cfg(target_arch = "x86_64")]
+mod simd;
+mod sparse_distance;
+mod strided_distance;
+mod subspace_distance;
+mod tanimoto_distance;
+mod topk_distance;
 mod utils;
+#[cfg(target_arch = "x86_64")]
+mod vnni_distance;
+mod weighted_distance;
 
+pub use crate::bfloat16::BFloat16;
 pub use crate::half::Half;
-pub use distance::FullPrecisionDistance;
+pub use distance::{BuiltinDistance, Distance, FullPrecisionDistance};
+pub use geo_distance::{HaversineDistance, EARTH_RADIUS_KM};
 pub use metric::Metric;
+pub use pq_scan::{
+    pq_dist_lookup_novector, pq_dist_lookup_packed4_novector, pq_dist_lookup_packed4_vector,
+    pq_dist_lookup_vector,
+};
+pub use preprocess::{multiply_in_place, normalize_in_place, subtract_in_place};
+#[cfg(target_arch = "x86_64")]
+pub use simd_dispatch::simd_level;
+#[cfg(target_arch = "aarch64")]
+pub use simd_dispatch::has_dotprod;
+pub use simd_dispatch::{kernel_report, kernel_selections, KernelBackend, KernelSelection, SimdLevel};
+pub use sparse_distance::{sparse_dense_dot, sparse_dot, sparse_l2};
+pub use strided_distance::{distances_to_strided_rows_f32, StridedRows};
+pub use subspace_distance::distance_l2_slice_f32;
+pub use topk_distance::select_topk_l2;
 pub use utils::prefetch_vector;
 
 #[cfg(test)]
@@ -11,9 +11,27 @@ pub enum Metric {
     /// Squared Euclidean (L2-Squared)
     L2,
 
-    /// Cosine similarity
-    /// TODO: T should be float for Cosine distance
+    /// Manhattan (L1), sum of absolute differences
+    L1,
+
+    /// Chebyshev (L∞), largest absolute difference
+    Chebyshev,
+
+    /// Cosine distance (1 - cosine similarity), data doesn't need to be normalized
     Cosine,
+
+    /// Hamming distance (number of differing bits) over packed binary vectors, 8 dimensions per byte
+    Hamming,
+
+    /// Tanimoto (Jaccard) distance (1 - |a AND b| / |a OR b|) over packed binary vectors, 8 dimensions per byte
+    Tanimoto,
+}
+
+impl Metric {
+    /// Whether the metric works on packed binary vectors of u8 or i8 rather than on values
+    pub fn is_binary(&self) -> bool {
+        matches!(self, Metric::Hamming | Metric::Tanimoto)
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -28,7 +46,11 @@ impl FromStr for Metric {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s.to_lowercase().as_str() {
             "l2" => Ok(Metric::L2),
+            "l1" => Ok(Metric::L1),
+            "chebyshev" | "linf" => Ok(Metric::Chebyshev),
             "cosine" => Ok(Metric::Cosine),
+            "hamming" => Ok(Metric::Hamming),
+            "tanimoto" | "jaccard" => Ok(Metric::Tanimoto),
             _ => Err(ParseMetricError::InvalidFormat(String::from(s))),
         }
     }
@@ -0,0 +1,129 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! NEON int8 kernels for aarch64 CPUs with the dot product instructions (Armv8.4, and most
+//! Armv8.2 cores such as Graviton2 or Apple M1). SDOT multiplies the 16 i8 lanes of two
+//! registers and adds each group of four products to an i32 lane. For L2 the absolute
+//! differences fit in u8 lanes and UDOT squares and sums them. Tails shorter than 16 lanes are
+//! handled in scalar code. Callers must check CPU support before calling, see `simd_dispatch`.
+
+use std::arch::aarch64::*;
+use std::arch::asm;
+
+/// Lanes of i8 consumed per iteration
+const I8_LANES: usize = 16;
+
+/// Calculate the squared L2 distance between two i8 vectors with UDOT
+/// # Safety
+/// The CPU must support neon and dotprod, and a and b must have equal lengths.
+#[target_feature(enable = "neon,dotprod")]
+pub unsafe fn distance_l2_i8_neon_dotprod(a: &[i8], b: &[i8]) -> f32 {
+    debug_assert_eq!(a.len(), b.len());
+
+    let len = a.len();
+    let mut sum = vdupq_n_u32(0);
+
+    let mut i = 0;
+    while i + I8_LANES <= len {
+        // |a - b| is at most 255, so it is exact in u8 lanes
+        let diff = vreinterpretq_u8_s8(vabdq_s8(
+            vld1q_s8(a.as_ptr().add(i)),
+            vld1q_s8(b.as_ptr().add(i)),
+        ));
+        sum = vdotq_u32(sum, diff, diff);
+        i += I8_LANES;
+    }
+
+    let mut total = vaddvq_u32(sum) as i32;
+    for j in i..len {
+        let diff = a[j] as i32 - b[j] as i32;
+        total += diff * diff;
+    }
+    total as f32
+}
+
+/// Calculate the dot product of two i8 vectors with SDOT
+/// # Safety
+/// The CPU must support neon and dotprod, and a and b must have equal lengths.
+#[target_feature(enable = "neon,dotprod")]
+pub unsafe fn dot_product_i8_neon_dotprod(a: &[i8], b: &[i8]) -> i32 {
+    debug_assert_eq!(a.len(), b.len());
+
+    let len = a.len();
+    let mut sum = vdupq_n_s32(0);
+
+    let mut i = 0;
+    while i + I8_LANES <= len {
+        sum = vdotq_s32(sum, vld1q_s8(a.as_ptr().add(i)), vld1q_s8(b.as_ptr().add(i)));
+        i += I8_LANES;
+    }
+
+    let mut total = vaddvq_s32(sum);
+    for j in i..len {
+        total += a[j] as i32 * b[j] as i32;
+    }
+    total
+}
+
+// The vdotq intrinsics of std::arch are unstable, these emit the same instructions
+
+/// Each i32 lane of acc plus the dot product of the four i8 lanes of a and b it covers
+#[inline]
+#[target_feature(enable = "neon,dotprod")]
+unsafe fn vdotq_s32(mut acc: int32x4_t, a: int8x16_t, b: int8x16_t) -> int32x4_t {
+    asm!(
+        "sdot {acc:v}.4s, {a:v}.16b, {b:v}.16b",
+        acc = inout(vreg) acc,
+        a = in(vreg) a,
+        b = in(vreg) b,
+        options(pure, nomem, nostack, preserves_flags),
+    );
+    acc
+}
+
+/// Each u32 lane of acc plus the dot product of the four u8 lanes of a and b it covers
+#[inline]
+#[target_feature(enable = "neon,dotprod")]
+unsafe fn vdotq_u32(mut acc: uint32x4_t, a: uint8x16_t, b: uint8x16_t) -> uint32x4_t {
+    asm!(
+        "udot {acc:v}.4s, {a:v}.16b, {b:v}.16b",
+        acc = inout(vreg) acc,
+        a = in(vreg) a,
+        b = in(vreg) b,
+        options(pure, nomem, nostack, preserves_flags),
+    );
+    acc
+}
+
+#[cfg(test)]
+mod neon_distance_test {
+    use rand::Rng;
+
+    use super::*;
+
+    #[test]
+    fn neon_dotprod_matches_novector_with_tails() {
+        if !std::arch::is_aarch64_feature_detected!("dotprod") {
+            return;
+        }
+
+        let mut rng = rand::thread_rng();
+        for len in [1, 15, 16, 17, 100, 768, 1025] {
+            let a: Vec<i8> = (0..len).map(|_| rng.gen()).collect();
+            let b: Vec<i8> = (0..len).map(|_| rng.gen()).collect();
+            let l2: i32 = a.iter().zip(&b).map(|(x, y)| (*x as i32 - *y as i32).pow(2)).sum();
+            let dot: i32 = a.iter().zip(&b).map(|(x, y)| *x as i32 * *y as i32).sum();
+
+            assert_eq!(unsafe { distance_l2_i8_neon_dotprod(&a, &b) }, l2 as f32);
+            assert_eq!(unsafe { dot_product_i8_neon_dotprod(&a, &b) }, dot);
+        }
+
+        let a = [i8::MIN; 64];
+        let b = [i8::MAX; 64];
+        assert_eq!(unsafe { distance_l2_i8_neon_dotprod(&a, &b) }, 64.0 * 255.0 * 255.0);
+        assert_eq!(unsafe { dot_product_i8_neon_dotprod(&a, &a) }, 64 * 128 * 128);
+    }
+}
@@ -0,0 +1,386 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! PQ distance lookup over a batch of candidates.
+//! The query side precomputes one table of 256 centroid distances per chunk, and the distance
+//! of a candidate is the sum of the entries its codes select. The AVX2 kernel scores eight
+//! candidates per pass: their codes for a chunk become the indices of one gather from that
+//! chunk's table. Chunks are added in order, so the sums are the same as the scalar loop's.
+//! 4-bit codes, two per byte with the even chunk in the low nibble, select from tables of 16
+//! centroid distances per chunk the same way.
+//! On aarch64, NEON has no gather: 8-bit codes load four candidates' entries into the lanes
+//! one at a time, while a 4-bit table of 16 f32 fits the 64 bytes of one TBL lookup.
+
+#[cfg(target_arch = "aarch64")]
+use std::arch::aarch64::*;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+/// Centroids per chunk, i.e. entries per chunk in the distance table
+const TABLE_SIZE: usize = 256;
+
+/// Centroids per chunk of 4-bit codes
+const TABLE_SIZE_4BIT: usize = 16;
+
+/// Candidates scored per pass, one per f32 lane
+#[cfg(target_arch = "x86_64")]
+const LANES: usize = 8;
+
+/// Candidates scored per pass, one per f32 lane
+#[cfg(target_arch = "aarch64")]
+const LANES: usize = 4;
+
+/// Sum the table entries selected by the PQ codes of each candidate with AVX2 gathers.
+/// * `pq_codes` - codes of the candidates, num_chunks per candidate
+/// * `pq_dists` - distance from the query to every centroid, 256 per chunk
+/// * `dists_out` - one distance per candidate, the number of candidates is its length
+#[cfg(target_arch = "x86_64")]
+#[inline(never)]
+pub fn pq_dist_lookup_vector(
+    pq_codes: &[u8],
+    num_chunks: usize,
+    pq_dists: &[f32],
+    dists_out: &mut [f32],
+) {
+    // The gathers don't check bounds
+    assert!(pq_codes.len() >= dists_out.len() * num_chunks);
+    assert!(pq_dists.len() >= TABLE_SIZE * num_chunks);
+
+    let tail_start = dists_out.len() - dists_out.len() % LANES;
+    for (block_idx, block) in dists_out[..tail_start].chunks_exact_mut(LANES).enumerate() {
+        let codes = &pq_codes[block_idx * LANES * num_chunks..];
+        unsafe {
+            let mut sum = _mm256_setzero_ps();
+            for chunk in 0..num_chunks {
+                let code = |lane: usize| codes[lane * num_chunks + chunk] as i32;
+                let indices = _mm256_set_epi32(
+                    code(7),
+                    code(6),
+                    code(5),
+                    code(4),
+                    code(3),
+                    code(2),
+                    code(1),
+                    code(0),
+                );
+                let table = pq_dists.as_ptr().add(chunk * TABLE_SIZE);
+                sum = _mm256_add_ps(sum, _mm256_i32gather_ps::<4>(table, indices));
+            }
+            _mm256_storeu_ps(block.as_mut_ptr(), sum);
+        }
+    }
+
+    pq_dist_lookup_novector(
+        &pq_codes[tail_start * num_chunks..],
+        num_chunks,
+        pq_dists,
+        &mut dists_out[tail_start..],
+    );
+}
+
+/// Sum the table entries selected by the PQ codes of each candidate with NEON lane loads.
+/// * `pq_codes` - codes of the candidates, num_chunks per candidate
+/// * `pq_dists` - distance from the query to every centroid, 256 per chunk
+/// * `dists_out` - one distance per candidate, the number of candidates is its length
+#[cfg(target_arch = "aarch64")]
+#[inline(never)]
+pub fn pq_dist_lookup_vector(
+    pq_codes: &[u8],
+    num_chunks: usize,
+    pq_dists: &[f32],
+    dists_out: &mut [f32],
+) {
+    // The lane loads don't check bounds
+    assert!(pq_codes.len() >= dists_out.len() * num_chunks);
+    assert!(pq_dists.len() >= TABLE_SIZE * num_chunks);
+
+    let tail_start = dists_out.len() - dists_out.len() % LANES;
+    for (block_idx, block) in dists_out[..tail_start].chunks_exact_mut(LANES).enumerate() {
+        let codes = &pq_codes[block_idx * LANES * num_chunks..];
+        unsafe {
+            let mut sum = vdupq_n_f32(0.0);
+            for chunk in 0..num_chunks {
+                let table = pq_dists.as_ptr().add(chunk * TABLE_SIZE);
+                let entry = |lane: usize| table.add(codes[lane * num_chunks + chunk] as usize);
+                let mut entries = vld1q_dup_f32(entry(0));
+                entries = vld1q_lane_f32::<1>(entry(1), entries);
+                entries = vld1q_lane_f32::<2>(entry(2), entries);
+                entries = vld1q_lane_f32::<3>(entry(3), entries);
+                sum = vaddq_f32(sum, entries);
+            }
+            vst1q_f32(block.as_mut_ptr(), sum);
+        }
+    }
+
+    pq_dist_lookup_novector(
+        &pq_codes[tail_start * num_chunks..],
+        num_chunks,
+        pq_dists,
+        &mut dists_out[tail_start..],
+    );
+}
+
+/// Sum the table entries selected by the PQ codes of each candidate, one candidate at a time
+/// on targets without AVX2 or NEON
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+#[inline(never)]
+pub fn pq_dist_lookup_vector(
+    pq_codes: &[u8],
+    num_chunks: usize,
+    pq_dists: &[f32],
+    dists_out: &mut [f32],
+) {
+    pq_dist_lookup_novector(pq_codes, num_chunks, pq_dists, dists_out);
+}
+
+/// Sum the table entries selected by the PQ codes of each candidate, one candidate at a time
+pub fn pq_dist_lookup_novector(
+    pq_codes: &[u8],
+    num_chunks: usize,
+    pq_dists: &[f32],
+    dists_out: &mut [f32],
+) {
+    for (codes, dist) in pq_codes.chunks_exact(num_chunks).zip(dists_out.iter_mut()) {
+        *dist = codes.iter().enumerate().fold(0.0, |sum, (chunk, code)| {
+            sum + pq_dists[chunk * TABLE_SIZE + *code as usize]
+        });
+    }
+}
+
+/// Sum the table entries selected by the packed 4-bit PQ codes of each candidate with AVX2
+/// gathers.
+/// * `pq_codes` - codes of the candidates, (num_chunks + 1) / 2 bytes per candidate, the code
+///   of chunk 2i in the low nibble of byte i and the code of chunk 2i + 1 in its high nibble
+/// * `pq_dists` - distance from the query to every centroid, 16 per chunk
+/// * `dists_out` - one distance per candidate, the number of candidates is its length
+#[cfg(target_arch = "x86_64")]
+#[inline(never)]
+pub fn pq_dist_lookup_packed4_vector(
+    pq_codes: &[u8],
+    num_chunks: usize,
+    pq_dists: &[f32],
+    dists_out: &mut [f32],
+) {
+    let code_bytes = num_chunks.div_ceil(2);
+    // The gathers don't check bounds
+    assert!(pq_codes.len() >= dists_out.len() * code_bytes);
+    assert!(pq_dists.len() >= TABLE_SIZE_4BIT * num_chunks);
+
+    let tail_start = dists_out.len() - dists_out.len() % LANES;
+    for (block_idx, block) in dists_out[..tail_start].chunks_exact_mut(LANES).enumerate() {
+        let codes = &pq_codes[block_idx * LANES * code_bytes..];
+        unsafe {
+            let mut sum = _mm256_setzero_ps();
+            for chunk in 0..num_chunks {
+                let code = |lane: usize| nibble(codes[lane * code_bytes + chunk / 2], chunk) as i32;
+                let indices = _mm256_set_epi32(
+                    code(7),
+                    code(6),
+                    code(5),
+                    code(4),
+                    code(3),
+                    code(2),
+                    code(1),
+                    code(0),
+                );
+                let table = pq_dists.as_ptr().add(chunk * TABLE_SIZE_4BIT);
+                sum = _mm256_add_ps(sum, _mm256_i32gather_ps::<4>(table, indices));
+            }
+            _mm256_storeu_ps(block.as_mut_ptr(), sum);
+        }
+    }
+
+    pq_dist_lookup_packed4_novector(
+        &pq_codes[tail_start * code_bytes..],
+        num_chunks,
+        pq_dists,
+        &mut dists_out[tail_start..],
+    );
+}
+
+/// Sum the table entries selected by the packed 4-bit PQ codes of each candidate with NEON
+/// table lookups, the 16 entries of a chunk being the 64 bytes one TBL instruction indexes.
+/// * `pq_codes` - codes of the candidates, (num_chunks + 1) / 2 bytes per candidate, the code
+///   of chunk 2i in the low nibble of byte i and the code of chunk 2i + 1 in its high nibble
+/// * `pq_dists` - distance from the query to every centroid, 16 per chunk
+/// * `dists_out` - one distance per candidate, the number of candidates is its length
+#[cfg(target_arch = "aarch64")]
+#[inline(never)]
+pub fn pq_dist_lookup_packed4_vector(
+    pq_codes: &[u8],
+    num_chunks: usize,
+    pq_dists: &[f32],
+    dists_out: &mut [f32],
+) {
+    let code_bytes = num_chunks.div_ceil(2);
+    // The table loads don't check bounds
+    assert!(pq_codes.len() >= dists_out.len() * code_bytes);
+    assert!(pq_dists.len() >= TABLE_SIZE_4BIT * num_chunks);
+
+    let tail_start = dists_out.len() - dists_out.len() % LANES;
+    for (block_idx, block) in dists_out[..tail_start].chunks_exact_mut(LANES).enumerate() {
+        let codes = &pq_codes[block_idx * LANES * code_bytes..];
+        unsafe {
+            // Byte k of the entry for code c is byte 4c + k of the table
+            let byte_offsets = vdupq_n_u32(0x0302_0100);
+            let mut sum = vdupq_n_f32(0.0);
+            for chunk in 0..num_chunks {
+                let code = |lane: usize| nibble(codes[lane * code_bytes + chunk / 2], chunk) as u32;
+                let lane_codes = [code(0), code(1), code(2), code(3)];
+                let indices =
+                    vmlaq_n_u32(byte_offsets, vld1q_u32(lane_codes.as_ptr()), 0x0404_0404);
+
+                let table = pq_dists.as_ptr().add(chunk * TABLE_SIZE_4BIT) as *const u8;
+                let table = uint8x16x4_t(
+                    vld1q_u8(table),
+                    vld1q_u8(table.add(16)),
+                    vld1q_u8(table.add(32)),
+                    vld1q_u8(table.add(48)),
+                );
+                let entries = vqtbl4q_u8(table, vreinterpretq_u8_u32(indices));
+                sum = vaddq_f32(sum, vreinterpretq_f32_u8(entries));
+            }
+            vst1q_f32(block.as_mut_ptr(), sum);
+        }
+    }
+
+    pq_dist_lookup_packed4_novector(
+        &pq_codes[tail_start * code_bytes..],
+        num_chunks,
+        pq_dists,
+        &mut dists_out[tail_start..],
+    );
+}
+
+/// Sum the table entries selected by the packed 4-bit PQ codes of each candidate, one
+/// candidate at a time on targets without AVX2 or NEON
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+#[inline(never)]
+pub fn pq_dist_lookup_packed4_vector(
+    pq_codes: &[u8],
+    num_chunks: usize,
+    pq_dists: &[f32],
+    dists_out: &mut [f32],
+) {
+    pq_dist_lookup_packed4_novector(pq_codes, num_chunks, pq_dists, dists_out);
+}
+
+/// Sum the table entries selected by the packed 4-bit PQ codes of each candidate, one
+/// candidate at a time
+pub fn pq_dist_lookup_packed4_novector(
+    pq_codes: &[u8],
+    num_chunks: usize,
+    pq_dists: &[f32],
+    dists_out: &mut [f32],
+) {
+    let code_bytes = num_chunks.div_ceil(2);
+    for (codes, dist) in pq_codes.chunks_exact(code_bytes).zip(dists_out.iter_mut()) {
+        *dist = (0..num_chunks).fold(0.0, |sum, chunk| {
+            sum + pq_dists[chunk * TABLE_SIZE_4BIT + nibble(codes[chunk / 2], chunk) as usize]
+        });
+    }
+}
+
+/// Code of chunk in the byte packing it with its neighboring chunk
+#[inline(always)]
+fn nibble(byte: u8, chunk: usize) -> u8 {
+    if chunk.is_multiple_of(2) {
+        byte & 0x0f
+    } else {
+        byte >> 4
+    }
+}
+
+#[cfg(test)]
+mod pq_scan_test {
+    use super::*;
+
+    #[test]
+    fn vector_lookup_matches_novector() {
+        let num_chunks = 5;
+        let pq_dists: Vec<f32> = (0..TABLE_SIZE * num_chunks)
+            .map(|i| (i as f32 * 0.37).sin() * 10.0)
+            .collect();
+
+        // Full blocks of candidates plus a tail of 3
+        let num_points = 19;
+        let pq_codes: Vec<u8> = (0..num_points * num_chunks)
+            .map(|i| (i * 53 % 256) as u8)
+            .collect();
+
+        let mut expected = vec![0.0; num_points];
+        let mut actual = vec![0.0; num_points];
+        pq_dist_lookup_novector(&pq_codes, num_chunks, &pq_dists, &mut expected);
+        pq_dist_lookup_vector(&pq_codes, num_chunks, &pq_dists, &mut actual);
+        assert_eq!(actual, expected);
+
+        let first: f32 = (0..num_chunks)
+            .map(|chunk| pq_dists[chunk * TABLE_SIZE + pq_codes[chunk] as usize])
+            .sum();
+        assert_eq!(actual[0], first);
+    }
+
+    #[test]
+    fn packed4_vector_lookup_matches_novector() {
+        // An odd number of chunks leaves the high nibble of the last byte unused
+        let num_chunks = 5;
+        let code_bytes = 3;
+        let pq_dists: Vec<f32> = (0..TABLE_SIZE_4BIT * num_chunks)
+            .map(|i| (i as f32 * 0.37).sin() * 10.0)
+            .collect();
+
+        let num_points = 19;
+        let pq_codes: Vec<u8> = (0..num_points * code_bytes)
+            .map(|i| (i * 53 % 256) as u8)
+            .collect();
+
+        let mut expected = vec![0.0; num_points];
+        let mut actual = vec![0.0; num_points];
+        pq_dist_lookup_packed4_novector(&pq_codes, num_chunks, &pq_dists, &mut expected);
+        pq_dist_lookup_packed4_vector(&pq_codes, num_chunks, &pq_dists, &mut actual);
+        assert_eq!(actual, expected);
+
+        let first: f32 = (0..num_chunks)
+            .map(|chunk| {
+                let byte = pq_codes[chunk / 2];
+                let code = if chunk % 2 == 0 {
+                    byte & 0x0f
+                } else {
+                    byte >> 4
+                };
+                pq_dists[chunk * TABLE_SIZE_4BIT + code as usize]
+            })
+            .sum();
+        assert_eq!(actual[0], first);
+    }
+
+    #[test]
+    fn vector_lookups_match_novector_for_every_tail() {
+        // Full blocks of either kernel width, every tail, and the largest codes
+        for num_chunks in [1, 2, 7, 32] {
+            let pq_dists: Vec<f32> = (0..TABLE_SIZE * num_chunks)
+                .map(|i| (i as f32 * 0.61).cos() * 100.0)
+                .collect();
+            for num_points in 0..=17 {
+                let pq_codes: Vec<u8> = (0..num_points * num_chunks)
+                    .map(|i| if i % 5 == 0 { u8::MAX } else { (i * 97 % 256) as u8 })
+                    .collect();
+
+                let mut expected = vec![0.0; num_points];
+                let mut actual = vec![0.0; num_points];
+                pq_dist_lookup_novector(&pq_codes, num_chunks, &pq_dists, &mut expected);
+                pq_dist_lookup_vector(&pq_codes, num_chunks, &pq_dists, &mut actual);
+                assert_eq!(actual, expected);
+
+                let code_bytes = num_chunks.div_ceil(2);
+                let pq_codes = &pq_codes[..num_points * code_bytes];
+                pq_dist_lookup_packed4_novector(pq_codes, num_chunks, &pq_dists, &mut expected);
+                pq_dist_lookup_packed4_vector(pq_codes, num_chunks, &pq_dists, &mut actual);
+                assert_eq!(actual, expected);
+            }
+        }
+    }
+}
@@ -0,0 +1,164 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! In-place f32 vector transformations used to preprocess points and queries:
+//! L2 normalization, subtracting a mean and per-dimension scaling.
+//! Vectors can have any length and alignment, the last len % 8 values are handled one by one.
+
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+#[cfg(target_arch = "x86_64")]
+use crate::simd::horizontal_sum;
+
+/// Scale v to unit L2 norm and return its original norm. A zero vector is left unchanged.
+#[cfg(target_arch = "x86_64")]
+#[inline(never)]
+pub fn normalize_in_place(v: &mut [f32]) -> f32 {
+    let len = v.len();
+    let vector_len = len - len % 8;
+
+    let mut norm_sq = unsafe {
+        let mut sum = _mm256_setzero_ps();
+        for i in (0..vector_len).step_by(8) {
+            let x = _mm256_loadu_ps(v.as_ptr().add(i));
+            sum = _mm256_fmadd_ps(x, x, sum);
+        }
+        horizontal_sum(sum)
+    };
+    for x in &v[vector_len..] {
+        norm_sq += x * x;
+    }
+
+    let norm = norm_sq.sqrt();
+    if norm > 0.0 {
+        let inv_norm = 1.0 / norm;
+        unsafe {
+            let factor = _mm256_set1_ps(inv_norm);
+            for i in (0..vector_len).step_by(8) {
+                let ptr = v.as_mut_ptr().add(i);
+                _mm256_storeu_ps(ptr, _mm256_mul_ps(_mm256_loadu_ps(ptr), factor));
+            }
+        }
+        for x in &mut v[vector_len..] {
+            *x *= inv_norm;
+        }
+    }
+
+    norm
+}
+
+/// Scale v to unit L2 norm and return its original norm, element by element on targets
+/// without AVX2. A zero vector is left unchanged.
+#[cfg(not(target_arch = "x86_64"))]
+#[inline(never)]
+pub fn normalize_in_place(v: &mut [f32]) -> f32 {
+    let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        let inv_norm = 1.0 / norm;
+        v.iter_mut().for_each(|x| *x *= inv_norm);
+    }
+
+    norm
+}
+
+/// Subtract b from v element by element, e.g. to center v on the dataset mean
+#[cfg(target_arch = "x86_64")]
+#[inline(never)]
+pub fn subtract_in_place(v: &mut [f32], b: &[f32]) {
+    debug_assert_eq!(v.len(), b.len());
+    let len = v.len().min(b.len());
+    let vector_len = len - len % 8;
+
+    unsafe {
+        for i in (0..vector_len).step_by(8) {
+            let ptr = v.as_mut_ptr().add(i);
+            let diff = _mm256_sub_ps(_mm256_loadu_ps(ptr), _mm256_loadu_ps(b.as_ptr().add(i)));
+            _mm256_storeu_ps(ptr, diff);
+        }
+    }
+    for i in vector_len..len {
+        v[i] -= b[i];
+    }
+}
+
+/// Subtract b from v element by element, on targets without AVX2
+#[cfg(not(target_arch = "x86_64"))]
+#[inline(never)]
+pub fn subtract_in_place(v: &mut [f32], b: &[f32]) {
+    debug_assert_eq!(v.len(), b.len());
+    v.iter_mut().zip(b).for_each(|(x, y)| *x -= y);
+}
+
+/// Multiply v by scales element by element
+#[cfg(target_arch = "x86_64")]
+#[inline(never)]
+pub fn multiply_in_place(v: &mut [f32], scales: &[f32]) {
+    debug_assert_eq!(v.len(), scales.len());
+    let len = v.len().min(scales.len());
+    let vector_len = len - len % 8;
+
+    unsafe {
+        for i in (0..vector_len).step_by(8) {
+            let ptr = v.as_mut_ptr().add(i);
+            let scaled = _mm256_mul_ps(
+                _mm256_loadu_ps(ptr),
+                _mm256_loadu_ps(scales.as_ptr().add(i)),
+            );
+            _mm256_storeu_ps(ptr, scaled);
+        }
+    }
+    for i in vector_len..len {
+        v[i] *= scales[i];
+    }
+}
+
+/// Multiply v by scales element by element, on targets without AVX2
+#[cfg(not(target_arch = "x86_64"))]
+#[inline(never)]
+pub fn multiply_in_place(v: &mut [f32], scales: &[f32]) {
+    debug_assert_eq!(v.len(), scales.len());
+    v.iter_mut().zip(scales).for_each(|(x, scale)| *x *= scale);
+}
+
+#[cfg(test)]
+mod preprocess_test {
+    use approx::assert_abs_diff_eq;
+
+    use super::*;
+
+    #[test]
+    fn transformations_match_scalar() {
+        // 19 values: two vector steps and a scalar tail
+        let original: Vec<f32> = (0..19).map(|i| (i as f32 * 0.9).sin() * 4.0).collect();
+        let other: Vec<f32> = (0..19).map(|i| i as f32 * 0.2 - 1.0).collect();
+
+        let mut v = original.clone();
+        subtract_in_place(&mut v, &other);
+        for i in 0..19 {
+            assert_eq!(v[i], original[i] - other[i]);
+        }
+
+        let mut v = original.clone();
+        multiply_in_place(&mut v, &other);
+        for i in 0..19 {
+            assert_eq!(v[i], original[i] * other[i]);
+        }
+
+        let mut v = original.clone();
+        let norm = normalize_in_place(&mut v);
+        let expected_norm = original.iter().map(|x| x * x).sum::<f32>().sqrt();
+        assert_abs_diff_eq!(norm, expected_norm, epsilon = 1e-5);
+        assert_abs_diff_eq!(v.iter().map(|x| x * x).sum::<f32>(), 1.0, epsilon = 1e-5);
+        for i in 0..19 {
+            assert_abs_diff_eq!(v[i], original[i] / expected_norm, epsilon = 1e-6);
+        }
+
+        let mut zero = vec![0.0f32; 10];
+        assert_eq!(normalize_in_place(&mut zero), 0.0);
+        assert!(zero.iter().all(|x| *x == 0.0));
+    }
+}
@@ -0,0 +1,17 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+use std::arch::x86_64::*;
+
+/// Sum the 8 lanes of an AVX register
+#[inline(always)]
+pub(crate) unsafe fn horizontal_sum(sum: __m256) -> f32 {
+    let x128: __m128 = _mm_add_ps(_mm256_extractf128_ps(sum, 1), _mm256_castps256_ps128(sum));
+    /* ( -, -, x1+x3+x5+x7, x0+x2+x4+x6 ) */
+    let x64: __m128 = _mm_add_ps(x128, _mm_movehl_ps(x128, x128));
+    /* ( -, -, -, x0+x1+x2+x3+x4+x5+x6+x7 ) */
+    let x32: __m128 = _mm_add_ss(x64, _mm_shuffle_ps(x64, x64, 0x55));
+    /* Conversion to float is a no-op on x86-64 */
+    _mm_cvtss_f32(x32)
+}
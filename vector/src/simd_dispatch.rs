@@ -0,0 +1,418 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Runtime selection of distance kernels based on the instruction sets of the host CPU.
+//! AVX2 is the compile-time baseline; wider kernels are picked once at first use.
+//! With the simd-native feature, the level follows the target features the crate was compiled
+//! with instead, e.g. with -C target-cpu=native, for binaries that run where they are built.
+//! kernel_selections reports what each kernel resolved to, so that a slow run can be told
+//! apart from a run that fell back to narrower kernels.
+//! On aarch64 the int8 kernels use the NEON dot products when the CPU has them, and on other
+//! targets every kernel is the portable element by element one.
+
+use std::fmt;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+use std::sync::OnceLock;
+
+#[cfg(target_arch = "x86_64")]
+use crate::argmin_distance::{distance_l2_argmin_f32_avx512, distance_l2_argmin_vector_f32};
+#[cfg(target_arch = "x86_64")]
+use crate::avx512_distance::{
+    distance_l2_f32_avx512, distance_l2_i8_avx512, distance_l2_i8_avx512_vnni,
+    dot_product_i8_avx512, dot_product_i8_avx512_vnni,
+};
+#[cfg(target_arch = "x86_64")]
+use crate::cosine_distance::{cosine_distance, distance_cosine_vector_i8, dot_product_vector_i8};
+#[cfg(target_arch = "x86_64")]
+use crate::l2_float_distance::{distance_l2_vector_f32, distance_l2_vector_i8};
+#[cfg(target_arch = "x86_64")]
+use crate::vnni_distance::{distance_l2_i8_avx_vnni, dot_product_i8_avx_vnni};
+
+#[cfg(target_arch = "aarch64")]
+use crate::cosine_distance::{cosine_distance, distance_cosine_vector_i8};
+#[cfg(target_arch = "aarch64")]
+use crate::l2_float_distance::distance_l2_vector_i8;
+#[cfg(target_arch = "aarch64")]
+use crate::neon_distance::{distance_l2_i8_neon_dotprod, dot_product_i8_neon_dotprod};
+
+// Without SIMD levels to pick from, the kernels are called directly
+#[cfg(not(target_arch = "x86_64"))]
+pub(crate) use crate::argmin_distance::distance_l2_argmin_vector_f32 as distance_l2_argmin_f32;
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+pub(crate) use crate::cosine_distance::distance_cosine_vector_i8 as distance_cosine_i8;
+#[cfg(not(target_arch = "x86_64"))]
+pub(crate) use crate::l2_float_distance::distance_l2_vector_f32 as distance_l2_f32;
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+pub(crate) use crate::l2_float_distance::distance_l2_vector_i8 as distance_l2_i8;
+
+/// Instruction set level the distance kernels resolved to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SimdLevel {
+    /// AVX2 + FMA, the compile-time baseline
+    Avx2,
+
+    /// AVX2 with the 256-bit AVX-VNNI integer dot products, on CPUs without AVX-512
+    AvxVnni,
+
+    /// AVX-512 F/BW/VL
+    Avx512,
+
+    /// AVX-512 F/BW/VL with VNNI integer dot products
+    Avx512Vnni,
+
+    /// aarch64 NEON, the baseline of that target
+    Neon,
+
+    /// aarch64 NEON with the SDOT/UDOT integer dot products
+    NeonDotprod,
+}
+
+impl fmt::Display for SimdLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SimdLevel::Avx2 => "avx2",
+            SimdLevel::AvxVnni => "avx-vnni",
+            SimdLevel::Avx512 => "avx512",
+            SimdLevel::Avx512Vnni => "avx512-vnni",
+            SimdLevel::Neon => "neon",
+            SimdLevel::NeonDotprod => "neon-dotprod",
+        })
+    }
+}
+
+/// Implementation a kernel resolved to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KernelBackend {
+    /// Plain loop, one element at a time
+    Scalar,
+
+    /// SIMD instructions of the given level
+    Simd(SimdLevel),
+}
+
+impl fmt::Display for KernelBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KernelBackend::Scalar => f.write_str("scalar"),
+            KernelBackend::Simd(level) => level.fmt(f),
+        }
+    }
+}
+
+/// A kernel and the implementation it resolved to in this process
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KernelSelection {
+    /// Name of the kernel, e.g. l2_f32
+    pub kernel: &'static str,
+
+    /// Implementation used for it
+    pub backend: KernelBackend,
+}
+
+impl fmt::Display for KernelSelection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.kernel, self.backend)
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+static SIMD_LEVEL: OnceLock<SimdLevel> = OnceLock::new();
+
+/// Get the instruction set level used by the distance kernels, detected once per process
+#[cfg(target_arch = "x86_64")]
+pub fn simd_level() -> SimdLevel {
+    *SIMD_LEVEL.get_or_init(detect_simd_level)
+}
+
+/// Get the implementation each distance kernel resolved to, e.g. "l2_f32: avx512, hamming:
+/// scalar" once displayed
+#[cfg(target_arch = "x86_64")]
+pub fn kernel_selections() -> Vec<KernelSelection> {
+    let level = simd_level();
+    let f32_level = match level {
+        SimdLevel::Avx512 | SimdLevel::Avx512Vnni => SimdLevel::Avx512,
+        _ => SimdLevel::Avx2,
+    };
+    let selection = |kernel, backend| KernelSelection { kernel, backend };
+
+    vec![
+        selection("l2_f32", KernelBackend::Simd(f32_level)),
+        selection("l2_argmin_f32", KernelBackend::Simd(f32_level)),
+        selection("l2_i8", KernelBackend::Simd(level)),
+        selection("dot_product_i8", KernelBackend::Simd(level)),
+        selection("cosine_i8", KernelBackend::Simd(level)),
+        selection("pq_lut", KernelBackend::Simd(SimdLevel::Avx2)),
+        selection("hamming", KernelBackend::Scalar),
+    ]
+}
+
+/// Get the implementation each distance kernel resolved to: on aarch64 the PQ lookup with NEON
+/// and the int8 ones with NEON dot products on CPUs that have them, all of them scalar on
+/// other targets
+#[cfg(not(target_arch = "x86_64"))]
+pub fn kernel_selections() -> Vec<KernelSelection> {
+    #[cfg(target_arch = "aarch64")]
+    let i8_backend = match has_dotprod() {
+        true => KernelBackend::Simd(SimdLevel::NeonDotprod),
+        false => KernelBackend::Scalar,
+    };
+    #[cfg(target_arch = "aarch64")]
+    let pq_lut_backend = KernelBackend::Simd(SimdLevel::Neon);
+    #[cfg(not(target_arch = "aarch64"))]
+    let (i8_backend, pq_lut_backend) = (KernelBackend::Scalar, KernelBackend::Scalar);
+
+    [
+        ("l2_f32", KernelBackend::Scalar),
+        ("l2_argmin_f32", KernelBackend::Scalar),
+        ("l2_i8", i8_backend),
+        ("dot_product_i8", i8_backend),
+        ("cosine_i8", i8_backend),
+        ("pq_lut", pq_lut_backend),
+        ("hamming", KernelBackend::Scalar),
+    ]
+    .into_iter()
+    .map(|(kernel, backend)| KernelSelection { kernel, backend })
+    .collect()
+}
+
+/// Get the kernel selections on one line, for build and health reports
+pub fn kernel_report() -> String {
+    kernel_selections()
+        .iter()
+        .map(KernelSelection::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(all(target_arch = "x86_64", feature = "simd-native"))]
+fn detect_simd_level() -> SimdLevel {
+    if cfg!(all(
+        target_feature = "avx512f",
+        target_feature = "avx512bw",
+        target_feature = "avx512vl"
+    )) {
+        if cfg!(target_feature = "avx512vnni") {
+            SimdLevel::Avx512Vnni
+        } else {
+            SimdLevel::Avx512
+        }
+    } else if cfg!(target_feature = "avxvnni") {
+        SimdLevel::AvxVnni
+    } else {
+        SimdLevel::Avx2
+    }
+}
+
+#[cfg(all(target_arch = "x86_64", not(feature = "simd-native")))]
+fn detect_simd_level() -> SimdLevel {
+    let avx512 = is_x86_feature_detected!("avx512f")
+        && is_x86_feature_detected!("avx512bw")
+        && is_x86_feature_detected!("avx512vl");
+
+    if avx512 && is_x86_feature_detected!("avx512vnni") {
+        SimdLevel::Avx512Vnni
+    } else if avx512 {
+        SimdLevel::Avx512
+    } else if is_x86_feature_detected!("avxvnni") {
+        SimdLevel::AvxVnni
+    } else {
+        SimdLevel::Avx2
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+static DOTPROD: OnceLock<bool> = OnceLock::new();
+
+/// Whether the CPU has the NEON integer dot products, detected once per process
+#[cfg(target_arch = "aarch64")]
+pub fn has_dotprod() -> bool {
+    *DOTPROD.get_or_init(detect_dotprod)
+}
+
+#[cfg(all(target_arch = "aarch64", feature = "simd-native"))]
+fn detect_dotprod() -> bool {
+    cfg!(target_feature = "dotprod")
+}
+
+#[cfg(all(target_arch = "aarch64", not(feature = "simd-native")))]
+fn detect_dotprod() -> bool {
+    std::arch::is_aarch64_feature_detected!("dotprod")
+}
+
+/// Squared L2 distance between two f32 vectors using the best available kernel
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+pub(crate) fn distance_l2_f32<const N: usize>(a: &[f32; N], b: &[f32; N]) -> f32 {
+    match simd_level() {
+        // Safety: avx512f support was checked by simd_level
+        SimdLevel::Avx512 | SimdLevel::Avx512Vnni => unsafe { distance_l2_f32_avx512(a, b) },
+        _ => distance_l2_vector_f32::<N>(a, b),
+    }
+}
+
+/// Closest f32 candidate by squared L2 using the best available kernel
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+pub(crate) fn distance_l2_argmin_f32<const N: usize>(
+    a: &[f32; N],
+    candidates: &[&[f32; N]],
+) -> Option<(usize, f32)> {
+    match simd_level() {
+        // Safety: avx512f support was checked by simd_level
+        SimdLevel::Avx512 | SimdLevel::Avx512Vnni => unsafe {
+            distance_l2_argmin_f32_avx512::<N>(a, candidates)
+        },
+        _ => distance_l2_argmin_vector_f32::<N>(a, candidates),
+    }
+}
+
+/// Squared L2 distance between two i8 vectors using the best available kernel
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+pub(crate) fn distance_l2_i8<const N: usize>(a: &[i8; N], b: &[i8; N]) -> f32 {
+    match simd_level() {
+        // Safety: avx512f/bw/vl (and vnni) or avxvnni support was checked by simd_level
+        SimdLevel::Avx512Vnni => unsafe { distance_l2_i8_avx512_vnni(a, b) },
+        SimdLevel::Avx512 => unsafe { distance_l2_i8_avx512(a, b) },
+        SimdLevel::AvxVnni => unsafe { distance_l2_i8_avx_vnni(a, b) },
+        _ => distance_l2_vector_i8::<N>(a, b),
+    }
+}
+
+/// Dot product of two i8 vectors using the best available kernel
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+pub(crate) fn dot_product_i8<const N: usize>(a: &[i8; N], b: &[i8; N]) -> i32 {
+    match simd_level() {
+        // Safety: avx512f/bw/vl (and vnni) or avxvnni support was checked by simd_level
+        SimdLevel::Avx512Vnni => unsafe { dot_product_i8_avx512_vnni(a, b) },
+        SimdLevel::Avx512 => unsafe { dot_product_i8_avx512(a, b) },
+        SimdLevel::AvxVnni => unsafe { dot_product_i8_avx_vnni(a, b) },
+        _ => dot_product_vector_i8::<N>(a, b),
+    }
+}
+
+/// Cosine distance between two i8 vectors from three dispatched dot products.
+/// Without integer dot product instructions the single pass kernel is faster.
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+pub(crate) fn distance_cosine_i8<const N: usize>(a: &[i8; N], b: &[i8; N]) -> f32 {
+    if simd_level() == SimdLevel::Avx2 {
+        return distance_cosine_vector_i8::<N>(a, b);
+    }
+
+    cosine_distance(
+        dot_product_i8::<N>(a, b) as f32,
+        dot_product_i8::<N>(a, a) as f32,
+        dot_product_i8::<N>(b, b) as f32,
+    )
+}
+
+/// Squared L2 distance between two i8 vectors using the NEON dot products when available
+#[cfg(target_arch = "aarch64")]
+#[inline(always)]
+pub(crate) fn distance_l2_i8<const N: usize>(a: &[i8; N], b: &[i8; N]) -> f32 {
+    match has_dotprod() {
+        // Safety: dotprod support was checked by has_dotprod
+        true => unsafe { distance_l2_i8_neon_dotprod(a, b) },
+        false => distance_l2_vector_i8::<N>(a, b),
+    }
+}
+
+/// Dot product of two i8 vectors with the NEON dot products, callers check has_dotprod
+#[cfg(target_arch = "aarch64")]
+#[inline(always)]
+fn dot_product_i8<const N: usize>(a: &[i8; N], b: &[i8; N]) -> i32 {
+    debug_assert!(has_dotprod());
+    // Safety: dotprod support was checked by the caller
+    unsafe { dot_product_i8_neon_dotprod(a, b) }
+}
+
+/// Cosine distance between two i8 vectors from three NEON dot products when available
+#[cfg(target_arch = "aarch64")]
+#[inline(always)]
+pub(crate) fn distance_cosine_i8<const N: usize>(a: &[i8; N], b: &[i8; N]) -> f32 {
+    if !has_dotprod() {
+        return distance_cosine_vector_i8::<N>(a, b);
+    }
+
+    cosine_distance(
+        dot_product_i8::<N>(a, b) as f32,
+        dot_product_i8::<N>(a, a) as f32,
+        dot_product_i8::<N>(b, b) as f32,
+    )
+}
+
+#[cfg(all(test, target_arch = "x86_64"))]
+mod simd_dispatch_test {
+    use super::*;
+
+    #[test]
+    fn simd_level_is_stable() {
+        assert_eq!(simd_level(), simd_level());
+        assert_eq!(simd_level(), detect_simd_level());
+    }
+
+    #[test]
+    fn kernel_selections_follow_simd_level() {
+        let selections = kernel_selections();
+        let l2_i8 = selections.iter().find(|s| s.kernel == "l2_i8").unwrap();
+        assert_eq!(l2_i8.backend, KernelBackend::Simd(simd_level()));
+        assert!(selections.iter().all(|s| match s.backend {
+            KernelBackend::Scalar => true,
+            KernelBackend::Simd(level) => level <= simd_level(),
+        }));
+        assert!(kernel_report().starts_with("l2_f32: avx"));
+
+        let hamming = selections.iter().find(|s| s.kernel == "hamming").unwrap();
+        assert_eq!(hamming.to_string(), "hamming: scalar");
+        let pq_lut = selections.iter().find(|s| s.kernel == "pq_lut").unwrap();
+        assert_eq!(pq_lut.to_string(), "pq_lut: avx2");
+    }
+
+    #[test]
+    fn dispatched_i8_matches_avx2() {
+        let a: [i8; 104] = std::array::from_fn(|i| (i as i32 * 7 - 300) as i8);
+        let b: [i8; 104] = std::array::from_fn(|i| (i as i32 * 13 + 5) as i8);
+
+        assert_eq!(distance_l2_i8::<104>(&a, &b), distance_l2_vector_i8::<104>(&a, &b));
+        assert_eq!(dot_product_i8::<104>(&a, &b), dot_product_vector_i8::<104>(&a, &b));
+        assert_eq!(
+            distance_cosine_i8::<104>(&a, &b),
+            distance_cosine_vector_i8::<104>(&a, &b)
+        );
+    }
+}
+
+#[cfg(all(test, target_arch = "aarch64"))]
+mod simd_dispatch_aarch64_test {
+    use super::*;
+
+    #[test]
+    fn kernel_selections_follow_dotprod() {
+        let backend = match has_dotprod() {
+            true => "neon-dotprod",
+            false => "scalar",
+        };
+        let selections = kernel_selections();
+        let l2_i8 = selections.iter().find(|s| s.kernel == "l2_i8").unwrap();
+        assert_eq!(l2_i8.to_string(), format!("l2_i8: {}", backend));
+        assert!(kernel_report().starts_with("l2_f32: scalar"));
+        let pq_lut = selections.iter().find(|s| s.kernel == "pq_lut").unwrap();
+        assert_eq!(pq_lut.to_string(), "pq_lut: neon");
+    }
+
+    #[test]
+    fn dispatched_i8_matches_scalar() {
+        let a: [i8; 104] = std::array::from_fn(|i| (i as i32 * 7 - 300) as i8);
+        let b: [i8; 104] = std::array::from_fn(|i| (i as i32 * 13 + 5) as i8);
+
+        assert_eq!(distance_l2_i8::<104>(&a, &b), distance_l2_vector_i8::<104>(&a, &b));
+        let cosine = distance_cosine_i8::<104>(&a, &b);
+        let scalar = distance_cosine_vector_i8::<104>(&a, &b);
+        assert!((cosine - scalar).abs() <= 1e-6, "{} vs {}", cosine, scalar);
+    }
+}
@@ -0,0 +1,167 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Kernels for sparse vectors stored as parallel (indices, values) slices with strictly
+//! increasing indices, i.e. one row of a CSR matrix.
+//! Two sparse vectors are combined by merging their index lists; a sparse vector and a dense
+//! one by gathering the dense values at the sparse indices, eight at a time.
+
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+#[cfg(target_arch = "x86_64")]
+use crate::simd::horizontal_sum;
+
+/// Dot product of two sparse vectors
+#[inline(never)]
+pub fn sparse_dot(a_indices: &[u32], a_values: &[f32], b_indices: &[u32], b_values: &[f32]) -> f32 {
+    debug_assert_eq!(a_indices.len(), a_values.len());
+    debug_assert_eq!(b_indices.len(), b_values.len());
+
+    let (mut i, mut j) = (0, 0);
+    let mut dot = 0.0;
+    while i < a_indices.len() && j < b_indices.len() {
+        match a_indices[i].cmp(&b_indices[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                dot += a_values[i] * b_values[j];
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    dot
+}
+
+/// Squared L2 distance between two sparse vectors.
+/// Computed over the merged indices rather than as |a|² + |b|² - 2a·b, which cancels badly
+/// for close vectors.
+#[inline(never)]
+pub fn sparse_l2(a_indices: &[u32], a_values: &[f32], b_indices: &[u32], b_values: &[f32]) -> f32 {
+    debug_assert_eq!(a_indices.len(), a_values.len());
+    debug_assert_eq!(b_indices.len(), b_values.len());
+
+    let (mut i, mut j) = (0, 0);
+    let mut sum = 0.0;
+    while i < a_indices.len() && j < b_indices.len() {
+        match a_indices[i].cmp(&b_indices[j]) {
+            std::cmp::Ordering::Less => {
+                sum += a_values[i] * a_values[i];
+                i += 1;
+            }
+            std::cmp::Ordering::Greater => {
+                sum += b_values[j] * b_values[j];
+                j += 1;
+            }
+            std::cmp::Ordering::Equal => {
+                let diff = a_values[i] - b_values[j];
+                sum += diff * diff;
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+
+    sum + a_values[i..].iter().map(|x| x * x).sum::<f32>()
+        + b_values[j..].iter().map(|x| x * x).sum::<f32>()
+}
+
+/// Dot product of a sparse vector and a dense one with AVX2 gathers.
+/// Every sparse index must be within the dense vector.
+#[cfg(target_arch = "x86_64")]
+#[inline(never)]
+pub fn sparse_dense_dot(indices: &[u32], values: &[f32], dense: &[f32]) -> f32 {
+    debug_assert_eq!(indices.len(), values.len());
+    // The gathers don't check bounds, indices are sorted so the last one is the largest
+    assert!(indices
+        .last()
+        .is_none_or(|last| (*last as usize) < dense.len()));
+
+    let len = indices.len().min(values.len());
+    let vector_len = len - len % 8;
+
+    let mut dot = unsafe {
+        let mut sum = _mm256_setzero_ps();
+        for i in (0..vector_len).step_by(8) {
+            let idx = _mm256_loadu_si256(indices.as_ptr().add(i) as *const __m256i);
+            let gathered = _mm256_i32gather_ps::<4>(dense.as_ptr(), idx);
+            sum = _mm256_fmadd_ps(gathered, _mm256_loadu_ps(values.as_ptr().add(i)), sum);
+        }
+        horizontal_sum(sum)
+    };
+
+    for i in vector_len..len {
+        dot += values[i] * dense[indices[i] as usize];
+    }
+    dot
+}
+
+/// Dot product of a sparse vector and a dense one, one non-zero at a time on targets without
+/// AVX2. Every sparse index must be within the dense vector.
+#[cfg(not(target_arch = "x86_64"))]
+#[inline(never)]
+pub fn sparse_dense_dot(indices: &[u32], values: &[f32], dense: &[f32]) -> f32 {
+    debug_assert_eq!(indices.len(), values.len());
+    indices
+        .iter()
+        .zip(values)
+        .map(|(idx, value)| value * dense[*idx as usize])
+        .sum()
+}
+
+#[cfg(test)]
+mod sparse_distance_test {
+    use approx::assert_abs_diff_eq;
+
+    use super::*;
+
+    fn densify(indices: &[u32], values: &[f32], dim: usize) -> Vec<f32> {
+        let mut dense = vec![0.0; dim];
+        for (idx, value) in indices.iter().zip(values) {
+            dense[*idx as usize] = *value;
+        }
+        dense
+    }
+
+    #[test]
+    fn sparse_kernels_match_dense() {
+        let dim = 64;
+        let a_indices: Vec<u32> = (0..dim as u32).filter(|i| i % 3 == 0).collect();
+        let a_values: Vec<f32> = a_indices.iter().map(|i| (*i as f32 * 0.7).sin()).collect();
+        let b_indices: Vec<u32> = (0..dim as u32).filter(|i| i % 5 == 1 || *i == 63).collect();
+        let b_values: Vec<f32> = b_indices.iter().map(|i| *i as f32 * 0.1 - 2.0).collect();
+
+        let a = densify(&a_indices, &a_values, dim);
+        let b = densify(&b_indices, &b_values, dim);
+        let dense_dot: f32 = a.iter().zip(&b).map(|(x, y)| x * y).sum();
+        let dense_l2: f32 = a.iter().zip(&b).map(|(x, y)| (x - y) * (x - y)).sum();
+
+        assert_abs_diff_eq!(
+            sparse_dot(&a_indices, &a_values, &b_indices, &b_values),
+            dense_dot,
+            epsilon = 1e-4
+        );
+        assert_abs_diff_eq!(
+            sparse_l2(&a_indices, &a_values, &b_indices, &b_values),
+            dense_l2,
+            epsilon = 1e-3
+        );
+        // 22 non-zeros: two gathers and a scalar tail
+        assert_abs_diff_eq!(
+            sparse_dense_dot(&a_indices, &a_values, &b),
+            dense_dot,
+            epsilon = 1e-4
+        );
+
+        assert_eq!(sparse_dot(&[], &[], &b_indices, &b_values), 0.0);
+        assert_abs_diff_eq!(
+            sparse_l2(&[], &[], &b_indices, &b_values),
+            b.iter().map(|x| x * x).sum::<f32>(),
+            epsilon = 1e-3
+        );
+    }
+}
@@ -0,0 +1,121 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Distances to rows laid out with a stride larger than their dimension, e.g. a dataset whose
+//! rows are padded to an aligned length. Only the first dim values of a row are compared, so
+//! the rows are read in place instead of being repacked into a dense array first.
+
+use crate::subspace_distance::{
+    distance_cosine_slice_f32, distance_l2_slice_f32, distance_subspace_novector,
+};
+use crate::Metric;
+
+/// Rows of dim values, each starting stride values after the previous one
+#[derive(Debug, Clone, Copy)]
+pub struct StridedRows<'a, T> {
+    data: &'a [T],
+    dim: usize,
+    stride: usize,
+}
+
+impl<'a, T> StridedRows<'a, T> {
+    /// View data as rows of dim values, stride values apart. The last row may omit its padding.
+    /// None if the stride is smaller than the dimension or the dimension is 0.
+    pub fn new(data: &'a [T], dim: usize, stride: usize) -> Option<Self> {
+        if dim == 0 || stride < dim {
+            return None;
+        }
+
+        Some(Self { data, dim, stride })
+    }
+
+    /// Number of complete rows
+    pub fn num_rows(&self) -> usize {
+        if self.data.len() < self.dim {
+            0
+        } else {
+            (self.data.len() - self.dim) / self.stride + 1
+        }
+    }
+
+    /// Dimension of the rows
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// The dim values of row i, without the padding
+    pub fn row(&self, i: usize) -> &'a [T] {
+        let start = i * self.stride;
+        &self.data[start..start + self.dim]
+    }
+}
+
+/// Distance from the query to every row, written to distances in row order.
+/// The query has the dimension of the rows; distances holds num_rows values at most.
+pub fn distances_to_strided_rows_f32(
+    query: &[f32],
+    rows: &StridedRows<f32>,
+    metric: Metric,
+    distances: &mut [f32],
+) {
+    assert_eq!(query.len(), rows.dim);
+
+    for (i, distance) in distances.iter_mut().take(rows.num_rows()).enumerate() {
+        let row = rows.row(i);
+        *distance = match metric {
+            Metric::L2 => distance_l2_slice_f32(query, row),
+            Metric::Cosine => distance_cosine_slice_f32(query, row),
+            Metric::L1 | Metric::Chebyshev | Metric::Hamming | Metric::Tanimoto => {
+                distance_subspace_novector(query, row, metric)
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod strided_distance_test {
+    use approx::assert_abs_diff_eq;
+
+    use super::*;
+
+    #[test]
+    fn strided_rows_match_packed_rows() {
+        // 5 rows of 13 values padded to 16, the last row without its padding
+        let (dim, stride, num_rows) = (13, 16, 5);
+        let mut data = vec![-100.0f32; stride * (num_rows - 1) + dim];
+        let mut packed = Vec::new();
+        for i in 0..num_rows {
+            for d in 0..dim {
+                let value = ((i * dim + d) as f32 * 0.37).sin();
+                data[i * stride + d] = value;
+                packed.push(value);
+            }
+        }
+
+        let rows = StridedRows::new(&data, dim, stride).unwrap();
+        assert_eq!(rows.num_rows(), num_rows);
+        let query: Vec<f32> = (0..dim).map(|d| d as f32 * 0.1).collect();
+
+        for metric in [Metric::L2, Metric::Cosine, Metric::L1, Metric::Chebyshev] {
+            let mut distances = vec![0.0; num_rows];
+            distances_to_strided_rows_f32(&query, &rows, metric, &mut distances);
+            for (i, distance) in distances.iter().enumerate() {
+                let expected =
+                    distance_subspace_novector(&query, &packed[i * dim..(i + 1) * dim], metric);
+                assert_abs_diff_eq!(*distance, expected, epsilon = 1e-5);
+            }
+        }
+
+        assert!(StridedRows::new(&data, 17, 16).is_none());
+        assert!(StridedRows::new(&data, 0, 16).is_none());
+        assert_eq!(
+            StridedRows::new(&data[..10], dim, stride)
+                .unwrap()
+                .num_rows(),
+            0
+        );
+    }
+}
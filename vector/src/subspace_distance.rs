@@ -0,0 +1,150 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Distances over a contiguous range of dimensions, e.g. the leading dimensions of a
+//! Matryoshka embedding, which are a cheaper but coarser view of the full vector.
+//! The range is chosen per query, so the kernels take slices of any length and alignment.
+
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+use crate::cosine_distance::cosine_distance;
+#[cfg(target_arch = "x86_64")]
+use crate::simd::horizontal_sum;
+use crate::Metric;
+
+/// Calculate the squared L2 distance between two f32 slices of the same length
+#[cfg(target_arch = "x86_64")]
+#[inline(never)]
+pub fn distance_l2_slice_f32(a: &[f32], b: &[f32]) -> f32 {
+    debug_assert_eq!(a.len(), b.len());
+    let len = a.len().min(b.len());
+    let vector_len = len - len % 8;
+
+    let mut sum = unsafe {
+        let mut sum = _mm256_setzero_ps();
+        for i in (0..vector_len).step_by(8) {
+            let diff = _mm256_sub_ps(
+                _mm256_loadu_ps(a.as_ptr().add(i)),
+                _mm256_loadu_ps(b.as_ptr().add(i)),
+            );
+            sum = _mm256_fmadd_ps(diff, diff, sum);
+        }
+        horizontal_sum(sum)
+    };
+
+    for i in vector_len..len {
+        sum += (a[i] - b[i]) * (a[i] - b[i]);
+    }
+    sum
+}
+
+/// Calculate the squared L2 distance element by element, on targets without AVX2
+#[cfg(not(target_arch = "x86_64"))]
+#[inline(never)]
+pub fn distance_l2_slice_f32(a: &[f32], b: &[f32]) -> f32 {
+    debug_assert_eq!(a.len(), b.len());
+    distance_subspace_novector(a, b, Metric::L2)
+}
+
+/// Calculate the cosine distance between two f32 slices of the same length
+#[cfg(target_arch = "x86_64")]
+#[inline(never)]
+pub fn distance_cosine_slice_f32(a: &[f32], b: &[f32]) -> f32 {
+    debug_assert_eq!(a.len(), b.len());
+    let len = a.len().min(b.len());
+    let vector_len = len - len % 8;
+
+    let (mut dot, mut norm_a, mut norm_b) = unsafe {
+        let mut dot = _mm256_setzero_ps();
+        let mut norm_a = _mm256_setzero_ps();
+        let mut norm_b = _mm256_setzero_ps();
+        for i in (0..vector_len).step_by(8) {
+            let a_vec = _mm256_loadu_ps(a.as_ptr().add(i));
+            let b_vec = _mm256_loadu_ps(b.as_ptr().add(i));
+            dot = _mm256_fmadd_ps(a_vec, b_vec, dot);
+            norm_a = _mm256_fmadd_ps(a_vec, a_vec, norm_a);
+            norm_b = _mm256_fmadd_ps(b_vec, b_vec, norm_b);
+        }
+        (
+            horizontal_sum(dot),
+            horizontal_sum(norm_a),
+            horizontal_sum(norm_b),
+        )
+    };
+
+    for i in vector_len..len {
+        dot += a[i] * b[i];
+        norm_a += a[i] * a[i];
+        norm_b += b[i] * b[i];
+    }
+    cosine_distance(dot, norm_a, norm_b)
+}
+
+/// Calculate the cosine distance element by element, on targets without AVX2
+#[cfg(not(target_arch = "x86_64"))]
+#[inline(never)]
+pub fn distance_cosine_slice_f32(a: &[f32], b: &[f32]) -> f32 {
+    debug_assert_eq!(a.len(), b.len());
+    distance_subspace_novector(a, b, Metric::Cosine)
+}
+
+/// Calculate the distance between two slices element by element, for the types without a
+/// slice kernel
+// reason = "a range of packed binary codes doesn't line up with a range of dimensions"
+#[allow(clippy::panic)]
+pub fn distance_subspace_novector<T: Copy + Into<f32>>(a: &[T], b: &[T], metric: Metric) -> f32 {
+    let values = a
+        .iter()
+        .zip(b.iter())
+        .map(|(x, y)| ((*x).into(), (*y).into()));
+    match metric {
+        Metric::L2 => values.map(|(x, y): (f32, f32)| (x - y) * (x - y)).sum(),
+        Metric::L1 => values.map(|(x, y): (f32, f32)| (x - y).abs()).sum(),
+        Metric::Chebyshev => values
+            .map(|(x, y): (f32, f32)| (x - y).abs())
+            .fold(0.0, f32::max),
+        Metric::Cosine => {
+            let (dot, norm_a, norm_b) = values.fold((0.0, 0.0, 0.0), |(dot, na, nb), (x, y)| {
+                (dot + x * y, na + x * x, nb + y * y)
+            });
+            cosine_distance(dot, norm_a, norm_b)
+        }
+        Metric::Hamming | Metric::Tanimoto => {
+            panic!("Subspace distance is not supported for the {:?} metric", metric)
+        }
+    }
+}
+
+#[cfg(test)]
+mod subspace_distance_test {
+    use approx::assert_abs_diff_eq;
+
+    use super::*;
+
+    #[test]
+    fn slice_kernels_match_novector() {
+        let a: Vec<f32> = (0..40).map(|i| (i as f32 * 0.3).cos() * 2.0).collect();
+        let b: Vec<f32> = (0..40).map(|i| i as f32 * 0.1 - 1.5).collect();
+
+        // Unaligned starts and lengths with a scalar tail
+        for (start, end) in [(0, 40), (1, 20), (3, 3), (5, 37)] {
+            let (a, b) = (&a[start..end], &b[start..end]);
+            assert_abs_diff_eq!(
+                distance_l2_slice_f32(a, b),
+                distance_subspace_novector(a, b, Metric::L2),
+                epsilon = 1e-4
+            );
+            if start != end {
+                assert_abs_diff_eq!(
+                    distance_cosine_slice_f32(a, b),
+                    distance_subspace_novector(a, b, Metric::Cosine),
+                    epsilon = 1e-5
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,106 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Distance calculation for Tanimoto (Jaccard) Metric over packed binary vectors,
+//! e.g. molecular fingerprints. The distance is 1 - |a AND b| / |a OR b| over the set bits.
+
+/// Bytes consumed per popcount
+const WORD_BYTES: usize = std::mem::size_of::<u64>();
+
+/// Calculate the Tanimoto distance between two packed binary vectors
+#[inline(never)]
+pub fn distance_tanimoto_u8<const N: usize>(a: &[u8; N], b: &[u8; N]) -> f32 {
+    tanimoto_bytes(a, b)
+}
+
+/// Calculate the Tanimoto distance between two packed binary vectors stored as i8
+#[inline(never)]
+pub fn distance_tanimoto_i8<const N: usize>(a: &[i8; N], b: &[i8; N]) -> f32 {
+    let a_bytes: &[u8] = bytemuck::cast_slice(a);
+    let b_bytes: &[u8] = bytemuck::cast_slice(b);
+    tanimoto_bytes(a_bytes, b_bytes)
+}
+
+/// Popcount the intersection and the union a word at a time, then finish the tail byte by byte
+#[inline(always)]
+fn tanimoto_bytes(a: &[u8], b: &[u8]) -> f32 {
+    debug_assert_eq!(a.len(), b.len());
+
+    let a_words = a.chunks_exact(WORD_BYTES);
+    let b_words = b.chunks_exact(WORD_BYTES);
+    let (mut intersection, mut union) = a_words
+        .remainder()
+        .iter()
+        .zip(b_words.remainder())
+        .fold((0u32, 0u32), |(i, u), (x, y)| {
+            (i + (x & y).count_ones(), u + (x | y).count_ones())
+        });
+
+    for (x, y) in a_words.zip(b_words) {
+        let (x, y) = (word(x), word(y));
+        intersection += (x & y).count_ones();
+        union += (x | y).count_ones();
+    }
+
+    // Two empty fingerprints are identical
+    if union == 0 {
+        return 0.0;
+    }
+    1.0 - intersection as f32 / union as f32
+}
+
+#[inline(always)]
+fn word(bytes: &[u8]) -> u64 {
+    let mut buf = [0u8; WORD_BYTES];
+    buf.copy_from_slice(bytes);
+    u64::from_ne_bytes(buf)
+}
+
+#[cfg(test)]
+mod tanimoto_distance_test {
+    use approx::assert_abs_diff_eq;
+    use rand::Rng;
+
+    use super::*;
+
+    fn no_vector_tanimoto(a: &[u8], b: &[u8]) -> f32 {
+        let (mut intersection, mut union) = (0, 0);
+        for (x, y) in a.iter().zip(b) {
+            for bit in 0..8 {
+                let (x, y) = ((x >> bit) & 1, (y >> bit) & 1);
+                intersection += x & y;
+                union += x | y;
+            }
+        }
+        1.0 - intersection as f32 / union as f32
+    }
+
+    #[test]
+    fn tanimoto_matches_novector() {
+        let mut rng = rand::thread_rng();
+        let a: [u8; 36] = std::array::from_fn(|_| rng.gen());
+        let b: [u8; 36] = std::array::from_fn(|_| rng.gen());
+
+        assert_abs_diff_eq!(
+            distance_tanimoto_u8::<36>(&a, &b),
+            no_vector_tanimoto(&a, &b),
+            epsilon = 1e-6
+        );
+        assert_eq!(distance_tanimoto_u8::<36>(&a, &a), 0.0);
+    }
+
+    #[test]
+    fn tanimoto_of_disjoint_and_empty_fingerprints() {
+        let a = [0b0000_1111u8; 16];
+        let b = [0b1111_0000u8; 16];
+        let c = [0b0011_1100u8; 16];
+
+        assert_eq!(distance_tanimoto_u8::<16>(&a, &b), 1.0);
+        assert_eq!(distance_tanimoto_u8::<16>(&a, &c), 1.0 - 2.0 / 6.0);
+        assert_eq!(distance_tanimoto_u8::<16>(&[0; 16], &[0; 16]), 0.0);
+        assert_eq!(distance_tanimoto_i8::<16>(&[0; 16], &[-1; 16]), 1.0);
+    }
+}
@@ -0,0 +1,169 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Fused distance + top-k selection over a contiguous block of candidates.
+//! Distances are computed four candidates per pass over the query and fed straight into a
+//! sorted buffer of k entries; once it is full, a candidate no closer than the current k-th
+//! one is dropped with a single comparison, so the buffer is rarely touched.
+
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+#[cfg(target_arch = "x86_64")]
+use crate::simd::horizontal_sum;
+#[cfg(not(target_arch = "x86_64"))]
+use crate::{subspace_distance::distance_subspace_novector, Metric};
+
+/// Candidates sharing one pass over the query
+const GROUP: usize = 4;
+
+/// Positions and squared L2 distances of the k candidates of `block` closest to `query`,
+/// closest first. Ties keep the candidate that comes first in the block.
+/// The block doesn't need to be aligned.
+#[inline(never)]
+pub fn select_topk_l2<const N: usize>(
+    query: &[f32; N],
+    block: &[[f32; N]],
+    k: usize,
+) -> Vec<(usize, f32)> {
+    debug_assert_eq!(N % 8, 0);
+
+    let mut topk = TopK::new(k);
+    if k == 0 {
+        return topk.into_sorted();
+    }
+
+    let mut groups = block.chunks_exact(GROUP);
+    for (group_idx, group) in groups.by_ref().enumerate() {
+        let distances = unsafe { l2_group::<N, GROUP>(query, std::array::from_fn(|i| &group[i])) };
+        for (offset, distance) in distances.into_iter().enumerate() {
+            topk.push(group_idx * GROUP + offset, distance);
+        }
+    }
+
+    let tail_start = block.len() - groups.remainder().len();
+    for (offset, b) in groups.remainder().iter().enumerate() {
+        let [distance] = unsafe { l2_group::<N, 1>(query, [b]) };
+        topk.push(tail_start + offset, distance);
+    }
+
+    topk.into_sorted()
+}
+
+/// The k closest (position, distance) pairs seen so far, sorted by distance
+struct TopK {
+    k: usize,
+    entries: Vec<(usize, f32)>,
+}
+
+impl TopK {
+    fn new(k: usize) -> Self {
+        Self {
+            k,
+            entries: Vec::with_capacity(k + 1),
+        }
+    }
+
+    #[inline(always)]
+    fn push(&mut self, idx: usize, distance: f32) {
+        if self.entries.len() == self.k && distance >= self.entries[self.k - 1].1 {
+            return;
+        }
+
+        // Positions arrive in increasing order, so inserting after equal distances keeps ties stable
+        let pos = self.entries.partition_point(|(_, d)| *d <= distance);
+        self.entries.insert(pos, (idx, distance));
+        self.entries.truncate(self.k);
+    }
+
+    fn into_sorted(self) -> Vec<(usize, f32)> {
+        self.entries
+    }
+}
+
+/// Squared L2 distances of G candidates with unaligned loads, sharing each query load
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+unsafe fn l2_group<const N: usize, const G: usize>(
+    a: &[f32; N],
+    group: [&[f32; N]; G],
+) -> [f32; G] {
+    let mut sums = [_mm256_setzero_ps(); G];
+
+    for i in (0..N).step_by(8) {
+        let a_vec = _mm256_loadu_ps(&a[i]);
+        for (sum, b) in sums.iter_mut().zip(group) {
+            let diff = _mm256_sub_ps(a_vec, _mm256_loadu_ps(&b[i]));
+            *sum = _mm256_fmadd_ps(diff, diff, *sum);
+        }
+    }
+
+    sums.map(|sum| horizontal_sum(sum))
+}
+
+/// Squared L2 distances of G candidates element by element, on targets without AVX2
+#[cfg(not(target_arch = "x86_64"))]
+#[inline(always)]
+unsafe fn l2_group<const N: usize, const G: usize>(
+    a: &[f32; N],
+    group: [&[f32; N]; G],
+) -> [f32; G] {
+    group.map(|b| distance_subspace_novector(a, b, Metric::L2))
+}
+
+#[cfg(test)]
+mod topk_distance_test {
+    use rand::Rng;
+
+    use super::*;
+
+    fn expected_topk(query: &[f32; 24], block: &[[f32; 24]], k: usize) -> Vec<(usize, f32)> {
+        let mut all: Vec<(usize, f32)> = block
+            .iter()
+            .enumerate()
+            .map(|(i, b)| (i, unsafe { l2_group::<24, 1>(query, [b]) }[0]))
+            .collect();
+        all.sort_by(|x, y| x.1.total_cmp(&y.1).then(x.0.cmp(&y.0)));
+        all.truncate(k);
+        all
+    }
+
+    #[test]
+    fn topk_matches_full_sort() {
+        let mut rng = rand::thread_rng();
+        let query: [f32; 24] = std::array::from_fn(|_| rng.gen_range(-1.0..1.0));
+        let block: Vec<[f32; 24]> = (0..37)
+            .map(|_| std::array::from_fn(|_| rng.gen_range(-1.0..1.0)))
+            .collect();
+
+        for k in [0, 1, 5, 36, 37, 50] {
+            assert_eq!(
+                select_topk_l2::<24>(&query, &block, k),
+                expected_topk(&query, &block, k)
+            );
+        }
+
+        // Distances agree with a plain scalar computation
+        let nearest = select_topk_l2::<24>(&query, &block, 1)[0];
+        let sequential: f32 = query
+            .iter()
+            .zip(&block[nearest.0])
+            .map(|(a, b)| (a - b) * (a - b))
+            .sum();
+        assert!((nearest.1 - sequential).abs() < 1e-5);
+    }
+
+    #[test]
+    fn topk_keeps_first_of_ties() {
+        let query = [0.5f32; 24];
+        let block = vec![[1.0f32; 24]; 6];
+        let ids: Vec<usize> = select_topk_l2::<24>(&query, &block, 3)
+            .iter()
+            .map(|(idx, _)| *idx)
+            .collect();
+        assert_eq!(ids, vec![0, 1, 2]);
+    }
+}
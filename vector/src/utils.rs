@@ -2,10 +2,12 @@
  * Copyright (c) Microsoft Corporation. All rights reserved.
  * Licensed under the MIT license.
  */
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
 
 /// Prefetch the given vector in chunks of 64 bytes, which is a cache line size
 /// NOTE: good efficiency when total_vec_size is integral multiple of 64
+#[cfg(target_arch = "x86_64")]
 #[inline]
 pub fn prefetch_vector<T>(vec: &[T]) {
     let vec_ptr = vec.as_ptr() as *const i8;
@@ -19,3 +21,8 @@ pub fn prefetch_vector<T>(vec: &[T]) {
     }
 }
 
+/// Prefetching is left to the hardware on targets other than x86_64
+#[cfg(not(target_arch = "x86_64"))]
+#[inline]
+pub fn prefetch_vector<T>(_vec: &[T]) {}
+
@@ -0,0 +1,109 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! AVX-VNNI int8 kernels for CPUs with the VEX-encoded 256-bit VNNI instructions but
+//! no AVX-512 (e.g. Alder Lake). i8 lanes are widened to i16 and vpdpwssd fuses the
+//! multiply and the i32 accumulation. Tails shorter than 16 lanes are handled in scalar code.
+//! Callers must check CPU support before calling, see `simd_dispatch`.
+
+use std::arch::x86_64::*;
+
+/// Lanes of i8 consumed per iteration (widened to 16 x i16)
+const I8_LANES: usize = 16;
+
+/// Calculate the squared L2 distance between two i8 vectors with AVX-VNNI
+/// # Safety
+/// The CPU must support avx2 and avxvnni, and a and b must have equal lengths.
+#[target_feature(enable = "avx2,avxvnni")]
+pub unsafe fn distance_l2_i8_avx_vnni(a: &[i8], b: &[i8]) -> f32 {
+    debug_assert_eq!(a.len(), b.len());
+
+    let len = a.len();
+    let mut sum = _mm256_setzero_si256();
+
+    let mut i = 0;
+    while i + I8_LANES <= len {
+        let diff = _mm256_sub_epi16(load_i8_as_i16(a, i), load_i8_as_i16(b, i));
+        sum = _mm256_dpwssd_avx_epi32(sum, diff, diff);
+        i += I8_LANES;
+    }
+
+    let mut total = horizontal_sum_epi32(sum);
+    for j in i..len {
+        let diff = a[j] as i32 - b[j] as i32;
+        total += diff * diff;
+    }
+    total as f32
+}
+
+/// Calculate the dot product of two i8 vectors with AVX-VNNI
+/// # Safety
+/// The CPU must support avx2 and avxvnni, and a and b must have equal lengths.
+#[target_feature(enable = "avx2,avxvnni")]
+pub unsafe fn dot_product_i8_avx_vnni(a: &[i8], b: &[i8]) -> i32 {
+    debug_assert_eq!(a.len(), b.len());
+
+    let len = a.len();
+    let mut sum = _mm256_setzero_si256();
+
+    let mut i = 0;
+    while i + I8_LANES <= len {
+        sum = _mm256_dpwssd_avx_epi32(sum, load_i8_as_i16(a, i), load_i8_as_i16(b, i));
+        i += I8_LANES;
+    }
+
+    let mut total = horizontal_sum_epi32(sum);
+    for j in i..len {
+        total += a[j] as i32 * b[j] as i32;
+    }
+    total
+}
+
+/// Load 16 i8 at offset and sign extend them to i16 lanes
+#[inline]
+#[target_feature(enable = "avx2")]
+unsafe fn load_i8_as_i16(v: &[i8], offset: usize) -> __m256i {
+    _mm256_cvtepi8_epi16(_mm_loadu_si128(v.as_ptr().add(offset) as *const __m128i))
+}
+
+#[inline]
+#[target_feature(enable = "avx2")]
+unsafe fn horizontal_sum_epi32(sum: __m256i) -> i32 {
+    let x128 = _mm_add_epi32(_mm256_extracti128_si256(sum, 1), _mm256_castsi256_si128(sum));
+    let x64 = _mm_add_epi32(x128, _mm_unpackhi_epi64(x128, x128));
+    let x32 = _mm_add_epi32(x64, _mm_shuffle_epi32(x64, 0b01));
+    _mm_cvtsi128_si32(x32)
+}
+
+#[cfg(test)]
+mod vnni_distance_test {
+    use rand::Rng;
+
+    use super::*;
+
+    #[test]
+    fn avx_vnni_matches_novector_with_tails() {
+        if !is_x86_feature_detected!("avxvnni") {
+            return;
+        }
+
+        let mut rng = rand::thread_rng();
+        for len in [1, 15, 16, 17, 100, 768, 1025] {
+            let a: Vec<i8> = (0..len).map(|_| rng.gen()).collect();
+            let b: Vec<i8> = (0..len).map(|_| rng.gen()).collect();
+            let l2: i32 = a.iter().zip(&b).map(|(x, y)| (*x as i32 - *y as i32).pow(2)).sum();
+            let dot: i32 = a.iter().zip(&b).map(|(x, y)| *x as i32 * *y as i32).sum();
+
+            assert_eq!(unsafe { distance_l2_i8_avx_vnni(&a, &b) }, l2 as f32);
+            assert_eq!(unsafe { dot_product_i8_avx_vnni(&a, &b) }, dot);
+        }
+
+        let a = [i8::MIN; 64];
+        let b = [i8::MAX; 64];
+        assert_eq!(unsafe { distance_l2_i8_avx_vnni(&a, &b) }, 64.0 * 255.0 * 255.0);
+        assert_eq!(unsafe { dot_product_i8_avx_vnni(&a, &a) }, 64 * 128 * 128);
+    }
+}
@@ -0,0 +1,170 @@
+/*
+ * Copyright (c) Microsoft Corporation. All rights reserved.
+ * Licensed under the MIT license.
+ */
+#![warn(missing_debug_implementations, missing_docs)]
+
+//! Distances with a per-dimension weight vector, so a query can emphasize some features
+//! without rebuilding the index. Weighted L2 is sum(w * (a - b)^2), weighted cosine uses the
+//! weighted inner product sum(w * a * b) for the dot product and both norms.
+
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+use crate::cosine_distance::cosine_distance;
+#[cfg(target_arch = "x86_64")]
+use crate::simd::horizontal_sum;
+use crate::Metric;
+
+/// Calculate the weighted L2 distance by vector arithmetic
+#[cfg(target_arch = "x86_64")]
+#[inline(never)]
+pub fn distance_l2_weighted_f32<const N: usize>(a: &[f32; N], b: &[f32; N], w: &[f32; N]) -> f32 {
+    debug_assert_eq!(N % 8, 0);
+
+    unsafe {
+        let mut sum = _mm256_setzero_ps();
+
+        // Iterate over the elements in steps of 8
+        for i in (0..N).step_by(8) {
+            let diff = _mm256_sub_ps(_mm256_loadu_ps(&a[i]), _mm256_loadu_ps(&b[i]));
+            let weighted = _mm256_mul_ps(_mm256_loadu_ps(&w[i]), diff);
+            sum = _mm256_fmadd_ps(weighted, diff, sum);
+        }
+
+        horizontal_sum(sum)
+    }
+}
+
+/// Calculate the weighted L2 distance element by element, on targets without AVX2
+#[cfg(not(target_arch = "x86_64"))]
+#[inline(never)]
+pub fn distance_l2_weighted_f32<const N: usize>(a: &[f32; N], b: &[f32; N], w: &[f32; N]) -> f32 {
+    distance_weighted_novector(a, b, w, Metric::L2)
+}
+
+/// Calculate the weighted cosine distance by vector arithmetic
+#[cfg(target_arch = "x86_64")]
+#[inline(never)]
+pub fn distance_cosine_weighted_f32<const N: usize>(
+    a: &[f32; N],
+    b: &[f32; N],
+    w: &[f32; N],
+) -> f32 {
+    debug_assert_eq!(N % 8, 0);
+
+    unsafe {
+        let mut dot = _mm256_setzero_ps();
+        let mut norm_a = _mm256_setzero_ps();
+        let mut norm_b = _mm256_setzero_ps();
+
+        // Iterate over the elements in steps of 8
+        for i in (0..N).step_by(8) {
+            let w_vec = _mm256_loadu_ps(&w[i]);
+            let a_vec = _mm256_loadu_ps(&a[i]);
+            let b_vec = _mm256_loadu_ps(&b[i]);
+            let wa = _mm256_mul_ps(w_vec, a_vec);
+            let wb = _mm256_mul_ps(w_vec, b_vec);
+            dot = _mm256_fmadd_ps(wa, b_vec, dot);
+            norm_a = _mm256_fmadd_ps(wa, a_vec, norm_a);
+            norm_b = _mm256_fmadd_ps(wb, b_vec, norm_b);
+        }
+
+        cosine_distance(
+            horizontal_sum(dot),
+            horizontal_sum(norm_a),
+            horizontal_sum(norm_b),
+        )
+    }
+}
+
+/// Calculate the weighted cosine distance element by element, on targets without AVX2
+#[cfg(not(target_arch = "x86_64"))]
+#[inline(never)]
+pub fn distance_cosine_weighted_f32<const N: usize>(
+    a: &[f32; N],
+    b: &[f32; N],
+    w: &[f32; N],
+) -> f32 {
+    distance_weighted_novector(a, b, w, Metric::Cosine)
+}
+
+/// Calculate the weighted distance element by element, for the types without a vector kernel
+// reason = "Hamming and Tanimoto distances work on packed bits, a per-dimension weight has no meaning there"
+#[allow(clippy::panic)]
+pub fn distance_weighted_novector<T: Copy + Into<f32>, const N: usize>(
+    a: &[T; N],
+    b: &[T; N],
+    w: &[f32; N],
+    metric: Metric,
+) -> f32 {
+    let values = a
+        .iter()
+        .zip(b.iter())
+        .zip(w.iter())
+        .map(|((x, y), w)| ((*x).into(), (*y).into(), *w));
+    match metric {
+        Metric::L2 => values
+            .map(|(x, y, w): (f32, f32, f32)| w * (x - y) * (x - y))
+            .sum(),
+        Metric::L1 => values
+            .map(|(x, y, w): (f32, f32, f32)| w * (x - y).abs())
+            .sum(),
+        Metric::Chebyshev => values
+            .map(|(x, y, w): (f32, f32, f32)| w * (x - y).abs())
+            .fold(0.0, f32::max),
+        Metric::Cosine => {
+            let (dot, norm_a, norm_b) = values.fold((0.0, 0.0, 0.0), |(dot, na, nb), (x, y, w)| {
+                (dot + w * x * y, na + w * x * x, nb + w * y * y)
+            });
+            cosine_distance(dot, norm_a, norm_b)
+        }
+        Metric::Hamming | Metric::Tanimoto => {
+            panic!("Weighted distance is not supported for the {:?} metric", metric)
+        }
+    }
+}
+
+#[cfg(test)]
+mod weighted_distance_test {
+    use approx::assert_abs_diff_eq;
+
+    use super::*;
+
+    #[test]
+    fn weighted_kernels_match_novector() {
+        let a: [f32; 24] = std::array::from_fn(|i| (i as f32 * 0.7).sin() * 3.0);
+        let b: [f32; 24] = std::array::from_fn(|i| i as f32 * 0.25 - 2.0);
+        let w: [f32; 24] = std::array::from_fn(|i| (i % 4) as f32 * 0.5);
+
+        assert_abs_diff_eq!(
+            distance_l2_weighted_f32::<24>(&a, &b, &w),
+            distance_weighted_novector(&a, &b, &w, Metric::L2),
+            epsilon = 1e-4
+        );
+        assert_abs_diff_eq!(
+            distance_cosine_weighted_f32::<24>(&a, &b, &w),
+            distance_weighted_novector(&a, &b, &w, Metric::Cosine),
+            epsilon = 1e-5
+        );
+    }
+
+    #[test]
+    fn unit_weights_match_unweighted_and_zero_weights_mask_dimensions() {
+        let a: [f32; 16] = std::array::from_fn(|i| i as f32);
+        let b: [f32; 16] = std::array::from_fn(|i| if i < 8 { i as f32 } else { 0.0 });
+
+        let ones = [1.0f32; 16];
+        let l2: f32 = (8..16).map(|i| (i * i) as f32).sum();
+        assert_eq!(distance_l2_weighted_f32::<16>(&a, &b, &ones), l2);
+
+        // Only the first 8 dimensions count, where a and b are equal
+        let first_half: [f32; 16] = std::array::from_fn(|i| if i < 8 { 1.0 } else { 0.0 });
+        assert_eq!(distance_l2_weighted_f32::<16>(&a, &b, &first_half), 0.0);
+        assert_abs_diff_eq!(
+            distance_cosine_weighted_f32::<16>(&a, &b, &first_half),
+            0.0,
+            epsilon = 1e-6
+        );
+    }
+}